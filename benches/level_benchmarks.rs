@@ -29,7 +29,7 @@ use criterion::criterion_group;
 use criterion::criterion_main;
 use criterion::Criterion;
 
-use level_hash::util::generate_seeds;
+use level_hash::util::generate_seeds_deterministic;
 use level_hash::LevelHash;
 
 fn gxhash(seed: u64, data: &[u8]) -> u64 {
@@ -51,7 +51,7 @@ fn create_level_hash(
         fs::create_dir_all(&index_dir).expect("Failed to create directories");
     }
 
-    let (s1, s2) = generate_seeds();
+    let (s1, s2) = generate_seeds_deterministic();
 
     let mut options = LevelHash::options();
     options
@@ -145,9 +145,29 @@ fn bench_level_insert_auto_expand(c: &mut Criterion) {
     });
 }
 
+fn bench_level_insert_growing_values(c: &mut Criterion) {
+    c.bench_function("insert_growing_values", |b| {
+        b.iter(|| {
+            // the values file grows by 64 KiB after every ~60 insertions
+            let mut hash = create_level_hash("insert_growing_values", true, |ops| {
+                ops.level_size(10)
+                    .bucket_size(10)
+                    .auto_expand(false)
+                    .values_growth(64 * 1024);
+            });
+            let value = [0u8; 1024];
+            for i in 0..10000u32 {
+                let key = black_box(i.to_le_bytes());
+                let _ = hash.insert(&key, black_box(&value));
+            }
+        })
+    });
+}
+
 criterion_group!(
     name = crud_benches;
     config = Criterion::default().sample_size(10).measurement_time(Duration::from_secs(30));
-    targets = bench_level_insert, bench_level_lookup, bench_level_delete, bench_level_insert_auto_expand
+    targets = bench_level_insert, bench_level_lookup, bench_level_delete, bench_level_insert_auto_expand,
+        bench_level_insert_growing_values
 );
 criterion_main!(crud_benches);
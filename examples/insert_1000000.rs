@@ -1,26 +1,15 @@
 use std::path::Path;
 
-use gxhash::GxHasher;
-use level_hash::{util::generate_seeds, LevelHash};
-use std::hash::Hasher;
-
-fn gxhash(seed: u64, data: &[u8]) -> u64 {
-    let mut hasher = GxHasher::with_seed(seed as i64);
-    hasher.write(data);
-    hasher.finish()
-}
+use level_hash::LevelHash;
 
 fn main() {
     let index_dir = Path::new("target");
-    let (seed_1, seed_2) = generate_seeds();
     let mut hash = LevelHash::options()
         .auto_expand(true)
         .bucket_size(10)
         .level_size(13)
         .index_dir(&index_dir)
         .index_name("insert-1000000")
-        .seeds(seed_1, seed_2)
-        .hash_fns(self::gxhash, self::gxhash)
         .build()
         .expect("failed to create level hash");
 
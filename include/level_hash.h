@@ -0,0 +1,107 @@
+#ifndef LEVEL_HASH_H
+#define LEVEL_HASH_H
+
+/* Generated with cbindgen, do not edit this file manually. */
+
+#include <stdint.h>
+
+// The operation completed successfully.
+#define LEVELHASH_OK 0
+
+// There is no entry for the given key.
+#define LEVELHASH_NOT_FOUND -1
+
+// One of the arguments is invalid (e.g. a null pointer or an empty key).
+#define LEVELHASH_ERR_INVALID_ARG -2
+
+// The entry could not be written to the level hash.
+#define LEVELHASH_ERR_WRITE -3
+
+// The entry could not be read from the level hash (e.g. the index files are corrupt).
+#define LEVELHASH_ERR_IO -4
+
+// The level hash panicked during the call. The level hash may be in an inconsistent state and
+// should be closed.
+#define LEVELHASH_ERR_PANIC -5
+
+typedef struct LevelHash LevelHash;
+
+// Open the level hash with the given name in the given directory, creating it if it does not
+// exist.
+//
+// ## Parameters
+//
+// * `index_dir` - The NUL-terminated path of the directory of the index.
+// * `index_name` - The NUL-terminated name of the index.
+//
+// ## Returns
+//
+// The pointer to the level hash, or a null pointer if the level hash could not be opened. The
+// level hash must be released with [levelhash_close].
+//
+// # Safety
+//
+// `index_dir` and `index_name` must be null or point to valid NUL-terminated strings.
+LevelHash *levelhash_open(const char *index_dir, const char *index_name);
+
+// Get the value associated with the given key. The value is copied to `value` only if it fits in
+// `value_cap` bytes, so the buffer can be resized and the call can be repeated if the returned
+// size is larger than `value_cap`.
+//
+// ## Parameters
+//
+// * `hash` - The level hash returned by [levelhash_open].
+// * `key`, `key_len` - The key to get the value for.
+// * `value`, `value_cap` - The buffer to copy the value to, and its capacity.
+//
+// ## Returns
+//
+// The size of the value, [LEVELHASH_NOT_FOUND] if there is no entry for the key,
+// [LEVELHASH_ERR_INVALID_ARG] if the arguments are invalid, [LEVELHASH_ERR_IO] if the entry
+// could not be read, or [LEVELHASH_ERR_PANIC].
+//
+// # Safety
+//
+// `hash` must be a pointer returned by [levelhash_open] which has not been closed. `key` must
+// point to `key_len` readable bytes, and `value` must be null or point to `value_cap` writable
+// bytes.
+int64_t levelhash_get(const LevelHash *hash,
+                      const uint8_t *key,
+                      uintptr_t key_len,
+                      uint8_t *value,
+                      uintptr_t value_cap);
+
+// Insert the given key-value pair in the level hash, replacing the value of the existing entry
+// for the key, if any.
+//
+// ## Parameters
+//
+// * `hash` - The level hash returned by [levelhash_open].
+// * `key`, `key_len` - The key for the entry. This must not be empty.
+// * `value`, `value_len` - The value for the entry.
+//
+// ## Returns
+//
+// [LEVELHASH_OK] if the entry was written, [LEVELHASH_ERR_INVALID_ARG] if the arguments are
+// invalid, [LEVELHASH_ERR_WRITE] if the entry could not be written, or [LEVELHASH_ERR_PANIC].
+//
+// # Safety
+//
+// `hash` must be a pointer returned by [levelhash_open] which has not been closed. `key` must
+// point to `key_len` readable bytes, and `value` must point to `value_len` readable bytes (it may
+// be null if `value_len` is `0`).
+int levelhash_put(LevelHash *hash,
+                  const uint8_t *key,
+                  uintptr_t key_len,
+                  const uint8_t *value,
+                  uintptr_t value_len);
+
+// Close the level hash, syncing the changes to the disk. The pointer must not be used after
+// this call.
+//
+// # Safety
+//
+// `hash` must be null or a pointer returned by [levelhash_open] which has not been closed.
+void levelhash_close(LevelHash *hash);
+
+#endif  /* LEVEL_HASH_H */
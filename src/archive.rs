@@ -0,0 +1,248 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The portable archive format used by [LevelHash::export][crate::LevelHash::export] and
+//! [LevelHash::import][crate::LevelHash::import].
+//!
+//! The index files are written in the native byte order with the layout of the in-memory structs,
+//! so they cannot be copied to a device with a different architecture. The archive contains only
+//! the entries of the level hash, and can be imported into any level hash regardless of its
+//! configuration.
+//!
+//! Structure of the archive file :
+//!
+//! ```text
+//! archive {
+//!   u64 magic_number;
+//!   u8 version;
+//!   record records[];
+//!   u32 end_marker;    // 0, which is never the size of a key
+//!   u64 record_count;
+//! }
+//! record {
+//!   u32 key_size;
+//!   u64 value_size;
+//!   u64 expires_at;    // seconds since the Unix epoch, 0 if the entry does not expire
+//!   u8 key[key_size];
+//!   u8 value[value_size];
+//! }
+//! ```
+//!
+//! All the fields are little-endian.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use crate::result::IntoLevelIOErr;
+use crate::result::LevelArchiveError;
+use crate::result::LevelResult;
+use crate::result::StdIOError;
+
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+
+/// Magic number that is used as the file signature to identify the archive files.
+pub const ARCHIVE_MAGIC_NUMBER: u64 = 0x4149444C4841;
+
+/// The version of the archive file format.
+pub const ARCHIVE_VERSION: u8 = 1;
+
+/// An entry in the archive.
+///
+/// # Properties
+///
+/// * `key`: The key of the entry.
+/// * `value`: The value of the entry.
+/// * `expires_at`: The time (seconds since the Unix epoch) at which the entry expires, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ArchiveRecord {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub expires_at: Option<u64>,
+}
+
+/// Writes the entries of a level hash to an archive file.
+#[derive(Debug)]
+pub(crate) struct ArchiveWriter {
+    out: BufWriter<File>,
+    records: u64,
+}
+
+impl ArchiveWriter {
+    /// Create (or truncate) the archive file at the given path and write the header to it.
+    pub fn create(path: &Path) -> LevelResult<Self, StdIOError> {
+        let file = File::create(path)
+            .into_lvl_io_e_msg(format!("failed to create archive: {}", path.display()))?;
+
+        let mut writer = Self {
+            out: BufWriter::new(file),
+            records: 0,
+        };
+
+        writer
+            .out
+            .write_u64::<LittleEndian>(ARCHIVE_MAGIC_NUMBER)
+            .and_then(|_| writer.out.write_u8(ARCHIVE_VERSION))
+            .into_lvl_io_e_msg(format!(
+                "failed to write archive header: {}",
+                path.display()
+            ))?;
+
+        Ok(writer)
+    }
+
+    /// Write an entry to the archive.
+    pub fn write(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> LevelResult<(), StdIOError> {
+        self.write_record(key, value, expires_at)
+            .into_lvl_io_e_msg("failed to write archive record".to_string())?;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn write_record(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> std::io::Result<()> {
+        self.out.write_u32::<LittleEndian>(key.len() as u32)?;
+        self.out.write_u64::<LittleEndian>(value.len() as u64)?;
+        self.out
+            .write_u64::<LittleEndian>(expires_at.unwrap_or(0))?;
+        self.out.write_all(key)?;
+        self.out.write_all(value)
+    }
+
+    /// Write the end of the archive and flush it to the file.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries in the archive.
+    pub fn finish(mut self) -> LevelResult<u64, StdIOError> {
+        self.out
+            .write_u32::<LittleEndian>(0)
+            .and_then(|_| self.out.write_u64::<LittleEndian>(self.records))
+            .and_then(|_| self.out.flush())
+            .and_then(|_| self.out.get_ref().sync_all())
+            .into_lvl_io_e_msg("failed to finish archive".to_string())?;
+
+        Ok(self.records)
+    }
+}
+
+/// Reads the entries from an archive file.
+#[derive(Debug)]
+pub(crate) struct ArchiveReader {
+    input: BufReader<File>,
+    records: u64,
+}
+
+impl ArchiveReader {
+    /// Open the archive file at the given path and verify its header.
+    pub fn open(path: &Path) -> LevelResult<Self, LevelArchiveError> {
+        let file = File::open(path)
+            .into_lvl_io_e_msg(format!("failed to open archive: {}", path.display()))?;
+        let mut input = BufReader::new(file);
+
+        let magic = input
+            .read_u64::<LittleEndian>()
+            .into_lvl_io_e_msg("failed to read archive header".to_string())?;
+        if magic != ARCHIVE_MAGIC_NUMBER {
+            return Err(LevelArchiveError::InvalidArchive(format!(
+                "magic number mismatch: {} != {}",
+                magic, ARCHIVE_MAGIC_NUMBER
+            )));
+        }
+
+        let version = input
+            .read_u8()
+            .into_lvl_io_e_msg("failed to read archive header".to_string())?;
+        if version != ARCHIVE_VERSION {
+            return Err(LevelArchiveError::InvalidArchive(format!(
+                "unsupported archive version: {}",
+                version
+            )));
+        }
+
+        Ok(Self { input, records: 0 })
+    }
+
+    /// Read the next entry from the archive.
+    ///
+    /// ## Returns
+    ///
+    /// The next entry, or `None` if the end of the archive has been reached.
+    pub fn next_record(&mut self) -> LevelResult<Option<ArchiveRecord>, LevelArchiveError> {
+        let key_size = self
+            .input
+            .read_u32::<LittleEndian>()
+            .into_lvl_io_e_msg("truncated archive".to_string())?;
+
+        if key_size == 0 {
+            let records = self
+                .input
+                .read_u64::<LittleEndian>()
+                .into_lvl_io_e_msg("truncated archive".to_string())?;
+            if records != self.records {
+                return Err(LevelArchiveError::InvalidArchive(format!(
+                    "record count mismatch: {} != {}",
+                    self.records, records
+                )));
+            }
+
+            return Ok(None);
+        }
+
+        let mut record = || -> std::io::Result<ArchiveRecord> {
+            let value_size = self.input.read_u64::<LittleEndian>()?;
+            let expires_at = self.input.read_u64::<LittleEndian>()?;
+            let key = read_bytes(&mut self.input, key_size as u64)?;
+            let value = read_bytes(&mut self.input, value_size)?;
+            Ok(ArchiveRecord {
+                key,
+                value,
+                expires_at: (expires_at != 0).then_some(expires_at),
+            })
+        };
+
+        let record = record().into_lvl_io_e_msg("truncated archive record".to_string())?;
+        self.records += 1;
+        Ok(Some(record))
+    }
+}
+
+/// Read exactly `size` bytes from the input. The buffer grows as the bytes are read, so that a
+/// corrupt size fails with an EOF error instead of allocating a huge buffer up front.
+fn read_bytes(input: &mut impl Read, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let read = input.take(size).read_to_end(&mut bytes)?;
+    if (read as u64) < size {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(bytes)
+}
@@ -0,0 +1,195 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An asynchronous API on top of [LevelHash], for use with the [tokio] runtime.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::result::LevelAsyncError;
+use crate::result::LevelAsyncResult;
+use crate::types::LevelKeyT;
+use crate::types::LevelValueT;
+use crate::LevelHash;
+
+use tokio::task;
+
+/// A [LevelHash] whose operations are performed on the blocking thread pool of the tokio runtime,
+/// so that touching the memory-mapped files never blocks the async tasks. The operations return
+/// futures which complete once the operation has been performed.
+///
+/// [AsyncLevelHash] can be cloned cheaply, all the clones share the same level hash. The operations
+/// on the level hash are serialized.
+#[derive(Clone)]
+pub struct AsyncLevelHash {
+    hash: Arc<Mutex<LevelHash>>,
+}
+
+impl AsyncLevelHash {
+    /// Create a new [AsyncLevelHash] which performs its operations on the given level hash.
+    pub fn new(hash: LevelHash) -> Self {
+        Self {
+            hash: Arc::new(Mutex::new(hash)),
+        }
+    }
+
+    /// Get the value associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the value if an entry is found, an empty [Vec] otherwise.
+    pub async fn get_value(&self, key: &LevelKeyT) -> LevelAsyncResult<Vec<u8>> {
+        let key = key.to_vec();
+        self.run(move |hash| hash.get_value(&key)).await
+    }
+
+    /// Insert the given key-value pair in the level hash.
+    pub async fn insert(&self, key: &LevelKeyT, value: &LevelValueT) -> LevelAsyncResult<()> {
+        let key = key.to_vec();
+        let value = value.to_vec();
+        Ok(self.run(move |hash| hash.insert(&key, &value)).await??)
+    }
+
+    /// Update the entry associated with the given key with the new value.
+    ///
+    /// ## Returns
+    ///
+    /// The previous value of the entry.
+    pub async fn update(
+        &self,
+        key: &LevelKeyT,
+        new_value: &LevelValueT,
+    ) -> LevelAsyncResult<Vec<u8>> {
+        let key = key.to_vec();
+        let new_value = new_value.to_vec();
+        Ok(self
+            .run(move |hash| hash.update(&key, &new_value))
+            .await??)
+    }
+
+    /// Remove the entry associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the value of the removed entry if found, `None` otherwise.
+    pub async fn remove(&self, key: &LevelKeyT) -> LevelAsyncResult<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        self.run(move |hash| hash.remove(&key)).await
+    }
+
+    /// Perform the given operation on the level hash on the blocking thread pool. This can be used
+    /// for the operations which are not exposed by [AsyncLevelHash], like [LevelHash::expand].
+    ///
+    /// ## Parameters
+    ///
+    /// * `op` - The operation to perform.
+    ///
+    /// ## Returns
+    ///
+    /// The result of the operation, or [LevelAsyncError::TaskError] if the operation panicked.
+    pub async fn run<F, R>(&self, op: F) -> LevelAsyncResult<R>
+    where
+        F: FnOnce(&mut LevelHash) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let hash = Arc::clone(&self.hash);
+        task::spawn_blocking(move || {
+            let mut hash = hash.lock().expect("level hash mutex is poisoned");
+            op(&mut hash)
+        })
+        .await
+        .map_err(LevelAsyncError::TaskError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::hash::Hasher;
+    use std::path::Path;
+
+    use gxhash::GxHasher;
+
+    use crate::async_hash::AsyncLevelHash;
+    use crate::LevelHash;
+
+    fn gxhash(seed: u64, data: &[u8]) -> u64 {
+        let mut hasher = GxHasher::with_seed(seed as i64);
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    fn create_async_level_hash(name: &str) -> AsyncLevelHash {
+        let dir_path = format!("target/tests/level-hash/async-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+
+        let hash = LevelHash::options()
+            .index_dir(index_dir)
+            .index_name(name)
+            .level_size(2)
+            .bucket_size(4)
+            .hash_fns(self::gxhash, self::gxhash)
+            .build()
+            .expect("failed to create level hash");
+
+        AsyncLevelHash::new(hash)
+    }
+
+    #[tokio::test]
+    async fn async_insert_get_update_remove() {
+        let hash = create_async_level_hash("insert-get-update-remove");
+
+        assert_eq!(hash.get_value(b"key1").await.unwrap(), vec![]);
+
+        hash.insert(b"key1", b"value1").await.unwrap();
+        assert_eq!(hash.get_value(b"key1").await.unwrap(), b"value1".to_vec());
+
+        assert_eq!(
+            hash.update(b"key1", b"value2").await.unwrap(),
+            b"value1".to_vec()
+        );
+
+        let other = hash.clone();
+        assert_eq!(other.get_value(b"key1").await.unwrap(), b"value2".to_vec());
+
+        assert_eq!(
+            hash.remove(b"key1").await.unwrap(),
+            Some(b"value2".to_vec())
+        );
+        assert_eq!(other.remove(b"key1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn async_run() {
+        let hash = create_async_level_hash("run");
+        hash.insert(b"key1", b"value1").await.unwrap();
+
+        let bucket_count = hash
+            .run(|hash| {
+                hash.expand().expect("failed to expand level hash");
+                hash.total_bucket_count()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(bucket_count, 8 + 4);
+        assert_eq!(hash.get_value(b"key1").await.unwrap(), b"value1".to_vec());
+    }
+}
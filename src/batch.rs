@@ -0,0 +1,220 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Batches of write operations which are applied to a [LevelHash] together.
+
+use crate::result::LevelBatchResult;
+use crate::types::LevelKeyT;
+use crate::types::LevelValueT;
+use crate::LevelHash;
+
+/// A write operation buffered in a [WriteBatch].
+pub(crate) enum BatchOp {
+    Insert {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        // the expiry timestamp of an entry which is inserted again when the batch is rolled back
+        expires_at: Option<u64>,
+    },
+    Update {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Remove {
+        key: Vec<u8>,
+    },
+}
+
+/// A batch of insertions, updates and removals, created with [LevelHash::begin_batch]. The
+/// operations are buffered in memory and are not visible in the level hash until the batch is
+/// committed with [WriteBatch::commit]. Dropping the batch without committing it discards the
+/// buffered operations.
+///
+/// When the batch is committed, the operations are applied in the order they were added and the
+/// changes are synced once (according to the [SyncPolicy][crate::SyncPolicy] of the level hash)
+/// instead of after every operation. If one of the operations fails, the operations which were
+/// applied before it are rolled back.
+pub struct WriteBatch<'a> {
+    hash: &'a mut LevelHash,
+    ops: Vec<BatchOp>,
+}
+
+impl<'a> WriteBatch<'a> {
+    pub(crate) fn new(hash: &'a mut LevelHash) -> Self {
+        Self {
+            hash,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Add the insertion of the given key-value pair to the batch. See [LevelHash::insert].
+    pub fn insert(&mut self, key: &LevelKeyT, value: &LevelValueT) -> &mut Self {
+        self.ops.push(BatchOp::Insert {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            expires_at: None,
+        });
+        self
+    }
+
+    /// Add the update of the entry for the given key to the batch. The batch fails to commit if
+    /// there is no entry for the key when the update is applied. See [LevelHash::update].
+    pub fn update(&mut self, key: &LevelKeyT, new_value: &LevelValueT) -> &mut Self {
+        self.ops.push(BatchOp::Update {
+            key: key.to_vec(),
+            value: new_value.to_vec(),
+        });
+        self
+    }
+
+    /// Add the removal of the entry for the given key to the batch. Removing a key which has no
+    /// entry is not an error. See [LevelHash::remove].
+    pub fn remove(&mut self, key: &LevelKeyT) -> &mut Self {
+        self.ops.push(BatchOp::Remove { key: key.to_vec() });
+        self
+    }
+
+    /// Get the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether the batch has no operations.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Apply the operations of the batch to the level hash.
+    ///
+    /// ## Returns
+    ///
+    /// `Ok(())` if all the operations were applied. If an operation fails, the operations applied
+    /// before it are rolled back and the error of the failed operation is returned along with its
+    /// index in the batch. If the changes cannot be synced after all the operations have been
+    /// applied, the changes are kept and the I/O error is returned.
+    pub fn commit(self) -> LevelBatchResult {
+        self.hash.apply_batch(self.ops)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::result::LevelBatchError;
+    use crate::result::LevelUpdateError;
+    use crate::test_macros::assert_matches;
+    use crate::LevelHash;
+
+    fn batch_level_hash(name: &str, unique_keys: bool) -> LevelHash {
+        let dir_path = format!("target/tests/level-hash/batch-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+
+        LevelHash::options()
+            .index_dir(index_dir)
+            .index_name(name)
+            .level_size(2)
+            .bucket_size(4)
+            .unique_keys(unique_keys)
+            .build()
+            .expect("Failed to create level hash")
+    }
+
+    #[test]
+    fn batch_commit() {
+        let mut hash = batch_level_hash("commit", true);
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+
+        let mut batch = hash.begin_batch();
+        batch
+            .insert(b"key3", b"value3")
+            .update(b"key1", b"value1.1")
+            .remove(b"key2")
+            .remove(b"key4");
+        assert_eq!(batch.len(), 4);
+        batch.commit().unwrap();
+
+        assert_eq!(hash.get_value(b"key1"), b"value1.1");
+        assert!(!hash.contains_key(b"key2"));
+        assert_eq!(hash.get_value(b"key3"), b"value3");
+        assert_eq!(hash.keys().count(), 2);
+
+        // the operations of a dropped batch are discarded
+        let mut batch = hash.begin_batch();
+        batch.remove(b"key1").insert(b"key5", b"value5");
+        drop(batch);
+        assert_eq!(hash.get_value(b"key1"), b"value1.1");
+        assert!(!hash.contains_key(b"key5"));
+
+        assert!(hash.begin_batch().is_empty());
+        hash.begin_batch().commit().unwrap();
+    }
+
+    #[test]
+    fn batch_rollback() {
+        let mut hash = batch_level_hash("rollback", true);
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+        let fingerprint = hash.content_fingerprint();
+
+        let mut batch = hash.begin_batch();
+        batch
+            .insert(b"key3", b"value3")
+            .update(b"key1", b"value1.1")
+            .remove(b"key2")
+            .update(b"key4", b"value4");
+        assert_matches!(
+            batch.commit(),
+            Err(LevelBatchError::UpdateError {
+                index: 3,
+                error: LevelUpdateError::SlotNotFound
+            })
+        );
+
+        assert_eq!(hash.get_value(b"key1"), b"value1");
+        assert_eq!(hash.get_value(b"key2"), b"value2");
+        assert!(!hash.contains_key(b"key3"));
+        assert_eq!(hash.keys().count(), 2);
+        assert_eq!(hash.content_fingerprint(), fingerprint);
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn batch_rollback_duplicate_keys() {
+        let mut hash = batch_level_hash("rollback-duplicates", false);
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key1", b"value2").unwrap();
+
+        let mut batch = hash.begin_batch();
+        batch
+            .remove(b"key1")
+            .insert(b"key1", b"value3")
+            .insert(b"", b"value4");
+        assert_matches!(
+            batch.commit(),
+            Err(LevelBatchError::InsertionError { index: 2, .. })
+        );
+
+        assert_eq!(hash.get_all(b"key1"), [b"value2", b"value1"]);
+        assert!(hash.verify().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A command line tool to inspect and modify the level hash indexes, e.g. to debug an index which
+//! was reported as corrupt. Only available with the `cli` feature.
+//!
+//! The indexes created with other than the default hash functions cannot be opened, as the hash
+//! functions are not stored in the index.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+
+use level_hash::LevelHash;
+
+const USAGE: &str = "\
+usage: levelhash-cli <command> <index-dir> <index-name> [args]
+
+commands:
+  inspect              print the layout and the capacity of the index
+  dump                 print the occupied slots of the keymap and their entries
+  get <key>            write the value of the given key to the standard output
+  put <key> <value>    insert or replace the value of the given key
+  verify               check the index for inconsistencies
+  compact              compact the values file of the index
+  stats                print the statistics and the health of the index";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [command, index_dir, index_name, args @ ..] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    match run(command, Path::new(index_dir), index_name, args) {
+        Ok(code) => code,
+        Err(why) => {
+            eprintln!("levelhash-cli: {}", why);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(
+    command: &str,
+    index_dir: &Path,
+    index_name: &str,
+    args: &[String],
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let read_only = !matches!(command, "put" | "compact");
+    let open = || {
+        LevelHash::options()
+            .index_dir(index_dir)
+            .index_name(index_name)
+            .read_only(read_only)
+            .open()
+    };
+
+    match (command, args) {
+        ("inspect", []) => inspect(&open()?),
+        ("dump", []) => dump(&open()?),
+        ("get", [key]) => {
+            let Some(value) = open()?.try_get_value(key.as_bytes())? else {
+                eprintln!("levelhash-cli: no entry for the key '{}'", key);
+                return Ok(ExitCode::FAILURE);
+            };
+            std::io::stdout().write_all(&value)?;
+        }
+        ("put", [key, value]) => {
+            let mut hash = open()?;
+            if hash.contains_key(key.as_bytes()) {
+                hash.update(key.as_bytes(), value.as_bytes())?;
+            } else {
+                hash.insert(key.as_bytes(), value.as_bytes())?;
+            }
+            hash.flush()?;
+        }
+        ("verify", []) => {
+            let found = open()?.verify();
+            for inconsistency in &found {
+                println!("{:?}", inconsistency);
+            }
+            println!("{} inconsistencies found", found.len());
+            if !found.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        ("compact", []) => {
+            let mut hash = open()?;
+            let reclaimed = hash.compact()?;
+            hash.flush()?;
+            println!("reclaimed {} bytes", reclaimed);
+        }
+        ("stats", []) => {
+            let hash = open()?;
+            println!("{:#?}", hash.stats());
+            println!("{:#?}", hash.health());
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn inspect(hash: &LevelHash) {
+    println!("level count: {}", hash.level_count());
+    println!("level size: {}", hash.level_size());
+    println!("bucket size: {}", hash.bucket_size());
+    println!("total slots: {}", hash.total_slots());
+    println!("load factor: {:.3}", hash.load_factor());
+    println!("values growth: {}", hash.values_growth());
+    println!("generation: {}", hash.generation());
+    println!("fingerprint: {:#018x}", hash.content_fingerprint());
+    println!("{:#?}", hash.capacity_info());
+}
+
+fn dump(hash: &LevelHash) {
+    hash.for_each_slot(|level, bucket, slot, entry| {
+        let Some(entry) = entry else {
+            return;
+        };
+
+        println!(
+            "{:?}/{}/{} @{} ({} bytes): {} = {}",
+            level,
+            bucket,
+            slot,
+            entry.addr(),
+            entry.size(),
+            escape(&entry.key()),
+            escape(&entry.value())
+        );
+    });
+}
+
+/// Escape the given bytes for printing, as they may not be valid UTF-8.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
@@ -0,0 +1,183 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs::remove_file;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::path::Path;
+
+use crate::fs::ftruncate_safe;
+use crate::fs::init_sparse_file;
+use crate::io::MappedFile;
+use crate::log_macros::log_warn;
+use crate::result::IntoLevelIOErr;
+use crate::result::IntoLevelInitErr;
+use crate::result::LevelInitError;
+use crate::result::LevelMapError;
+use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::size::SIZE_U64;
+use crate::types::OffT;
+use crate::util::align_8;
+
+/// A bloom filter of the keys in the level hash, which is stored in a memory-mapped sidecar file.
+/// The bit positions of a key are derived from the two hashes of the key that are used to find
+/// its buckets (double hashing), so no additional hashing is required.
+///
+/// Bits are never cleared when an entry is removed. The removed keys remain false positives until
+/// the filter is rebuilt, which happens when the level hash is expanded, shrunk or cleared.
+#[derive(Debug)]
+pub(crate) struct BloomFilter {
+    file: MappedFile,
+}
+
+impl BloomFilter {
+    /// Magic number that is used as the file signature to identify the bloom filter file.
+    pub const MAGIC_NUMBER: u64 = 0x4149445842;
+
+    /// The size of the header (bytes) in the bloom filter file.
+    pub const HEADER_SIZE_BYTES: u64 = SIZE_U64;
+
+    /// The number of bits in the filter for each slot in the level hash.
+    pub const BITS_PER_SLOT: u64 = 10;
+
+    /// The number of bits that are set for each key.
+    pub const HASH_COUNT: u64 = 7;
+
+    /// Open (or create) the bloom filter file at the given path.
+    pub fn new(path: &Path) -> LevelResult<Self, LevelInitError> {
+        match init_sparse_file(path, Some(Self::MAGIC_NUMBER)) {
+            // the filter is rebuilt from the values file, so a file with a bad magic number can
+            // be replaced safely
+            Err(LevelInitError::IOError(why)) if why.error.kind() == ErrorKind::InvalidData => {
+                log_warn!("replacing the bloom filter: {}", why);
+                remove_file(path)
+                    .into_lvl_io_e_msg(format!("failed to delete file: {}", path.display()))?;
+                init_sparse_file(path, Some(Self::MAGIC_NUMBER))?;
+            }
+            result => result?,
+        }
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(path)
+            .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))
+            .into_lvl_init_err()?;
+
+        Self::from_file(file)
+    }
+
+    /// Create a new [BloomFilter] from the given (already opened) file. The filter is empty if the
+    /// file does not contain a bit array yet.
+    pub fn from_file(file: File) -> LevelResult<Self, LevelInitError> {
+        let len = file
+            .metadata()
+            .into_lvl_io_e_msg("failed to read bloom filter metadata".to_string())
+            .into_lvl_init_err()?
+            .len();
+
+        let size = align_8(len.saturating_sub(Self::HEADER_SIZE_BYTES));
+        ftruncate_safe(file.as_raw_fd(), Self::HEADER_SIZE_BYTES + size)
+            .map_err(LevelMapError::TruncateFailed)
+            .into_lvl_init_err()?;
+
+        let file =
+            MappedFile::new(file.into(), Self::HEADER_SIZE_BYTES, size).into_lvl_init_err()?;
+
+        Ok(Self { file })
+    }
+
+    /// Get the size (bytes) of the bit array for a level hash with the given number of slots.
+    pub fn size_for(slots: u64) -> OffT {
+        align_8((slots * Self::BITS_PER_SLOT).div_ceil(8)).max(SIZE_U64)
+    }
+
+    /// Get the size (bytes) of the bit array.
+    #[inline]
+    pub fn size(&self) -> OffT {
+        self.file.size
+    }
+
+    /// Remove all the keys from the filter and resize its bit array to the given size (bytes).
+    pub fn reset(&mut self, size: OffT) -> LevelResult<(), LevelMapError> {
+        let fd = self.file.fd.as_raw_fd();
+
+        // truncating the file to its header zeroes the bit array
+        ftruncate_safe(fd, Self::HEADER_SIZE_BYTES).map_err(LevelMapError::TruncateFailed)?;
+        ftruncate_safe(fd, Self::HEADER_SIZE_BYTES + size)
+            .map_err(LevelMapError::TruncateFailed)?;
+
+        self.file.map =
+            MappedFile::do_map(&self.file.fd, Self::HEADER_SIZE_BYTES, size, self.file.opts)?;
+        self.file.size = size;
+
+        Ok(())
+    }
+
+    /// Get the positions of the bits for the key with the given hashes.
+    fn bits(&self, fhash: u64, shash: u64) -> impl Iterator<Item = u64> {
+        let bit_count = self.file.size * 8;
+        (0..Self::HASH_COUNT).map(move |i| fhash.wrapping_add(i.wrapping_mul(shash)) % bit_count)
+    }
+
+    /// Add the key with the given hashes to the filter.
+    pub fn insert(&mut self, fhash: u64, shash: u64) {
+        if self.file.size == 0 {
+            return;
+        }
+
+        let bits = self.bits(fhash, shash).collect::<Vec<_>>();
+        for bit in bits {
+            let off = (bit / 64) * SIZE_U64;
+            let word = self.file.r_u64(off);
+            self.file.w_u64(off, word | (1 << (bit % 64)));
+        }
+    }
+
+    /// Check whether the key with the given hashes may be present in the filter. The key is
+    /// definitely not present if this returns `false`.
+    pub fn may_contain(&self, fhash: u64, shash: u64) -> bool {
+        if self.file.size == 0 {
+            return true;
+        }
+
+        self.bits(fhash, shash).all(|bit| {
+            let word = self.file.r_u64((bit / 64) * SIZE_U64);
+            word & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// Get the descriptor of the bloom filter file.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.file.fd.as_fd()
+    }
+
+    /// Schedule the changes made to the filter to be written to the file.
+    pub fn flush_async(&self) -> LevelResult<(), StdIOError> {
+        self.file.flush_async()
+    }
+
+    /// Write the changes made to the filter to the file, waiting for the writes to complete.
+    pub fn flush(&self) -> LevelResult<(), StdIOError> {
+        self.file.flush()
+    }
+}
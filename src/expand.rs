@@ -0,0 +1,160 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Policies which determine when a [LevelHash] expands automatically. The policy is set with
+//! [LevelHashOptions::expand_policy](crate::LevelHashOptions::expand_policy).
+
+use std::time::Duration;
+
+use crate::LevelHash;
+
+/// The policy which determines when a [LevelHash] expands automatically. The policy is consulted
+/// at two points of an insertion : before the slot for the new entry is looked up, and after the
+/// entry could not be inserted because both of its buckets are full and none of the entries
+/// could be moved. In the latter case, the insertion is retried once after the level hash is
+/// expanded.
+pub trait ExpandPolicy: Send {
+    /// Check whether the level hash must be expanded before an entry is inserted.
+    fn expand_before_insert(&mut self, hash: &LevelHash) -> bool;
+
+    /// Check whether the level hash must be expanded after an entry could not be inserted.
+    fn expand_on_failure(&mut self, hash: &LevelHash) -> bool;
+
+    /// Get the load factor at which the level hash is expanded, if the policy expands based on
+    /// the load factor. This is used to decide whether [LevelHash::shrink] would cause an
+    /// expansion and whether [LevelHash::health] recommends expansion, and is recorded in the
+    /// operation traces.
+    fn load_factor_threshold(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// The progress of an expansion, reported by [LevelHash::expand_with_progress] after every few
+/// buckets of the bottom level are rehashed.
+///
+/// # Properties
+///
+/// * `buckets_moved`: The number of buckets of the bottom level whose entries have been moved to
+///   the new top level.
+/// * `total_buckets`: The number of buckets in the bottom level.
+/// * `estimated_remaining`: The estimated time to move the remaining buckets, based on the rate at
+///   which the buckets have been moved so far. This is `None` until the first buckets are moved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExpandProgress {
+    pub buckets_moved: u32,
+    pub total_buckets: u32,
+    pub estimated_remaining: Option<Duration>,
+}
+
+impl ExpandProgress {
+    /// Get the fraction of the buckets which have been moved, between `0.0` and `1.0`.
+    pub fn fraction(&self) -> f32 {
+        if self.total_buckets == 0 {
+            return 1.0;
+        }
+
+        self.buckets_moved as f32 / self.total_buckets as f32
+    }
+
+    /// Whether all the buckets have been moved.
+    pub fn is_done(&self) -> bool {
+        self.buckets_moved == self.total_buckets
+    }
+}
+
+/// Expand the level hash before an insertion when its load factor reaches the given threshold, or
+/// when an entry cannot be inserted before the threshold is reached. This is the default policy,
+/// with [LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT](crate::LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT).
+#[derive(Debug, Copy, Clone)]
+pub struct ExpandAtLoadFactor(pub f32);
+
+impl ExpandPolicy for ExpandAtLoadFactor {
+    fn expand_before_insert(&mut self, hash: &LevelHash) -> bool {
+        hash.load_factor() >= self.0
+    }
+
+    fn expand_on_failure(&mut self, _hash: &LevelHash) -> bool {
+        true
+    }
+
+    fn load_factor_threshold(&self) -> Option<f32> {
+        Some(self.0)
+    }
+}
+
+/// Expand the level hash only when an entry cannot be inserted.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ExpandOnFailure;
+
+impl ExpandPolicy for ExpandOnFailure {
+    fn expand_before_insert(&mut self, _hash: &LevelHash) -> bool {
+        false
+    }
+
+    fn expand_on_failure(&mut self, _hash: &LevelHash) -> bool {
+        true
+    }
+}
+
+/// Expand the level hash when the given number of insertions have failed since the last expansion.
+/// The insertions before the last one fail with an error.
+#[derive(Debug, Copy, Clone)]
+pub struct ExpandAfterFailures {
+    failures: u32,
+    failed: u32,
+}
+
+impl ExpandAfterFailures {
+    /// Create a new policy which expands the level hash after `failures` insertions fail.
+    pub fn new(failures: u32) -> Self {
+        Self {
+            failures: failures.max(1),
+            failed: 0,
+        }
+    }
+}
+
+impl ExpandPolicy for ExpandAfterFailures {
+    fn expand_before_insert(&mut self, _hash: &LevelHash) -> bool {
+        false
+    }
+
+    fn expand_on_failure(&mut self, _hash: &LevelHash) -> bool {
+        self.failed += 1;
+        if self.failed < self.failures {
+            return false;
+        }
+
+        self.failed = 0;
+        true
+    }
+}
+
+/// Never expand the level hash automatically. The level hash can still be expanded with
+/// [LevelHash::expand].
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NeverExpand;
+
+impl ExpandPolicy for NeverExpand {
+    fn expand_before_insert(&mut self, _hash: &LevelHash) -> bool {
+        false
+    }
+
+    fn expand_on_failure(&mut self, _hash: &LevelHash) -> bool {
+        false
+    }
+}
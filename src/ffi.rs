@@ -0,0 +1,359 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! C bindings for [LevelHash], so that the index can be used from other languages (e.g. through
+//! JNI) without a separate wrapper crate. The header for these functions is `include/level_hash.h`,
+//! which is generated with `cbindgen --config cbindgen.toml --output include/level_hash.h`.
+//!
+//! The level hash is passed to the functions as an opaque pointer which is returned by
+//! [levelhash_open] and must be released with [levelhash_close]. The keys and the values are
+//! passed as pointers to their bytes along with their sizes.
+//!
+//! A panic never unwinds into the caller. The functions return [LEVELHASH_ERR_PANIC] (or a null
+//! pointer from [levelhash_open]) instead, after which the level hash should only be closed.
+
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::CStr;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use crate::log_macros::log_error;
+use crate::LevelHash;
+
+/// The operation completed successfully.
+pub const LEVELHASH_OK: c_int = 0;
+
+/// There is no entry for the given key.
+pub const LEVELHASH_NOT_FOUND: c_int = -1;
+
+/// One of the arguments is invalid (e.g. a null pointer or an empty key).
+pub const LEVELHASH_ERR_INVALID_ARG: c_int = -2;
+
+/// The entry could not be written to the level hash.
+pub const LEVELHASH_ERR_WRITE: c_int = -3;
+
+/// The entry could not be read from the level hash (e.g. the index files are corrupt).
+pub const LEVELHASH_ERR_IO: c_int = -4;
+
+/// The level hash panicked during the call. The level hash may be in an inconsistent state and
+/// should be closed.
+pub const LEVELHASH_ERR_PANIC: c_int = -5;
+
+/// Open the level hash with the given name in the given directory, creating it if it does not
+/// exist.
+///
+/// ## Parameters
+///
+/// * `index_dir` - The NUL-terminated path of the directory of the index.
+/// * `index_name` - The NUL-terminated name of the index.
+///
+/// ## Returns
+///
+/// The pointer to the level hash, or a null pointer if the level hash could not be opened. The
+/// level hash must be released with [levelhash_close].
+///
+/// # Safety
+///
+/// `index_dir` and `index_name` must be null or point to valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn levelhash_open(
+    index_dir: *const c_char,
+    index_name: *const c_char,
+) -> *mut LevelHash {
+    catch_panic(ptr::null_mut(), || {
+        if index_dir.is_null() || index_name.is_null() {
+            return ptr::null_mut();
+        }
+
+        let (Ok(index_dir), Ok(index_name)) = (
+            CStr::from_ptr(index_dir).to_str(),
+            CStr::from_ptr(index_name).to_str(),
+        ) else {
+            log_error!("index directory and index name must be valid UTF-8");
+            return ptr::null_mut();
+        };
+
+        match LevelHash::options()
+            .index_dir(Path::new(index_dir))
+            .index_name(index_name)
+            .build()
+        {
+            Ok(hash) => Box::into_raw(Box::new(hash)),
+            Err(why) => {
+                log_error!("failed to open level hash: {:?}", why);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Get the value associated with the given key. The value is copied to `value` only if it fits in
+/// `value_cap` bytes, so the buffer can be resized and the call can be repeated if the returned
+/// size is larger than `value_cap`.
+///
+/// ## Parameters
+///
+/// * `hash` - The level hash returned by [levelhash_open].
+/// * `key`, `key_len` - The key to get the value for.
+/// * `value`, `value_cap` - The buffer to copy the value to, and its capacity.
+///
+/// ## Returns
+///
+/// The size of the value, [LEVELHASH_NOT_FOUND] if there is no entry for the key,
+/// [LEVELHASH_ERR_INVALID_ARG] if the arguments are invalid, [LEVELHASH_ERR_IO] if the entry
+/// could not be read, or [LEVELHASH_ERR_PANIC].
+///
+/// # Safety
+///
+/// `hash` must be a pointer returned by [levelhash_open] which has not been closed. `key` must
+/// point to `key_len` readable bytes, and `value` must be null or point to `value_cap` writable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn levelhash_get(
+    hash: *const LevelHash,
+    key: *const u8,
+    key_len: usize,
+    value: *mut u8,
+    value_cap: usize,
+) -> i64 {
+    catch_panic(LEVELHASH_ERR_PANIC as i64, || {
+        let (Some(hash), Some(key)) = (hash.as_ref(), bytes(key, key_len)) else {
+            return LEVELHASH_ERR_INVALID_ARG as i64;
+        };
+
+        let found = match hash.try_get_value(key) {
+            Ok(Some(found)) => found,
+            Ok(None) => return LEVELHASH_NOT_FOUND as i64,
+            Err(why) => {
+                log_error!("failed to get entry: {:?}", why);
+                return LEVELHASH_ERR_IO as i64;
+            }
+        };
+
+        if !value.is_null() && found.len() <= value_cap {
+            ptr::copy_nonoverlapping(found.as_ptr(), value, found.len());
+        }
+
+        found.len() as i64
+    })
+}
+
+/// Insert the given key-value pair in the level hash, replacing the value of the existing entry
+/// for the key, if any.
+///
+/// ## Parameters
+///
+/// * `hash` - The level hash returned by [levelhash_open].
+/// * `key`, `key_len` - The key for the entry. This must not be empty.
+/// * `value`, `value_len` - The value for the entry.
+///
+/// ## Returns
+///
+/// [LEVELHASH_OK] if the entry was written, [LEVELHASH_ERR_INVALID_ARG] if the arguments are
+/// invalid, [LEVELHASH_ERR_WRITE] if the entry could not be written, or [LEVELHASH_ERR_PANIC].
+///
+/// # Safety
+///
+/// `hash` must be a pointer returned by [levelhash_open] which has not been closed. `key` must
+/// point to `key_len` readable bytes, and `value` must point to `value_len` readable bytes (it may
+/// be null if `value_len` is `0`).
+#[no_mangle]
+pub unsafe extern "C" fn levelhash_put(
+    hash: *mut LevelHash,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    catch_panic(LEVELHASH_ERR_PANIC, || {
+        let (Some(hash), Some(key), Some(value)) =
+            (hash.as_mut(), bytes(key, key_len), bytes(value, value_len))
+        else {
+            return LEVELHASH_ERR_INVALID_ARG;
+        };
+
+        if key.is_empty() {
+            return LEVELHASH_ERR_INVALID_ARG;
+        }
+
+        match hash.insert_replace(key, value) {
+            Ok(_) => LEVELHASH_OK,
+            Err(why) => {
+                log_error!("failed to put entry: {:?}", why);
+                LEVELHASH_ERR_WRITE
+            }
+        }
+    })
+}
+
+/// Close the level hash, syncing the changes to the disk. The pointer must not be used after
+/// this call.
+///
+/// # Safety
+///
+/// `hash` must be null or a pointer returned by [levelhash_open] which has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn levelhash_close(hash: *mut LevelHash) {
+    catch_panic((), || {
+        if !hash.is_null() {
+            drop(Box::from_raw(hash));
+        }
+    })
+}
+
+/// Run the body of an exported function, returning `on_panic` if it panics, as unwinding into
+/// the caller is undefined behavior.
+fn catch_panic<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| {
+        log_error!("level hash panicked in a foreign call");
+        on_panic
+    })
+}
+
+/// Get the slice of `len` bytes at the given pointer, or `None` if the pointer is null. A null
+/// pointer is allowed for empty slices.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        return Some(&[]);
+    }
+
+    (!data.is_null()).then(|| slice::from_raw_parts(data, len))
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+    use std::fs;
+    use std::ptr;
+
+    use crate::ffi::levelhash_close;
+    use crate::ffi::levelhash_get;
+    use crate::ffi::levelhash_open;
+    use crate::ffi::levelhash_put;
+    use crate::ffi::LEVELHASH_ERR_INVALID_ARG;
+    use crate::ffi::LEVELHASH_ERR_IO;
+    use crate::ffi::LEVELHASH_NOT_FOUND;
+    use crate::ffi::LEVELHASH_OK;
+    use crate::level_io::LevelHashIO;
+
+    #[test]
+    fn open_put_get_close() {
+        let dir = "target/tests/level-hash/ffi";
+        let _ = fs::remove_dir_all(dir);
+
+        let index_dir = CString::new(dir).unwrap();
+        let index_name = CString::new("ffi").unwrap();
+
+        unsafe {
+            let hash = levelhash_open(index_dir.as_ptr(), index_name.as_ptr());
+            assert!(!hash.is_null());
+            assert!(levelhash_open(ptr::null(), index_name.as_ptr()).is_null());
+
+            let key = b"key1";
+            assert_eq!(
+                levelhash_put(hash, key.as_ptr(), key.len(), b"value1".as_ptr(), 6),
+                LEVELHASH_OK
+            );
+            assert_eq!(
+                levelhash_put(hash, ptr::null(), 0, b"value1".as_ptr(), 6),
+                LEVELHASH_ERR_INVALID_ARG
+            );
+
+            // the value is not copied if the buffer is too small
+            let mut value = [0u8; 4];
+            let len = levelhash_get(hash, key.as_ptr(), key.len(), value.as_mut_ptr(), 4);
+            assert_eq!(len, 6);
+            assert_eq!(value, [0u8; 4]);
+
+            // put replaces the value of an existing entry
+            assert_eq!(
+                levelhash_put(hash, key.as_ptr(), key.len(), b"value2".as_ptr(), 6),
+                LEVELHASH_OK
+            );
+
+            let mut value = [0u8; 16];
+            let len = levelhash_get(hash, key.as_ptr(), key.len(), value.as_mut_ptr(), 16);
+            assert_eq!(&value[..len as usize], b"value2");
+
+            let missing = b"key2";
+            assert_eq!(
+                levelhash_get(hash, missing.as_ptr(), 4, value.as_mut_ptr(), 16),
+                LEVELHASH_NOT_FOUND as i64
+            );
+
+            levelhash_close(hash);
+
+            // the entries are persisted after the level hash is closed
+            let hash = levelhash_open(index_dir.as_ptr(), index_name.as_ptr());
+            assert!(!hash.is_null());
+            let len = levelhash_get(hash, key.as_ptr(), key.len(), ptr::null_mut(), 0);
+            assert_eq!(len, 6);
+            levelhash_close(hash);
+        }
+    }
+
+    // with debug-invariants, opening the corrupt index panics (and returns a null pointer)
+    #[test]
+    #[cfg(not(feature = "debug-invariants"))]
+    fn get_corrupt_entry() {
+        let dir = "target/tests/level-hash/ffi-corrupt";
+        let _ = fs::remove_dir_all(dir);
+
+        let index_dir = CString::new(dir).unwrap();
+        let index_name = CString::new("ffi").unwrap();
+        let key = b"key1";
+
+        unsafe {
+            let hash = levelhash_open(index_dir.as_ptr(), index_name.as_ptr());
+            assert_eq!(
+                levelhash_put(hash, key.as_ptr(), key.len(), b"value1".as_ptr(), 6),
+                LEVELHASH_OK
+            );
+            levelhash_close(hash);
+        }
+
+        // point the slot of the entry inside the values file, but not to the start of an entry
+        let keymap = format!(
+            "{}/ffi{}{}",
+            dir,
+            LevelHashIO::LEVEL_INDEX_EXT,
+            LevelHashIO::LEVEL_KEYMAP_EXT
+        );
+        let mut bytes = fs::read(&keymap).unwrap();
+        let start = LevelHashIO::km_real_offset(0) as usize;
+        for slot in bytes[start..].chunks_exact_mut(8) {
+            if slot.iter().any(|b| *b != 0) {
+                slot.copy_from_slice(&5u64.to_le_bytes());
+            }
+        }
+        fs::write(&keymap, bytes).unwrap();
+
+        unsafe {
+            let hash = levelhash_open(index_dir.as_ptr(), index_name.as_ptr());
+            assert!(!hash.is_null());
+            assert_eq!(
+                levelhash_get(hash, key.as_ptr(), key.len(), ptr::null_mut(), 0),
+                LEVELHASH_ERR_IO as i64
+            );
+            levelhash_close(hash);
+        }
+    }
+}
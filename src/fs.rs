@@ -14,19 +14,29 @@
  *  You should have received a copy of the GNU General Public License
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
+use std::ffi::CString;
+use std::fs;
 use std::fs::create_dir_all;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use std::path::PathBuf;
 
 use byteorder::ReadBytesExt;
 use byteorder::WriteBytesExt;
 
 use crate::io::IOEndianness;
 use crate::log_macros::log_error;
+use crate::log_macros::log_warn;
 use crate::result::IntoLevelIOErr;
 use crate::result::IntoLevelInitErr;
 use crate::result::LevelInitError;
@@ -34,48 +44,318 @@ use crate::result::LevelResult;
 use crate::result::StdIOError;
 use crate::size::SIZE_U64;
 use crate::types::OffT;
-use crate::util::file_open_or_panic;
+use crate::LockMode;
+use crate::LockOwner;
 
+/// The size of the buffer used to copy files which cannot be cloned.
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The file which contains the boot id of the running kernel, which changes on every boot.
+const BOOT_ID_FILE: &str = "/proc/sys/kernel/random/boot_id";
+
+/// The minimum and the maximum size (bytes) by which the disk space of a file is reserved ahead of
+/// its size by [ftruncate_reserve].
+const RESERVE_AHEAD_MIN: OffT = 1024 * 1024;
+const RESERVE_AHEAD_MAX: OffT = 64 * 1024 * 1024;
+
+/// The number of times the lock file is opened again if it was deleted by the process which
+/// released the lock while it was being opened.
+const LOCK_OPEN_ATTEMPTS: u32 = 8;
+
+/// The lock file of an index. The process which holds the exclusive lock writes its PID and the
+/// boot id of the kernel to the lock file, so that the lock files left by the processes which did
+/// not close the index can be detected (see [LockOwner]). The lock file is deleted when it is
+/// dropped, if no other process holds a lock on it.
 #[derive(Debug)]
 pub(crate) struct LockFile {
     _file: File,
+    path: PathBuf,
+    mode: LockMode,
 }
 
 impl LockFile {
-    /// Create a new lock file.
-    pub fn new(path: &Path) -> LevelResult<Self, LevelInitError> {
-        // we do not request blocking if the lock is already acquired
-        // in that case, this `open` call will fail
-        let file = File::options()
-            .read(true)
-            .write(true)
-            .create_new(!path.exists())
-            .open(path)
-            .into_lvl_io_e_msg(format!("failed to open lock file: {}", path.display()))
-            .into_lvl_init_err()?;
+    /// Create a new lock file, and acquire the lock in the given mode.
+    pub fn new(path: &Path, mode: LockMode) -> LevelResult<Self, LevelInitError> {
+        for _ in 0..LOCK_OPEN_ATTEMPTS {
+            // we do not request blocking if the lock is already acquired
+            // in that case, the `flock` call will fail
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .into_lvl_io_e_msg(format!("failed to open lock file: {}", path.display()))
+                .into_lvl_init_err()?;
 
-        let result = __flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB);
-        if result != 0 {
-            // any other error returned by flock
-            return Err(LevelInitError::IOError(StdIOError::new(
-                Some(format!(
-                    "failed to acquire lock on lock file: {}",
-                    path.display()
-                )),
-                std::io::Error::last_os_error(),
-            )));
-        };
+            if let Err(why) = flock_nb(&file, mode) {
+                return Err(LevelInitError::IOError(StdIOError::with_message(
+                    format!("failed to acquire lock on lock file: {}", path.display()),
+                    why.error,
+                )));
+            }
+
+            // the lock is useless if the file was deleted by the process which released the lock
+            // before we locked it, the lock is released when the file is closed
+            if !is_linked(path, &file) {
+                continue;
+            }
+
+            let lock_file = Self {
+                _file: file,
+                path: path.to_path_buf(),
+                mode,
+            };
+            if mode == LockMode::Exclusive {
+                lock_file.write_owner();
+            }
+            return Ok(lock_file);
+        }
+
+        Err(LevelInitError::IOError(StdIOError::with_message(
+            format!("failed to acquire lock on lock file: {}", path.display()),
+            std::io::Error::from(ErrorKind::WouldBlock),
+        )))
+    }
+
+    /// Get the mode of the lock which is held.
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
 
-        Ok(Self { _file: file })
+    /// Upgrade the shared lock to an exclusive lock. Converting the lock is not atomic, the shared
+    /// lock is released before the exclusive lock is acquired. If the exclusive lock cannot be
+    /// acquired, the shared lock is acquired again.
+    pub fn upgrade(&mut self) -> LevelResult<(), StdIOError> {
+        if self.mode == LockMode::Exclusive {
+            return Ok(());
+        }
+
+        if let Err(why) = self.lock(LockMode::Exclusive) {
+            if let Err(why) = self.lock(LockMode::Shared) {
+                log_error!("failed to reacquire the shared lock: {}", why);
+            }
+            return Err(why);
+        }
+
+        Ok(())
+    }
+
+    /// Downgrade the exclusive lock to a shared lock.
+    pub fn downgrade(&mut self) -> LevelResult<(), StdIOError> {
+        if self.mode == LockMode::Shared {
+            return Ok(());
+        }
+
+        self.lock(LockMode::Shared)
+    }
+
+    /// Acquire the lock in the given mode, without blocking if the lock is held by another
+    /// process. The owner of the exclusive lock is written to the lock file.
+    fn lock(&mut self, mode: LockMode) -> LevelResult<(), StdIOError> {
+        flock_nb(&self._file, mode)?;
+        self.mode = mode;
+        if mode == LockMode::Exclusive {
+            self.write_owner();
+        }
+        Ok(())
+    }
+
+    /// Write the current process as the owner of the lock to the lock file. A previous owner is
+    /// logged, as the owners delete the lock file when they release the lock.
+    fn write_owner(&self) {
+        if let Ok(Some(owner)) = read_lock_owner(&self._file) {
+            if owner.pid != std::process::id() {
+                log_warn!(
+                    "the lock file {} was left by the process {}, which did not close the index",
+                    self.path.display(),
+                    owner.pid
+                );
+            }
+        }
+
+        let owner = format!(
+            "{}\n{}\n",
+            std::process::id(),
+            boot_id().unwrap_or_default()
+        );
+        let file = &self._file;
+        let result = file
+            .set_len(0)
+            .and_then(|_| file.write_all_at(owner.as_bytes(), 0));
+        if let Err(why) = result {
+            log_warn!(
+                "failed to write the owner of the lock file {}: {}",
+                self.path.display(),
+                why
+            );
+        }
     }
 }
 
 impl Drop for LockFile {
     fn drop(&mut self) {
+        // the lock file is deleted only if no other process holds a lock on it, in which case the
+        // lock can be converted to an exclusive lock
+        let unused = flock_nb(&self._file, LockMode::Exclusive).is_ok();
+        if unused && is_linked(&self.path, &self._file) {
+            if let Err(why) = fs::remove_file(&self.path) {
+                log_warn!(
+                    "failed to delete the lock file {}: {}",
+                    self.path.display(),
+                    why
+                );
+            }
+        }
         __flock(self._file.as_raw_fd(), libc::LOCK_UN | libc::LOCK_NB);
     }
 }
 
+/// Acquire the lock of the given file in the given mode, without blocking if the lock is held by
+/// another process.
+fn flock_nb(file: &File, mode: LockMode) -> LevelResult<(), StdIOError> {
+    let operation = match mode {
+        LockMode::Shared => libc::LOCK_SH,
+        LockMode::Exclusive => libc::LOCK_EX,
+    };
+
+    if __flock(file.as_raw_fd(), operation | libc::LOCK_NB) != 0 {
+        return Err(StdIOError::with_message(
+            format!("failed to acquire {:?} lock", mode),
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether the given (lock) file is still linked at the given path.
+fn is_linked(path: &Path, file: &File) -> bool {
+    match (fs::metadata(path), file.metadata()) {
+        (Ok(linked), Ok(locked)) => linked.dev() == locked.dev() && linked.ino() == locked.ino(),
+        _ => false,
+    }
+}
+
+/// Get the boot id of the running kernel.
+fn boot_id() -> Option<String> {
+    fs::read_to_string(BOOT_ID_FILE)
+        .ok()
+        .map(|boot_id| boot_id.trim().to_string())
+}
+
+/// Read the owner of the lock written to the given lock file, if any.
+fn read_lock_owner(file: &File) -> std::io::Result<Option<LockOwner>> {
+    let mut contents = String::new();
+    (&*file).seek(SeekFrom::Start(0))?;
+    (&*file).read_to_string(&mut contents)?;
+
+    let mut lines = contents.lines();
+    let Some(Ok(pid)) = lines.next().map(str::parse::<u32>) else {
+        return Ok(None);
+    };
+
+    let boot_id = lines.next().filter(|boot_id| !boot_id.is_empty());
+    Ok(Some(LockOwner {
+        pid,
+        boot_id: boot_id.map(str::to_string),
+    }))
+}
+
+impl LockOwner {
+    /// Whether the owner of the lock is still running : the lock file was written since the
+    /// system was booted and a process with the PID of the owner exists. The PIDs are reused, so
+    /// the owner may be reported as running if another process got its PID.
+    pub fn is_running(&self) -> bool {
+        if let (Some(owner_boot_id), Some(boot_id)) = (&self.boot_id, boot_id()) {
+            if *owner_boot_id != boot_id {
+                return false;
+            }
+        }
+
+        // signal 0 only checks whether the process exists
+        let Ok(pid) = libc::pid_t::try_from(self.pid) else {
+            return false;
+        };
+        let exists = unsafe { libc::kill(pid, 0) } == 0;
+        exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+/// Get the owner of the lock recorded in the lock file at the given path, if the lock file exists
+/// and records an owner.
+pub(crate) fn lock_owner(path: &Path) -> LevelResult<Option<LockOwner>, StdIOError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(why) => {
+            return Err(why)
+                .into_lvl_io_e_msg(format!("failed to open lock file: {}", path.display()))
+        }
+    };
+
+    read_lock_owner(&file)
+        .into_lvl_io_e_msg(format!("failed to read lock file: {}", path.display()))
+}
+
+/// Delete the lock file at the given path, if it is stale : no process holds a lock on it, and its
+/// owner (if any) is not running.
+///
+/// ## Returns
+///
+/// Whether the lock file was deleted, or [LevelInitError::Locked] if the lock is held.
+pub(crate) fn force_unlock(path: &Path) -> LevelResult<bool, LevelInitError> {
+    let file = match File::options().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(why) => {
+            return Err(why)
+                .into_lvl_io_e_msg(format!("failed to open lock file: {}", path.display()))
+                .into_lvl_init_err()
+        }
+    };
+
+    let owner = read_lock_owner(&file)
+        .into_lvl_io_e_msg(format!("failed to read lock file: {}", path.display()))
+        .into_lvl_init_err()?;
+    if flock_nb(&file, LockMode::Exclusive).is_err() {
+        return Err(LevelInitError::Locked { owner });
+    }
+
+    // the owner may hold the lock on another machine if the file system does not support flock
+    if owner.as_ref().is_some_and(LockOwner::is_running) {
+        __flock(file.as_raw_fd(), libc::LOCK_UN | libc::LOCK_NB);
+        return Err(LevelInitError::Locked { owner });
+    }
+
+    let result = fs::remove_file(path)
+        .into_lvl_io_e_msg(format!("failed to delete file: {}", path.display()))
+        .into_lvl_init_err();
+    __flock(file.as_raw_fd(), libc::LOCK_UN | libc::LOCK_NB);
+    result.map(|_| true)
+}
+
+/// Create an anonymous file which lives in memory. The name is only used for debugging purposes
+/// and is shown as the target of the symlink in `/proc/self/fd/`.
+pub(crate) fn memfd_file(name: &str) -> LevelResult<File, LevelInitError> {
+    let c_name = CString::new(name).map_err(|_| {
+        LevelInitError::InvalidArg(format!("Name must not contain NUL bytes: {}", name))
+    })?;
+
+    let fd = unsafe { libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(LevelInitError::IOError(StdIOError::new(
+            Some(format!("failed to create memory file: {}", name)),
+            std::io::Error::last_os_error(),
+        )));
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Create the file at the given path with the given magic number, if it does not exist. An
+/// existing file with another magic number is rejected with an [ErrorKind::InvalidData] error
+/// instead of being emptied.
 pub(crate) fn init_sparse_file(
     path: &Path,
     magic_number: Option<u64>,
@@ -103,15 +383,26 @@ pub(crate) fn init_sparse_file(
         if file.metadata().map(|m| m.len()).unwrap_or(0) >= SIZE_U64 {
             match file.read_u64::<IOEndianness>() {
                 Ok(magic_f) => {
+                    if magic_f == magic.swap_bytes() && magic_f != magic {
+                        // the file was written in the native byte order of a big-endian host, its
+                        // contents are converted when the index is opened
+                        return write_magic_file(&mut file, Some(magic));
+                    }
+
                     if magic_f != magic {
+                        // the file may have been written by another program or a newer version of
+                        // level hash, it is never emptied here
                         log_error!("magic number mismatch: {} != {}", magic_f, magic);
-                        log_error!("removing {}", path.display());
-                        file.set_len(0)
-                            .into_lvl_io_e_msg("couldn't truncate file".to_string())?;
-                        write_magic_file(&mut file, Some(magic))?;
-                    } else {
-                        return Ok(());
+                        return Err(LevelInitError::IOError(StdIOError::with_message(
+                            format!("bad magic number in file: {}", path.display()),
+                            std::io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!("expected {:#x}, found {:#x}", magic, magic_f),
+                            ),
+                        )));
                     }
+
+                    return Ok(());
                 }
                 Err(why) => {
                     return Err(LevelInitError::IOError(StdIOError::with_message(
@@ -156,42 +447,287 @@ fn write_magic_file(file: &mut File, magic_number: Option<u64>) -> LevelResult<(
     Ok(())
 }
 
+/// Read the magic number at the start of the file with the given descriptor.
+pub(crate) fn read_magic_fd(fd: BorrowedFd) -> LevelResult<u64, StdIOError> {
+    let mut buf = [0u8; SIZE_U64 as usize];
+    let read = unsafe { libc::pread(fd.as_raw_fd(), buf.as_mut_ptr() as *mut _, buf.len(), 0) };
+    if read < 0 {
+        return Err(StdIOError::with_message(
+            "failed to read magic number".to_string(),
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    if read as usize != buf.len() {
+        return Err(StdIOError::with_message(
+            "failed to read magic number".to_string(),
+            ErrorKind::UnexpectedEof.into(),
+        ));
+    }
+
+    (&buf[..])
+        .read_u64::<IOEndianness>()
+        .into_lvl_io_e_msg("failed to read magic number".to_string())
+}
+
+/// Write the magic number at the start of the file with the given descriptor.
+pub(crate) fn write_magic_fd(fd: BorrowedFd, magic: u64) -> LevelResult<(), StdIOError> {
+    let mut buf = Vec::with_capacity(SIZE_U64 as usize);
+    buf.write_u64::<IOEndianness>(magic)
+        .into_lvl_io_e_msg("failed to write magic number".to_string())?;
+
+    let written = unsafe { libc::pwrite(fd.as_raw_fd(), buf.as_ptr() as *const _, buf.len(), 0) };
+    if written < 0 {
+        return Err(StdIOError::with_message(
+            "failed to write magic number".to_string(),
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    if written as usize != buf.len() {
+        return Err(StdIOError::with_message(
+            "failed to write magic number".to_string(),
+            ErrorKind::WriteZero.into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Truncate (or extend) the file with the given descriptor to `len` bytes.
 #[inline]
-pub(crate) fn ftruncate_safe(fd: libc::c_int, len: OffT) {
-    unsafe {
-        libc::ftruncate(fd, len as libc::off_t);
+pub(crate) fn ftruncate_safe(fd: libc::c_int, len: OffT) -> LevelResult<(), StdIOError> {
+    let result = unsafe { libc::ftruncate(fd, len as libc::off_t) };
+    if result != 0 {
+        return Err(StdIOError::with_message(
+            format!("failed to truncate file to {} bytes", len),
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Resize the file with the given descriptor to `len` bytes, like [ftruncate_safe]. The disk space
+/// of the region by which the file grows is allocated first, so that a full disk is reported here
+/// (with `ENOSPC`) instead of raising `SIGBUS` when the region is first written through a memory
+/// map. The file keeps its size if the space cannot be allocated. The space is not allocated on
+/// the file systems which do not support it.
+///
+/// The space is reserved ahead of the size of the file, by as much as the size of the file (within
+/// [RESERVE_AHEAD_MIN] and [RESERVE_AHEAD_MAX]), so that a file which grows in small steps is
+/// only allocated once in a while. `reserved` is the length of the file up to which the space has
+/// been reserved, and is updated by this function.
+pub(crate) fn ftruncate_reserve(
+    fd: libc::c_int,
+    len: OffT,
+    reserved: &mut OffT,
+) -> LevelResult<(), StdIOError> {
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        return Err(StdIOError::with_message(
+            "failed to read the size of the file".to_string(),
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    let size = stat.st_size as OffT;
+    if len > size && len > *reserved {
+        let target = len + len.clamp(RESERVE_AHEAD_MIN, RESERVE_AHEAD_MAX);
+        let reserve = |end: OffT| {
+            fallocate_safe(fd, libc::FALLOC_FL_KEEP_SIZE, size, end - size).map(|_| end)
+        };
+
+        // the disk may have space for the new size, but not for the reservation ahead of it
+        match reserve(target).or_else(|why| match why.is_no_space() {
+            true => reserve(len),
+            false => Err(why),
+        }) {
+            Ok(end) => *reserved = end,
+            Err(why) if why.is_no_space() => {
+                // the blocks which were allocated before the disk got full are released
+                let _ = ftruncate_safe(fd, size);
+                return Err(why);
+            }
+            // the file is only truncated if the file system cannot allocate the space
+            Err(_) => *reserved = target,
+        }
+    }
+
+    ftruncate_safe(fd, len)?;
+
+    // the space reserved beyond the end of the file is released when the file shrinks
+    if len < size {
+        *reserved = len;
     }
+
+    Ok(())
 }
 
 #[inline]
-pub(crate) fn ftruncate_safe_file(file: &File, len: OffT) {
-    ftruncate_safe(file.as_raw_fd(), len);
+pub(crate) fn ftruncate_safe_file(file: &File, len: OffT) -> LevelResult<(), StdIOError> {
+    ftruncate_safe(file.as_raw_fd(), len)
 }
 
 #[inline]
-pub(crate) fn ftruncate_safe_path(file: &Path, len: OffT) {
-    let file = file_open_or_panic(file, true, true, false);
-    ftruncate_safe_file(&file, len);
+pub(crate) fn ftruncate_safe_path(path: &Path, len: OffT) -> LevelResult<(), StdIOError> {
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(false)
+        .open(path)
+        .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))?;
+    ftruncate_safe_file(&file, len)
 }
 
+/// Manipulate the allocated disk space for the region of the file with the given descriptor.
 #[inline]
-pub(crate) fn fallocate_safe(fd: libc::c_int, mode: libc::c_int, offset: OffT, len: OffT) {
-    unsafe {
-        libc::fallocate(fd, mode, offset as libc::off_t, len as libc::off_t);
+pub(crate) fn fallocate_safe(
+    fd: libc::c_int,
+    mode: libc::c_int,
+    offset: OffT,
+    len: OffT,
+) -> LevelResult<(), StdIOError> {
+    let result = unsafe { libc::fallocate(fd, mode, offset as libc::off_t, len as libc::off_t) };
+    if result != 0 {
+        return Err(StdIOError::with_message(
+            format!(
+                "failed to fallocate {} bytes at offset {} (mode={})",
+                len, offset, mode
+            ),
+            std::io::Error::last_os_error(),
+        ));
     }
+
+    Ok(())
 }
 
+/// Punch a hole in the region of the file with the given descriptor. The region reads as zeros
+/// afterwards, and the size of the file is not changed.
 #[inline]
-pub(crate) fn fallocate_safe_punch(fd: libc::c_int, offset: OffT, len: OffT) {
+pub(crate) fn fallocate_safe_punch(
+    fd: libc::c_int,
+    offset: OffT,
+    len: OffT,
+) -> LevelResult<(), StdIOError> {
     fallocate_safe(
         fd,
         libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
         offset,
         len,
+    )
+}
+
+/// Copy the contents of the file with the given descriptor to the `dst` file. The file is cloned
+/// (sharing the data blocks with the source file) if the file system supports it, and copied
+/// otherwise.
+pub(crate) fn clone_or_copy_file(src: BorrowedFd, dst: &File) -> LevelResult<(), StdIOError> {
+    let result = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE as _, src.as_raw_fd()) };
+    if result == 0 {
+        return Ok(());
+    }
+
+    // cloning is not supported (different file systems, memfd, etc.), copy the contents instead
+    let src = File::from(
+        src.try_clone_to_owned()
+            .into_lvl_io_e_msg("failed to duplicate file descriptor".to_string())?,
     );
+
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    let mut off: OffT = 0;
+    loop {
+        let read = src.read_at(&mut buf, off).into_lvl_io_e_msg(format!(
+            "failed to read {} bytes at {}",
+            buf.len(),
+            off
+        ))?;
+        if read == 0 {
+            break;
+        }
+
+        dst.write_all_at(&buf[..read], off)
+            .into_lvl_io_e_msg(format!("failed to write {} bytes at {}", read, off))?;
+        off += read as OffT;
+    }
+
+    Ok(())
 }
 
 #[inline]
 fn __flock(fd: i32, operation: i32) -> i32 {
     unsafe { libc::flock(fd, operation) }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+    use std::path::Path;
+
+    use crate::fs::fallocate_safe_punch;
+    use crate::fs::ftruncate_reserve;
+    use crate::fs::ftruncate_safe;
+    use crate::fs::ftruncate_safe_path;
+    use crate::fs::RESERVE_AHEAD_MIN;
+
+    #[test]
+    fn truncate_errors_are_returned() {
+        let dir = Path::new("target/tests/level-hash/fs-truncate");
+        fs::create_dir_all(dir).expect("Failed to create directories");
+
+        let result = ftruncate_safe(-1, 1024);
+        assert_eq!(
+            result.err().map(|e| e.error.raw_os_error()),
+            Some(Some(libc::EBADF))
+        );
+
+        assert!(ftruncate_safe_path(&dir.join("missing"), 1024).is_err());
+
+        let path = dir.join("file");
+        fs::write(&path, [1u8; 16]).expect("failed to write file");
+        ftruncate_safe_path(&path, 1024).expect("failed to truncate file");
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1024);
+    }
+
+    #[test]
+    fn punch_errors_are_returned() {
+        let dir = Path::new("target/tests/level-hash/fs-punch");
+        fs::create_dir_all(dir).expect("Failed to create directories");
+
+        let path = dir.join("file");
+        fs::write(&path, [1u8; 16]).expect("failed to write file");
+
+        // the file must be opened for writing
+        let file = File::open(&path).expect("failed to open file");
+        assert!(fallocate_safe_punch(file.as_raw_fd(), 0, 8).is_err());
+    }
+
+    #[test]
+    fn space_is_reserved_ahead() {
+        let dir = Path::new("target/tests/level-hash/fs-reserve");
+        fs::create_dir_all(dir).expect("Failed to create directories");
+
+        let path = dir.join("file");
+        let file = File::create(&path).expect("failed to create file");
+        let fd = file.as_raw_fd();
+
+        let mut reserved = 0;
+        ftruncate_reserve(fd, 4096, &mut reserved).unwrap();
+        assert_eq!(reserved, 4096 + RESERVE_AHEAD_MIN);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4096);
+
+        // the file grows into the reserved space
+        ftruncate_reserve(fd, 8192, &mut reserved).unwrap();
+        assert_eq!(reserved, 4096 + RESERVE_AHEAD_MIN);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8192);
+
+        ftruncate_reserve(fd, 2 * RESERVE_AHEAD_MIN, &mut reserved).unwrap();
+        assert_eq!(reserved, 4 * RESERVE_AHEAD_MIN);
+
+        // the reservation is released with the space past the end of the file
+        ftruncate_reserve(fd, 4096, &mut reserved).unwrap();
+        assert_eq!(reserved, 4096);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4096);
+    }
+}
@@ -0,0 +1,126 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The inconsistencies reported by [LevelHash::verify](crate::LevelHash::verify) and the result of
+//! [LevelHash::repair](crate::LevelHash::repair).
+
+use crate::types::OffT;
+use crate::types::_BucketIdxT;
+use crate::types::_SlotIdxT;
+use crate::Level;
+
+/// The index files which start with a magic number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexFile {
+    /// The values file.
+    Values,
+
+    /// The keymap file.
+    Keymap,
+
+    /// The overflow file. Its magic number is only checked when the index is opened.
+    Overflow,
+}
+
+/// The position of a slot in the keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotPos {
+    pub level: Level,
+    pub bucket: _BucketIdxT,
+    pub slot: _SlotIdxT,
+}
+
+/// An inconsistency in the index files of a level hash. The addresses are the (0-based) addresses
+/// in the values file, except for [Inconsistency::DanglingPointer] which has the (1-based) address
+/// stored in the slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The levels described by the meta file do not fit in the keymap file. The slots are not
+    /// checked in this case.
+    InvalidLayout(String),
+
+    /// The magic number at the start of an index file is not the expected magic number.
+    BadMagicNumber {
+        file: IndexFile,
+        expected: u64,
+        found: u64,
+    },
+
+    /// The slot points outside of the used region of the values file, or to an empty entry.
+    DanglingPointer { slot: SlotPos, addr: OffT },
+
+    /// The slot points inside the entry at `entry` instead of its start.
+    OverlappingEntry {
+        slot: SlotPos,
+        addr: OffT,
+        entry: OffT,
+    },
+
+    /// The slot points to an entry whose contents do not match its checksum, or which extends
+    /// beyond the used region of the values file.
+    CorruptEntry { slot: SlotPos, addr: OffT },
+
+    /// Both the slots point to the same entry.
+    SharedEntry {
+        slot: SlotPos,
+        other: SlotPos,
+        addr: OffT,
+    },
+
+    /// The entry is not in any of the buckets for its key, so it cannot be looked up with its key.
+    MisplacedEntry { slot: SlotPos, addr: OffT },
+
+    /// The slot is tagged with `tag`, which is not the tag of the key of its entry, so the entry
+    /// cannot be looked up with its key.
+    TagMismatch { slot: SlotPos, addr: OffT, tag: u16 },
+
+    /// An entry in the chain of the entry in the slot links to `addr`, which is not the start of
+    /// an entry for the same key.
+    BrokenChain { slot: SlotPos, addr: OffT },
+
+    /// The entry in the values file is not pointed to by any slot.
+    UnreachableEntry { addr: OffT },
+
+    /// The number of occupied slots in the level which is stored in the meta file does not match
+    /// the keymap.
+    OccupancyMismatch {
+        level: Level,
+        stored: u32,
+        actual: u32,
+    },
+
+    /// The content fingerprint stored in the meta file does not match the entries.
+    FingerprintMismatch { stored: u64, actual: u64 },
+}
+
+/// The result of [LevelHash::repair](crate::LevelHash::repair).
+///
+/// # Properties
+///
+/// * `inconsistencies`: The inconsistencies that were found before the repair. The level hash is
+///   not modified if this is empty.
+/// * `relinked_entries`: The number of entries in the values file that were linked to a slot in the
+///   rebuilt keymap, including the entries in the chains of the linked entries.
+/// * `dropped_entries`: The number of entries that were removed from the values file, because
+///   they were corrupt, were older entries of a key in a level hash with unique keys, or no
+///   slot could be found for them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub inconsistencies: Vec<Inconsistency>,
+    pub relinked_entries: u64,
+    pub dropped_entries: u64,
+}
@@ -0,0 +1,334 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Entry points for the fuzz targets in the `fuzz` directory. The functions in this module accept
+//! arbitrary bytes and must never panic, unless the level hash itself is broken.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::level_io::LevelHashIO;
+use crate::LevelHash;
+use crate::LevelHashOptions;
+
+/// The maximum length of the keys generated by [fuzz_operations].
+const KEY_SIZE_MAX: usize = 32;
+
+/// The maximum length of the values generated by [fuzz_operations].
+const VALUE_SIZE_MAX: usize = 256;
+
+/// The number of entries in the index mutated by [fuzz_mutated_index].
+const MUTATED_INDEX_ENTRIES: usize = 8;
+
+/// The name of the index created by the fuzz targets.
+const INDEX_NAME: &str = "fuzz";
+
+/// Drive the public API of [LevelHash] with the operations decoded from the given bytes.
+///
+/// The first two bytes configure the level hash :
+///
+/// * `data[0]`: The level size (lower 2 bits, plus 1) and whether the level hash should auto
+///   expand (bit 2).
+/// * `data[1]`: The bucket size (lower 2 bits, plus 1) and whether the values larger than 16
+///   bytes should be spilled to the overflow file (bit 2).
+///
+/// The remaining bytes are decoded as a sequence of operations, each starting with an opcode
+/// byte, followed by the operands of the operation (if any).
+pub fn fuzz_operations(data: &[u8]) {
+    let mut input = Input::new(data);
+    let (Some(conf_1), Some(conf_2)) = (input.byte(), input.byte()) else {
+        return;
+    };
+
+    let dir = fuzz_dir("operations");
+    let conf = |options: &mut LevelHashOptions| {
+        options
+            .level_size((conf_1 & 0b11) + 1)
+            .auto_expand(conf_1 & 0b100 != 0)
+            .bucket_size((conf_2 & 0b11) + 1)
+            .no_sync();
+
+        if conf_2 & 0b100 != 0 {
+            options.spill_threshold(16);
+        }
+    };
+
+    let Some(mut hash) = open_level_hash(&dir, true, conf) else {
+        return;
+    };
+
+    while let Some(op) = input.byte() {
+        match op % 7 {
+            0 => {
+                let key = input.bytes(KEY_SIZE_MAX);
+                let value = input.bytes(VALUE_SIZE_MAX);
+                let _ = hash.insert(key, value);
+            }
+            1 => {
+                let key = input.bytes(KEY_SIZE_MAX);
+                let _ = hash.get_value(key);
+            }
+            2 => {
+                let key = input.bytes(KEY_SIZE_MAX);
+                let value = input.bytes(VALUE_SIZE_MAX);
+                let _ = hash.update(key, value);
+            }
+            3 => {
+                let key = input.bytes(KEY_SIZE_MAX);
+                let _ = hash.remove(key);
+            }
+            4 => {
+                let _ = hash.expand();
+            }
+            5 => {
+                let _ = hash.clear();
+            }
+            _ => {
+                // drop the level hash before reopening it so that the lock is released
+                drop(hash);
+                hash = match open_level_hash(&dir, false, conf) {
+                    Some(hash) => hash,
+                    None => return,
+                };
+            }
+        }
+    }
+}
+
+/// Open a level hash from index files which contain the given bytes, and then perform a few
+/// operations on it.
+///
+/// The bytes are split into three chunks, each prefixed with its `u16` little-endian length,
+/// which are written to the meta, keymap and values files respectively. The values and keymap
+/// chunks are written after the magic number of the respective file.
+pub fn fuzz_index_files(data: &[u8]) {
+    let mut input = Input::new(data);
+    let meta = input.chunk();
+    let keymap = input.chunk();
+    let values = input.chunk();
+
+    let dir = fuzz_dir("index-files");
+    if dir.exists() {
+        fs::remove_dir_all(&dir).expect("failed to delete fuzz directory");
+    }
+    fs::create_dir_all(&dir).expect("failed to create fuzz directory");
+
+    let index_file = format!("{}{}", INDEX_NAME, LevelHashIO::LEVEL_INDEX_EXT);
+    let files = [
+        (LevelHashIO::LEVEL_META_EXT, None, meta),
+        (
+            LevelHashIO::LEVEL_KEYMAP_EXT,
+            Some(LevelHashIO::KEYMAP_MAGIC_NUMBER),
+            keymap,
+        ),
+        ("", Some(LevelHashIO::VALUES_MAGIC_NUMBER), values),
+    ];
+
+    for (ext, magic, chunk) in files {
+        let mut contents = Vec::with_capacity(chunk.len() + 8);
+        if let Some(magic) = magic {
+            contents.extend_from_slice(&magic.to_le_bytes());
+        }
+        contents.extend_from_slice(chunk);
+        fs::write(dir.join(format!("{}{}", index_file, ext)), contents)
+            .expect("failed to write index file");
+    }
+
+    let Some(mut hash) = open_level_hash(&dir, false, |options| {
+        options
+            .level_size(2)
+            .bucket_size(4)
+            .auto_expand(false)
+            .no_sync();
+    }) else {
+        return;
+    };
+
+    let key = input.bytes(KEY_SIZE_MAX);
+    let value = input.bytes(VALUE_SIZE_MAX);
+    let _ = hash.get_value(key);
+    let _ = hash.insert(key, value);
+    let _ = hash.remove(key);
+}
+
+/// Mutate the index files of a valid level hash with the given bytes, and then open the level
+/// hash with [LevelHashOptions::open_with_recovery].
+///
+/// The bytes are decoded as a sequence of mutations, each consisting of the file to mutate
+/// (`data[0] % 3`: the meta, keymap or values file), the `u16` little-endian offset in the file
+/// (modulo the size of the file) and the byte which is XOR-ed with the byte at that offset.
+pub fn fuzz_mutated_index(data: &[u8]) {
+    let dir = fuzz_dir("mutated-index");
+    let conf = |options: &mut LevelHashOptions| {
+        options
+            .level_size(2)
+            .bucket_size(4)
+            .auto_expand(false)
+            .no_sync();
+    };
+
+    {
+        let Some(mut hash) = open_level_hash(&dir, true, conf) else {
+            return;
+        };
+
+        for i in 0..MUTATED_INDEX_ENTRIES {
+            let key = format!("key-{}", i);
+            let value = format!("value-{}", i);
+            hash.insert(key.as_bytes(), value.as_bytes())
+                .expect("failed to insert entry");
+        }
+    }
+
+    let index_file = format!("{}{}", INDEX_NAME, LevelHashIO::LEVEL_INDEX_EXT);
+    let files = [
+        LevelHashIO::LEVEL_META_EXT,
+        LevelHashIO::LEVEL_KEYMAP_EXT,
+        "",
+    ]
+    .map(|ext| dir.join(format!("{}{}", index_file, ext)));
+    let mut contents = files
+        .each_ref()
+        .map(|path| fs::read(path).expect("failed to read index file"));
+
+    let mut input = Input::new(data);
+    while let (Some(file), Some(offset), Some(mask)) = (input.byte(), input.u16(), input.byte()) {
+        let file = &mut contents[file as usize % files.len()];
+        if !file.is_empty() {
+            let len = file.len();
+            file[offset as usize % len] ^= mask;
+        }
+    }
+
+    for (path, contents) in files.iter().zip(contents) {
+        fs::write(path, contents).expect("failed to write index file");
+    }
+
+    let mut options = LevelHash::options();
+    options.index_dir(&dir).index_name(INDEX_NAME).seeds(1, 2);
+    conf(&mut options);
+
+    let _ = options.open_with_recovery();
+}
+
+/// Get the directory in which the index files for the given fuzz target are created. The
+/// directory is unique to the current process so that multiple fuzzer jobs can run in parallel.
+fn fuzz_dir(target: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("level-hash-fuzz-{}-{}", target, std::process::id()))
+}
+
+fn open_level_hash(
+    dir: &Path,
+    create_new: bool,
+    conf: impl Fn(&mut LevelHashOptions),
+) -> Option<LevelHash> {
+    if create_new && dir.exists() {
+        fs::remove_dir_all(dir).expect("failed to delete fuzz directory");
+    }
+
+    let mut options = LevelHash::options();
+    options.index_dir(dir).index_name(INDEX_NAME).seeds(1, 2);
+
+    conf(&mut options);
+
+    options.build().ok()
+}
+
+/// Cursor over the fuzzer input. Reading past the end of the input yields empty data.
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let (first, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(*first)
+    }
+
+    /// Read a length byte (clamped to `max`), followed by that many bytes.
+    fn bytes(&mut self, max: usize) -> &'a [u8] {
+        let len = self.byte().unwrap_or(0) as usize % (max + 1);
+        self.take(len)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    /// Read a `u16` little-endian length, followed by that many bytes.
+    fn chunk(&mut self) -> &'a [u8] {
+        let len = self.u16().unwrap_or(0) as usize;
+        self.take(len)
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let (taken, rest) = self.data.split_at(len.min(self.data.len()));
+        self.data = rest;
+        taken
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::fuzz::fuzz_index_files;
+    use crate::fuzz::fuzz_mutated_index;
+    use crate::fuzz::fuzz_operations;
+
+    /// Run the given fuzz target with each input in the regression corpus of the target.
+    fn run_regressions(target: &str, fuzz: fn(&[u8])) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/regressions")
+            .join(target);
+
+        let mut inputs = fs::read_dir(&dir)
+            .expect("failed to read regressions directory")
+            .map(|entry| entry.expect("failed to read directory entry").path())
+            .collect::<Vec<_>>();
+        inputs.sort();
+
+        assert!(!inputs.is_empty(), "no regressions in {}", dir.display());
+
+        for input in inputs {
+            println!("running {}", input.display());
+            fuzz(&fs::read(&input).expect("failed to read regression input"));
+        }
+    }
+
+    #[test]
+    fn operations_regressions() {
+        run_regressions("operations", fuzz_operations);
+    }
+
+    #[test]
+    fn index_files_regressions() {
+        run_regressions("index_files", fuzz_index_files);
+    }
+
+    #[test]
+    fn mutated_index_regressions() {
+        run_regressions("mutated_index", fuzz_mutated_index);
+    }
+}
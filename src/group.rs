@@ -0,0 +1,352 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Multiple named level hashes (namespaces) which are stored in the same index directory.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::fs::LockFile;
+use crate::level_io::LevelHashIO;
+use crate::paths::IndexFiles;
+use crate::result::IntoLevelIOErr;
+use crate::result::IntoLevelInitErr;
+use crate::result::LevelInitError;
+use crate::result::LevelResult;
+use crate::result::LevelSyncResult;
+use crate::LevelHash;
+use crate::LevelHashOptions;
+use crate::LockMode;
+
+/// The extension of the lock file of a [LevelHashGroup].
+const GROUP_LOCK_EXT: &str = ".group.lock";
+
+/// The function which configures the options of the namespaces of a [LevelHashGroup].
+type ConfigureFn = dyn Fn(&str, &mut LevelHashOptions);
+
+/// A group of named level hashes (namespaces) in one index directory, e.g. one namespace for the
+/// classes and another one for the members of the classes. Each namespace is a separate
+/// [LevelHash] with its own index files, named with [LevelHashGroup::namespace_index_name]. The
+/// index files of the namespaces are always stored in the default layout (see
+/// [DefaultPaths](crate::paths::DefaultPaths)), so that the namespaces can be listed.
+///
+/// The group holds an exclusive lock on the group (in addition to the locks of the namespaces),
+/// so the namespaces of a group are only created, opened and removed by one [LevelHashGroup] at
+/// a time. The namespaces are opened on first use and stay open until the group is dropped.
+pub struct LevelHashGroup {
+    index_dir: PathBuf,
+    group_name: String,
+    configure: Box<ConfigureFn>,
+    namespaces: BTreeMap<String, LevelHash>,
+    _lock_file: LockFile,
+}
+
+impl LevelHashGroup {
+    /// Open the group with the given name in the given directory, creating the directory if it
+    /// does not exist. The namespaces are built with the default [LevelHashOptions].
+    pub fn open(index_dir: &Path, group_name: &str) -> LevelResult<Self, LevelInitError> {
+        Self::with_options(index_dir, group_name, |_, _| {})
+    }
+
+    /// Open the group with the given name in the given directory, creating the directory if it
+    /// does not exist.
+    ///
+    /// ## Parameters
+    ///
+    /// * `index_dir` - The directory of the index files of the namespaces.
+    /// * `group_name` - The name of the group.
+    /// * `configure` - Configures the options of the namespace with the given name before it is
+    ///   built. The index directory and the index name set by this function are ignored.
+    pub fn with_options(
+        index_dir: &Path,
+        group_name: &str,
+        configure: impl Fn(&str, &mut LevelHashOptions) + 'static,
+    ) -> LevelResult<Self, LevelInitError> {
+        if group_name.is_empty() {
+            return Err(LevelInitError::InvalidArg(
+                "Group name must not be empty".to_string(),
+            ));
+        }
+
+        fs::create_dir_all(index_dir)
+            .into_lvl_io_e_msg(format!(
+                "failed to create directory: {}",
+                index_dir.display()
+            ))
+            .into_lvl_init_err()?;
+
+        let lock_file = index_dir.join(format!("{}{}", group_name, GROUP_LOCK_EXT));
+        let lock_file = LockFile::new(&lock_file, LockMode::Exclusive)?;
+
+        Ok(Self {
+            index_dir: index_dir.to_path_buf(),
+            group_name: group_name.to_string(),
+            configure: Box::new(configure),
+            namespaces: BTreeMap::new(),
+            _lock_file: lock_file,
+        })
+    }
+
+    /// Get the name of the index of the given namespace of a group with the given name.
+    pub fn namespace_index_name(group_name: &str, namespace: &str) -> String {
+        format!("{}.{}", group_name, namespace)
+    }
+
+    /// Get the name of this group.
+    pub fn group_name(&self) -> &str {
+        &self.group_name
+    }
+
+    /// Get the level hash of the given namespace, opening it if it is not already open. The
+    /// namespace is created if it does not exist.
+    ///
+    /// The name of a namespace must only contain ASCII letters, digits, `_` and `-`.
+    pub fn namespace(&mut self, namespace: &str) -> LevelResult<&mut LevelHash, LevelInitError> {
+        Self::check_namespace(namespace)?;
+
+        match self.namespaces.entry(namespace.to_string()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let mut options = LevelHash::options();
+                (self.configure)(namespace, &mut options);
+                let hash = options
+                    .index_dir(&self.index_dir)
+                    .index_name(&Self::namespace_index_name(&self.group_name, namespace))
+                    .build()?;
+                Ok(entry.insert(hash))
+            }
+        }
+    }
+
+    /// Get the level hash of the given namespace if it is open.
+    pub fn get_namespace(&mut self, namespace: &str) -> Option<&mut LevelHash> {
+        self.namespaces.get_mut(namespace)
+    }
+
+    /// Get the names of the namespaces of this group which exist in the index directory or are
+    /// open, in sorted order.
+    pub fn namespace_names(&self) -> LevelResult<Vec<String>, LevelInitError> {
+        let prefix = format!("{}.", self.group_name);
+        let suffix = format!(
+            "{}{}",
+            LevelHashIO::LEVEL_INDEX_EXT,
+            LevelHashIO::LEVEL_META_EXT
+        );
+
+        let entries = fs::read_dir(&self.index_dir)
+            .into_lvl_io_e_msg(format!(
+                "failed to read directory: {}",
+                self.index_dir.display()
+            ))
+            .into_lvl_init_err()?;
+
+        let mut names = self.namespaces.keys().cloned().collect::<Vec<_>>();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(namespace) = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(&suffix))
+            else {
+                continue;
+            };
+
+            // the namespaces of another group whose name starts with the name of this group
+            // have a '.' in the remaining name
+            if Self::check_namespace(namespace).is_ok()
+                && LevelHashIO::index_exists(&IndexFiles::default_layout(
+                    &self.index_dir,
+                    &Self::namespace_index_name(&self.group_name, namespace),
+                ))
+            {
+                names.push(namespace.to_string());
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Close the given namespace (if it is open) and delete its index files.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the namespace existed.
+    pub fn remove_namespace(&mut self, namespace: &str) -> LevelResult<bool, LevelInitError> {
+        Self::check_namespace(namespace)?;
+
+        // the index files are unlocked when the level hash is dropped
+        let was_open = self.namespaces.remove(namespace).is_some();
+
+        let index_name = Self::namespace_index_name(&self.group_name, namespace);
+        let files = IndexFiles::default_layout(&self.index_dir, &index_name);
+        let exists = LevelHashIO::index_exists(&files);
+
+        let mut options = LevelHash::options();
+        options.index_dir(&self.index_dir).index_name(&index_name);
+        options.remove_existing_index()?;
+
+        if files.lock.exists() {
+            fs::remove_file(&files.lock)
+                .into_lvl_io_e_msg(format!("failed to delete file: {}", files.lock.display()))?;
+        }
+
+        Ok(was_open || exists)
+    }
+
+    /// Schedule the changes made to the open namespaces to be written to the disk. See
+    /// [LevelHash::flush].
+    pub fn flush(&mut self) -> LevelSyncResult {
+        for hash in self.namespaces.values_mut() {
+            hash.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the changes made to the open namespaces to the disk. See [LevelHash::sync_all].
+    pub fn sync_all(&mut self) -> LevelSyncResult {
+        for hash in self.namespaces.values_mut() {
+            hash.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the open namespaces of this group, mapped from their names.
+    pub fn into_namespaces(self) -> BTreeMap<String, LevelHash> {
+        self.namespaces
+    }
+
+    fn check_namespace(namespace: &str) -> LevelResult<(), LevelInitError> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if namespace.is_empty() || !namespace.chars().all(valid) {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Invalid namespace name: '{}'",
+                namespace
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::group::LevelHashGroup;
+    use crate::result::LevelInitError;
+    use crate::test_macros::assert_matches;
+
+    fn group_dir(name: &str) -> String {
+        let dir_path = format!("target/tests/level-hash/group-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+        dir_path
+    }
+
+    #[test]
+    fn group_namespaces() {
+        let dir_path = group_dir("namespaces");
+        let index_dir = Path::new(&dir_path);
+
+        {
+            let mut group = LevelHashGroup::with_options(index_dir, "symbols", |name, options| {
+                options.level_size(2).bucket_size(4);
+                if name == "members" {
+                    options.unique_keys(false);
+                }
+            })
+            .unwrap();
+
+            group
+                .namespace("classes")
+                .unwrap()
+                .insert(b"Foo", b"class")
+                .unwrap();
+            let members = group.namespace("members").unwrap();
+            members.insert(b"Foo", b"bar()").unwrap();
+            members.insert(b"Foo", b"baz()").unwrap();
+
+            assert_eq!(
+                group.namespace("classes").unwrap().get_value(b"Foo"),
+                b"class"
+            );
+            assert_eq!(group.namespace("members").unwrap().get_all(b"Foo").len(), 2);
+            assert!(group.get_namespace("fields").is_none());
+            assert_eq!(group.namespace_names().unwrap(), ["classes", "members"]);
+
+            // the group is locked while it is open
+            assert_matches!(
+                LevelHashGroup::open(index_dir, "symbols").err(),
+                Some(LevelInitError::IOError(_))
+            );
+        }
+
+        // another group in the same directory, whose name starts with the name of the group
+        let mut other = LevelHashGroup::open(index_dir, "symbols.old").unwrap();
+        other.namespace("classes").unwrap();
+        assert_eq!(other.namespace_names().unwrap(), ["classes"]);
+
+        let mut group = LevelHashGroup::open(index_dir, "symbols").unwrap();
+        assert_eq!(group.namespace_names().unwrap(), ["classes", "members"]);
+        assert_eq!(
+            group.namespace("classes").unwrap().get_value(b"Foo"),
+            b"class"
+        );
+
+        assert!(group.remove_namespace("classes").unwrap());
+        assert!(!group.remove_namespace("classes").unwrap());
+        assert_eq!(group.namespace_names().unwrap(), ["members"]);
+        assert_eq!(
+            group.namespace("classes").unwrap().get_value(b"Foo"),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn group_invalid_names() {
+        let dir_path = group_dir("invalid-names");
+        let index_dir = Path::new(&dir_path);
+
+        assert_matches!(
+            LevelHashGroup::open(index_dir, "").err(),
+            Some(LevelInitError::InvalidArg(_))
+        );
+
+        let mut group = LevelHashGroup::open(index_dir, "symbols").unwrap();
+        for name in ["", "a.b", "a/b", "../a"] {
+            assert_matches!(
+                group.namespace(name).err(),
+                Some(LevelInitError::InvalidArg(_))
+            );
+        }
+
+        assert_eq!(
+            LevelHashGroup::namespace_index_name("symbols", "classes"),
+            "symbols.classes"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The memory operations used by [MappedFile](super::MappedFile). The implementation is selected
+//! at runtime, the first time one of the operations is used, based on the features supported by
+//! the CPU. The SIMD implementations are only available with the `simd` feature.
+
+use std::sync::OnceLock;
+
+use crate::log_macros::log_debug;
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+#[path = "memops_aarch64.rs"]
+mod aarch64;
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[path = "memops_x86.rs"]
+mod x86;
+
+#[path = "memops_fallback.rs"]
+mod fallback;
+
+type MemEqFn = unsafe fn(*const u8, *const u8, usize) -> bool;
+type MemCpyFn = unsafe fn(*mut u8, *const u8, usize);
+
+/// An implementation of the memory operations.
+#[derive(Clone, Copy)]
+struct MemOps {
+    name: &'static str,
+    memeq: MemEqFn,
+    memcpy: MemCpyFn,
+}
+
+static MEMOPS: OnceLock<MemOps> = OnceLock::new();
+
+/// Get the implementations of the memory operations which are supported by the CPU, the
+/// preferred implementation first. The fallback implementation is always supported.
+#[allow(clippy::vec_init_then_push)] // the SIMD implementations are not pushed without `simd`
+fn supported() -> Vec<MemOps> {
+    let mut ops = Vec::new();
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("sse2") {
+        ops.push(MemOps {
+            name: "sse2",
+            memeq: x86::__memeq,
+            memcpy: x86::__memcpy,
+        });
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        ops.push(MemOps {
+            name: "neon",
+            memeq: aarch64::__memeq,
+            memcpy: aarch64::__memcpy,
+        });
+    }
+
+    ops.push(MemOps {
+        name: "fallback",
+        memeq: fallback::__memeq,
+        memcpy: fallback::__memcpy,
+    });
+
+    ops
+}
+
+#[inline]
+fn memops() -> &'static MemOps {
+    MEMOPS.get_or_init(|| {
+        let ops = supported()[0];
+        log_debug!("using the {} memory operations", ops.name);
+        ops
+    })
+}
+
+/// Check if the given memory regions of `len` bytes are equal.
+///
+/// ## Safety
+///
+/// Both the regions must be valid for reads of `len` bytes.
+#[inline]
+pub unsafe fn __memeq(lhs: *const u8, rhs: *const u8, len: usize) -> bool {
+    (memops().memeq)(lhs, rhs, len)
+}
+
+/// Copy `len` bytes from `src` to `dst`.
+///
+/// ## Safety
+///
+/// `src` must be valid for reads and `dst` must be valid for writes of `len` bytes, and the
+/// regions must not overlap.
+#[inline]
+pub unsafe fn __memcpy(dst: *mut u8, src: *const u8, len: usize) {
+    (memops().memcpy)(dst, src, len)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    use crate::io::memops::memops;
+    use crate::io::memops::supported;
+
+    /// The lengths which cover the empty regions, the regions shorter than a SIMD register, the
+    /// regions of whole registers and the regions with a tail.
+    const LENGTHS: [usize; 12] = [0, 1, 7, 8, 15, 16, 17, 31, 32, 33, 64, 100];
+
+    fn lengths(rng: &mut impl Rng) -> Vec<usize> {
+        let random = (0..200).map(|_| rng.gen_range(0..300)).collect::<Vec<_>>();
+        LENGTHS.into_iter().chain(random).collect()
+    }
+
+    #[test]
+    fn preferred_implementation_is_used() {
+        assert_eq!(memops().name, supported()[0].name);
+    }
+
+    #[test]
+    fn memeq_matches_slice_equality() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for ops in supported() {
+            for len in lengths(&mut rng) {
+                // compare at an odd offset so that the loads are unaligned
+                let mut lhs = vec![0u8; len + 1];
+                rng.fill(&mut lhs[..]);
+                let lhs = &lhs[1..];
+                let mut rhs = lhs.to_vec();
+
+                let eq = unsafe { (ops.memeq)(lhs.as_ptr(), rhs.as_ptr(), len) };
+                assert!(eq, "{}: equal regions of {} bytes", ops.name, len);
+
+                for pos in [0, len / 2, len.saturating_sub(1)] {
+                    if pos >= len {
+                        continue;
+                    }
+
+                    rhs[pos] ^= 1 << rng.gen_range(0..8);
+                    let eq = unsafe { (ops.memeq)(lhs.as_ptr(), rhs.as_ptr(), len) };
+                    assert_eq!(
+                        eq,
+                        lhs == rhs.as_slice(),
+                        "{}: regions of {} bytes which differ at {}",
+                        ops.name,
+                        len,
+                        pos
+                    );
+                    rhs[pos] = lhs[pos];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn memcpy_copies_all_bytes() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for ops in supported() {
+            for len in lengths(&mut rng) {
+                let mut src = vec![0u8; len + 1];
+                rng.fill(&mut src[..]);
+                let src = &src[1..];
+
+                // the bytes around the destination must not be written
+                let mut dst = vec![0xAAu8; len + 2];
+                unsafe { (ops.memcpy)(dst[1..].as_mut_ptr(), src.as_ptr(), len) };
+                assert_eq!(&dst[1..=len], src, "{}: copy of {} bytes", ops.name, len);
+                assert_eq!(dst[0], 0xAA);
+                assert_eq!(dst[len + 1], 0xAA);
+            }
+        }
+    }
+}
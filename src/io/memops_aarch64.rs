@@ -20,6 +20,7 @@
 /// ## Returns
 ///
 /// `true` if the memory regions are equal, `false` otherwise.
+#[target_feature(enable = "neon")]
 pub unsafe fn __memeq(lhs: *const u8, rhs: *const u8, len: usize) -> bool {
     use std::arch::aarch64::vceqq_u8;
     use std::arch::aarch64::vld1q_u8;
@@ -30,14 +31,25 @@ pub unsafe fn __memeq(lhs: *const u8, rhs: *const u8, len: usize) -> bool {
         let lchunk = vld1q_u8(lhs.add(i));
         let rchunk = vld1q_u8(rhs.add(i));
         let cmp = vceqq_u8(lchunk, rchunk);
-        if vminvq_u8(cmp) == 0xFF {
+        if vminvq_u8(cmp) != 0xFF {
             return false;
         }
         i += 16;
     }
+
+    // Compare the remaining bytes
+    while i < len {
+        if *lhs.add(i) != *rhs.add(i) {
+            return false;
+        }
+        i += 1;
+    }
+
     return true;
 }
 
+/// Copy the given memory region using Neon instructions.
+#[target_feature(enable = "neon")]
 pub unsafe fn __memcpy(dst: *mut u8, src: *const u8, len: usize) {
     let mut i = 0;
 
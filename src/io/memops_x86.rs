@@ -23,12 +23,12 @@ use std::arch::x86_64::_mm_storeu_si128;
 
 const MEMCMP_MIN_LEN: usize = 16;
 
-/// Check if the given memory regions are equal using Neon instructions.
+/// Check if the given memory regions are equal using SSE2 instructions.
 ///
 /// ## Returns
 ///
 /// `true` if the memory regions are equal, `false` otherwise.
-#[cfg(target_feature = "sse2")]
+#[target_feature(enable = "sse2")]
 pub unsafe fn __memeq(lhs: *const u8, rhs: *const u8, len: usize) -> bool {
     if len < MEMCMP_MIN_LEN {
         return libc::memcmp(lhs as *const libc::c_void, rhs as *const libc::c_void, len) == 0;
@@ -39,7 +39,7 @@ pub unsafe fn __memeq(lhs: *const u8, rhs: *const u8, len: usize) -> bool {
         let mem_chunk = _mm_loadu_si128(lhs.add(i) as *const __m128i);
         let arr_chunk = _mm_loadu_si128(rhs.add(i) as *const __m128i);
         let cmp = _mm_cmpeq_epi8(mem_chunk, arr_chunk);
-        if _mm_movemask_epi8(cmp) == 0xFFFF {
+        if _mm_movemask_epi8(cmp) != 0xFFFF {
             return false;
         }
         i += 16;
@@ -60,7 +60,8 @@ pub unsafe fn __memeq(lhs: *const u8, rhs: *const u8, len: usize) -> bool {
     true
 }
 
-#[cfg(target_feature = "sse2")]
+/// Copy the given memory region using SSE2 instructions.
+#[target_feature(enable = "sse2")]
 pub unsafe fn __memcpy(dst: *mut u8, src: *const u8, len: usize) {
     let mut i = 0;
 
@@ -15,16 +15,25 @@
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::io::inject_fault;
 use crate::io::MappedFile;
 use crate::result::{LevelMapError, LevelResult};
 use crate::types::OffT;
 
 impl MappedFile {
     pub fn remap(&mut self, size: OffT) -> LevelResult<(), LevelMapError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Resize { size }, |why| {
+            return Err(LevelMapError::IOError(
+                crate::result::StdIOError::with_message("failed to remap file".to_string(), why),
+            ));
+        });
+
         // reassigning drops the previous mmap which unmaps the file
         // then we map the file again with the new size
-        self.map = MappedFile::do_map(&self.fd, self.off, size)?;
+        self.map = MappedFile::do_map(&self.fd, self.off, size, self.opts)?;
+        self.locked = self.opts.lock(&self.map);
         self.size = size;
+        self.counters.remaps += 1;
 
         Ok(())
     }
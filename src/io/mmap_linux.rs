@@ -15,14 +15,29 @@
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use memmap2::Advice;
 use memmap2::RemapOptions;
 
+use crate::io::inject_fault;
 use crate::io::MappedFile;
 use crate::result::{IntoLevelIOErr, IntoLevelMapErr, LevelMapError, LevelResult};
 use crate::types::OffT;
 
 impl MappedFile {
     pub fn remap(&mut self, size: OffT) -> LevelResult<(), LevelMapError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Resize { size }, |why| {
+            return Err(LevelMapError::IOError(
+                crate::result::StdIOError::with_message("failed to remap file".to_string(), why),
+            ));
+        });
+
+        // growing a locked mapping fails if the new size exceeds RLIMIT_MEMLOCK, so the pages are
+        // unlocked and locked again (if possible) after the mapping is resized
+        if self.locked {
+            let _ = self.map.unlock();
+            self.locked = false;
+        }
+
         unsafe {
             self.map
                 .remap(size as usize, RemapOptions::new().may_move(true))
@@ -30,7 +45,17 @@ impl MappedFile {
         .into_lvl_io_e_msg("failed to remap file".to_string())
         .into_lvl_mmap_err()?;
 
+        // the pages added by mremap are neither populated nor advised
+        if self.opts.populate {
+            self.map
+                .advise(Advice::WillNeed)
+                .into_lvl_io_e_msg("failed to populate memory map".to_string())
+                .into_lvl_mmap_err()?;
+        }
+        self.opts.advise(&self.map);
+        self.locked = self.opts.lock(&self.map);
         self.size = size;
+        self.counters.remaps += 1;
 
         Ok(())
     }
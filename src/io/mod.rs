@@ -23,20 +23,8 @@ pub mod mmap;
 #[path = "mmap_linux.rs"]
 pub mod mmap;
 
-#[cfg(target_arch = "aarch64")]
-#[path = "memops_aarch64.rs"]
-pub mod memops;
-
-#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
-#[path = "memops_x86.rs"]
-pub mod memops;
-
-#[cfg(not(any(
-    all(target_arch = "x86_64", target_feature = "sse2"),
-    target_arch = "aarch64"
-)))]
-#[path = "memops_fallback.rs"]
 pub mod memops;
+pub mod segmented;
 
 use std::fs::File;
 use std::os::fd::AsRawFd;
@@ -48,14 +36,105 @@ use memmap2::MmapMut;
 use memmap2::MmapOptions;
 
 use crate::fs::fallocate_safe_punch;
+use crate::log_macros::log_error;
+use crate::log_macros::log_warn;
 use crate::result::IntoLevelIOErr;
 use crate::result::IntoLevelMapErr;
 use crate::result::LevelMapError;
+use crate::result::LevelReadError;
 use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::size::SIZE_U32;
 use crate::size::SIZE_U64;
 use crate::types::OffT;
 
-pub type IOEndianness = byteorder::NativeEndian;
+/// Consult the [IOShim](crate::testing::IOShim) of a file (if any) before an operation on the
+/// file, evaluating `$on_err` with the error if the shim fails the operation. This expands to
+/// nothing without the `testing` feature.
+macro_rules! inject_fault {
+    ($shim:expr, $op:expr, |$why:ident| $on_err:expr) => {
+        #[cfg(feature = "testing")]
+        if let Err($why) = crate::testing::before(&$shim, $op) {
+            $on_err;
+        }
+    };
+}
+
+pub(crate) use inject_fault;
+
+/// The byte order of the integers in the index files. The index files written before version 2 of
+/// the keymap use the native byte order of the host which created them, and are converted when
+/// they are opened.
+pub type IOEndianness = byteorder::LittleEndian;
+
+/// The options used to memory map a file.
+///
+/// # Properties
+///
+/// * `populate`: Prefault the pages of the mapping (`MAP_POPULATE`), so that the first accesses
+///   to the mapping do not cause page faults.
+/// * `huge_pages`: Advise the kernel to back the mapping with transparent huge pages
+///   (`MADV_HUGEPAGE`). This is only a hint, and is ignored if the kernel does not support
+///   transparent huge pages for the file.
+/// * `lock_memory`: Lock the pages of the mapping in memory (`mlock`), so that they are not paged
+///   out. If the pages cannot be locked (e.g. when `RLIMIT_MEMLOCK` is too low), the mapping is
+///   used without being locked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MapOptions {
+    pub populate: bool,
+    pub huge_pages: bool,
+    pub lock_memory: bool,
+}
+
+impl MapOptions {
+    /// Apply the advice for these options to the given memory map.
+    pub fn advise(&self, map: &MmapMut) {
+        if !self.huge_pages || map.is_empty() {
+            return;
+        }
+
+        let ptr = map.as_ptr() as *mut libc::c_void;
+        if unsafe { libc::madvise(ptr, map.len(), libc::MADV_HUGEPAGE) } != 0 {
+            log_warn!(
+                "failed to enable huge pages for memory map: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    /// Lock the pages of the given memory map in memory if [Self::lock_memory] is set.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the pages were locked.
+    pub fn lock(&self, map: &MmapMut) -> bool {
+        if !self.lock_memory || map.is_empty() {
+            return false;
+        }
+
+        match map.lock() {
+            Ok(()) => true,
+            Err(why) => {
+                log_warn!("failed to lock memory map, using it unlocked: {}", why);
+                false
+            }
+        }
+    }
+}
+
+/// The writes made to a file since it was opened.
+///
+/// # Properties
+///
+/// * `bytes`: The number of bytes written with the write accessors of the file.
+/// * `remaps`: The number of times the file was remapped, e.g. after it was resized.
+/// * `punches`: The number of regions of the file which were deallocated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteCounters {
+    pub bytes: u64,
+    pub remaps: u64,
+    pub punches: u64,
+}
 
 /// A memory-mapped file.
 #[derive(Debug)]
@@ -66,12 +145,32 @@ pub struct MappedFile {
     #[cfg_attr(target_os = "linux", allow(dead_code))]
     pub off: OffT,
     pub size: OffT,
+    pub opts: MapOptions,
+
+    /// The length of the file up to which its disk space has been reserved, see
+    /// [crate::fs::ftruncate_reserve].
+    pub reserved: OffT,
+
+    /// Whether the pages of the mapping are locked in memory. See [MapOptions::lock_memory].
+    pub locked: bool,
+
+    /// The writes made to the file since it was mapped.
+    pub counters: WriteCounters,
+
+    /// The shim which is consulted before the operations on the file.
+    #[cfg(feature = "testing")]
+    pub(crate) shim: crate::testing::FileShim,
 }
 
 impl MappedFile {
     /// Create a new [MappedFile] from the given file path. The region of the file from
-    /// offset `off` to `off + size` will be mapped.
-    pub fn from_path(path: &Path, off: OffT, size: OffT) -> LevelResult<Self, LevelMapError> {
+    /// offset `off` to `off + size` will be mapped with the given options.
+    pub fn from_path(
+        path: &Path,
+        off: OffT,
+        size: OffT,
+        opts: MapOptions,
+    ) -> LevelResult<Self, LevelMapError> {
         let file = File::options()
             .read(true)
             .write(true)
@@ -79,25 +178,76 @@ impl MappedFile {
             .open(path)
             .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))?;
 
-        Self::new(file.into(), off, size)
+        Self::with_options(file.into(), off, size, opts)
     }
 
     /// Create a new [MappedFile] from the given file. The region of the file from offset
     /// `off` to `off + size` will be mapped.
     pub fn new(fd: OwnedFd, off: OffT, size: OffT) -> LevelResult<Self, LevelMapError> {
-        let map = Self::do_map(&fd, off, size)?;
-        Ok(Self { map, fd, off, size })
+        Self::with_options(fd, off, size, MapOptions::default())
     }
 
-    pub fn do_map(fd: &OwnedFd, off: OffT, size: OffT) -> LevelResult<MmapMut, LevelMapError> {
-        unsafe {
-            MmapOptions::new()
-                .offset(off)
-                .len(size as usize)
-                .map_mut(fd.as_raw_fd())
+    /// Same as [Self::new], but maps the file with the given options.
+    pub fn with_options(
+        fd: OwnedFd,
+        off: OffT,
+        size: OffT,
+        opts: MapOptions,
+    ) -> LevelResult<Self, LevelMapError> {
+        let map = Self::do_map(&fd, off, size, opts)?;
+        let locked = opts.lock(&map);
+        Ok(Self {
+            map,
+            fd,
+            off,
+            size,
+            opts,
+            locked,
+            reserved: off + size,
+            counters: WriteCounters::default(),
+            #[cfg(feature = "testing")]
+            shim: None,
+        })
+    }
+
+    pub fn do_map(
+        fd: &OwnedFd,
+        off: OffT,
+        size: OffT,
+        opts: MapOptions,
+    ) -> LevelResult<MmapMut, LevelMapError> {
+        let mut options = MmapOptions::new();
+        options.offset(off).len(size as usize);
+        if opts.populate {
+            options.populate();
         }
-        .into_lvl_io_e_msg("failed to memory map file".to_string())
-        .into_lvl_mmap_err()
+
+        let map = unsafe { options.map_mut(fd.as_raw_fd()) }
+            .into_lvl_io_e_msg("failed to memory map file".to_string())
+            .into_lvl_mmap_err()?;
+
+        opts.advise(&map);
+        Ok(map)
+    }
+
+    /// Schedule the changes made to the memory map to be written to the file.
+    pub fn flush_async(&self) -> LevelResult<(), StdIOError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Flush, |why| {
+            return Err(why).into_lvl_io_e_msg("failed to flush memory map".to_string());
+        });
+        self.map
+            .flush_async()
+            .into_lvl_io_e_msg("failed to flush memory map".to_string())
+    }
+
+    /// Write the changes made to the memory map to the file, waiting for the writes to complete.
+    pub fn flush(&self) -> LevelResult<(), StdIOError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Flush, |why| {
+            return Err(why).into_lvl_io_e_msg("failed to flush memory map".to_string());
+        });
+        self.map
+            .flush()
+            .into_lvl_io_e_msg("failed to flush memory map".to_string())
     }
 
     pub fn memeq(&self, offset: OffT, arr: &[u8]) -> bool {
@@ -114,8 +264,39 @@ impl MappedFile {
     }
 
     #[inline]
-    pub fn deallocate(&mut self, offset: OffT, len: OffT) {
-        fallocate_safe_punch(self.fd.as_raw_fd(), offset, len)
+    pub fn deallocate(&mut self, offset: OffT, len: OffT) -> LevelResult<(), StdIOError> {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Deallocate {
+                off: offset.saturating_sub(self.off),
+                len
+            },
+            |why| return Err(why).into_lvl_io_e_msg("failed to deallocate region".to_string())
+        );
+        fallocate_safe_punch(self.fd.as_raw_fd(), offset, len)?;
+        self.counters.punches += 1;
+        Ok(())
+    }
+
+    /// Check whether the region of `len` bytes at the given offset is within the mapped region,
+    /// returning [LevelReadError::CorruptIndex] otherwise. Unlike the unchecked accessors, which
+    /// panic on out-of-bounds accesses, this is used for the addresses read from the index files.
+    pub fn check_bounds(&self, off: OffT, len: OffT) -> LevelResult<(), LevelReadError> {
+        match off.checked_add(len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(LevelReadError::CorruptIndex {
+                addr: off,
+                len,
+                size: self.size,
+            }),
+        }
+    }
+
+    /// Same as [Self::r_u64], but returns an error instead of panicking if the region is out of
+    /// bounds.
+    pub fn try_r_u64(&self, off: OffT) -> LevelResult<u64, LevelReadError> {
+        self.check_bounds(off, SIZE_U64)?;
+        Ok(self.r_u64(off))
     }
 
     pub fn read_at(&self, off: OffT, dst: &mut [u8]) {
@@ -127,11 +308,38 @@ impl MappedFile {
     }
 
     pub fn write_at(&mut self, off: OffT, src: &[u8]) {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Write {
+                off,
+                len: src.len() as OffT
+            },
+            |_why| return
+        );
         let pos = off as usize;
         let size = self.size as usize;
         let len = src.len();
         assert!(pos + len <= size);
         unsafe { self::memops::__memcpy(self.map[pos..pos + len].as_mut_ptr(), src.as_ptr(), len) }
+        self.counters.bytes += len as u64;
+    }
+
+    pub fn r_u32(&self, off: OffT) -> u32 {
+        assert!(off + SIZE_U32 <= self.size);
+        let pos = off as usize;
+        IOEndianness::read_u32(&self.map[pos..pos + SIZE_U32 as usize])
+    }
+
+    pub fn w_u32(&mut self, off: OffT, value: u32) {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Write { off, len: SIZE_U32 },
+            |_why| return
+        );
+        assert!(off + SIZE_U32 <= self.size);
+        let pos = off as usize;
+        IOEndianness::write_u32(&mut self.map[pos..pos + SIZE_U32 as usize], value);
+        self.counters.bytes += SIZE_U32;
     }
 
     pub fn r_u64(&self, off: OffT) -> u64 {
@@ -141,14 +349,24 @@ impl MappedFile {
     }
 
     pub fn w_u64(&mut self, off: OffT, value: u64) {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Write { off, len: SIZE_U64 },
+            |_why| return
+        );
         assert!(off + SIZE_U64 <= self.size);
         let pos = off as usize;
         IOEndianness::write_u64(&mut self.map[pos..pos + SIZE_U64 as usize], value);
+        self.counters.bytes += SIZE_U64;
     }
 }
 
 impl Drop for MappedFile {
     fn drop(&mut self) {
-        self.map.flush().expect("failed to flush memory map");
+        // a panic here would abort the process if the file is dropped while unwinding, the errors
+        // are reported by LevelHash::close instead
+        if let Err(why) = self.map.flush() {
+            log_error!("failed to flush memory map: {}", why);
+        }
     }
 }
@@ -0,0 +1,432 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs::remove_file;
+use std::fs::File;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::OwnedFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::fs::fallocate_safe_punch;
+use crate::fs::ftruncate_reserve;
+use crate::fs::write_magic_fd;
+use crate::io::inject_fault;
+use crate::io::MapOptions;
+use crate::io::MappedFile;
+use crate::io::WriteCounters;
+use crate::log_macros::log_error;
+use crate::result::IntoLevelIOErr;
+use crate::result::LevelMapError;
+use crate::result::LevelReadError;
+use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::size::SIZE_U64;
+use crate::types::OffT;
+
+/// A file whose contents are split in fixed-size segment files, each of which is memory-mapped
+/// separately. The segment `i` stores the (0-based) addresses from `i * segment_size` to
+/// `(i + 1) * segment_size`, so an address encodes both its segment and its offset in the segment.
+/// Only the last segment is resized when the file grows or shrinks, the mappings of the other
+/// segments are left as they are.
+///
+/// The first segment is the file at `path` and the segment `i` is the file at `path.i`. Every
+/// segment file starts with a header of `header_size` bytes, which is not mapped. A file which is
+/// not segmented has a single segment of unlimited size.
+///
+/// The region at an address must not cross the end of its segment. The unchecked accessors panic
+/// if it does, like they do for the regions which are out of bounds.
+///
+/// # Properties
+///
+/// * `segments`: The mapped segments, first segment first.
+/// * `path`: The path of the first segment, or `None` if the file is not segmented.
+/// * `header_size`: The size (bytes) of the header of each segment file.
+/// * `segment_size`: The number of bytes stored in each segment.
+/// * `magic_number`: The magic number written in the header of the new segment files.
+/// * `size`: The number of bytes stored in the file, i.e. the sum of the sizes of the segments.
+/// * `opts`: The options used to memory map the segments.
+/// * `counters`: The writes made to the file since it was opened. The segments are mapped again
+///   when the file is resized, so the writes are counted for the file rather than its segments.
+/// * `shim`: The shim which is consulted before the operations on the file. The operations are
+///   reported with the addresses in the file, the shims of the segments are not set.
+#[derive(Debug)]
+pub struct SegmentedFile {
+    segments: Vec<MappedFile>,
+    path: Option<PathBuf>,
+    header_size: OffT,
+    segment_size: OffT,
+    magic_number: u64,
+    pub size: OffT,
+    pub opts: MapOptions,
+    pub counters: WriteCounters,
+    #[cfg(feature = "testing")]
+    pub(crate) shim: crate::testing::FileShim,
+}
+
+impl SegmentedFile {
+    /// Create a [SegmentedFile] which is not segmented from the given mapped file.
+    pub fn single(file: MappedFile) -> Self {
+        Self {
+            path: None,
+            header_size: file.off,
+            segment_size: OffT::MAX,
+            magic_number: 0,
+            size: file.size,
+            opts: file.opts,
+            segments: vec![file],
+            counters: WriteCounters::default(),
+            #[cfg(feature = "testing")]
+            shim: None,
+        }
+    }
+
+    /// Map the segments of the file at the given path which store `size` bytes, each segment
+    /// storing up to `segment_size` bytes. The segment files which do not exist are created with
+    /// the given magic number in their header.
+    pub fn open(
+        path: &Path,
+        header_size: OffT,
+        size: OffT,
+        segment_size: OffT,
+        magic_number: u64,
+        opts: MapOptions,
+    ) -> LevelResult<Self, LevelMapError> {
+        let mut file = Self {
+            segments: Vec::new(),
+            path: Some(path.to_path_buf()),
+            header_size,
+            segment_size,
+            magic_number,
+            size: 0,
+            opts,
+            counters: WriteCounters::default(),
+            #[cfg(feature = "testing")]
+            shim: None,
+        };
+
+        file.map_segments(size, false)?;
+        Ok(file)
+    }
+
+    /// Get the path of the segment file at the given index, given the path of the first segment.
+    pub fn segment_path(path: &Path, idx: usize) -> PathBuf {
+        if idx == 0 {
+            return path.to_path_buf();
+        }
+
+        let mut path = path.as_os_str().to_os_string();
+        path.push(format!(".{}", idx));
+        PathBuf::from(path)
+    }
+
+    /// Get the number of bytes stored in each segment, which is [OffT::MAX] if the file is not
+    /// segmented.
+    #[inline]
+    pub fn segment_size(&self) -> OffT {
+        self.segment_size
+    }
+
+    /// Get the (0-based) address at which the segment of the given address ends.
+    #[inline]
+    pub fn segment_end(&self, off: OffT) -> OffT {
+        (off / self.segment_size + 1).saturating_mul(self.segment_size)
+    }
+
+    /// Get the descriptors of the segment files, first segment first.
+    pub fn segment_fds(&self) -> impl Iterator<Item = BorrowedFd<'_>> {
+        self.segments.iter().map(|segment| segment.fd.as_fd())
+    }
+
+    /// Get the descriptor of the first segment file.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.segments[0].fd.as_fd()
+    }
+
+    /// Resize the file to store `size` bytes, creating the new segment files and deleting the
+    /// segment files which are no longer needed.
+    pub fn resize(&mut self, size: OffT) -> LevelResult<(), LevelMapError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Resize { size }, |why| {
+            return Err(LevelMapError::IOError(StdIOError::with_message(
+                "failed to resize file".to_string(),
+                why,
+            )));
+        });
+        self.map_segments(size, true)?;
+        self.counters.remaps += 1;
+        Ok(())
+    }
+
+    /// Map the file again after it has been resized by another process.
+    pub fn remap(&mut self, size: OffT) -> LevelResult<(), LevelMapError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Resize { size }, |why| {
+            return Err(LevelMapError::IOError(StdIOError::with_message(
+                "failed to remap file".to_string(),
+                why,
+            )));
+        });
+        self.map_segments(size, false)?;
+        self.counters.remaps += 1;
+        Ok(())
+    }
+
+    /// Truncate and map the segments which store `size` bytes. The segment files which are
+    /// created while the file grows are emptied if `resize` is `true`, as they may have been left
+    /// by a resize which was interrupted before the new size was recorded.
+    fn map_segments(&mut self, size: OffT, resize: bool) -> LevelResult<(), LevelMapError> {
+        let count = size.div_ceil(self.segment_size).max(1) as usize;
+        while self.segments.len() > count {
+            self.segments.pop();
+            if let (true, Some(path)) = (resize, self.path.as_ref()) {
+                let path = Self::segment_path(path, self.segments.len());
+                if let Err(why) = remove_file(&path) {
+                    log_error!("failed to delete segment {}: {}", path.display(), why);
+                }
+            }
+        }
+
+        for idx in 0..count {
+            let start = idx as OffT * self.segment_size;
+            let segment_size = (size - start).min(self.segment_size);
+            if let Some(segment) = self.segments.get_mut(idx) {
+                if segment.size != segment_size {
+                    ftruncate_reserve(
+                        segment.fd.as_raw_fd(),
+                        self.header_size + segment_size,
+                        &mut segment.reserved,
+                    )
+                    .map_err(LevelMapError::TruncateFailed)?;
+                    segment.remap(segment_size)?;
+                }
+                continue;
+            }
+
+            let fd = self.open_segment(idx, resize)?;
+            let mut reserved = 0;
+            ftruncate_reserve(
+                fd.as_raw_fd(),
+                self.header_size + segment_size,
+                &mut reserved,
+            )
+            .map_err(LevelMapError::TruncateFailed)?;
+            let mut segment =
+                MappedFile::with_options(fd, self.header_size, segment_size, self.opts)?;
+            segment.reserved = reserved.max(segment.reserved);
+            self.segments.push(segment);
+        }
+
+        self.size = size;
+        Ok(())
+    }
+
+    /// Open (or create) the segment file at the given index, writing the magic number to the new
+    /// segment files.
+    fn open_segment(&self, idx: usize, truncate: bool) -> LevelResult<OwnedFd, StdIOError> {
+        let Some(path) = self.path.as_ref() else {
+            return Err(StdIOError::with_message(
+                "cannot add a segment to a file which is not segmented".to_string(),
+                std::io::ErrorKind::Unsupported.into(),
+            ));
+        };
+
+        let path = Self::segment_path(path, idx);
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .open(&path)
+            .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))?;
+
+        let len = file
+            .metadata()
+            .into_lvl_io_e_msg(format!("failed to read metadata of {}", path.display()))?
+            .len();
+        if len < self.header_size {
+            write_magic_fd(file.as_fd(), self.magic_number)?;
+        }
+
+        Ok(file.into())
+    }
+
+    /// Get the segment which stores the given address, along with the offset of the address in
+    /// the segment.
+    #[inline]
+    fn locate(&self, off: OffT) -> (&MappedFile, OffT) {
+        let idx = (off / self.segment_size) as usize;
+        (&self.segments[idx], off % self.segment_size)
+    }
+
+    #[inline]
+    fn locate_mut(&mut self, off: OffT) -> (&mut MappedFile, OffT) {
+        let idx = (off / self.segment_size) as usize;
+        (&mut self.segments[idx], off % self.segment_size)
+    }
+
+    /// Get a pointer to the given address.
+    #[inline]
+    pub fn ptr(&self, off: OffT) -> *const u8 {
+        let (segment, off) = self.locate(off);
+        unsafe { segment.map.as_ptr().add(off as usize) }
+    }
+
+    /// Get a mutable pointer to the given address.
+    #[inline]
+    pub fn ptr_mut(&mut self, off: OffT) -> *mut u8 {
+        let (segment, off) = self.locate_mut(off);
+        unsafe { segment.map.as_mut_ptr().add(off as usize) }
+    }
+
+    /// Get the region of `len` bytes at the given address.
+    pub fn slice(&self, off: OffT, len: OffT) -> &[u8] {
+        let (segment, off) = self.locate(off);
+        &segment.map[off as usize..(off + len) as usize]
+    }
+
+    /// Get the mutable region of `len` bytes at the given address.
+    pub fn slice_mut(&mut self, off: OffT, len: OffT) -> &mut [u8] {
+        self.counters.bytes += len;
+        let (segment, off) = self.locate_mut(off);
+        &mut segment.map[off as usize..(off + len) as usize]
+    }
+
+    /// Schedule the changes made to the segments to be written to the files.
+    pub fn flush_async(&self) -> LevelResult<(), StdIOError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Flush, |why| {
+            return Err(why).into_lvl_io_e_msg("failed to flush memory map".to_string());
+        });
+        self.segments.iter().try_for_each(MappedFile::flush_async)
+    }
+
+    /// Write the changes made to the segments to the files, waiting for the writes to complete.
+    pub fn flush(&self) -> LevelResult<(), StdIOError> {
+        inject_fault!(self.shim, crate::testing::FileOp::Flush, |why| {
+            return Err(why).into_lvl_io_e_msg("failed to flush memory map".to_string());
+        });
+        self.segments.iter().try_for_each(MappedFile::flush)
+    }
+
+    pub fn memeq(&self, off: OffT, arr: &[u8]) -> bool {
+        if self.check_bounds(off, arr.len() as OffT).is_err() {
+            return false;
+        }
+
+        let (segment, off) = self.locate(off);
+        segment.memeq(off, arr)
+    }
+
+    /// Deallocate the region of `len` bytes at the given address, which may span multiple
+    /// segments.
+    pub fn deallocate(&mut self, off: OffT, len: OffT) -> LevelResult<(), StdIOError> {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Deallocate { off, len },
+            |why| return Err(why).into_lvl_io_e_msg("failed to deallocate region".to_string())
+        );
+        let end = off + len;
+        let mut off = off;
+        while off < end {
+            let region_end = self.segment_end(off).min(end);
+            let (segment, seg_off) = self.locate(off);
+            fallocate_safe_punch(
+                segment.fd.as_raw_fd(),
+                self.header_size + seg_off,
+                region_end - off,
+            )?;
+            self.counters.punches += 1;
+            off = region_end;
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the region of `len` bytes at the given address is within the mapped region
+    /// of a single segment, returning [LevelReadError::CorruptIndex] otherwise.
+    pub fn check_bounds(&self, off: OffT, len: OffT) -> LevelResult<(), LevelReadError> {
+        match off.checked_add(len) {
+            Some(end) if end <= self.size && (len == 0 || end <= self.segment_end(off)) => Ok(()),
+            _ => Err(LevelReadError::CorruptIndex {
+                addr: off,
+                len,
+                size: self.size,
+            }),
+        }
+    }
+
+    /// Same as [Self::r_u64], but returns an error instead of panicking if the region is out of
+    /// bounds.
+    pub fn try_r_u64(&self, off: OffT) -> LevelResult<u64, LevelReadError> {
+        self.check_bounds(off, SIZE_U64)?;
+        Ok(self.r_u64(off))
+    }
+
+    pub fn read_at(&self, off: OffT, dst: &mut [u8]) {
+        let (segment, off) = self.locate(off);
+        segment.read_at(off, dst)
+    }
+
+    pub fn write_at(&mut self, off: OffT, src: &[u8]) {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Write {
+                off,
+                len: src.len() as OffT
+            },
+            |_why| return
+        );
+        self.counters.bytes += src.len() as OffT;
+        let (segment, off) = self.locate_mut(off);
+        segment.write_at(off, src)
+    }
+
+    pub fn r_u32(&self, off: OffT) -> u32 {
+        let (segment, off) = self.locate(off);
+        segment.r_u32(off)
+    }
+
+    pub fn w_u32(&mut self, off: OffT, value: u32) {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Write {
+                off,
+                len: crate::size::SIZE_U32
+            },
+            |_why| return
+        );
+        self.counters.bytes += crate::size::SIZE_U32;
+        let (segment, off) = self.locate_mut(off);
+        segment.w_u32(off, value)
+    }
+
+    pub fn r_u64(&self, off: OffT) -> u64 {
+        let (segment, off) = self.locate(off);
+        segment.r_u64(off)
+    }
+
+    pub fn w_u64(&mut self, off: OffT, value: u64) {
+        inject_fault!(
+            self.shim,
+            crate::testing::FileOp::Write { off, len: SIZE_U64 },
+            |_why| return
+        );
+        self.counters.bytes += SIZE_U64;
+        let (segment, off) = self.locate_mut(off);
+        segment.w_u64(off, value)
+    }
+}
@@ -0,0 +1,497 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! JNI bindings for [LevelHash], for use from the `com.itsaky.androidide.levelhash.LevelHash`
+//! class. The class is expected to declare the following static native methods :
+//!
+//! ```java
+//! static native long nativeOpen(String indexDir, String indexName) throws IOException;
+//! static native void nativeClose(long hash);
+//! static native byte[] nativeGet(long hash, byte[] key) throws IOException;
+//! static native void nativePut(long hash, byte[] key, byte[] value) throws IOException;
+//! static native byte[] nativeRemove(long hash, byte[] key);
+//! static native long nativeIterOpen(long hash, byte[] prefix);
+//! static native byte[][] nativeIterNext(long iter);
+//! static native void nativeIterClose(long iter);
+//! ```
+//!
+//! The level hash and the iterators are passed around as `long` handles. The level hash may be
+//! used from multiple threads, its operations are serialized with a lock. Each iterator holds a
+//! reference to the level hash, so the level hash stays open until it is closed and all of its
+//! iterators are closed. The iterators lock the level hash only while a batch of its buckets is
+//! scanned, so the level hash can be modified while an iterator is open.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use jni::objects::AutoElements;
+use jni::objects::JByteArray;
+use jni::objects::JClass;
+use jni::objects::JObject;
+use jni::objects::JString;
+use jni::objects::ReleaseMode;
+use jni::sys::jbyte;
+use jni::sys::jbyteArray;
+use jni::sys::jlong;
+use jni::sys::jobjectArray;
+use jni::JNIEnv;
+
+use crate::log_macros::log_error;
+use crate::LevelHash;
+
+const IO_EXCEPTION: &str = "java/io/IOException";
+const ILLEGAL_ARGUMENT_EXCEPTION: &str = "java/lang/IllegalArgumentException";
+const ILLEGAL_STATE_EXCEPTION: &str = "java/lang/IllegalStateException";
+const CONCURRENT_MODIFICATION_EXCEPTION: &str = "java/util/ConcurrentModificationException";
+
+/// The number of buckets which are scanned at once by [EntryIter].
+const ITER_BATCH_BUCKETS: u32 = 64;
+
+/// The level hash behind a handle returned by `nativeOpen`.
+type HashHandle = Arc<Mutex<LevelHash>>;
+
+/// The key and the value of an entry returned by [EntryIter].
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// The class and the message of an exception to throw.
+type Exception = (&'static str, &'static str);
+
+/// An iterator over the entries of a level hash, as returned by `nativeIterOpen`. The buckets of
+/// the level hash are scanned in batches of [ITER_BATCH_BUCKETS], and the level hash is locked
+/// only while a batch is scanned.
+struct EntryIter {
+    hash: HashHandle,
+    prefix: Vec<u8>,
+    // the generation of the keymap when the iterator was opened
+    generation: u64,
+    // the first bucket which has not been scanned yet
+    next_bucket: u32,
+    entries: VecDeque<Entry>,
+}
+
+impl EntryIter {
+    /// Get the next entry, scanning the next batch of buckets if needed.
+    ///
+    /// ## Returns
+    ///
+    /// The next entry, `None` if all the buckets have been scanned, or the exception to throw if
+    /// the level hash cannot be scanned further.
+    fn next_entry(&mut self) -> Result<Option<Entry>, Exception> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return Ok(Some(entry));
+            }
+
+            let hash = self
+                .hash
+                .lock()
+                .map_err(|_| (ILLEGAL_STATE_EXCEPTION, "level hash mutex is poisoned"))?;
+
+            // the buckets are renumbered by a structural change of the keymap, the remaining
+            // batches would skip or repeat entries
+            if hash.generation() != self.generation {
+                return Err((
+                    CONCURRENT_MODIFICATION_EXCEPTION,
+                    "level hash was restructured during iteration",
+                ));
+            }
+
+            let Some(cursor) = hash.partitions(1).pop() else {
+                return Ok(None);
+            };
+            if self.next_bucket >= cursor.buckets().end {
+                return Ok(None);
+            }
+
+            let batch = self.next_bucket..self.next_bucket.saturating_add(ITER_BATCH_BUCKETS);
+            self.next_bucket = batch.end;
+
+            let prefix = &self.prefix;
+            self.entries.extend(
+                cursor
+                    .take_buckets(batch)
+                    .filter(|(key, _)| key.starts_with(prefix)),
+            );
+        }
+    }
+}
+
+/// Open the level hash with the given name in the given directory, creating it if it does not
+/// exist. Throws an `IOException` if the level hash could not be opened.
+///
+/// ## Returns
+///
+/// The handle to the level hash, or `0` if the level hash could not be opened. The handle must be
+/// released with `nativeClose`.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeOpen(
+    mut env: JNIEnv,
+    _class: JClass,
+    index_dir: JString,
+    index_name: JString,
+) -> jlong {
+    let (Ok(index_dir), Ok(index_name)) = (env.get_string(&index_dir), env.get_string(&index_name))
+    else {
+        throw(
+            &mut env,
+            ILLEGAL_ARGUMENT_EXCEPTION,
+            "invalid index directory or name",
+        );
+        return 0;
+    };
+
+    let index_dir = String::from(index_dir);
+    let index_name = String::from(index_name);
+
+    match LevelHash::options()
+        .index_dir(Path::new(&index_dir))
+        .index_name(&index_name)
+        .build()
+    {
+        Ok(hash) => Arc::into_raw(Arc::new(Mutex::new(hash))) as jlong,
+        Err(why) => {
+            throw(
+                &mut env,
+                IO_EXCEPTION,
+                &format!("failed to open level hash: {:?}", why),
+            );
+            0
+        }
+    }
+}
+
+/// Close the level hash. The handle must not be used after this call. The level hash is synced to
+/// the disk and released once all of its iterators are closed too.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeClose(
+    _env: JNIEnv,
+    _class: JClass,
+    hash: jlong,
+) {
+    if hash != 0 {
+        drop(unsafe { Arc::from_raw(hash as *const Mutex<LevelHash>) });
+    }
+}
+
+/// Get the value associated with the given key. Throws an `IOException` if the entry could not be
+/// read.
+///
+/// ## Returns
+///
+/// The value of the entry, or `null` if there is no entry for the key.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeGet(
+    mut env: JNIEnv,
+    _class: JClass,
+    hash: jlong,
+    key: JByteArray,
+) -> jbyteArray {
+    let Some(hash) = (unsafe { hash_handle(hash) }) else {
+        throw(&mut env, ILLEGAL_ARGUMENT_EXCEPTION, "level hash is closed");
+        return ptr::null_mut();
+    };
+
+    let Some(key) = read_bytes(&mut env, &key, "key") else {
+        return ptr::null_mut();
+    };
+
+    let Some(hash) = lock(&mut env, &hash) else {
+        return ptr::null_mut();
+    };
+
+    let value = hash.try_get_value(as_bytes(&key));
+    drop(hash);
+
+    match value {
+        Ok(Some(value)) => new_byte_array(&mut env, &value),
+        Ok(None) => ptr::null_mut(),
+        Err(why) => {
+            throw(
+                &mut env,
+                IO_EXCEPTION,
+                &format!("failed to get entry: {:?}", why),
+            );
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Insert the given key-value pair in the level hash, replacing the value of the existing entry
+/// for the key, if any. Throws an `IOException` if the entry could not be written.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativePut(
+    mut env: JNIEnv,
+    _class: JClass,
+    hash: jlong,
+    key: JByteArray,
+    value: JByteArray,
+) {
+    let Some(hash) = (unsafe { hash_handle(hash) }) else {
+        throw(&mut env, ILLEGAL_ARGUMENT_EXCEPTION, "level hash is closed");
+        return;
+    };
+
+    let (Some(key), Some(value)) = (
+        read_bytes(&mut env, &key, "key"),
+        read_bytes(&mut env, &value, "value"),
+    ) else {
+        return;
+    };
+
+    if key.is_empty() {
+        throw(
+            &mut env,
+            ILLEGAL_ARGUMENT_EXCEPTION,
+            "key must not be empty",
+        );
+        return;
+    }
+
+    let Some(mut hash) = lock(&mut env, &hash) else {
+        return;
+    };
+
+    let result = hash.insert_replace(as_bytes(&key), as_bytes(&value));
+    drop(hash);
+
+    if let Err(why) = result {
+        throw(
+            &mut env,
+            IO_EXCEPTION,
+            &format!("failed to put entry: {:?}", why),
+        );
+    }
+}
+
+/// Remove the entry for the given key.
+///
+/// ## Returns
+///
+/// The value of the removed entry, or `null` if there is no entry for the key.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeRemove(
+    mut env: JNIEnv,
+    _class: JClass,
+    hash: jlong,
+    key: JByteArray,
+) -> jbyteArray {
+    let Some(hash) = (unsafe { hash_handle(hash) }) else {
+        throw(&mut env, ILLEGAL_ARGUMENT_EXCEPTION, "level hash is closed");
+        return ptr::null_mut();
+    };
+
+    let Some(key) = read_bytes(&mut env, &key, "key") else {
+        return ptr::null_mut();
+    };
+
+    let Some(mut hash) = lock(&mut env, &hash) else {
+        return ptr::null_mut();
+    };
+
+    let value = hash.remove(as_bytes(&key));
+    drop(hash);
+
+    match value {
+        Some(value) => new_byte_array(&mut env, &value),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Open an iterator over the entries whose keys start with the given prefix. The entries are
+/// read lazily, a batch of buckets at a time, like the cursors of [LevelHash::partitions]. The
+/// entries written while the iterator is open may or may not be returned by the iterator.
+///
+/// ## Returns
+///
+/// The handle to the iterator, which must be released with `nativeIterClose`.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeIterOpen(
+    mut env: JNIEnv,
+    _class: JClass,
+    hash: jlong,
+    prefix: JByteArray,
+) -> jlong {
+    let Some(hash) = (unsafe { hash_handle(hash) }) else {
+        throw(&mut env, ILLEGAL_ARGUMENT_EXCEPTION, "level hash is closed");
+        return 0;
+    };
+
+    let prefix = if prefix.is_null() {
+        Vec::new()
+    } else {
+        match read_bytes(&mut env, &prefix, "prefix") {
+            Some(prefix) => as_bytes(&prefix).to_vec(),
+            None => return 0,
+        }
+    };
+
+    let Some(generation) = lock(&mut env, &hash).map(|hash| hash.generation()) else {
+        return 0;
+    };
+
+    let iter = EntryIter {
+        hash,
+        prefix,
+        generation,
+        next_bucket: 0,
+        entries: VecDeque::new(),
+    };
+
+    Box::into_raw(Box::new(iter)) as jlong
+}
+
+/// Get the next entry from the iterator. Throws a `ConcurrentModificationException` if the level
+/// hash was expanded, shrunk, cleared or repaired since the iterator was opened.
+///
+/// ## Returns
+///
+/// An array containing the key and the value of the next entry, or `null` if there are no more
+/// entries.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeIterNext(
+    mut env: JNIEnv,
+    _class: JClass,
+    iter: jlong,
+) -> jobjectArray {
+    let Some(iter) = (unsafe { (iter as *mut EntryIter).as_mut() }) else {
+        throw(&mut env, ILLEGAL_ARGUMENT_EXCEPTION, "iterator is closed");
+        return ptr::null_mut();
+    };
+
+    let (key, value) = match iter.next_entry() {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return ptr::null_mut(),
+        Err((class, msg)) => {
+            throw(&mut env, class, msg);
+            return ptr::null_mut();
+        }
+    };
+
+    let entry = (|| {
+        let key = env.byte_array_from_slice(&key)?;
+        let value = env.byte_array_from_slice(&value)?;
+        let entry = env.new_object_array(2, "[B", JObject::null())?;
+        env.set_object_array_element(&entry, 0, key)?;
+        env.set_object_array_element(&entry, 1, value)?;
+        Ok::<_, jni::errors::Error>(entry)
+    })();
+
+    match entry {
+        Ok(entry) => entry.into_raw(),
+        Err(why) => {
+            log_error!("failed to create entry array: {:?}", why);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Close the iterator, releasing its reference to the level hash. The handle must not be used
+/// after this call.
+#[no_mangle]
+pub extern "system" fn Java_com_itsaky_androidide_levelhash_LevelHash_nativeIterClose(
+    _env: JNIEnv,
+    _class: JClass,
+    iter: jlong,
+) {
+    if iter != 0 {
+        drop(unsafe { Box::from_raw(iter as *mut EntryIter) });
+    }
+}
+
+/// Get a new reference to the level hash of the given handle, or `None` if the handle is `0`. The
+/// reference of the handle itself is released by `nativeClose`.
+///
+/// # Safety
+///
+/// `hash` must be `0` or a handle returned by `nativeOpen` which has not been closed.
+unsafe fn hash_handle(hash: jlong) -> Option<HashHandle> {
+    let hash = hash as *const Mutex<LevelHash>;
+    (!hash.is_null()).then(|| {
+        Arc::increment_strong_count(hash);
+        Arc::from_raw(hash)
+    })
+}
+
+/// Lock the level hash. Throws an `IllegalStateException` if the lock is poisoned.
+fn lock<'a>(env: &mut JNIEnv, hash: &'a HashHandle) -> Option<MutexGuard<'a, LevelHash>> {
+    match hash.lock() {
+        Ok(hash) => Some(hash),
+        Err(_) => {
+            throw(env, ILLEGAL_STATE_EXCEPTION, "level hash mutex is poisoned");
+            None
+        }
+    }
+}
+
+/// Get the elements of the given array, which are not copied back when they are released as they
+/// are only read. Throws an `IllegalArgumentException` if the array is `null`. The elements are
+/// not accessed in a critical region, as the thread may block on the lock of the level hash while
+/// the other thread which holds the lock allocates Java arrays.
+///
+/// ## Returns
+///
+/// The elements of the array, or `None` if the array is `null` or could not be read.
+fn read_bytes<'local, 'other, 'array>(
+    env: &mut JNIEnv<'local>,
+    array: &'array JByteArray<'other>,
+    name: &str,
+) -> Option<AutoElements<'local, 'other, 'array, jbyte>> {
+    if array.is_null() {
+        throw(
+            env,
+            ILLEGAL_ARGUMENT_EXCEPTION,
+            &format!("{} must not be null", name),
+        );
+        return None;
+    }
+
+    match unsafe { env.get_array_elements(array, ReleaseMode::NoCopyBack) } {
+        Ok(bytes) => Some(bytes),
+        Err(why) => {
+            log_error!("failed to read {}: {:?}", name, why);
+            None
+        }
+    }
+}
+
+/// Get the elements of a Java byte array as bytes.
+fn as_bytes(elements: &[jbyte]) -> &[u8] {
+    unsafe { slice::from_raw_parts(elements.as_ptr() as *const u8, elements.len()) }
+}
+
+/// Create a new Java byte array with the given contents.
+fn new_byte_array(env: &mut JNIEnv, bytes: &[u8]) -> jbyteArray {
+    match env.byte_array_from_slice(bytes) {
+        Ok(array) => array.into_raw(),
+        Err(why) => {
+            log_error!("failed to create byte array: {:?}", why);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Throw an exception of the given class with the given message.
+fn throw(env: &mut JNIEnv, class: &str, msg: &str) {
+    if let Err(why) = env.throw_new(class, msg) {
+        log_error!("failed to throw {}: {:?}", class, why);
+    }
+}
@@ -0,0 +1,63 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Transformations which are applied to the keys of a [LevelHash](crate::LevelHash) before they
+//! are hashed and compared. The transformation is set with
+//! [LevelHashOptions::key_transform](crate::LevelHashOptions::key_transform).
+
+use std::borrow::Cow;
+
+/// A transformation which is applied to every key passed to a [LevelHash](crate::LevelHash),
+/// before the key is hashed, compared with the keys of the entries or stored. The keys which
+/// transform to the same bytes are equivalent and refer to the same entry, and the entries are
+/// stored (and returned by [LevelHash::keys](crate::LevelHash::keys)) with the transformed keys.
+///
+/// The transformation must be deterministic and idempotent, i.e. transforming a transformed key
+/// must return it unchanged. Like the seeds and the hash functions, the transformation is not
+/// stored in the index and the same transformation must be used every time the index is opened.
+///
+/// Any `Fn(&[u8]) -> Vec<u8>` can be used as a transformation, e.g. to normalize the keys to
+/// Unicode NFC with a normalization crate.
+pub trait KeyTransform: Send + Sync {
+    /// Transform the given key. The key should be borrowed if it is already in the transformed
+    /// form, to avoid copying it.
+    fn transform<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]>;
+}
+
+/// Compare the keys case-insensitively by converting the ASCII letters in the keys to lowercase.
+/// The other bytes are not modified.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AsciiCaseInsensitive;
+
+impl KeyTransform for AsciiCaseInsensitive {
+    fn transform<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        if key.iter().any(u8::is_ascii_uppercase) {
+            Cow::Owned(key.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+}
+
+impl<F> KeyTransform for F
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + Sync,
+{
+    fn transform<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        Cow::Owned(self(key))
+    }
+}
@@ -15,55 +15,266 @@
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::iter;
+use std::mem;
 use std::path::Path;
 use std::path::PathBuf;
-
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::archive::ArchiveReader;
+use crate::archive::ArchiveWriter;
+use crate::batch::BatchOp;
+use crate::batch::WriteBatch;
+use crate::bitvec::BitVec;
+use crate::bloom::BloomFilter;
+use crate::cancel::CancellationToken;
+use crate::expand::ExpandAtLoadFactor;
+use crate::expand::ExpandPolicy;
+use crate::expand::ExpandProgress;
+use crate::expand::NeverExpand;
+use crate::fs::memfd_file;
+use crate::fsck::Inconsistency;
+use crate::fsck::IndexFile;
+use crate::fsck::RepairReport;
+use crate::fsck::SlotPos;
+use crate::io::segmented::SegmentedFile;
+use crate::io::IOEndianness;
+use crate::io::MapOptions;
+use crate::key_transform::KeyTransform;
 use crate::level_io::LevelHashIO;
 use crate::level_io::ValEntryReadExt;
 use crate::level_io::ValuesEntry;
+use crate::log_macros::log_error;
+use crate::log_macros::log_span;
+use crate::log_macros::log_warn;
+use crate::meta::MetaIO;
+use crate::observer::LevelEvent;
+use crate::observer::LevelObserver;
+use crate::observer::MovementKind;
+use crate::partition::KeymapLayout;
+use crate::partition::PartitionCursor;
+use crate::paths::DefaultPaths;
+use crate::paths::IndexFiles;
+use crate::paths::Paths;
+use crate::reprs::Occupancy;
+use crate::result::CandidateBucket;
+use crate::result::InsertionDiagnostics;
 use crate::result::IntoLevelExpErr;
+use crate::result::IntoLevelIOErr;
 use crate::result::IntoLevelInsertionErr;
+use crate::result::IntoLevelUpdateErr;
+use crate::result::LevelArchiveError;
+use crate::result::LevelBatchError;
+use crate::result::LevelBatchResult;
 use crate::result::LevelClearResult;
+use crate::result::LevelCompactError;
+use crate::result::LevelCompactResult;
 use crate::result::LevelExpansionError;
 use crate::result::LevelExpansionResult;
+use crate::result::LevelImportResult;
 use crate::result::LevelInitError;
 use crate::result::LevelInitResult;
 use crate::result::LevelInsertionError;
 use crate::result::LevelInsertionResult;
+use crate::result::LevelMapError;
+use crate::result::LevelReadError;
+use crate::result::LevelReadResult;
+use crate::result::LevelRemapResult;
+use crate::result::LevelResult;
+use crate::result::LevelShrinkError;
+use crate::result::LevelShrinkResult;
+use crate::result::LevelSyncResult;
+use crate::result::StdIOError;
 use crate::result::LevelUpdateError;
 use crate::result::LevelUpdateResult;
+#[cfg(feature = "secondary-index")]
+use crate::secondary::SecondaryKeys;
+#[cfg(feature = "secondary-index")]
+use crate::secondary::ValueHash;
+use crate::sharded::ShardedLevelHash;
+use crate::sharded::SHARD_COUNT_MAX;
+use crate::siphash::siphash13;
+use crate::size::SIZE_U32;
+use crate::size::SIZE_U64;
+#[cfg(feature = "serde")]
+use crate::typed::TypedLevelHash;
+use crate::trace::TraceHeader;
+use crate::trace::TraceKey;
+use crate::trace::TraceOp;
+use crate::trace::TraceWriter;
 use crate::types::BucketSizeT;
+use crate::types::LevelCountT;
 use crate::types::LevelKeyT;
 use crate::types::LevelSizeT;
 use crate::types::LevelValueT;
+use crate::types::OffT;
 use crate::types::_BucketIdxT;
 use crate::types::_LevelIdxT;
 use crate::types::_SlotIdxT;
+use crate::util::align_8;
 use crate::util::generate_seeds;
+use crate::util::generate_seeds_deterministic;
 use crate::util::IsTrue;
+use crate::util::page_size;
+#[cfg(feature = "ttl")]
+use crate::util::unix_time_secs;
+use crate::xxhash::xxh64;
 use crate::Level::L0;
 use crate::Level::L1;
+use crate::Level::L2;
+use crate::Level::L3;
+
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use byteorder::WriteBytesExt;
+
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 pub const LEVEL_SIZE_DEFAULT: u8 = 8;
 pub const LEVEL_SIZE_MAX: u8 = 24;
 pub const BUCKET_SIZE_DEFAULT: u8 = 10;
 pub const BUCKET_SIZE_MAX: u8 = u8::MAX;
+pub const LEVEL_COUNT_DEFAULT: LevelCountT = 2;
+pub const LEVEL_COUNT_MAX: LevelCountT = 4;
+pub const MOVEMENT_DEPTH_DEFAULT: u8 = 2;
+pub const MOVEMENT_DEPTH_MAX: u8 = 8;
+pub const STASH_SLOTS_MAX: u32 = 64;
 pub const LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT: f32 = 0.9;
+pub const KEY_SIZE_MAX_DEFAULT: u64 = u32::MAX as u64;
+pub const VALUE_SIZE_MAX_DEFAULT: u64 = u32::MAX as u64;
+pub const VALUE_SPILL_THRESHOLD_DEFAULT: u64 = 1024 * 1024;
+pub const VALUE_SPILL_THRESHOLD_MAX: u64 = ValuesEntry::VALUE_INLINE_SIZE_MAX;
+const IN_MEMORY_INDEX_NAME_DEFAULT: &str = "level-hash";
+
+/// The number of buckets in the probe length histograms of [LevelStats].
+pub const PROBE_HISTOGRAM_SIZE: usize = 12;
+
+/// The fragmentation of the values file above which [LevelHash::health] recommends compaction.
+const HEALTH_COMPACT_FRAGMENTATION: f32 = 0.5;
+
+/// The load factor above which the load factor starts to decrease the health score.
+const HEALTH_LOAD_FACTOR_OK: f32 = 0.7;
+
+// the number of occupied slots in each level is stored in a u32
+const _: () = assert!((1u64 << LEVEL_SIZE_MAX) * BUCKET_SIZE_MAX as u64 <= u32::MAX as u64);
+
+/// The load factor for which [LevelHash::bulk_load] sizes the keymap, which leaves most of the
+/// entries a free slot in one of their buckets.
+const BULK_LOAD_FACTOR: f32 = 0.75;
 
-pub(crate) const LEVEL_COUNT: usize = 2;
-static LEVELS: [Level; LEVEL_COUNT] = [L0, L1];
-static LEVELS_REV: [Level; LEVEL_COUNT] = [L1, L0];
+/// The key which is hashed to compute the hash check stored in the meta file.
+const HASH_CHECK_KEY: &[u8; 32] = b"level-hash: hash functions check";
+
+/// The number of buckets of the bottom level which are rehashed between the checks of the
+/// cancellation token in [LevelHash::expand_cancellable] and the progress reports of
+/// [LevelHash::expand_with_progress].
+const EXPAND_CANCEL_CHECK_BUCKETS: u32 = 64;
+
+/// The value of `km_default_hash` in the meta file of the indexes which use [siphash13] if no hash
+/// functions are set. The indexes with `0` use [xxh64] instead.
+const DEFAULT_HASH_SIPHASH13: u8 = 1;
+
+static LEVELS: [Level; LEVEL_COUNT_MAX as usize] = [L0, L1, L2, L3];
 
 /// A function which accepts a seed and data and computes the 64-bit hash of the data.
 pub type HashFn = fn(u64, &[u8]) -> u64;
 
+/// The location of an entry : the address of its value entry, and the level, bucket and slot which
+/// point to it.
+type EntryLoc = (OffT, Level, _BucketIdxT, _SlotIdxT);
+
 #[repr(u8)]
 #[derive(Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
 pub enum Level {
     L0 = 0u8,
     L1 = 1u8,
+    L2 = 2u8,
+    L3 = 3u8,
+
+    /// The stash of the entries which did not fit in their buckets (see
+    /// [LevelHashOptions::stash_slots]). The slots of the stash are all in bucket `0`.
+    Stash = 4u8,
+}
+
+/// The policy which determines when the changes made to a [LevelHash] are synced to the disk.
+/// Regardless of the policy, the changes are always synced when the level hash is dropped (or
+/// closed with [LevelHash::close], which reports the errors of the sync).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync the changes after every write operation. This is the most durable, and the slowest
+    /// policy.
+    OnWrite,
+
+    /// Sync the changes after a write operation if at least the given duration has elapsed since
+    /// the last sync.
+    Interval(Duration),
+
+    /// Never sync the changes unless [LevelHash::flush] or [LevelHash::sync_all] is called
+    /// explicitly. A process kill may lose any of the changes made since the last sync.
+    NoSync,
+}
+
+/// The mode of the advisory lock which a [LevelHash] holds on its index files. Any number of
+/// processes can hold a shared lock on an index at the same time, while an exclusive lock can only
+/// be held by one process when no other process holds a lock on the index.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockMode {
+    /// The lock held by the read-only level hashes. The write operations fail with this lock.
+    Shared,
+
+    /// The lock held by the level hashes which can be modified.
+    Exclusive,
+}
+
+/// The process which holds (or held) the exclusive lock of an index, as written to the lock file of
+/// the index. The lock file is deleted when the index is closed, so the owner of a lock file which
+/// is not locked is a process which did not close the index, e.g. because it crashed. See
+/// [LevelHashOptions::lock_owner].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    /// The PID of the owner.
+    pub pid: u32,
+
+    /// The boot id of the kernel the owner was running on, if it is known.
+    pub boot_id: Option<String>,
 }
 
+/// The order in which the levels are probed to find the entry of a key. See
+/// [LevelHashOptions::probe_order].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProbeOrder {
+    /// Probe the levels with more occupied slots first, according to the number of occupied slots
+    /// in each level. All the slots of the buckets of the key are probed.
+    LevelLoad,
+
+    /// Probe the levels in which the buckets of the key have fewer occupied slots first, skipping
+    /// the free slots of the buckets. The occupancy of the buckets is read from the bitmap of the
+    /// occupied slots which is kept in memory (see [LevelHash::bucket_occupancy]).
+    BucketLoad,
+}
+
+/// A pin of an entry, returned by [LevelHash::pin]. The pinned entry is not moved by
+/// [LevelHash::compact], and if it is removed or updated while it is pinned, its region in the
+/// values file is not reused until the pin is removed with [LevelHash::unpin]. The value of the
+/// pinned entry can be read with [LevelHash::pinned_value] and [LevelHash::pinned_value_ref] until
+/// then. The pins which are not removed are removed when the level hash is dropped.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct EntryPin(u64);
+
 /// Level hash is a write-optimized and high-performance hashing index scheme with cost-efficient
 /// resizing and low-overhead consistency guarantee for persistent memory.
 ///
@@ -83,29 +294,379 @@ pub enum Level {
 /// as well. This results in decreased access performance and insertion failures.
 pub struct LevelHash {
     unique_keys: bool,
-    auto_expand: bool,
-    load_factor_threshold: f32,
+    expand_policy: Box<dyn ExpandPolicy>,
+    movement_depth: u8,
+    max_key_size: u64,
+    max_value_size: u64,
     seed_1: u64,
     seed_2: u64,
     hashfn_1: HashFn,
     hashfn_2: HashFn,
-    item_counts: [u32; 2],
+    key_transform: Option<Box<dyn KeyTransform>>,
+    observer: Option<Box<dyn LevelObserver>>,
+    // the sizes of the values and keymap files, to notify the observer when they are resized
+    file_sizes: (OffT, OffT),
     expand_count: u32,
+    insertion_failures: u64,
+    sync_policy: SyncPolicy,
+    last_sync: Instant,
+    trace: Option<TraceWriter>,
+    probe_stats: ProbeStats,
+    // the expiry timestamp of the entry which is being inserted
+    entry_expiry: Option<u64>,
+    // whether the operations of a write batch are being applied
+    in_batch: bool,
+    // the number of buckets rehashed by every write during an incremental expansion
+    rehash_step: Option<u32>,
+    // the garbage ratio of the values file above which the writes compact the values file
+    compact_threshold: Option<f32>,
+    // the number of times the meta file was flushed or synced since the level hash was opened
+    meta_flushes: u64,
+    // the bytes written after which the observer is notified that the write budget was exceeded
+    write_budget: Option<u64>,
+    next_budget_mark: u64,
+    probe_order: ProbeOrder,
     io: LevelHashIO,
 }
 
+/// Statistics about the keys which have multiple live entries in a [LevelHash]. Keys can have
+/// multiple entries only if the level hash was built with [LevelHashOptions::unique_keys] set
+/// to `false`.
+///
+/// # Properties
+///
+/// * `duplicate_keys`: The number of keys which have more than one live entry.
+/// * `redundant_entries`: The number of entries which would be removed by
+///   [LevelHash::dedupe_keep_latest].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DuplicateStats {
+    pub duplicate_keys: u64,
+    pub redundant_entries: u64,
+}
+
+/// The capacity of a [LevelHash] and the limits of its index files, returned by
+/// [LevelHash::capacity_info].
+///
+/// # Properties
+///
+/// * `entries`: The number of occupied slots in all the levels and the stash.
+/// * `slots`: The number of slots in all the levels.
+/// * `max_slots`: The number of slots in all the levels after the level hash is expanded to
+///   [LEVEL_SIZE_MAX]. The number of entries can exceed `u32::MAX`, but the number of entries in a
+///   single level cannot.
+/// * `values_size`: The size (bytes) of the used region of the values file.
+/// * `max_values_size`: The size (bytes) beyond which the values file cannot grow, as the
+///   addresses of the entries would not fit in the slots of the keymap. Inserting an entry beyond
+///   this size fails with [LevelInsertionError::CapacityExhausted].
+/// * `max_key_size`: The maximum size (bytes) of a key, see [LevelHashOptions::max_key_size].
+/// * `max_value_size`: The maximum size (bytes) of a value, see
+///   [LevelHashOptions::max_value_size].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityInfo {
+    pub entries: u64,
+    pub slots: u64,
+    pub max_slots: u64,
+    pub values_size: u64,
+    pub max_values_size: u64,
+    pub max_key_size: u64,
+    pub max_value_size: u64,
+}
+
+/// The maintenance action recommended by [LevelHash::health].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthAction {
+    /// The level hash is healthy and does not require any maintenance.
+    None,
+
+    /// A large part of the values file does not contain any live entries. Copying the live entries
+    /// to a new index reclaims the unused space.
+    Compact,
+
+    /// The level hash is (nearly) full and insertions are likely to fail. Expanding the level
+    /// hash with [LevelHash::expand] doubles its capacity.
+    Expand,
+
+    /// The level hash contains entries which cannot be found with their keys, or duplicate
+    /// entries for keys which must be unique, or cannot be expanded any further. The index must be
+    /// rebuilt from its source data.
+    Rebuild,
+}
+
+/// A report about the health of a [LevelHash], returned by [LevelHash::health].
+///
+/// # Properties
+///
+/// * `score`: The overall health of the level hash, from `0.0` (unusable) to `1.0` (perfectly
+///   healthy).
+/// * `action`: The recommended maintenance action.
+/// * `load_factor`: The ratio of occupied slots to the total slots in both the levels.
+/// * `fragmentation`: The fraction of the used region of the values file which does not contain
+///   live entries.
+/// * `probe_histogram`: The number of entries in each of the buckets that are probed when looking
+///   up a key, in the order : first bucket in the top level, second bucket in the top level, first
+///   bucket in the next level and so on. The entries for the levels beyond the level count are 0.
+/// * `misplaced_entries`: The number of entries which are not in any of the buckets for their key.
+///   Such entries cannot be looked up with their keys.
+/// * `duplicate_keys`: The number of keys which have more than one live entry.
+/// * `insertion_failures`: The number of insertions which failed because no slot could be found
+///   for the entry, since the level hash was opened, expanded or cleared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelHealth {
+    pub score: f32,
+    pub action: HealthAction,
+    pub load_factor: f32,
+    pub fragmentation: f32,
+    pub probe_histogram: [u64; 2 * LEVEL_COUNT_MAX as usize],
+    pub misplaced_entries: u64,
+    pub duplicate_keys: u64,
+    pub insertion_failures: u64,
+}
+
+/// Statistics about a [LevelHash], returned by [LevelHash::stats].
+///
+/// # Properties
+///
+/// * `load_factor`: The ratio of occupied slots to the total slots in all the levels.
+/// * `level_load_factors`: The ratio of occupied slots to the total slots in each level, starting
+///   from the top level. The entries for the levels beyond the level count are 0.
+/// * `occupancy_histogram`: The number of buckets (in all the levels) with `i` occupied slots, at
+///   index `i`.
+/// * `values_file_size`: The size (bytes) of the values file.
+/// * `dead_bytes`: An estimate of the bytes in the used region of the values file which do not
+///   contain live entries.
+/// * `free_bytes`: The bytes of the deleted entries which are reused for the new entries. These are
+///   included in `dead_bytes`.
+/// * `expand_count`: The number of times the level hash was expanded since it was opened.
+/// * `insertion_failures`: The number of insertions which failed because no slot could be found
+///   for the entry, since the level hash was opened, expanded or cleared.
+/// * `hit_probe_histogram`: The number of key lookups which found an entry after comparing `0`,
+///   `1`, `2..=3`, `4..=7` (and so on) slots, since the level hash was opened. The last entry
+///   counts all the longer lookups.
+/// * `miss_probe_histogram`: Same as `hit_probe_histogram`, for the lookups which did not find an
+///   entry.
+/// * `writes`: The writes made to the index files since the level hash was opened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelStats {
+    pub load_factor: f32,
+    pub level_load_factors: [f32; LEVEL_COUNT_MAX as usize],
+    pub occupancy_histogram: Vec<u64>,
+    pub values_file_size: u64,
+    pub dead_bytes: u64,
+    pub free_bytes: u64,
+    pub expand_count: u32,
+    pub insertion_failures: u64,
+    pub hit_probe_histogram: [u64; PROBE_HISTOGRAM_SIZE],
+    pub miss_probe_histogram: [u64; PROBE_HISTOGRAM_SIZE],
+    pub writes: WriteStats,
+}
+
+/// The writes made to the index files of a [LevelHash] since it was opened, reported in
+/// [LevelStats::writes]. Comparing the bytes written with the size of the stored entries gives the
+/// write amplification of the workload.
+///
+/// # Properties
+///
+/// * `values_bytes`: The bytes written to the values file, including the regions zeroed when the
+///   entries are removed.
+/// * `keymap_bytes`: The bytes written to the keymap file.
+/// * `meta_bytes`: The bytes of the meta file written to the disk. The meta file is written as a
+///   whole when the level hash is flushed or synced, so this is its size times the number of
+///   flushes and syncs.
+/// * `remaps`: The number of times the values and keymap files were mapped again, e.g. after they
+///   were resized.
+/// * `hole_punches`: The number of regions deallocated in the values and keymap files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStats {
+    pub values_bytes: u64,
+    pub keymap_bytes: u64,
+    pub meta_bytes: u64,
+    pub remaps: u64,
+    pub hole_punches: u64,
+}
+
+impl WriteStats {
+    /// Get the bytes written to the values, keymap and meta files.
+    pub fn total_bytes(&self) -> u64 {
+        self.values_bytes + self.keymap_bytes + self.meta_bytes
+    }
+}
+
+/// A handle to the slot of an entry in a [LevelHash], returned by [LevelHash::find_handle] and
+/// [LevelHash::get_with_handle]. The handle lets the callers which cache their hot entries read,
+/// update and remove an entry without looking up its key again.
+///
+/// A handle is only valid as long as the entry stays in its slot at the same address in the values
+/// file, and is validated before it is used : it becomes stale when the entry is removed, moved by
+/// a compaction or an expansion, or when the keymap is changed structurally (see
+/// [LevelHash::generation]). The stale handles are never resolved to another entry, the entry must
+/// then be looked up again with its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SlotHandle {
+    level: _LevelIdxT,
+    bucket: _BucketIdxT,
+    slot: _SlotIdxT,
+    // the address of the entry in the values file, which changes whenever it is written again
+    addr: OffT,
+    // the first hash of the key, to detect an entry of another key reusing the same address
+    key_hash: u64,
+    generation: u64,
+}
+
+/// A read-only view of an entry in the values file of a [LevelHash], passed to the function given
+/// to [LevelHash::for_each_slot].
+pub struct EntryRef<'a> {
+    io: &'a LevelHashIO,
+    entry: ValuesEntry<'a>,
+}
+
+impl EntryRef<'_> {
+    /// Get the (0-based) address of the entry in the values file.
+    pub fn addr(&self) -> u64 {
+        self.entry.addr
+    }
+
+    /// Get the size (bytes) of the entry in the values file, including its header, and its expiry
+    /// timestamp, chain link and checksum (if any).
+    pub fn size(&self) -> u64 {
+        self.entry.esize()
+    }
+
+    /// Get the key of the entry.
+    pub fn key(&self) -> Vec<u8> {
+        self.io.entry_key(&self.entry)
+    }
+
+    /// Get the value of the entry. The value is read from the overflow file if it has been
+    /// spilled, and decompressed if it has been compressed.
+    pub fn value(&self) -> Vec<u8> {
+        self.io.entry_value(&self.entry)
+    }
+
+    /// Whether the value of the entry has been spilled to the overflow file.
+    pub fn is_spilled(&self) -> bool {
+        self.entry.is_spilled()
+    }
+
+    /// Whether the key of the entry has been spilled to the overflow file.
+    pub fn is_key_spilled(&self) -> bool {
+        self.entry.is_key_spilled()
+    }
+
+    /// Whether the value of the entry has been compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.entry.is_compressed()
+    }
+
+    /// Get the expiry timestamp (seconds since the Unix epoch) of the entry, or `None` if the
+    /// entry does not expire.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.entry.expires_at(&self.io.values)
+    }
+
+    /// Whether the entry is linked to the older entries of its key, in a level hash which does
+    /// not have unique keys.
+    pub fn has_older_entries(&self) -> bool {
+        self.entry.chain_next(&self.io.values).is_some()
+    }
+
+    /// Get the version of the entry, or `None` if the level hash was not created with
+    /// [LevelHashOptions::entry_versions].
+    pub fn version(&self) -> Option<u64> {
+        self.entry.version(&self.io.values)
+    }
+}
+
+/// The probe length counters of the key lookups, which are updated by [LevelHash::find_slot].
+#[derive(Debug, Default)]
+struct ProbeStats {
+    hits: [Cell<u64>; PROBE_HISTOGRAM_SIZE],
+    misses: [Cell<u64>; PROBE_HISTOGRAM_SIZE],
+}
+
+impl ProbeStats {
+    /// Record a lookup which compared `probes` slots.
+    fn record(&self, found: bool, probes: u64) {
+        let idx = ((u64::BITS - probes.leading_zeros()) as usize).min(PROBE_HISTOGRAM_SIZE - 1);
+        let counters = if found { &self.hits } else { &self.misses };
+        counters[idx].set(counters[idx].get() + 1);
+    }
+
+    fn histogram(counters: &[Cell<u64>; PROBE_HISTOGRAM_SIZE]) -> [u64; PROBE_HISTOGRAM_SIZE] {
+        counters.each_ref().map(Cell::get)
+    }
+}
+
+/// A bucket visited while searching for a free slot in [LevelHash::try_movement].
+///
+/// # Properties
+///
+/// * `bucket`: The index of the bucket.
+/// * `depth`: The number of entries that must be displaced to reach this bucket.
+/// * `parent`: The index of the node from which this bucket was reached, and the slot in the
+///   bucket of that node whose entry can be moved to this bucket.
+struct MovementNode {
+    bucket: _BucketIdxT,
+    depth: u8,
+    parent: Option<(usize, _SlotIdxT)>,
+}
+
+impl MovementNode {
+    fn root(bucket: _BucketIdxT) -> Self {
+        Self {
+            bucket,
+            depth: 0,
+            parent: None,
+        }
+    }
+}
+
 /// Options for building a [LevelHash] instance.
 pub struct LevelHashOptions {
     level_size: LevelSizeT,
     bucket_size: BucketSizeT,
+    level_count: LevelCountT,
     unique_keys: bool,
     auto_expand: bool,
     load_factor_threshold: f32,
+    expand_policy: Option<Box<dyn ExpandPolicy>>,
+    incremental_expansion: Option<u32>,
+    auto_compact: Option<f32>,
+    write_budget: Option<u64>,
+    movement_depth: u8,
+    max_key_size: Option<u64>,
+    max_value_size: Option<u64>,
+    spill_threshold: u64,
+    compress_threshold: Option<u64>,
+    key_spill_threshold: Option<u64>,
+    checksums: bool,
+    entry_versions: bool,
+    aligned_buckets: bool,
+    stash_slots: u32,
+    probe_order: ProbeOrder,
+    values_growth: Option<u64>,
+    values_segment_size: Option<u64>,
+    bloom_filter: bool,
+    sync_policy: SyncPolicy,
     seeds: Option<(u64, u64)>,
+    seeds_from_entropy: bool,
     hashfn_1: Option<HashFn>,
     hashfn_2: Option<HashFn>,
+    key_transform: Option<Box<dyn KeyTransform>>,
+    observer: Option<Box<dyn LevelObserver>>,
+    paths: Box<dyn Paths>,
+    #[cfg(feature = "secondary-index")]
+    secondary_index: Option<Box<dyn SecondaryKeys>>,
+    #[cfg(feature = "testing")]
+    io_shim: Option<std::sync::Arc<dyn crate::testing::IOShim>>,
     index_dir: Option<PathBuf>,
     index_name: Option<String>,
+    in_memory: bool,
+    read_only: bool,
+    populate: bool,
+    huge_pages: bool,
+    lock_memory: bool,
+    trace_file: Option<PathBuf>,
+    recovery: bool,
 }
 
 impl LevelHashOptions {
@@ -120,11 +681,6 @@ impl LevelHashOptions {
     ///
     /// * size: The level size.
     pub fn level_size(&mut self, size: LevelSizeT) -> &mut Self {
-        assert!(
-            size <= LEVEL_SIZE_MAX,
-            "Level size must be <= {}",
-            LEVEL_SIZE_MAX
-        );
         self.level_size = size;
         self
     }
@@ -135,23 +691,35 @@ impl LevelHashOptions {
     ///
     /// * size: The bucket size.
     pub fn bucket_size(&mut self, size: BucketSizeT) -> &mut Self {
-        assert!(
-            size <= BUCKET_SIZE_MAX,
-            "Bucket size must be <= {}",
-            BUCKET_SIZE_MAX
-        );
         self.bucket_size = size;
         self
     }
 
-    /// Set whether the level hash must have unique keys.
+    /// Set the number of levels in the level hash. Each level has half the buckets of the level
+    /// above it. More levels use less memory for the same capacity of the top level, at the cost of
+    /// more buckets being probed for each key. The level count of an existing index cannot be
+    /// changed.
+    ///
+    /// ## Parameters
+    ///
+    /// * count: The level count, between 2 and [LEVEL_COUNT_MAX].
+    pub fn level_count(&mut self, count: LevelCountT) -> &mut Self {
+        self.level_count = count;
+        self
+    }
+
+    /// Set whether the level hash must have unique keys. If the keys are not unique, every insert
+    /// adds a new entry for the key, which is chained to the previous entries of the key in the
+    /// values file. The lookups return the latest entry, while all the entries can be read with
+    /// [LevelHash::get_all] and removed with [LevelHash::remove_all].
     pub fn unique_keys(&mut self, unique_keys: bool) -> &mut Self {
         self.unique_keys = unique_keys;
         self
     }
 
     /// Set whether the level hash should expand automatically when [Self::load_factor_threshold]
-    /// is reached.
+    /// is reached. This is same as setting [ExpandAtLoadFactor] or [NeverExpand] with
+    /// [Self::expand_policy], and is ignored if an expand policy is set.
     pub fn auto_expand(&mut self, auto_expand: bool) -> &mut Self {
         self.auto_expand = auto_expand;
         self
@@ -159,1064 +727,10556 @@ impl LevelHashOptions {
 
     /// Set the load factor threshold for automatically expanding the level hash.
     pub fn load_factor_threshold(&mut self, threshold: f32) -> &mut Self {
-        assert!(
-            threshold >= 0.5 && threshold <= 1.0,
-            "threshold value must be between 0.5 and 1.0"
-        );
         self.load_factor_threshold = threshold;
         self
     }
 
-    /// Set the path of the directory where the index files will be stored. The directory,
-    /// including the parent directories will be created if they do not exist.
-    pub fn index_dir(&mut self, index_dir: &Path) -> &mut Self {
-        self.index_dir = Some(index_dir.to_path_buf());
+    /// Set the [ExpandPolicy] which determines when the level hash expands automatically. This
+    /// overrides [Self::auto_expand] and [Self::load_factor_threshold].
+    pub fn expand_policy(&mut self, policy: impl ExpandPolicy + 'static) -> &mut Self {
+        self.expand_policy = Some(Box::new(policy));
         self
     }
 
-    /// Set the name of the index.
-    pub fn index_name(&mut self, index_name: &str) -> &mut Self {
-        self.index_name = Some(index_name.to_string());
+    /// Expand the level hash incrementally when the [ExpandPolicy] asks for an expansion before
+    /// an insertion. Instead of rehashing the whole bottom level at once, the new top level is
+    /// prepared and every subsequent write operation rehashes the given number of buckets of the
+    /// bottom level, until the expansion is complete. This bounds the latency of the writes which
+    /// trigger an expansion. The expansion is completed at once if an insertion fails or
+    /// [LevelHash::expand] is called while it is in progress.
+    ///
+    /// The progress of the expansion is recorded in the meta file. An expansion which was not
+    /// completed when the level hash was closed is discarded when the index is opened again.
+    pub fn incremental_expansion(&mut self, buckets_per_write: u32) -> &mut Self {
+        self.incremental_expansion = Some(buckets_per_write);
         self
     }
 
-    /// Set the two random seeds that will be used to calculate the slot positions in
-    /// the level hash. While loading an existing level hash from the disk, the same
-    /// seeds that were used to create the level hash must be used or the slot positions
-    /// may not be calculated properly.
+    /// Compact the values file (see [LevelHash::compact]) after a write operation when its
+    /// garbage ratio (see [LevelHash::garbage_ratio]) exceeds the given threshold. The values file
+    /// is only compacted once the garbage is at least as large as the growth size of the values
+    /// file (see [Self::values_growth]), so that the small indexes are not compacted after every
+    /// removal. The values file is never compacted automatically by default.
     ///
-    /// The default value for these seeds is calculate using the
-    /// [crate::util::generate_seeds] function.
-    pub fn seeds(&mut self, seed_1: u64, seed_2: u64) -> &mut Self {
-        assert!(seed_1 > 0, "Seed 1 must be greater than 0");
-        assert!(seed_2 > 0, "Seed 2 must be greater than 0");
-        self.seeds = Some((seed_1, seed_2));
+    /// ## Parameters
+    ///
+    /// * threshold: The garbage ratio, between `0.0` and `1.0` (exclusive).
+    pub fn auto_compact(&mut self, threshold: f32) -> &mut Self {
+        self.auto_compact = Some(threshold);
         self
     }
 
-    /// Set the two hash functions which will be used to compute the slot position for keys.
+    /// Set a soft budget for the bytes written to the index files (see [WriteStats]). Every time
+    /// another `bytes` bytes have been written since the level hash was opened, the [LevelObserver]
+    /// is notified with [LevelEvent::WriteBudgetExceeded] after the write operation, suggesting
+    /// that the application flushes or compacts the level hash in the background. The writes are
+    /// never blocked by the budget. There is no budget by default.
     ///
     /// ## Parameters
     ///
-    /// * - `fn1` - The first hash function.
-    /// * - `fn2` - The second hash function.
-    pub fn hash_fns(&mut self, fn1: HashFn, fn2: HashFn) -> &mut Self {
-        self.hashfn_1 = Some(fn1);
-        self.hashfn_2 = Some(fn2);
+    /// * bytes: The number of bytes, which must not be `0`.
+    pub fn write_budget(&mut self, bytes: u64) -> &mut Self {
+        self.write_budget = Some(bytes);
         self
     }
 
-    /// Build the level hash instance
-    pub fn build(&mut self) -> LevelInitResult {
-        let index_dir = self.index_dir.take().ok_or_else(|| {
-            LevelInitError::InvalidArg("Index directory must be specified".to_string())
-        })?;
-        let index_name = self.index_name.take().ok_or_else(|| {
-            LevelInitError::InvalidArg("Index name must be specified".to_string())
-        })?;
-
-        let seeds = self.seeds.take().unwrap_or_else(|| generate_seeds());
-        let fn1 = self.hashfn_1.take().expect("HashFn 1 is not set");
-        let fn2 = self.hashfn_2.take().expect("HashFn 2 is not set");
-
-        LevelHash::new(
-            &index_dir,
-            &index_name,
-            self.level_size,
-            self.bucket_size,
-            self.unique_keys,
-            self.auto_expand,
-            self.load_factor_threshold,
-            seeds.0,
-            seeds.1,
-            fn1,
-            fn2,
-        )
+    /// Set the maximum number of entries that may be displaced to make room for a new entry when
+    /// both of its buckets are full. A larger depth raises the load factor that can be reached
+    /// before the insertions start to fail, at the cost of slower insertions near that load factor.
+    /// `0` disables the displacement.
+    ///
+    /// ## Parameters
+    ///
+    /// * depth: The movement depth, at most [MOVEMENT_DEPTH_MAX].
+    pub fn movement_depth(&mut self, depth: u8) -> &mut Self {
+        self.movement_depth = depth;
+        self
     }
-}
 
-impl Default for LevelHashOptions {
-    fn default() -> Self {
-        Self {
-            level_size: LEVEL_SIZE_DEFAULT,
-            bucket_size: BUCKET_SIZE_DEFAULT,
-            unique_keys: true,
-            auto_expand: true,
-            load_factor_threshold: LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT,
-            seeds: Some(generate_seeds()),
-            hashfn_1: None,
-            hashfn_2: None,
-            index_dir: None,
-            index_name: None,
-        }
+    /// Set the maximum size (bytes) of the keys that can be inserted in the level hash. Inserting
+    /// a key larger than this size (after the [KeyTransform], if any) fails with
+    /// [LevelInsertionError::KeyTooLarge]. The limit is stored in the index, and is used when the
+    /// index is opened without this option. Defaults to [KEY_SIZE_MAX_DEFAULT].
+    pub fn max_key_size(&mut self, size: u64) -> &mut Self {
+        self.max_key_size = Some(size);
+        self
     }
-}
 
-impl LevelHash {
-    /// Create new [LevelHashOptions] used to build a [LevelHash].
-    pub fn options() -> LevelHashOptions {
-        LevelHashOptions::new()
+    /// Set the maximum size (bytes) of the values that can be inserted in the level hash. Inserting
+    /// a value larger than this size fails with [LevelInsertionError::ValueTooLarge]. The limit is
+    /// stored in the index, and is used when the index is opened without this option. Defaults to
+    /// [VALUE_SIZE_MAX_DEFAULT].
+    pub fn max_value_size(&mut self, size: u64) -> &mut Self {
+        self.max_value_size = Some(size);
+        self
     }
 
-    fn new(
-        index_dir: &Path,
-        index_name: &str,
-        level_size: LevelSizeT,
-        bucket_size: BucketSizeT,
-        unique_keys: bool,
-        auto_expand: bool,
-        load_factor_threshold: f32,
-        seed_1: u64,
-        seed_2: u64,
-        hashfn_1: HashFn,
-        hashfn_2: HashFn,
-    ) -> LevelInitResult {
-        let io = LevelHashIO::new(index_dir, index_name, level_size, bucket_size)?;
-        Ok(Self {
-            unique_keys,
-            auto_expand,
-            load_factor_threshold,
-            seed_1,
-            seed_2,
-            hashfn_1,
-            hashfn_2,
-            item_counts: [0u32, 0],
-            expand_count: 0,
-            io,
-        })
+    /// Set the size (bytes) above which the values are stored in a separate overflow file instead
+    /// of the values file. This keeps the values file dense when the level hash contains a few
+    /// very large values.
+    pub fn spill_threshold(&mut self, threshold: u64) -> &mut Self {
+        self.spill_threshold = threshold;
+        self
     }
 
-    /// Get the number of buckets in the top level.
-    #[inline]
-    pub fn top_level_bucket_count(&self) -> u32 {
-        1 << self.io.meta.read().km_level_size
+    /// Set the size (bytes) above which the keys are stored in the overflow file instead of the
+    /// values file. The entries of such keys only store a reference to the key and its hash, which
+    /// keeps the entries compact when the level hash contains long keys (e.g. fully qualified
+    /// names). The hash is compared before a spilled key is read, so the probes only read the
+    /// spilled keys which are likely to match. The keys are not spilled by default.
+    pub fn key_spill_threshold(&mut self, threshold: u64) -> &mut Self {
+        self.key_spill_threshold = Some(threshold);
+        self
     }
 
-    /// Get the total number of buckets in the level hash.
-    pub fn total_bucket_count(&self) -> u32 {
-        let top_level = self.top_level_bucket_count();
-        return top_level + (top_level >> 1);
+    /// Set the size (bytes) above which the values are compressed with LZ4 before they are
+    /// written. The values are decompressed when they are read. A value is stored as is if it does
+    /// not get smaller when compressed. The values are not compressed by default.
+    ///
+    /// Once a compressed value has been written, the index can only be opened with the
+    /// `compression` feature.
+    #[cfg(feature = "compression")]
+    pub fn compress_threshold(&mut self, threshold: u64) -> &mut Self {
+        self.compress_threshold = Some(threshold);
+        self
     }
 
-    /// Get the total number of slots in the level hash.
-    #[inline]
-    pub fn total_slots(&self) -> u64 {
-        let meta = self.io.meta.read();
-        return (1u64 << meta.km_level_size) * meta.km_bucket_size as u64;
+    /// Set whether a checksum is stored with each entry in the values file. The checksum is verified
+    /// when the value of an entry is read, which detects the entries that have been silently
+    /// corrupted on the storage. Whether the entries are checksummed cannot be changed for an
+    /// existing index.
+    pub fn checksums(&mut self, checksums: bool) -> &mut Self {
+        self.checksums = checksums;
+        self
     }
 
-    /// Get the load factor of the level hash.
-    pub fn load_factor(&self) -> f32 {
-        let sum = self.item_counts[0] as u64 + self.item_counts[1] as u64;
-        return (sum / self.total_slots()) as f32;
+    /// Set whether a version is stored with each entry in the values file. The version of an entry
+    /// is the value of a write counter of the level hash when the entry was written (or its value
+    /// was last updated in place), so it changes whenever the value of a key changes and can be
+    /// used to invalidate the values cached by the callers, see [LevelHash::entry_version]. The
+    /// versioned entries are also checksummed. Whether the entries are versioned cannot be changed
+    /// for an existing index, and the versioned indexes cannot be opened by the older versions of
+    /// level hash.
+    pub fn entry_versions(&mut self, entry_versions: bool) -> &mut Self {
+        self.entry_versions = entry_versions;
+        self
     }
-}
 
-impl LevelHash {
-    #[inline]
-    fn fhash(&self, key: &LevelKeyT) -> u64 {
-        return (self.hashfn_1)(self.seed_1, key);
+    /// Set whether each bucket in the keymap is padded to the size of a cache line (64 bytes) and
+    /// starts at a cache line boundary, so that a bucket lookup never touches more than the cache
+    /// lines of the bucket. This uses more space in the keymap when the size of a bucket is not a
+    /// multiple of 64 bytes. Like the bucket size, this is only used when the index is created.
+    pub fn aligned_buckets(&mut self, aligned_buckets: bool) -> &mut Self {
+        self.aligned_buckets = aligned_buckets;
+        self
     }
 
-    #[inline]
-    fn shash(&self, key: &LevelKeyT) -> u64 {
-        return (self.hashfn_2)(self.seed_2, key);
+    /// Set the number of slots in the stash, a small area at the start of the keymap which holds
+    /// the entries that do not fit in either of their buckets in any level. An entry is placed in
+    /// the stash before the entries in its buckets are moved, and the stash is emptied into the
+    /// levels whenever the level hash is expanded. The lookups which do not find the key in its
+    /// buckets also scan the stash, so the stash is kept small.
+    ///
+    /// Defaults to `0` (no stash). Like the bucket size, this is only used when the index is
+    /// created, and the indexes with a stash cannot be opened by the older versions of level hash.
+    ///
+    /// ## Parameters
+    ///
+    /// * slots: The number of slots in the stash, at most [STASH_SLOTS_MAX].
+    pub fn stash_slots(&mut self, slots: u32) -> &mut Self {
+        self.stash_slots = slots;
+        self
     }
 
-    fn buck_idx_lvl(&self, key_hash: u64, level: Level) -> u32 {
-        let mut capacity = self.top_level_bucket_count() as u64;
-        if level == L1 {
-            capacity = capacity >> 1;
-        }
-
-        return Self::buck_idx_cap(key_hash, capacity);
+    /// Set the [ProbeOrder] in which the levels are probed to find the entry of a key. With
+    /// [ProbeOrder::BucketLoad], the lookups probe fewer slots when the levels are unevenly
+    /// loaded across the buckets (e.g. with a skewed key distribution). Defaults to
+    /// [ProbeOrder::LevelLoad].
+    pub fn probe_order(&mut self, order: ProbeOrder) -> &mut Self {
+        self.probe_order = order;
+        self
     }
 
-    fn buck_idx_cap(key_hash: u64, capacity: u64) -> u32 {
-        // since capacity is a power of two and key hash is unsigned
-        // keyHash % capacity can be simplified with simple bitwise operation
-        return (key_hash & (capacity - 1)) as u32;
+    /// Set the size (bytes) by which the values file grows when it runs out of space. This must be a
+    /// multiple of the page size. The size is stored in the meta file and is used when the index is
+    /// opened again, unless it is set again. Defaults to 512 KiB for
+    /// new indexes.
+    ///
+    /// ## Parameters
+    ///
+    /// * block_size: The growth size of the values file.
+    pub fn values_growth(&mut self, block_size: u64) -> &mut Self {
+        self.values_growth = Some(block_size);
+        self
     }
 
-    fn cmp_key_and_get_entry(
-        &self,
-        level: Level,
-        bucket: _BucketIdxT,
-        slot: _SlotIdxT,
-        key: &LevelKeyT,
-    ) -> Option<ValuesEntry> {
-        return self
-            .io
-            .val_entry_for_slot(level as _LevelIdxT, bucket, slot)
-            .take_if(|e| {
-                (!e.is_empty())
-                    .then(|| e.keyeq(&self.io.values, key))
-                    .is_true()
-            });
+    /// Split the values file of a new index into segment files which store `segment_size` bytes
+    /// each. The first segment is the values file itself and the other segments are stored next to
+    /// it, with the index of the segment appended to its name (`name.index.1`, `name.index.2`,
+    /// ...). Each segment is mapped separately and only the last segment is remapped when the
+    /// values file grows, which keeps the mappings small and lets the segments which are no longer
+    /// used be deleted when the values file shrinks. The entries are not stored across the end of
+    /// a segment, the entries which would not fit in a segment are stored in the overflow file.
+    ///
+    /// The segment size must be a multiple of the page size, and at least 64 KiB. It is stored in
+    /// the meta file and cannot be changed for an existing index. This is ignored for in-memory
+    /// level hashes.
+    ///
+    /// ## Parameters
+    ///
+    /// * segment_size: The size (bytes) of the segments of the values file.
+    pub fn values_segment_size(&mut self, segment_size: u64) -> &mut Self {
+        self.values_segment_size = Some(segment_size);
+        self
     }
 
-    fn find_slot(
-        &self,
-        key: &LevelKeyT,
-    ) -> Option<(ValuesEntry, _LevelIdxT, _BucketIdxT, _SlotIdxT)> {
-        let fhash = self.fhash(key);
-        let shash = self.shash(key);
-
-        let levels = if self.item_counts[0] < self.item_counts[1] {
-            // if there are more occupied slots in the bottom level
-            // than in the top level, then scan the bottom level first
-            LEVELS_REV
-        } else {
-            LEVELS
-        };
+    /// Set whether a bloom filter of the keys is maintained in a sidecar file. The lookups of the
+    /// keys which are definitely not present in the level hash return without probing any slots.
+    /// The filter is built from the values file if it does not exist, and is deleted if an index is
+    /// opened without this option. Defaults to `false`.
+    pub fn bloom_filter(&mut self, enabled: bool) -> &mut Self {
+        self.bloom_filter = enabled;
+        self
+    }
 
-        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+    /// Set the [SyncPolicy] which determines when the changes made to the level hash are synced
+    /// to the disk. Defaults to [SyncPolicy::NoSync].
+    pub fn sync_policy(&mut self, policy: SyncPolicy) -> &mut Self {
+        self.sync_policy = policy;
+        self
+    }
 
-        for level in levels {
-            let fidx = self.buck_idx_lvl(fhash, level);
-            let sidx = self.buck_idx_lvl(shash, level);
+    /// Sync the changes to the disk after every write operation. This is same as setting
+    /// [SyncPolicy::OnWrite] with [Self::sync_policy].
+    pub fn sync_on_write(&mut self) -> &mut Self {
+        self.sync_policy(SyncPolicy::OnWrite)
+    }
 
-            for j in 0..bucket_size {
-                if let Some((e, buck)) = self
-                    .cmp_key_and_get_entry(level, fidx, j, key)
-                    .map(|e| (e, fidx))
-                    .or_else(|| {
-                        self.cmp_key_and_get_entry(level, sidx, j, key)
-                            .map(|e| (e, sidx))
-                    })
-                {
-                    return Some((e, level as _LevelIdxT, buck, j));
-                }
-            }
-        }
+    /// Sync the changes to the disk after a write operation if at least `interval` has elapsed
+    /// since the last sync. This is same as setting [SyncPolicy::Interval] with
+    /// [Self::sync_policy].
+    pub fn sync_interval(&mut self, interval: Duration) -> &mut Self {
+        self.sync_policy(SyncPolicy::Interval(interval))
+    }
 
-        None
+    /// Do not sync the changes to the disk unless requested explicitly. This is same as setting
+    /// [SyncPolicy::NoSync] with [Self::sync_policy].
+    pub fn no_sync(&mut self) -> &mut Self {
+        self.sync_policy(SyncPolicy::NoSync)
     }
 
-    fn insert_entry_at_slot(
-        &mut self,
-        level: _LevelIdxT,
-        bucket: _BucketIdxT,
-        slot: _SlotIdxT,
-        key: &LevelKeyT,
-        value: &LevelValueT,
-        fail_on_dup: bool,
-    ) -> LevelInsertionResult {
-        let (slot_addr, val_addr) = self.io.slot_and_val_addr_at(level, bucket, slot);
-        if val_addr.is_none() {
-            // slot is empty
-            // append the value entry and return
-            return self.io.append_entry_at_slot(slot_addr, key, value);
-        }
-
-        let val_addr = val_addr.unwrap();
-        let entry = ValuesEntry::at(val_addr - 1, &self.io.values);
-
-        if entry.is_empty() {
-            // slot is occupied, but the entry is empty
-            return self.io.append_entry_at_slot(slot_addr, key, value);
-        }
-
-        // check for duplicate key
-        if fail_on_dup && entry.keyeq(&self.io.values, key) {
-            return Err(LevelInsertionError::DuplicateKey);
-        }
-
-        return Err(LevelInsertionError::InsertionFailure);
+    /// Set the path of the directory where the index files will be stored. The directory,
+    /// including the parent directories will be created if they do not exist.
+    pub fn index_dir(&mut self, index_dir: &Path) -> &mut Self {
+        self.index_dir = Some(index_dir.to_path_buf());
+        self
     }
 
-    fn try_movement(
-        &mut self,
-        level: Level,
-        bucket: _BucketIdxT,
-        bucket_size: _SlotIdxT,
-        key: &LevelKeyT,
-        value: &LevelValueT,
-    ) -> LevelInsertionResult {
-        for i in 0..bucket_size {
-            let (this_key, this_value) = {
-                let this_entry = self
-                    .io
-                    .val_entry_for_slot(level as _LevelIdxT, bucket, i)
-                    .unwrap();
-                (
-                    this_entry.key(&self.io.values),
-                    this_entry.value(&self.io.values),
-                )
-            };
+    /// Set the name of the index.
+    pub fn index_name(&mut self, index_name: &str) -> &mut Self {
+        self.index_name = Some(index_name.to_string());
+        self
+    }
 
-            let fhash = self.fhash(&this_key);
-            let shash = self.shash(&this_key);
+    /// Set the [Paths] which names the index files, to place them elsewhere than in the index
+    /// directory or to name them differently. The files are named with [DefaultPaths] by default.
+    /// An index must always be opened with the same paths.
+    pub fn paths(&mut self, paths: impl Paths + 'static) -> &mut Self {
+        self.paths = Box::new(paths);
+        self
+    }
 
-            let fidx = self.buck_idx_lvl(fhash, level);
-            let sidx = self.buck_idx_lvl(shash, level);
-            let jidx = if fidx == bucket { sidx } else { fidx };
+    /// Record the operations performed on the level hash to the trace file at the given path. The
+    /// file is truncated if it exists. The keys and values are not written to the trace, only their
+    /// sizes and the hashes of the keys are recorded. See [crate::trace] for more details.
+    ///
+    /// The recorded operations can be replayed against a fresh index with [crate::trace::replay].
+    /// Hence, the trace should be recorded for a level hash which is initially empty.
+    pub fn trace_file(&mut self, path: &Path) -> &mut Self {
+        self.trace_file = Some(path.to_path_buf());
+        self
+    }
 
-            for j in 0..bucket_size {
-                if self
-                    .insert_entry_at_slot(
-                        level as _LevelIdxT,
-                        jidx,
-                        j,
-                        &this_key,
-                        &this_value,
-                        false,
-                    )
-                    .is_ok()
-                {
-                    self.io
-                        .create_or_update_entry(level as _LevelIdxT, bucket, i, key, value)?;
-                    self.item_counts[level as usize] += 1;
-                    return Ok(());
-                }
-            }
-        }
+    /// Set whether the level hash should be backed by anonymous memory instead of the files in
+    /// the index directory. An in-memory level hash does not require an index directory, and its
+    /// contents are lost when it is dropped.
+    pub fn in_memory(&mut self, in_memory: bool) -> &mut Self {
+        self.in_memory = in_memory;
+        self
+    }
 
-        return Err(LevelInsertionError::MovementFailure);
+    /// Set whether the level hash should be opened read-only. A read-only level hash holds a
+    /// [LockMode::Shared] lock on the index, so that the index can be read by multiple processes at
+    /// the same time. The write operations fail until the lock is upgraded with
+    /// [LevelHash::upgrade_lock]. The index must exist. Defaults to `false`.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
     }
 
-    fn b2t_movement(&mut self, bucket: _BucketIdxT, bucket_size: _SlotIdxT) -> Option<_SlotIdxT> {
-        for i in 0..bucket_size {
-            let (bottom_entry_key, bottom_entry_value) = {
-                let bottom_entry = self
-                    .io
-                    .val_entry_for_slot(L1 as _LevelIdxT, bucket, i)
-                    .unwrap();
+    /// Set whether the pages of the keymap are prefaulted (`MAP_POPULATE`) when it is mapped. This
+    /// avoids the page faults, and the latency spikes caused by them, during the first lookups
+    /// after the index is opened, at the cost of a slower open. Defaults to `false`.
+    pub fn populate(&mut self, populate: bool) -> &mut Self {
+        self.populate = populate;
+        self
+    }
 
-                let key = bottom_entry.key(&self.io.values);
-                let value = bottom_entry.value(&self.io.values);
-                (key, value)
-            };
+    /// Set whether the kernel is advised to back the keymap with transparent huge pages
+    /// (`MADV_HUGEPAGE`), which reduces the TLB misses for large level sizes. This is only a hint,
+    /// and is ignored if the kernel does not support huge pages for the index files. Defaults to
+    /// `false`.
+    pub fn huge_pages(&mut self, huge_pages: bool) -> &mut Self {
+        self.huge_pages = huge_pages;
+        self
+    }
 
-            let fhash = self.fhash(&bottom_entry_key);
-            let shash = self.shash(&bottom_entry_key);
-            let fidx = self.buck_idx_lvl(fhash, L0);
-            let sidx = self.buck_idx_lvl(shash, L0);
+    /// Set whether the pages of the keymap are locked in memory (`mlock`), so that the lookups do
+    /// not wait for the pages to be read again after they were paged out under memory pressure.
+    /// The locked pages count against `RLIMIT_MEMLOCK`, which is low for unprivileged processes. If
+    /// the keymap cannot be locked, a warning is logged and the keymap is used without being
+    /// locked, see [LevelHash::is_memory_locked]. The values file is never locked. Defaults to
+    /// `false`.
+    pub fn lock_memory(&mut self, lock_memory: bool) -> &mut Self {
+        self.lock_memory = lock_memory;
+        self
+    }
 
-            for j in 0..bucket_size {
-                if self
-                    .insert_entry_at_slot(
-                        L0 as _LevelIdxT,
-                        fidx,
-                        j,
-                        &bottom_entry_key,
-                        &bottom_entry_value,
-                        false,
-                    )
-                    .is_ok()
-                    || self
-                        .insert_entry_at_slot(
-                            L0 as _LevelIdxT,
-                            sidx,
-                            j,
-                            &bottom_entry_key,
-                            &bottom_entry_value,
-                            false,
-                        )
-                        .is_ok()
-                {
-                    // we could delete the bottom_entry here, but we don't,
-                    // this is because we can provide the bottom entry to the caller
-                    // and let it decide where the bottom_entry can be reused
-                    self.item_counts[L0 as usize] += 1;
-                    self.item_counts[L1 as usize] -= 1;
-                    return Some(i);
-                }
-            }
-        }
+    /// Set the two random seeds that will be used to calculate the slot positions in
+    /// the level hash. The seeds of a new index are stored in its meta file, and are used when the
+    /// index is opened again without setting the seeds. Opening an existing index with other seeds
+    /// fails with [LevelInitError::SeedMismatch]. Both the seeds must be greater than 0.
+    ///
+    /// The default seeds of a new index are generated from the entropy of the operating system
+    /// with [crate::util::generate_seeds], see [Self::seeds_from_entropy].
+    pub fn seeds(&mut self, seed_1: u64, seed_2: u64) -> &mut Self {
+        self.seeds = Some((seed_1, seed_2));
+        self
+    }
 
-        return None;
+    /// Set whether the default seeds of a new index are generated from the entropy of the
+    /// operating system. If `false`, the fixed seeds of
+    /// [crate::util::generate_seeds_deterministic] are used instead, which makes the slot positions
+    /// of the keys predictable (e.g. for reproducible tests), but also lets an attacker choose keys
+    /// which collide in the same buckets. This has no effect if the seeds are set with
+    /// [Self::seeds], or for existing indexes. Defaults to `true`.
+    pub fn seeds_from_entropy(&mut self, from_entropy: bool) -> &mut Self {
+        self.seeds_from_entropy = from_entropy;
+        self
     }
-}
 
-impl LevelHash {
-    /// Get the value associated with the given key.
+    /// Set the two hash functions which will be used to compute the slot position for keys. If not
+    /// set, the keyed [siphash13] is used for both the hash functions, or [xxh64] for the indexes
+    /// created before SipHash-1-3 was the default. The hash functions are not stored in the index,
+    /// so the same hash functions must be set every time the index is opened. A fixed 32-byte
+    /// key is hashed when the index is opened to detect other hash functions, in which case the
+    /// index fails to open with [LevelInitError::HashFnMismatch].
     ///
     /// ## Parameters
     ///
-    /// * `key` - The key to get the value for.
-    ///
-    /// ## Returns
-    ///
-    /// The raw bytes of the value if an entry is found, an empty [Vec] otherwise.
-    pub fn get_value(&self, key: &LevelKeyT) -> Vec<u8> {
-        return self
-            .find_slot(key)
-            .map(|e| e.0.value(&self.io.values))
-            .unwrap_or(vec![]);
+    /// * - `fn1` - The first hash function.
+    /// * - `fn2` - The second hash function.
+    pub fn hash_fns(&mut self, fn1: HashFn, fn2: HashFn) -> &mut Self {
+        self.hashfn_1 = Some(fn1);
+        self.hashfn_2 = Some(fn2);
+        self
     }
 
-    /// Get the value at the given slot position.
-    ///
-    /// ## Parameters
-    ///
-    /// * `level` - The level index of the slot.
-    /// * `bucket` The bucket index of the slot.
-    /// * `slot` - The slot index of the slot.
-    ///
-    /// # Returns
-    ///
-    /// The raw bytes of the value if an entry is found and is occupied, an empty [Vec] otherwise.
-    pub fn get_value_at(&mut self, level: Level, bucket: _BucketIdxT, slot: _SlotIdxT) -> Vec<u8> {
-        return self.io.value(level as _LevelIdxT, bucket, slot);
+    /// Set the [KeyTransform] which is applied to the keys before they are hashed and compared,
+    /// e.g. [AsciiCaseInsensitive](crate::key_transform::AsciiCaseInsensitive) to look up the
+    /// entries case-insensitively. Like the seeds and the hash functions, the same transformation
+    /// must be set every time the index is opened.
+    pub fn key_transform(&mut self, transform: impl KeyTransform + 'static) -> &mut Self {
+        self.key_transform = Some(Box::new(transform));
+        self
     }
 
-    /// Insert the given key-value pair in the level hash.
-    ///
-    /// ## Parameters
-    ///
-    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
-    /// * `value` - The value for the entry. This may be an empty slice in which case, [Self::get_value]
-    ///     for the given key will return `None`.
-    ///
-    /// ## Returns
-    ///
-    /// `true` if the value was inserted successfully, `false` otherwise.
-    pub fn insert(&mut self, key: &LevelKeyT, value: &LevelValueT) -> LevelInsertionResult {
-        if self.load_factor() >= self.load_factor_threshold && self.auto_expand {
-            self.expand().into_lvl_ins_err()?;
-        }
+    /// Set the [LevelObserver] which is notified of the internal events of the level hash, e.g. the
+    /// movements of the entries, the expansions and the insertion failures.
+    pub fn observer(&mut self, observer: impl LevelObserver + 'static) -> &mut Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
 
-        if self.load_factor() >= 1f32 {
-            return Err(LevelInsertionError::LevelOverflow);
-        }
+    /// Maintain a secondary index of the entries, which maps the secondary keys extracted from the
+    /// entries by the given [SecondaryKeys] to the keys of the entries, see
+    /// [LevelHash::find_keys_by_secondary]. Use [ValueHash] to find the keys of a value with
+    /// [LevelHash::find_keys_by_value_hash]. The secondary index is kept in memory and is built
+    /// from the entries in the values file when the index is opened, so the extractor does not need
+    /// to be the same every time the index is opened. Only available with the `secondary-index`
+    /// feature.
+    #[cfg(feature = "secondary-index")]
+    pub fn secondary_index(&mut self, extractor: impl SecondaryKeys + 'static) -> &mut Self {
+        self.secondary_index = Some(Box::new(extractor));
+        self
+    }
 
-        let fhash = self.fhash(key);
-        let shash = self.shash(key);
-        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+    /// Set the [IOShim](crate::testing::IOShim) which is consulted before the operations on the
+    /// keymap and the values file, to inject failures in the tests. The shim is set once the index
+    /// has been opened. Only available with the `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn io_shim(&mut self, shim: std::sync::Arc<dyn crate::testing::IOShim>) -> &mut Self {
+        self.io_shim = Some(shim);
+        self
+    }
 
-        // Check if there are any empty slots availale in any of the levels
-        // If there are, insert the key-value pair and return true
-        for level in LEVELS {
-            let fidx = self.buck_idx_lvl(fhash, level);
-            let sidx = self.buck_idx_lvl(shash, level);
-            for j in 0..bucket_size {
-                if self
-                    .insert_entry_at_slot(
-                        level as _LevelIdxT,
-                        fidx,
-                        j,
-                        key,
-                        value,
-                        self.unique_keys,
-                    )
-                    .is_ok()
-                    || self
-                        .insert_entry_at_slot(
-                            level as _LevelIdxT,
-                            sidx,
-                            j,
-                            key,
-                            value,
-                            self.unique_keys,
-                        )
-                        .is_ok()
-                {
-                    self.item_counts[level as usize] += 1;
-                    return Ok(());
+    /// Build the level hash instance
+    pub fn build(&mut self) -> LevelInitResult {
+        self.validate()?;
+
+        let mut io = if self.in_memory {
+            let index_name = self.index_name.take();
+            let index_name = index_name
+                .as_deref()
+                .unwrap_or(IN_MEMORY_INDEX_NAME_DEFAULT);
+            let mut io = LevelHashIO::new_in_memory(
+                index_name,
+                self.level_size,
+                self.bucket_size,
+                self.level_count,
+                self.spill_threshold,
+                self.checksums,
+                self.entry_versions,
+                self.aligned_buckets,
+                self.stash_slots,
+                self.values_growth,
+                self.keymap_options(),
+            )?;
+
+            if self.bloom_filter {
+                let file_name = format!(
+                    "{}{}{}",
+                    index_name,
+                    LevelHashIO::LEVEL_INDEX_EXT,
+                    LevelHashIO::LEVEL_BLOOM_EXT
+                );
+                io.bloom = Some(BloomFilter::from_file(memfd_file(&file_name)?)?);
+            }
+
+            io
+        } else {
+            let index_dir = self.index_dir.take().ok_or_else(|| {
+                LevelInitError::InvalidArg("Index directory must be specified".to_string())
+            })?;
+            let index_name = self.index_name.take().ok_or_else(|| {
+                LevelInitError::InvalidArg("Index name must be specified".to_string())
+            })?;
+
+            let files = IndexFiles::new(self.paths.as_ref(), &index_dir, &index_name);
+            if self.read_only && !LevelHashIO::index_exists(&files) {
+                return Err(LevelInitError::IndexNotFound {
+                    index_dir,
+                    index_name,
+                });
+            }
+
+            let lock_mode = if self.read_only {
+                LockMode::Shared
+            } else {
+                LockMode::Exclusive
+            };
+
+            let mut io = LevelHashIO::new(
+                &index_dir,
+                &index_name,
+                &files,
+                self.level_size,
+                self.bucket_size,
+                self.level_count,
+                self.spill_threshold,
+                self.checksums,
+                self.entry_versions,
+                self.aligned_buckets,
+                self.stash_slots,
+                self.values_growth,
+                self.values_segment_size,
+                lock_mode,
+                self.keymap_options(),
+                self.recovery,
+            )?;
+
+            let bloom_file = &files.bloom;
+            if self.read_only {
+                // the readers neither create nor delete the filter, but an existing filter
+                // must be kept up to date if the lock is upgraded
+                if bloom_file.exists() {
+                    io.bloom = Some(BloomFilter::new(bloom_file)?);
                 }
+            } else if self.bloom_filter {
+                io.bloom = Some(BloomFilter::new(bloom_file)?);
+            } else if bloom_file.exists() {
+                // the filter would not contain the keys inserted without it
+                fs::remove_file(bloom_file).into_lvl_io_e_msg(format!(
+                    "failed to delete file: {}",
+                    bloom_file.display()
+                ))?;
             }
+
+            io
+        };
+        io.compress_threshold = self.compress_threshold;
+        io.key_spill_threshold = self.key_spill_threshold;
+        #[cfg(feature = "testing")]
+        if let Some(shim) = self.io_shim.clone() {
+            io.set_io_shim(shim);
+        }
+        #[cfg(feature = "secondary-index")]
+        if let Some(extractor) = self.secondary_index.take() {
+            io.set_secondary_index(extractor);
         }
 
-        for level in LEVELS {
-            let fidx = self.buck_idx_lvl(fhash, level);
-            let sidx = self.buck_idx_lvl(shash, level);
+        let default_fn = Self::resolve_default_hash_fn(&mut io);
+        let fn1 = self.hashfn_1.take().unwrap_or(default_fn);
+        let fn2 = self.hashfn_2.take().unwrap_or(default_fn);
+        let seeds = self.resolve_seeds(&mut io, fn1, fn2)?;
 
-            if self
-                .try_movement(level, fidx, bucket_size, key, value)
-                .is_ok()
-                || self
-                    .try_movement(level, sidx, bucket_size, key, value)
-                    .is_ok()
-            {
-                return Ok(());
+        let expand_policy = self.expand_policy.take().unwrap_or_else(|| {
+            if self.auto_expand {
+                Box::new(ExpandAtLoadFactor(self.load_factor_threshold))
+            } else {
+                Box::new(NeverExpand)
             }
-        }
+        });
 
-        if self.expand_count > 0 {
-            let fidx = self.buck_idx_lvl(fhash, L0);
-            let sidx = self.buck_idx_lvl(shash, L0);
-            let from_pos = self
-                .b2t_movement(fidx, bucket_size)
-                .map(|slot| (fidx, slot))
-                .or_else(|| {
-                    self.b2t_movement(sidx, bucket_size)
-                        .map(|slot| (sidx, slot))
-                });
+        let trace = match self.trace_file.take() {
+            Some(path) => {
+                let meta = io.meta.read();
+                let header = TraceHeader {
+                    level_size: meta.km_level_size,
+                    bucket_size: meta.km_bucket_size,
+                    level_count: meta.km_level_count,
+                    unique_keys: self.unique_keys,
+                    auto_expand: expand_policy.load_factor_threshold().is_some(),
+                    load_factor_threshold: expand_policy
+                        .load_factor_threshold()
+                        .unwrap_or(self.load_factor_threshold),
+                    movement_depth: self.movement_depth,
+                };
+                Some(TraceWriter::create(&path, &header)?)
+            }
+            None => None,
+        };
 
-            if let Some((bucket, slot)) = from_pos {
-                self.io
-                    .create_or_update_entry(L0 as _LevelIdxT, bucket, slot, key, value)?;
-                self.item_counts[L0 as usize] += 1;
-                return Ok(());
+        let (max_key_size, max_value_size) =
+            io.init_size_limits(self.max_key_size, self.max_value_size);
+        let mut hash = LevelHash::new(
+            io,
+            self.unique_keys,
+            expand_policy,
+            self.movement_depth,
+            max_key_size,
+            max_value_size,
+            self.sync_policy,
+            seeds.0,
+            seeds.1,
+            fn1,
+            fn2,
+        )?;
+        hash.trace = trace;
+        hash.key_transform = self.key_transform.take();
+        hash.observer = self.observer.take();
+        hash.rehash_step = self.incremental_expansion;
+        hash.compact_threshold = self.auto_compact;
+        hash.write_budget = self.write_budget;
+        hash.next_budget_mark = self.write_budget.unwrap_or(0);
+        hash.probe_order = self.probe_order;
+
+        // the values and the keymap may have been modified after the restored checkpoint by the
+        // interrupted operation, the slots and entries which do not match the metadata are fixed
+        if hash.io.meta.restored && !hash.io.is_read_only() && hash.modified_since_checkpoint() {
+            match hash.repair() {
+                Ok(report) => log_warn!(
+                    "repaired the index after restoring the metadata: {} inconsistencies, {} entries relinked, {} entries dropped",
+                    report.inconsistencies.len(),
+                    report.relinked_entries,
+                    report.dropped_entries
+                ),
+                Err(why) => log_warn!("failed to repair the index after restoring the metadata: {:?}", why),
             }
         }
+        hash.io.commit_meta();
+
+        // the entries of an index opened with recovery are not verified, only the slots which
+        // point outside of the values file are fixed
+        #[cfg(feature = "debug-invariants")]
+        if !self.recovery {
+            hash.assert_invariants("open");
+        }
 
-        Err(LevelInsertionError::InsertionFailure)
+        Ok(hash)
     }
 
-    /// Remove the entry associated with the given key.
-    ///
-    /// ## Parameters
-    ///
-    /// * `key` - The key for the entry to remove.
-    ///
-    /// ## Returns
-    ///
-    /// `Some` containing the raw bytes of the value of the deleted entry (if found and is occupied), `None` otherwise.
-    pub fn remove(&mut self, key: &LevelKeyT) -> Option<Vec<u8>> {
-        if let Some((e, _, _, _)) = self.find_slot(key) {
-            // e.addr is 0-based and delete_at accepts a 1-based address
-            return self.io.delete_at(e.addr + 1, Some(key), true);
+    /// Get the hash function which is used for the given index if no hash functions are set. New
+    /// indexes use [siphash13], which is recorded in their meta file, while the existing indexes
+    /// which do not record it keep using [xxh64].
+    fn resolve_default_hash_fn(io: &mut LevelHashIO) -> HashFn {
+        let meta = io.meta.read();
+        // same as in resolve_seeds, the seeds are not stored yet in a new index
+        let new_index = meta.val_next_addr == 1 && (meta.km_seed_1, meta.km_seed_2) == (0, 0);
+        let default_hash = meta.km_default_hash;
+
+        if new_index && !io.is_read_only() {
+            io.meta.write().km_default_hash = DEFAULT_HASH_SIPHASH13;
         }
 
-        None
+        if new_index || default_hash == DEFAULT_HASH_SIPHASH13 {
+            siphash13
+        } else {
+            xxh64
+        }
     }
 
-    /// Update the entry associated with the given key with the new value.
-    ///
-    /// ## Parameters
-    ///
-    /// * `key` - The key to update the value for.
-    /// * `new_value` - The new value for the entry.
-    ///
-    /// ## Returns
-    ///
-    /// `Some` containing the raw bytes of the previous value of the entry (if found and is occupied), `None` otherwise.
-    pub fn update(&mut self, key: &LevelKeyT, new_value: &LevelValueT) -> LevelUpdateResult {
-        let slot = self.find_slot(key);
+    /// Get the seeds for the given index. The seeds stored in the index are used if no seeds were
+    /// set, and the seeds of a new index are stored in its meta file.
+    fn resolve_seeds(
+        &mut self,
+        io: &mut LevelHashIO,
+        fn1: HashFn,
+        fn2: HashFn,
+    ) -> LevelResult<(u64, u64), LevelInitError> {
+        let seeds = self.seeds.take();
+        let meta = io.meta.read();
+        let stored = (meta.km_seed_1, meta.km_seed_2);
+        if stored == (0, 0) {
+            // the entries written by older versions of level hash use the deterministic seeds,
+            // unless other seeds were set
+            let new_index = meta.val_next_addr == 1;
+            let seeds = seeds.unwrap_or_else(|| {
+                if new_index && self.seeds_from_entropy {
+                    generate_seeds()
+                } else {
+                    generate_seeds_deterministic()
+                }
+            });
 
-        if slot.is_none() {
-            return Err(LevelUpdateError::SlotNotFound);
+            if new_index && !io.is_read_only() {
+                let meta = io.meta.write();
+                meta.km_seed_1 = seeds.0;
+                meta.km_seed_2 = seeds.1;
+                meta.km_hash_check = LevelHash::hash_check(fn1, fn2, seeds);
+            }
+
+            return Ok(seeds);
+        }
+
+        let seeds = seeds.unwrap_or(stored);
+        if seeds != stored {
+            return Err(LevelInitError::SeedMismatch {
+                expected: stored,
+                found: seeds,
+            });
         }
 
-        let (_, level, bucket, slot) = slot.unwrap();
-        self.io.update_entry_value(level, bucket, slot, new_value)
+        if LevelHash::hash_check(fn1, fn2, seeds) != meta.km_hash_check {
+            return Err(LevelInitError::HashFnMismatch);
+        }
+
+        Ok(seeds)
     }
 
-    /// Expand the level hash by one level size, doubling its capacity. This is an expensive operation
-    /// and must be used carefully. Consider enabling [LevelHashOptions::auto_expand] to automatically expand
-    /// the level hash when appropriate. A level hash can have a maximum of [LEVEL_SIZE_MAX] level size.
-    ///
-    /// # Returns
+    /// Open the existing index with the configured directory and name. Unlike [Self::build], the
+    /// index is not created if it does not exist. The level size, the bucket size, the level count
+    /// and whether the entries are checksummed are always read from the index, the values set in
+    /// these options are ignored.
     ///
-    /// The result of the expansion.
-    pub fn expand(&mut self) -> LevelExpansionResult {
-        let level_size = self.io.meta.read().km_level_size;
-        if level_size == LEVEL_SIZE_MAX {
-            return Err(crate::result::LevelExpansionError::MaxLevelSizeReached);
+    /// The seeds are read from the index, unless they are set. The hash functions are not stored in
+    /// the index, they must be set if the index was created with other than the default hash
+    /// functions.
+    pub fn open(&mut self) -> LevelInitResult {
+        if self.in_memory {
+            return Err(LevelInitError::InvalidArg(
+                "In-memory level hashes cannot be opened".to_string(),
+            ));
         }
 
-        let level_size = level_size + 1;
+        let (Some(index_dir), Some(index_name)) = (&self.index_dir, &self.index_name) else {
+            return Err(LevelInitError::InvalidArg(
+                "Index directory and index name must be specified".to_string(),
+            ));
+        };
 
-        let new_top_level_capacity: u64 = 1u64 << level_size;
-        let mut new_level_item_count = 0u32;
+        if !LevelHashIO::index_exists(&self.index_files(index_dir, index_name)) {
+            return Err(LevelInitError::IndexNotFound {
+                index_dir: index_dir.clone(),
+                index_name: index_name.clone(),
+            });
+        }
 
-        self.io
-            .prepare_interim(new_top_level_capacity as u32)
-            .into_lvl_exp_err()?;
+        // these are read from the index, reset them so that they pass the validation
+        self.level_size = LEVEL_SIZE_DEFAULT;
+        self.bucket_size = BUCKET_SIZE_DEFAULT;
+        self.level_count = LEVEL_COUNT_DEFAULT;
 
-        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        self.build()
+    }
 
-        for old_buck_idx in 0..(self.top_level_bucket_count() >> 1) {
-            for old_slot_idx in 0..bucket_size {
-                if !self
-                    .io
-                    .is_occupied(L1 as _LevelIdxT, old_buck_idx, old_slot_idx)
-                {
-                    continue;
-                }
+    /// Open the existing index like [Self::open], but check the index before it is used so that a
+    /// corrupt index is rejected with [LevelInitError::Corrupt] instead of causing a panic or a
+    /// `SIGBUS` later. The magic numbers of the values and keymap files, the level parameters and
+    /// the addresses in the meta file, the sizes of the files and the addresses in the slots of
+    /// the keymap are checked.
+    ///
+    /// The slots which point outside of the values file are fixed with [LevelHash::repair] if the
+    /// index is opened for writing. A read-only index with such slots is rejected.
+    pub fn open_with_recovery(&mut self) -> LevelInitResult {
+        self.recovery = true;
+        let hash = self.open();
+        self.recovery = false;
+        let mut hash = hash?;
+
+        let layout = hash.io.check_layout();
+        if !layout.is_empty() {
+            return Err(LevelInitError::Corrupt(
+                layout
+                    .into_iter()
+                    .map(Inconsistency::InvalidLayout)
+                    .collect(),
+            ));
+        }
 
-                let entry = self
-                    .io
-                    .val_entry_for_slot(L1 as _LevelIdxT, old_buck_idx, old_slot_idx)
-                    .unwrap();
-                let key = entry.key(&self.io.values);
-                let fhash = self.fhash(&key);
-                let shash = self.shash(&key);
+        let dangling = hash.dangling_slots();
+        if dangling.is_empty() {
+            return Ok(hash);
+        }
 
-                let fidx = Self::buck_idx_cap(fhash, new_top_level_capacity);
-                let sidx = Self::buck_idx_cap(shash, new_top_level_capacity);
-
-                let mut insert_success = false;
-                for new_slot_idx in 0..bucket_size {
-                    if self.io.move_to_interim(
-                        L1 as _LevelIdxT,
-                        old_buck_idx,
-                        old_slot_idx,
-                        fidx,
-                        new_slot_idx,
-                    ) || self.io.move_to_interim(
-                        L1 as _LevelIdxT,
-                        old_buck_idx,
-                        old_slot_idx,
-                        sidx,
-                        new_slot_idx,
-                    ) {
-                        insert_success = true;
-                        new_level_item_count += 1;
-                        break;
-                    }
-                }
+        if hash.io.is_read_only() {
+            return Err(LevelInitError::Corrupt(dangling));
+        }
 
-                if !insert_success {
-                    return Err(LevelExpansionError::from(
-                        LevelInsertionError::InsertionFailure,
-                    ));
-                }
+        match hash.repair() {
+            Ok(report) => {
+                log_warn!(
+                    "repaired {} dangling slots, dropped {} entries",
+                    dangling.len(),
+                    report.dropped_entries
+                );
+                Ok(hash)
+            }
+            Err(why) => {
+                log_error!("failed to repair the level hash: {:?}", why);
+                Err(LevelInitError::Corrupt(dangling))
             }
         }
-
-        self.io.commit_interim(level_size);
-        self.item_counts = [new_level_item_count, self.item_counts[L0 as usize]];
-        self.expand_count += 1;
-
-        Ok(())
     }
 
-    pub fn clear(&mut self) -> LevelClearResult {
-        self.io.clear()?;
-        self.expand_count = 0;
-        self.item_counts = [0, 0];
+    /// Get the owner of the exclusive lock of the index with the configured directory and name, as
+    /// written to its lock file. See [LockOwner::is_running] to check whether the lock file is
+    /// stale.
+    ///
+    /// ## Returns
+    ///
+    /// The owner of the lock, or `None` if the index is not locked, is in memory, or is only
+    /// locked by read-only level hashes.
+    pub fn lock_owner(&self) -> LevelResult<Option<LockOwner>, LevelInitError> {
+        match self.lock_file()? {
+            Some(lock_file) => crate::fs::lock_owner(&lock_file).map_err(LevelInitError::IOError),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the lock file of the index with the configured directory and name, if it was left
+    /// by a process which did not close the index. The lock file is only deleted if no process
+    /// holds a lock on it, and if its owner is not running (see [LockOwner::is_running]). The
+    /// stale lock files do not prevent the index from being opened, so this is only needed to
+    /// clean up the index directory.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the lock file was deleted, or [LevelInitError::Locked] if the index is locked.
+    pub fn force_unlock(&self) -> LevelResult<bool, LevelInitError> {
+        match self.lock_file()? {
+            Some(lock_file) => crate::fs::force_unlock(&lock_file),
+            None => Ok(false),
+        }
+    }
+
+    /// Get the path of the lock file of the index with the configured directory and name, or
+    /// `None` for the in-memory level hashes.
+    fn lock_file(&self) -> LevelResult<Option<PathBuf>, LevelInitError> {
+        if self.in_memory {
+            return Ok(None);
+        }
+
+        let Some(index_dir) = &self.index_dir else {
+            return Err(LevelInitError::InvalidArg(
+                "Index directory must be specified".to_string(),
+            ));
+        };
+        let Some(index_name) = &self.index_name else {
+            return Err(LevelInitError::InvalidArg(
+                "Index name must be specified".to_string(),
+            ));
+        };
+
+        Ok(Some(self.index_files(index_dir, index_name).lock))
+    }
+
+    /// Delete the index files of the index with the configured directory and name, if they exist.
+    /// This does nothing for in-memory level hashes.
+    pub(crate) fn remove_existing_index(&self) -> LevelResult<(), StdIOError> {
+        if self.in_memory {
+            return Ok(());
+        }
+
+        let (Some(index_dir), Some(index_name)) = (&self.index_dir, &self.index_name) else {
+            return Ok(());
+        };
+
+        let files = self.index_files(index_dir, index_name);
+        for path in files.data_files() {
+            if path.exists() {
+                fs::remove_file(path)
+                    .into_lvl_io_e_msg(format!("failed to delete file: {}", path.display()))?;
+            }
+        }
+
+        // the other segments of a segmented values file
+        for idx in 1.. {
+            let path = SegmentedFile::segment_path(&files.values, idx);
+            if !path.exists() {
+                break;
+            }
+
+            fs::remove_file(&path)
+                .into_lvl_io_e_msg(format!("failed to delete file: {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the paths of the files of the given index, named with the configured [Paths].
+    fn index_files(&self, index_dir: &Path, index_name: &str) -> IndexFiles {
+        IndexFiles::new(self.paths.as_ref(), index_dir, index_name)
+    }
+
+    /// The options used to memory map the keymap file.
+    fn keymap_options(&self) -> MapOptions {
+        MapOptions {
+            populate: self.populate,
+            huge_pages: self.huge_pages,
+            lock_memory: self.lock_memory,
+        }
+    }
+
+    /// Check whether the options are valid, returning [LevelInitError::InvalidArg] describing the
+    /// first invalid option otherwise.
+    fn validate(&self) -> LevelResult<(), LevelInitError> {
+        if self.level_size == 0 || self.level_size > LEVEL_SIZE_MAX {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Level size must be between 1 and {}",
+                LEVEL_SIZE_MAX
+            )));
+        }
+
+        if self.bucket_size == 0 {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Bucket size must be between 1 and {}",
+                BUCKET_SIZE_MAX
+            )));
+        }
+
+        if self.level_count < 2 || self.level_count > LEVEL_COUNT_MAX {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Level count must be between 2 and {}",
+                LEVEL_COUNT_MAX
+            )));
+        }
+
+        if self.level_size < self.level_count - 1 {
+            // the bottom level must have at least one bucket
+            return Err(LevelInitError::InvalidArg(format!(
+                "Level size must be at least {} for {} levels",
+                self.level_count - 1,
+                self.level_count
+            )));
+        }
+
+        let policy_threshold = self
+            .expand_policy
+            .as_ref()
+            .and_then(|policy| policy.load_factor_threshold());
+        if !(0.5..=1.0).contains(&self.load_factor_threshold)
+            || policy_threshold.is_some_and(|threshold| !(0.5..=1.0).contains(&threshold))
+        {
+            return Err(LevelInitError::InvalidArg(
+                "Load factor threshold must be between 0.5 and 1.0".to_string(),
+            ));
+        }
+
+        if self
+            .auto_compact
+            .is_some_and(|threshold| !(threshold > 0.0 && threshold < 1.0))
+        {
+            return Err(LevelInitError::InvalidArg(
+                "Compaction threshold must be between 0.0 and 1.0 (exclusive)".to_string(),
+            ));
+        }
+
+        if self.write_budget == Some(0) {
+            return Err(LevelInitError::InvalidArg(
+                "Write budget must not be 0".to_string(),
+            ));
+        }
+
+        if self.incremental_expansion == Some(0) {
+            return Err(LevelInitError::InvalidArg(
+                "Incremental expansion must rehash at least one bucket per write".to_string(),
+            ));
+        }
+
+        if self.movement_depth > MOVEMENT_DEPTH_MAX {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Movement depth must be <= {}",
+                MOVEMENT_DEPTH_MAX
+            )));
+        }
+
+        if self.stash_slots > STASH_SLOTS_MAX {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Stash slots must be <= {}",
+                STASH_SLOTS_MAX
+            )));
+        }
+
+        if self.spill_threshold > VALUE_SPILL_THRESHOLD_MAX {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Spill threshold must be <= {}",
+                VALUE_SPILL_THRESHOLD_MAX
+            )));
+        }
+
+        // 0 is stored in the meta file of the indexes which do not have limits
+        if self.max_key_size == Some(0) || self.max_value_size == Some(0) {
+            return Err(LevelInitError::InvalidArg(
+                "Maximum key and value sizes must be > 0".to_string(),
+            ));
+        }
+
+        if let Some(block_size) = self.values_growth {
+            let page_size = page_size();
+            if block_size == 0 || block_size % page_size != 0 {
+                return Err(LevelInitError::InvalidArg(format!(
+                    "Values growth must be a non-zero multiple of the page size ({})",
+                    page_size
+                )));
+            }
+        }
+
+        if let Some(segment_size) = self.values_segment_size {
+            let page_size = page_size();
+            if segment_size < LevelHashIO::VALUES_SEGMENT_SIZE_MIN || segment_size % page_size != 0
+            {
+                return Err(LevelInitError::InvalidArg(format!(
+                    "Values segment size must be a multiple of the page size ({}) and at least {}",
+                    page_size,
+                    LevelHashIO::VALUES_SEGMENT_SIZE_MIN
+                )));
+            }
+        }
+
+        if self.read_only && self.in_memory {
+            return Err(LevelInitError::InvalidArg(
+                "In-memory level hashes cannot be read-only".to_string(),
+            ));
+        }
+
+        if self.read_only && cfg!(target_endian = "big") {
+            // the meta file is converted to the native byte order while the index is open
+            return Err(LevelInitError::InvalidArg(
+                "Read-only level hashes are not supported on big-endian hosts".to_string(),
+            ));
+        }
+
+        if let Some((seed_1, seed_2)) = self.seeds {
+            if seed_1 == 0 || seed_2 == 0 {
+                return Err(LevelInitError::InvalidArg(
+                    "Seeds must be greater than 0".to_string(),
+                ));
+            }
+        }
+
         Ok(())
     }
-}
 
-//noinspection DuplicatedCode
-#[cfg(test)]
-mod test {
-    use std::assert_matches::assert_matches;
-    use std::fs;
-    use std::fs::File;
-    use std::io;
-    use std::os::fd::AsRawFd;
-    use std::path::Path;
+    /// Build a [ShardedLevelHash] which splits the keys across `shard_count` level hashes, built
+    /// with these options. The index of each shard is named with
+    /// [ShardedLevelHash::shard_index_name]. The existing shards are opened, in which case the
+    /// shard count must be the same as when the shards were created.
+    ///
+    /// The key transformation (if any) is applied by the [ShardedLevelHash] before the shard of a
+    /// key is selected. An [ExpandPolicy] cannot be shared by the shards, so only
+    /// [Self::auto_expand] and [Self::load_factor_threshold] can be used, and the operations cannot
+    /// be observed or traced.
+    pub fn build_sharded(
+        &mut self,
+        shard_count: usize,
+    ) -> LevelResult<ShardedLevelHash, LevelInitError> {
+        if shard_count == 0 || shard_count > SHARD_COUNT_MAX {
+            return Err(LevelInitError::InvalidArg(format!(
+                "Shard count must be between 1 and {}",
+                SHARD_COUNT_MAX
+            )));
+        }
+
+        if self.expand_policy.is_some() || self.observer.is_some() || self.trace_file.is_some() {
+            return Err(LevelInitError::InvalidArg(
+                "Expand policies, observers and traces are not supported for sharded level hashes"
+                    .to_string(),
+            ));
+        }
+
+        let index_name = match self.index_name.clone() {
+            Some(index_name) => index_name,
+            None if self.in_memory => IN_MEMORY_INDEX_NAME_DEFAULT.to_string(),
+            None => {
+                return Err(LevelInitError::InvalidArg(
+                    "Index name must be specified".to_string(),
+                ))
+            }
+        };
+
+        if let (false, Some(index_dir)) = (self.in_memory, &self.index_dir) {
+            // the shard of a key depends on the shard count, which is not stored in the index
+            let exists = |shard| {
+                let shard_name = ShardedLevelHash::shard_index_name(&index_name, shard);
+                LevelHashIO::index_exists(&self.index_files(index_dir, &shard_name))
+            };
+            let existing = (0..shard_count).filter(|shard| exists(*shard)).count();
+
+            if exists(shard_count) || (existing != 0 && existing != shard_count) {
+                return Err(LevelInitError::InvalidArg(format!(
+                    "The index '{}' was created with another shard count",
+                    index_name
+                )));
+            }
+        }
+
+        let key_transform = self.key_transform.take();
+        let (index_dir, seeds) = (self.index_dir.clone(), self.seeds);
+        let (hashfn_1, hashfn_2) = (self.hashfn_1, self.hashfn_2);
+
+        let mut shards = Vec::with_capacity(shard_count);
+        for shard in 0..shard_count {
+            self.index_dir = index_dir.clone();
+            self.index_name = Some(ShardedLevelHash::shard_index_name(&index_name, shard));
+            self.seeds = seeds;
+            self.hashfn_1 = hashfn_1;
+            self.hashfn_2 = hashfn_2;
+            shards.push(self.build()?);
+        }
+
+        Ok(ShardedLevelHash::new(shards, key_transform))
+    }
+
+    /// Build a [TypedLevelHash] instance which stores keys of type `K` and values of type `V`.
+    #[cfg(feature = "serde")]
+    pub fn build_typed<K, V>(&mut self) -> LevelResult<TypedLevelHash<K, V>, LevelInitError>
+    where
+        K: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned,
+    {
+        self.build().map(TypedLevelHash::new)
+    }
+}
+
+impl Default for LevelHashOptions {
+    fn default() -> Self {
+        Self {
+            level_size: LEVEL_SIZE_DEFAULT,
+            bucket_size: BUCKET_SIZE_DEFAULT,
+            level_count: LEVEL_COUNT_DEFAULT,
+            unique_keys: true,
+            auto_expand: true,
+            load_factor_threshold: LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT,
+            expand_policy: None,
+            incremental_expansion: None,
+            auto_compact: None,
+            write_budget: None,
+            movement_depth: MOVEMENT_DEPTH_DEFAULT,
+            max_key_size: None,
+            max_value_size: None,
+            spill_threshold: VALUE_SPILL_THRESHOLD_DEFAULT,
+            compress_threshold: None,
+            key_spill_threshold: None,
+            checksums: false,
+            entry_versions: false,
+            aligned_buckets: false,
+            stash_slots: 0,
+            probe_order: ProbeOrder::LevelLoad,
+            values_growth: None,
+            values_segment_size: None,
+            bloom_filter: false,
+            sync_policy: SyncPolicy::NoSync,
+            seeds: None,
+            seeds_from_entropy: true,
+            hashfn_1: None,
+            hashfn_2: None,
+            key_transform: None,
+            observer: None,
+            paths: Box::new(DefaultPaths),
+            #[cfg(feature = "secondary-index")]
+            secondary_index: None,
+            #[cfg(feature = "testing")]
+            io_shim: None,
+            index_dir: None,
+            index_name: None,
+            in_memory: false,
+            read_only: false,
+            populate: false,
+            huge_pages: false,
+            lock_memory: false,
+            trace_file: None,
+            recovery: false,
+        }
+    }
+}
+
+impl LevelHash {
+    /// Create new [LevelHashOptions] used to build a [LevelHash].
+    pub fn options() -> LevelHashOptions {
+        LevelHashOptions::new()
+    }
+
+    /// Open the existing index with the given name in the given directory, with the default seeds
+    /// and hash functions. The level size, the bucket size and the level count are read from the
+    /// index. Use [LevelHashOptions::open] to open the index with other options.
+    ///
+    /// ## Parameters
+    ///
+    /// * `index_dir` - The directory of the index.
+    /// * `index_name` - The name of the index.
+    ///
+    /// ## Returns
+    ///
+    /// The level hash, or [LevelInitError::IndexNotFound] if the index does not exist.
+    pub fn open(index_dir: &Path, index_name: &str) -> LevelInitResult {
+        Self::options()
+            .index_dir(index_dir)
+            .index_name(index_name)
+            .open()
+    }
+
+    /// Build a level hash with the given options and load the given entries in one shot. The
+    /// keymap is sized up front for the number of entries, so that the level hash is not expanded
+    /// while the entries are loaded, and the entries are written sequentially to the values file.
+    /// The entries are placed in the free slots of their buckets, and fall back to the regular
+    /// insertion (which moves the existing entries) only if both of their buckets are full. The
+    /// changes are synced once, after all the entries have been loaded.
+    ///
+    /// If the keys are unique, the later entries of a key replace the earlier ones. The index must
+    /// be empty if it exists.
+    ///
+    /// ## Parameters
+    ///
+    /// * `entries` - The key-value pairs to load.
+    /// * `options` - The options used to build the level hash. The level size is raised to fit the
+    ///   entries.
+    ///
+    /// ## Returns
+    ///
+    /// The level hash, or [LevelInitError::InsertionError] if one of the entries could not be
+    /// inserted.
+    pub fn bulk_load<I, K, V>(entries: I, options: &mut LevelHashOptions) -> LevelInitResult
+    where
+        I: IntoIterator<Item = (K, V)>,
+        I::IntoIter: ExactSizeIterator,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let entries = entries.into_iter();
+        let count = entries.len() as u64;
+        log_span!("bulk_load", entries = count);
+        let fits = |level_size: LevelSizeT, bucket_size: BucketSizeT| {
+            let slots = (1u64 << level_size) * bucket_size as u64;
+            slots as f32 * BULK_LOAD_FACTOR >= count as f32
+        };
+
+        options.level_size = (options.level_size..LEVEL_SIZE_MAX)
+            .find(|level_size| fits(*level_size, options.bucket_size))
+            .unwrap_or(LEVEL_SIZE_MAX);
+
+        let mut hash = options.build()?;
+        if hash.occupancy().total() != 0 {
+            return Err(LevelInitError::InvalidArg(
+                "Entries can only be bulk loaded into an empty index".to_string(),
+            ));
+        }
+
+        // the level size of an existing index is not changed by the options
+        while !fits(hash.level_size(), hash.bucket_size()) && hash.level_size() < LEVEL_SIZE_MAX {
+            hash.do_expand()
+                .into_lvl_ins_err()
+                .map_err(LevelInitError::InsertionError)?;
+        }
+
+        for (key, value) in entries {
+            let key = hash.transform_key(key.as_ref()).into_owned();
+            let value = value.as_ref();
+            let result = hash.bulk_insert(&key, value);
+            hash.trace_op(TraceOp::Insert, Some(&key), value.len(), result.is_ok());
+            result.map_err(LevelInitError::InsertionError)?;
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        hash.assert_invariants("bulk_load");
+
+        hash.sync_after_write()?;
+        Ok(hash)
+    }
+
+    fn new(
+        io: LevelHashIO,
+        unique_keys: bool,
+        expand_policy: Box<dyn ExpandPolicy>,
+        movement_depth: u8,
+        max_key_size: u64,
+        max_value_size: u64,
+        sync_policy: SyncPolicy,
+        seed_1: u64,
+        seed_2: u64,
+        hashfn_1: HashFn,
+        hashfn_2: HashFn,
+    ) -> LevelInitResult {
+        let file_sizes = (io.meta.read().val_file_size, io.meta.km_size());
+        let mut hash = Self {
+            unique_keys,
+            expand_policy,
+            movement_depth,
+            max_key_size,
+            max_value_size,
+            seed_1,
+            seed_2,
+            hashfn_1,
+            hashfn_2,
+            key_transform: None,
+            observer: None,
+            file_sizes,
+            expand_count: 0,
+            insertion_failures: 0,
+            sync_policy,
+            last_sync: Instant::now(),
+            trace: None,
+            probe_stats: ProbeStats::default(),
+            entry_expiry: None,
+            in_batch: false,
+            rehash_step: None,
+            compact_threshold: None,
+            meta_flushes: 0,
+            write_budget: None,
+            next_budget_mark: 0,
+            probe_order: ProbeOrder::LevelLoad,
+            io,
+        };
+
+        if hash
+            .io
+            .bloom
+            .as_ref()
+            .is_some_and(|bloom| bloom.size() != hash.bloom_size())
+        {
+            hash.rebuild_bloom()?;
+        }
+
+        if !hash.occupancy().is_valid() {
+            // the occupancy is not stored in the indexes created before it was
+            // maintained, count the occupied slots once
+            let mut counts = [0u32; LEVEL_COUNT_MAX as usize];
+            for &level in hash.levels() {
+                counts[level as usize] = hash.count_occupied(level);
+            }
+            hash.occupancy_mut().set(counts);
+        }
+
+        Ok(hash)
+    }
+
+    /// Get the level size of the level hash. The top level has `2^level_size` buckets.
+    #[inline]
+    pub fn level_size(&self) -> LevelSizeT {
+        self.io.meta.read().km_level_size
+    }
+
+    /// Get the number of slots in each bucket of the level hash.
+    #[inline]
+    pub fn bucket_size(&self) -> BucketSizeT {
+        self.io.meta.read().km_bucket_size
+    }
+
+    /// Get the size (bytes) by which the values file grows.
+    #[inline]
+    pub fn values_growth(&self) -> u64 {
+        self.io.val_block_size()
+    }
+
+    /// Get the number of buckets in the top level.
+    #[inline]
+    pub fn top_level_bucket_count(&self) -> u32 {
+        1 << self.io.meta.read().km_level_size
+    }
+
+    /// Get the total number of buckets in the level hash.
+    pub fn total_bucket_count(&self) -> u32 {
+        self.levels()
+            .iter()
+            .map(|level| self.level_bucket_count(*level))
+            .sum()
+    }
+
+    /// Get the number of levels in the level hash.
+    #[inline]
+    pub fn level_count(&self) -> LevelCountT {
+        self.io.meta.read().km_level_count
+    }
+
+    /// Get the levels of the level hash, top level first.
+    #[inline]
+    fn levels(&self) -> &'static [Level] {
+        &LEVELS[..self.level_count() as usize]
+    }
+
+    /// Get the content fingerprint of the level hash. The fingerprint is an order-independent
+    /// digest of all the live key-value pairs, which is maintained incrementally as the entries are
+    /// inserted, updated and removed. Two level hashes with the same entries have the same
+    /// fingerprint, irrespective of their seeds, hash functions, sizes and the order in which the
+    /// entries were inserted.
+    #[inline]
+    pub fn content_fingerprint(&self) -> u64 {
+        self.io.meta.read().val_fingerprint
+    }
+
+    /// Get the generation of the keymap. The generation is stored in the meta file and is
+    /// incremented before and after every structural change of the keymap ([Self::expand],
+    /// [Self::shrink], [Self::clear] and [Self::repair]), so it is odd while such a change is in
+    /// progress. A read which observed the same even generation before and after it did not read
+    /// through the interim state of a structural change.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.io.meta.generation()
+    }
+
+    /// Perform a structural change of the keymap, incrementing the generation before and after
+    /// the change. The generation is not changed for read-only level hashes (which fail to make the
+    /// change) or if a structural change is already in progress (e.g. the expansions made by
+    /// [Self::repair]).
+    fn structural_change<R>(&mut self, change: impl FnOnce(&mut Self) -> R) -> R {
+        if self.io.is_read_only() || self.io.meta.generation() & 1 == 1 {
+            return change(self);
+        }
+
+        self.io.meta.begin_structural_change();
+        let result = change(self);
+        self.io.meta.end_structural_change();
+        self.io.commit_meta();
+        result
+    }
+
+    /// Perform a read, failing with [LevelReadError::ConcurrentModification] if a structural
+    /// change of the keymap was in progress or was made while reading.
+    fn read_consistent<T>(
+        &self,
+        read: impl FnOnce() -> LevelResult<T, LevelReadError>,
+    ) -> LevelResult<T, LevelReadError> {
+        let generation = self.io.meta.generation();
+        if generation & 1 == 1 {
+            return Err(LevelReadError::ConcurrentModification { generation });
+        }
+
+        // the result of a read made through the interim state is not reliable, even if it failed
+        let result = read();
+        if self.io.meta.generation_after_read() != generation {
+            return Err(LevelReadError::ConcurrentModification { generation });
+        }
+
+        result
+    }
+
+    /// Get the total number of slots in all the levels of the level hash, excluding the stash.
+    /// This is same as [CapacityInfo::slots].
+    #[inline]
+    pub fn total_slots(&self) -> u64 {
+        self.level_slots(self.io.meta.read().km_level_size)
+    }
+
+    /// Get the number of slots in all the levels if the top level had the given size.
+    fn level_slots(&self, level_size: LevelSizeT) -> u64 {
+        let meta = self.io.meta.read();
+        (0..meta.km_level_count as u32)
+            .map(|level| (1u64 << level_size >> level) * meta.km_bucket_size as u64)
+            .sum()
+    }
+
+    /// Get the load factor of the level hash, i.e. the number of entries in all the levels divided
+    /// by [Self::total_slots].
+    pub fn load_factor(&self) -> f32 {
+        self.occupancy().load_factor(self.total_slots())
+    }
+
+    #[inline]
+    fn occupancy(&self) -> &Occupancy {
+        &self.io.meta.read().km_occupancy
+    }
+
+    #[inline]
+    fn occupancy_mut(&mut self) -> &mut Occupancy {
+        &mut self.io.meta.write().km_occupancy
+    }
+}
+
+impl LevelHash {
+    #[inline]
+    fn fhash(&self, key: &LevelKeyT) -> u64 {
+        return (self.hashfn_1)(self.seed_1, key);
+    }
+
+    #[inline]
+    fn shash(&self, key: &LevelKeyT) -> u64 {
+        return (self.hashfn_2)(self.seed_2, key);
+    }
+
+    /// Compute the hash check of the given hash functions and seeds, which is stored in the meta
+    /// file to detect that an index is opened with other hash functions.
+    fn hash_check(fn1: HashFn, fn2: HashFn, seeds: (u64, u64)) -> u64 {
+        fn1(seeds.0, HASH_CHECK_KEY) ^ fn2(seeds.1, HASH_CHECK_KEY).rotate_left(32)
+    }
+
+    /// Apply the [KeyTransform] of the level hash (if any) to the given key.
+    #[inline]
+    fn transform_key<'a>(&self, key: &'a LevelKeyT) -> Cow<'a, LevelKeyT> {
+        match self.key_transform.as_ref() {
+            Some(transform) => transform.transform(key),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    fn buck_idx_lvl(&self, key_hash: u64, level: Level) -> u32 {
+        let capacity = self.level_bucket_count(level) as u64;
+        return Self::buck_idx_cap(key_hash, capacity);
+    }
+
+    fn level_bucket_count(&self, level: Level) -> u32 {
+        // each level has half the buckets of the level above it
+        self.top_level_bucket_count() >> level as u32
+    }
+
+    fn buck_idx_cap(key_hash: u64, capacity: u64) -> u32 {
+        // since capacity is a power of two and key hash is unsigned
+        // keyHash % capacity can be simplified with simple bitwise operation
+        return (key_hash & (capacity - 1)) as u32;
+    }
+
+    /// Check that the given key is not empty and is not larger than the maximum key size.
+    fn check_key(&self, key: &LevelKeyT) -> LevelInsertionResult {
+        if key.is_empty() {
+            return Err(LevelInsertionError::EmptyKey);
+        }
+
+        let size = key.len() as u64;
+        if size > self.max_key_size {
+            return Err(LevelInsertionError::KeyTooLarge {
+                size,
+                max: self.max_key_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn check_value_size(&self, value: &LevelValueT) -> LevelInsertionResult {
+        let size = value.len() as u64;
+        if size > self.max_value_size {
+            return Err(LevelInsertionError::ValueTooLarge {
+                size,
+                max: self.max_value_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn cmp_key_and_get_entry(
+        &self,
+        level: Level,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        key: &LevelKeyT,
+        key_tag: u16,
+    ) -> LevelResult<Option<ValuesEntry<'_>>, LevelReadError> {
+        let entry = self
+            .io
+            .val_entry_for_key_tag(level as _LevelIdxT, bucket, slot, key_tag)?;
+        Ok(entry.filter(|e| {
+            (!e.is_empty())
+                .then(|| self.io.entry_keyeq(e, key))
+                .is_true()
+        }))
+    }
+
+    /// Find the slot of the entry for the given key, or `None` if there is no entry for the key.
+    /// The slots which point outside of the values file are logged and treated as empty.
+    fn find_slot(
+        &self,
+        key: &LevelKeyT,
+    ) -> Option<(ValuesEntry<'_>, _LevelIdxT, _BucketIdxT, _SlotIdxT)> {
+        match self.try_find_slot(key) {
+            Ok(slot) => slot,
+            Err(why) => {
+                log_error!("{:?}", why);
+                None
+            }
+        }
+    }
+
+    /// Find the slot of the entry for the given key along with the first free slot in the buckets
+    /// of the key (in the order the slots are filled by the insertions), with a single traversal
+    /// of the slots. The free slot is only returned if there is no entry for the key.
+    #[allow(clippy::type_complexity)]
+    fn find_slot_or_free(
+        &self,
+        key: &LevelKeyT,
+    ) -> LevelResult<
+        (
+            Option<(ValuesEntry<'_>, _LevelIdxT, _BucketIdxT, _SlotIdxT)>,
+            Option<(_LevelIdxT, _BucketIdxT, _SlotIdxT)>,
+        ),
+        LevelReadError,
+    > {
+        let fhash = self.fhash(key);
+        let shash = self.shash(key);
+        let may_contain = self.io.bloom_may_contain(fhash, shash);
+        let key_tag = LevelHashIO::key_tag(key);
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+
+        let mut free_slot = None;
+        let mut probes = 0u64;
+        for &level in self.levels() {
+            let fidx = self.buck_idx_lvl(fhash, level);
+            let sidx = self.buck_idx_lvl(shash, level);
+
+            for j in 0..bucket_size {
+                for buck in [fidx, sidx] {
+                    if !self.io.is_occupied(level as _LevelIdxT, buck, j) {
+                        free_slot.get_or_insert((level as _LevelIdxT, buck, j));
+                        if !may_contain {
+                            // the key is not present, the first free slot is all that is needed
+                            return Ok((None, free_slot));
+                        }
+                    } else if may_contain {
+                        probes += 1;
+                        if let Some(e) = self.cmp_key_and_get_entry(level, buck, j, key, key_tag)? {
+                            self.probe_stats.record(true, probes);
+                            return Ok((Some((e, level as _LevelIdxT, buck, j)), None));
+                        }
+                    }
+                }
+            }
+        }
+
+        if may_contain {
+            if let Some(found) = self.find_in_stash(key, key_tag, &mut probes)? {
+                self.probe_stats.record(true, probes);
+                return Ok((Some(found), None));
+            }
+        }
+
+        self.probe_stats.record(false, probes);
+        Ok((None, free_slot))
+    }
+
+    /// Find the slot of the entry for the given key, or [LevelReadError::CorruptIndex] if one of
+    /// the probed slots points outside of the values file.
+    fn try_find_slot(
+        &self,
+        key: &LevelKeyT,
+    ) -> LevelResult<Option<(ValuesEntry<'_>, _LevelIdxT, _BucketIdxT, _SlotIdxT)>, LevelReadError>
+    {
+        let fhash = self.fhash(key);
+        let shash = self.shash(key);
+
+        if !self.io.bloom_may_contain(fhash, shash) {
+            self.probe_stats.record(false, 0);
+            return Ok(None);
+        }
+
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        let key_tag = LevelHashIO::key_tag(key);
+        let skip_free = self.probe_order == ProbeOrder::BucketLoad;
+
+        let mut levels = LEVELS;
+        let levels = &mut levels[..self.level_count() as usize];
+        match self.probe_order {
+            // scan the levels in which the buckets of the key have fewer occupied slots
+            // first, the upper level is scanned first if the counts are equal
+            ProbeOrder::BucketLoad => levels.sort_by_key(|level| {
+                [fhash, shash]
+                    .map(|hash| {
+                        let bucket = self.buck_idx_lvl(hash, *level);
+                        self.io.bucket_occupancy(*level as _LevelIdxT, bucket) as u32
+                    })
+                    .iter()
+                    .sum::<u32>()
+            }),
+            // scan the levels with more occupied slots first, the
+            // upper level is scanned first if the counts are equal
+            ProbeOrder::LevelLoad => {
+                levels.sort_by_key(|level| Reverse(self.occupancy().level(*level as _LevelIdxT)))
+            }
+        }
+
+        let mut probes = 0u64;
+        for &mut level in levels {
+            let fidx = self.buck_idx_lvl(fhash, level);
+            let sidx = self.buck_idx_lvl(shash, level);
+
+            for j in 0..bucket_size {
+                for buck in [fidx, sidx] {
+                    if skip_free && !self.io.is_occupied(level as _LevelIdxT, buck, j) {
+                        continue;
+                    }
+
+                    probes += 1;
+                    if let Some(e) = self.cmp_key_and_get_entry(level, buck, j, key, key_tag)? {
+                        self.probe_stats.record(true, probes);
+                        return Ok(Some((e, level as _LevelIdxT, buck, j)));
+                    }
+                }
+            }
+        }
+
+        if let Some(found) = self.find_in_stash(key, key_tag, &mut probes)? {
+            self.probe_stats.record(true, probes);
+            return Ok(Some(found));
+        }
+
+        self.probe_stats.record(false, probes);
+        Ok(None)
+    }
+
+    /// Get the number of slots in the stash, `0` if the level hash does not have a stash.
+    #[inline]
+    fn stash_slots(&self) -> _SlotIdxT {
+        self.io.meta.read().km_stash_slots
+    }
+
+    /// Find the slot of the entry for the given key in the stash, counting the probed slots in
+    /// `probes`.
+    fn find_in_stash(
+        &self,
+        key: &LevelKeyT,
+        key_tag: u16,
+        probes: &mut u64,
+    ) -> LevelResult<Option<(ValuesEntry<'_>, _LevelIdxT, _BucketIdxT, _SlotIdxT)>, LevelReadError>
+    {
+        for slot in 0..self.stash_slots() {
+            if !self.io.is_occupied(LevelHashIO::STASH_LEVEL, 0, slot) {
+                continue;
+            }
+
+            *probes += 1;
+            if let Some(e) = self.cmp_key_and_get_entry(Level::Stash, 0, slot, key, key_tag)? {
+                return Ok(Some((e, LevelHashIO::STASH_LEVEL, 0, slot)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Get the number of occupied slots in the stash.
+    fn stash_count(&self) -> u32 {
+        (0..self.stash_slots())
+            .filter(|slot| self.io.is_occupied(LevelHashIO::STASH_LEVEL, 0, *slot))
+            .count() as u32
+    }
+
+    /// Get the size (bytes) of the bloom filter for the current capacity of the level hash.
+    fn bloom_size(&self) -> OffT {
+        let slots = self.total_bucket_count() as u64 * self.bucket_size() as u64;
+        BloomFilter::size_for(slots)
+    }
+
+    /// Resize the bloom filter for the current capacity of the level hash and add the keys of all
+    /// the entries to it, dropping the keys of the removed entries. This does nothing if the level
+    /// hash does not have a bloom filter.
+    fn rebuild_bloom(&mut self) -> LevelResult<(), LevelMapError> {
+        if self.io.bloom.is_none() {
+            return Ok(());
+        }
+
+        let hashes = self
+            .keys()
+            .map(|key| (self.fhash(&key), self.shash(&key)))
+            .collect::<Vec<_>>();
+
+        let size = self.bloom_size();
+        let bloom = self.io.bloom.as_mut().unwrap();
+        bloom.reset(size)?;
+        for (fhash, shash) in hashes {
+            bloom.insert(fhash, shash);
+        }
+
+        Ok(())
+    }
+
+    fn insert_entry_at_slot(
+        &mut self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+        fail_on_dup: bool,
+    ) -> LevelInsertionResult {
+        let (slot_addr, val_addr) = self.io.slot_and_val_addr_at(level, bucket, slot)?;
+        if val_addr.is_none() {
+            // slot is empty
+            // append the value entry and return
+            return self
+                .io
+                .append_entry_at_slot(slot_addr, key, value, self.entry_expiry, None);
+        }
+
+        let val_addr = val_addr.unwrap();
+        let entry = ValuesEntry::at(val_addr - 1, &self.io.values);
+
+        if entry.is_empty() {
+            // slot is occupied, but the entry is empty
+            return self
+                .io
+                .append_entry_at_slot(slot_addr, key, value, self.entry_expiry, None);
+        }
+
+        // check for duplicate key
+        if fail_on_dup && self.io.entry_keyeq(&entry, key) {
+            return Err(LevelInsertionError::DuplicateKey);
+        }
+
+        return Err(LevelInsertionError::InsertionFailure(
+            InsertionDiagnostics::default(),
+        ));
+    }
+
+    /// Make room for the given key-value pair in one of its buckets (`fidx` and `sidx`) in the
+    /// given level by moving the existing entries to their alternative buckets in the same level.
+    ///
+    /// The buckets are searched breadth-first for a free slot, displacing up to
+    /// [LevelHashOptions::movement_depth] entries along the path to the free slot. Each bucket is
+    /// visited at most once.
+    fn try_movement(
+        &mut self,
+        level: Level,
+        fidx: _BucketIdxT,
+        sidx: _BucketIdxT,
+        bucket_size: _SlotIdxT,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+    ) -> LevelInsertionResult {
+        let mut nodes = vec![MovementNode::root(fidx)];
+        let mut visited = HashSet::from([fidx]);
+        if visited.insert(sidx) {
+            nodes.push(MovementNode::root(sidx));
+        }
+
+        let mut next = 0;
+        while next < nodes.len() {
+            let node = next;
+            next += 1;
+
+            let (bucket, depth) = (nodes[node].bucket, nodes[node].depth);
+            if depth > 0 {
+                if let Some(free_slot) = self.free_slot(level, bucket) {
+                    return self.move_along_path(level, &nodes, node, free_slot, key, value);
+                }
+            }
+
+            if depth == self.movement_depth {
+                continue;
+            }
+
+            for slot in 0..bucket_size {
+                let Some(this_key) = self
+                    .io
+                    .occupied_entry(level as _LevelIdxT, bucket, slot)
+                    .map(|e| self.io.entry_key(&e))
+                else {
+                    continue;
+                };
+
+                let this_fidx = self.buck_idx_lvl(self.fhash(&this_key), level);
+                let this_sidx = self.buck_idx_lvl(self.shash(&this_key), level);
+                let alt_bucket = if this_fidx == bucket {
+                    this_sidx
+                } else {
+                    this_fidx
+                };
+
+                if visited.insert(alt_bucket) {
+                    nodes.push(MovementNode {
+                        bucket: alt_bucket,
+                        depth: depth + 1,
+                        parent: Some((node, slot)),
+                    });
+                }
+            }
+        }
+
+        Err(LevelInsertionError::MovementFailure(
+            InsertionDiagnostics::default(),
+        ))
+    }
+
+    /// Get the index of the first free slot in the given bucket, if any. The free slot is found from
+    /// the bitmap of the occupied slots, without scanning the slots of the bucket.
+    fn free_slot(&self, level: Level, bucket: _BucketIdxT) -> Option<_SlotIdxT> {
+        self.io.first_free_slot(level as _LevelIdxT, bucket)
+    }
+
+    /// Get the first free slot in the given buckets of a key, if any. The slots with a lower index
+    /// are preferred, and the slot in `fidx` is preferred over the slot with the same index in
+    /// `sidx`, which is the order in which the slots of the buckets are probed.
+    fn first_free_slot(
+        &self,
+        level: Level,
+        fidx: _BucketIdxT,
+        sidx: _BucketIdxT,
+    ) -> Option<(_BucketIdxT, _SlotIdxT)> {
+        let first = self.free_slot(level, fidx).map(|slot| (fidx, slot));
+        let second = self.free_slot(level, sidx).map(|slot| (sidx, slot));
+        match (first, second) {
+            (Some(first), Some(second)) if second.1 < first.1 => Some(second),
+            (first, second) => first.or(second),
+        }
+    }
+
+    /// Move the entries on the path from the root of `nodes[node]` to the free slot in the bucket
+    /// of `nodes[node]`, and then insert the key-value pair in the slot freed in the root bucket.
+    /// The entries are moved starting from the end of the path, so that every entry remains
+    /// reachable while the entries are moved.
+    fn move_along_path(
+        &mut self,
+        level: Level,
+        nodes: &[MovementNode],
+        node: usize,
+        free_slot: _SlotIdxT,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+    ) -> LevelInsertionResult {
+        let (mut node, mut dest_slot) = (node, free_slot);
+        while let Some((parent, slot)) = nodes[node].parent {
+            self.io.move_slot(
+                level as _LevelIdxT,
+                nodes[parent].bucket,
+                slot,
+                nodes[node].bucket,
+                dest_slot,
+            );
+            (node, dest_slot) = (parent, slot);
+        }
+
+        self.insert_entry_at_slot(
+            level as _LevelIdxT,
+            nodes[node].bucket,
+            dest_slot,
+            key,
+            value,
+            false,
+        )?;
+        self.occupancy_mut().increment(level as _LevelIdxT);
+        Ok(())
+    }
+
+    /// Make room for the given key-value pair in the given bucket of a lower level by moving one of
+    /// the entries in the bucket to one of its buckets in the level above. Only the slot of the
+    /// moved entry is updated, so the entry keeps its expiry and its chain.
+    fn b2t_movement(
+        &mut self,
+        level: Level,
+        bucket: _BucketIdxT,
+        bucket_size: _SlotIdxT,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+    ) -> LevelInsertionResult {
+        let upper_level = LEVELS[level as usize - 1];
+        for i in 0..bucket_size {
+            let Some(bottom_entry_key) = self
+                .io
+                .occupied_entry(level as _LevelIdxT, bucket, i)
+                .map(|e| self.io.entry_key(&e))
+            else {
+                continue;
+            };
+
+            let fidx = self.buck_idx_lvl(self.fhash(&bottom_entry_key), upper_level);
+            let sidx = self.buck_idx_lvl(self.shash(&bottom_entry_key), upper_level);
+
+            let Some((dest_bucket, dest_slot)) = self.first_free_slot(upper_level, fidx, sidx)
+            else {
+                continue;
+            };
+
+            self.io.move_slot_to_level(
+                level as _LevelIdxT,
+                bucket,
+                i,
+                upper_level as _LevelIdxT,
+                dest_bucket,
+                dest_slot,
+            );
+            self.occupancy_mut().increment(upper_level as _LevelIdxT);
+
+            // the slot of the moved entry is reused for the new entry
+            // so the item count of the lower level does not change
+            self.insert_entry_at_slot(level as _LevelIdxT, bucket, i, key, value, false)?;
+            return Ok(());
+        }
+
+        Err(LevelInsertionError::MovementFailure(
+            InsertionDiagnostics::default(),
+        ))
+    }
+}
+
+impl LevelHash {
+    /// Get the value associated with the given key.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to get the value for.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the value if an entry is found, an empty [Vec] otherwise.
+    pub fn get_value(&self, key: &LevelKeyT) -> Vec<u8> {
+        return self.find_value(key).unwrap_or(vec![]);
+    }
+
+    /// Check whether the level hash contains an entry for the given key.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to look for.
+    ///
+    /// ## Returns
+    ///
+    /// `true` if an entry is found, `false` otherwise.
+    pub fn contains_key(&self, key: &LevelKeyT) -> bool {
+        let key = self.transform_key(key);
+        self.find_slot(&key)
+            .is_some_and(|(entry, ..)| !self.io.is_expired(&entry))
+    }
+
+    /// Check which of the given keys have an entry in the level hash, e.g. to find which of a set
+    /// of keys are already indexed. Like with [Self::contains_key], the keys missing from the
+    /// bloom filter (if any) are rejected without probing the keymap, the slots whose tag does not
+    /// match the tag of a key are skipped without reading the values file, and only the keys of the
+    /// matching entries are compared. The values of the entries are never read.
+    ///
+    /// ## Parameters
+    ///
+    /// * `keys` - The keys to look for.
+    ///
+    /// ## Returns
+    ///
+    /// A [BitVec] with one bit for each of the given keys, in the same order, which is set if an
+    /// entry is found for the key.
+    pub fn contains_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> BitVec {
+        let mut found = BitVec::new(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            if self.contains_key(key.as_ref()) {
+                found.set(i, true);
+            }
+        }
+        found
+    }
+
+    /// Get the value associated with the given key, verifying the checksum of the entry if the
+    /// level hash was created with [LevelHashOptions::checksums].
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to get the value for.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the value if an entry is found, `None` if there is no entry for the key
+    /// (or the entry has expired), [LevelReadError::CorruptEntry] if the entry has been corrupted,
+    /// [LevelReadError::CorruptIndex] if the index files have been corrupted, or
+    /// [LevelReadError::ConcurrentModification] if the keymap was changed structurally while the
+    /// value was being read, in which case the read can be retried.
+    pub fn try_get_value(&self, key: &LevelKeyT) -> LevelReadResult {
+        let key = self.transform_key(key);
+        self.read_consistent(|| {
+            self.try_find_slot(&key)?
+                .filter(|e| !self.io.is_expired(&e.0))
+                .map(|e| self.io.verified_entry_value(&e.0))
+                .transpose()
+        })
+    }
+
+    /// Write the value associated with the given key to the given writer, without reading the whole
+    /// value in memory if it is stored in the overflow file (see [LevelHashOptions::spill_threshold]).
+    /// The checksum of the entry is verified like with [Self::try_get_value]. The compressed values
+    /// are decompressed in memory before they are written.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to read the value for.
+    /// * `writer` - The writer to write the value to.
+    ///
+    /// ## Returns
+    ///
+    /// The size (bytes) of the value if an entry is found, `None` if there is no entry for the key
+    /// (or the entry has expired), [LevelReadError::IOError] if the value could not be read or
+    /// written, or the errors of [Self::try_get_value]. The writer may have received a part of the
+    /// value if an error is returned.
+    pub fn read_value_into(
+        &self,
+        key: &LevelKeyT,
+        writer: impl Write,
+    ) -> LevelResult<Option<u64>, LevelReadError> {
+        let key = self.transform_key(key);
+        self.read_consistent(|| {
+            let Some((entry, ..)) = self
+                .try_find_slot(&key)?
+                .filter(|e| !self.io.is_expired(&e.0))
+            else {
+                return Ok(None);
+            };
+
+            entry.verify_checksum(&self.io.values)?;
+            Ok(Some(self.io.write_entry_value(&entry, writer)?))
+        })
+    }
+
+    /// Get the values of all the entries associated with the given key. The keys have a single entry
+    /// unless the level hash was built with [LevelHashOptions::unique_keys] set to `false`. The
+    /// expired and the corrupt entries are skipped.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to get the values for.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the values, from the latest to the oldest entry.
+    pub fn get_all(&self, key: &LevelKeyT) -> Vec<Vec<u8>> {
+        let key = self.transform_key(key);
+        let Some((entry, ..)) = self.find_slot(&key) else {
+            return vec![];
+        };
+
+        self.io
+            .chain_entries(entry.addr + 1)
+            .into_iter()
+            .filter(|entry| !self.io.is_expired(entry))
+            .filter_map(|entry| match self.io.verified_entry_value(&entry) {
+                Ok(value) => Some(value),
+                Err(why) => {
+                    log_error!("{:?}", why);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Get the version of the entry associated with the given key, see
+    /// [LevelHashOptions::entry_versions]. The version changes whenever the value of the key is
+    /// inserted, updated or replaced, so the callers can cache the values they deserialized along
+    /// with their version, and check the version (without reading the value) to find out whether
+    /// the cached value is still current.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to get the version for.
+    ///
+    /// ## Returns
+    ///
+    /// The version of the entry, or `None` if there is no entry for the key (or the entry has
+    /// expired) or if the level hash was not created with [LevelHashOptions::entry_versions].
+    pub fn entry_version(&self, key: &LevelKeyT) -> Option<u64> {
+        let key = self.transform_key(key);
+        let version = self.read_consistent(|| {
+            Ok(self
+                .try_find_slot(&key)?
+                .filter(|e| !self.io.is_expired(&e.0))
+                .and_then(|e| e.0.version(&self.io.values)))
+        });
+
+        match version {
+            Ok(version) => version,
+            Err(why) => {
+                log_error!("{:?}", why);
+                None
+            }
+        }
+    }
+
+    /// Get the value associated with the given key, along with the version of its entry (see
+    /// [Self::entry_version]). The value and the version are read from the same entry.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to get the value for.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the value and the version of the entry (or `None` if the level hash was
+    /// not created with [LevelHashOptions::entry_versions]) if an entry is found, `None` otherwise.
+    pub fn get_with_version(&self, key: &LevelKeyT) -> Option<(Vec<u8>, Option<u64>)> {
+        let key = self.transform_key(key);
+        let found = self.read_consistent(|| {
+            self.try_find_slot(&key)?
+                .filter(|e| !self.io.is_expired(&e.0))
+                .map(|e| {
+                    let value = self.io.verified_entry_value(&e.0)?;
+                    Ok((value, e.0.version(&self.io.values)))
+                })
+                .transpose()
+        });
+
+        match found {
+            Ok(found) => found,
+            Err(why) => {
+                log_error!("{:?}", why);
+                None
+            }
+        }
+    }
+
+    /// Find the keys of the entries which have the given secondary key, see
+    /// [LevelHashOptions::secondary_index]. The keys are returned as they are stored, i.e. after
+    /// the [KeyTransform] of the level hash (if any) was applied, like with [Self::keys]. The keys
+    /// whose entries have all expired are skipped.
+    ///
+    /// ## Parameters
+    ///
+    /// * `secondary_key` - The secondary key to look for.
+    ///
+    /// ## Returns
+    ///
+    /// The keys of the matching entries in sorted order, or `None` if the level hash does not
+    /// have a secondary index.
+    #[cfg(feature = "secondary-index")]
+    pub fn find_keys_by_secondary(&self, secondary_key: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let secondary = self.io.secondary.as_ref()?;
+        let keys = secondary
+            .get(secondary_key)
+            .into_iter()
+            .filter(|key| !self.live_chain(key).is_empty())
+            .collect();
+        Some(keys)
+    }
+
+    /// Find the keys of the entries whose value is the given value. The level hash must have been
+    /// built with [ValueHash] as its [LevelHashOptions::secondary_index]. The values of the
+    /// candidate entries are compared with the given value, so the keys whose value only has the
+    /// same hash are not returned.
+    ///
+    /// ## Parameters
+    ///
+    /// * `value` - The value to look for.
+    ///
+    /// ## Returns
+    ///
+    /// The keys of the matching entries in sorted order, or `None` if the level hash does not
+    /// have a secondary index.
+    #[cfg(feature = "secondary-index")]
+    pub fn find_keys_by_value_hash(&self, value: &LevelValueT) -> Option<Vec<Vec<u8>>> {
+        let keys = self.find_keys_by_secondary(&ValueHash::secondary_key(value))?;
+        let keys = keys
+            .into_iter()
+            .filter(|key| {
+                self.live_chain(key)
+                    .iter()
+                    .any(|entry| self.io.entry_value(entry) == value)
+            })
+            .collect();
+        Some(keys)
+    }
+
+    /// Get the entries of the given (transformed) key which have not expired, from the latest to
+    /// the oldest entry.
+    #[cfg(feature = "secondary-index")]
+    fn live_chain(&self, key: &LevelKeyT) -> Vec<ValuesEntry<'_>> {
+        let Some((entry, ..)) = self.find_slot(key) else {
+            return vec![];
+        };
+
+        let mut entries = self.io.chain_entries(entry.addr + 1);
+        entries.retain(|entry| !self.io.is_expired(entry));
+        entries
+    }
+
+    /// Get the value associated with the given key, or `None` if there is no entry for the key or
+    /// the entry is corrupt.
+    fn find_value(&self, key: &LevelKeyT) -> Option<Vec<u8>> {
+        match self.try_get_value(key) {
+            Ok(value) => value,
+            Err(why) => {
+                log_error!("{:?}", why);
+                None
+            }
+        }
+    }
+
+    /// Iterate over the entries whose keys start with the given prefix. The entries are read
+    /// sequentially from the values file (skipping holes) instead of probing the hash slots, so
+    /// they are yielded in storage order.
+    ///
+    /// ## Parameters
+    ///
+    /// * `prefix` - The prefix of the keys to look for. An empty prefix matches all entries.
+    ///
+    /// ## Returns
+    ///
+    /// An iterator over the `(key, value)` pairs of the matching entries.
+    pub fn scan_prefix<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a {
+        let prefix = self.transform_key(prefix);
+        self.live_entries()
+            .filter(move |entry| self.io.entry_key_starts_with(entry, &prefix))
+            .map(|entry| (self.io.entry_key(&entry), self.io.entry_value(&entry)))
+    }
+
+    /// Iterate over the entries whose keys match the given predicate. Like [Self::scan_prefix],
+    /// the entries are read sequentially from the values file.
+    ///
+    /// ## Parameters
+    ///
+    /// * `filter` - The predicate which is tested against the key of each entry.
+    ///
+    /// ## Returns
+    ///
+    /// An iterator over the `(key, value)` pairs of the matching entries.
+    pub fn scan_filter<'a, F>(&'a self, filter: F) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a
+    where
+        F: Fn(&[u8]) -> bool + 'a,
+    {
+        self.live_entries().filter_map(move |entry| {
+            let key = self.io.entry_key(&entry);
+            if !filter(&key) {
+                return None;
+            }
+
+            let value = self.io.entry_value(&entry);
+            Some((key, value))
+        })
+    }
+
+    /// Iterate over the keys of all the entries, without reading their values. Like
+    /// [Self::scan_prefix], the entries are read sequentially from the values file.
+    ///
+    /// ## Returns
+    ///
+    /// An iterator over the keys of the entries.
+    pub fn keys(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.live_entries().map(|entry| self.io.entry_key(&entry))
+    }
+
+    /// Call the given function for each slot in the keymap, with the position of the slot and the
+    /// entry it points to (if any). The slots are visited level by level, starting from the top
+    /// level, and the slots of the stash (see [LevelHashOptions::stash_slots]) are visited last
+    /// with [Level::Stash]. This reads all the slots and is meant for the tools which inspect the layout of the
+    /// index (e.g. to visualize the occupancy of the buckets).
+    ///
+    /// The expired entries are passed like the other entries, as they occupy their slots until
+    /// they are purged. The slots which point outside of the values file are passed as empty.
+    pub fn for_each_slot<F>(&self, mut f: F)
+    where
+        F: FnMut(Level, _BucketIdxT, _SlotIdxT, Option<EntryRef<'_>>),
+    {
+        for pos in self.keymap_slots() {
+            let entry = self
+                .io
+                .occupied_entry(pos.level as _LevelIdxT, pos.bucket, pos.slot)
+                .map(|entry| EntryRef {
+                    io: &self.io,
+                    entry,
+                });
+            f(pos.level, pos.bucket, pos.slot, entry);
+        }
+    }
+
+    /// Split the buckets of the level hash into (at most) `n` disjoint ranges of consecutive
+    /// buckets, and get a [PartitionCursor] over the entries of each range, e.g. to scan a large
+    /// index in parallel. Like [Self::for_each_slot], the entries are read by probing the slots of
+    /// the keymap, and the slots of the stash (see [LevelHashOptions::stash_slots]) are scanned by
+    /// the last cursor. The layout of the keymap is read once, and the cursors do not read the meta
+    /// file or update the statistics of the level hash.
+    ///
+    /// ## Parameters
+    ///
+    /// * `n` - The number of partitions. There are fewer partitions if the level hash has fewer
+    ///   buckets, and `0` is treated as `1`.
+    ///
+    /// ## Returns
+    ///
+    /// The cursors, whose entries together are the entries of the level hash.
+    pub fn partitions(&self, n: usize) -> Vec<PartitionCursor<'_>> {
+        let layout = {
+            let meta = self.io.meta.read();
+            KeymapLayout {
+                levels: self
+                    .levels()
+                    .iter()
+                    .map(|&level| {
+                        (
+                            meta.km_level_addr(level as _LevelIdxT),
+                            self.level_bucket_count(level),
+                        )
+                    })
+                    .collect(),
+                bucket_bytes: meta.km_bucket_bytes(),
+                bucket_size: meta.km_bucket_size as u32,
+                stash_slots: self.stash_slots(),
+            }
+        };
+
+        let buckets = layout.bucket_count() as usize;
+        let n = n.clamp(1, buckets.max(1));
+        (0..n)
+            .map(|i| {
+                let start = (buckets * i / n) as u32;
+                let end = (buckets * (i + 1) / n) as u32;
+                PartitionCursor::new(&self.io, layout.clone(), start..end)
+            })
+            .collect()
+    }
+
+    /// Iterate over the positions of all the slots in the keymap, level by level starting from the
+    /// top level, followed by the slots of the stash.
+    fn keymap_slots(&self) -> impl Iterator<Item = SlotPos> + '_ {
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        let levels = self.levels().iter().flat_map(move |&level| {
+            (0..self.level_bucket_count(level)).flat_map(move |bucket| {
+                (0..bucket_size).map(move |slot| SlotPos {
+                    level,
+                    bucket,
+                    slot,
+                })
+            })
+        });
+        let stash = (0..self.stash_slots()).map(|slot| SlotPos {
+            level: Level::Stash,
+            bucket: 0,
+            slot,
+        });
+        levels.chain(stash)
+    }
+
+    /// Iterate over the entries in the values file which have not expired.
+    fn live_entries(&self) -> impl Iterator<Item = ValuesEntry<'_>> {
+        self.io
+            .live_values_iter()
+            .filter(|entry| !self.io.is_expired(entry))
+    }
+
+    /// Write the keys of all the entries to the given writer, without reading their values. Each
+    /// key is written as its size (`u32`, little endian) followed by the key bytes.
+    ///
+    /// ## Parameters
+    ///
+    /// * `writer` - The writer to write the keys to.
+    ///
+    /// ## Returns
+    ///
+    /// The number of keys that were written.
+    pub fn export_keys(&self, mut writer: impl Write) -> LevelResult<u64, StdIOError> {
+        let mut count = 0u64;
+        for key in self.keys() {
+            writer
+                .write_u32::<LittleEndian>(key.len() as u32)
+                .and_then(|_| writer.write_all(&key))
+                .into_lvl_io_e_msg("failed to export key".to_string())?;
+            count += 1;
+        }
+
+        writer
+            .flush()
+            .into_lvl_io_e_msg("failed to flush exported keys".to_string())?;
+
+        Ok(count)
+    }
+
+    /// Export all the entries to an archive at the given path, which is created or truncated. The
+    /// archive is independent of the byte order and the layout of the index files, so it can be
+    /// imported with [Self::import] on a device with a different architecture. See
+    /// [crate::archive] for the format of the archive.
+    ///
+    /// ## Parameters
+    ///
+    /// * `path` - The path of the archive file.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries that were exported.
+    pub fn export(&self, path: &Path) -> LevelResult<u64, StdIOError> {
+        self.do_export(path, None)
+    }
+
+    /// Export all the entries to an archive at the given path like [Self::export], checking the
+    /// given token before every entry is written.
+    ///
+    /// ## Parameters
+    ///
+    /// * `path` - The path of the archive file.
+    /// * `token` - The token which cancels the export.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries that were exported, or an error of kind [ErrorKind::Interrupted] if
+    /// the token was cancelled. The archive is incomplete in that case and cannot be imported.
+    pub fn export_cancellable(
+        &self,
+        path: &Path,
+        token: &CancellationToken,
+    ) -> LevelResult<u64, StdIOError> {
+        self.do_export(path, Some(token))
+    }
+
+    fn do_export(
+        &self,
+        path: &Path,
+        token: Option<&CancellationToken>,
+    ) -> LevelResult<u64, StdIOError> {
+        let mut writer = ArchiveWriter::create(path)?;
+        for entry in self.live_entries() {
+            if token.is_some_and(CancellationToken::is_cancelled) {
+                return Err(StdIOError::with_message(
+                    "the export was cancelled".to_string(),
+                    ErrorKind::Interrupted.into(),
+                ));
+            }
+
+            let key = self.io.entry_key(&entry);
+            let value = self.io.entry_value(&entry);
+            writer.write(&key, &value, entry.expires_at(&self.io.values))?;
+        }
+
+        writer.finish()
+    }
+
+    /// Import the entries from an archive created with [Self::export]. The existing entries for
+    /// the imported keys are replaced.
+    ///
+    /// ## Parameters
+    ///
+    /// * `path` - The path of the archive file.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries that were imported, or the error if the archive is invalid or an entry
+    /// could not be written. The entries read before the error are kept.
+    pub fn import(&mut self, path: &Path) -> LevelImportResult {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly.into());
+        }
+
+        let mut reader = ArchiveReader::open(path)?;
+        let mut count = 0u64;
+        while let Some(record) = reader.next_record()? {
+            if record.expires_at.is_some() && !cfg!(feature = "ttl") {
+                return Err(LevelArchiveError::InvalidArchive(
+                    "expiring entries require the 'ttl' feature".to_string(),
+                ));
+            }
+
+            // the entry is replaced instead of updated, so that it gets the expiry from the archive,
+            // while the entries of a key are chained again if the keys are not unique
+            if self.unique_keys {
+                self.remove(&record.key);
+            }
+            self.entry_expiry = record.expires_at;
+            let result = self.insert(&record.key, &record.value);
+            self.entry_expiry = None;
+
+            result.into_lvl_upd_err()?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Get the occupancy of the slots in the given bucket, as a bitmap in which bit `i % 8` of byte
+    /// `i / 8` is set if slot `i` is occupied. The occupancy of the slots is kept in memory, so
+    /// neither the keymap nor the values file is read.
+    ///
+    /// ## Parameters
+    ///
+    /// * `level` - The level of the bucket. The stash is a single bucket with
+    ///   [LevelHashOptions::stash_slots] slots.
+    /// * `bucket` - The index of the bucket in the level.
+    ///
+    /// ## Returns
+    ///
+    /// The bitmap of the slots of the bucket, or `None` if the level hash does not have the given
+    /// level or bucket.
+    pub fn bucket_occupancy(&self, level: Level, bucket: _BucketIdxT) -> Option<Vec<u8>> {
+        let exists = match level {
+            Level::Stash => bucket == 0 && self.stash_slots() > 0,
+            level => self.levels().contains(&level) && bucket < self.level_bucket_count(level),
+        };
+        exists.then(|| self.io.bucket_bitmap(level as _LevelIdxT, bucket))
+    }
+
+    /// Get the value at the given slot position.
+    ///
+    /// ## Parameters
+    ///
+    /// * `level` - The level index of the slot.
+    /// * `bucket` The bucket index of the slot.
+    /// * `slot` - The slot index of the slot.
+    ///
+    /// # Returns
+    ///
+    /// The raw bytes of the value if an entry is found and is occupied, an empty [Vec] otherwise.
+    pub fn get_value_at(&mut self, level: Level, bucket: _BucketIdxT, slot: _SlotIdxT) -> Vec<u8> {
+        return self.io.value(level as _LevelIdxT, bucket, slot);
+    }
+
+    /// Insert the given key-value pair in the level hash.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
+    /// * `value` - The value for the entry. This may be an empty slice in which case, [Self::get_value]
+    ///   for the given key will return `None`.
+    ///
+    /// ## Returns
+    ///
+    /// `true` if the value was inserted successfully, `false` otherwise. If the keys are unique,
+    /// the insertion fails with [LevelInsertionError::DuplicateKey] if an entry of the key exists.
+    pub fn insert(&mut self, key: &LevelKeyT, value: &LevelValueT) -> LevelInsertionResult {
+        let key: &LevelKeyT = &self.transform_key(key);
+        // the value of insert_from_reader is passed as an empty value
+        let value_size = self
+            .io
+            .streamed_value
+            .map_or(value.len(), |streamed| streamed.len as usize);
+        log_span!("insert", key_bytes = key.len(), value_bytes = value_size);
+        let result = self.do_insert(key, value);
+        if let Err(
+            error @ (LevelInsertionError::InsertionFailure(_)
+            | LevelInsertionError::LevelOverflow
+            | LevelInsertionError::MovementFailure(_)),
+        ) = &result
+        {
+            self.insertion_failures += 1;
+            self.notify(LevelEvent::InsertionFailed { error });
+        }
+
+        self.trace_op(TraceOp::Insert, Some(key), value_size, result.is_ok());
+        result
+    }
+
+    /// Insert an entry for the given key whose value of `len` bytes is read from the given reader.
+    /// The value is copied in chunks from the reader to the overflow file, so large values do not
+    /// need to be buffered in memory. The streamed values are never compressed.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
+    /// * `len` - The size (bytes) of the value. Exactly `len` bytes are read from the reader.
+    /// * `reader` - The reader to read the value from.
+    ///
+    /// ## Returns
+    ///
+    /// `Ok(())` if the value was inserted successfully, [LevelInsertionError::IOError] if the
+    /// reader failed or ended before `len` bytes were read, the error otherwise. The level hash is
+    /// not modified if the insertion fails.
+    pub fn insert_from_reader(
+        &mut self,
+        key: &LevelKeyT,
+        len: u64,
+        reader: impl Read,
+    ) -> LevelInsertionResult {
+        if self.io.is_read_only() {
+            return Err(LevelInsertionError::ReadOnly);
+        }
+
+        if len > self.max_value_size {
+            return Err(LevelInsertionError::ValueTooLarge {
+                size: len,
+                max: self.max_value_size,
+            });
+        }
+
+        let streamed = self
+            .io
+            .stream_value(&self.transform_key(key), len, reader)?;
+
+        // the appended entry takes the streamed value, which is left unreferenced if the entry
+        // could not be appended
+        self.io.streamed_value = Some(streamed);
+        let result = self.insert(key, &[]);
+        if let Some(unused) = self.io.streamed_value.take() {
+            self.io.release_streamed(unused);
+        }
+
+        result
+    }
+
+    /// Insert the given key-value pair in the level hash, which expires after the given duration.
+    /// The expired entries are not returned by the lookups, and are removed by
+    /// [Self::purge_expired] or when their key is inserted, updated or removed.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
+    /// * `value` - The value for the entry.
+    /// * `ttl` - The duration after which the entry expires. This is rounded up to whole seconds.
+    ///
+    /// ## Returns
+    ///
+    /// `Ok(())` if the value was inserted successfully, the error otherwise.
+    #[cfg(feature = "ttl")]
+    pub fn insert_with_ttl(
+        &mut self,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+        ttl: Duration,
+    ) -> LevelInsertionResult {
+        let ttl_secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+        self.entry_expiry = Some(unix_time_secs().saturating_add(ttl_secs));
+        let result = self.insert(key, value);
+        self.entry_expiry = None;
+        result
+    }
+
+    /// Remove all the entries which have expired. This scans all the slots in the level hash and
+    /// must be used carefully.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries which were removed.
+    #[cfg(feature = "ttl")]
+    pub fn purge_expired(&mut self) -> u64 {
+        if self.io.is_read_only() {
+            log_error!("cannot purge the expired entries of a read-only level hash");
+            return 0;
+        }
+
+        let purged = self.remove_entries_where(|io, entry| io.is_expired(entry));
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("purge_expired");
+
+        if let Err(why) = self.sync_after_write() {
+            log_error!("{}", why);
+        }
+
+        purged
+    }
+
+    /// Remove all the entries for which the given predicate returns `false`, e.g. to remove the
+    /// entries of the source files which have been deleted. This scans all the slots in the level
+    /// hash like [Self::purge_expired], but the regions of the removed entries are deallocated
+    /// together and the changes are synced once, instead of once for each removed entry like with
+    /// [Self::remove]. The expired entries are neither passed to the predicate nor removed.
+    ///
+    /// ## Parameters
+    ///
+    /// * `f` - The predicate, which is called with the key and the value of each entry. The keys
+    ///   are passed as they are stored, i.e. after the [KeyTransform] of the level hash (if any)
+    ///   was applied, like with [Self::keys].
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries which were removed.
+    pub fn retain<F>(&mut self, mut f: F) -> u64
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        if self.io.is_read_only() {
+            log_error!("cannot remove the entries of a read-only level hash");
+            return 0;
+        }
+
+        self.io.defer_deallocations();
+        let removed = self.remove_entries_where(|io, entry| {
+            !io.is_expired(entry) && !f(&io.entry_key(entry), &io.entry_value(entry))
+        });
+        self.io.flush_deallocations();
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("retain");
+
+        if let Err(why) = self.sync_after_write() {
+            log_error!("{}", why);
+        }
+
+        removed
+    }
+
+    /// Remove the entries in all the slots of the level hash for which the given function returns
+    /// `true`, returning the number of entries which were removed.
+    fn remove_entries_where<F>(&mut self, mut remove: F) -> u64
+    where
+        F: FnMut(&LevelHashIO, &ValuesEntry) -> bool,
+    {
+        let mut removed = 0u64;
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+
+        for &level in self.levels() {
+            for bucket in 0..self.level_bucket_count(level) {
+                for slot in 0..bucket_size {
+                    removed +=
+                        self.remove_entries_at(level as _LevelIdxT, bucket, slot, &mut remove);
+                }
+            }
+        }
+
+        for slot in 0..self.stash_slots() {
+            removed += self.remove_entries_at(LevelHashIO::STASH_LEVEL, 0, slot, &mut remove);
+        }
+
+        removed
+    }
+
+    /// Remove the entries in the chain of the entry at the given slot for which the given function
+    /// returns `true`, returning the number of entries which were removed.
+    fn remove_entries_at<F>(
+        &mut self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        remove: &mut F,
+    ) -> u64
+    where
+        F: FnMut(&LevelHashIO, &ValuesEntry) -> bool,
+    {
+        let Some(entry) = self.io.occupied_entry(level, bucket, slot) else {
+            return 0;
+        };
+
+        let chain = self
+            .io
+            .chain_entries(entry.addr + 1)
+            .into_iter()
+            .map(|entry| (entry.addr, remove(&self.io, &entry)))
+            .collect::<Vec<_>>();
+
+        let mut removed = 0u64;
+
+        // the latest entry which is kept, whose chain link skips the removed entries
+        let mut prev = None;
+        for (i, &(addr, matches)) in chain.iter().enumerate() {
+            if !matches {
+                prev = Some(addr);
+                continue;
+            }
+
+            let next = chain
+                .get(i + 1)
+                .map_or(LevelHashIO::POS_INVALID, |(addr, _)| addr + 1);
+            match prev {
+                Some(prev) => self.io.set_chain_next(prev, next),
+                None => {
+                    let slot_addr = self.io.slot_addr(level, bucket, slot);
+                    self.io.km_relink(slot_addr, next);
+                    if next == LevelHashIO::POS_INVALID {
+                        self.occupancy_mut().decrement(level);
+                    }
+                }
+            }
+
+            self.io.delete_at(addr + 1, None, false);
+            removed += 1;
+        }
+
+        removed
+    }
+
+    fn do_insert(&mut self, key: &LevelKeyT, value: &LevelValueT) -> LevelInsertionResult {
+        if self.io.is_read_only() {
+            return Err(LevelInsertionError::ReadOnly);
+        }
+
+        self.check_key(key)?;
+        self.check_value_size(value)?;
+        self.remove_expired(key);
+
+        // the existing entry of the key is looked up first, so that a unique key is rejected
+        // with DuplicateKey. If the keys are not unique, the new entry is appended to the slot of
+        // the latest entry of the key, which becomes the next entry in the chain of the new entry
+        let (existing, free_slot) = self.find_slot_or_free(key)?;
+        let existing = existing.map(|(entry, level, bucket, slot)| {
+            (self.io.slot_addr(level, bucket, slot), entry.addr + 1)
+        });
+        if self.unique_keys && existing.is_some() {
+            return Err(LevelInsertionError::DuplicateKey);
+        }
+
+        let result = match existing {
+            Some((slot_addr, next)) => {
+                self.io
+                    .append_entry_at_slot(slot_addr, key, value, self.entry_expiry, Some(next))
+            }
+            None => self.insert_at_free_slot(key, value, free_slot),
+        };
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("insert");
+
+        result?;
+        self.sync_after_write()?;
+        Ok(())
+    }
+
+    /// Insert an entry loaded by [Self::bulk_load], without syncing the changes.
+    fn bulk_insert(&mut self, key: &LevelKeyT, value: &LevelValueT) -> LevelInsertionResult {
+        if self.io.is_read_only() {
+            return Err(LevelInsertionError::ReadOnly);
+        }
+
+        self.check_key(key)?;
+        self.check_value_size(value)?;
+
+        // the index is empty before the load, so the key exists only if it was loaded before
+        let (existing, free_slot) = self.find_slot_or_free(key)?;
+        if let Some((entry, level, bucket, slot)) = existing {
+            let slot_addr = self.io.slot_addr(level, bucket, slot);
+            let chain_next = if self.unique_keys {
+                self.io.delete_at_slot(slot_addr, key, false);
+                None
+            } else {
+                Some(entry.addr + 1)
+            };
+
+            return self
+                .io
+                .append_entry_at_slot(slot_addr, key, value, None, chain_next);
+        }
+
+        self.insert_at_free_slot(key, value, free_slot)
+    }
+
+    /// Insert a new entry for the key with [Self::insert_entry], given the free slot in the
+    /// buckets of the key found with [Self::find_slot_or_free].
+    fn insert_at_free_slot(
+        &mut self,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+        free_slot: Option<(_LevelIdxT, _BucketIdxT, _SlotIdxT)>,
+    ) -> LevelInsertionResult {
+        self.insert_entry(key, value, free_slot)?;
+
+        if self.io.bloom.is_some() {
+            self.io.bloom_insert(self.fhash(key), self.shash(key));
+        }
+
+        Ok(())
+    }
+
+    /// Insert a new entry for the key. All the insertions decide here whether the level hash
+    /// must be expanded or overflows, so that they fail in the same state. The free slot, if
+    /// any, is used unless the level hash was expanded before the insertion.
+    fn insert_entry(
+        &mut self,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+        free_slot: Option<(_LevelIdxT, _BucketIdxT, _SlotIdxT)>,
+    ) -> LevelInsertionResult {
+        self.io.check_val_capacity()?;
+
+        let mut expansion_attempted =
+            self.consult_expand_policy(|policy, hash| policy.expand_before_insert(hash));
+        if expansion_attempted {
+            match self.rehash_step {
+                Some(_) => self.begin_expansion(),
+                None => self.do_expand(),
+            }
+            .into_lvl_ins_err()?;
+        }
+
+        // the bucket positions of the key change when the level hash expands
+        if let (Some((level, bucket, slot)), false) = (free_slot, expansion_attempted) {
+            let slot_addr = self.io.slot_addr(level, bucket, slot);
+            if Self::attempt(self.io.append_entry_at_slot(
+                slot_addr,
+                key,
+                value,
+                self.entry_expiry,
+                None,
+            ))? {
+                self.occupancy_mut().increment(level);
+                return Ok(());
+            }
+        }
+
+        let mut overflow = self.is_full();
+        if !overflow && self.try_insert_entry(key, value)? {
+            return Ok(());
+        }
+
+        // an incremental expansion in progress is completed before the insertion is retried
+        if self.is_expanding()
+            || self.consult_expand_policy(|policy, hash| policy.expand_on_failure(hash))
+        {
+            expansion_attempted = true;
+            self.do_expand().into_lvl_ins_err()?;
+
+            overflow = self.is_full();
+            if !overflow && self.try_insert_entry(key, value)? {
+                return Ok(());
+            }
+        }
+
+        if overflow {
+            return Err(LevelInsertionError::LevelOverflow);
+        }
+
+        Err(LevelInsertionError::InsertionFailure(
+            self.insertion_diagnostics(key, expansion_attempted),
+        ))
+    }
+
+    /// Whether all the slots in the levels and the stash are occupied.
+    fn is_full(&self) -> bool {
+        self.occupancy().total() >= self.total_slots() && self.stash_count() >= self.stash_slots()
+    }
+
+    /// Ask the [ExpandPolicy] of the level hash whether the level hash must be expanded.
+    fn consult_expand_policy<F>(&mut self, ask: F) -> bool
+    where
+        F: FnOnce(&mut dyn ExpandPolicy, &LevelHash) -> bool,
+    {
+        // the policy is taken out so that it can inspect the level hash, replacing it with a
+        // zero-sized policy does not allocate
+        let mut policy = mem::replace(&mut self.expand_policy, Box::new(NeverExpand));
+        let expand = ask(policy.as_mut(), self);
+        self.expand_policy = policy;
+        expand
+    }
+
+    /// Insert the entry in one of the free slots of its buckets, placing it in the stash or moving
+    /// the existing entries if both of the buckets are full.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the entry was inserted, or [LevelInsertionError::NoSpace] if the values file could
+    /// not grow for the entry.
+    fn try_insert_entry(
+        &mut self,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+    ) -> LevelResult<bool, LevelInsertionError> {
+        let fhash = self.fhash(key);
+        let shash = self.shash(key);
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+
+        // Check if there are any empty slots availale in any of the levels
+        // If there are, insert the key-value pair and return true
+        for &level in self.levels() {
+            let fidx = self.buck_idx_lvl(fhash, level);
+            let sidx = self.buck_idx_lvl(shash, level);
+            let Some((bucket, slot)) = self.first_free_slot(level, fidx, sidx) else {
+                continue;
+            };
+
+            if Self::attempt(self.insert_entry_at_slot(
+                level as _LevelIdxT,
+                bucket,
+                slot,
+                key,
+                value,
+                self.unique_keys,
+            ))? {
+                self.occupancy_mut().increment(level as _LevelIdxT);
+                return Ok(true);
+            }
+        }
+
+        // the stash is filled before the entries are moved
+        if let Some(slot) = (0..self.stash_slots())
+            .find(|slot| !self.io.is_occupied(LevelHashIO::STASH_LEVEL, 0, *slot))
+        {
+            if Self::attempt(self.insert_entry_at_slot(
+                LevelHashIO::STASH_LEVEL,
+                0,
+                slot,
+                key,
+                value,
+                false,
+            ))? {
+                return Ok(true);
+            }
+        }
+
+        for &level in self.levels() {
+            let fidx = self.buck_idx_lvl(fhash, level);
+            let sidx = self.buck_idx_lvl(shash, level);
+
+            let moved =
+                Self::attempt(self.try_movement(level, fidx, sidx, bucket_size, key, value))?;
+            self.notify(LevelEvent::Movement {
+                level,
+                kind: MovementKind::SameLevel,
+                moved,
+            });
+            if moved {
+                return Ok(true);
+            }
+        }
+
+        if self.expand_count > 0 {
+            for &level in &self.levels()[1..] {
+                let fidx = self.buck_idx_lvl(fhash, level);
+                let sidx = self.buck_idx_lvl(shash, level);
+
+                for bucket in [fidx, sidx] {
+                    let moved =
+                        Self::attempt(self.b2t_movement(level, bucket, bucket_size, key, value))?;
+                    self.notify(LevelEvent::Movement {
+                        level,
+                        kind: MovementKind::BottomToTop,
+                        moved,
+                    });
+                    if moved {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Get whether an attempt to insert an entry succeeded. The other attempts are made after a
+    /// failed attempt, unless the disk is full or the index files are corrupt, in which case they
+    /// would fail as well.
+    fn attempt(result: LevelInsertionResult) -> LevelResult<bool, LevelInsertionError> {
+        match result {
+            Ok(()) => Ok(true),
+            Err(LevelInsertionError::NoSpace(why)) => Err(LevelInsertionError::NoSpace(why)),
+            Err(LevelInsertionError::ReadError(why @ LevelReadError::CorruptIndex { .. })) => {
+                Err(LevelInsertionError::ReadError(why))
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Collect the [InsertionDiagnostics] for the given key, which could not be inserted.
+    fn insertion_diagnostics(
+        &self,
+        key: &LevelKeyT,
+        expansion_attempted: bool,
+    ) -> InsertionDiagnostics {
+        let fhash = self.fhash(key);
+        let shash = self.shash(key);
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+
+        let mut candidate_buckets = Vec::new();
+        for &level in self.levels() {
+            for bucket in [
+                self.buck_idx_lvl(fhash, level),
+                self.buck_idx_lvl(shash, level),
+            ] {
+                let free = (0..bucket_size)
+                    .filter(|slot| {
+                        self.io
+                            .occupied_entry(level as _LevelIdxT, bucket, *slot)
+                            .is_none()
+                    })
+                    .count() as u32;
+
+                candidate_buckets.push(CandidateBucket {
+                    level,
+                    bucket,
+                    occupied: bucket_size - free,
+                    slots: bucket_size,
+                });
+            }
+        }
+
+        InsertionDiagnostics {
+            candidate_buckets,
+            load_factor: self.load_factor(),
+            expand_count: self.expand_count,
+            expansion_attempted,
+        }
+    }
+
+    /// Remove the entry associated with the given key. If the keys are not unique, only the latest
+    /// entry of the key is removed.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry to remove.
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the raw bytes of the value of the deleted entry (if found and is occupied), `None` otherwise.
+    pub fn remove(&mut self, key: &LevelKeyT) -> Option<Vec<u8>> {
+        let key: &LevelKeyT = &self.transform_key(key);
+        let value = self.do_remove(key);
+        self.trace_op(TraceOp::Remove, Some(key), 0, value.is_some());
+        value
+    }
+
+    /// Remove all the entries associated with the given key.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entries to remove.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the values of the deleted entries which had not expired, from the latest to
+    /// the oldest entry.
+    pub fn remove_all(&mut self, key: &LevelKeyT) -> Vec<Vec<u8>> {
+        let key: &LevelKeyT = &self.transform_key(key);
+        let values = self.do_remove_all(key);
+        self.trace_op(TraceOp::RemoveAll, Some(key), 0, !values.is_empty());
+        values
+    }
+
+    fn do_remove_all(&mut self, key: &LevelKeyT) -> Vec<Vec<u8>> {
+        if self.io.is_read_only() {
+            log_error!("cannot remove the entries from a read-only level hash");
+            return vec![];
+        }
+
+        // the entries of a key which were inserted without chaining occupy separate slots
+        let mut values = Vec::new();
+        while let Some((_, level, bucket, slot)) = self.find_slot(key) {
+            let slot_addr = self.io.slot_addr(level, bucket, slot);
+            values.extend(self.io.delete_chain_at_slot(slot_addr));
+            self.occupancy_mut().decrement(level);
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("remove_all");
+
+        if let Err(why) = self.sync_after_write() {
+            log_error!("{}", why);
+        }
+
+        values
+    }
+
+    fn do_remove(&mut self, key: &LevelKeyT) -> Option<Vec<u8>> {
+        self.do_remove_if(key, |_, _| true)
+    }
+
+    /// Remove the latest entry of the given key if it matches the given predicate.
+    fn do_remove_if<P>(&mut self, key: &LevelKeyT, predicate: P) -> Option<Vec<u8>>
+    where
+        P: FnOnce(&LevelHashIO, &ValuesEntry) -> bool,
+    {
+        if self.io.is_read_only() {
+            log_error!("cannot remove an entry from a read-only level hash");
+            return None;
+        }
+
+        if let Some((entry, level, bucket, slot)) = self.find_slot(key) {
+            if !predicate(&self.io, &entry) {
+                return None;
+            }
+
+            let expired = self.io.is_expired(&entry);
+            let chained = entry.chain_next(&self.io.values).is_some();
+            let slot_addr = self.io.slot_addr(level, bucket, slot);
+            let value = self.io.delete_at_slot(slot_addr, key, true);
+            if !chained {
+                // the slot is still occupied by the next entry in the chain otherwise
+                self.occupancy_mut().decrement(level);
+            }
+
+            #[cfg(feature = "debug-invariants")]
+            self.assert_invariants("remove");
+
+            if let Err(why) = self.sync_after_write() {
+                log_error!("{}", why);
+            }
+
+            // the expired entry is removed, but it is reported as not found
+            return value.filter(|_| !expired);
+        }
+
+        None
+    }
+
+    /// Remove the entry associated with the given key if its value matches the given predicate.
+    /// The value is checked and the entry is removed with a single lookup of the slot of the
+    /// entry, so there is no window between reading and removing the entry like with
+    /// [Self::get_value] followed by [Self::remove]. If the keys are not unique, only the latest
+    /// entry of the key is checked.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry to remove.
+    /// * `predicate` - The function which is called with the value of the entry, and returns
+    ///   whether the entry must be removed.
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the raw bytes of the value of the deleted entry (if found and removed),
+    /// `None` otherwise.
+    pub fn remove_if<P>(&mut self, key: &LevelKeyT, predicate: P) -> Option<Vec<u8>>
+    where
+        P: FnOnce(&[u8]) -> bool,
+    {
+        let key: &LevelKeyT = &self.transform_key(key);
+
+        // the expired entry is removed regardless of the predicate, and reported as not found
+        let value = self.do_remove_if(key, |io, entry| {
+            io.is_expired(entry) || predicate(&io.entry_value(entry))
+        });
+        self.trace_op(TraceOp::Remove, Some(key), 0, value.is_some());
+        value
+    }
+
+    /// Remove the entry for the given key if it has expired, so that the expired entry does not
+    /// prevent the key from being inserted again.
+    fn remove_expired(&mut self, key: &LevelKeyT) {
+        if self.io.meta.read().val_expiry == 0 {
+            // none of the entries expire
+            return;
+        }
+
+        // the next entry in the chain of the removed entry may have expired too
+        while self
+            .find_slot(key)
+            .is_some_and(|(entry, ..)| self.io.is_expired(&entry))
+        {
+            self.do_remove(key);
+        }
+    }
+
+    /// Update the entry associated with the given key with the new value.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to update the value for.
+    /// * `new_value` - The new value for the entry.
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the raw bytes of the previous value of the entry (if found and is occupied), `None` otherwise.
+    pub fn update(&mut self, key: &LevelKeyT, new_value: &LevelValueT) -> LevelUpdateResult {
+        let key: &LevelKeyT = &self.transform_key(key);
+        let result = self.do_update(key, new_value);
+        self.trace_op(TraceOp::Update, Some(key), new_value.len(), result.is_ok());
+        result
+    }
+
+    fn do_update(&mut self, key: &LevelKeyT, new_value: &LevelValueT) -> LevelUpdateResult {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly);
+        }
+
+        self.check_value_size(new_value).into_lvl_upd_err()?;
+
+        let slot = self.try_find_slot(key)?;
+
+        if slot.is_none() {
+            return Err(LevelUpdateError::SlotNotFound);
+        }
+
+        let (entry, level, bucket, slot) = slot.unwrap();
+        if self.io.is_expired(&entry) {
+            self.do_remove(key);
+            return Err(LevelUpdateError::SlotNotFound);
+        }
+
+        let result = self.io.update_entry_value(level, bucket, slot, new_value);
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("update");
+
+        let value = result?;
+        self.sync_after_write()
+            .map_err(LevelInsertionError::from)
+            .into_lvl_upd_err()?;
+
+        Ok(value)
+    }
+
+    /// Update the entry associated with the given key with the new value, if its value is equal
+    /// to the expected value. The value is compared and updated with a single lookup of the slot
+    /// of the entry, so there is no window between reading and updating the value like with
+    /// [Self::get_value] followed by [Self::update].
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to update the value for.
+    /// * `expected` - The expected value of the entry.
+    /// * `new_value` - The new value for the entry.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the previous value of the entry, [LevelUpdateError::ValueMismatch] with the
+    /// current value of the entry if it is not equal to `expected`, or
+    /// [LevelUpdateError::SlotNotFound] if there is no entry for the key.
+    pub fn compare_and_update(
+        &mut self,
+        key: &LevelKeyT,
+        expected: &LevelValueT,
+        new_value: &LevelValueT,
+    ) -> LevelUpdateResult {
+        let key: &LevelKeyT = &self.transform_key(key);
+        let result = self.do_compare_and_update(key, expected, new_value);
+        self.trace_op(TraceOp::Update, Some(key), new_value.len(), result.is_ok());
+        result
+    }
+
+    fn do_compare_and_update(
+        &mut self,
+        key: &LevelKeyT,
+        expected: &LevelValueT,
+        new_value: &LevelValueT,
+    ) -> LevelUpdateResult {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly);
+        }
+
+        self.check_value_size(new_value).into_lvl_upd_err()?;
+
+        let Some((entry, level, bucket, slot)) = self.try_find_slot(key)? else {
+            return Err(LevelUpdateError::SlotNotFound);
+        };
+
+        if self.io.is_expired(&entry) {
+            self.do_remove(key);
+            return Err(LevelUpdateError::SlotNotFound);
+        }
+
+        let current = self.io.verified_entry_value(&entry)?;
+        if current != expected {
+            return Err(LevelUpdateError::ValueMismatch(current));
+        }
+
+        let result = self.io.update_entry_value(level, bucket, slot, new_value);
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("compare_and_update");
+
+        let value = result?;
+        self.sync_after_write()
+            .map_err(LevelInsertionError::from)
+            .into_lvl_upd_err()?;
+
+        Ok(value)
+    }
+
+    /// Mutate the value of the entry associated with the given key in-place, e.g. to increment a
+    /// counter or to update a field of a fixed-size record. The size of the value cannot be
+    /// changed. Unlike [Self::update], which appends a new entry and deletes the current entry,
+    /// the bytes of the value are mutated directly in the values file. The values which have been
+    /// spilled to the overflow file or compressed, and the values of the pinned entries (see
+    /// [Self::pin]) are updated like with [Self::update] instead.
+    ///
+    /// An in-place mutation is not atomic : a crash while the value is being mutated may leave the
+    /// value partially mutated, which is detected by the checksum of the entry if the level hash
+    /// was created with [LevelHashOptions::checksums].
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to update the value for.
+    /// * `f` - The function which mutates the bytes of the value.
+    ///
+    /// ## Returns
+    ///
+    /// `Ok(())` if the value was updated, or [LevelUpdateError::SlotNotFound] if there is no entry
+    /// for the key.
+    pub fn update_in_place<F>(&mut self, key: &LevelKeyT, f: F) -> LevelResult<(), LevelUpdateError>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let key: &LevelKeyT = &self.transform_key(key);
+        let result = self.do_update_in_place(key, f);
+        let value_size = *result.as_ref().unwrap_or(&0) as usize;
+        self.trace_op(TraceOp::Update, Some(key), value_size, result.is_ok());
+        result.map(|_| ())
+    }
+
+    fn do_update_in_place<F>(&mut self, key: &LevelKeyT, f: F) -> LevelResult<u64, LevelUpdateError>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly);
+        }
+
+        let Some((entry, level, bucket, slot)) = self.try_find_slot(key)? else {
+            return Err(LevelUpdateError::SlotNotFound);
+        };
+
+        if self.io.is_expired(&entry) {
+            self.do_remove(key);
+            return Err(LevelUpdateError::SlotNotFound);
+        }
+
+        let result = self.io.mutate_entry_value(level, bucket, slot, f);
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("update_in_place");
+
+        let value_size = result?;
+        self.sync_after_write()
+            .map_err(LevelInsertionError::from)
+            .into_lvl_upd_err()?;
+
+        Ok(value_size)
+    }
+
+    /// Begin a batch of write operations, which are applied together when the batch is committed.
+    /// See [WriteBatch].
+    pub fn begin_batch(&mut self) -> WriteBatch<'_> {
+        WriteBatch::new(self)
+    }
+
+    /// Apply the operations of a [WriteBatch], rolling back the applied operations if one of them
+    /// fails.
+    pub(crate) fn apply_batch(&mut self, ops: Vec<BatchOp>) -> LevelBatchResult {
+        if self.io.is_read_only() {
+            return Err(LevelBatchError::ReadOnly);
+        }
+
+        log_span!("batch", ops = ops.len());
+
+        // the operations which revert the applied operations, in the order they were applied
+        let mut undo = Vec::with_capacity(ops.len());
+        let mut result = Ok(());
+
+        self.in_batch = true;
+        for (index, op) in ops.into_iter().enumerate() {
+            match self.apply_batch_op(index, op) {
+                Ok(op) => undo.extend(op.map(|op| (index, op))),
+                Err(why) => {
+                    result = Err(why);
+                    break;
+                }
+            }
+        }
+
+        if result.is_err() {
+            for (index, op) in undo.into_iter().rev() {
+                if let Err(why) = self.apply_batch_op(index, op) {
+                    log_error!("failed to roll back batch: {:?}", why);
+                }
+            }
+        }
+        self.in_batch = false;
+
+        result?;
+        self.sync_after_write()?;
+        Ok(())
+    }
+
+    /// Apply the operation at the given index of a [WriteBatch], returning the operation which
+    /// reverts it (if any).
+    fn apply_batch_op(
+        &mut self,
+        index: usize,
+        op: BatchOp,
+    ) -> LevelResult<Option<BatchOp>, LevelBatchError> {
+        match op {
+            BatchOp::Insert {
+                key,
+                value,
+                expires_at,
+            } => {
+                self.entry_expiry = expires_at;
+                let result = self.insert(&key, &value);
+                self.entry_expiry = None;
+                result
+                    .map(|_| Some(BatchOp::Remove { key }))
+                    .map_err(|error| LevelBatchError::InsertionError { index, error })
+            }
+            BatchOp::Update { key, value } => self
+                .update(&key, &value)
+                .map(|value| Some(BatchOp::Update { key, value }))
+                .map_err(|error| LevelBatchError::UpdateError { index, error }),
+            BatchOp::Remove { key } => {
+                // the entry is inserted again with its expiry if the batch is rolled back
+                let expires_at = self
+                    .find_slot(&self.transform_key(&key))
+                    .and_then(|(entry, ..)| entry.expires_at(&self.io.values));
+                Ok(self.remove(&key).map(|value| BatchOp::Insert {
+                    key,
+                    value,
+                    expires_at,
+                }))
+            }
+        }
+    }
+
+    /// Merge the given operand into the value of the entry for the given key, inserting a new entry
+    /// if there is none. The existing value is read, merged and written back with a single lookup
+    /// of the slot of the entry, so there is no window between reading and updating the value like
+    /// with [Self::get_value] followed by [Self::update].
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
+    /// * `operand` - The operand which is passed to `merge_fn`.
+    /// * `merge_fn` - The function which computes the new value from the existing value (`None` if
+    ///   there is no entry for the key) and the operand.
+    ///
+    /// ## Returns
+    ///
+    /// The new value of the entry, or the error if the value could not be written.
+    pub fn insert_or_merge<F>(
+        &mut self,
+        key: &LevelKeyT,
+        operand: &[u8],
+        merge_fn: F,
+    ) -> LevelResult<Vec<u8>, LevelUpdateError>
+    where
+        F: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8>,
+    {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly);
+        }
+
+        let key: &LevelKeyT = &self.transform_key(key);
+
+        // the expired entries are removed by insert()
+        let found = self
+            .try_find_slot(key)?
+            .filter(|(entry, ..)| !self.io.is_expired(entry))
+            .map(|(entry, level, bucket, slot)| (self.io.entry_value(&entry), level, bucket, slot));
+
+        let Some((existing, level, bucket, slot)) = found else {
+            let merged = merge_fn(None, operand);
+            self.insert(key, &merged).into_lvl_upd_err()?;
+            return Ok(merged);
+        };
+
+        let merged = merge_fn(Some(&existing), operand);
+        let result = self.merge_entry(level, bucket, slot, &merged);
+        self.trace_op(TraceOp::Update, Some(key), merged.len(), result.is_ok());
+
+        result?;
+        Ok(merged)
+    }
+
+    /// Get the value of the entry for the given key, or insert the value computed by `value_fn` if
+    /// there is no entry for the key. The slots of the key are traversed once, looking for the
+    /// entry of the key and a free slot for the new entry at the same time, so there is no window
+    /// between the lookup and the insertion like with [Self::get_value] followed by
+    /// [Self::insert]. The regular insertion (which may move the existing entries or expand the
+    /// level hash) is used only if the buckets of the key are full in all the levels.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
+    /// * `value_fn` - The function which computes the value of the new entry. It is called only if
+    ///   there is no entry for the key.
+    ///
+    /// ## Returns
+    ///
+    /// The value of the existing entry, or the value of the inserted entry.
+    pub fn get_or_insert_with<F>(
+        &mut self,
+        key: &LevelKeyT,
+        value_fn: F,
+    ) -> LevelResult<Vec<u8>, LevelInsertionError>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        if self.io.is_read_only() {
+            return Err(LevelInsertionError::ReadOnly);
+        }
+
+        let key: &LevelKeyT = &self.transform_key(key);
+        self.check_key(key)?;
+
+        self.remove_expired(key);
+        let (existing, free_slot) = self.find_slot_or_free(key)?;
+        if let Some((entry, ..)) = existing {
+            return Ok(self.io.verified_entry_value(&entry)?);
+        }
+
+        let value = value_fn();
+        let result = self
+            .check_value_size(&value)
+            .and_then(|_| self.insert_at_free_slot(key, &value, free_slot));
+        if let Err(
+            error @ (LevelInsertionError::InsertionFailure(_)
+            | LevelInsertionError::LevelOverflow
+            | LevelInsertionError::MovementFailure(_)),
+        ) = &result
+        {
+            self.insertion_failures += 1;
+            self.notify(LevelEvent::InsertionFailed { error });
+        }
+        self.trace_op(TraceOp::Insert, Some(key), value.len(), result.is_ok());
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("get_or_insert_with");
+
+        result?;
+        self.sync_after_write()?;
+        Ok(value)
+    }
+
+    /// Insert an entry for the given key, or replace the value of the existing entry of the key.
+    /// The slots of the key are traversed once, looking for the entry of the key and a free slot
+    /// for the new entry at the same time, so the existing entry is replaced in its slot instead of
+    /// failing with [LevelInsertionError::DuplicateKey] and looking up the slot again with
+    /// [Self::update]. If the keys are not unique, the latest entry of the key is replaced and the
+    /// older entries of the key are kept.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key for the entry. This must be a non-empty slice of bytes.
+    /// * `value` - The value for the entry.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the previous value of the entry if the entry was replaced, `None` if a new
+    /// entry was inserted.
+    pub fn insert_replace(
+        &mut self,
+        key: &LevelKeyT,
+        value: &LevelValueT,
+    ) -> LevelResult<Option<Vec<u8>>, LevelUpdateError> {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly);
+        }
+
+        let key: &LevelKeyT = &self.transform_key(key);
+        self.check_key(key).into_lvl_upd_err()?;
+
+        self.check_value_size(value).into_lvl_upd_err()?;
+        self.remove_expired(key);
+
+        let (existing, free_slot) = self.find_slot_or_free(key)?;
+        if let Some((_, level, bucket, slot)) = existing {
+            let result = self.io.update_entry_value(level, bucket, slot, value);
+            self.trace_op(TraceOp::Update, Some(key), value.len(), result.is_ok());
+
+            #[cfg(feature = "debug-invariants")]
+            self.assert_invariants("insert_replace");
+
+            let previous = result?;
+            self.sync_after_write()
+                .map_err(LevelInsertionError::from)
+                .into_lvl_upd_err()?;
+            return Ok(Some(previous));
+        }
+
+        let result = self.insert_at_free_slot(key, value, free_slot);
+        if let Err(
+            error @ (LevelInsertionError::InsertionFailure(_)
+            | LevelInsertionError::LevelOverflow
+            | LevelInsertionError::MovementFailure(_)),
+        ) = &result
+        {
+            self.insertion_failures += 1;
+            self.notify(LevelEvent::InsertionFailed { error });
+        }
+        self.trace_op(TraceOp::Insert, Some(key), value.len(), result.is_ok());
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("insert_replace");
+
+        result.into_lvl_upd_err()?;
+        self.sync_after_write()
+            .map_err(LevelInsertionError::from)
+            .into_lvl_upd_err()?;
+        Ok(None)
+    }
+
+    fn merge_entry(
+        &mut self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        merged: &LevelValueT,
+    ) -> LevelResult<(), LevelUpdateError> {
+        self.check_value_size(merged).into_lvl_upd_err()?;
+
+        let result = self.io.update_entry_value(level, bucket, slot, merged);
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("insert_or_merge");
+
+        result?;
+        self.sync_after_write()
+            .map_err(LevelInsertionError::from)
+            .into_lvl_upd_err()
+    }
+
+    /// Get the capacity of the level hash and the limits of its index files. Unlike [Self::stats],
+    /// this does not scan the slots.
+    pub fn capacity_info(&self) -> CapacityInfo {
+        let meta = self.io.meta.read();
+        CapacityInfo {
+            entries: self.occupancy().total() + self.stash_count() as u64,
+            slots: self.total_slots(),
+            max_slots: self.level_slots(LEVEL_SIZE_MAX),
+            values_size: meta.val_next_addr - 1,
+            max_values_size: self.io.val_addr_max(),
+            max_key_size: self.max_key_size,
+            max_value_size: self.max_value_size,
+        }
+    }
+
+    /// Get the statistics about the keys which have multiple live entries in the level hash. This
+    /// scans all the slots in the level hash and must be used carefully.
+    pub fn duplicate_stats(&self) -> DuplicateStats {
+        let mut stats = DuplicateStats::default();
+        for entries in self.find_duplicates().values() {
+            stats.duplicate_keys += 1;
+            stats.redundant_entries += entries.len() as u64 - 1;
+        }
+
+        stats
+    }
+
+    /// Get the statistics about the occupancy of the slots, the values file and the key lookups of
+    /// the level hash. This scans all the slots in the level hash and must be used carefully.
+    pub fn stats(&self) -> LevelStats {
+        let meta = self.io.meta.read();
+        let bucket_size = meta.km_bucket_size as _SlotIdxT;
+
+        let mut level_load_factors = [0f32; LEVEL_COUNT_MAX as usize];
+        let mut occupancy_histogram = vec![0u64; bucket_size as usize + 1];
+        let mut occupied = 0u64;
+        let mut live_bytes = 0u64;
+        let mut total_slots = 0u64;
+
+        for &level in self.levels() {
+            let buckets = self.level_bucket_count(level);
+            let mut level_occupied = 0u64;
+
+            for bucket in 0..buckets {
+                let mut bucket_occupied = 0usize;
+                for slot in 0..bucket_size {
+                    let Some(entry) = self.io.occupied_entry(level as _LevelIdxT, bucket, slot)
+                    else {
+                        continue;
+                    };
+
+                    bucket_occupied += 1;
+                    live_bytes += self.chain_size(&entry);
+                }
+
+                occupancy_histogram[bucket_occupied] += 1;
+                level_occupied += bucket_occupied as u64;
+            }
+
+            let level_slots = buckets as u64 * bucket_size as u64;
+            level_load_factors[level as usize] = level_occupied as f32 / level_slots as f32;
+            occupied += level_occupied;
+            total_slots += level_slots;
+        }
+
+        live_bytes += self.stash_live_bytes();
+
+        LevelStats {
+            load_factor: occupied as f32 / total_slots as f32,
+            level_load_factors,
+            occupancy_histogram,
+            values_file_size: meta.val_file_size,
+            dead_bytes: (meta.val_next_addr - 1).saturating_sub(live_bytes),
+            free_bytes: self.io.free_list.free_bytes(),
+            expand_count: self.expand_count,
+            insertion_failures: self.insertion_failures,
+            hit_probe_histogram: ProbeStats::histogram(&self.probe_stats.hits),
+            miss_probe_histogram: ProbeStats::histogram(&self.probe_stats.misses),
+            writes: self.write_stats(),
+        }
+    }
+
+    /// Get the writes made to the index files since the level hash was opened. These are also
+    /// reported by [Self::stats], which is much slower as it walks all the slots.
+    pub fn write_stats(&self) -> WriteStats {
+        let values = self.io.values.counters;
+        let keymap = self.io.keymap.counters;
+        WriteStats {
+            values_bytes: values.bytes,
+            keymap_bytes: keymap.bytes,
+            meta_bytes: self.meta_flushes * MetaIO::META__SIZE_BYTES,
+            remaps: values.remaps + keymap.remaps,
+            hole_punches: values.punches + keymap.punches,
+        }
+    }
+
+    /// Get the size (bytes) of the entries in the stash and their chains in the values file.
+    fn stash_live_bytes(&self) -> u64 {
+        (0..self.stash_slots())
+            .filter_map(|slot| self.io.occupied_entry(LevelHashIO::STASH_LEVEL, 0, slot))
+            .map(|entry| self.chain_size(&entry))
+            .sum()
+    }
+
+    /// Get the size (bytes) of the given entry and the entries in its chain in the values file.
+    fn chain_size(&self, entry: &ValuesEntry) -> u64 {
+        self.io
+            .chain_entries(entry.addr + 1)
+            .iter()
+            .map(|entry| align_8(entry.esize()))
+            .sum()
+    }
+
+    /// Get a report about the health of the level hash, along with the recommended maintenance
+    /// action. This scans all the slots in the level hash and must be used carefully.
+    ///
+    /// The action is chosen in the order of priority : [HealthAction::Rebuild] if there are
+    /// misplaced entries or duplicate keys in a level hash with unique keys, [HealthAction::Expand]
+    /// if the load factor has reached the threshold of the [ExpandPolicy] (or
+    /// [LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT]) or insertions have failed,
+    /// [HealthAction::Compact] if more than half of the values file is unused, and
+    /// [HealthAction::None] otherwise.
+    pub fn health(&self) -> LevelHealth {
+        let meta = self.io.meta.read();
+        let bucket_size = meta.km_bucket_size as _SlotIdxT;
+
+        let mut probe_histogram = [0u64; 2 * LEVEL_COUNT_MAX as usize];
+        let mut misplaced_entries = 0u64;
+        let mut occupied = 0u64;
+        let mut live_bytes = 0u64;
+        let mut total_slots = 0u64;
+
+        for &level in self.levels() {
+            let buckets = self.level_bucket_count(level);
+            total_slots += buckets as u64 * bucket_size as u64;
+
+            for bucket in 0..buckets {
+                for slot in 0..bucket_size {
+                    let Some(entry) = self.io.occupied_entry(level as _LevelIdxT, bucket, slot)
+                    else {
+                        continue;
+                    };
+
+                    occupied += 1;
+                    live_bytes += self.chain_size(&entry);
+
+                    let key = self.io.entry_key(&entry);
+                    let probe = if bucket == self.buck_idx_lvl(self.fhash(&key), level) {
+                        0
+                    } else if bucket == self.buck_idx_lvl(self.shash(&key), level) {
+                        1
+                    } else {
+                        misplaced_entries += 1;
+                        continue;
+                    };
+
+                    probe_histogram[level as usize * 2 + probe] += 1;
+                }
+            }
+        }
+
+        live_bytes += self.stash_live_bytes();
+
+        let duplicate_keys = self.duplicate_stats().duplicate_keys;
+        let load_factor = occupied as f32 / total_slots as f32;
+        let used_bytes = meta.val_next_addr - 1;
+        let fragmentation = if used_bytes == 0 {
+            0f32
+        } else {
+            1f32 - (live_bytes as f32 / used_bytes as f32)
+        };
+
+        let needs_rebuild = misplaced_entries > 0 || (self.unique_keys && duplicate_keys > 0);
+        let expand_threshold = self
+            .expand_policy
+            .load_factor_threshold()
+            .unwrap_or(LEVEL_AUTO_EXPAND_THRESHOLD_DEFAULT);
+        let needs_expand = load_factor >= expand_threshold || self.insertion_failures > 0;
+
+        // a level hash which cannot be expanded anymore must be rebuilt
+        let action = if needs_rebuild || (needs_expand && meta.km_level_size >= LEVEL_SIZE_MAX) {
+            HealthAction::Rebuild
+        } else if needs_expand {
+            HealthAction::Expand
+        } else if fragmentation >= HEALTH_COMPACT_FRAGMENTATION {
+            HealthAction::Compact
+        } else {
+            HealthAction::None
+        };
+
+        let score = if needs_rebuild {
+            0f32
+        } else {
+            // entries in the later probe positions make the lookups slower
+            let probes = probe_histogram
+                .iter()
+                .enumerate()
+                .map(|(i, count)| i as u64 * count)
+                .sum::<u64>();
+            let mean_probe = probes as f32 / occupied.max(1) as f32;
+            let max_probe = (2 * self.level_count() - 1) as f32;
+            let failures = (self.insertion_failures as f32 / 100f32).min(1f32);
+            let load =
+                (load_factor - HEALTH_LOAD_FACTOR_OK).max(0f32) / (1f32 - HEALTH_LOAD_FACTOR_OK);
+
+            1f32 - (0.3 * load)
+                - (0.3 * fragmentation)
+                - (0.2 * mean_probe / max_probe)
+                - (0.2 * failures)
+        };
+
+        LevelHealth {
+            score: score.clamp(0f32, 1f32),
+            action,
+            load_factor,
+            fragmentation,
+            probe_histogram,
+            misplaced_entries,
+            duplicate_keys,
+            insertion_failures: self.insertion_failures,
+        }
+    }
+
+    /// Remove the older entries of the keys which have multiple live entries, keeping only the
+    /// latest (most recently written) entry for each key. This scans all the slots in the level
+    /// hash and must be used carefully.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries that were removed.
+    pub fn dedupe_keep_latest(&mut self) -> u64 {
+        if self.io.is_read_only() {
+            log_error!("cannot remove the duplicates from a read-only level hash");
+            return 0;
+        }
+
+        let mut removed = 0u64;
+        for (_, entries) in self.find_duplicates() {
+            // the entry linked to a slot is the latest entry of its chain
+            let mut heads = entries
+                .into_iter()
+                .filter(|(addr, level, bucket, slot)| {
+                    self.io
+                        .slot_and_val_addr_at(*level as _LevelIdxT, *bucket, *slot)
+                        .is_ok_and(|(_, val_addr)| val_addr == Some(addr + 1))
+                })
+                .collect::<Vec<_>>();
+
+            // entries are always appended to the values file, so the entry with
+            // the highest address is the one that was written most recently
+            heads.sort_by_key(|h| std::cmp::Reverse(h.0));
+            for (i, (addr, level, bucket, slot)) in heads.into_iter().enumerate() {
+                if i == 0 {
+                    removed += self.io.truncate_chain(addr);
+                    continue;
+                }
+
+                let slot_addr = self.io.slot_addr(level as _LevelIdxT, bucket, slot);
+                removed += self.io.chain_entries(addr + 1).len() as u64;
+                self.io.delete_chain_at_slot(slot_addr);
+                self.occupancy_mut().decrement(level as _LevelIdxT);
+            }
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("dedupe_keep_latest");
+
+        self.trace_op(TraceOp::DedupeKeepLatest, None, 0, true);
+        removed
+    }
+
+    /// Find all the keys which have more than one live entry, mapped to the (value address, level,
+    /// bucket, slot) of each of their entries. The entries in a chain have the slot of the chain.
+    fn find_duplicates(&self) -> HashMap<Vec<u8>, Vec<EntryLoc>> {
+        let mut entries: HashMap<Vec<u8>, Vec<EntryLoc>> = HashMap::new();
+
+        for SlotPos {
+            level,
+            bucket,
+            slot,
+        } in self.keymap_slots()
+        {
+            if let Some(entry) = self.io.occupied_entry(level as _LevelIdxT, bucket, slot) {
+                entries
+                    .entry(self.io.entry_key(&entry))
+                    .or_default()
+                    .extend(
+                        self.io
+                            .chain_entries(entry.addr + 1)
+                            .iter()
+                            .map(|entry| (entry.addr, level, bucket, slot)),
+                    );
+            }
+        }
+
+        entries.retain(|_, e| e.len() > 1);
+        entries
+    }
+
+    /// Check whether the keymap was modified after the checkpoint of the restored metadata, i.e.
+    /// whether a slot points to an entry which was written after the checkpoint, or the occupancy
+    /// of a level does not match its slots. Unlike [Self::verify], this reads only the slots and
+    /// none of the entries written before the checkpoint.
+    fn modified_since_checkpoint(&self) -> bool {
+        let used = self.io.meta.read().val_next_addr.saturating_sub(1);
+        let mut occupied = [0u32; LEVEL_COUNT_MAX as usize];
+        for SlotPos { level, bucket, slot } in self.keymap_slots() {
+            let slot_addr = self.io.slot_addr(level as _LevelIdxT, bucket, slot);
+            let val_addr = LevelHashIO::slot_val_addr(self.io.keymap.r_u64(slot_addr));
+            if val_addr == LevelHashIO::POS_INVALID {
+                continue;
+            }
+
+            if val_addr - 1 + ValuesEntry::ENTRY_SIZE_MIN > used {
+                return true;
+            }
+
+            if level != Level::Stash {
+                occupied[level as usize] += 1;
+            }
+        }
+
+        self.levels()
+            .iter()
+            .any(|&level| self.occupancy().level(level as _LevelIdxT) != occupied[level as usize])
+    }
+
+    /// Check the index files for inconsistencies : bad magic numbers, slots which point outside of
+    /// the values file or inside another entry, entries which are shared by multiple slots,
+    /// corrupt, misplaced or unreachable entries, broken chains of entries, and occupancy counts or a content fingerprint
+    /// which do not match the entries. This scans all the slots and all the entries in the values
+    /// file and must be used carefully.
+    ///
+    /// The inconsistencies can be fixed with [Self::repair].
+    pub fn verify(&self) -> Vec<Inconsistency> {
+        let mut found = Vec::new();
+
+        match self.io.read_magic_numbers() {
+            Ok(Some((values, keymap))) => {
+                for (file, expected, magic) in [
+                    (IndexFile::Values, LevelHashIO::VALUES_MAGIC_NUMBER, values),
+                    (IndexFile::Keymap, LevelHashIO::KEYMAP_MAGIC_NUMBER, keymap),
+                ] {
+                    if magic != expected {
+                        found.push(Inconsistency::BadMagicNumber {
+                            file,
+                            expected,
+                            found: magic,
+                        });
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(why) => log_error!("{}", why),
+        }
+
+        let layout = self.io.check_layout();
+        if !layout.is_empty() {
+            found.extend(layout.into_iter().map(Inconsistency::InvalidLayout));
+            return found;
+        }
+
+        // the entries in the values file, mapped from their start address to their end address
+        let entries = self
+            .io
+            .live_values_iter()
+            .map(|entry| (entry.addr, entry.addr + entry.esize()))
+            .collect::<BTreeMap<_, _>>();
+        let used = (self.io.meta.read().val_next_addr.saturating_sub(1)).min(self.io.values.size);
+        let mut linked: HashMap<OffT, SlotPos> = HashMap::new();
+
+        for pos in self.keymap_slots() {
+            let SlotPos {
+                level,
+                bucket,
+                slot,
+            } = pos;
+            let slot_addr = self.io.slot_addr(level as _LevelIdxT, bucket, slot);
+            let slot_entry = self.io.keymap.r_u64(slot_addr);
+            let val_addr = LevelHashIO::slot_val_addr(slot_entry);
+            if val_addr == LevelHashIO::POS_INVALID {
+                continue;
+            }
+
+            let addr = val_addr - 1;
+            if addr + ValuesEntry::ENTRY_SIZE_MIN > used {
+                found.push(Inconsistency::DanglingPointer {
+                    slot: pos,
+                    addr: val_addr,
+                });
+                continue;
+            }
+
+            if entries.contains_key(&addr) {
+                if let Some(other) = linked.insert(addr, pos) {
+                    found.push(Inconsistency::SharedEntry {
+                        slot: pos,
+                        other,
+                        addr,
+                    });
+                    continue;
+                }
+
+                let entry = ValuesEntry::at(addr, &self.io.values);
+                if entry.verify_checksum(&self.io.values).is_err() {
+                    found.push(Inconsistency::CorruptEntry { slot: pos, addr });
+                    continue;
+                }
+
+                let key = self.io.entry_key(&entry);
+                // the entries in the stash can be in any slot of the stash
+                if level != Level::Stash
+                    && bucket != self.buck_idx_lvl(self.fhash(&key), level)
+                    && bucket != self.buck_idx_lvl(self.shash(&key), level)
+                {
+                    found.push(Inconsistency::MisplacedEntry { slot: pos, addr });
+                }
+
+                let tag = LevelHashIO::slot_tag(slot_entry);
+                if tag != LevelHashIO::SLOT_TAG_NONE && tag != LevelHashIO::key_tag(&key) {
+                    found.push(Inconsistency::TagMismatch {
+                        slot: pos,
+                        addr,
+                        tag,
+                    });
+                }
+
+                self.verify_chain(&entry, pos, &key, &entries, &mut linked, &mut found);
+                continue;
+            }
+
+            if let Some((&start, _)) = entries
+                .range(..addr)
+                .next_back()
+                .filter(|(_, end)| **end > addr)
+            {
+                found.push(Inconsistency::OverlappingEntry {
+                    slot: pos,
+                    addr,
+                    entry: start,
+                });
+                continue;
+            }
+
+            if !addr.is_multiple_of(SIZE_U64) || ValuesEntry::at(addr, &self.io.values).is_empty() {
+                found.push(Inconsistency::DanglingPointer {
+                    slot: pos,
+                    addr: val_addr,
+                });
+            } else {
+                // the entry extends beyond the used region of the values file
+                found.push(Inconsistency::CorruptEntry { slot: pos, addr });
+            }
+        }
+
+        for &addr in entries.keys() {
+            if !linked.contains_key(&addr) {
+                found.push(Inconsistency::UnreachableEntry { addr });
+            }
+        }
+
+        for &level in self.levels() {
+            let stored = self.occupancy().level(level as _LevelIdxT);
+            let actual = self.count_occupied(level);
+            if stored != actual {
+                found.push(Inconsistency::OccupancyMismatch {
+                    level,
+                    stored,
+                    actual,
+                });
+            }
+        }
+
+        let meta = self.io.meta.read();
+        if meta.val_fingerprint_valid != 0 {
+            let actual = self.io.compute_fingerprint();
+            if meta.val_fingerprint != actual {
+                found.push(Inconsistency::FingerprintMismatch {
+                    stored: meta.val_fingerprint,
+                    actual,
+                });
+            }
+        }
+
+        found
+    }
+
+    /// Check the entries in the chain of the given entry, which is linked to the given slot. The
+    /// entries in the chain are added to `linked`.
+    /// Get the slots which point outside of the used region of the values file, or to an address
+    /// which is not 8-byte aligned like the entries.
+    fn dangling_slots(&self) -> Vec<Inconsistency> {
+        let used = (self.io.meta.read().val_next_addr.saturating_sub(1)).min(self.io.values.size);
+        let mut found = Vec::new();
+
+        for pos in self.keymap_slots() {
+            let slot_addr = self
+                .io
+                .slot_addr(pos.level as _LevelIdxT, pos.bucket, pos.slot);
+            let val_addr = self.io.km_val_addr(slot_addr);
+            if val_addr != LevelHashIO::POS_INVALID
+                && (!(val_addr - 1).is_multiple_of(SIZE_U64)
+                    || val_addr - 1 + ValuesEntry::ENTRY_SIZE_MIN > used)
+            {
+                found.push(Inconsistency::DanglingPointer {
+                    slot: pos,
+                    addr: val_addr,
+                });
+            }
+        }
+
+        found
+    }
+
+    fn verify_chain(
+        &self,
+        head: &ValuesEntry,
+        slot: SlotPos,
+        key: &LevelKeyT,
+        entries: &BTreeMap<OffT, OffT>,
+        linked: &mut HashMap<OffT, SlotPos>,
+        found: &mut Vec<Inconsistency>,
+    ) {
+        let mut next = head.chain_next(&self.io.values);
+        while let Some(val_addr) = next {
+            let addr = val_addr - 1;
+            if !entries.contains_key(&addr) {
+                found.push(Inconsistency::BrokenChain { slot, addr });
+                return;
+            }
+
+            if let Some(other) = linked.insert(addr, slot) {
+                found.push(Inconsistency::SharedEntry { slot, other, addr });
+                return;
+            }
+
+            let entry = ValuesEntry::at(addr, &self.io.values);
+            if entry.verify_checksum(&self.io.values).is_err() {
+                found.push(Inconsistency::CorruptEntry { slot, addr });
+                return;
+            }
+
+            if !self.io.entry_keyeq(&entry, key) {
+                found.push(Inconsistency::BrokenChain { slot, addr });
+                return;
+            }
+
+            next = entry.chain_next(&self.io.values);
+        }
+    }
+
+    /// Repair the inconsistencies found by [Self::verify] by rebuilding the keymap from the entries
+    /// in the values file. All the slots are emptied and each entry is linked to a free slot in one
+    /// of the buckets for its key, after which the occupancy counts, the content fingerprint, the
+    /// free list and the bloom filter are recomputed. The level hash is expanded if no slot can be
+    /// found for some of the entries.
+    ///
+    /// The corrupt entries are removed, along with the older entries of a key in a level hash with
+    /// unique keys. The entry which was linked to a slot is kept in that case, or the latest
+    /// (most recently written) entry if none of them was linked. The level hash is not modified if
+    /// no inconsistencies are found.
+    ///
+    /// # Returns
+    ///
+    /// The inconsistencies that were found, along with the number of entries that were relinked
+    /// and removed.
+    pub fn repair(&mut self) -> LevelResult<RepairReport, LevelMapError> {
+        self.abort_expansion();
+        self.structural_change(Self::do_repair)
+    }
+
+    fn do_repair(&mut self) -> LevelResult<RepairReport, LevelMapError> {
+        if self.io.is_read_only() {
+            return Err(LevelMapError::IOError(StdIOError::with_message(
+                "cannot repair a read-only level hash".to_string(),
+                ErrorKind::PermissionDenied.into(),
+            )));
+        }
+
+        let mut report = RepairReport {
+            inconsistencies: self.verify(),
+            ..RepairReport::default()
+        };
+
+        if report.inconsistencies.is_empty() {
+            return Ok(report);
+        }
+
+        if let Some(why) = self.io.check_level_params() {
+            // the layout of the keymap cannot be rebuilt without a valid level size and count
+            return Err(LevelMapError::IOError(StdIOError::with_message(
+                format!("cannot repair the level hash: {}", why),
+                ErrorKind::InvalidData.into(),
+            )));
+        }
+
+        self.io.write_magic_numbers()?;
+
+        // the entries which are linked to a slot are preferred over the other entries of their key
+        let mut linked = HashSet::new();
+        let stash = (0, self.io.meta.read().km_stash_bytes(), 0);
+        for (lvl_addr, end, _) in iter::once(stash).chain(self.io.level_extents()) {
+            for slot_addr in (lvl_addr..end.min(self.io.keymap.size))
+                .step_by(LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES as usize)
+            {
+                let val_addr = self.io.km_val_addr(slot_addr);
+                if val_addr != LevelHashIO::POS_INVALID {
+                    linked.insert(val_addr - 1);
+                }
+            }
+        }
+
+        let mut dropped = Vec::new();
+        let mut entries = Vec::new();
+        for entry in self.io.live_values_iter() {
+            if entry.verify_checksum(&self.io.values).is_err() {
+                dropped.push(entry.addr);
+            } else {
+                entries.push((entry.addr, self.io.entry_key(&entry)));
+            }
+        }
+
+        if self.unique_keys {
+            let mut latest: HashMap<Vec<u8>, OffT> = HashMap::new();
+            for (addr, key) in entries.drain(..) {
+                let Some(current) = latest.get_mut(&key) else {
+                    latest.insert(key, addr);
+                    continue;
+                };
+
+                // entries are always appended to the values file, so the entry with
+                // the highest address is the one that was written most recently
+                if (linked.contains(&addr), addr) > (linked.contains(&*current), *current) {
+                    dropped.push(mem::replace(current, addr));
+                } else {
+                    dropped.push(addr);
+                }
+            }
+
+            entries = latest.into_iter().map(|(key, addr)| (addr, key)).collect();
+            entries.sort();
+        }
+
+        let chains = self.rebuild_chains(entries);
+
+        self.io.reset_keymap()?;
+        self.occupancy_mut().set([0; LEVEL_COUNT_MAX as usize]);
+
+        let mut pending = Vec::new();
+        for (addr, key, len) in chains {
+            if self.relink_entry(addr, &key) {
+                report.relinked_entries += len;
+            } else {
+                pending.push((addr, key, len));
+            }
+        }
+
+        for &addr in &dropped {
+            self.io.delete_at(addr + 1, None, false);
+        }
+
+        self.io.rebuild_values_state();
+        self.rebuild_bloom()?;
+
+        // the entries which could not be linked are unreachable until a slot is found for them
+        while !pending.is_empty() && self.level_size() < LEVEL_SIZE_MAX {
+            if let Err(why) = self.do_expand() {
+                log_error!("{:?}", why);
+                break;
+            }
+
+            pending.retain(|(addr, key, len)| {
+                let relinked = self.relink_entry(*addr, key);
+                if relinked {
+                    report.relinked_entries += len;
+                }
+                !relinked
+            });
+        }
+
+        let mut dropped_entries = dropped.len() as u64;
+        for (addr, _, _) in &pending {
+            let chain = self
+                .io
+                .chain_entries(addr + 1)
+                .iter()
+                .map(|entry| entry.addr + 1)
+                .collect::<Vec<_>>();
+            for val_addr in chain {
+                self.io.delete_at(val_addr, None, false);
+                dropped_entries += 1;
+            }
+        }
+
+        report.dropped_entries = dropped_entries;
+        self.rebuild_bloom()?;
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("repair");
+
+        self.sync_after_write()?;
+        Ok(report)
+    }
+
+    /// Rebuild the chains of the given (valid) entries, mapped from their (0-based) address to their
+    /// key. The chain links which do not point to another one of the entries for the same key are
+    /// cut, so that every entry is in exactly one chain. The entries of a level hash with unique keys
+    /// are not chained.
+    ///
+    /// # Returns
+    ///
+    /// The first entry of each chain along with its key and the number of entries in the chain.
+    fn rebuild_chains(&mut self, entries: Vec<(OffT, Vec<u8>)>) -> Vec<(OffT, Vec<u8>, u64)> {
+        let index = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (addr, _))| (*addr, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut next = vec![None; entries.len()];
+        let mut linked = vec![false; entries.len()];
+        let mut cut = Vec::new();
+        for (i, (addr, key)) in entries.iter().enumerate() {
+            let entry = ValuesEntry::at(*addr, &self.io.values);
+            let Some(val_addr) = entry.chain_next(&self.io.values) else {
+                continue;
+            };
+
+            match index.get(&(val_addr - 1)) {
+                Some(&j) if !self.unique_keys && !linked[j] && entries[j].1 == *key => {
+                    next[i] = Some(j);
+                    linked[j] = true;
+                }
+                _ => cut.push(i),
+            }
+        }
+
+        let mut reached = vec![false; entries.len()];
+        let mut heads = Vec::new();
+        for (i, _) in linked.iter().enumerate().filter(|(_, linked)| !**linked) {
+            let mut len = 0u64;
+            let mut current = Some(i);
+            while let Some(j) = current {
+                reached[j] = true;
+                len += 1;
+                current = next[j];
+            }
+            heads.push((i, len));
+        }
+
+        // the entries in a cycle of links cannot be reached from the first entry of a chain
+        for (i, _) in reached.iter().enumerate().filter(|(_, reached)| !**reached) {
+            cut.push(i);
+            heads.push((i, 1));
+        }
+
+        for i in cut {
+            self.io
+                .set_chain_next(entries[i].0, LevelHashIO::POS_INVALID);
+        }
+
+        heads
+            .into_iter()
+            .map(|(i, len)| (entries[i].0, entries[i].1.clone(), len))
+            .collect()
+    }
+
+    /// Link the entry at the given (0-based) address in the values file to a free slot in one of
+    /// the buckets for its key, returning `false` if the buckets are full in all the levels.
+    fn relink_entry(&mut self, addr: OffT, key: &LevelKeyT) -> bool {
+        let fhash = self.fhash(key);
+        let shash = self.shash(key);
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+
+        for &level in self.levels() {
+            let fidx = self.buck_idx_lvl(fhash, level);
+            let sidx = self.buck_idx_lvl(shash, level);
+
+            for slot in 0..bucket_size {
+                for bucket in [fidx, sidx] {
+                    let slot_addr = self.io.slot_addr(level as _LevelIdxT, bucket, slot);
+                    if self.io.keymap.r_u64(slot_addr) == LevelHashIO::POS_INVALID {
+                        self.io.km_write_entry(slot_addr, addr + 1, key);
+                        self.occupancy_mut().increment(level as _LevelIdxT);
+                        return true;
+                    }
+                }
+            }
+        }
+
+        for slot in 0..self.stash_slots() {
+            let slot_addr = self.io.slot_addr(LevelHashIO::STASH_LEVEL, 0, slot);
+            if self.io.keymap.r_u64(slot_addr) == LevelHashIO::POS_INVALID {
+                self.io.km_write_entry(slot_addr, addr + 1, key);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Expand the level hash by one level size, doubling its capacity. This is an expensive operation
+    /// and must be used carefully. Consider setting an [ExpandPolicy] with
+    /// [LevelHashOptions::expand_policy] to automatically expand the level hash when appropriate.
+    /// A level hash can have a maximum of [LEVEL_SIZE_MAX] level size.
+    ///
+    /// If an incremental expansion is in progress (see [LevelHashOptions::incremental_expansion]),
+    /// it is completed instead of starting another expansion.
+    ///
+    /// # Returns
+    ///
+    /// The result of the expansion.
+    pub fn expand(&mut self) -> LevelExpansionResult {
+        let result = self.do_expand();
+        self.trace_op(TraceOp::Expand, None, 0, result.is_ok());
+        result
+    }
+
+    /// Expand the level hash like [Self::expand], checking the given token after every few buckets
+    /// of the bottom level are rehashed. The expansion is performed like an incremental expansion
+    /// (see [LevelHashOptions::incremental_expansion]), so the level hash remains usable if the
+    /// expansion is cancelled. The expansion stays in progress in that case and is resumed by the
+    /// next call to this function or [Self::expand], or by the write operations if the level hash
+    /// expands incrementally. Like any incremental expansion, it is discarded if the level hash is
+    /// closed before it is completed.
+    ///
+    /// # Returns
+    ///
+    /// The result of the expansion, or [LevelExpansionError::Cancelled] if the token was cancelled
+    /// before the expansion was completed.
+    pub fn expand_cancellable(&mut self, token: &CancellationToken) -> LevelExpansionResult {
+        let result = self.do_expand_chunked(Some(token), None);
+        self.trace_op(TraceOp::Expand, None, 0, result.is_ok());
+        result
+    }
+
+    /// Expand the level hash like [Self::expand_cancellable], calling the given callback with the
+    /// progress of the expansion after every few buckets of the bottom level are rehashed, so that
+    /// the progress of a long expansion can be shown to the user. The callback is called one last
+    /// time with [ExpandProgress::is_done] once the expansion has been completed.
+    ///
+    /// # Returns
+    ///
+    /// The result of the expansion.
+    pub fn expand_with_progress(
+        &mut self,
+        mut callback: impl FnMut(ExpandProgress),
+    ) -> LevelExpansionResult {
+        let result = self.do_expand_chunked(None, Some(&mut callback));
+        self.trace_op(TraceOp::Expand, None, 0, result.is_ok());
+        result
+    }
+
+    fn do_expand_chunked(
+        &mut self,
+        token: Option<&CancellationToken>,
+        mut progress: Option<&mut dyn FnMut(ExpandProgress)>,
+    ) -> LevelExpansionResult {
+        self.begin_expansion()?;
+
+        let bottom_level = LEVELS[self.level_count() as usize - 1];
+        let total_buckets = self.level_bucket_count(bottom_level);
+        let rehash_next = |this: &Self| {
+            this.io
+                .rehash
+                .as_ref()
+                .map_or(total_buckets, |rehash| rehash.next_bucket)
+        };
+
+        // an expansion which is resumed is estimated from the buckets moved by this call only
+        let start = Instant::now();
+        let start_bucket = rehash_next(self);
+        while self.is_expanding() {
+            if token.is_some_and(CancellationToken::is_cancelled) {
+                // the rehashed buckets are kept, like after a step of an incremental expansion
+                self.sync_after_write()
+                    .map_err(LevelMapError::from)
+                    .into_lvl_exp_err()?;
+                return Err(LevelExpansionError::Cancelled);
+            }
+
+            self.rehash_buckets(EXPAND_CANCEL_CHECK_BUCKETS)?;
+
+            if let Some(callback) = progress.as_mut() {
+                let buckets_moved = rehash_next(self);
+                let moved_now = buckets_moved - start_bucket;
+                let estimated_remaining = (moved_now > 0).then(|| {
+                    start
+                        .elapsed()
+                        .mul_f64((total_buckets - buckets_moved) as f64 / moved_now as f64)
+                });
+                callback(ExpandProgress {
+                    buckets_moved,
+                    total_buckets,
+                    estimated_remaining,
+                });
+            }
+        }
+
+        self.sync_after_write()
+            .map_err(LevelMapError::from)
+            .into_lvl_exp_err()
+    }
+
+    fn do_expand(&mut self) -> LevelExpansionResult {
+        if self.is_expanding() {
+            // the expansion in progress is completed instead of starting another one
+            self.rehash_buckets(u32::MAX)?;
+            return self
+                .sync_after_write()
+                .map_err(LevelMapError::from)
+                .into_lvl_exp_err();
+        }
+
+        log_span!(
+            "expand",
+            level_size = self.level_size(),
+            keymap_bytes = self.io.meta.read().km_level_bytes(0) * 2,
+        );
+        self.notify(LevelEvent::ExpansionStarted {
+            level_size: self.level_size(),
+        });
+        let result = self.structural_change(Self::expand_levels);
+        self.notify(LevelEvent::ExpansionFinished {
+            level_size: self.level_size(),
+            ok: result.is_ok(),
+        });
+        result
+    }
+
+    fn expand_levels(&mut self) -> LevelExpansionResult {
+        if self.io.is_read_only() {
+            return Err(LevelExpansionError::ReadOnly);
+        }
+
+        let level_size = self.io.meta.read().km_level_size;
+        if level_size == LEVEL_SIZE_MAX {
+            return Err(crate::result::LevelExpansionError::MaxLevelSizeReached);
+        }
+
+        let level_size = level_size + 1;
+
+        let new_top_level_capacity: u64 = 1u64 << level_size;
+
+        self.io.prepare_interim(new_top_level_capacity as u32)?;
+
+        let new_level_item_count = match self.fill_interim(new_top_level_capacity) {
+            Ok(count) => count,
+            Err(why) => {
+                // the levels are not modified until the interim level is committed, so dropping
+                // the interim level restores the keymap to its state before the expansion
+                if let Err(rollback_err) = self.io.rollback_interim() {
+                    log_error!("failed to roll back expansion: {:?}", rollback_err);
+                }
+                return Err(why);
+            }
+        };
+
+        self.io.commit_interim(level_size);
+        // every level moves one level down
+        let level_count = self.level_count();
+        self.occupancy_mut()
+            .shift_down(level_count, new_level_item_count);
+        self.expand_count += 1;
+        self.insertion_failures = 0;
+        self.drain_stash();
+        self.rebuild_bloom()?;
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("expand");
+
+        self.sync_after_write()
+            .map_err(LevelMapError::from)
+            .into_lvl_exp_err()
+    }
+
+    /// Point the slots of the interim level (the new top level of the expansion) to the entries
+    /// in the bottom level. The slots of the bottom level are left as they are, so that the
+    /// expansion can be rolled back.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries in the interim level, or the error if an entry of the bottom level
+    /// does not fit in the interim level.
+    fn fill_interim(
+        &mut self,
+        new_top_level_capacity: u64,
+    ) -> LevelResult<u32, LevelExpansionError> {
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        let mut new_level_item_count = 0u32;
+
+        // the entries in the bottom level are moved to the new top level
+        let bottom_level = LEVELS[self.level_count() as usize - 1];
+        for old_buck_idx in 0..self.level_bucket_count(bottom_level) {
+            for old_slot_idx in 0..bucket_size {
+                if self.rehash_slot(
+                    bottom_level,
+                    old_buck_idx,
+                    old_slot_idx,
+                    new_top_level_capacity,
+                )? {
+                    new_level_item_count += 1;
+                }
+            }
+        }
+
+        Ok(new_level_item_count)
+    }
+
+    /// Point a slot of the interim level to the entry in the given slot of the bottom level.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the slot of the bottom level was occupied, or the error if its entry does not fit in
+    /// the interim level.
+    fn rehash_slot(
+        &mut self,
+        bottom_level: Level,
+        old_buck_idx: _BucketIdxT,
+        old_slot_idx: _SlotIdxT,
+        new_top_level_capacity: u64,
+    ) -> LevelResult<bool, LevelExpansionError> {
+        if !self
+            .io
+            .is_occupied(bottom_level as _LevelIdxT, old_buck_idx, old_slot_idx)
+        {
+            return Ok(false);
+        }
+
+        // the free slots are skipped without reading the values file
+        let Some(entry) =
+            self.io
+                .occupied_entry(bottom_level as _LevelIdxT, old_buck_idx, old_slot_idx)
+        else {
+            return Ok(false);
+        };
+        let key = self.io.entry_key(&entry);
+        let fhash = self.fhash(&key);
+        let shash = self.shash(&key);
+
+        let fidx = Self::buck_idx_cap(fhash, new_top_level_capacity);
+        let sidx = Self::buck_idx_cap(shash, new_top_level_capacity);
+
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        for new_slot_idx in 0..bucket_size {
+            if self.io.move_to_interim(
+                bottom_level as _LevelIdxT,
+                old_buck_idx,
+                old_slot_idx,
+                fidx,
+                new_slot_idx,
+            ) || self.io.move_to_interim(
+                bottom_level as _LevelIdxT,
+                old_buck_idx,
+                old_slot_idx,
+                sidx,
+                new_slot_idx,
+            ) {
+                return Ok(true);
+            }
+        }
+
+        Err(LevelExpansionError::from(
+            LevelInsertionError::InsertionFailure(self.insertion_diagnostics(&key, false)),
+        ))
+    }
+
+    /// Move the entries in the stash to the free slots of their buckets, after the level hash has
+    /// been expanded. The entries which still do not fit in their buckets stay in the stash.
+    fn drain_stash(&mut self) {
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        for stash_slot in 0..self.stash_slots() {
+            let Some(entry) = self
+                .io
+                .occupied_entry(LevelHashIO::STASH_LEVEL, 0, stash_slot)
+            else {
+                continue;
+            };
+
+            let key = self.io.entry_key(&entry);
+            let fhash = self.fhash(&key);
+            let shash = self.shash(&key);
+            let free_slot = self.levels().iter().find_map(|&level| {
+                let fidx = self.buck_idx_lvl(fhash, level);
+                let sidx = self.buck_idx_lvl(shash, level);
+                (0..bucket_size)
+                    .flat_map(|slot| [(fidx, slot), (sidx, slot)])
+                    .find(|(bucket, slot)| {
+                        !self.io.is_occupied(level as _LevelIdxT, *bucket, *slot)
+                    })
+                    .map(|(bucket, slot)| (level as _LevelIdxT, bucket, slot))
+            });
+
+            if let Some((level, bucket, slot)) = free_slot {
+                self.io.move_slot_to_level(
+                    LevelHashIO::STASH_LEVEL,
+                    0,
+                    stash_slot,
+                    level,
+                    bucket,
+                    slot,
+                );
+                self.occupancy_mut().increment(level);
+            }
+        }
+    }
+
+    /// Whether an incremental expansion is in progress. See
+    /// [LevelHashOptions::incremental_expansion].
+    pub fn is_expanding(&self) -> bool {
+        self.io.rehash.is_some()
+    }
+
+    /// Start an incremental expansion of the level hash, whose buckets are rehashed by the
+    /// subsequent write operations. See [LevelHashOptions::incremental_expansion].
+    fn begin_expansion(&mut self) -> LevelExpansionResult {
+        if self.is_expanding() {
+            return Ok(());
+        }
+
+        if self.io.is_read_only() {
+            return Err(LevelExpansionError::ReadOnly);
+        }
+
+        let level_size = self.level_size();
+        if level_size == LEVEL_SIZE_MAX {
+            return Err(LevelExpansionError::MaxLevelSizeReached);
+        }
+
+        self.notify(LevelEvent::ExpansionStarted { level_size });
+        let result = self.io.begin_rehash((1u64 << (level_size + 1)) as u32);
+        if result.is_err() {
+            self.notify(LevelEvent::ExpansionFinished {
+                level_size,
+                ok: false,
+            });
+        }
+
+        result
+    }
+
+    /// Rehash the next buckets of the bottom level in the incremental expansion which is in
+    /// progress, after the slots which were written after their buckets were rehashed. The
+    /// expansion is committed once all the buckets have been rehashed, and is rolled back if an
+    /// entry does not fit in the new top level.
+    fn rehash_buckets(&mut self, count: u32) -> LevelExpansionResult {
+        let Some(rehash) = self.io.rehash.as_mut() else {
+            return Ok(());
+        };
+
+        let pending = mem::take(&mut rehash.pending);
+        let start = rehash.next_bucket;
+        let bottom_level = LEVELS[self.level_count() as usize - 1];
+        let end = start
+            .saturating_add(count)
+            .min(self.level_bucket_count(bottom_level));
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        let new_top_level_capacity = 1u64 << (self.level_size() + 1);
+
+        let result = pending
+            .into_iter()
+            .chain((start..end).flat_map(|bucket| (0..bucket_size).map(move |slot| (bucket, slot))))
+            .try_for_each(|(bucket, slot)| {
+                self.rehash_slot(bottom_level, bucket, slot, new_top_level_capacity)
+                    .map(|_| ())
+            });
+        if let Err(why) = result {
+            log_warn!("incremental expansion failed: {:?}", why);
+            self.abort_expansion();
+            return Err(why);
+        }
+
+        self.io.set_rehash_next(end);
+        if end == self.level_bucket_count(bottom_level) {
+            return self.commit_expansion();
+        }
+
+        Ok(())
+    }
+
+    /// Commit the incremental expansion whose buckets have all been rehashed.
+    fn commit_expansion(&mut self) -> LevelExpansionResult {
+        let result = self.structural_change(|this| {
+            this.io.end_rehash();
+            this.io.commit_interim(this.level_size() + 1);
+            // every level moves one level down
+            let new_level_item_count = this.count_occupied(LEVELS[0]);
+            let level_count = this.level_count();
+            this.occupancy_mut()
+                .shift_down(level_count, new_level_item_count);
+            this.expand_count += 1;
+            this.insertion_failures = 0;
+            this.drain_stash();
+            this.rebuild_bloom()?;
+
+            #[cfg(feature = "debug-invariants")]
+            this.assert_invariants("expand");
+
+            Ok(())
+        });
+
+        self.notify(LevelEvent::ExpansionFinished {
+            level_size: self.level_size(),
+            ok: result.is_ok(),
+        });
+        result
+    }
+
+    /// Drop the interim level of the incremental expansion which is in progress, if any. The
+    /// levels are not modified until the expansion is committed, so this restores the keymap to
+    /// its state before the expansion.
+    fn abort_expansion(&mut self) {
+        if !self.is_expanding() {
+            return;
+        }
+
+        self.io.end_rehash();
+        if let Err(why) = self.io.rollback_interim() {
+            log_error!("failed to roll back expansion: {:?}", why);
+        }
+
+        self.notify(LevelEvent::ExpansionFinished {
+            level_size: self.level_size(),
+            ok: false,
+        });
+    }
+
+    /// Shrink the level hash by one level size, halving its capacity. This is the inverse of
+    /// [Self::expand] : the top level is dropped and its entries are moved to the remaining levels
+    /// and a new bottom level, after which the keymap file is truncated. Like [Self::expand], this
+    /// is an expensive operation and is meant to be used after a large number of entries have been
+    /// removed from the level hash.
+    ///
+    /// The level hash is not modified if any of the entries cannot be moved.
+    ///
+    /// # Returns
+    ///
+    /// The result of the shrink operation.
+    pub fn shrink(&mut self) -> LevelShrinkResult {
+        let result = self.do_shrink();
+        self.trace_op(TraceOp::Shrink, None, 0, result.is_ok());
+        result
+    }
+
+    fn do_shrink(&mut self) -> LevelShrinkResult {
+        self.abort_expansion();
+        self.structural_change(Self::shrink_levels)
+    }
+
+    fn shrink_levels(&mut self) -> LevelShrinkResult {
+        if self.io.is_read_only() {
+            return Err(LevelShrinkError::ReadOnly);
+        }
+
+        let level_size = self.io.meta.read().km_level_size;
+        let level_count = self.level_count();
+        if level_size < level_count {
+            // the bottom level must have at least one bucket
+            return Err(LevelShrinkError::MinLevelSizeReached);
+        }
+
+        let new_level_size = level_size - 1;
+        let new_top_level_capacity = 1u64 << new_level_size;
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+
+        let item_count = self.occupancy().total();
+        let new_total_slots: u64 = (0..level_count)
+            .map(|level| (new_top_level_capacity >> level) * bucket_size as u64)
+            .sum();
+        let load_factor = item_count as f32 / new_total_slots as f32;
+        let max_load_factor = self.expand_policy.load_factor_threshold().unwrap_or(1.0);
+
+        if load_factor > max_load_factor {
+            return Err(LevelShrinkError::LoadFactorTooHigh { load_factor });
+        }
+
+        // find the new slots for all the entries in the top level before modifying the keymap,
+        // the level `n` after shrinking is the level `n + 1` before shrinking
+        let bottom_level = level_count as _LevelIdxT - 1;
+        let mut taken = HashSet::new();
+        let mut moves = Vec::new();
+        let mut item_counts = [0u32; LEVEL_COUNT_MAX as usize];
+        item_counts[..bottom_level as usize]
+            .copy_from_slice(&self.occupancy().counts[1..=bottom_level as usize]);
+
+        for bucket in 0..self.level_bucket_count(L0) {
+            for slot in 0..bucket_size {
+                let Some(entry) = self.io.occupied_entry(L0 as _LevelIdxT, bucket, slot) else {
+                    continue;
+                };
+
+                let key = self.io.entry_key(&entry);
+                let fhash = self.fhash(&key);
+                let shash = self.shash(&key);
+
+                let dest = (0..=bottom_level).find_map(|level| {
+                    let capacity = new_top_level_capacity >> level;
+                    let fidx = Self::buck_idx_cap(fhash, capacity);
+                    let sidx = Self::buck_idx_cap(shash, capacity);
+                    (0..bucket_size).find_map(|dest_slot| {
+                        [fidx, sidx]
+                            .into_iter()
+                            .map(|dest_bucket| (level, dest_bucket, dest_slot))
+                            .find(|dest| {
+                                !taken.contains(dest)
+                                    && (level == bottom_level
+                                        || !self.io.is_occupied(level + 1, dest.1, dest.2))
+                            })
+                    })
+                });
+
+                let Some(dest) = dest else {
+                    return Err(LevelShrinkError::InsertionFailure);
+                };
+
+                taken.insert(dest);
+                moves.push(((bucket, slot), dest));
+                item_counts[dest.0 as usize] += 1;
+            }
+        }
+
+        self.io.shrink_keymap(new_level_size, &moves)?;
+        self.occupancy_mut().set(item_counts);
+        self.insertion_failures = 0;
+        self.rebuild_bloom()?;
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("shrink");
+
+        self.sync_after_write()
+            .map_err(LevelMapError::from)
+            .map_err(LevelShrinkError::from)
+    }
+
+    /// Get the garbage ratio of the values file, i.e. the fraction of the used region of the values
+    /// file which is not used by any entry. The regions of the removed and updated entries are
+    /// garbage until they are reused by the new entries or the values file is compacted with
+    /// [Self::compact]. The expired entries which have not been purged are not garbage.
+    pub fn garbage_ratio(&self) -> f32 {
+        let used = self.io.meta.read().val_next_addr - 1;
+        if used == 0 {
+            return 0f32;
+        }
+
+        self.io.val_garbage_bytes() as f32 / used as f32
+    }
+
+    /// Compact the values file by moving all the entries towards the start of the file, so that
+    /// the used region of the file has no garbage (see [Self::garbage_ratio]). The slots and the
+    /// chains of the entries are updated to the new addresses of the entries, and the region of
+    /// the file after the last entry is deallocated. This reads and writes every entry in the
+    /// values file and must be used carefully. An incremental expansion in progress is discarded.
+    ///
+    /// The entries are moved in place, so a process killed while the values file is being
+    /// compacted may leave the index inconsistent. Such an index can be fixed with
+    /// [Self::repair].
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes by which the used region of the values file shrunk.
+    pub fn compact(&mut self) -> LevelCompactResult {
+        if self.io.is_read_only() {
+            return Err(LevelCompactError::ReadOnly);
+        }
+
+        log_span!(
+            "compact",
+            values_bytes = self.io.meta.read().val_next_addr - 1
+        );
+        self.abort_expansion();
+        let reclaimed = self.structural_change(|this| {
+            let slot_addrs = this
+                .keymap_slots()
+                .map(|pos| {
+                    this.io
+                        .slot_addr(pos.level as _LevelIdxT, pos.bucket, pos.slot)
+                })
+                .collect::<Vec<_>>();
+            this.io.compact_values(&slot_addrs)
+        })?;
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("compact");
+
+        self.sync_after_write()?;
+        Ok(reclaimed)
+    }
+
+    /// Pin the entry of the given key, so that its value can be read with [Self::pinned_value] or
+    /// [Self::pinned_value_ref] even after the entry is moved, updated or removed (see
+    /// [EntryPin]).
+    ///
+    /// The pins are not stored in the index files. The region of an entry which is removed while
+    /// it is pinned is released when the pin is removed, or when the level hash is dropped. If the
+    /// process is killed before that, the removed entry is left in the values file and is reported
+    /// by [Self::verify] until the index is repaired with [Self::repair].
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key of the entry to pin.
+    ///
+    /// ## Returns
+    ///
+    /// The pin of the entry, or `None` if there is no entry for the key.
+    pub fn pin(&mut self, key: &LevelKeyT) -> Option<EntryPin> {
+        let key = self.transform_key(key);
+        let (entry, ..) = self.find_slot(&key)?;
+        if self.io.is_expired(&entry) {
+            return None;
+        }
+
+        let addr = entry.addr;
+        Some(EntryPin(self.io.pin_entry(addr)))
+    }
+
+    /// Get the value of the entry pinned with the given pin.
+    ///
+    /// ## Parameters
+    ///
+    /// * `pin` - The pin of the entry.
+    ///
+    /// ## Returns
+    ///
+    /// The value of the entry when it was pinned, or `None` if the pin was not returned by this
+    /// level hash.
+    pub fn pinned_value(&self, pin: &EntryPin) -> Option<Vec<u8>> {
+        let entry = self.io.pinned_entry(pin.0)?;
+        Some(self.io.entry_value(&entry))
+    }
+
+    /// Get the value of the entry pinned with the given pin without copying it out of the values
+    /// file.
+    ///
+    /// ## Parameters
+    ///
+    /// * `pin` - The pin of the entry.
+    ///
+    /// ## Returns
+    ///
+    /// The value of the entry when it was pinned, or `None` if the pin was not returned by this
+    /// level hash, or if the value has been spilled to the overflow file or compressed. Such
+    /// values can be read with [Self::pinned_value].
+    pub fn pinned_value_ref(&self, pin: &EntryPin) -> Option<&[u8]> {
+        let entry = self.io.pinned_entry(pin.0)?;
+        self.io.entry_value_slice(&entry)
+    }
+
+    /// Remove the given pin. If the pinned entry was removed or updated while it was pinned and
+    /// this was its last pin, its region in the values file is released.
+    ///
+    /// ## Parameters
+    ///
+    /// * `pin` - The pin to remove.
+    pub fn unpin(&mut self, pin: EntryPin) {
+        self.io.unpin_entry(pin.0);
+    }
+
+    /// Compact the values file if its garbage ratio exceeds the threshold set with
+    /// [LevelHashOptions::auto_compact].
+    fn maybe_compact(&mut self) {
+        let Some(threshold) = self.compact_threshold else {
+            return;
+        };
+
+        // an incremental expansion in progress is completed by the writes first
+        if self.is_expanding()
+            || self.io.val_garbage_bytes() < self.io.val_block_size()
+            || self.garbage_ratio() <= threshold
+        {
+            return;
+        }
+
+        if let Err(why) = self.compact() {
+            log_error!("failed to compact the values file: {}", why);
+        }
+    }
+
+    /// Remove all the entries of the level hash, keeping its capacity. This is the same as
+    /// [Self::clear_keep_capacity].
+    pub fn clear(&mut self) -> LevelClearResult {
+        self.clear_keep_capacity()
+    }
+
+    /// Remove all the entries of the level hash, keeping the level size it has been expanded (or
+    /// shrunk) to. Once the level hash has been cleared :
+    ///
+    /// * The level hash has no entries, and keeps its level size, bucket size and level count.
+    /// * The levels are placed next to each other in the keymap, and the values and the overflow
+    ///   files are truncated to their initial size.
+    /// * The occupancy of the levels, the expansion count, the insertion failures and the probe
+    ///   lengths reported by [Self::stats] are reset.
+    /// * An incremental expansion in progress is discarded.
+    pub fn clear_keep_capacity(&mut self) -> LevelClearResult {
+        let result = self.do_clear(None);
+        self.trace_op(TraceOp::Clear, None, 0, result.is_ok());
+        result
+    }
+
+    /// Remove all the entries of the level hash and resize its levels for the given level size,
+    /// e.g. to return an expanded level hash to its initial size. Once the level hash has been
+    /// cleared, it has the given level size and the keymap file is truncated to the size of the
+    /// levels, the bloom filter (if any) is resized for the new capacity, and the rest is the same
+    /// as with [Self::clear_keep_capacity].
+    ///
+    /// ## Parameters
+    ///
+    /// * `level_size` - The new level size. Like with [LevelHashOptions::level_size], the bottom
+    ///   level must have at least one bucket.
+    ///
+    /// ## Returns
+    ///
+    /// [LevelMapError::IOError] of kind [ErrorKind::InvalidInput] if the level size is invalid, in
+    /// which case the level hash is not modified.
+    pub fn clear_and_reset(&mut self, level_size: LevelSizeT) -> LevelClearResult {
+        let result = self.do_clear(Some(level_size));
+        self.trace_op(
+            TraceOp::ClearAndReset,
+            None,
+            level_size as usize,
+            result.is_ok(),
+        );
+        result
+    }
+
+    fn do_clear(&mut self, level_size: Option<LevelSizeT>) -> LevelClearResult {
+        if let Some(level_size) = level_size {
+            let min_level_size = self.level_count() - 1;
+            if level_size == 0 || level_size < min_level_size || level_size > LEVEL_SIZE_MAX {
+                return Err(LevelMapError::IOError(StdIOError::with_message(
+                    format!(
+                        "level size must be between {} and {}",
+                        min_level_size.max(1),
+                        LEVEL_SIZE_MAX
+                    ),
+                    ErrorKind::InvalidInput.into(),
+                )));
+            }
+        }
+
+        log_span!("clear", values_bytes = self.io.meta.read().val_file_size);
+        self.abort_expansion();
+        self.structural_change(|this| this.clear_levels(level_size))
+    }
+
+    fn clear_levels(&mut self, level_size: Option<LevelSizeT>) -> LevelClearResult {
+        if self.io.is_read_only() {
+            return Err(LevelMapError::IOError(StdIOError::with_message(
+                "cannot clear a read-only level hash".to_string(),
+                ErrorKind::PermissionDenied.into(),
+            )));
+        }
+
+        if let Some(level_size) = level_size {
+            // the levels are laid out for the new level size by the clearing
+            self.io.meta.write().km_level_size = level_size;
+        }
+
+        self.io.clear()?;
+        self.expand_count = 0;
+        self.occupancy_mut().set([0; LEVEL_COUNT_MAX as usize]);
+        self.insertion_failures = 0;
+        self.probe_stats = ProbeStats::default();
+        if level_size.is_some() {
+            self.rebuild_bloom()?;
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("clear");
+
+        self.sync_after_write()?;
+        Ok(())
+    }
+
+    /// Write a consistent copy of the index files to the given directory, without closing the level
+    /// hash. The changes are first written to the disk, and then the files are cloned if the file
+    /// system supports it (e.g. btrfs, XFS, F2FS), or copied otherwise. The copy has the same index
+    /// name as this level hash and can be opened like any other index.
+    ///
+    /// ## Parameters
+    ///
+    /// * `dir` - The directory to write the copy to. It is created if it does not exist.
+    pub fn snapshot_to(&mut self, dir: &Path) -> LevelSyncResult {
+        self.sync_all()?;
+        self.io.snapshot_to(dir)
+    }
+
+    /// Schedule the changes made to the level hash to be written to the disk, without waiting for
+    /// the writes to complete.
+    pub fn flush(&mut self) -> LevelSyncResult {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush()?;
+        }
+
+        self.io.commit_meta();
+        self.io.flush()?;
+        self.meta_flushes += 1;
+        Ok(())
+    }
+
+    /// Write the changes made to the level hash to the disk, waiting for the writes to complete.
+    pub fn sync_all(&mut self) -> LevelSyncResult {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush()?;
+        }
+
+        self.io.commit_meta();
+        self.io.sync_all()?;
+        self.meta_flushes += 1;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Close the level hash, writing the changes to the disk. The errors which occur when a level
+    /// hash is dropped (e.g. when the disk is full or the index files have been removed) are only
+    /// logged, this should be used instead of dropping the level hash to know whether the changes
+    /// were written.
+    pub fn close(mut self) -> LevelSyncResult {
+        // release the entries which were deleted while they were pinned before the final sync
+        self.io.unpin_all();
+        self.sync_all()
+    }
+
+    /// Whether the pages of the keymap are locked in memory. This is `false` if
+    /// [LevelHashOptions::lock_memory] is not set, or if the keymap could not be locked (e.g.
+    /// because `RLIMIT_MEMLOCK` is too low). The keymap is locked again after it is resized, which
+    /// may fail even if it was locked before.
+    pub fn is_memory_locked(&self) -> bool {
+        self.io.keymap.locked
+    }
+
+    /// Get the mode of the lock held on the index, or `None` for the in-memory level hashes.
+    pub fn lock_mode(&self) -> Option<LockMode> {
+        self.io.lock_mode()
+    }
+
+    /// Upgrade the shared lock held by a read-only level hash to an exclusive lock, so that the
+    /// level hash can be modified. This fails if another process holds a lock on the index. The
+    /// conversion is not atomic, if the exclusive lock cannot be acquired, the shared lock is
+    /// acquired again.
+    pub fn upgrade_lock(&mut self) -> LevelRemapResult {
+        self.io.upgrade_lock()
+    }
+
+    /// Downgrade the exclusive lock held on the index to a shared lock, so that the index can be
+    /// opened by the read-only level hashes in other processes. The changes are flushed before
+    /// the lock is downgraded, and the write operations fail until the lock is upgraded again.
+    pub fn downgrade_lock(&mut self) -> LevelSyncResult {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush()?;
+        }
+
+        self.io.downgrade_lock()
+    }
+
+    /// Record the given operation in the trace, if tracing is enabled.
+    fn trace_op(&mut self, op: TraceOp, key: Option<&LevelKeyT>, value_size: usize, ok: bool) {
+        if self.trace.is_none() {
+            return;
+        }
+
+        let key = key.map(|key| TraceKey {
+            fhash: self.fhash(key),
+            shash: self.shash(key),
+            size: key.len() as u32,
+        });
+
+        let trace = self.trace.as_mut().unwrap();
+        if let Err(why) = trace.record(op, key, value_size as u64, ok) {
+            log_error!("{}", why);
+        }
+    }
+
+    /// Notify the [LevelObserver] of the level hash (if any) of the given event.
+    fn notify(&mut self, event: LevelEvent<'_>) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer.on_event(&event);
+        }
+    }
+
+    /// Notify the [LevelObserver] of the index files which were resized since the last write
+    /// operation.
+    fn notify_resizes(&mut self) {
+        if self.observer.is_none() {
+            return;
+        }
+
+        let (val_size, km_size) = (self.io.meta.read().val_file_size, self.io.meta.km_size());
+        let (old_val_size, old_km_size) = mem::replace(&mut self.file_sizes, (val_size, km_size));
+        for (file, old_size, new_size) in [
+            (IndexFile::Values, old_val_size, val_size),
+            (IndexFile::Keymap, old_km_size, km_size),
+        ] {
+            if old_size != new_size {
+                self.notify(LevelEvent::Resized {
+                    file,
+                    old_size,
+                    new_size,
+                });
+            }
+        }
+    }
+
+    /// Notify the [LevelObserver] if the bytes written since the level hash was opened have
+    /// exceeded the next multiple of the write budget.
+    fn check_write_budget(&mut self) {
+        let Some(budget) = self.write_budget else {
+            return;
+        };
+
+        let written = self.write_stats().total_bytes();
+        if written < self.next_budget_mark {
+            return;
+        }
+
+        // a single write may exceed more than one multiple of the budget, it is reported once
+        self.next_budget_mark = (written / budget + 1).saturating_mul(budget);
+        self.notify(LevelEvent::WriteBudgetExceeded { written, budget });
+    }
+
+    /// Sync the changes made by a write operation according to the configured [SyncPolicy].
+    fn sync_after_write(&mut self) -> LevelSyncResult {
+        self.notify_resizes();
+        if self.in_batch {
+            // the batch is synced once all of its operations have been applied
+            return Ok(());
+        }
+
+        self.check_write_budget();
+
+        if let (Some(step), true) = (self.rehash_step, self.is_expanding()) {
+            // a failed step rolls back the expansion, the write itself has succeeded
+            let _ = self.rehash_buckets(step);
+        }
+
+        self.maybe_compact();
+
+        // the metadata is consistent between the write operations
+        self.io.commit_meta();
+
+        match self.sync_policy {
+            SyncPolicy::OnWrite => self.sync_all(),
+            SyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => {
+                self.sync_all()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count the occupied slots in the given level.
+    fn count_occupied(&self, level: Level) -> u32 {
+        let bucket_size = self.io.meta.read().km_bucket_size as _SlotIdxT;
+        let mut count = 0u32;
+        for bucket in 0..self.level_bucket_count(level) {
+            for slot in 0..bucket_size {
+                if self.io.is_occupied(level as _LevelIdxT, bucket, slot) {
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+}
+
+/// The operations on the entries through their [SlotHandle], which skip the lookup of the key.
+impl LevelHash {
+    /// Find the entry for the given key and get a handle to its slot.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to look up.
+    ///
+    /// ## Returns
+    ///
+    /// The handle to the slot of the entry if an entry is found, `None` otherwise.
+    pub fn find_handle(&self, key: &LevelKeyT) -> Option<SlotHandle> {
+        let key = self.transform_key(key);
+        let (entry, level, bucket, slot) = self.find_slot(&key)?;
+        if self.io.is_expired(&entry) {
+            return None;
+        }
+
+        Some(self.slot_handle(&key, &entry, level, bucket, slot))
+    }
+
+    /// Get the value associated with the given key, along with a handle to the slot of its entry.
+    ///
+    /// ## Parameters
+    ///
+    /// * `key` - The key to get the value for.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the value and the handle to the slot of the entry if an entry is found,
+    /// `None` otherwise.
+    pub fn get_with_handle(&self, key: &LevelKeyT) -> Option<(Vec<u8>, SlotHandle)> {
+        let key = self.transform_key(key);
+        let found = self.read_consistent(|| {
+            self.try_find_slot(&key)?
+                .filter(|(entry, ..)| !self.io.is_expired(entry))
+                .map(|(entry, level, bucket, slot)| {
+                    let value = self.io.verified_entry_value(&entry)?;
+                    Ok((value, self.slot_handle(&key, &entry, level, bucket, slot)))
+                })
+                .transpose()
+        });
+
+        match found {
+            Ok(found) => found,
+            Err(why) => {
+                log_error!("{:?}", why);
+                None
+            }
+        }
+    }
+
+    /// Check whether the given handle still refers to the entry it was returned for.
+    pub fn is_handle_valid(&self, handle: &SlotHandle) -> bool {
+        matches!(self.handle_entry(handle), Ok(Some(_)))
+    }
+
+    /// Get the value of the entry referred to by the given handle.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the value, or `None` if the handle is stale or the entry has expired.
+    pub fn get_at(&self, handle: &SlotHandle) -> Option<Vec<u8>> {
+        let value = self.handle_entry(handle).and_then(|entry| {
+            entry
+                .filter(|entry| !self.io.is_expired(entry))
+                .map(|entry| self.io.verified_entry_value(&entry))
+                .transpose()
+        });
+
+        match value {
+            Ok(value) => value,
+            Err(why) => {
+                log_error!("{:?}", why);
+                None
+            }
+        }
+    }
+
+    /// Update the entry referred to by the given handle with the new value, like [Self::update].
+    /// The new value is written to a new address in the values file, so the handle is refreshed
+    /// to refer to the updated entry.
+    ///
+    /// ## Parameters
+    ///
+    /// * `handle` - The handle to the slot of the entry.
+    /// * `new_value` - The new value for the entry.
+    ///
+    /// ## Returns
+    ///
+    /// The raw bytes of the previous value of the entry, [LevelUpdateError::StaleHandle] if the
+    /// handle is stale or [LevelUpdateError::SlotNotFound] if the entry has expired.
+    pub fn update_at(
+        &mut self,
+        handle: &mut SlotHandle,
+        new_value: &LevelValueT,
+    ) -> LevelUpdateResult {
+        if self.io.is_read_only() {
+            return Err(LevelUpdateError::ReadOnly);
+        }
+
+        self.check_value_size(new_value).into_lvl_upd_err()?;
+
+        let Some(entry) = self.handle_entry(handle)? else {
+            return Err(LevelUpdateError::StaleHandle);
+        };
+
+        let key = self.io.entry_key(&entry);
+        if self.io.is_expired(&entry) {
+            self.do_remove(&key);
+            return Err(LevelUpdateError::SlotNotFound);
+        }
+
+        let (level, bucket, slot) = (handle.level, handle.bucket, handle.slot);
+        let result = self.io.update_entry_value(level, bucket, slot, new_value);
+        if result.is_ok() {
+            // the slot points to the new entry once the value has been written
+            let entry = self.io.val_entry_for_slot(level, bucket, slot)?;
+            handle.addr = entry.map_or(handle.addr, |entry| entry.addr);
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("update_at");
+
+        self.trace_op(TraceOp::Update, Some(&key), new_value.len(), result.is_ok());
+        let value = result?;
+        self.sync_after_write()
+            .map_err(LevelInsertionError::from)
+            .into_lvl_upd_err()?;
+
+        Ok(value)
+    }
+
+    /// Remove the entry referred to by the given handle, like [Self::remove].
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the raw bytes of the value of the deleted entry, or `None` if the handle
+    /// is stale or the entry has expired.
+    pub fn remove_at(&mut self, handle: SlotHandle) -> Option<Vec<u8>> {
+        if self.io.is_read_only() {
+            log_error!("cannot remove an entry from a read-only level hash");
+            return None;
+        }
+
+        let entry = match self.handle_entry(&handle) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return None,
+            Err(why) => {
+                log_error!("{:?}", why);
+                return None;
+            }
+        };
+
+        let key = self.io.entry_key(&entry);
+        let expired = self.io.is_expired(&entry);
+        let chained = entry.chain_next(&self.io.values).is_some();
+        let slot_addr = self.io.slot_addr(handle.level, handle.bucket, handle.slot);
+        let value = self.io.delete_at_slot(slot_addr, &key, true);
+        if !chained {
+            // the slot is still occupied by the next entry in the chain otherwise
+            self.occupancy_mut().decrement(handle.level);
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("remove_at");
+
+        if let Err(why) = self.sync_after_write() {
+            log_error!("{}", why);
+        }
+
+        // the expired entry is removed, but it is reported as not found
+        let value = value.filter(|_| !expired);
+        self.trace_op(TraceOp::Remove, Some(&key), 0, value.is_some());
+        value
+    }
+
+    /// Create a handle to the given slot, which holds the given entry for the given (transformed)
+    /// key.
+    fn slot_handle(
+        &self,
+        key: &LevelKeyT,
+        entry: &ValuesEntry,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+    ) -> SlotHandle {
+        SlotHandle {
+            level,
+            bucket,
+            slot,
+            addr: entry.addr,
+            key_hash: self.fhash(key),
+            generation: self.generation(),
+        }
+    }
+
+    /// Get the entry referred to by the given handle, or `None` if the handle is stale.
+    fn handle_entry(
+        &self,
+        handle: &SlotHandle,
+    ) -> LevelResult<Option<ValuesEntry<'_>>, LevelReadError> {
+        // the slots are moved by the structural changes, which may also remove the slot
+        if handle.generation != self.generation() {
+            return Ok(None);
+        }
+
+        let entry = self
+            .io
+            .val_entry_for_slot(handle.level, handle.bucket, handle.slot)?;
+        Ok(entry.filter(|entry| {
+            entry.addr == handle.addr
+                && !entry.is_empty()
+                && self.fhash(&self.io.entry_key(entry)) == handle.key_hash
+        }))
+    }
+}
+
+/// The convenience methods for the values which are fixed-width integers (e.g. offsets in other
+/// files), or slices of them. The integers are stored in little-endian byte order, like the
+/// integers of the index files, so the values are portable between the hosts.
+impl LevelHash {
+    /// Insert the given key with a `u64` value, see [Self::insert].
+    pub fn insert_u64(&mut self, key: &LevelKeyT, value: u64) -> LevelInsertionResult {
+        self.insert(key, &value.to_le_bytes())
+    }
+
+    /// Get the `u64` value associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// The value if an entry is found and its value is 8 bytes long, `None` otherwise.
+    pub fn get_u64(&self, key: &LevelKeyT) -> Option<u64> {
+        let value = self.find_value(key)?;
+        Some(u64::from_le_bytes(value.try_into().ok()?))
+    }
+
+    /// Insert the given key with a `u32` value, see [Self::insert].
+    pub fn insert_u32(&mut self, key: &LevelKeyT, value: u32) -> LevelInsertionResult {
+        self.insert(key, &value.to_le_bytes())
+    }
+
+    /// Get the `u32` value associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// The value if an entry is found and its value is 4 bytes long, `None` otherwise.
+    pub fn get_u32(&self, key: &LevelKeyT) -> Option<u32> {
+        let value = self.find_value(key)?;
+        Some(u32::from_le_bytes(value.try_into().ok()?))
+    }
+
+    /// Insert the given key with a slice of `u64` values, see [Self::insert].
+    pub fn insert_u64s(&mut self, key: &LevelKeyT, values: &[u64]) -> LevelInsertionResult {
+        let mut value = vec![0u8; values.len() * SIZE_U64 as usize];
+        IOEndianness::write_u64_into(values, &mut value);
+        self.insert(key, &value)
+    }
+
+    /// Get the `u64` values associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// The values if an entry is found and the size of its value is a multiple of 8 bytes, `None`
+    /// otherwise.
+    pub fn get_u64s(&self, key: &LevelKeyT) -> Option<Vec<u64>> {
+        let value = self.find_value(key)?;
+        if !value.len().is_multiple_of(SIZE_U64 as usize) {
+            return None;
+        }
+
+        let mut values = vec![0u64; value.len() / SIZE_U64 as usize];
+        IOEndianness::read_u64_into(&value, &mut values);
+        Some(values)
+    }
+
+    /// Insert the given key with a slice of `u32` values, see [Self::insert].
+    pub fn insert_u32s(&mut self, key: &LevelKeyT, values: &[u32]) -> LevelInsertionResult {
+        let mut value = vec![0u8; values.len() * SIZE_U32 as usize];
+        IOEndianness::write_u32_into(values, &mut value);
+        self.insert(key, &value)
+    }
+
+    /// Get the `u32` values associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// The values if an entry is found and the size of its value is a multiple of 4 bytes, `None`
+    /// otherwise.
+    pub fn get_u32s(&self, key: &LevelKeyT) -> Option<Vec<u32>> {
+        let value = self.find_value(key)?;
+        if !value.len().is_multiple_of(SIZE_U32 as usize) {
+            return None;
+        }
+
+        let mut values = vec![0u32; value.len() / SIZE_U32 as usize];
+        IOEndianness::read_u32_into(&value, &mut values);
+        Some(values)
+    }
+}
+
+#[cfg(feature = "debug-invariants")]
+impl LevelHash {
+    /// Check the invariants of the level hash, panicking with a detailed report if any of them is
+    /// violated.
+    fn assert_invariants(&self, op: &str) {
+        let violations = self.io.check_invariants(&self.occupancy().counts);
+        if violations.is_empty() {
+            return;
+        }
+
+        let meta = self.io.meta.read();
+        panic!(
+            "level hash invariants violated after '{}':\n  - {}\n\nitem counts: {:?}\nexpand count: {}\nmeta: {:#?}",
+            op,
+            violations.join("\n  - "),
+            self.occupancy().counts,
+            self.expand_count,
+            meta
+        );
+    }
+}
+
+//noinspection DuplicatedCode
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::fs::File;
+    use std::io;
+    use std::io::ErrorKind;
+    use std::mem::offset_of;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::FileExt;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::cancel::CancellationToken;
+    use crate::expand::ExpandAfterFailures;
+    use crate::expand::ExpandAtLoadFactor;
+    use crate::expand::ExpandOnFailure;
+    use crate::expand::ExpandPolicy;
+    use crate::expand::NeverExpand;
+    use crate::fsck::Inconsistency;
+    use crate::fsck::IndexFile;
+    use crate::fsck::RepairReport;
+    use crate::fsck::SlotPos;
+    use crate::io::segmented::SegmentedFile;
+    use crate::io::IOEndianness;
+    use crate::key_transform::AsciiCaseInsensitive;
+    use crate::level_hash::BULK_LOAD_FACTOR;
+    use crate::level_hash::DEFAULT_HASH_SIPHASH13;
+    use crate::level_hash::LEVELS;
+    use crate::level_io::LevelHashIO;
+    use crate::level_io::ValEntryReadExt;
+    use crate::level_io::ValuesEntry;
+    use crate::level_io::LEVEL_KEYMAP_VERSION;
+    use crate::level_io::LEVEL_KEYMAP_VERSION_ALIGNED;
+    use crate::level_io::LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN;
+    use crate::level_io::LEVEL_KEYMAP_VERSION_STASH;
+    use crate::level_io::LEVEL_KEYMAP_VERSION_UNTAGGED;
+    use crate::level_io::LEVEL_VALUES_VERSION;
+    use crate::level_io::LEVEL_VALUES_VERSION_ENTRY_VERSIONS;
+    use crate::meta::MetaIO;
+    use crate::paths::Paths;
+    use crate::reprs::LevelMeta;
+    use crate::reprs::ValuesData;
+    use crate::result::LevelArchiveError;
+    use crate::result::LevelExpansionError;
+    use crate::result::LevelInitError;
+    use crate::result::LevelInitResult;
+    use crate::result::LevelInsertionError;
+    use crate::result::LevelMapError;
+    use crate::result::LevelReadError;
+    use crate::result::LevelShrinkError;
+    use crate::result::LevelUpdateError;
+    use crate::siphash::siphash13;
+    use crate::size::SIZE_U64;
+    use crate::test_macros::assert_matches;
+    use crate::types::_LevelIdxT;
+    use crate::util::align_8;
+    use crate::util::generate_seeds_deterministic;
+    use crate::util::page_size;
+    use crate::xxhash::xxh64;
+    use crate::DuplicateStats;
+    use crate::HashFn;
+    use crate::HealthAction;
+    use crate::LevelHash;
+    use crate::LevelHashOptions;
+    use crate::LevelStats;
+    use crate::LockMode;
+    use crate::ProbeOrder;
+    use crate::Level::L0;
+    use crate::Level::L1;
+    use crate::Level::L2;
+    use crate::Level::Stash;
+    use crate::LEVEL_COUNT_MAX;
+    use crate::LEVEL_SIZE_MAX;
+    use crate::MOVEMENT_DEPTH_MAX;
+    use crate::STASH_SLOTS_MAX;
+    use crate::VALUE_SIZE_MAX_DEFAULT;
+    use crate::VALUE_SPILL_THRESHOLD_MAX;
+
+    use byteorder::ByteOrder;
+    use byteorder::LittleEndian;
+    use gxhash::GxHasher;
+    use std::hash::Hasher;
+
+    fn gxhash(seed: u64, data: &[u8]) -> u64 {
+        let mut hasher = GxHasher::with_seed(seed as i64);
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    fn create_level_hash_3(
+        name: &str,
+        create_new: bool,
+        conf: impl Fn(&mut LevelHashOptions),
+    ) -> (LevelInitResult, String) {
+        let dir_path = format!("target/tests/level-hash/index-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if create_new && index_dir.exists() {
+            fs::remove_dir_all(&index_dir).expect("Failed to delete existing directory");
+        } else {
+            fs::create_dir_all(&index_dir).expect("Failed to create directories");
+        }
+
+        let (s1, s2) = generate_seeds_deterministic();
+        let mut options = LevelHash::options();
+        options
+            .index_dir(index_dir)
+            .index_name(name)
+            .seeds(s1, s2)
+            .hash_fns(self::gxhash, self::gxhash);
+
+        conf(&mut options);
+
+        (options.build(), dir_path)
+    }
+
+    fn create_level_hash_2(
+        name: &str,
+        create_new: bool,
+        conf: impl Fn(&mut LevelHashOptions),
+    ) -> (LevelHash, String) {
+        let (hash, dir) = create_level_hash_3(name, create_new, conf);
+        (hash.expect("failed to create level hash"), dir)
+    }
+
+    fn create_level_hash(
+        name: &str,
+        create_new: bool,
+        conf: impl Fn(&mut LevelHashOptions),
+    ) -> LevelHash {
+        create_level_hash_2(name, create_new, conf).0
+    }
+
+    fn default_level_hash(name: &str) -> LevelHash {
+        create_level_hash(name, true, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        })
+    }
+
+    #[test]
+    fn simple_insertion() {
+        let mut hash = default_level_hash("insert");
+
+        assert!(hash.insert(b"key1", b"value1").is_ok());
+        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+    }
+
+    #[test]
+    fn overflow() {
+        let mut hash = create_level_hash("overflow", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .stash_slots(8)
+                .auto_expand(false);
+        });
+
+        // the level hash overflows once the slots in all the levels and the stash are occupied
+        let capacity = hash.capacity_info().slots + 8;
+        for i in 0..capacity {
+            let key = format!("key{}", i).as_bytes().to_vec();
+            let value = format!("value{}", i).as_bytes().to_vec();
+            assert!(hash.insert(&key, &value).is_ok());
+        }
+        assert_eq!(hash.load_factor(), 1.0);
+        assert_eq!(hash.capacity_info().entries, capacity);
+
+        assert_matches!(
+            hash.insert(b"kkk", b"vvv").err(),
+            Some(LevelInsertionError::LevelOverflow)
+        );
+        assert_matches!(
+            hash.insert_replace(b"kkk", b"vvv").err(),
+            Some(LevelUpdateError::InsertionErr(
+                LevelInsertionError::LevelOverflow
+            ))
+        );
+
+        // an existing entry can still be replaced
+        assert_matches!(
+            hash.insert_replace(b"key0", b"value"),
+            Ok(Some(previous)) if previous == b"value0"
+        );
+    }
+
+    #[test]
+    fn insert_and_insert_replace_fail_alike() {
+        let create = |name: &str| {
+            create_level_hash(name, true, |options| {
+                options.level_size(2).bucket_size(4).auto_expand(false);
+            })
+        };
+
+        // both the insertions place the entries in the same slots, and fail for the same key
+        let mut inserted = create("fail-alike-insert");
+        let mut replaced = create("fail-alike-insert-replace");
+        let mut i = 0;
+        loop {
+            let key = format!("key{}", i).into_bytes();
+            let result = inserted.insert(&key, b"value");
+            let replace_result = replaced.insert_replace(&key, b"value");
+            match result {
+                Ok(()) => assert_matches!(replace_result, Ok(None)),
+                Err(LevelInsertionError::InsertionFailure(_)) => {
+                    assert_matches!(
+                        replace_result,
+                        Err(LevelUpdateError::InsertionErr(
+                            LevelInsertionError::InsertionFailure(_)
+                        ))
+                    );
+                    break;
+                }
+                Err(why) => panic!("unexpected insertion failure: {:?}", why),
+            }
+            i += 1;
+        }
+
+        assert!(i < inserted.total_slots());
+        assert_eq!(inserted.load_factor(), replaced.load_factor());
+        assert_eq!(inserted.stats().load_factor, inserted.load_factor());
+    }
+
+    #[test]
+    fn removal() {
+        let mut hash = default_level_hash("remove");
+        assert!(hash.insert(b"key1", b"value1").is_ok());
+        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+        assert_eq!(hash.remove(b"key1"), Some(b"value1".to_vec()));
+        assert_eq!(hash.get_value(b"key1"), vec![]);
+    }
+
+    #[test]
+    fn hash_clear() {
+        let mut hash = default_level_hash("clear");
+        assert!(hash.insert(b"key1", b"value1").is_ok());
+        assert!(hash.insert(b"key2", b"value2").is_ok());
+
+        hash.clear().expect("failed to clear level hash");
+
+        assert_eq!(hash.get_value(b"key1"), vec![]);
+        assert_eq!(hash.get_value(b"key2"), vec![]);
+    }
+
+    #[test]
+    fn clear_variants() {
+        let mut hash = create_level_hash("clear-variants", true, |options| {
+            options.level_size(2).bucket_size(4).bloom_filter(true);
+        });
+        for i in 0..100 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        let level_size = hash.level_size();
+        assert!(level_size > 2);
+        assert!(hash.stats().expand_count > 0);
+
+        hash.clear_keep_capacity().unwrap();
+        assert_eq!(hash.level_size(), level_size);
+        assert_eq!(hash.keys().count(), 0);
+        assert_eq!(hash.load_factor(), 0.0);
+        let stats = hash.stats();
+        assert_eq!(stats.expand_count, 0);
+        assert_eq!(stats.hit_probe_histogram.iter().sum::<u64>(), 0);
+        assert_eq!(stats.miss_probe_histogram.iter().sum::<u64>(), 0);
+        assert_eq!(hash.io.meta.read().val_next_addr, 1);
+
+        hash.insert(b"key", b"value").unwrap();
+        let km_size = hash.io.keymap.size;
+
+        // the level size is validated before the level hash is cleared
+        for invalid in [0, LEVEL_SIZE_MAX + 1] {
+            assert_matches!(
+                hash.clear_and_reset(invalid),
+                Err(LevelMapError::IOError(why)) if why.error.kind() == ErrorKind::InvalidInput
+            );
+        }
+        assert_eq!(hash.get_value(b"key"), b"value".to_vec());
+
+        hash.clear_and_reset(2).unwrap();
+        assert_eq!(hash.level_size(), 2);
+        assert_eq!(hash.keys().count(), 0);
+        assert_eq!(hash.stats().load_factor, 0.0);
+        assert!(hash.io.keymap.size < km_size);
+        assert!(hash.verify().is_empty());
+
+        for i in 0..10 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        drop(hash);
+
+        // the new level size is kept when the index is reopened
+        let hash = create_level_hash("clear-variants", false, |options| {
+            options.level_size(2).bucket_size(4).bloom_filter(true);
+        });
+        assert_eq!(hash.level_size(), 2);
+        assert_eq!(hash.keys().count(), 10);
+        assert!(hash.contains_key(b"key9"));
+    }
+
+    #[test]
+    fn value_update_for_non_existent_entry() {
+        let mut hash = default_level_hash("value_update_for_non_existent_entry");
+        assert!(hash.insert(b"k", b"v").is_ok());
+
+        let entry = hash.find_slot(b"k");
+        assert!(entry.is_some());
+
+        let entry = entry.unwrap();
+        assert_eq!(entry.0.key(&hash.io.values), b"k".to_vec());
+        assert_eq!(entry.0.value(&hash.io.values), b"v".to_vec());
+
+        assert_matches!(
+            hash.update(b"kk", b"vv"),
+            Err(LevelUpdateError::SlotNotFound)
+        );
+    }
+
+    #[test]
+    fn value_update_for_existing_entry_with_null_value() {
+        let mut hash = default_level_hash("update-existing-with-null-value");
+
+        assert!(hash.insert(b"k", &[]).is_ok());
+        let entry = hash.find_slot(b"k").unwrap();
+        assert_eq!(entry.0.key(&hash.io.values), b"k".to_vec());
+        assert_eq!(entry.0.value(&hash.io.values), vec![]);
+
+        assert_eq!(
+            hash.update(b"k", b"newV").expect("failed to update entry"),
+            vec![]
+        );
+
+        let entry = hash.find_slot(b"k").unwrap();
+        assert_eq!(entry.0.key(&hash.io.values), b"k".to_vec());
+        assert_eq!(entry.0.value(&hash.io.values), b"newV".to_vec());
+    }
+
+    #[test]
+    fn insert_or_merge() {
+        let mut hash = default_level_hash("insert-or-merge");
+
+        // reference counts stored as little endian u32
+        fn add(existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+            let count = existing.map_or(0, |v| u32::from_le_bytes(v.try_into().unwrap()));
+            let delta = u32::from_le_bytes(operand.try_into().unwrap());
+            (count + delta).to_le_bytes().to_vec()
+        }
+
+        let merged = hash
+            .insert_or_merge(b"refs", &1u32.to_le_bytes(), add)
+            .unwrap();
+        assert_eq!(merged, 1u32.to_le_bytes());
+
+        for _ in 0..4 {
+            hash.insert_or_merge(b"refs", &2u32.to_le_bytes(), add)
+                .unwrap();
+        }
+
+        assert_eq!(hash.get_value(b"refs"), 9u32.to_le_bytes());
+        assert_eq!(hash.keys().count(), 1);
+
+        // the merged value may be larger than the existing value
+        hash.insert(b"list", b"a").unwrap();
+        let merged = hash
+            .insert_or_merge(b"list", b"bcdef", |existing, operand| {
+                [existing.unwrap(), operand].concat()
+            })
+            .unwrap();
+        assert_eq!(merged, b"abcdef");
+        assert_eq!(hash.get_value(b"list"), b"abcdef");
+
+        assert_matches!(
+            hash.insert_or_merge(b"", b"v", |_, operand| operand.to_vec()),
+            Err(LevelUpdateError::InsertionErr(
+                LevelInsertionError::EmptyKey
+            ))
+        );
+    }
+
+    #[test]
+    fn remove_if() {
+        let mut hash = default_level_hash("remove-if");
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+
+        assert_eq!(hash.remove_if(b"key1", |value| value == b"value2"), None);
+        assert_eq!(hash.get_value(b"key1"), b"value1");
+
+        assert_eq!(
+            hash.remove_if(b"key1", |value| value == b"value1"),
+            Some(b"value1".to_vec())
+        );
+        assert!(!hash.contains_key(b"key1"));
+        assert_eq!(hash.remove_if(b"key1", |_| true), None);
+        assert_eq!(hash.get_value(b"key2"), b"value2");
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn compare_and_update() {
+        let mut hash = default_level_hash("compare-and-update");
+        hash.insert(b"key", b"value1").unwrap();
+
+        assert_matches!(
+            hash.compare_and_update(b"key", b"value2", b"value3"),
+            Err(LevelUpdateError::ValueMismatch(current)) if current == b"value1"
+        );
+        assert_eq!(hash.get_value(b"key"), b"value1");
+
+        let previous = hash
+            .compare_and_update(b"key", b"value1", b"a longer value2")
+            .unwrap();
+        assert_eq!(previous, b"value1");
+        assert_eq!(hash.get_value(b"key"), b"a longer value2");
+
+        assert_matches!(
+            hash.compare_and_update(b"missing", b"value1", b"value2"),
+            Err(LevelUpdateError::SlotNotFound)
+        );
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn slot_handles() {
+        let mut hash = default_level_hash("slot-handles");
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+        assert!(hash.find_handle(b"missing").is_none());
+
+        let (value, mut handle) = hash.get_with_handle(b"key1").unwrap();
+        assert_eq!(value, b"value1");
+        assert_eq!(hash.find_handle(b"key1"), Some(handle));
+        assert_eq!(hash.get_at(&handle).unwrap(), b"value1");
+
+        // the handle is refreshed by the update, the copies of the old handle are stale
+        let old_handle = handle;
+        let previous = hash.update_at(&mut handle, b"new value1").unwrap();
+        assert_eq!(previous, b"value1");
+        assert_eq!(hash.get_at(&handle).unwrap(), b"new value1");
+        assert_eq!(hash.get_value(b"key1"), b"new value1");
+        assert!(!hash.is_handle_valid(&old_handle));
+        let mut stale = old_handle;
+        assert_matches!(
+            hash.update_at(&mut stale, b"value"),
+            Err(LevelUpdateError::StaleHandle)
+        );
+
+        // the handle of an entry which was removed by its key is stale, even if another entry
+        // is inserted in its place
+        let handle2 = hash.find_handle(b"key2").unwrap();
+        hash.remove(b"key2").unwrap();
+        hash.insert(b"key3", b"value3").unwrap();
+        assert!(!hash.is_handle_valid(&handle2));
+        assert!(hash.get_at(&handle2).is_none());
+        assert!(hash.remove_at(handle2).is_none());
+
+        assert_eq!(hash.remove_at(handle).unwrap(), b"new value1");
+        assert!(!hash.contains_key(b"key1"));
+        assert!(hash.remove_at(handle).is_none());
+
+        // the structural changes invalidate all the handles
+        let handle3 = hash.find_handle(b"key3").unwrap();
+        hash.expand().unwrap();
+        assert!(!hash.is_handle_valid(&handle3));
+        assert_eq!(hash.get_value(b"key3"), b"value3");
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn update_in_place() {
+        let mut hash = create_level_hash("update-in-place", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .checksums(true)
+                .spill_threshold(16);
+        });
+        hash.insert(b"counter", &1u64.to_le_bytes()).unwrap();
+        hash.insert(b"spilled", &[1u8; 32]).unwrap();
+        let fingerprint = hash.content_fingerprint();
+        let addr = hash.find_slot(b"counter").unwrap().0.addr;
+
+        for _ in 0..10 {
+            hash.update_in_place(b"counter", |value| {
+                let counter = u64::from_le_bytes(value.try_into().unwrap());
+                value.copy_from_slice(&(counter + 1).to_le_bytes());
+            })
+            .unwrap();
+        }
+        assert_eq!(hash.get_value(b"counter"), 11u64.to_le_bytes());
+        assert_eq!(
+            hash.try_get_value(b"counter").unwrap().unwrap(),
+            11u64.to_le_bytes()
+        );
+        assert_eq!(hash.find_slot(b"counter").unwrap().0.addr, addr);
+
+        // the spilled values are updated with a new entry
+        hash.update_in_place(b"spilled", |value| value.fill(2))
+            .unwrap();
+        assert_eq!(hash.get_value(b"spilled"), [2u8; 32]);
+
+        // the pinned entries keep their value
+        let pin = hash.pin(b"counter").unwrap();
+        hash.update_in_place(b"counter", |value| value.fill(0))
+            .unwrap();
+        assert_eq!(hash.get_value(b"counter"), [0u8; 8]);
+        assert_eq!(hash.pinned_value(&pin).unwrap(), 11u64.to_le_bytes());
+        assert_ne!(hash.find_slot(b"counter").unwrap().0.addr, addr);
+        hash.unpin(pin);
+
+        assert_matches!(
+            hash.update_in_place(b"missing", |_| unreachable!()),
+            Err(LevelUpdateError::SlotNotFound)
+        );
+
+        hash.update_in_place(b"counter", |value| {
+            value.copy_from_slice(&1u64.to_le_bytes())
+        })
+        .unwrap();
+        hash.update_in_place(b"spilled", |value| value.fill(1))
+            .unwrap();
+        assert_eq!(hash.content_fingerprint(), fingerprint);
+        assert_eq!(hash.content_fingerprint(), hash.io.compute_fingerprint());
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn integer_values() {
+        let mut hash = default_level_hash("integer-values");
+        hash.insert_u64(b"u64", u64::MAX - 1).unwrap();
+        hash.insert_u32(b"u32", 42).unwrap();
+        hash.insert_u64s(b"u64s", &[1, 2, u64::MAX]).unwrap();
+        hash.insert_u32s(b"u32s", &[3, 4]).unwrap();
+        hash.insert_u64s(b"empty", &[]).unwrap();
+
+        assert_eq!(hash.get_u64(b"u64"), Some(u64::MAX - 1));
+        assert_eq!(hash.get_value(b"u64"), (u64::MAX - 1).to_le_bytes());
+        assert_eq!(hash.get_u32(b"u32"), Some(42));
+        assert_eq!(hash.get_u64s(b"u64s"), Some(vec![1, 2, u64::MAX]));
+        assert_eq!(hash.get_u32s(b"u32s"), Some(vec![3, 4]));
+        assert_eq!(hash.get_value(b"u32s"), [3, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(hash.get_u64s(b"empty"), Some(vec![]));
+
+        // the values of other sizes
+        assert_eq!(hash.get_u32(b"u64"), None);
+        assert_eq!(hash.get_u64(b"u32"), None);
+        assert_eq!(hash.get_u64s(b"u32"), None);
+        assert_eq!(
+            hash.get_u32s(b"u64s"),
+            Some(vec![1, 0, 2, 0, u32::MAX, u32::MAX])
+        );
+        assert_eq!(hash.get_u64(b"missing"), None);
+        assert_eq!(hash.get_u32s(b"missing"), None);
+    }
+
+    #[test]
+    fn key_transform() {
+        let mut hash = create_level_hash("key-transform", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .auto_expand(false)
+                .key_transform(AsciiCaseInsensitive);
+        });
+
+        hash.insert(b"MainActivity", b"1").unwrap();
+        assert_eq!(hash.get_value(b"mainactivity"), b"1");
+        assert!(hash.contains_key(b"MAINACTIVITY"));
+
+        hash.update(b"MAINactivity", b"3").unwrap();
+        assert_eq!(
+            hash.try_get_value(b"MainActivity").unwrap(),
+            Some(b"3".to_vec())
+        );
+        assert_eq!(
+            hash.keys().collect::<Vec<_>>(),
+            vec![b"mainactivity".to_vec()]
+        );
+        assert_eq!(hash.scan_prefix(b"MAIN").count(), 1);
+
+        assert_eq!(hash.remove(b"mainACTIVITY"), Some(b"3".to_vec()));
+        assert!(!hash.contains_key(b"MainActivity"));
+
+        // any function can be used as the transformation
+        let mut hash = create_level_hash("key-transform-fn", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .auto_expand(false)
+                .key_transform(|key: &[u8]| key.trim_ascii().to_vec());
+        });
+
+        hash.insert(b"  key ", b"value").unwrap();
+        assert_eq!(hash.get_value(b"key"), b"value");
+        assert_matches!(
+            hash.insert(b"   ", b"value"),
+            Err(LevelInsertionError::EmptyKey)
+        );
+    }
+
+    #[test]
+    fn existing_level_init() {
+        {
+            let mut hash = default_level_hash("init-existing");
+            hash.insert(b"key", b"value").unwrap();
+            hash.insert(b"null", &[]).unwrap();
+            hash.insert(
+                b"long",
+                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            )
+            .unwrap();
+
+            assert_eq!(hash.get_value(b"key"), b"value".to_vec());
+            assert_eq!(hash.get_value(b"null"), vec![]);
+            assert_eq!(
+                hash.get_value(b"long"),
+                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".to_vec()
+            );
+        }
+
+        {
+            let hash = create_level_hash("init-existing", false, |options| {
+                options.level_size(2).bucket_size(4).auto_expand(false);
+            });
+
+            assert_eq!(hash.get_value(b"key"), b"value".to_vec());
+            assert_eq!(hash.get_value(b"null"), vec![]);
+            assert_eq!(
+                hash.get_value(b"long"),
+                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn level_hash_expand() {
+        let mut hash = create_level_hash("expand", true, |options| {
+            options.level_size(5).bucket_size(10).auto_expand(false);
+        });
+
+        let slots = hash.total_slots() - hash.io.meta.read().km_bucket_size as u64;
+        for i in 0..slots {
+            let key = format!("key{}", i).as_bytes().to_vec();
+            let value = format!("value{}", i).as_bytes().to_vec();
+            hash.insert(&key, &value).expect("failed to insert entry");
+        }
+
+        println!("Inserted {} entries", slots);
+
+        for i in 0..slots {
+            let key = format!("key{}", i).as_bytes().to_vec();
+            let value = format!("value{}", i).as_bytes().to_vec();
+            assert_eq!(hash.get_value(&key), value);
+        }
+
+        hash.expand().expect("failed to expand level hash");
+
+        for i in 0..slots {
+            let key = format!("key{}", i).as_bytes().to_vec();
+            let value = format!("value{}", i).as_bytes().to_vec();
+            assert_eq!(hash.get_value(key.as_slice()), value.clone());
+        }
+    }
+
+    #[test]
+    fn failed_expansion_is_rolled_back() {
+        use std::cell::Cell;
+
+        thread_local! {
+            // whether all the keys have the same buckets, which fails the expansions
+            static COLLIDE: Cell<bool> = const { Cell::new(false) };
+        }
+
+        // the first and second bytes of the keys are their bucket indexes
+        fn first_byte(_: u64, data: &[u8]) -> u64 {
+            if COLLIDE.get() {
+                0
+            } else {
+                data[0] as u64
+            }
+        }
+        fn second_byte(_: u64, data: &[u8]) -> u64 {
+            if COLLIDE.get() {
+                0
+            } else {
+                data[1] as u64
+            }
+        }
+
+        let mut hash = create_level_hash("expand-rollback", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(1)
+                .auto_expand(false)
+                .movement_depth(0)
+                .hash_fns(first_byte, second_byte);
+        });
+
+        // A and C fill the buckets 0 and 1 in the top level, B and D fill the bottom level
+        let keys: [&[u8]; 4] = [&[0, 0, b'A'], &[4, 4, b'B'], &[1, 1, b'C'], &[5, 5, b'D']];
+        for key in keys {
+            hash.insert(key, key).unwrap();
+        }
+        assert_eq!(hash.occupancy().level(1), 2);
+
+        let keymap_size = hash.io.keymap.size;
+        let generation = hash.generation();
+
+        // B and D are moved to the same bucket of the new top level, which has a single slot
+        COLLIDE.set(true);
+        let result = hash.expand();
+        COLLIDE.set(false);
+        assert_matches!(
+            result,
+            Err(LevelExpansionError::UpdateError(
+                LevelUpdateError::InsertionErr(LevelInsertionError::InsertionFailure(_))
+            ))
+        );
+
+        assert_eq!(hash.level_size(), 2);
+        assert_eq!(hash.io.interim_lvl_addr, None);
+        assert_eq!(hash.io.keymap.size, keymap_size);
+        assert_eq!(hash.generation(), generation + 2);
+        assert_eq!(hash.occupancy().counts[..2], [2, 2]);
+        assert!(hash.verify().is_empty());
+        for key in keys {
+            assert_eq!(hash.get_value(key), key);
+        }
+
+        hash.expand().expect("failed to expand level hash");
+        assert_eq!(hash.level_size(), 3);
+        for key in keys {
+            assert_eq!(hash.get_value(key), key);
+        }
+    }
+
+    #[test]
+    fn movement_depth() {
+        // the first and second bytes of the keys are their bucket indexes
+        fn first_byte(_: u64, data: &[u8]) -> u64 {
+            data[0] as u64
+        }
+        fn second_byte(_: u64, data: &[u8]) -> u64 {
+            data[1] as u64
+        }
+
+        for depth in [1, 2] {
+            let mut hash = create_level_hash("movement-depth", true, |options| {
+                options
+                    .level_size(2)
+                    .bucket_size(1)
+                    .auto_expand(false)
+                    .movement_depth(depth)
+                    .hash_fns(first_byte, second_byte);
+            });
+
+            // A and B fill the buckets 0 and 1 in the top level, C fills bucket 0 in the bottom level
+            hash.insert(&[0, 1, b'A'], b"a").unwrap();
+            hash.insert(&[1, 2, b'B'], b"b").unwrap();
+            hash.insert(&[0, 0, b'C'], b"c").unwrap();
+
+            // D can be inserted only if B is moved to bucket 2, and then A is moved to bucket 1
+            let result = hash.insert(&[0, 0, b'D'], b"d");
+            if depth == 1 {
+                let Err(LevelInsertionError::InsertionFailure(diagnostics)) = result else {
+                    panic!("expected insertion failure, got {:?}", result);
+                };
+
+                let buckets = diagnostics
+                    .candidate_buckets
+                    .iter()
+                    .map(|b| (b.level, b.bucket, b.occupied, b.slots))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    buckets,
+                    vec![(L0, 0, 1, 1), (L0, 0, 1, 1), (L1, 0, 1, 1), (L1, 0, 1, 1)]
+                );
+                assert_eq!(diagnostics.expand_count, 0);
+                assert!(!diagnostics.expansion_attempted);
+                continue;
+            }
+
+            result.expect("failed to insert entry");
+            assert_eq!(hash.find_slot(&[0, 0, b'D']).map(|e| e.2), Some(0));
+            assert_eq!(hash.find_slot(&[0, 1, b'A']).map(|e| e.2), Some(1));
+            assert_eq!(hash.find_slot(&[1, 2, b'B']).map(|e| e.2), Some(2));
+            assert_eq!(hash.get_value(&[0, 0, b'C']), b"c");
+            assert_eq!(hash.get_value(&[0, 0, b'D']), b"d");
+        }
+    }
+
+    #[test]
+    fn expand_policies() {
+        // the first byte of the keys is their bucket index, the keys 0, 4 and 8 have the same
+        // buckets until the level hash is expanded
+        fn first_byte(_: u64, data: &[u8]) -> u64 {
+            data[0] as u64
+        }
+
+        fn create(policy: impl ExpandPolicy + Clone + 'static) -> LevelHash {
+            create_level_hash("expand-policies", true, |options| {
+                options
+                    .level_size(2)
+                    .bucket_size(1)
+                    .movement_depth(0)
+                    .hash_fns(first_byte, first_byte)
+                    .expand_policy(policy.clone());
+            })
+        }
+
+        let mut hash = create(NeverExpand);
+        hash.insert(&[0], b"value1").unwrap();
+        hash.insert(&[4], b"value2").unwrap();
+        assert_matches!(
+            hash.insert(&[8], b"value3"),
+            Err(LevelInsertionError::InsertionFailure(_))
+        );
+        assert_eq!(hash.level_size(), 2);
+
+        let mut hash = create(ExpandOnFailure);
+        hash.insert(&[0], b"value1").unwrap();
+        hash.insert(&[4], b"value2").unwrap();
+        hash.insert(&[8], b"value3").unwrap();
+        assert_eq!(hash.level_size(), 3);
+        assert_eq!(hash.get_value(&[8]), b"value3");
+
+        let mut hash = create(ExpandAfterFailures::new(2));
+        hash.insert(&[0], b"value1").unwrap();
+        hash.insert(&[4], b"value2").unwrap();
+        let Err(LevelInsertionError::InsertionFailure(diagnostics)) = hash.insert(&[8], b"value3")
+        else {
+            panic!("expected insertion failure");
+        };
+        assert!(!diagnostics.expansion_attempted);
+        hash.insert(&[8], b"value3").unwrap();
+        assert_eq!(hash.level_size(), 3);
+
+        // the load factor based policy is validated like the threshold option
+        let mut options = LevelHash::options();
+        options
+            .in_memory(true)
+            .expand_policy(ExpandAtLoadFactor(0.2));
+        assert_matches!(options.build().err(), Some(LevelInitError::InvalidArg(_)));
+    }
+
+    #[test]
+    fn three_levels() {
+        let conf = |options: &mut LevelHashOptions| {
+            options
+                .level_size(4)
+                .bucket_size(4)
+                .level_count(3)
+                .auto_expand(false);
+        };
+
+        let entries = 48;
+        {
+            let mut hash = create_level_hash("three-levels", true, conf);
+            assert_eq!(hash.level_count(), 3);
+            assert_eq!(hash.total_bucket_count(), 16 + 8 + 4);
+
+            for i in 0..entries {
+                let key = format!("key{}", i).into_bytes();
+                let value = format!("value{}", i).into_bytes();
+                hash.insert(&key, &value).expect("failed to insert entry");
+            }
+
+            hash.expand().expect("failed to expand level hash");
+            assert_eq!(hash.level_count(), 3);
+            assert_eq!(hash.total_bucket_count(), 32 + 16 + 8);
+
+            for i in 0..entries {
+                let key = format!("key{}", i).into_bytes();
+                let value = format!("value{}", i).into_bytes();
+                assert_eq!(hash.get_value(&key), value);
+            }
+        }
+
+        // the level count of an existing index is read from the index
+        let hash = create_level_hash("three-levels", false, |options| {
+            conf(options);
+            options.level_count(2);
+        });
+
+        assert_eq!(hash.level_count(), 3);
+        for i in 0..entries {
+            let key = format!("key{}", i).into_bytes();
+            let value = format!("value{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), value);
+        }
+    }
+
+    #[test]
+    // the invariants are checked after every insertion, which is too slow for this test
+    #[cfg_attr(feature = "debug-invariants", ignore)]
+    fn expansion_with_huge_slot_count() {
+        let mut hash = create_level_hash("expand-huge-slot-count", true, |options| {
+            options.level_size(15).bucket_size(4).auto_expand(false);
+        });
+
+        let mut i = 0u32;
+        while hash
+            .insert(
+                format!("key{}", i).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            )
+            .is_ok()
+        {
+            i += 1;
+        }
+
+        println!("inserted {} entries", i);
+        println!("load factor: {}", hash.load_factor());
+
+        hash.expand().expect("failed to expand level hash");
+
+        println!("load factor after expansion: {}", hash.load_factor());
+
+        for j in 0..i {
+            let key = format!("key{}", j).as_bytes().to_vec();
+            let value = format!("value{}", j).as_bytes().to_vec();
+            assert_eq!(hash.get_value(&key), value);
+        }
+    }
+
+    #[test]
+    fn level_hash_shrink() {
+        let (mut hash, dir) = create_level_hash_2("shrink", true, |options| {
+            options.level_size(3).bucket_size(4).auto_expand(false);
+        });
+
+        let keymap_file = Path::new(&dir).join(format!(
+            "shrink{}{}",
+            LevelHashIO::LEVEL_INDEX_EXT,
+            LevelHashIO::LEVEL_KEYMAP_EXT
+        ));
+        let keymap_len = || fs::metadata(&keymap_file).unwrap().len();
+
+        hash.expand().expect("failed to expand level hash");
+        hash.expand().expect("failed to expand level hash");
+        assert_eq!(hash.io.meta.read().km_level_size, 5);
+
+        for i in 0..100 {
+            let key = format!("key{}", i).into_bytes();
+            hash.insert(&key, b"value").expect("failed to insert entry");
+        }
+
+        assert_matches!(
+            hash.shrink(),
+            Err(LevelShrinkError::LoadFactorTooHigh { .. })
+        );
+
+        for i in 20..100 {
+            hash.remove(format!("key{}", i).as_bytes())
+                .expect("failed to remove entry");
+        }
+
+        let fingerprint = hash.content_fingerprint();
+        let expanded_len = keymap_len();
+
+        hash.shrink().expect("failed to shrink level hash");
+        hash.shrink().expect("failed to shrink level hash");
+
+        assert_eq!(hash.io.meta.read().km_level_size, 3);
+        assert_eq!(hash.total_bucket_count(), 8 + 4);
+        assert_eq!(hash.content_fingerprint(), fingerprint);
+        assert!(keymap_len() < expanded_len);
+        assert_eq!(
+            keymap_len(),
+            LevelHashIO::KEYMAP_HEADER_SIZE_BYTES
+                + (8 + 4) * 4 * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES
+        );
+
+        for i in 0..20 {
+            let key = format!("key{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), b"value".to_vec());
+        }
+
+        hash.shrink().expect("failed to shrink level hash");
+        assert_matches!(
+            hash.shrink(),
+            Err(LevelShrinkError::LoadFactorTooHigh { .. })
+        );
+
+        for i in 5..20 {
+            hash.remove(format!("key{}", i).as_bytes())
+                .expect("failed to remove entry");
+        }
+
+        hash.shrink().expect("failed to shrink level hash");
+        assert_matches!(hash.shrink(), Err(LevelShrinkError::MinLevelSizeReached));
+        drop(hash);
+
+        let hash = create_level_hash("shrink", false, |options| {
+            options.level_size(3).bucket_size(4).auto_expand(false);
+        });
+        assert_eq!(hash.io.meta.read().km_level_size, 1);
+        for i in 0..5 {
+            let key = format!("key{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), b"value".to_vec());
+        }
+    }
+
+    #[test]
+    fn open_existing_index() {
+        let index_dir = Path::new("target/tests/level-hash/index-open");
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+
+        assert_matches!(
+            LevelHash::open(index_dir, "open").err(),
+            Some(LevelInitError::IndexNotFound { .. })
+        );
+
+        {
+            let mut hash = LevelHash::options()
+                .index_dir(index_dir)
+                .index_name("open")
+                .level_size(3)
+                .bucket_size(6)
+                .level_count(3)
+                .build()
+                .expect("failed to create level hash");
+            hash.insert(b"key1", b"value1").unwrap();
+        }
+
+        {
+            let hash = LevelHash::open(index_dir, "open").expect("failed to open level hash");
+            assert_eq!(hash.level_size(), 3);
+            assert_eq!(hash.bucket_size(), 6);
+            assert_eq!(hash.level_count(), 3);
+            assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+        }
+
+        {
+            let mut hash = LevelHash::open(index_dir, "open").unwrap();
+            hash.io.meta.write().km_version = LEVEL_KEYMAP_VERSION_STASH + 1;
+        }
+
+        assert_matches!(
+            LevelHash::open(index_dir, "open").err(),
+            Some(LevelInitError::UnsupportedVersion { .. })
+        );
+    }
+
+    #[test]
+    fn stored_seeds() {
+        let mut hash = create_level_hash("stored-seeds", true, |options| {
+            options.seeds(1, 2);
+        });
+        hash.insert(b"key", b"value").unwrap();
+        drop(hash);
+
+        // the seeds are read from the index if they are not set
+        let hash = LevelHash::options()
+            .index_dir(Path::new("target/tests/level-hash/index-stored-seeds"))
+            .index_name("stored-seeds")
+            .hash_fns(gxhash, gxhash)
+            .open()
+            .expect("failed to open level hash");
+        assert_eq!(hash.get_value(b"key"), b"value");
+        drop(hash);
+
+        assert_matches!(
+            create_level_hash_3("stored-seeds", false, |options| {
+                options.seeds(2, 1);
+            })
+            .0
+            .err(),
+            Some(LevelInitError::SeedMismatch {
+                expected: (1, 2),
+                found: (2, 1)
+            })
+        );
+
+        assert_matches!(
+            create_level_hash_3("stored-seeds", false, |options| {
+                options.seeds(1, 2).hash_fns(xxh64, xxh64);
+            })
+            .0
+            .err(),
+            Some(LevelInitError::HashFnMismatch)
+        );
+    }
+
+    #[test]
+    fn default_seeds() {
+        let seeds = |from_entropy: bool| {
+            let hash = LevelHash::options()
+                .in_memory(true)
+                .seeds_from_entropy(from_entropy)
+                .build()
+                .expect("failed to create level hash");
+            (hash.seed_1, hash.seed_2)
+        };
+
+        assert_ne!(seeds(true), seeds(true));
+        assert_eq!(seeds(false), generate_seeds_deterministic());
+    }
+
+    #[test]
+    fn default_hash_fn() {
+        let dir = Path::new("target/tests/level-hash/index-default-hash-fn");
+        if dir.exists() {
+            fs::remove_dir_all(dir).expect("Failed to delete existing directory");
+        }
+
+        let open = |fns: Option<HashFn>| {
+            let mut options = LevelHash::options();
+            options
+                .index_dir(dir)
+                .index_name("default-hash-fn")
+                .seeds_from_entropy(false);
+            if let Some(hash_fn) = fns {
+                options.hash_fns(hash_fn, hash_fn);
+            }
+            options.build()
+        };
+
+        let mut hash = open(None).expect("failed to create level hash");
+        assert_eq!(hash.io.meta.read().km_default_hash, DEFAULT_HASH_SIPHASH13);
+        hash.insert(b"key", b"value").unwrap();
+        drop(hash);
+
+        assert_eq!(open(None).unwrap().get_value(b"key"), b"value");
+        assert_eq!(open(Some(siphash13)).unwrap().get_value(b"key"), b"value");
+        assert_matches!(
+            open(Some(xxh64)).err(),
+            Some(LevelInitError::HashFnMismatch)
+        );
+
+        // the indexes created before the seeds and the default hash function were stored keep
+        // using xxh64 with the deterministic seeds
+        fs::remove_dir_all(dir).expect("Failed to delete existing directory");
+        let mut hash = open(Some(xxh64)).expect("failed to create level hash");
+        hash.insert(b"key", b"value").unwrap();
+        let meta = hash.io.meta.write();
+        meta.km_seed_1 = 0;
+        meta.km_seed_2 = 0;
+        meta.km_hash_check = 0;
+        meta.km_default_hash = 0;
+        drop(hash);
+
+        assert_eq!(open(None).unwrap().get_value(b"key"), b"value");
+    }
+
+    #[test]
+    // the address of the next entry is beyond the values file to simulate a full values file
+    #[cfg_attr(feature = "debug-invariants", ignore)]
+    fn capacity_info() {
+        let mut hash = create_level_hash("capacity-info", true, |options| {
+            options.level_size(4).bucket_size(4).level_count(3);
+        });
+        hash.insert(b"key1", b"value1").unwrap();
+
+        let info = hash.capacity_info();
+        assert_eq!(info.entries, 1);
+        assert_eq!(info.slots, (16 + 8 + 4) * 4);
+        assert_eq!(info.max_slots, ((1 << 24) + (1 << 23) + (1 << 22)) * 4);
+        assert_eq!(info.values_size, hash.io.meta.read().val_next_addr - 1);
+        assert_eq!(info.max_values_size, (1 << LevelHashIO::SLOT_ADDR_BITS) - 1);
+        assert_eq!(info.max_value_size, VALUE_SIZE_MAX_DEFAULT);
+
+        // the entries whose address would not fit in the slots are rejected
+        let next_addr = hash.io.meta.read().val_next_addr;
+        hash.io.meta.write().val_next_addr = info.max_values_size + 1;
+        assert_matches!(
+            hash.insert(b"key2", b"value2"),
+            Err(LevelInsertionError::CapacityExhausted(_))
+        );
+        hash.io.meta.write().val_next_addr = next_addr;
+        assert!(!hash.contains_key(b"key2"));
+        assert_eq!(hash.get_value(b"key1"), b"value1");
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn snapshot() {
+        let snapshot_dir = Path::new("target/tests/level-hash/index-snapshot-copy");
+        if snapshot_dir.exists() {
+            fs::remove_dir_all(snapshot_dir).expect("Failed to delete existing directory");
+        }
+
+        let large = "large".repeat(100).into_bytes();
+        let mut hash = create_level_hash("snapshot", true, |options| {
+            options.level_size(3).bucket_size(4).spill_threshold(16);
+        });
+
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", &large).unwrap();
+        hash.snapshot_to(snapshot_dir)
+            .expect("failed to write snapshot");
+
+        // changes made after the snapshot are not in the snapshot
+        hash.insert(b"key3", b"value3").unwrap();
+        hash.remove(b"key1").unwrap();
+        hash.snapshot_to(snapshot_dir)
+            .expect("failed to overwrite snapshot");
+        hash.update(b"key3", b"updated").unwrap();
+
+        let copy = LevelHash::options()
+            .index_dir(snapshot_dir)
+            .index_name("snapshot")
+            .hash_fns(self::gxhash, self::gxhash)
+            .open()
+            .expect("failed to open snapshot");
+
+        assert_eq!(copy.get_value(b"key1"), vec![]);
+        assert_eq!(copy.get_value(b"key2"), large);
+        assert_eq!(copy.get_value(b"key3"), b"value3".to_vec());
+        assert_eq!(hash.get_value(b"key3"), b"updated".to_vec());
+        assert!(!snapshot_dir.join("snapshot.index._meta.tmp").exists());
+    }
+
+    #[test]
+    fn meta_after_expand() {
+        let mut hash = create_level_hash("meta-after-expand", true, |options| {
+            options.level_size(5).bucket_size(10).auto_expand(false);
+        });
+
+        let l0_size: u64 = hash.top_level_bucket_count() as u64
+            * hash.io.meta.read().km_bucket_size as u64
+            * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES;
+
+        {
+            let meta = hash.io.meta.read();
+            assert_eq!(meta.km_level_size, 5);
+            assert_eq!(meta.km_bucket_size, 10);
+            assert_eq!(meta.km_l0_addr, 0);
+            assert_eq!(meta.km_l1_addr, l0_size);
+        }
+
+        hash.expand().expect("failed to expand level hash");
+
+        {
+            let meta = hash.io.meta.read();
+            assert_eq!(meta.km_level_size, 6);
+            assert_eq!(meta.km_bucket_size, 10);
+            assert_eq!(meta.km_l0_addr, l0_size + (l0_size >> 1));
+            assert_eq!(meta.km_l1_addr, 0);
+        }
+    }
+
+    #[test]
+    fn expand_at_load_factor_threshold() {
+        let mut hash = create_level_hash("expand-at-threshold", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .load_factor_threshold(0.5);
+        });
+
+        // the threshold is reached after 12 of the 24 slots in both the levels are occupied
+        assert_eq!(hash.total_slots(), 24);
+        for i in 0..12 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        assert_eq!(hash.load_factor(), 0.5);
+        assert_eq!(hash.load_factor(), hash.stats().load_factor);
+        assert_eq!(hash.level_size(), 2);
+
+        hash.insert(b"key12", b"value").unwrap();
+        assert_eq!(hash.level_size(), 3);
+        assert_eq!(hash.total_slots(), hash.capacity_info().slots);
+        assert_eq!(hash.load_factor(), 13.0 / 48.0);
+        assert_eq!(hash.load_factor(), hash.stats().load_factor);
+
+        hash.remove(b"key12");
+        assert_eq!(hash.load_factor(), 0.25);
+    }
+
+    #[test]
+    fn occupancy_is_persisted() {
+        let create = |create_new| {
+            create_level_hash("occupancy-persisted", create_new, |options| {
+                options.level_size(3).bucket_size(4).auto_expand(false);
+            })
+        };
+
+        {
+            let mut hash = create(true);
+            for i in 0..10 {
+                hash.insert(format!("key{}", i).as_bytes(), b"value")
+                    .unwrap();
+            }
+            assert_eq!(hash.occupancy().total(), 10);
+        }
+
+        {
+            let mut hash = create(false);
+            assert_eq!(hash.occupancy().total(), 10);
+            assert_eq!(hash.load_factor(), 10.0 / 48.0);
+
+            // simulate an index created before the occupancy was stored
+            hash.occupancy_mut().valid = 0;
+            hash.occupancy_mut().counts = [0; 4];
+        }
+
+        let hash = create(false);
+        assert!(hash.occupancy().is_valid());
+        assert_eq!(hash.occupancy().total(), 10);
+        assert_eq!(
+            hash.occupancy().level(0) + hash.occupancy().level(1),
+            hash.count_occupied(L0) + hash.count_occupied(L1)
+        );
+    }
+
+    #[test]
+    fn verify_consistent_level_hash() {
+        let mut hash = default_level_hash("verify-consistent");
+        for i in 0..10 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        hash.remove(b"key3");
+
+        assert_eq!(hash.verify(), vec![]);
+        assert_eq!(hash.repair().unwrap(), RepairReport::default());
+    }
+
+    #[test]
+    fn repair_rebuilds_keymap() {
+        let (mut hash, dir) = create_level_hash_2("repair-keymap", true, |options| {
+            options.level_size(3).bucket_size(4).auto_expand(false);
+        });
+
+        for i in 0..16 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            hash.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        let slot_addr = |hash: &LevelHash, key: &[u8]| {
+            let (_, level, bucket, slot) = hash.find_slot(key).unwrap();
+            hash.io.slot_addr(level, bucket, slot)
+        };
+
+        let addr0 = hash.find_slot(b"key0").unwrap().0.addr;
+        let addr1 = hash.find_slot(b"key1").unwrap().0.addr;
+        let slot0 = slot_addr(&hash, b"key0");
+        let slot2 = slot_addr(&hash, b"key2");
+        let slot3 = slot_addr(&hash, b"key3");
+
+        hash.io.km_write_addr(slot0, 0);
+        hash.io.km_write_addr(slot2, addr1 + 1);
+        hash.io.km_write_addr(slot3, hash.io.values.size + 1);
+
+        let values_file = format!("{}/repair-keymap.index", dir);
+        File::options()
+            .write(true)
+            .open(&values_file)
+            .unwrap()
+            .write_all_at(&[0xFF; 8], 0)
+            .unwrap();
+
+        let inconsistencies = hash.verify();
+        assert!(inconsistencies.contains(&Inconsistency::UnreachableEntry { addr: addr0 }));
+        assert!(inconsistencies
+            .iter()
+            .any(|i| matches!(i, Inconsistency::SharedEntry { addr, .. } if *addr == addr1)));
+        assert!(inconsistencies
+            .iter()
+            .any(|i| matches!(i, Inconsistency::DanglingPointer { .. })));
+        assert!(inconsistencies
+            .iter()
+            .any(|i| matches!(i, Inconsistency::OccupancyMismatch { .. })));
+        assert!(inconsistencies.contains(&Inconsistency::BadMagicNumber {
+            file: IndexFile::Values,
+            expected: LevelHashIO::VALUES_MAGIC_NUMBER,
+            found: u64::MAX,
+        }));
+
+        let report = hash.repair().unwrap();
+        assert_eq!(report.inconsistencies, inconsistencies);
+        assert_eq!(report.relinked_entries, 16);
+        assert_eq!(report.dropped_entries, 0);
+
+        assert_eq!(hash.verify(), vec![]);
+        assert_eq!(hash.occupancy().total(), 16);
+        for i in 0..16 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            assert_eq!(hash.get_value(key.as_bytes()), value.as_bytes());
+        }
+    }
+
+    #[test]
+    fn meta_checkpoint_restored() {
+        let conf = |options: &mut LevelHashOptions| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        };
+        let (mut hash, dir) = create_level_hash_2("meta-checkpoint", true, conf);
+        for i in 0..8 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            hash.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        let next_addr = hash.io.meta.read().val_next_addr;
+        drop(hash);
+
+        // the metadata matches the checkpoint after the index is closed
+        let hash = create_level_hash("meta-checkpoint", false, conf);
+        assert!(!hash.io.meta.restored);
+        drop(hash);
+
+        // a crash while the metadata was being updated
+        let meta_file = format!(
+            "{}/meta-checkpoint{}{}",
+            dir,
+            LevelHashIO::LEVEL_INDEX_EXT,
+            LevelHashIO::LEVEL_META_EXT
+        );
+        let next_addr_off = offset_of!(LevelMeta, val_next_addr) as u64;
+        File::options()
+            .write(true)
+            .open(&meta_file)
+            .unwrap()
+            .write_all_at(&(next_addr / 2).to_le_bytes(), next_addr_off)
+            .unwrap();
+
+        let mut hash = create_level_hash("meta-checkpoint", false, conf);
+        assert!(hash.io.meta.restored);
+        assert_eq!(hash.io.meta.read().val_next_addr, next_addr);
+        assert!(hash.verify().is_empty());
+        for i in 0..8 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            assert_eq!(
+                hash.try_get_value(key.as_bytes()).unwrap(),
+                Some(value.into_bytes())
+            );
+        }
+        hash.insert(b"key8", b"value8").unwrap();
+        assert_eq!(
+            hash.try_get_value(b"key8").unwrap(),
+            Some(b"value8".to_vec())
+        );
+        let next_addr = hash.io.meta.read().val_next_addr;
+
+        // the metadata of an operation interrupted by a panic is not checkpointed
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            hash.io.meta.write().val_next_addr = next_addr / 2;
+            panic!("interrupted");
+        }));
+        assert!(panicked.is_err());
+
+        let mut hash = create_level_hash("meta-checkpoint", false, conf);
+        assert!(hash.io.meta.restored);
+        assert_eq!(hash.io.meta.read().val_next_addr, next_addr);
+
+        // the slot written by an interrupted insertion points past the restored metadata, and the
+        // entry is dropped by the repair
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let (_, free_slot) = hash.find_slot_or_free(b"key9").unwrap();
+            let (level, bucket, slot) = free_slot.unwrap();
+            let slot_addr = hash.io.slot_addr(level, bucket, slot);
+            hash.io
+                .append_entry_at_slot(slot_addr, b"key9", b"value9", None, None)
+                .unwrap();
+            panic!("interrupted");
+        }));
+        assert!(panicked.is_err());
+
+        let hash = create_level_hash("meta-checkpoint", false, conf);
+        assert!(hash.io.meta.restored);
+        assert_eq!(hash.io.meta.read().val_next_addr, next_addr);
+        assert!(hash.verify().is_empty());
+        assert_eq!(hash.try_get_value(b"key9").unwrap(), None);
+        assert_eq!(
+            hash.try_get_value(b"key8").unwrap(),
+            Some(b"value8".to_vec())
+        );
+    }
+
+    #[test]
+    fn open_with_recovery() {
+        let (mut hash, dir) = create_level_hash_2("recovery", true, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+
+        for i in 0..8 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            hash.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        let (_, level, bucket, slot) = hash.find_slot(b"key0").unwrap();
+        let slot0 = hash.io.slot_addr(level, bucket, slot);
+        drop(hash);
+
+        let open = |read_only: bool| {
+            let (s1, s2) = generate_seeds_deterministic();
+            let mut options = LevelHash::options();
+            options
+                .index_dir(Path::new(&dir))
+                .index_name("recovery")
+                .seeds(s1, s2)
+                .hash_fns(self::gxhash, self::gxhash)
+                .read_only(read_only);
+            options.open_with_recovery()
+        };
+
+        let index_file =
+            |ext: &str| format!("{}/recovery{}{}", dir, LevelHashIO::LEVEL_INDEX_EXT, ext);
+        let write_at = |ext: &str, off: u64, data: &[u8]| {
+            File::options()
+                .write(true)
+                .open(index_file(ext))
+                .unwrap()
+                .write_all_at(data, off)
+                .unwrap();
+        };
+
+        // the files with a bad magic number are not emptied
+        let keymap_size = fs::metadata(index_file(LevelHashIO::LEVEL_KEYMAP_EXT))
+            .unwrap()
+            .len();
+        write_at(LevelHashIO::LEVEL_KEYMAP_EXT, 0, &[0xFF; 8]);
+        assert_matches!(
+            open(false).err(),
+            Some(LevelInitError::Corrupt(found)) if found == [Inconsistency::BadMagicNumber {
+                file: IndexFile::Keymap,
+                expected: LevelHashIO::KEYMAP_MAGIC_NUMBER,
+                found: u64::MAX,
+            }]
+        );
+        assert_eq!(
+            fs::metadata(index_file(LevelHashIO::LEVEL_KEYMAP_EXT))
+                .unwrap()
+                .len(),
+            keymap_size
+        );
+        write_at(
+            LevelHashIO::LEVEL_KEYMAP_EXT,
+            0,
+            &LevelHashIO::KEYMAP_MAGIC_NUMBER.to_le_bytes(),
+        );
+
+        // the corrupt metadata would be restored from the checkpoint pages
+        File::options()
+            .write(true)
+            .open(index_file(LevelHashIO::LEVEL_META_EXT))
+            .unwrap()
+            .set_len(MetaIO::META__SIZE_BYTES)
+            .unwrap();
+
+        let level_size_off = offset_of!(LevelMeta, km_level_size) as u64;
+        write_at(
+            LevelHashIO::LEVEL_META_EXT,
+            level_size_off,
+            &[LEVEL_SIZE_MAX + 1],
+        );
+        assert_matches!(
+            open(false).err(),
+            Some(LevelInitError::Corrupt(found))
+                if matches!(found.as_slice(), [Inconsistency::InvalidLayout(_)])
+        );
+        write_at(LevelHashIO::LEVEL_META_EXT, level_size_off, &[2]);
+
+        // a slot which points inside the values file, but not to the start of an entry
+        write_at(
+            LevelHashIO::LEVEL_KEYMAP_EXT,
+            LevelHashIO::km_real_offset(slot0),
+            &5u64.to_le_bytes(),
+        );
+        #[cfg(not(feature = "debug-invariants"))]
+        {
+            let hash = create_level_hash("recovery", false, |_| {});
+            assert_matches!(
+                hash.try_get_value(b"key0"),
+                Err(LevelReadError::CorruptIndex { addr: 4, .. })
+            );
+        }
+
+        assert_matches!(
+            open(true).err(),
+            Some(LevelInitError::Corrupt(found))
+                if matches!(found.as_slice(), [Inconsistency::DanglingPointer { addr: 5, .. }])
+        );
+
+        let hash = open(false).unwrap();
+        assert_eq!(hash.verify(), vec![]);
+        for i in 0..8 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            assert_eq!(hash.get_value(key.as_bytes()), value.as_bytes());
+        }
+    }
+
+    #[test]
+    fn for_each_slot() {
+        let mut hash = create_level_hash("for-each-slot", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .auto_expand(false)
+                .spill_threshold(16);
+        });
+
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i).repeat(i + 1);
+            hash.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        let mut slots = 0;
+        let mut entries = Vec::new();
+        hash.for_each_slot(|level, bucket, slot, entry| {
+            slots += 1;
+            let Some(entry) = entry else {
+                return;
+            };
+
+            let key = entry.key();
+            let (found, found_level, found_bucket, found_slot) = hash.find_slot(&key).unwrap();
+            assert_eq!(
+                (level as _LevelIdxT, bucket, slot),
+                (found_level, found_bucket, found_slot)
+            );
+            assert_eq!(entry.addr(), found.addr);
+            assert_eq!(entry.size(), found.esize());
+            assert_eq!(entry.is_spilled(), entry.value().len() > 16);
+            assert!(!entry.is_compressed());
+            assert_eq!(entry.expires_at(), None);
+            assert!(!entry.has_older_entries());
+            entries.push((key, entry.value()));
+        });
+
+        // 4 buckets in the top level and 2 in the bottom level, with 4 slots each
+        assert_eq!(slots, (4 + 2) * 4);
+        entries.sort();
+        let mut expected = (0..10)
+            .map(|i| {
+                let key = format!("key{}", i).into_bytes();
+                let value = format!("value{}", i).repeat(i + 1).into_bytes();
+                (key, value)
+            })
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn partitions() {
+        let mut hash = create_level_hash("partitions", true, |options| {
+            options
+                .level_size(3)
+                .bucket_size(4)
+                .auto_expand(false)
+                .stash_slots(4)
+                .spill_threshold(16);
+        });
+
+        let mut expected = Vec::new();
+        for i in 0..30 {
+            let key = format!("key{}", i).into_bytes();
+            let value = format!("value{}", i).repeat(i % 4 + 1).into_bytes();
+            hash.insert(&key, &value).unwrap();
+            expected.push((key, value));
+        }
+        expected.sort();
+
+        // 8 buckets in the top level, 4 in the bottom level and the stash
+        let partitions = hash.partitions(4);
+        assert_eq!(
+            partitions.iter().map(|p| p.buckets()).collect::<Vec<_>>(),
+            vec![0..3, 3..6, 6..9, 9..13]
+        );
+
+        let mut entries = std::thread::scope(|scope| {
+            let scans = partitions
+                .into_iter()
+                .map(|partition| scope.spawn(move || partition.collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+            scans
+                .into_iter()
+                .flat_map(|scan| scan.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        entries.sort();
+        assert_eq!(entries, expected);
+
+        assert_eq!(hash.partitions(0).len(), 1);
+        assert_eq!(hash.partitions(100).len(), 13);
+        let mut entries = hash
+            .partitions(100)
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, expected);
+
+        // scanning the buckets of a cursor in batches
+        #[cfg(feature = "jni")]
+        {
+            let mut entries = (0..13)
+                .step_by(5)
+                .flat_map(|start| {
+                    let cursor = hash.partitions(1).pop().unwrap();
+                    cursor.take_buckets(start..start + 5).collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            entries.sort();
+            assert_eq!(entries, expected);
+            let cursor = hash.partitions(2).pop().unwrap();
+            assert_eq!(cursor.take_buckets(0..8).buckets(), 6..8);
+        }
+    }
+
+    #[test]
+    fn slot_tags() {
+        let slot_entry = |hash: &LevelHash, key: &[u8]| {
+            let (_, level, bucket, slot) = hash.find_slot(key).unwrap();
+            let slot_addr = hash.io.slot_addr(level, bucket, slot);
+            (slot_addr, hash.io.keymap.r_u64(slot_addr))
+        };
+
+        {
+            let mut hash = default_level_hash("slot-tags");
+            assert_eq!(hash.io.meta.read().km_version, LEVEL_KEYMAP_VERSION);
+
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.insert(b"key2", b"value2").unwrap();
+
+            let (slot_addr, entry) = slot_entry(&hash, b"key1");
+            let tag = LevelHashIO::slot_tag(entry);
+            assert_ne!(tag, LevelHashIO::SLOT_TAG_NONE);
+            assert_eq!(tag, LevelHashIO::key_tag(b"key1"));
+
+            // the entry is not found with the tag of another key
+            let addr = LevelHashIO::slot_val_addr(entry);
+            let other_tag = tag.wrapping_add(1).max(1) as u64;
+            hash.io
+                .km_write_addr(slot_addr, addr | other_tag << LevelHashIO::SLOT_ADDR_BITS);
+            assert_eq!(hash.try_get_value(b"key1").unwrap(), None);
+            assert_matches!(
+                hash.verify().as_slice(),
+                [Inconsistency::TagMismatch { addr: a, .. }] if *a == addr - 1
+            );
+
+            hash.repair().unwrap();
+            assert_eq!(hash.verify(), vec![]);
+            assert_eq!(hash.get_value(b"key1"), b"value1");
+
+            // the untagged slots are always compared
+            let (slot_addr, entry) = slot_entry(&hash, b"key2");
+            hash.io
+                .km_write_addr(slot_addr, LevelHashIO::slot_val_addr(entry));
+            assert_eq!(hash.get_value(b"key2"), b"value2");
+            assert_eq!(hash.verify(), vec![]);
+
+            hash.io.meta.write().km_version = LEVEL_KEYMAP_VERSION_UNTAGGED;
+        }
+
+        // the keymaps of the older versions are not tagged
+        let mut hash = create_level_hash("slot-tags", false, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+        assert_eq!(
+            hash.io.meta.read().km_version,
+            LEVEL_KEYMAP_VERSION_UNTAGGED
+        );
+
+        hash.insert(b"key3", b"value3").unwrap();
+        let (_, entry) = slot_entry(&hash, b"key3");
+        assert_eq!(LevelHashIO::slot_tag(entry), LevelHashIO::SLOT_TAG_NONE);
+        for i in 1..=3 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            assert_eq!(hash.get_value(key.as_bytes()), value.as_bytes());
+        }
+        assert_eq!(hash.verify(), vec![]);
+    }
+
+    #[test]
+    fn repair_drops_corrupt_entries() {
+        let mut hash = create_level_hash("repair-corrupt", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .auto_expand(false)
+                .checksums(true);
+        });
+
+        for i in 0..5 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+
+        let (entry, level, bucket, slot) = hash.find_slot(b"key1").unwrap();
+        let addr = entry.addr;
+        hash.io
+            .values
+            .write_at(addr + ValuesEntry::OFF_KEY + 4, b"X");
+
+        let slot = SlotPos {
+            level: LEVELS[level as usize],
+            bucket,
+            slot,
+        };
+        assert!(hash
+            .verify()
+            .contains(&Inconsistency::CorruptEntry { slot, addr }));
+
+        let report = hash.repair().unwrap();
+        assert_eq!(report.relinked_entries, 4);
+        assert_eq!(report.dropped_entries, 1);
+        assert!(!hash.contains_key(b"key1"));
+        assert_eq!(hash.get_value(b"key2"), b"value");
+        assert_eq!(hash.verify(), vec![]);
+    }
+
+    #[test]
+    fn values_file_binary_repr() {
+        let file_name = "values-binary-repr";
+        let mut hash = create_level_hash(file_name, true, |options| {
+            options.auto_expand(false);
+        });
+
+        // min_size + "key1".len() + "value1".len()
+        let entry_size = ValuesEntry::ENTRY_SIZE_MIN + 4 + 6;
+
+        for i in 0..10 {
+            let key = format!("key{}", i).as_bytes().to_vec();
+            let value = format!("value{}", i).as_bytes().to_vec();
+            assert!(hash.insert(&key, &value).is_ok());
+        }
+
+        let index_file = &format!(
+            "target/tests/level-hash/index-{}/{}.index",
+            file_name, file_name
+        );
+        let mut input = fs::read(&index_file).expect("Unable to read index file");
+        let input = input.as_mut_slice();
+
+        assert_eq!(
+            IOEndianness::read_u64(input),
+            LevelHashIO::VALUES_MAGIC_NUMBER
+        );
+
+        let pos = SIZE_U64; // magic number
+        let input = &mut input[pos as usize..];
+
+        // since no entries are removed here, values should be laid out sequentially in the file
+        let mut pos = 0;
+        for i in 0..10 {
+            let data = unsafe { &*(input.as_ptr().add(pos as usize) as *const ValuesData) };
+
+            assert_eq!(data.key_size, 4);
+            assert_eq!(data.value_size, 6);
+            assert_eq!(
+                &input[(pos + ValuesEntry::OFF_KEY) as usize
+                    ..(pos + ValuesEntry::OFF_KEY + data.key_size as u64) as usize],
+                format!("key{}", i).as_bytes()
+            );
+            assert_eq!(
+                &input[(pos + ValuesEntry::OFF_KEY + data.key_size as u64) as usize
+                    ..(pos + ValuesEntry::OFF_KEY + data.key_size as u64 + data.value_size as u64)
+                        as usize],
+                format!("value{}", i).as_bytes()
+            );
+
+            pos = align_8(pos + entry_size); // + magic number len
+        }
+    }
+
+    #[test]
+    fn v1_index_is_migrated() {
+        {
+            let mut hash = default_level_hash("migrate-v1");
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.io.meta.write().km_version = LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN;
+        }
+
+        let hash = create_level_hash("migrate-v1", false, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+
+        assert_eq!(
+            hash.io.meta.read().km_version,
+            LEVEL_KEYMAP_VERSION_UNTAGGED
+        );
+        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+
+        // the files are not rewritten, so they are not backed up
+        assert!(!Path::new("target/tests/level-hash/index-migrate-v1")
+            .join("migrate-v1.index.backup-little-endian")
+            .exists());
+    }
+
+    #[test]
+    fn v1_big_endian_index_is_migrated() {
+        let large = "large".repeat(100).into_bytes();
+        let conf = |options: &mut LevelHashOptions| {
+            options
+                .level_size(4)
+                .bucket_size(4)
+                .spill_threshold(16)
+                .checksums(true)
+                .bloom_filter(true);
+        };
+
+        let (mut hash, dir) = create_level_hash_2("migrate-v1-be", true, conf);
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", &large).unwrap();
+        hash.remove(b"key1");
+        hash.insert(b"key3", b"value3").unwrap();
+
+        // convert the files to the layout written by v1 on a big-endian host
+        let entries = hash
+            .io
+            .values_iter()
+            .map(|entry| {
+                let value_off = entry.addr + ValuesEntry::OFF_KEY + entry.key_size() as u64;
+                (entry.addr, entry.esize(), entry.is_spilled(), value_off)
+            })
+            .collect::<Vec<_>>();
+
+        for (addr, esize, spilled, value_off) in entries {
+            let values = &mut hash.io.values;
+            for off in [ValuesEntry::OFF_KEY_SIZE, ValuesEntry::OFF_VAL_SIZE] {
+                let size = values.r_u32(addr + off);
+                values.w_u32(addr + off, size.swap_bytes());
+            }
+            if spilled {
+                for off in [value_off, value_off + SIZE_U64] {
+                    let value = values.r_u64(off);
+                    values.w_u64(off, value.swap_bytes());
+                }
+            }
+
+            let checksum_off = addr + esize - ValuesEntry::CHECKSUM_SIZE;
+            let checksum = ValuesEntry::checksum(values.slice(addr, checksum_off - addr));
+            values.w_u32(checksum_off, checksum.swap_bytes());
+        }
+
+        hash.io.swap_keymap();
+        hash.io.meta.write().km_version = LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN;
+        hash.io.meta.write().swap_bytes();
+        drop(hash);
+
+        let index_file = format!("{}/migrate-v1-be.index", dir);
+        let file = File::options().write(true).open(&index_file).unwrap();
+        let mut magic = [0u8; SIZE_U64 as usize];
+        IOEndianness::write_u64(&mut magic, LevelHashIO::VALUES_MAGIC_NUMBER.swap_bytes());
+        file.write_all_at(&magic, 0).unwrap();
+        drop(file);
+
+        // the meta files written by v1 do not have the checkpoint pages
+        File::options()
+            .write(true)
+            .open(format!("{}{}", index_file, LevelHashIO::LEVEL_META_EXT))
+            .unwrap()
+            .set_len(MetaIO::META__SIZE_BYTES)
+            .unwrap();
+
+        let (hash, _) = create_level_hash_2("migrate-v1-be", false, conf);
+        assert_eq!(
+            hash.io.meta.read().km_version,
+            LEVEL_KEYMAP_VERSION_UNTAGGED
+        );
+        assert_eq!(hash.io.meta.read().km_level_size, 4);
+        assert_eq!(hash.try_get_value(b"key1").unwrap(), None);
+        assert_eq!(hash.try_get_value(b"key2").unwrap(), Some(large));
+        assert_eq!(
+            hash.try_get_value(b"key3").unwrap(),
+            Some(b"value3".to_vec())
+        );
+
+        // the files are backed up in the big-endian byte order before they are converted
+        let backup = format!("{}/migrate-v1-be.index.backup-little-endian", dir);
+        let backup_meta = fs::read(format!("{}/migrate-v1-be.index._meta", backup)).unwrap();
+        let version_off = offset_of!(LevelMeta, km_version);
+        assert_eq!(
+            IOEndianness::read_u32(&backup_meta[version_off..]),
+            LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN.swap_bytes()
+        );
+    }
+
+    #[test]
+    fn bad_magic_number_is_not_emptied() {
+        let (mut hash, dir) = create_level_hash_2("bad-magic", true, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+        hash.insert(b"key1", b"value1").unwrap();
+        drop(hash);
+
+        let index_file = format!("{}/bad-magic.index", dir);
+        let size = fs::metadata(&index_file).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&index_file)
+            .unwrap()
+            .write_all_at(&[0xFF; 8], 0)
+            .unwrap();
+
+        let (s1, s2) = generate_seeds_deterministic();
+        let result = LevelHash::options()
+            .index_dir(Path::new(&dir))
+            .index_name("bad-magic")
+            .seeds(s1, s2)
+            .hash_fns(self::gxhash, self::gxhash)
+            .build();
+        assert_matches!(
+            result.err(),
+            Some(LevelInitError::Corrupt(found)) if found == [Inconsistency::BadMagicNumber {
+                file: IndexFile::Values,
+                expected: LevelHashIO::VALUES_MAGIC_NUMBER,
+                found: u64::MAX,
+            }]
+        );
+        assert_eq!(fs::metadata(&index_file).unwrap().len(), size);
+    }
+
+    #[test]
+    fn test_file_lock_is_acquired() {
+        let file_name = "check-file-lock-acquired";
+        let (_hash, dir) = create_level_hash_2(file_name, true, |_| {});
+        let lock_path = Path::new(&dir).join(&format!("{}.index.lock", file_name));
+        assert!(lock_path.exists());
+
+        let lock_file = File::options()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(lock_path)
+            .unwrap();
+
+        // assert that trying to acquire an exclusive lock on the lock file would block
+        assert_eq!(
+            unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
+            -1
+        );
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error().unwrap(),
+            libc::EWOULDBLOCK
+        );
+    }
+
+    #[test]
+    fn test_file_lock_is_released_on_drop() {
+        let file_name = "check-file-lock-released-on-drop";
+        let (hash, dir) = create_level_hash_2(file_name, true, |_| {});
+        let lock_path = Path::new(&dir).join(&format!("{}.index.lock", file_name));
+        assert!(lock_path.exists());
+
+        let lock_file = File::options()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(lock_path)
+            .unwrap();
+
+        assert_eq!(
+            unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
+            -1
+        );
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error().unwrap(),
+            libc::EWOULDBLOCK
+        );
+
+        drop(hash);
+
+        assert_eq!(
+            unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
+            0
+        );
+    }
+
+    #[test]
+    fn stale_lock_files() {
+        let name = "stale-lock-files";
+        let (hash, dir) = create_level_hash_2(name, true, |_| {});
+        let lock_path = Path::new(&dir).join(format!("{}.index.lock", name));
+        let mut options = LevelHash::options();
+        options.index_dir(Path::new(&dir)).index_name(name);
+
+        let owner = options.lock_owner().unwrap().unwrap();
+        assert_eq!(owner.pid, std::process::id());
+        assert!(owner.is_running());
+        assert_matches!(
+            options.force_unlock(),
+            Err(LevelInitError::Locked { owner: Some(locked) }) if locked == owner
+        );
+
+        // the lock file is deleted when the index is closed
+        drop(hash);
+        assert!(!lock_path.exists());
+        assert_eq!(options.lock_owner().unwrap(), None);
+        assert!(!options.force_unlock().unwrap());
+
+        // the lock file of a process which crashed during a previous boot
+        fs::write(&lock_path, "1\nprevious-boot\n").unwrap();
+        let owner = options.lock_owner().unwrap().unwrap();
+        assert_eq!(owner.pid, 1);
+        assert!(!owner.is_running());
+
+        // the index can still be opened
+        let hash = create_level_hash(name, false, |_| {});
+        assert!(options.lock_owner().unwrap().unwrap().is_running());
+        drop(hash);
+
+        fs::write(&lock_path, "1\nprevious-boot\n").unwrap();
+        assert!(options.force_unlock().unwrap());
+        assert!(!lock_path.exists());
+
+        // the readers do not delete the lock file while another reader holds the lock
+        let read_only = |options: &mut LevelHashOptions| {
+            options.read_only(true);
+        };
+        let reader1 = create_level_hash(name, false, read_only);
+        let reader2 = create_level_hash(name, false, read_only);
+        assert_eq!(options.lock_owner().unwrap(), None);
+        drop(reader1);
+        assert!(lock_path.exists());
+        drop(reader2);
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_level_hash_creation_fails_if_lock_acquired() {
+        let file_name = "check-level-fail-if-lock-acquired";
+        let (_hash, dir) = create_level_hash_2(file_name, true, |_| {});
+        let lock_path = Path::new(&dir).join(&format!("{}.index.lock", file_name));
+        assert!(lock_path.exists());
+
+        let (result, _) = create_level_hash_3(file_name, false, |_| {});
+        match result.err() {
+            Some(err) => match err {
+                LevelInitError::IOError(io) => {
+                    assert_eq!(io.error.raw_os_error().unwrap(), libc::EWOULDBLOCK);
+                }
+                _ => panic!("expected IO err"),
+            },
+            None => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn shared_locks() {
+        let name = "shared-locks";
+        let read_only = |options: &mut LevelHashOptions| {
+            options.read_only(true);
+        };
+
+        let (result, _) = create_level_hash_3(name, true, read_only);
+        assert_matches!(result.err(), Some(LevelInitError::IndexNotFound { .. }));
+
+        {
+            let mut writer = create_level_hash(name, false, |_| {});
+            assert_eq!(writer.lock_mode(), Some(LockMode::Exclusive));
+            writer.insert(b"key1", b"value1").unwrap();
+
+            // the readers cannot open the index while it is being written
+            let (result, _) = create_level_hash_3(name, false, read_only);
+            assert_matches!(result.err(), Some(LevelInitError::IOError(_)));
+        }
+
+        let mut reader1 = create_level_hash(name, false, read_only);
+        let reader2 = create_level_hash(name, false, read_only);
+        assert_eq!(reader1.lock_mode(), Some(LockMode::Shared));
+        assert_eq!(reader1.get_value(b"key1"), b"value1".to_vec());
+        assert_eq!(reader2.get_value(b"key1"), b"value1".to_vec());
+
+        let (result, _) = create_level_hash_3(name, false, |_| {});
+        assert_matches!(result.err(), Some(LevelInitError::IOError(_)));
+
+        assert_matches!(
+            reader1.insert(b"key2", b"value2"),
+            Err(LevelInsertionError::ReadOnly)
+        );
+        assert_matches!(
+            reader1.update(b"key1", b"value2"),
+            Err(LevelUpdateError::ReadOnly)
+        );
+        assert_eq!(reader1.remove(b"key1"), None);
+
+        // the lock cannot be upgraded while another reader holds the lock
+        assert!(reader1.upgrade_lock().is_err());
+        assert_eq!(reader1.lock_mode(), Some(LockMode::Shared));
+        drop(reader2);
+
+        reader1.upgrade_lock().unwrap();
+        assert_eq!(reader1.lock_mode(), Some(LockMode::Exclusive));
+        reader1.insert(b"key2", b"value2").unwrap();
+
+        reader1.downgrade_lock().unwrap();
+        assert_eq!(reader1.lock_mode(), Some(LockMode::Shared));
+        let reader3 = create_level_hash(name, false, read_only);
+        assert_eq!(reader3.get_value(b"key2"), b"value2".to_vec());
+    }
+
+    #[test]
+    fn test_entry_size_gt_values_block_size() {
+        let mut hash = default_level_hash("name");
+        let kv = "1"
+            .repeat(LevelHashIO::VALUES_BLOCK_SIZE_BYTES as usize / 2)
+            .into_bytes();
+        assert_eq!(
+            hash.io.meta.read().val_file_size,
+            LevelHashIO::VALUES_BLOCK_SIZE_BYTES
+        );
+
+        hash.insert(&kv, &kv).unwrap();
+
+        assert_eq!(
+            hash.io.meta.read().val_file_size,
+            LevelHashIO::VALUES_BLOCK_SIZE_BYTES * 2 + SIZE_U64
+        );
+    }
+
+    #[test]
+    fn keys_and_export() {
+        let mut hash = default_level_hash("keys");
+        for i in 0..10 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        hash.remove(b"key3").unwrap();
+
+        let mut keys = hash.keys().collect::<Vec<_>>();
+        keys.sort();
+        let mut expected = (0..10)
+            .filter(|i| *i != 3)
+            .map(|i| format!("key{}", i).into_bytes())
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        let mut out = Vec::new();
+        assert_eq!(hash.export_keys(&mut out).unwrap(), 9);
+
+        let mut exported = Vec::new();
+        let mut pos = 0;
+        while pos < out.len() {
+            let size = LittleEndian::read_u32(&out[pos..pos + 4]) as usize;
+            exported.push(out[pos + 4..pos + 4 + size].to_vec());
+            pos += 4 + size;
+        }
+        exported.sort();
+        assert_eq!(exported, expected);
+    }
+
+    #[test]
+    fn export_and_import_archive() {
+        let dir = Path::new("target/tests/level-hash/archive");
+        fs::create_dir_all(dir).unwrap();
+        let archive = dir.join("entries.lha");
+
+        let mut hash = default_level_hash("archive-export");
+        for i in 0..10 {
+            hash.insert(
+                format!("key{}", i).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            )
+            .unwrap();
+        }
+        hash.insert(b"empty", &[]).unwrap();
+        hash.remove(b"key3").unwrap();
+        assert_eq!(hash.export(&archive).unwrap(), 10);
+
+        // the archive can be imported into a level hash with another configuration
+        let mut imported = create_level_hash("archive-import", true, |options| {
+            options.level_size(4).bucket_size(8).level_count(3);
+        });
+        imported.insert(b"key1", b"old").unwrap();
+        assert_eq!(imported.import(&archive).unwrap(), 10);
+
+        let mut entries = imported.scan_prefix(b"").collect::<Vec<_>>();
+        entries.sort();
+        let mut expected = hash.scan_prefix(b"").collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(entries, expected);
+
+        // truncated archives are rejected
+        let bytes = fs::read(&archive).unwrap();
+        fs::write(&archive, &bytes[..bytes.len() - 4]).unwrap();
+        assert_matches!(
+            imported.import(&archive),
+            Err(LevelArchiveError::IOError(_))
+        );
+
+        fs::write(&archive, b"not an archive").unwrap();
+        assert_matches!(
+            imported.import(&archive),
+            Err(LevelArchiveError::InvalidArchive(_))
+        );
+    }
+
+    #[test]
+    fn bloom_filter() {
+        let (mut hash, dir) = create_level_hash_2("bloom", true, |options| {
+            options.level_size(3).bucket_size(4).bloom_filter(true);
+        });
+        let bloom_file = Path::new(&dir).join("bloom.index._bloom");
+        assert!(bloom_file.exists());
+
+        for i in 0..60 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        assert!(hash.expand_count > 0);
+        hash.remove(b"key0").unwrap();
+
+        let contains_all =
+            |hash: &LevelHash| (1..60).all(|i| hash.contains_key(format!("key{}", i).as_bytes()));
+        assert!(contains_all(&hash));
+        assert!(!hash.contains_key(b"key0"));
+
+        // most of the absent keys are rejected by the filter without probing any slot
+        let rejected = (0..1000)
+            .map(|i| format!("absent{}", i))
+            .filter(|key| {
+                let key = key.as_bytes();
+                !hash.io.bloom_may_contain(hash.fhash(key), hash.shash(key))
+            })
+            .count();
+        assert!(
+            rejected > 900,
+            "only {} absent keys were rejected",
+            rejected
+        );
+        drop(hash);
+
+        let hash = create_level_hash("bloom", false, |options| {
+            options.bloom_filter(true);
+        });
+        assert!(contains_all(&hash));
+        drop(hash);
+
+        // the filter is deleted when the index is opened without it
+        let mut hash = create_level_hash("bloom", false, |_| {});
+        assert!(!bloom_file.exists());
+        hash.insert(b"key60", b"value").unwrap();
+        drop(hash);
+
+        let hash = create_level_hash("bloom", false, |options| {
+            options.bloom_filter(true);
+        });
+        assert!(contains_all(&hash));
+        assert!(hash.contains_key(b"key40"));
+    }
+
+    #[test]
+    fn level_stats() {
+        let mut hash = default_level_hash("stats");
+        for i in 0..10 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+
+        let before = hash.stats();
+        for i in 0..10 {
+            assert!(hash.contains_key(format!("key{}", i).as_bytes()));
+        }
+        assert!(!hash.contains_key(b"absent"));
+        hash.remove(b"key0").unwrap();
+
+        let stats = hash.stats();
+        let hits = |stats: &LevelStats| stats.hit_probe_histogram.iter().sum::<u64>();
+        let misses = |stats: &LevelStats| stats.miss_probe_histogram.iter().sum::<u64>();
+        assert_eq!(hits(&stats) - hits(&before), 11);
+        assert_eq!(misses(&stats) - misses(&before), 1);
+        assert_eq!(stats.hit_probe_histogram[0], 0);
+
+        assert_eq!(
+            stats.occupancy_histogram.iter().sum::<u64>(),
+            hash.total_bucket_count() as u64
+        );
+        let occupied = stats
+            .occupancy_histogram
+            .iter()
+            .enumerate()
+            .map(|(i, count)| i as u64 * count)
+            .sum::<u64>();
+        assert_eq!(occupied, 9);
+        assert_eq!(
+            stats.load_factor,
+            9f32 / (hash.total_bucket_count() * hash.bucket_size() as u32) as f32
+        );
+        assert!(stats.level_load_factors[0] > 0f32);
+        assert_eq!(stats.level_load_factors[2], 0f32);
+
+        assert!(stats.values_file_size >= LevelHashIO::VALUES_BLOCK_SIZE_BYTES);
+        assert!(stats.dead_bytes > 0);
+        assert_eq!(stats.expand_count, 0);
+    }
+
+    #[test]
+    fn write_stats() {
+        let mut hash = default_level_hash("write-stats");
+        let initial = hash.write_stats();
+        assert_eq!(initial.meta_bytes, 0);
+
+        hash.insert(b"key", b"value").unwrap();
+        let inserted = hash.write_stats();
+        assert!(inserted.values_bytes >= initial.values_bytes + 8);
+        assert!(inserted.keymap_bytes > initial.keymap_bytes);
+        assert_eq!(hash.stats().writes, inserted);
+
+        hash.sync_all().unwrap();
+        hash.flush().unwrap();
+        let synced = hash.write_stats();
+        assert_eq!(synced.meta_bytes, 2 * MetaIO::META__SIZE_BYTES);
+        assert_eq!(
+            synced.total_bytes(),
+            synced.values_bytes + synced.keymap_bytes + synced.meta_bytes
+        );
+
+        let remaps = synced.remaps;
+        hash.expand().unwrap();
+        assert!(hash.write_stats().remaps > remaps);
+    }
+
+    #[test]
+    fn corrupt_keymap_pointer() {
+        let mut hash = default_level_hash("corrupt-keymap");
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+
+        let (_, level, bucket, slot) = hash.find_slot(b"key1").unwrap();
+        let slot_addr = hash.io.slot_addr(level, bucket, slot);
+        hash.io.km_write_addr(slot_addr, u64::MAX - 4);
+
+        assert_matches!(
+            hash.try_get_value(b"key1"),
+            Err(LevelReadError::CorruptIndex { .. })
+        );
+        assert_eq!(hash.get_value(b"key1"), Vec::<u8>::new());
+        assert!(!hash.contains_key(b"key1"));
+        assert_matches!(
+            hash.update(b"key1", b"value"),
+            Err(LevelUpdateError::ReadError(
+                LevelReadError::CorruptIndex { .. }
+            ))
+        );
+
+        // the corrupt slot is treated as empty by the scans
+        let stats = hash.stats();
+        assert!(stats.load_factor > 0f32);
+        hash.health();
+    }
+
+    #[test]
+    fn values_space_reuse() {
+        let next_addr = |hash: &LevelHash| hash.io.meta.read().val_next_addr;
+        let free_bytes;
+        {
+            let mut hash = create_level_hash("space-reuse", true, |options| {
+                options.level_size(3).bucket_size(4).auto_expand(false);
+            });
+            for i in 0..10 {
+                hash.insert(format!("key{}", i).as_bytes(), &[i; 16])
+                    .unwrap();
+            }
+
+            let end = next_addr(&hash);
+            for i in 2..6 {
+                hash.remove(format!("key{}", i).as_bytes()).unwrap();
+            }
+            assert_eq!(next_addr(&hash), end);
+            let freed = hash.stats().free_bytes;
+            assert!(freed > 0);
+
+            // the new entries are written to the regions of the deleted entries
+            hash.insert(b"new1", &[1; 16]).unwrap();
+            hash.insert(b"new2", &[2; 16]).unwrap();
+            assert_eq!(next_addr(&hash), end);
+            assert!(hash.stats().free_bytes < freed);
+            assert_eq!(hash.get_value(b"new1"), vec![1; 16]);
+            assert_eq!(hash.get_value(b"key6"), vec![6; 16]);
+
+            free_bytes = hash.stats().free_bytes;
+        }
+
+        // the free list is rebuilt from the holes in the values file
+        let mut hash = create_level_hash("space-reuse", false, |_| {});
+        assert_eq!(hash.stats().free_bytes, free_bytes);
+
+        // removing the last entries releases the free regions before them
+        let end = next_addr(&hash);
+        for i in 6..10 {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        assert!(next_addr(&hash) < end);
+        assert_eq!(hash.get_value(b"new2"), vec![2; 16]);
+    }
+
+    #[test]
+    fn values_growth() {
+        let block_size = page_size() * 2;
+        {
+            let mut hash = create_level_hash("values-growth", true, |options| {
+                options
+                    .level_size(2)
+                    .bucket_size(4)
+                    .values_growth(block_size);
+            });
+            assert_eq!(hash.values_growth(), block_size);
+            assert_eq!(hash.io.meta.read().val_file_size, block_size);
+
+            let value = vec![1u8; block_size as usize];
+            hash.insert(b"key", &value).unwrap();
+            assert_eq!(hash.io.meta.read().val_file_size, block_size * 2 + SIZE_U64);
+        }
+
+        // the growth size is read from the meta file
+        let hash = create_level_hash("values-growth", false, |options| {
+            options.level_size(2).bucket_size(4);
+        });
+        assert_eq!(hash.values_growth(), block_size);
+
+        for invalid in [0, page_size() + 1] {
+            let (result, _) = create_level_hash_3("values-growth-invalid", true, |options| {
+                options.values_growth(invalid);
+            });
+            assert_matches!(result.err(), Some(LevelInitError::InvalidArg(_)));
+        }
+    }
+
+    #[test]
+    fn value_too_large() {
+        let mut hash = create_level_hash("value-too-large", true, |options| {
+            options.level_size(2).bucket_size(4).max_value_size(8);
+        });
+
+        assert!(hash.insert(b"key", b"12345678").is_ok());
+        assert_matches!(
+            hash.insert(b"key2", b"123456789"),
+            Err(LevelInsertionError::ValueTooLarge { size: 9, max: 8 })
+        );
+        assert_matches!(
+            hash.update(b"key", b"123456789"),
+            Err(LevelUpdateError::InsertionErr(
+                LevelInsertionError::ValueTooLarge { size: 9, max: 8 }
+            ))
+        );
+        assert_eq!(hash.get_value(b"key"), b"12345678".to_vec());
+    }
+
+    #[test]
+    fn size_limits() {
+        let conf = |options: &mut LevelHashOptions| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .spill_threshold(16)
+                .max_key_size(8)
+                .max_value_size(32);
+        };
+        let mut hash = create_level_hash("size-limits", true, conf);
+        hash.insert(b"12345678", &[1; 32]).unwrap();
+        assert_matches!(
+            hash.insert(b"123456789", b"value"),
+            Err(LevelInsertionError::KeyTooLarge { size: 9, max: 8 })
+        );
+        assert_matches!(
+            hash.insert(b"key", &[1; 33]),
+            Err(LevelInsertionError::ValueTooLarge { size: 33, max: 32 })
+        );
+        assert_matches!(
+            hash.insert_replace(b"123456789", b"value"),
+            Err(LevelUpdateError::InsertionErr(
+                LevelInsertionError::KeyTooLarge { size: 9, max: 8 }
+            ))
+        );
+        assert_matches!(
+            hash.get_or_insert_with(b"123456789", Vec::new),
+            Err(LevelInsertionError::KeyTooLarge { size: 9, max: 8 })
+        );
+        drop(hash);
+
+        // the limits are stored in the index
+        let mut hash = create_level_hash("size-limits", false, |options| {
+            options.level_size(2).bucket_size(4).spill_threshold(16);
+        });
+        let info = hash.capacity_info();
+        assert_eq!((info.max_key_size, info.max_value_size), (8, 32));
+        assert_matches!(
+            hash.insert(b"123456789", b"value"),
+            Err(LevelInsertionError::KeyTooLarge { size: 9, max: 8 })
+        );
+        assert_eq!(hash.get_value(b"12345678"), [1; 32]);
+        drop(hash);
+
+        let mut hash = create_level_hash("size-limits", false, |options| {
+            conf(options);
+            options.max_key_size(16);
+        });
+        hash.insert(b"123456789", b"value").unwrap();
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn invalid_options() {
+        let confs: [fn(&mut LevelHashOptions); 12] = [
+            |options| {
+                options.level_size(0);
+            },
+            |options| {
+                options.level_size(LEVEL_SIZE_MAX + 1);
+            },
+            |options| {
+                options.bucket_size(0);
+            },
+            |options| {
+                options.load_factor_threshold(0.2);
+            },
+            |options| {
+                options.spill_threshold(VALUE_SPILL_THRESHOLD_MAX + 1);
+            },
+            |options| {
+                options.seeds(0, 1);
+            },
+            |options| {
+                options.level_count(1);
+            },
+            |options| {
+                options.level_count(LEVEL_COUNT_MAX + 1);
+            },
+            |options| {
+                options.level_size(2).level_count(4);
+            },
+            |options| {
+                options.movement_depth(MOVEMENT_DEPTH_MAX + 1);
+            },
+            |options| {
+                options.max_key_size(0);
+            },
+            |options| {
+                options.max_value_size(0);
+            },
+        ];
+
+        for conf in confs {
+            let (result, _) = create_level_hash_3("invalid-options", true, conf);
+            assert_matches!(result.err(), Some(LevelInitError::InvalidArg(_)));
+        }
+    }
+
+    #[test]
+    fn default_hash_fns() {
+        let dir_path = "target/tests/level-hash/index-default-hash-fns";
+        let index_dir = Path::new(dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(&index_dir).expect("Failed to delete existing directory");
+        }
+
+        let mut hash = LevelHash::options()
+            .index_dir(index_dir)
+            .index_name("default-hash-fns")
+            .level_size(2)
+            .bucket_size(4)
+            .build()
+            .expect("failed to create level hash");
+
+        assert!(hash.insert(b"key1", b"value1").is_ok());
+        assert_eq!(hash.get_value(b"key1"), b"value1");
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut hash = LevelHash::options()
+            .in_memory(true)
+            .level_size(2)
+            .bucket_size(4)
+            .spill_threshold(16)
+            .build()
+            .expect("failed to create in-memory level hash");
+
+        for i in 0..64 {
+            let key = format!("key{}", i).into_bytes();
+            let value = format!("value{}", i).repeat(i % 4 + 1).into_bytes();
+            hash.insert(&key, &value).expect("failed to insert entry");
+        }
+
+        assert!(hash.expand_count > 0);
+
+        for i in 0..64 {
+            let key = format!("key{}", i).into_bytes();
+            let value = format!("value{}", i).repeat(i % 4 + 1).into_bytes();
+            assert_eq!(hash.get_value(&key), value);
+        }
+
+        assert_eq!(hash.remove(b"key1"), Some(b"value1value1".to_vec()));
+        assert_eq!(hash.get_value(b"key1"), vec![]);
+
+        hash.clear().expect("failed to clear level hash");
+        assert_eq!(hash.get_value(b"key2"), vec![]);
+    }
+
+    #[test]
+    fn in_memory_instances_are_independent() {
+        let mut options = LevelHash::options();
+        options
+            .in_memory(true)
+            .index_name("in-memory")
+            .level_size(2);
+
+        let mut hash_1 = options
+            .build()
+            .expect("failed to create in-memory level hash");
+        let hash_2 = options
+            .index_name("in-memory")
+            .build()
+            .expect("failed to create in-memory level hash");
+
+        hash_1.insert(b"key1", b"value1").unwrap();
+        assert_eq!(hash_1.get_value(b"key1"), b"value1");
+        assert_eq!(hash_2.get_value(b"key1"), vec![]);
+    }
+
+    #[test]
+    fn locked_keymap() {
+        let mut hash = create_level_hash("lock-memory", true, |options| {
+            options.level_size(2).bucket_size(4).lock_memory(true);
+        });
+
+        // the keymap fits in a few pages, which can be locked unless mlock is not permitted
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) },
+            0
+        );
+        let lockable = limit.rlim_cur >= 1 << 20;
+        assert!(hash.io.keymap.opts.lock_memory && !hash.io.values.opts.lock_memory);
+        assert!(!lockable || hash.is_memory_locked());
+
+        for i in 0..64 {
+            let key = format!("key{}", i).into_bytes();
+            hash.insert(&key, b"value").expect("failed to insert entry");
+        }
+
+        // the keymap is locked again after it is expanded
+        assert!(hash.expand_count > 0);
+        assert!(!lockable || hash.is_memory_locked());
+        for i in 0..64 {
+            let key = format!("key{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), b"value");
+        }
+
+        assert!(!default_level_hash("lock-memory-default").is_memory_locked());
+    }
+
+    #[test]
+    fn populated_keymap_with_huge_pages() {
+        let mut hash = create_level_hash("populate", true, |options| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .populate(true)
+                .huge_pages(true);
+        });
+
+        assert!(hash.io.keymap.opts.populate && hash.io.keymap.opts.huge_pages);
+        assert!(!hash.io.values.opts.populate && !hash.io.values.opts.huge_pages);
+
+        for i in 0..64 {
+            let key = format!("key{}", i).into_bytes();
+            hash.insert(&key, b"value").expect("failed to insert entry");
+        }
+
+        assert!(hash.expand_count > 0);
+        for i in 0..64 {
+            let key = format!("key{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), b"value");
+        }
+    }
+
+    #[test]
+    fn health_of_healthy_level_hash() {
+        let mut hash = create_level_hash("health-healthy", true, |options| {
+            options.level_size(4).bucket_size(4);
+        });
+
+        for i in 0..16 {
+            let key = format!("key{}", i).into_bytes();
+            hash.insert(&key, b"value").unwrap();
+        }
+
+        let health = hash.health();
+        assert_eq!(health.action, HealthAction::None);
+        assert_eq!(health.probe_histogram.iter().sum::<u64>(), 16);
+        assert_eq!(health.misplaced_entries, 0);
+        assert_eq!(health.fragmentation, 0f32);
+        assert!(health.score > 0.8);
+    }
+
+    #[test]
+    fn health_recommendations() {
+        let mut hash = create_level_hash("health-recommendations", true, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+
+        let mut i = 0;
+        while hash
+            .insert(format!("key{}", i).as_bytes(), b"value")
+            .is_ok()
+        {
+            i += 1;
+        }
+
+        let health = hash.health();
+        assert_eq!(health.action, HealthAction::Expand);
+        assert_eq!(health.insertion_failures, 1);
+
+        hash.expand().unwrap();
+        for j in 0..i - 1 {
+            hash.remove(format!("key{}", j).as_bytes()).unwrap();
+        }
+
+        let health = hash.health();
+        assert_eq!(health.action, HealthAction::Compact);
+        assert!(health.fragmentation > 0.9);
+
+        // move the remaining entry to a bucket where it cannot be found
+        let key = format!("key{}", i - 1).into_bytes();
+        let (_, level, bucket, slot) = hash.find_slot(&key).unwrap();
+        let slot_addr = hash.io.slot_addr(level, bucket, slot);
+        let val_addr = hash.io.km_read_addr(slot_addr).unwrap();
+        hash.io.km_write_addr(slot_addr, 0);
+
+        let (fidx, sidx) = (
+            hash.buck_idx_lvl(hash.fhash(&key), L0),
+            hash.buck_idx_lvl(hash.shash(&key), L0),
+        );
+        let other_bucket = (0..hash.level_bucket_count(L0))
+            .find(|bucket| *bucket != fidx && *bucket != sidx)
+            .unwrap();
+        hash.io
+            .km_write_addr(hash.io.slot_addr(0, other_bucket, 0), val_addr);
+
+        let health = hash.health();
+        assert_eq!(health.action, HealthAction::Rebuild);
+        assert_eq!(health.misplaced_entries, 1);
+        assert_eq!(health.score, 0f32);
+    }
+
+    #[test]
+    fn empty_key() {
+        let mut hash = default_level_hash("empty-key");
+        assert_matches!(
+            hash.insert(b"", b"").err(),
+            Some(LevelInsertionError::EmptyKey)
+        );
+        assert_matches!(
+            hash.insert(b"", b"value").err(),
+            Some(LevelInsertionError::EmptyKey)
+        );
+        assert_eq!(hash.get_value(b""), vec![]);
+    }
+
+    #[test]
+    fn spilled_values() {
+        let small = b"small".to_vec();
+        let large = "large".repeat(100).into_bytes();
+        let larger = "larger".repeat(100).into_bytes();
+
+        {
+            let mut hash = create_level_hash("spilled-values", true, |options| {
+                options.level_size(2).bucket_size(4).spill_threshold(16);
+            });
+
+            hash.insert(b"small", &small).unwrap();
+            hash.insert(b"large", &large).unwrap();
+
+            let (entry, _, _, _) = hash.find_slot(b"small").unwrap();
+            assert!(!entry.is_spilled());
+
+            let (entry, _, _, _) = hash.find_slot(b"large").unwrap();
+            assert!(entry.is_spilled());
+            assert_eq!(entry.value_size() as u64, ValuesEntry::SPILL_REF_SIZE);
+
+            assert_eq!(hash.get_value(b"small"), small);
+            assert_eq!(hash.get_value(b"large"), large);
+
+            assert_eq!(hash.update(b"large", &larger).unwrap(), large);
+            assert_eq!(hash.get_value(b"large"), larger);
+        }
+
+        {
+            let mut hash = create_level_hash("spilled-values", false, |options| {
+                options.level_size(2).bucket_size(4).spill_threshold(16);
+            });
+
+            assert_eq!(hash.get_value(b"small"), small);
+            assert_eq!(hash.get_value(b"large"), larger);
+            assert_eq!(hash.remove(b"large"), Some(larger));
+            assert_eq!(hash.get_value(b"large"), vec![]);
+        }
+    }
+
+    #[test]
+    fn spilled_keys() {
+        let short = b"Ljava/lang/Object;".to_vec();
+        let long = "Lcom/itsaky/androidide/".repeat(10).into_bytes();
+        let mut other = long.clone();
+        *other.last_mut().unwrap() = b'_';
+
+        {
+            let mut hash = create_level_hash("spilled-keys", true, |options| {
+                options
+                    .level_size(2)
+                    .bucket_size(4)
+                    .spill_threshold(16)
+                    .key_spill_threshold(64)
+                    .checksums(true)
+                    .unique_keys(false);
+            });
+
+            hash.insert(&short, b"short").unwrap();
+            hash.insert(&long, b"long").unwrap();
+            hash.insert(&other, &"other".repeat(10).into_bytes())
+                .unwrap();
+
+            let (entry, _, _, _) = hash.find_slot(&short).unwrap();
+            assert!(!entry.is_key_spilled());
+
+            let (entry, _, _, _) = hash.find_slot(&long).unwrap();
+            assert!(entry.is_key_spilled());
+            assert_eq!(entry.key_size() as u64, ValuesEntry::KEY_SPILL_REF_SIZE);
+            assert_eq!(hash.io.entry_key(&entry), long);
+
+            // the key and the value of an entry can both be spilled
+            let (entry, _, _, _) = hash.find_slot(&other).unwrap();
+            assert!(entry.is_key_spilled());
+            assert!(entry.is_spilled());
+
+            assert_eq!(hash.get_value(&short), b"short");
+            assert_eq!(hash.get_value(&long), b"long");
+            assert_eq!(hash.get_value(&other), "other".repeat(10).into_bytes());
+            assert!(!hash.contains_key(&long[..long.len() - 1]));
+            assert_eq!(hash.scan_prefix(&long[..64]).count(), 2);
+
+            hash.insert(&long, b"long2").unwrap();
+            assert_eq!(hash.get_all(&long), [b"long2".to_vec(), b"long".to_vec()]);
+            assert_eq!(hash.update(&long, b"long3").unwrap(), b"long2");
+        }
+
+        let mut hash = create_level_hash("spilled-keys", false, |options| {
+            options.level_size(2).bucket_size(4).spill_threshold(16);
+        });
+
+        let mut keys: Vec<_> = hash.keys().collect();
+        keys.sort();
+        assert_eq!(keys, [long.clone(), long.clone(), other.clone(), short]);
+        assert_eq!(hash.get_value(&long), b"long3");
+        assert_eq!(hash.remove(&other), Some("other".repeat(10).into_bytes()));
+        assert!(!hash.contains_key(&other));
+        assert_eq!(hash.content_fingerprint(), hash.io.compute_fingerprint());
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_values() {
+        let small = "small".repeat(2).into_bytes();
+        let large = "large".repeat(100).into_bytes();
+        let larger = "larger".repeat(200).into_bytes();
+        let random = (0..256u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let conf = |options: &mut LevelHashOptions| {
+            options
+                .level_size(2)
+                .bucket_size(4)
+                .checksums(true)
+                .compress_threshold(16)
+                .spill_threshold(200);
+        };
+
+        {
+            let mut hash = create_level_hash("compressed-values", true, conf);
+            assert_eq!(hash.io.meta.read().val_compressed, 0);
+
+            hash.insert(b"small", &small).unwrap();
+            hash.insert(b"large", &large).unwrap();
+            hash.insert(b"larger", &larger).unwrap();
+            hash.insert(b"random", &random).unwrap();
+            assert_eq!(hash.io.meta.read().val_compressed, 1);
+
+            let (entry, _, _, _) = hash.find_slot(b"small").unwrap();
+            assert!(!entry.is_compressed());
+
+            // the compressed value is small enough to be stored inline
+            let (entry, _, _, _) = hash.find_slot(b"large").unwrap();
+            assert!(entry.is_compressed());
+            assert!(!entry.is_spilled());
+            assert!((entry.value_size() as usize) < large.len());
+
+            let (entry, _, _, _) = hash.find_slot(b"larger").unwrap();
+            assert!(entry.is_compressed());
+
+            // the values which do not get smaller are stored as is
+            let (entry, _, _, _) = hash.find_slot(b"random").unwrap();
+            assert!(!entry.is_compressed());
+            assert!(entry.is_spilled());
+
+            assert_eq!(hash.get_value(b"small"), small);
+            assert_eq!(hash.get_value(b"large"), large);
+            assert_eq!(hash.get_value(b"larger"), larger);
+            assert_eq!(hash.get_value(b"random"), random);
+
+            assert_eq!(hash.update(b"large", &small).unwrap(), large);
+            assert_eq!(hash.verify(), vec![]);
+        }
+
+        let mut hash = create_level_hash("compressed-values", false, conf);
+        assert_eq!(hash.get_value(b"large"), small);
+        assert_eq!(hash.get_value(b"larger"), larger);
+        assert_eq!(hash.remove(b"larger"), Some(larger));
+        assert_eq!(hash.verify(), vec![]);
+
+        hash.clear().unwrap();
+        assert_eq!(hash.io.meta.read().val_compressed, 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn compressed_values_require_feature() {
+        {
+            let mut hash = default_level_hash("compressed-values-feature");
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.io.meta.write().val_compressed = 1;
+        }
+
+        let (hash, _) = create_level_hash_3("compressed-values-feature", false, |options| {
+            options.level_size(2).bucket_size(4);
+        });
+        assert_matches!(
+            hash.err(),
+            Some(LevelInitError::FeatureRequired("compression"))
+        );
+    }
+
+    #[test]
+    fn scan_prefix_and_filter() {
+        let large = "large".repeat(100).into_bytes();
+        let mut hash = create_level_hash("scan-prefix", true, |options| {
+            options.level_size(4).bucket_size(4).spill_threshold(16);
+        });
+
+        hash.insert(b"com.example.A", b"a").unwrap();
+        hash.insert(b"com.example.B", b"b").unwrap();
+        hash.insert(b"com.example.C", b"c").unwrap();
+        hash.insert(b"com.other.D", b"d").unwrap();
+        hash.insert(b"org.example.E", &large).unwrap();
+        hash.insert(b"com.exam", b"short").unwrap();
+
+        hash.remove(b"com.example.B").unwrap();
+        hash.update(b"com.example.A", b"updated").unwrap();
+
+        let mut entries: Vec<_> = hash.scan_prefix(b"com.example.").collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                (b"com.example.A".to_vec(), b"updated".to_vec()),
+                (b"com.example.C".to_vec(), b"c".to_vec()),
+            ]
+        );
+
+        assert_eq!(hash.scan_prefix(b"").count(), 5);
+        assert_eq!(hash.scan_prefix(b"net.").count(), 0);
+
+        let entries: Vec<_> = hash.scan_filter(|key| key.ends_with(b".E")).collect();
+        assert_eq!(entries, vec![(b"org.example.E".to_vec(), large)]);
+    }
+
+    #[test]
+    fn checksums() {
+        let large = "large".repeat(100).into_bytes();
+        {
+            let mut hash = create_level_hash("checksums", true, |options| {
+                options
+                    .level_size(4)
+                    .bucket_size(4)
+                    .spill_threshold(16)
+                    .checksums(true);
+            });
+
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.insert(b"key2", &large).unwrap();
+        }
+
+        // the checksums are enabled for the existing index even if not requested
+        let mut hash = create_level_hash("checksums", false, |options| {
+            options.level_size(4).bucket_size(4).spill_threshold(16);
+        });
+
+        hash.insert(b"key3", b"value3").unwrap();
+
+        let (entry, _, _, _) = hash.find_slot(b"key3").unwrap();
+        assert!(entry.has_checksum());
+        assert_eq!(entry.key_size(), 4);
+
+        assert_eq!(
+            hash.try_get_value(b"key1").unwrap(),
+            Some(b"value1".to_vec())
+        );
+        assert_eq!(hash.try_get_value(b"key2").unwrap(), Some(large.clone()));
+        assert_eq!(hash.try_get_value(b"missing").unwrap(), None);
+
+        // flip a byte in the value of key1
+        let (entry, _, _, _) = hash.find_slot(b"key1").unwrap();
+        let value_addr = entry.addr + ValuesEntry::OFF_KEY + 4;
+        hash.io.values.write_at(value_addr, b"V");
+
+        assert_matches!(
+            hash.try_get_value(b"key1"),
+            Err(LevelReadError::CorruptEntry { .. })
+        );
+        assert_eq!(hash.get_value(b"key1"), vec![]);
+        assert_eq!(hash.get_value(b"key2"), large);
+    }
+
+    // the invariants are violated once the meta file is out of sync with the values file
+    #[test]
+    #[cfg(not(feature = "debug-invariants"))]
+    fn next_entry_addr_out_of_sync() {
+        let mut hash = default_level_hash("next-entry-addr-out-of-sync");
+        hash.insert(b"key1", b"value1").unwrap();
+
+        // the next entry would overwrite the last entry
+        let tail_addr = hash.io.meta.read().val_tail_addr;
+        hash.io.meta.write().val_next_addr = tail_addr;
+
+        assert_matches!(
+            hash.insert(b"key2", b"value2"),
+            Err(LevelInsertionError::ReadError(
+                LevelReadError::CorruptIndex { addr, .. }
+            )) if addr == tail_addr - 1
+        );
+        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+        assert!(!hash.contains_key(b"key2"));
+
+        // an interim level cannot be prepared twice
+        hash.io.prepare_interim(4).unwrap();
+        assert_matches!(
+            hash.io.prepare_interim(4),
+            Err(LevelExpansionError::ConcurrentModificationError)
+        );
+        hash.io.rollback_interim().unwrap();
+    }
+
+    #[test]
+    fn entry_versions() {
+        let (v1, v2) = {
+            let mut hash = create_level_hash("entry-versions", true, |options| {
+                options.level_size(4).bucket_size(4).entry_versions(true);
+            });
+
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.insert(b"key2", b"value2").unwrap();
+
+            let v1 = hash.entry_version(b"key1").unwrap();
+            let v2 = hash.entry_version(b"key2").unwrap();
+            assert!(v2 > v1);
+            assert_eq!(
+                hash.get_with_version(b"key1"),
+                Some((b"value1".to_vec(), Some(v1)))
+            );
+            assert_eq!(hash.entry_version(b"missing"), None);
+            (v1, v2)
+        };
+
+        // the versions are enabled for the existing index even if not requested
+        let mut hash = create_level_hash("entry-versions", false, |options| {
+            options.level_size(4).bucket_size(4);
+        });
+        assert_eq!(
+            hash.io.meta.read().val_version,
+            LEVEL_VALUES_VERSION_ENTRY_VERSIONS
+        );
+        assert_eq!(hash.entry_version(b"key1"), Some(v1));
+
+        // the versioned entries are checksummed
+        let (entry, _, _, _) = hash.find_slot(b"key1").unwrap();
+        assert!(entry.has_checksum());
+        assert!(entry.has_version());
+        assert_eq!(entry.key_size(), 4);
+
+        hash.update(b"key1", b"updated").unwrap();
+        let v3 = hash.entry_version(b"key1").unwrap();
+        assert!(v3 > v2);
+
+        hash.update_in_place(b"key2", |value| value.make_ascii_uppercase())
+            .unwrap();
+        let v4 = hash.entry_version(b"key2").unwrap();
+        assert!(v4 > v3);
+        assert_eq!(
+            hash.try_get_value(b"key2").unwrap(),
+            Some(b"VALUE2".to_vec())
+        );
+        assert_eq!(hash.io.meta.read().val_write_counter, v4);
+
+        // the versions are kept by the compaction
+        hash.compact().unwrap();
+        assert_eq!(hash.entry_version(b"key1"), Some(v3));
+        assert_eq!(hash.entry_version(b"key2"), Some(v4));
+        assert!(hash.verify().is_empty());
+
+        let mut versions = Vec::new();
+        hash.for_each_slot(|_, _, _, entry| versions.extend(entry.map(|entry| entry.version())));
+        versions.sort();
+        assert_eq!(versions, vec![Some(v3), Some(v4)]);
+
+        // the entries are not versioned by default
+        let mut hash = default_level_hash("entry-versions-default");
+        hash.insert(b"key", b"value").unwrap();
+        assert_eq!(hash.entry_version(b"key"), None);
+        assert_eq!(
+            hash.get_with_version(b"key"),
+            Some((b"value".to_vec(), None))
+        );
+    }
+
+    #[test]
+    fn aligned_buckets() {
+        let align = LevelHashIO::KEYMAP_BUCKET_ALIGN_BYTES as u64;
+        let assert_aligned = |hash: &LevelHash| {
+            for &level in hash.levels() {
+                for bucket in 0..hash.level_bucket_count(level) {
+                    let addr = hash.io.slot_addr(level as _LevelIdxT, bucket, 0);
+                    assert_eq!((LevelHashIO::KEYMAP_HEADER_SIZE_BYTES + addr) % align, 0);
+                }
+            }
+        };
+
+        {
+            let mut hash = create_level_hash("aligned-buckets", true, |options| {
+                options
+                    .level_size(2)
+                    .bucket_size(3)
+                    .auto_expand(false)
+                    .aligned_buckets(true);
+            });
+            assert_eq!(hash.io.meta.read().km_version, LEVEL_KEYMAP_VERSION_ALIGNED);
+            assert_eq!(hash.io.meta.read().km_bucket_bytes(), align);
+            assert_aligned(&hash);
+
+            for i in 0..10 {
+                hash.insert(format!("key{}", i).as_bytes(), b"value")
+                    .unwrap();
+            }
+
+            hash.expand().expect("failed to expand level hash");
+            assert_aligned(&hash);
+            hash.expand().expect("failed to expand level hash");
+            assert_aligned(&hash);
+            hash.shrink().expect("failed to shrink level hash");
+            assert_aligned(&hash);
+            assert!(hash.verify().is_empty());
+        }
+
+        // the alignment of an existing index is kept even if not requested
+        let mut hash = create_level_hash("aligned-buckets", false, |options| {
+            options.level_size(2).bucket_size(3);
+        });
+        assert_eq!(hash.io.meta.read().km_version, LEVEL_KEYMAP_VERSION_ALIGNED);
+        assert_aligned(&hash);
+        for i in 0..10 {
+            assert_eq!(hash.get_value(format!("key{}", i).as_bytes()), b"value");
+        }
+
+        hash.insert(b"key10", b"value").unwrap();
+        hash.expand().expect("failed to expand level hash");
+        assert_aligned(&hash);
+        assert_eq!(hash.keys().count(), 11);
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn incremental_expansion() {
+        let mut expected = BTreeMap::new();
+        {
+            let mut hash = create_level_hash("incremental-expansion", true, |options| {
+                options
+                    .level_size(3)
+                    .bucket_size(4)
+                    .load_factor_threshold(0.5)
+                    .incremental_expansion(1);
+            });
+
+            let mut i = 0;
+            while !hash.is_expanding() {
+                let key = format!("key{}", i).into_bytes();
+                hash.insert(&key, b"value").unwrap();
+                expected.insert(key, b"value".to_vec());
+                i += 1;
+            }
+
+            // the bottom level has 4 buckets, so the expansion completes after 4 more writes
+            assert_eq!(hash.level_size(), 3);
+            hash.update(b"key0", b"value0").unwrap();
+            expected.insert(b"key0".to_vec(), b"value0".to_vec());
+            hash.remove(b"key1").unwrap();
+            expected.remove(b"key1".as_slice());
+            assert!(hash.is_expanding());
+
+            while hash.is_expanding() {
+                let key = format!("key{}", i).into_bytes();
+                hash.insert(&key, b"value").unwrap();
+                expected.insert(key, b"value".to_vec());
+                i += 1;
+            }
+
+            assert_eq!(hash.level_size(), 4);
+            assert!(hash.verify().is_empty());
+            for (key, value) in &expected {
+                assert_eq!(&hash.get_value(key), value);
+            }
+            assert_eq!(hash.keys().count(), expected.len());
+
+            // an expansion in progress is completed by expand()
+            while !hash.is_expanding() {
+                let key = format!("key{}", i).into_bytes();
+                hash.insert(&key, b"value").unwrap();
+                expected.insert(key, b"value".to_vec());
+                i += 1;
+            }
+            hash.expand().expect("failed to expand level hash");
+            assert!(!hash.is_expanding());
+            assert_eq!(hash.level_size(), 5);
+            assert!(hash.verify().is_empty());
+
+            // an expansion in progress when the level hash is closed is discarded
+            while !hash.is_expanding() {
+                let key = format!("key{}", i).into_bytes();
+                hash.insert(&key, b"value").unwrap();
+                expected.insert(key, b"value".to_vec());
+                i += 1;
+            }
+            assert_ne!(hash.io.meta.read().km_interim_addr, 0);
+        }
+
+        let hash = create_level_hash("incremental-expansion", false, |options| {
+            options.level_size(3).bucket_size(4);
+        });
+        assert!(!hash.is_expanding());
+        assert_eq!(hash.io.meta.read().km_interim_addr, 0);
+        assert_eq!(hash.level_size(), 5);
+        assert!(hash.verify().is_empty());
+        for (key, value) in &expected {
+            assert_eq!(&hash.get_value(key), value);
+        }
+    }
+
+    #[test]
+    fn streamed_values() {
+        let mut hash = create_level_hash("streamed-values", true, |options| {
+            options.level_size(4).bucket_size(4);
+        });
+
+        let large = (0..300_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        hash.insert_from_reader(b"large", large.len() as u64, large.as_slice())
+            .unwrap();
+        assert_eq!(hash.get_value(b"large"), large);
+
+        let mut out = Vec::new();
+        assert_eq!(
+            hash.read_value_into(b"large", &mut out).unwrap(),
+            Some(large.len() as u64)
+        );
+        assert_eq!(out, large);
+
+        // the inline values are written from the values file
+        hash.insert(b"small", b"value").unwrap();
+        out.clear();
+        assert_eq!(hash.read_value_into(b"small", &mut out).unwrap(), Some(5));
+        assert_eq!(out, b"value");
+        assert_eq!(hash.read_value_into(b"missing", &mut out).unwrap(), None);
+
+        hash.remove(b"large").unwrap();
+        hash.insert_from_reader(b"abc", 3, &b"abc"[..]).unwrap();
+        assert_eq!(hash.get_value(b"abc"), b"abc");
+
+        // the streamed value is released if the entry is not inserted
+        let ovf_next_addr = hash.io.meta.read().ovf_next_addr;
+        assert_matches!(
+            hash.insert_from_reader(b"short", 10, &b"abc"[..]),
+            Err(LevelInsertionError::IOError(_))
+        );
+        assert_matches!(
+            hash.insert_from_reader(b"", 3, &b"abc"[..]),
+            Err(LevelInsertionError::EmptyKey)
+        );
+        assert!(!hash.contains_key(b"short"));
+        assert_eq!(hash.io.meta.read().ovf_next_addr, ovf_next_addr);
+        assert!(hash.io.streamed_value.is_none());
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn expand_cancellable() {
+        let mut hash = create_level_hash("expand-cancellable", true, |options| {
+            options.level_size(8).bucket_size(4).auto_expand(false);
+        });
+        for i in 0..500 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+
+        // the expansion stays in progress when it is cancelled
+        let token = CancellationToken::new();
+        token.clone().cancel();
+        assert_matches!(
+            hash.expand_cancellable(&token),
+            Err(LevelExpansionError::Cancelled)
+        );
+        assert!(hash.is_expanding());
+        assert_eq!(hash.level_size(), 8);
+        hash.insert(b"key500", b"value").unwrap();
+        assert_eq!(hash.get_value(b"key0"), b"value");
+
+        assert_matches!(
+            hash.expand_cancellable(&CancellationToken::with_timeout(Duration::ZERO)),
+            Err(LevelExpansionError::Cancelled)
+        );
+
+        hash.expand_cancellable(&CancellationToken::new())
+            .expect("failed to expand level hash");
+        assert!(!hash.is_expanding());
+        assert_eq!(hash.level_size(), 9);
+        assert!(hash.verify().is_empty());
+        for i in 0..=500 {
+            assert_eq!(hash.get_value(format!("key{}", i).as_bytes()), b"value");
+        }
+
+        let dir = Path::new("target/tests/level-hash/archive");
+        fs::create_dir_all(dir).unwrap();
+        let archive = dir.join("cancellable.lha");
+        let error = hash.export_cancellable(&archive, &token).unwrap_err();
+        assert_eq!(error.error.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(
+            hash.export_cancellable(&archive, &CancellationToken::new())
+                .unwrap(),
+            501
+        );
+    }
+
+    #[test]
+    fn expand_with_progress() {
+        let mut hash = create_level_hash("expand-progress", true, |options| {
+            options.level_size(9).bucket_size(4).auto_expand(false);
+        });
+        for i in 0..500 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+
+        // the bottom level of a level hash with level size 9 has 256 buckets
+        let mut reports = Vec::new();
+        hash.expand_with_progress(|progress| reports.push(progress))
+            .expect("failed to expand level hash");
+        assert_eq!(reports.len(), 4);
+        assert!(reports.iter().all(|progress| progress.total_buckets == 256));
+        assert_eq!(
+            reports
+                .iter()
+                .map(|progress| progress.buckets_moved)
+                .collect::<Vec<_>>(),
+            [64, 128, 192, 256]
+        );
+        assert!(reports[..3].iter().all(|progress| !progress.is_done()));
+        assert!(reports[3].is_done());
+        assert_eq!(reports[1].fraction(), 0.5);
+        assert_eq!(reports[3].estimated_remaining, Some(Duration::ZERO));
+
+        assert_eq!(hash.level_size(), 10);
+        assert!(hash.verify().is_empty());
+        for i in 0..500 {
+            assert_eq!(hash.get_value(format!("key{}", i).as_bytes()), b"value");
+        }
+    }
+
+    #[test]
+    fn stash() {
+        let conf = |options: &mut LevelHashOptions| {
+            options
+                .level_size(2)
+                .bucket_size(2)
+                .stash_slots(4)
+                .auto_expand(false);
+        };
+
+        let mut hash = create_level_hash("stash", true, conf);
+        assert_eq!(hash.io.meta.read().km_version, LEVEL_KEYMAP_VERSION_STASH);
+
+        // the entries which do not fit in their buckets are placed in the stash
+        let mut count = 0;
+        while hash
+            .insert(format!("key{}", count).as_bytes(), b"value")
+            .is_ok()
+        {
+            count += 1;
+        }
+        assert!(count > hash.occupancy().total());
+        assert_eq!(hash.stash_count() as u64, count - hash.occupancy().total());
+        assert_eq!(hash.capacity_info().entries, count);
+        assert!(hash.verify().is_empty());
+
+        let mut stashed = Vec::new();
+        hash.for_each_slot(|level, _, _, entry| {
+            if let (Stash, Some(entry)) = (level, entry) {
+                stashed.push(entry.key());
+            }
+        });
+        assert_eq!(stashed.len(), hash.stash_count() as usize);
+        assert_eq!(hash.remove(&stashed[0]), Some(b"value".to_vec()));
+        assert_eq!(hash.get_value(&stashed[0]), b"");
+        drop(hash);
+
+        let mut hash = create_level_hash("stash", false, conf);
+        for key in &stashed[1..] {
+            assert_eq!(hash.get_value(key), b"value");
+        }
+
+        // the stash is drained when the level hash is expanded
+        hash.expand().expect("failed to expand level hash");
+        assert_eq!(hash.stash_count(), 0);
+        assert_eq!(hash.occupancy().total(), count - 1);
+        assert!(hash.verify().is_empty());
+        for key in &stashed[1..] {
+            assert_eq!(hash.get_value(key), b"value");
+        }
+
+        assert_matches!(
+            LevelHashOptions::new()
+                .stash_slots(STASH_SLOTS_MAX + 1)
+                .in_memory(true)
+                .build()
+                .err(),
+            Some(LevelInitError::InvalidArg(_))
+        );
+    }
+
+    #[test]
+    fn insert_duplicate_key() {
+        let mut hash = create_level_hash("insert-duplicate", true, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+
+        // the free slots of the buckets of the key were taken before the key was compared with
+        // the occupied slots, so the duplicate was inserted in another slot
+        hash.insert(b"key", b"value").unwrap();
+        assert_matches!(
+            hash.insert(b"key", b"other value").err(),
+            Some(LevelInsertionError::DuplicateKey)
+        );
+        assert_eq!(hash.get_all(b"key").len(), 1);
+        assert_eq!(hash.get_value(b"key"), b"value");
+
+        // the entry is in the bottom level after an expansion, the top level has free slots
+        hash.expand().unwrap();
+        assert_ne!(hash.find_slot(b"key").unwrap().1, L0 as _LevelIdxT);
+        assert_matches!(
+            hash.insert(b"key", b"other value").err(),
+            Some(LevelInsertionError::DuplicateKey)
+        );
+        assert_eq!(hash.capacity_info().entries, 1);
+        assert_eq!(hash.get_value(b"key"), b"value");
+    }
+
+    #[test]
+    fn insert_replace() {
+        let mut hash = default_level_hash("insert-replace");
+        assert_eq!(hash.insert_replace(b"key", b"value").unwrap(), None);
+        assert_eq!(
+            hash.insert_replace(b"key", b"new value").unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(hash.get_value(b"key"), b"new value");
+        assert_eq!(hash.capacity_info().entries, 1);
+        assert_matches!(
+            hash.insert_replace(b"", b"value").err(),
+            Some(LevelUpdateError::InsertionErr(
+                LevelInsertionError::EmptyKey
+            ))
+        );
+
+        let mut hash = create_level_hash("insert-replace-chain", true, |options| {
+            options.unique_keys(false);
+        });
+        hash.insert(b"key", b"first").unwrap();
+        hash.insert(b"key", b"second").unwrap();
+        assert_eq!(
+            hash.insert_replace(b"key", b"third").unwrap(),
+            Some(b"second".to_vec())
+        );
+        assert_eq!(
+            hash.get_all(b"key"),
+            vec![b"third".to_vec(), b"first".to_vec()]
+        );
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn retain() {
+        let mut hash = create_level_hash("retain", true, |options| {
+            options.level_size(4).bucket_size(4).stash_slots(2);
+        });
+        for i in 0..50 {
+            hash.insert(format!("key{}", i).as_bytes(), format!("{}", i).as_bytes())
+                .unwrap();
+        }
+
+        let removed = hash.retain(|_, value| {
+            let i: u32 = std::str::from_utf8(value).unwrap().parse().unwrap();
+            i % 3 == 0
+        });
+        assert_eq!(removed, 33);
+        for i in 0..50 {
+            let key = format!("key{}", i);
+            assert_eq!(hash.contains_key(key.as_bytes()), i % 3 == 0, "{}", key);
+        }
+        assert_eq!(hash.keys().count(), 17);
+        assert_eq!(hash.retain(|_, _| true), 0);
+        assert!(hash.verify().is_empty());
+
+        // the removed entries are unlinked from the chains of their keys
+        let mut hash = create_level_hash("retain-chain", true, |options| {
+            options.unique_keys(false);
+        });
+        hash.insert(b"key", b"first").unwrap();
+        hash.insert(b"key", b"second").unwrap();
+        hash.insert(b"key", b"third").unwrap();
+        hash.insert(b"other", b"second").unwrap();
+        assert_eq!(hash.retain(|_, value| value != b"second"), 2);
+        assert_eq!(
+            hash.get_all(b"key"),
+            vec![b"third".to_vec(), b"first".to_vec()]
+        );
+        assert!(!hash.contains_key(b"other"));
+        assert!(hash.verify().is_empty());
+    }
+
+    #[test]
+    fn bucket_occupancy() {
+        let mut hash = create_level_hash("bucket-occupancy", true, |options| {
+            options.level_size(4).bucket_size(4).stash_slots(2);
+        });
+        for i in 0..60 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        for i in (0..60).step_by(4) {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        hash.expand().unwrap();
+
+        let mut occupied = 0u64;
+        for &level in hash.levels() {
+            for bucket in 0..hash.level_bucket_count(level) {
+                let bitmap = (0..hash.bucket_size())
+                    .filter(|slot| {
+                        hash.io
+                            .occupied_entry(level as _LevelIdxT, bucket, *slot as u32)
+                            .is_some()
+                    })
+                    .fold(0u8, |bitmap, slot| bitmap | 1 << slot);
+                assert_eq!(hash.bucket_occupancy(level, bucket), Some(vec![bitmap]));
+                occupied += bitmap.count_ones() as u64;
+            }
+        }
+        let stash = hash.bucket_occupancy(Stash, 0).unwrap();
+        assert_eq!(stash.len(), 1);
+        occupied += stash[0].count_ones() as u64;
+        assert_eq!(occupied, 45);
+        assert_eq!(hash.capacity_info().entries, 45);
+
+        // the levels and the buckets which do not exist
+        assert_eq!(hash.bucket_occupancy(Stash, 1), None);
+        assert_eq!(hash.bucket_occupancy(L0, hash.level_bucket_count(L0)), None);
+        assert_eq!(hash.bucket_occupancy(L2, 0), None);
+        let hash = default_level_hash("bucket-occupancy-no-stash");
+        assert_eq!(hash.bucket_occupancy(Stash, 0), None);
+
+        // the buckets with more than 8 slots
+        let mut hash = create_level_hash("bucket-occupancy-large", true, |options| {
+            options.level_size(1).bucket_size(12).auto_expand(false);
+        });
+        for i in 0..30 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        let bitmap = hash.bucket_occupancy(L0, 0).unwrap();
+        assert_eq!(bitmap.len(), 2);
+        assert_eq!(bitmap[1] & 0xF0, 0);
+        assert_eq!(
+            bitmap[0].count_ones() + bitmap[1].count_ones(),
+            hash.io.bucket_occupancy(L0 as _LevelIdxT, 0) as u32
+        );
+    }
+
+    #[test]
+    fn slot_bitmap_is_saved() {
+        let create = |create_new| {
+            create_level_hash_2("slot-bitmap-saved", create_new, |options| {
+                options.level_size(3).bucket_size(4).stash_slots(2);
+            })
+        };
+        let bitmaps = |hash: &LevelHash| {
+            (hash.levels().iter())
+                .flat_map(|&level| {
+                    (0..hash.level_bucket_count(level))
+                        .map(move |bucket| hash.bucket_occupancy(level, bucket).unwrap())
+                })
+                .chain(hash.bucket_occupancy(Stash, 0))
+                .collect::<Vec<_>>()
+        };
 
-    use crate::io::IOEndianness;
-    use crate::level_io::LevelHashIO;
-    use crate::level_io::ValEntryReadExt;
-    use crate::level_io::ValuesEntry;
-    use crate::reprs::ValuesData;
-    use crate::result::LevelInitError;
-    use crate::result::LevelInitResult;
-    use crate::result::LevelInsertionError;
-    use crate::result::LevelUpdateError;
-    use crate::size::SIZE_U64;
-    use crate::util::align_8;
-    use crate::util::generate_seeds;
-    use crate::LevelHash;
-    use crate::LevelHashOptions;
+        let (mut hash, dir) = create(true);
+        let slots_file = Path::new(&dir).join("slot-bitmap-saved.index._slots");
+        for i in 0..40 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        hash.remove(b"key3").unwrap();
+        let expected = bitmaps(&hash);
+        assert!(!slots_file.exists());
+        drop(hash);
+        assert!(slots_file.exists());
+
+        // the saved bitmap is loaded and removed, it goes stale with the first write
+        let (mut hash, _) = create(false);
+        assert!(!slots_file.exists());
+        assert_eq!(bitmaps(&hash), expected);
+        assert!(hash.verify().is_empty());
+
+        // a bitmap saved for another state of the keymap is built again
+        let stale_file = Path::new(&dir).join("slot-bitmap-saved.stale");
+        let stamp = hash.io.slot_bitmap_stamp();
+        hash.io.slot_bitmap.save(&stale_file, &stamp).unwrap();
+        hash.remove(b"key5").unwrap();
+        let expected = bitmaps(&hash);
+        hash.io.slots_file = None;
+        drop(hash);
+        fs::rename(&stale_file, &slots_file).unwrap();
+        let (hash, _) = create(false);
+        assert_eq!(bitmaps(&hash), expected);
+        assert!(hash.verify().is_empty());
+        drop(hash);
 
-    use byteorder::ByteOrder;
-    use gxhash::GxHasher;
-    use std::hash::Hasher;
+        // a damaged bitmap is built again
+        let mut bytes = fs::read(&slots_file).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&slots_file, bytes).unwrap();
+        let (hash, _) = create(false);
+        assert_eq!(bitmaps(&hash), expected);
+        assert!(hash.verify().is_empty());
+    }
 
-    fn gxhash(seed: u64, data: &[u8]) -> u64 {
-        let mut hasher = GxHasher::with_seed(seed as i64);
-        hasher.write(data);
-        hasher.finish()
+    #[test]
+    fn free_slot_hints() {
+        // the buckets of 10 slots straddle the words of the slot bitmap
+        let mut hash = create_level_hash("free-slot-hints", true, |options| {
+            options.level_size(3).bucket_size(10);
+        });
+        for i in 0..150 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        for i in (0..150).step_by(7) {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+
+        for &level in hash.levels() {
+            for bucket in 0..hash.level_bucket_count(level) {
+                let first_free = (0..hash.bucket_size() as u32).find(|slot| {
+                    hash.io
+                        .occupied_entry(level as _LevelIdxT, bucket, *slot)
+                        .is_none()
+                });
+                assert_eq!(
+                    hash.io.first_free_slot(level as _LevelIdxT, bucket),
+                    first_free
+                );
+            }
+        }
+
+        for i in 0..150 {
+            let value = hash.get_value(format!("key{}", i).as_bytes());
+            assert_eq!(value.is_empty(), i % 7 == 0);
+        }
+        assert!(hash.verify().is_empty());
     }
 
-    fn create_level_hash_3(
-        name: &str,
-        create_new: bool,
-        conf: impl Fn(&mut LevelHashOptions),
-    ) -> (LevelInitResult, String) {
-        let dir_path = format!("target/tests/level-hash/index-{}", name);
-        let index_dir = Path::new(&dir_path);
-        if create_new && index_dir.exists() {
-            fs::remove_dir_all(&index_dir).expect("Failed to delete existing directory");
-        } else {
-            fs::create_dir_all(&index_dir).expect("Failed to create directories");
+    #[test]
+    fn custom_paths() {
+        #[derive(Debug)]
+        struct SplitPaths;
+
+        impl Paths for SplitPaths {
+            fn values_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+                index_dir
+                    .join("values")
+                    .join(format!("{}.vals", index_name))
+            }
+
+            fn keymap_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+                index_dir.join("keys").join(format!("{}.keys", index_name))
+            }
+
+            fn lock_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+                index_dir.join(format!("{}.lck", index_name))
+            }
         }
 
-        let (s1, s2) = generate_seeds();
-        let mut options = LevelHash::options();
-        options
-            .index_dir(index_dir)
-            .index_name(name)
-            .seeds(s1, s2)
-            .hash_fns(self::gxhash, self::gxhash);
+        let conf = |options: &mut LevelHashOptions| {
+            options.level_size(2).bucket_size(4).paths(SplitPaths);
+        };
+        let (mut hash, dir) = create_level_hash_2("custom-paths", true, conf);
+        for i in 0..20 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        let dir = Path::new(&dir);
+        assert!(dir.join("custom-paths.lck").exists());
+        drop(hash);
 
-        conf(&mut options);
+        assert!(dir.join("values/custom-paths.vals").exists());
+        assert!(dir.join("keys/custom-paths.keys").exists());
+        assert!(dir.join("custom-paths.index._meta").exists());
+        assert!(!dir.join("custom-paths.index").exists());
+        assert!(!dir.join("custom-paths.index._keymap").exists());
 
-        (options.build(), dir_path)
+        let hash = create_level_hash("custom-paths", false, conf);
+        for i in 0..20 {
+            assert_eq!(hash.get_value(format!("key{}", i).as_bytes()), b"value");
+        }
+        assert!(hash.verify().is_empty());
+        drop(hash);
+
+        LevelHash::options()
+            .index_dir(dir)
+            .index_name("custom-paths")
+            .paths(SplitPaths)
+            .remove_existing_index()
+            .unwrap();
+        assert!(!dir.join("values/custom-paths.vals").exists());
+        assert!(!dir.join("keys/custom-paths.keys").exists());
+        assert!(!dir.join("custom-paths.index._meta").exists());
     }
 
-    fn create_level_hash_2(
-        name: &str,
-        create_new: bool,
-        conf: impl Fn(&mut LevelHashOptions),
-    ) -> (LevelHash, String) {
-        let (hash, dir) = create_level_hash_3(name, create_new, conf);
-        (hash.expect("failed to create level hash"), dir)
+    #[test]
+    fn contains_many() {
+        let mut hash = create_level_hash("contains-many", true, |options| {
+            options.level_size(4).bucket_size(4).bloom_filter(true);
+        });
+        for i in (0..100).step_by(3) {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+
+        let keys = (0..100)
+            .map(|i| format!("key{}", i).into_bytes())
+            .collect::<Vec<_>>();
+        let found = hash.contains_many(&keys);
+        assert_eq!(found.len(), 100);
+        assert_eq!(found.count_ones(), 34);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(found.get(i), i % 3 == 0);
+            assert_eq!(found.get(i), hash.contains_key(key));
+        }
+        assert!(found.iter_ones().eq((0..100).step_by(3)));
+        assert!(hash.contains_many::<&[u8]>(&[]).is_empty());
     }
 
-    fn create_level_hash(
-        name: &str,
-        create_new: bool,
-        conf: impl Fn(&mut LevelHashOptions),
-    ) -> LevelHash {
-        create_level_hash_2(name, create_new, conf).0
+    #[test]
+    fn probe_order() {
+        let build = |name: &str, order: ProbeOrder| {
+            let mut hash = create_level_hash(name, true, |options| {
+                options
+                    .level_size(4)
+                    .bucket_size(4)
+                    .seeds(1, 2)
+                    .incremental_expansion(2)
+                    .probe_order(order);
+            });
+            for i in 0..150 {
+                hash.insert(format!("key{}", i).as_bytes(), b"value")
+                    .unwrap();
+            }
+            for i in (0..150).step_by(3) {
+                hash.remove(format!("key{}", i).as_bytes()).unwrap();
+            }
+            for i in 0..200 {
+                assert!(!hash.contains_key(format!("absent{}", i).as_bytes()));
+            }
+            for i in 0..150 {
+                let key = format!("key{}", i);
+                assert_eq!(hash.contains_key(key.as_bytes()), i % 3 != 0);
+            }
+            assert!(hash.verify().is_empty());
+            hash
+        };
+
+        let probes = |stats: &LevelStats| {
+            stats
+                .miss_probe_histogram
+                .iter()
+                .enumerate()
+                .map(|(idx, count)| idx as u64 * count)
+                .sum::<u64>()
+        };
+
+        let level_load = build("probe-order-level-load", ProbeOrder::LevelLoad);
+        let mut bucket_load = build("probe-order-bucket-load", ProbeOrder::BucketLoad);
+        assert!(probes(&bucket_load.stats()) < probes(&level_load.stats()));
+
+        // the bitmap is rebuilt after the slots are rewritten in bulk
+        bucket_load.expand().unwrap();
+        bucket_load.shrink().unwrap();
+        for i in 0..150 {
+            let key = format!("key{}", i);
+            assert_eq!(bucket_load.contains_key(key.as_bytes()), i % 3 != 0);
+        }
+        bucket_load.clear().unwrap();
+        assert!(!bucket_load.contains_key(b"key1"));
+        bucket_load.insert(b"key1", b"value").unwrap();
+        assert!(bucket_load.contains_key(b"key1"));
     }
 
-    fn default_level_hash(name: &str) -> LevelHash {
-        create_level_hash(name, true, |options| {
-            options.level_size(2).bucket_size(4).auto_expand(false);
-        })
+    #[test]
+    fn compact() {
+        let mut hash = create_level_hash("compact", true, |options| {
+            options.level_size(6).bucket_size(4).unique_keys(false);
+        });
+        assert_eq!(hash.garbage_ratio(), 0f32);
+
+        for i in 0..200 {
+            let value = vec![b'v'; i % 50 + 1];
+            hash.insert(format!("key{}", i).as_bytes(), &value).unwrap();
+        }
+        hash.insert(b"key1", b"older").unwrap();
+        hash.insert(b"key1", b"latest").unwrap();
+        for i in (0..200).step_by(3) {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        assert!(hash.garbage_ratio() > 0.2);
+
+        let used = hash.capacity_info().values_size;
+        let garbage = hash.io.val_garbage_bytes();
+        assert_eq!(hash.compact().unwrap(), garbage);
+        assert_eq!(hash.capacity_info().values_size, used - garbage);
+        assert_eq!(hash.garbage_ratio(), 0f32);
+        assert!(hash.verify().is_empty());
+
+        for i in 0..200 {
+            let value = hash.get_value(format!("key{}", i).as_bytes());
+            if i % 3 == 0 {
+                assert!(value.is_empty());
+            } else if i != 1 {
+                assert_eq!(value, vec![b'v'; i % 50 + 1]);
+            }
+        }
+        assert_eq!(
+            hash.get_all(b"key1"),
+            vec![b"latest".to_vec(), b"older".to_vec(), b"vv".to_vec()]
+        );
+
+        // the live bytes are recomputed when the index is opened
+        drop(hash);
+        let hash = create_level_hash("compact", false, |options| {
+            options.level_size(6).bucket_size(4).unique_keys(false);
+        });
+        assert_eq!(hash.garbage_ratio(), 0f32);
     }
 
     #[test]
-    fn simple_insertion() {
-        let mut hash = default_level_hash("insert");
+    fn entry_pins() {
+        let mut hash = create_level_hash("entry-pins", true, |options| {
+            options.level_size(4).bucket_size(4);
+        });
+        for i in 0..20 {
+            hash.insert(format!("key{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        assert_eq!(hash.pin(b"missing"), None);
 
-        assert!(hash.insert(b"key1", b"value1").is_ok());
-        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+        let pin = hash.pin(b"key10").unwrap();
+        let other = hash.pin(b"key10").unwrap();
+        assert_ne!(pin, other);
+        assert_eq!(hash.pinned_value_ref(&pin), Some(&b"value"[..]));
+
+        // the pinned entry is not moved by the compaction
+        for i in 0..10 {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        hash.compact().unwrap();
+        let addr = hash.io.pinned_entry(pin.0).unwrap().addr;
+        assert_eq!(hash.pinned_value_ref(&pin), Some(&b"value"[..]));
+
+        // the pinned entry is kept after it is updated or removed
+        hash.update(b"key10", b"new value").unwrap();
+        assert_eq!(hash.get_value(b"key10"), b"new value");
+        assert_eq!(hash.pinned_value(&pin), Some(b"value".to_vec()));
+        hash.remove(b"key10").unwrap();
+        hash.compact().unwrap();
+        assert_eq!(hash.io.pinned_entry(pin.0).unwrap().addr, addr);
+        assert_eq!(hash.pinned_value(&other), Some(b"value".to_vec()));
+        assert!(hash.verify().is_empty());
+
+        // the region of the entry is released with its last pin
+        hash.unpin(pin);
+        assert!(hash.io.pins.is_released(addr));
+        hash.unpin(other);
+        assert!(!hash.io.pins.is_pinned(addr));
+        assert!(!hash.io.pins.is_released(addr));
+        assert!(hash.verify().is_empty());
+        let garbage = hash.io.val_garbage_bytes();
+        assert!(garbage > 0);
+        assert_eq!(hash.compact().unwrap(), garbage);
+        assert!(hash.verify().is_empty());
     }
 
     #[test]
-    fn overflow() {
-        let mut hash = default_level_hash("overflow");
+    fn auto_compact() {
+        let block_size = page_size();
+        let mut hash = create_level_hash("auto-compact", true, |options| {
+            options
+                .level_size(6)
+                .bucket_size(4)
+                .values_growth(block_size)
+                .auto_compact(0.5);
+        });
 
-        for i in 0..hash.total_slots() {
-            let key = format!("key{}", i).as_bytes().to_vec();
-            let value = format!("value{}", i).as_bytes().to_vec();
-            assert!(hash.insert(&key, &value).is_ok());
+        let value = vec![b'v'; 100];
+        for i in 0..200 {
+            hash.insert(format!("key{}", i).as_bytes(), &value).unwrap();
+        }
+
+        // the garbage stays below the threshold until most of the entries are removed
+        for i in 0..100 {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        assert!(hash.garbage_ratio() > 0.45);
+        for i in 100..150 {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        assert!(hash.garbage_ratio() < 0.5);
+        assert!(hash.verify().is_empty());
+        for i in 150..200 {
+            assert_eq!(hash.get_value(format!("key{}", i).as_bytes()), value);
         }
 
         assert_matches!(
-            hash.insert(b"kkk", b"vvv").err(),
-            Some(LevelInsertionError::LevelOverflow)
+            LevelHashOptions::new()
+                .auto_compact(1.0)
+                .in_memory(true)
+                .build()
+                .err(),
+            Some(LevelInitError::InvalidArg(_))
         );
     }
 
     #[test]
-    fn removal() {
-        let mut hash = default_level_hash("remove");
-        assert!(hash.insert(b"key1", b"value1").is_ok());
-        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
-        assert_eq!(hash.remove(b"key1"), Some(b"value1".to_vec()));
-        assert_eq!(hash.get_value(b"key1"), vec![]);
+    fn values_segments() {
+        let segment_size = LevelHashIO::VALUES_SEGMENT_SIZE_MIN;
+        let conf = |options: &mut LevelHashOptions| {
+            options
+                .level_size(9)
+                .bucket_size(4)
+                .values_growth(segment_size)
+                .values_segment_size(segment_size);
+        };
+
+        let (mut hash, dir) = create_level_hash_2("values-segments", true, conf);
+        let values_file = Path::new(&dir).join("values-segments.index");
+        let value = vec![b'v'; 100];
+        for i in 0..2000 {
+            hash.insert(format!("key{}", i).as_bytes(), &value).unwrap();
+        }
+
+        // a value which does not fit in a segment is stored in the overflow file
+        let large = vec![b'l'; segment_size as usize];
+        hash.insert(b"large", &large).unwrap();
+        assert_eq!(hash.get_value(b"large"), large);
+
+        let segments = hash.io.values.size.div_ceil(segment_size) as usize;
+        assert!(segments > 2);
+        for idx in 0..segments {
+            assert!(SegmentedFile::segment_path(&values_file, idx).exists());
+        }
+
+        // the entries do not cross the ends of the segments
+        for entry in hash.io.values_iter() {
+            let end = entry.addr + entry.esize() - 1;
+            assert_eq!(
+                hash.io.values.segment_end(entry.addr),
+                hash.io.values.segment_end(end)
+            );
+        }
+
+        for i in (0..2000).step_by(2) {
+            hash.remove(format!("key{}", i).as_bytes()).unwrap();
+        }
+        for i in 2000..2500 {
+            hash.insert(format!("key{}", i).as_bytes(), &value).unwrap();
+        }
+        assert!(hash.verify().is_empty());
+        drop(hash);
+
+        let mut hash = create_level_hash("values-segments", false, conf);
+        assert_eq!(hash.io.meta.read().val_segment_size, segment_size);
+        for i in 0..2500 {
+            let key = format!("key{}", i);
+            assert_eq!(hash.contains_key(key.as_bytes()), i >= 2000 || i % 2 == 1);
+        }
+        assert_eq!(hash.get_value(b"key2001"), value);
+        assert!(hash.verify().is_empty());
+
+        // the segments which are no longer used are deleted
+        hash.clear().unwrap();
+        let cleared_segments = hash.io.values.size.div_ceil(segment_size) as usize;
+        assert!(cleared_segments < segments);
+        assert!(SegmentedFile::segment_path(&values_file, cleared_segments - 1).exists());
+        assert!(!SegmentedFile::segment_path(&values_file, cleared_segments).exists());
+        hash.insert(b"key", b"value").unwrap();
+        assert_eq!(hash.get_value(b"key"), b"value");
     }
 
     #[test]
-    fn hash_clear() {
-        let mut hash = default_level_hash("clear");
-        assert!(hash.insert(b"key1", b"value1").is_ok());
-        assert!(hash.insert(b"key2", b"value2").is_ok());
+    fn no_checksums_by_default() {
+        let mut hash = default_level_hash("no-checksums");
+        hash.insert(b"key1", b"value1").unwrap();
+
+        let (entry, _, _, _) = hash.find_slot(b"key1").unwrap();
+        assert!(!entry.has_checksum());
+        assert_eq!(entry.esize(), ValuesEntry::ENTRY_SIZE_MIN + 4 + 6);
+        assert_eq!(hash.io.meta.read().val_version, LEVEL_VALUES_VERSION);
+    }
 
-        hash.clear().expect("failed to clear level hash");
+    #[test]
+    #[cfg(feature = "testing")]
+    fn injected_io_failures() {
+        use crate::testing::FaultPlan;
+        use crate::testing::FileOpKind;
+
+        let plan = FaultPlan::new()
+            .fail_nth(Some(IndexFile::Values), FileOpKind::Resize, 1)
+            .fail_nth(Some(IndexFile::Keymap), FileOpKind::Resize, 1)
+            .fail_nth(None, FileOpKind::Flush, 1)
+            .shared();
+        let mut hash = create_level_hash("injected-io-failures", true, |options| {
+            options
+                .level_size(3)
+                .bucket_size(4)
+                .auto_expand(false)
+                .io_shim(plan.clone());
+        });
 
-        assert_eq!(hash.get_value(b"key1"), vec![]);
-        assert_eq!(hash.get_value(b"key2"), vec![]);
+        // the values file is resized again for the next free slot of the entry
+        hash.insert(b"key0", b"value").unwrap();
+        assert_eq!(plan.count(Some(IndexFile::Values), FileOpKind::Resize), 2);
+        assert_eq!(hash.get_value(b"key0"), b"value");
+
+        assert!(hash.expand().is_err());
+        assert_eq!(hash.level_size(), 3);
+        hash.expand().unwrap();
+        assert_eq!(hash.level_size(), 4);
+
+        assert!(hash.flush().is_err());
+        hash.flush().unwrap();
+        assert_eq!(hash.get_value(b"key0"), b"value");
+        assert!(hash.verify().is_empty());
     }
 
     #[test]
-    fn value_update_for_non_existent_entry() {
-        let mut hash = default_level_hash("value_update_for_non_existent_entry");
-        assert!(hash.insert(b"k", b"v").is_ok());
+    #[cfg(feature = "testing")]
+    fn close_reports_errors() {
+        use crate::testing::FaultPlan;
+        use crate::testing::FileOpKind;
+
+        let plan = FaultPlan::new()
+            .fail_nth(Some(IndexFile::Keymap), FileOpKind::Flush, 1)
+            .shared();
+        let mut hash = create_level_hash("close-reports-errors", true, |options| {
+            options.level_size(3).bucket_size(4).io_shim(plan.clone());
+        });
+        hash.insert(b"key", b"value").unwrap();
+        assert!(hash.close().is_err());
 
-        let entry = hash.find_slot(b"k");
-        assert!(entry.is_some());
+        let hash = create_level_hash("close-reports-errors", false, |options| {
+            options.level_size(3).bucket_size(4).io_shim(plan.clone());
+        });
+        assert_eq!(hash.get_value(b"key"), b"value");
+        hash.close().unwrap();
+    }
 
-        let entry = entry.unwrap();
-        assert_eq!(entry.0.key(&hash.io.values), b"k".to_vec());
-        assert_eq!(entry.0.value(&hash.io.values), b"v".to_vec());
+    #[test]
+    #[cfg(feature = "secondary-index")]
+    fn secondary_index() {
+        use crate::secondary::ValueHash;
+
+        // the first byte of the value is the owner of the entry
+        let by_owner =
+            |_: &[u8], value: &[u8]| value.first().map(|b| vec![*b]).into_iter().collect();
+        let mut hash = create_level_hash("secondary-index", true, |options| {
+            options
+                .level_size(3)
+                .bucket_size(4)
+                .unique_keys(false)
+                .secondary_index(by_owner);
+        });
+        assert!(hash.insert(b"key1", b"avalue1").is_ok());
+        assert!(hash.insert(b"key2", b"bvalue2").is_ok());
+        assert!(hash.insert(b"key3", b"avalue3").is_ok());
+        assert!(hash.insert_from_reader(b"key4", 7, &b"bvalue4"[..]).is_ok());
+        assert_eq!(
+            hash.find_keys_by_secondary(b"a"),
+            Some(vec![b"key1".to_vec(), b"key3".to_vec()])
+        );
+        assert_eq!(
+            hash.find_keys_by_secondary(b"b"),
+            Some(vec![b"key2".to_vec(), b"key4".to_vec()])
+        );
+        assert_eq!(hash.find_keys_by_secondary(b"c"), Some(vec![]));
 
-        assert_matches!(
-            hash.update(b"kk", b"vv"),
-            Err(LevelUpdateError::SlotNotFound)
+        hash.update(b"key1", b"cvalue1").unwrap();
+        hash.remove(b"key2").unwrap();
+        assert_eq!(
+            hash.find_keys_by_secondary(b"a"),
+            Some(vec![b"key3".to_vec()])
+        );
+        assert_eq!(
+            hash.find_keys_by_secondary(b"b"),
+            Some(vec![b"key4".to_vec()])
+        );
+        assert_eq!(
+            hash.find_keys_by_secondary(b"c"),
+            Some(vec![b"key1".to_vec()])
         );
-    }
 
-    #[test]
-    fn value_update_for_existing_entry_with_null_value() {
-        let mut hash = default_level_hash("update-existing-with-null-value");
+        // the older entries of a key are indexed until they are removed
+        hash.insert(b"key3", b"bvalue5").unwrap();
+        assert_eq!(
+            hash.find_keys_by_secondary(b"a"),
+            Some(vec![b"key3".to_vec()])
+        );
+        assert_eq!(
+            hash.find_keys_by_secondary(b"b"),
+            Some(vec![b"key3".to_vec(), b"key4".to_vec()])
+        );
+        hash.remove_all(b"key3");
+        assert_eq!(hash.find_keys_by_secondary(b"a"), Some(vec![]));
+        assert_eq!(
+            hash.find_keys_by_secondary(b"b"),
+            Some(vec![b"key4".to_vec()])
+        );
 
-        assert!(hash.insert(b"k", &[]).is_ok());
-        let entry = hash.find_slot(b"k").unwrap();
-        assert_eq!(entry.0.key(&hash.io.values), b"k".to_vec());
-        assert_eq!(entry.0.value(&hash.io.values), vec![]);
+        // the index is rebuilt when the level hash is reopened or repaired
+        drop(hash);
+        let mut hash = create_level_hash("secondary-index", false, |options| {
+            options
+                .level_size(3)
+                .bucket_size(4)
+                .unique_keys(false)
+                .secondary_index(by_owner);
+        });
+        assert_eq!(
+            hash.find_keys_by_secondary(b"b"),
+            Some(vec![b"key4".to_vec()])
+        );
+        assert_eq!(
+            hash.find_keys_by_secondary(b"c"),
+            Some(vec![b"key1".to_vec()])
+        );
+        hash.repair().unwrap();
+        assert_eq!(
+            hash.find_keys_by_secondary(b"c"),
+            Some(vec![b"key1".to_vec()])
+        );
+        hash.update_in_place(b"key1", |value| value[0] = b'd')
+            .unwrap();
+        assert_eq!(hash.find_keys_by_secondary(b"c"), Some(vec![]));
+        assert_eq!(
+            hash.find_keys_by_secondary(b"d"),
+            Some(vec![b"key1".to_vec()])
+        );
+
+        hash.clear().unwrap();
+        assert_eq!(hash.find_keys_by_secondary(b"d"), Some(vec![]));
+        drop(hash);
 
+        // without a secondary index
+        let hash = create_level_hash("secondary-index", false, |options| {
+            options.level_size(3).bucket_size(4).unique_keys(false);
+        });
+        assert_eq!(hash.find_keys_by_secondary(b"c"), None);
+        assert_eq!(hash.find_keys_by_value_hash(b"value"), None);
+        drop(hash);
+
+        let mut hash = create_level_hash("secondary-index", true, |options| {
+            options
+                .level_size(3)
+                .bucket_size(4)
+                .spill_threshold(4)
+                .secondary_index(ValueHash);
+        });
+        assert!(hash.insert(b"key1", b"value1").is_ok());
+        assert!(hash.insert(b"key2", b"value2").is_ok());
+        assert!(hash.insert(b"key3", b"value1").is_ok());
         assert_eq!(
-            hash.update(b"k", b"newV").expect("failed to update entry"),
-            vec![]
+            hash.find_keys_by_value_hash(b"value1"),
+            Some(vec![b"key1".to_vec(), b"key3".to_vec()])
         );
+        assert_eq!(
+            hash.find_keys_by_value_hash(b"value2"),
+            Some(vec![b"key2".to_vec()])
+        );
+        assert_eq!(hash.find_keys_by_value_hash(b"value3"), Some(vec![]));
+    }
+
+    // the invariants are violated once the writes are dropped
+    #[test]
+    #[cfg(all(feature = "testing", not(feature = "debug-invariants")))]
+    fn injected_crash() {
+        use crate::testing::FaultPlan;
+
+        let plan = FaultPlan::new().crash_after_writes(0).shared();
+        let mut hash = create_level_hash("injected-crash", true, |options| {
+            options.level_size(3).bucket_size(4).io_shim(plan.clone());
+        });
+        assert!(!plan.crashed());
+        let _ = hash.insert(b"key", b"value");
+        assert!(plan.crashed());
+        assert!(hash.get_value(b"key").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn disk_full() {
+        use crate::testing::FileOp;
+        use crate::testing::IOShim;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
+        use std::sync::Arc;
+
+        #[derive(Debug, Default)]
+        struct DiskFull(AtomicBool);
+
+        impl IOShim for DiskFull {
+            fn before(&self, file: IndexFile, op: FileOp) -> std::io::Result<()> {
+                if file == IndexFile::Values
+                    && matches!(op, FileOp::Resize { .. })
+                    && self.0.load(Ordering::SeqCst)
+                {
+                    return Err(std::io::Error::from_raw_os_error(libc::ENOSPC));
+                }
+                Ok(())
+            }
+        }
+
+        let shim = Arc::new(DiskFull::default());
+        let mut hash = create_level_hash("disk-full", true, |options| {
+            options.level_size(3).bucket_size(4).io_shim(shim.clone());
+        });
+        hash.insert(b"key0", b"value0").unwrap();
 
-        let entry = hash.find_slot(b"k").unwrap();
-        assert_eq!(entry.0.key(&hash.io.values), b"k".to_vec());
-        assert_eq!(entry.0.value(&hash.io.values), b"newV".to_vec());
+        // the other slots are not tried once the disk is full
+        shim.0.store(true, Ordering::SeqCst);
+        assert_matches!(
+            hash.insert(b"key1", b"value1"),
+            Err(LevelInsertionError::NoSpace(_))
+        );
+        assert_eq!(hash.get_value(b"key0"), b"value0");
+        assert!(hash.get_value(b"key1").is_empty());
+        assert!(hash.verify().is_empty());
+
+        shim.0.store(false, Ordering::SeqCst);
+        hash.insert(b"key1", b"value1").unwrap();
+        assert_eq!(hash.get_value(b"key1"), b"value1");
+        assert!(hash.verify().is_empty());
     }
 
     #[test]
-    fn existing_level_init() {
+    #[cfg(feature = "ttl")]
+    fn expiring_entries() {
         {
-            let mut hash = default_level_hash("init-existing");
-            hash.insert(b"key", b"value").unwrap();
-            hash.insert(b"null", &[]).unwrap();
-            hash.insert(
-                b"long",
-                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ",
-            )
-            .unwrap();
+            let mut hash = create_level_hash("expiring-entries", true, |options| {
+                options.level_size(2).bucket_size(4).checksums(true);
+            });
 
-            assert_eq!(hash.get_value(b"key"), b"value".to_vec());
-            assert_eq!(hash.get_value(b"null"), vec![]);
+            hash.insert_with_ttl(b"expired", b"value1", Duration::ZERO)
+                .unwrap();
+            hash.insert_with_ttl(b"live", b"value2", Duration::from_secs(3600))
+                .unwrap();
+            hash.insert(b"forever", b"value3").unwrap();
+            assert_eq!(hash.io.meta.read().val_expiry, 1);
+
+            let (entry, _, _, _) = hash.find_slot(b"live").unwrap();
+            assert!(entry.has_expiry());
+            assert_eq!(entry.key_size(), 4);
             assert_eq!(
-                hash.get_value(b"long"),
-                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".to_vec()
+                entry.esize(),
+                ValuesEntry::ENTRY_SIZE_MIN
+                    + 4
+                    + 6
+                    + ValuesEntry::EXPIRY_SIZE
+                    + ValuesEntry::CHECKSUM_SIZE
             );
-        }
-
-        {
-            let hash = create_level_hash("init-existing", false, |options| {
-                options.level_size(2).bucket_size(4).auto_expand(false);
-            });
 
-            assert_eq!(hash.get_value(b"key"), b"value".to_vec());
-            assert_eq!(hash.get_value(b"null"), vec![]);
+            // the expired entries are not returned by the lookups
+            assert!(!hash.contains_key(b"expired"));
+            assert_eq!(hash.get_value(b"expired"), vec![]);
             assert_eq!(
-                hash.get_value(b"long"),
-                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".to_vec()
+                hash.try_get_value(b"live").unwrap(),
+                Some(b"value2".to_vec())
+            );
+            assert_eq!(hash.keys().count(), 2);
+
+            // the updated entry keeps its expiry
+            hash.update(b"live", b"value4").unwrap();
+            let (entry, _, _, _) = hash.find_slot(b"live").unwrap();
+            assert!(entry.has_expiry());
+            assert_matches!(
+                hash.update(b"expired", b"value5"),
+                Err(LevelUpdateError::SlotNotFound)
             );
         }
-    }
 
-    #[test]
-    fn level_hash_expand() {
-        let mut hash = create_level_hash("expand", true, |options| {
-            options.level_size(5).bucket_size(10).auto_expand(false);
+        let mut hash = create_level_hash("expiring-entries", false, |options| {
+            options.level_size(2).bucket_size(4);
         });
 
-        let slots = hash.total_slots() - hash.io.meta.read().km_bucket_size as u64;
-        for i in 0..slots {
-            let key = format!("key{}", i).as_bytes().to_vec();
-            let value = format!("value{}", i).as_bytes().to_vec();
-            hash.insert(&key, &value).expect("failed to insert entry");
-        }
-
-        println!("Inserted {} entries", slots);
-
-        for i in 0..slots {
-            let key = format!("key{}", i).as_bytes().to_vec();
-            let value = format!("value{}", i).as_bytes().to_vec();
-            assert_eq!(hash.get_value(&key), value);
-        }
+        assert_eq!(hash.get_value(b"live"), b"value4".to_vec());
+        hash.insert_with_ttl(b"key1", b"value6", Duration::ZERO)
+            .unwrap();
+        hash.insert_with_ttl(b"key2", b"value7", Duration::ZERO)
+            .unwrap();
 
-        hash.expand().expect("failed to expand level hash");
+        // the key of an expired entry can be inserted again
+        hash.insert(b"key1", b"value8").unwrap();
+        assert_eq!(hash.get_value(b"key1"), b"value8".to_vec());
 
-        for i in 0..slots {
-            let key = format!("key{}", i).as_bytes().to_vec();
-            let value = format!("value{}", i).as_bytes().to_vec();
-            assert_eq!(hash.get_value(key.as_slice()), value.clone());
-        }
+        assert_eq!(hash.purge_expired(), 1);
+        assert_eq!(hash.purge_expired(), 0);
+        assert!(hash.find_slot(b"key2").is_none());
+        assert_eq!(hash.keys().count(), 3);
     }
 
     #[test]
-    fn expansion_with_huge_slot_count() {
-        let mut hash = create_level_hash("expand-huge-slot-count", true, |options| {
-            options.level_size(15).bucket_size(4).auto_expand(false);
+    #[cfg(not(feature = "ttl"))]
+    fn expiring_entries_require_feature() {
+        {
+            let mut hash = default_level_hash("expiring-entries-feature");
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.io.meta.write().val_expiry = 1;
+        }
+
+        let (hash, _) = create_level_hash_3("expiring-entries-feature", false, |options| {
+            options.level_size(2).bucket_size(4);
         });
+        assert_matches!(hash.err(), Some(LevelInitError::FeatureRequired("ttl")));
+    }
 
-        let mut i = 0u32;
-        while hash
-            .insert(
-                format!("key{}", i).as_bytes(),
-                format!("value{}", i).as_bytes(),
-            )
-            .is_ok()
+    #[test]
+    fn content_fingerprint() {
+        let large = "large".repeat(100).into_bytes();
+        let fingerprint;
         {
-            i += 1;
+            let mut hash = create_level_hash("fingerprint-1", true, |options| {
+                options.level_size(4).bucket_size(4).spill_threshold(16);
+            });
+            assert_eq!(hash.content_fingerprint(), 0);
+
+            hash.insert(b"key1", b"value1").unwrap();
+            hash.insert(b"key2", b"value2").unwrap();
+            hash.insert(b"key3", &large).unwrap();
+            hash.update(b"key1", b"updated").unwrap();
+            hash.remove(b"key2").unwrap();
+            fingerprint = hash.content_fingerprint();
         }
 
-        println!("inserted {} entries", i);
-        println!("load factor: {}", hash.load_factor());
+        let mut other = create_level_hash("fingerprint-2", true, |options| {
+            options.level_size(2).bucket_size(8).checksums(true);
+        });
+        other.insert(b"key3", &large).unwrap();
+        other.insert(b"key1", b"updated").unwrap();
+        assert_eq!(other.content_fingerprint(), fingerprint);
 
-        hash.expand().expect("failed to expand level hash");
+        other.insert(b"key4", b"value4").unwrap();
+        assert_ne!(other.content_fingerprint(), fingerprint);
 
-        println!("load factor after expansion: {}", hash.load_factor());
+        other.clear().unwrap();
+        assert_eq!(other.content_fingerprint(), 0);
 
-        for j in 0..i {
-            let key = format!("key{}", j).as_bytes().to_vec();
-            let value = format!("value{}", j).as_bytes().to_vec();
-            assert_eq!(hash.get_value(&key), value);
+        {
+            let mut hash = create_level_hash("fingerprint-1", false, |options| {
+                options.level_size(4).bucket_size(4).spill_threshold(16);
+            });
+            assert_eq!(hash.content_fingerprint(), fingerprint);
+
+            // simulate an index created before the fingerprint was maintained
+            let meta = hash.io.meta.write();
+            meta.val_fingerprint = 0;
+            meta.val_fingerprint_valid = 0;
         }
+
+        let hash = create_level_hash("fingerprint-1", false, |options| {
+            options.level_size(4).bucket_size(4).spill_threshold(16);
+        });
+        assert_eq!(hash.content_fingerprint(), fingerprint);
     }
 
     #[test]
-    fn meta_after_expand() {
-        let mut hash = create_level_hash("meta-after-expand", true, |options| {
-            options.level_size(5).bucket_size(10).auto_expand(false);
+    fn duplicate_stats_and_dedupe() {
+        let mut hash = create_level_hash("dedupe-keep-latest", true, |options| {
+            options.level_size(4).bucket_size(4).unique_keys(false);
         });
 
-        let l0_size: u64 = hash.top_level_bucket_count() as u64
-            * hash.io.meta.read().km_bucket_size as u64
-            * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES;
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key1", b"value2").unwrap();
+        hash.insert(b"key1", b"value3").unwrap();
+        hash.insert(b"key2", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+        hash.insert(b"key3", b"value1").unwrap();
 
-        {
-            let meta = hash.io.meta.read();
-            assert_eq!(meta.km_level_size, 5);
-            assert_eq!(meta.km_bucket_size, 10);
-            assert_eq!(meta.km_l0_addr, 0);
-            assert_eq!(meta.km_l1_addr, l0_size);
-        }
+        assert_eq!(
+            hash.duplicate_stats(),
+            DuplicateStats {
+                duplicate_keys: 2,
+                redundant_entries: 3
+            }
+        );
 
-        hash.expand().expect("failed to expand level hash");
+        assert_eq!(hash.dedupe_keep_latest(), 3);
+        assert_eq!(hash.duplicate_stats(), DuplicateStats::default());
 
-        {
-            let meta = hash.io.meta.read();
-            assert_eq!(meta.km_level_size, 6);
-            assert_eq!(meta.km_bucket_size, 10);
-            assert_eq!(meta.km_l0_addr, l0_size + (l0_size >> 1));
-            assert_eq!(meta.km_l1_addr, 0);
-        }
+        assert_eq!(hash.get_value(b"key1"), b"value3".to_vec());
+        assert_eq!(hash.get_value(b"key2"), b"value2".to_vec());
+        assert_eq!(hash.get_value(b"key3"), b"value1".to_vec());
     }
 
     #[test]
-    fn values_file_binary_repr() {
-        let file_name = "values-binary-repr";
-        let mut hash = create_level_hash(file_name, true, |options| {
-            options.auto_expand(false);
+    fn chained_entries() {
+        let mut hash = create_level_hash("chained-entries", true, |options| {
+            options
+                .level_size(4)
+                .bucket_size(4)
+                .unique_keys(false)
+                .checksums(true);
         });
 
-        // min_size + "key1".len() + "value1".len()
-        let entry_size = ValuesEntry::ENTRY_SIZE_MIN + 4 + 6;
-
-        for i in 0..10 {
-            let key = format!("key{}", i).as_bytes().to_vec();
-            let value = format!("value{}", i).as_bytes().to_vec();
-            assert!(hash.insert(&key, &value).is_ok());
-        }
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key1", b"value2").unwrap();
+        hash.insert(b"key1", b"value3").unwrap();
+        hash.insert(b"key2", b"value1").unwrap();
 
-        let index_file = &format!(
-            "target/tests/level-hash/index-{}/{}.index",
-            file_name, file_name
+        // the entries of a key share a single slot
+        assert_eq!(hash.occupancy().total(), 2);
+        assert_eq!(hash.get_value(b"key1"), b"value3".to_vec());
+        assert_eq!(
+            hash.get_all(b"key1"),
+            vec![b"value3".to_vec(), b"value2".to_vec(), b"value1".to_vec()]
         );
-        let mut input = fs::read(&index_file).expect("Unable to read index file");
-        let input = input.as_mut_slice();
+        assert_eq!(hash.get_all(b"key2"), vec![b"value1".to_vec()]);
+        assert!(hash.get_all(b"key3").is_empty());
 
+        assert_eq!(hash.update(b"key1", b"value4").unwrap(), b"value3".to_vec());
+        assert_eq!(hash.remove(b"key1"), Some(b"value4".to_vec()));
         assert_eq!(
-            IOEndianness::read_u64(input),
-            LevelHashIO::VALUES_MAGIC_NUMBER
+            hash.get_all(b"key1"),
+            vec![b"value2".to_vec(), b"value1".to_vec()]
         );
+        assert_eq!(hash.occupancy().total(), 2);
+        assert_eq!(hash.verify(), vec![]);
 
-        let pos = SIZE_U64; // magic number
-        let input = &mut input[pos as usize..];
+        assert_eq!(
+            hash.remove_all(b"key1"),
+            vec![b"value2".to_vec(), b"value1".to_vec()]
+        );
+        assert!(hash.get_all(b"key1").is_empty());
+        assert!(!hash.contains_key(b"key1"));
+        assert_eq!(hash.occupancy().total(), 1);
+
+        hash.insert(b"key3", b"value1").unwrap();
+        hash.insert(b"key3", b"value2").unwrap();
+        hash.insert(b"key3", b"value3").unwrap();
+
+        // the chain is relinked to a slot by repair
+        let (_, level, bucket, slot) = hash.find_slot(b"key3").unwrap();
+        let slot_addr = hash.io.slot_addr(level, bucket, slot);
+        hash.io.km_write_addr(slot_addr, 0);
+        assert!(hash
+            .verify()
+            .iter()
+            .any(|i| matches!(i, Inconsistency::UnreachableEntry { .. })));
+
+        let report = hash.repair().unwrap();
+        assert_eq!(report.relinked_entries, 4);
+        assert_eq!(report.dropped_entries, 0);
+        assert_eq!(
+            hash.get_all(b"key3"),
+            vec![b"value3".to_vec(), b"value2".to_vec(), b"value1".to_vec()]
+        );
 
-        // since no entries are removed here, values should be laid out sequentially in the file
-        let mut pos = 0;
-        for i in 0..10 {
-            let data = unsafe { &*(input.as_ptr().add(pos as usize) as *const ValuesData) };
+        assert_eq!(hash.dedupe_keep_latest(), 2);
+        assert_eq!(hash.get_all(b"key3"), vec![b"value3".to_vec()]);
+        assert_eq!(hash.verify(), vec![]);
+    }
 
-            assert_eq!(data.key_size, 4);
-            assert_eq!(data.value_size, 6);
-            assert_eq!(
-                &input[(pos + ValuesEntry::OFF_KEY) as usize
-                    ..(pos + ValuesEntry::OFF_KEY + data.key_size as u64) as usize],
-                format!("key{}", i).as_bytes()
-            );
+    #[test]
+    fn get_or_insert_with() {
+        let mut hash = default_level_hash("get-or-insert-with");
+        hash.insert(b"key1", b"value1").unwrap();
+
+        let value = hash.get_or_insert_with(b"key1", || unreachable!());
+        assert_eq!(value.unwrap(), b"value1");
+
+        let value = hash.get_or_insert_with(b"key2", || b"value2".to_vec());
+        assert_eq!(value.unwrap(), b"value2");
+        assert_eq!(hash.get_value(b"key2"), b"value2");
+        assert_eq!(hash.occupancy().total(), 2);
+
+        // the level hash is filled until some of the keys have no free slot in their buckets
+        for i in 3..14 {
+            let key = format!("key{}", i).into_bytes();
+            let value = format!("value{}", i).into_bytes();
             assert_eq!(
-                &input[(pos + ValuesEntry::OFF_KEY + data.key_size as u64) as usize
-                    ..(pos + ValuesEntry::OFF_KEY + data.key_size as u64 + data.value_size as u64)
-                        as usize],
-                format!("value{}", i).as_bytes()
+                hash.get_or_insert_with(&key, || value.clone()).unwrap(),
+                value
             );
+        }
 
-            pos = align_8(pos + entry_size); // + magic number len
+        for i in 1..14 {
+            let key = format!("key{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), format!("value{}", i).into_bytes());
         }
+
+        assert_matches!(
+            hash.get_or_insert_with(b"", Vec::new),
+            Err(LevelInsertionError::EmptyKey)
+        );
     }
 
     #[test]
-    fn test_file_lock_is_acquired() {
-        let file_name = "check-file-lock-acquired";
-        let (_hash, dir) = create_level_hash_2(file_name, true, |_| {});
-        let lock_path = Path::new(&dir).join(&format!("{}.index.lock", file_name));
-        assert!(lock_path.exists());
+    fn bulk_load() {
+        let dir = Path::new("target/tests/level-hash/index-bulk-load");
+        if dir.exists() {
+            fs::remove_dir_all(dir).expect("Failed to delete existing directory");
+        }
 
-        let lock_file = File::options()
-            .read(true)
-            .write(true)
-            .create(false)
-            .open(lock_path)
-            .unwrap();
+        let entries = (0..1000)
+            .map(|i| (format!("key{}", i), format!("value{}", i)))
+            .chain([("key1".to_string(), "latest".to_string())])
+            .collect::<Vec<_>>();
 
-        // assert that trying to acquire an exclusive lock on the lock file would block
-        assert_eq!(
-            unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
-            -1
-        );
-        assert_eq!(
-            io::Error::last_os_error().raw_os_error().unwrap(),
-            libc::EWOULDBLOCK
+        let mut options = LevelHash::options();
+        options
+            .index_dir(dir)
+            .index_name("bulk-load")
+            .level_size(2)
+            .bucket_size(4);
+
+        let hash = LevelHash::bulk_load(entries, &mut options).unwrap();
+        assert_eq!(hash.expand_count, 0);
+        assert_eq!(hash.level_size(), 9);
+        assert_eq!(hash.occupancy().total(), 1000);
+        assert!(hash.load_factor() <= BULK_LOAD_FACTOR);
+
+        for i in (0..1000).filter(|i| *i != 1) {
+            let key = format!("key{}", i).into_bytes();
+            assert_eq!(hash.get_value(&key), format!("value{}", i).into_bytes());
+        }
+        assert_eq!(hash.get_value(b"key1"), b"latest");
+        drop(hash);
+
+        let mut options = LevelHash::options();
+        options.index_dir(dir).index_name("bulk-load");
+        assert_matches!(
+            LevelHash::bulk_load([(b"key", b"value")], &mut options).err(),
+            Some(LevelInitError::InvalidArg(_))
         );
     }
 
     #[test]
-    fn test_file_lock_is_released_on_drop() {
-        let file_name = "check-file-lock-released-on-drop";
-        let (hash, dir) = create_level_hash_2(file_name, true, |_| {});
-        let lock_path = Path::new(&dir).join(&format!("{}.index.lock", file_name));
-        assert!(lock_path.exists());
+    fn generation_of_structural_changes() {
+        let mut hash = create_level_hash("generation", true, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
 
-        let lock_file = File::options()
-            .read(true)
-            .write(true)
-            .create(false)
-            .open(lock_path)
-            .unwrap();
+        hash.insert(b"key1", b"value1").unwrap();
+        assert_eq!(hash.generation(), 0);
 
+        hash.expand().unwrap();
+        assert_eq!(hash.generation(), 2);
+        hash.shrink().unwrap();
+        assert_eq!(hash.generation(), 4);
         assert_eq!(
-            unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
-            -1
-        );
-        assert_eq!(
-            io::Error::last_os_error().raw_os_error().unwrap(),
-            libc::EWOULDBLOCK
+            hash.try_get_value(b"key1").unwrap(),
+            Some(b"value1".to_vec())
         );
 
+        // a read which starts while a structural change is in progress must be retried
+        hash.io.meta.begin_structural_change();
+        let result = hash.try_get_value(b"key1");
+        assert_matches!(
+            result,
+            Err(LevelReadError::ConcurrentModification { generation: 5 })
+        );
+        assert!(result.unwrap_err().is_retryable());
         drop(hash);
 
+        // the interrupted change is not in progress after the index is opened again
+        let mut hash = create_level_hash("generation", false, |options| {
+            options.level_size(2).bucket_size(4).auto_expand(false);
+        });
+        assert_eq!(hash.generation(), 6);
         assert_eq!(
-            unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
-            0
+            hash.try_get_value(b"key1").unwrap(),
+            Some(b"value1".to_vec())
         );
+
+        hash.clear().unwrap();
+        assert_eq!(hash.generation(), 8);
     }
 
     #[test]
-    fn test_level_hash_creation_fails_if_lock_acquired() {
-        let file_name = "check-level-fail-if-lock-acquired";
-        let (_hash, dir) = create_level_hash_2(file_name, true, |_| {});
-        let lock_path = Path::new(&dir).join(&format!("{}.index.lock", file_name));
-        assert!(lock_path.exists());
+    fn sync_on_write() {
+        let mut hash = create_level_hash("sync-on-write", true, |options| {
+            options.level_size(2).bucket_size(4).sync_on_write();
+        });
 
-        let (result, _) = create_level_hash_3(file_name, false, |_| {});
-        match result.err() {
-            Some(err) => match err {
-                LevelInitError::IOError(io) => {
-                    assert_eq!(io.error.raw_os_error().unwrap(), libc::EWOULDBLOCK);
-                }
-                _ => panic!("expected IO err"),
-            },
-            None => panic!("expected an error"),
-        }
+        let last_sync = hash.last_sync;
+        hash.insert(b"key1", b"value1").unwrap();
+        assert!(hash.last_sync > last_sync);
+
+        let last_sync = hash.last_sync;
+        hash.update(b"key1", b"value2").unwrap();
+        assert!(hash.last_sync > last_sync);
+
+        let last_sync = hash.last_sync;
+        hash.remove(b"key1").unwrap();
+        assert!(hash.last_sync > last_sync);
     }
 
     #[test]
-    fn test_entry_size_gt_values_block_size() {
-        let mut hash = default_level_hash("name");
-        let kv = "1"
-            .repeat(LevelHashIO::VALUES_BLOCK_SIZE_BYTES as usize / 2)
-            .into_bytes();
-        assert_eq!(
-            hash.io.meta.read().val_file_size,
-            LevelHashIO::VALUES_BLOCK_SIZE_BYTES
-        );
+    fn no_sync_with_explicit_sync() {
+        let mut hash = create_level_hash("no-sync", true, |options| {
+            options.level_size(2).bucket_size(4).no_sync();
+        });
 
-        hash.insert(&kv, &kv).unwrap();
+        let last_sync = hash.last_sync;
+        hash.insert(b"key1", b"value1").unwrap();
+        assert_eq!(hash.last_sync, last_sync);
 
-        assert_eq!(
-            hash.io.meta.read().val_file_size,
-            LevelHashIO::VALUES_BLOCK_SIZE_BYTES * 2 + SIZE_U64
-        );
+        hash.flush().expect("failed to flush level hash");
+        assert_eq!(hash.last_sync, last_sync);
+
+        hash.sync_all().expect("failed to sync level hash");
+        assert!(hash.last_sync > last_sync);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-invariants")]
+    fn invariants_detect_dangling_slot() {
+        let mut hash = create_level_hash("invariants-dangling-slot", true, |options| {
+            options.level_size(2).bucket_size(4);
+        });
+
+        hash.insert(b"key1", b"value1").unwrap();
+        hash.insert(b"key2", b"value2").unwrap();
+        hash.update(b"key1", b"value3").unwrap();
+        hash.remove(b"key2").unwrap();
+        hash.expand().unwrap();
+        assert!(hash
+            .io
+            .check_invariants(&hash.occupancy().counts)
+            .is_empty());
+
+        let (_, level, bucket, slot) = hash.find_slot(b"key1").unwrap();
+        let slot_addr = hash.io.slot_addr(level, bucket, slot);
+        hash.io.km_write_addr(slot_addr, u64::MAX);
+
+        let violations = hash.io.check_invariants(&hash.occupancy().counts);
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].contains("invalid value address"));
+        assert!(violations[1].contains("occupied slots"));
     }
 }
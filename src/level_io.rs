@@ -14,32 +14,73 @@
  *  You should have received a copy of the GNU General Public License
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::create_dir_all;
+use std::fs::remove_file;
+use std::fs::rename;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::fd::AsFd;
 use std::os::fd::AsRawFd;
 use std::path::Path;
+use std::path::PathBuf;
 
+use crate::bloom::BloomFilter;
+use crate::fs::clone_or_copy_file;
+use crate::fs::ftruncate_reserve;
 use crate::fs::ftruncate_safe;
+use crate::fs::ftruncate_safe_file;
 use crate::fs::ftruncate_safe_path;
 use crate::fs::init_sparse_file;
+use crate::fs::memfd_file;
+use crate::fs::read_magic_fd;
+use crate::fs::write_magic_fd;
 use crate::fs::LockFile;
+use crate::fsck::Inconsistency;
+use crate::fsck::IndexFile;
+use crate::io::segmented::SegmentedFile;
+use crate::io::IOEndianness;
+use crate::io::MapOptions;
 use crate::io::MappedFile;
+use crate::log_macros::log_error;
+use crate::log_macros::log_warn;
 use crate::meta::MetaIO;
+use crate::migrations::run_migrations;
+use crate::migrations::MigrationContext;
+use crate::overflow::OverflowFile;
+use crate::paths::IndexFiles;
+use crate::reprs::LevelMeta;
 use crate::reprs::ValuesData;
 use crate::result::IntoLevelIOErr;
 use crate::result::IntoLevelInitErr;
 use crate::result::IntoLevelInsertionErr;
 use crate::result::IntoLevelUpdateErr;
 use crate::result::LevelClearResult;
+use crate::result::LevelExpansionError;
 use crate::result::LevelInitError;
 use crate::result::LevelInsertionError;
 use crate::result::LevelMapError;
+use crate::result::LevelReadError;
 use crate::result::LevelRemapResult;
 use crate::result::LevelResult;
+use crate::result::LevelSyncResult;
 use crate::result::LevelUpdateError;
 use crate::result::LevelUpdateResult;
+use crate::result::StdIOError;
+#[cfg(feature = "secondary-index")]
+use crate::secondary::SecondaryIndex;
+#[cfg(feature = "secondary-index")]
+use crate::secondary::SecondaryKeys;
 use crate::size::SIZE_U32;
 use crate::size::SIZE_U64;
+use crate::slot_bitmap::SlotBitmap;
 use crate::types::BucketSizeT;
+use crate::types::LevelCountT;
 use crate::types::LevelKeyT;
 use crate::types::LevelSizeT;
 use crate::types::LevelValueT;
@@ -48,27 +89,340 @@ use crate::types::_BucketIdxT;
 use crate::types::_LevelIdxT;
 use crate::types::_SlotIdxT;
 use crate::util::align_8;
+use crate::util::unix_time_secs;
+use crate::xxhash::xxh64;
+use crate::xxhash::Xxh64;
+use crate::LockMode;
+use crate::KEY_SIZE_MAX_DEFAULT;
+use crate::VALUE_SIZE_MAX_DEFAULT;
+
+use byteorder::ByteOrder;
 
 pub const LEVEL_VALUES_VERSION: u32 = 1;
-pub const LEVEL_KEYMAP_VERSION: u32 = 1;
+pub const LEVEL_KEYMAP_VERSION: u32 = 3;
+
+/// The version of the keymaps whose buckets are aligned (see
+/// [LevelHashOptions::aligned_buckets](crate::LevelHashOptions::aligned_buckets)). The keymaps
+/// with unaligned buckets keep [LEVEL_KEYMAP_VERSION], so that they can still be opened by the
+/// older versions of level hash.
+pub const LEVEL_KEYMAP_VERSION_ALIGNED: u32 = 4;
+
+/// The version of the keymaps which start with a stash (see
+/// [LevelHashOptions::stash_slots](crate::LevelHashOptions::stash_slots)). The keymaps without a
+/// stash keep their version, so that they can still be opened by the older versions of level hash.
+pub const LEVEL_KEYMAP_VERSION_STASH: u32 = 5;
+
+/// The version of the keymap before the slots stored the tag of the key of their entry. The slots
+/// of this version only store the address of the entry, and are never tagged.
+pub const LEVEL_KEYMAP_VERSION_UNTAGGED: u32 = 2;
+
+/// The version of the keymap before the index files were stored in little-endian byte order. The
+/// index files of this version use the native byte order of the host which created them.
+pub const LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN: u32 = 1;
+
+/// The version of the values file whose entries are followed by a checksum of their contents.
+pub const LEVEL_VALUES_VERSION_CHECKSUMS: u32 = 2;
+
+/// The version of the values file whose entries are followed by their version (see
+/// [LevelHashOptions::entry_versions](crate::LevelHashOptions::entry_versions)), in addition to
+/// the checksum of their contents. The values files without entry versions keep their version, so
+/// that they can still be opened by the older versions of level hash.
+pub const LEVEL_VALUES_VERSION_ENTRY_VERSIONS: u32 = 3;
+
+/// The move of an entry from the (bucket, slot) in the top level to the (level, bucket, slot) in
+/// the shrunk keymap.
+pub type SlotMove = (
+    (_BucketIdxT, _SlotIdxT),
+    (_LevelIdxT, _BucketIdxT, _SlotIdxT),
+);
 
 /// Helper for handling I/O for level hash.
 ///
 /// # Properties
 ///
-/// * `values`: The memory-mapped file containing the value entries.
+/// * `values`: The memory-mapped (segments of the) file containing the value entries.
 /// * `keymap`: The memory-mapped file containing the keymap.
 /// * `meta`: The memory-mapped file containing the level hash metadata.
+/// * `overflow`: The file containing the values which are too large to be stored in the values file.
 /// * `interim_lvm_addr`: The addreess of the interim level in the keymap file that is used during
-/// expansion.
+///   expansion.
+/// * `rehash`: The state of the incremental expansion in progress, if any.
+/// * `spill_threshold`: Values larger than this size (bytes) are spilled to the overflow file.
+/// * `compress_threshold`: Values larger than this size (bytes) are compressed, if set.
+/// * `key_spill_threshold`: Keys larger than this size (bytes) are spilled to the overflow file,
+///   if set.
+/// * `checksums`: Whether a checksum is written for the new entries in the values file.
+/// * `versions`: Whether the version of the new entries is written in the values file.
+/// * `slot_bitmap`: The occupancy of the slots of the keymap.
+/// * `slots_file`: The file to which the slot bitmap is saved when the index is closed, `None` for
+///   the in-memory indexes and until the bitmap is initialized.
+/// * `pins`: The pinned entries of the values file.
+/// * `secondary`: The secondary index of the entries, if enabled with
+///   [LevelHashOptions::secondary_index](crate::LevelHashOptions::secondary_index).
+/// * `streamed_value`: The value written to the overflow file by [LevelHashIO::stream_value], which
+///   is used instead of the given value by [LevelHashIO::append_entry_at_slot] while it is set.
+/// * `deferred_deallocs`: The regions of the values file whose deallocation has been deferred by
+///   [LevelHashIO::defer_deallocations], if set.
+/// * `index_name`: The name of the index.
 #[derive(Debug)]
 pub struct LevelHashIO {
-    pub values: MappedFile,
+    pub values: SegmentedFile,
     pub keymap: MappedFile,
     pub meta: MetaIO,
+    pub(crate) overflow: OverflowFile,
+    pub(crate) bloom: Option<BloomFilter>,
+    pub(crate) free_list: ValuesFreeList,
     pub interim_lvl_addr: Option<OffT>,
+    pub(crate) rehash: Option<Rehash>,
+    pub spill_threshold: u64,
+    pub compress_threshold: Option<u64>,
+    pub key_spill_threshold: Option<u64>,
+    pub checksums: bool,
+    pub versions: bool,
+    pub(crate) streamed_value: Option<StreamedValue>,
+    pub(crate) deferred_deallocs: Option<Vec<(OffT, OffT)>>,
+    pub(crate) slot_bitmap: SlotBitmap,
+    pub(crate) slots_file: Option<PathBuf>,
+    pub(crate) pins: EntryPins,
+    #[cfg(feature = "secondary-index")]
+    pub(crate) secondary: Option<SecondaryIndex>,
+    pub index_name: String,
+
+    _lock_file: Option<LockFile>,
+}
+
+/// A value which was streamed to the overflow file by [LevelHashIO::stream_value], before the
+/// entry referencing it is appended to the values file.
+///
+/// # Properties
+///
+/// * `off`: The (0-based) offset of the value in the overflow file.
+/// * `len`: The size (bytes) of the value.
+/// * `fingerprint`: The fingerprint of the entry with the value, see
+///   [LevelHashIO::entry_fingerprint].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StreamedValue {
+    pub off: OffT,
+    pub len: u64,
+    pub fingerprint: u64,
+}
+
+/// The regions of the values file which were freed by deleting entries, and are reused for the new
+/// entries instead of appending them to the end of the values file. The free list is not stored in
+/// the index files, it is rebuilt from the holes in the values file when the index is opened.
+///
+/// # Properties
+///
+/// * `by_addr`: The free regions, mapped from their (0-based) address to their size. Adjacent
+///   regions are merged.
+/// * `by_size`: The `(size, address)` pairs of the free regions, used to find the smallest region
+///   which fits an entry.
+/// * `segment_size`: The number of bytes stored in each segment of the values file, or `0` if the
+///   values file is not segmented. The regions are split at the ends of the segments, so that the
+///   entries allocated from them do not cross the end of a segment.
+#[derive(Debug, Default)]
+pub(crate) struct ValuesFreeList {
+    by_addr: BTreeMap<OffT, OffT>,
+    by_size: BTreeSet<(OffT, OffT)>,
+    segment_size: OffT,
+}
+
+impl ValuesFreeList {
+    /// Create an empty free list for a values file with the given segment size (`0` if the values
+    /// file is not segmented).
+    pub fn new(segment_size: OffT) -> Self {
+        Self {
+            segment_size,
+            ..Self::default()
+        }
+    }
+
+    /// Add the region at the given (0-based) address to the free list, merging it with the adjacent
+    /// free regions in the same segment.
+    pub fn insert(&mut self, addr: OffT, size: OffT) {
+        let end = addr + size;
+        let mut addr = addr;
+        while addr < end {
+            let region_end = match self.segment_size {
+                0 => end,
+                segment_size => ((addr / segment_size + 1) * segment_size).min(end),
+            };
+            self.insert_region(addr, region_end - addr);
+            addr = region_end;
+        }
+    }
+
+    fn insert_region(&mut self, addr: OffT, size: OffT) {
+        let mut addr = addr;
+        let mut size = size;
+
+        if let Some((&prev_addr, &prev_size)) = self.by_addr.range(..addr).next_back() {
+            if prev_addr + prev_size == addr && !self.is_segment_start(addr) {
+                self.remove(prev_addr, prev_size);
+                addr = prev_addr;
+                size += prev_size;
+            }
+        }
+
+        let next_addr = addr + size;
+        if let Some(&next_size) = self.by_addr.get(&next_addr) {
+            if !self.is_segment_start(next_addr) {
+                self.remove(next_addr, next_size);
+                size += next_size;
+            }
+        }
+
+        self.by_addr.insert(addr, size);
+        self.by_size.insert((size, addr));
+    }
+
+    /// Whether a segment of the values file starts at the given (0-based) address.
+    fn is_segment_start(&self, addr: OffT) -> bool {
+        self.segment_size != 0 && addr.is_multiple_of(self.segment_size)
+    }
+
+    fn remove(&mut self, addr: OffT, size: OffT) {
+        self.by_addr.remove(&addr);
+        self.by_size.remove(&(size, addr));
+    }
+
+    /// Take the smallest free region which fits `size` bytes, returning its (0-based) address. The
+    /// rest of the region remains in the free list.
+    pub fn allocate(&mut self, size: OffT) -> Option<OffT> {
+        let &(region_size, addr) = self.by_size.range((size, 0)..).next()?;
+        self.remove(addr, region_size);
+        if region_size > size {
+            self.insert(addr + size, region_size - size);
+        }
+
+        Some(addr)
+    }
+
+    /// Take the free region which ends at the given (0-based) address, returning its address.
+    pub fn take_ending_at(&mut self, end: OffT) -> Option<OffT> {
+        let (&addr, &size) = self.by_addr.range(..end).next_back()?;
+        if addr + size != end {
+            return None;
+        }
+
+        self.remove(addr, size);
+        Some(addr)
+    }
+
+    /// Check whether any of the free regions overlaps the region of `size` bytes at the given
+    /// (0-based) address.
+    #[cfg(feature = "debug-invariants")]
+    pub fn overlaps(&self, addr: OffT, size: OffT) -> bool {
+        self.by_addr
+            .range(..addr.saturating_add(size))
+            .next_back()
+            .is_some_and(|(&free_addr, &free_size)| free_addr + free_size > addr)
+    }
+
+    /// Get the total size (bytes) of the free regions.
+    pub fn free_bytes(&self) -> OffT {
+        self.by_addr.values().sum()
+    }
+
+    /// Whether there are no free regions.
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Remove all the regions from the free list.
+    pub fn clear(&mut self) {
+        self.by_addr.clear();
+        self.by_size.clear();
+    }
+}
+
+/// The entries of the values file which are pinned with [LevelHashIO::pin_entry]. A pinned entry
+/// is never moved by the compaction of the values file, and if it is deleted (or replaced by an
+/// update) while it is pinned, its region and its spilled key and value are released only when
+/// its last pin is removed. The pins are not stored in the index files.
+///
+/// # Properties
+///
+/// * `next_id`: The id of the next pin. The ids are never reused.
+/// * `pins`: The (0-based) addresses of the pinned entries, mapped from the ids of their pins.
+/// * `counts`: The number of pins of each pinned entry.
+/// * `released`: The pinned entries which have been deleted, whose regions are released when they
+///   are unpinned.
+#[derive(Debug, Default)]
+pub(crate) struct EntryPins {
+    next_id: u64,
+    pins: HashMap<u64, OffT>,
+    counts: HashMap<OffT, u32>,
+    released: HashSet<OffT>,
+}
+
+impl EntryPins {
+    /// Whether the entry at the given (0-based) address is pinned.
+    #[inline]
+    pub fn is_pinned(&self, addr: OffT) -> bool {
+        self.counts.contains_key(&addr)
+    }
+
+    /// Whether the entry at the given (0-based) address was deleted while it is pinned.
+    #[inline]
+    pub fn is_released(&self, addr: OffT) -> bool {
+        self.released.contains(&addr)
+    }
+}
+
+/// The state of an incremental expansion. The entries of the bottom level are rehashed to the
+/// interim level a few buckets at a time, by pointing the slots of the interim level to the same
+/// entries as the slots of the bottom level. The bottom level keeps its slots until the expansion
+/// is committed, so the changes made to the rehashed buckets of the bottom level in the meantime
+/// are mirrored to the interim level.
+///
+/// # Properties
+///
+/// * `bottom_addr`: The address of the bottom level in the keymap file.
+/// * `bucket_bytes`: The size (bytes) of a bucket in the keymap file.
+/// * `next_bucket`: The number of buckets of the bottom level which have been rehashed.
+/// * `mirror`: The slots of the rehashed buckets, mapped to the slots of the interim level which
+///   point to the same entries.
+/// * `pending`: The (bucket, slot) of the slots of the rehashed buckets which point to an entry
+///   that has not been rehashed, because the entry was written after its bucket was rehashed.
+#[derive(Debug)]
+pub(crate) struct Rehash {
+    bottom_addr: OffT,
+    bucket_bytes: OffT,
+    pub next_bucket: _BucketIdxT,
+    pub mirror: HashMap<OffT, OffT>,
+    pub pending: BTreeSet<(_BucketIdxT, _SlotIdxT)>,
+}
+
+impl Rehash {
+    /// Track the write of the given slot entry to the slot at the given address, returning the
+    /// address of the slot in the interim level to which the write must be mirrored, if any.
+    fn track_write(&mut self, slot_addr: OffT, slot_entry: OffT) -> Option<OffT> {
+        let rehashed_end = self.bottom_addr + self.next_bucket as OffT * self.bucket_bytes;
+        if slot_addr < self.bottom_addr || slot_addr >= rehashed_end {
+            return None;
+        }
+
+        if let Some(&interim_addr) = self.mirror.get(&slot_addr) {
+            if slot_entry == LevelHashIO::POS_INVALID {
+                self.mirror.remove(&slot_addr);
+            }
+            return Some(interim_addr);
+        }
+
+        let off = slot_addr - self.bottom_addr;
+        let slot = (
+            (off / self.bucket_bytes) as _BucketIdxT,
+            ((off % self.bucket_bytes) / LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES) as _SlotIdxT,
+        );
+        if slot_entry == LevelHashIO::POS_INVALID {
+            self.pending.remove(&slot);
+        } else {
+            self.pending.insert(slot);
+        }
 
-    _lock_file: LockFile,
+        None
+    }
 }
 
 /// An entry in the values file.
@@ -83,6 +437,20 @@ pub struct ValuesEntry<'inst> {
     pub data: &'inst ValuesData,
 }
 
+/// Iterator over the non-empty entries in the values file, in the order in which they are stored.
+/// Holes left by deleted entries are skipped.
+///
+/// # Properties
+///
+/// * `values`: The values file.
+/// * `addr`: The (0-based) address of the next entry to inspect.
+/// * `end`: The (0-based) address at which the iteration stops.
+pub struct ValuesIter<'inst> {
+    values: &'inst SegmentedFile,
+    addr: OffT,
+    end: OffT,
+}
+
 #[repr(C)]
 pub struct ValuesEntryMut<'inst> {
     pub addr: OffT,
@@ -91,17 +459,61 @@ pub struct ValuesEntryMut<'inst> {
 
 pub trait ValEntryReadExt {
     fn esize(&self) -> u64 {
-        let data = self.data();
-        SIZE_U32 as u64 + SIZE_U32 as u64 + data.key_size as u64 + data.value_size as u64
+        let expiry_size = if self.has_expiry() {
+            ValuesEntry::EXPIRY_SIZE
+        } else {
+            0
+        };
+
+        let chain_size = if self.has_chain() {
+            ValuesEntry::CHAIN_SIZE
+        } else {
+            0
+        };
+
+        let version_size = if self.has_version() {
+            ValuesEntry::VERSION_SIZE
+        } else {
+            0
+        };
+
+        let checksum_size = if self.has_checksum() {
+            ValuesEntry::CHECKSUM_SIZE
+        } else {
+            0
+        };
+
+        SIZE_U32 as u64
+            + SIZE_U32 as u64
+            + self.key_size() as u64
+            + self.value_size() as u64
+            + expiry_size
+            + chain_size
+            + version_size
+            + checksum_size
     }
 
     fn is_empty(&self) -> bool {
-        let data = self.data();
-        data.key_size + data.value_size == 0
+        self.raw_key_size() == 0 && self.raw_value_size() == 0
+    }
+
+    /// Get the `key_size` field of this entry, including the flags.
+    fn raw_key_size(&self) -> u32 {
+        u32::from_le(self.data().key_size)
+    }
+
+    /// Get the `value_size` field of this entry, including the flags.
+    fn raw_value_size(&self) -> u32 {
+        u32::from_le(self.data().value_size)
     }
 
     fn key_size(&self) -> u32 {
-        self.data().key_size
+        self.raw_key_size()
+            & !(ValuesEntry::KEY_CHECKSUM_FLAG
+                | ValuesEntry::KEY_EXPIRY_FLAG
+                | ValuesEntry::KEY_CHAIN_FLAG
+                | ValuesEntry::KEY_SPILLED_FLAG
+                | ValuesEntry::KEY_VERSION_FLAG)
     }
 
     fn ksizeeq(&self, size: u32) -> bool {
@@ -109,16 +521,89 @@ pub trait ValEntryReadExt {
     }
 
     fn value_size(&self) -> u32 {
-        self.data().value_size
+        self.raw_value_size()
+            & !(ValuesEntry::VALUE_SPILLED_FLAG | ValuesEntry::VALUE_COMPRESSED_FLAG)
+    }
+
+    /// Whether the value of this entry has been spilled to the overflow file.
+    fn is_spilled(&self) -> bool {
+        self.raw_value_size() & ValuesEntry::VALUE_SPILLED_FLAG != 0
+    }
+
+    /// Whether the key of this entry has been spilled to the overflow file.
+    fn is_key_spilled(&self) -> bool {
+        self.raw_key_size() & ValuesEntry::KEY_SPILLED_FLAG != 0
+    }
+
+    /// Whether the value of this entry has been compressed.
+    fn is_compressed(&self) -> bool {
+        self.raw_value_size() & ValuesEntry::VALUE_COMPRESSED_FLAG != 0
+    }
+
+    /// Whether this entry is followed by the checksum of its contents.
+    fn has_checksum(&self) -> bool {
+        self.raw_key_size() & ValuesEntry::KEY_CHECKSUM_FLAG != 0
+    }
+
+    /// Whether the value of this entry is followed by an expiry timestamp.
+    fn has_expiry(&self) -> bool {
+        self.raw_key_size() & ValuesEntry::KEY_EXPIRY_FLAG != 0
+    }
+
+    /// Whether this entry is followed by the address of the next entry in its chain.
+    fn has_chain(&self) -> bool {
+        self.raw_key_size() & ValuesEntry::KEY_CHAIN_FLAG != 0
+    }
+
+    /// Whether this entry is followed by its version.
+    fn has_version(&self) -> bool {
+        self.raw_key_size() & ValuesEntry::KEY_VERSION_FLAG != 0
+    }
+
+    /// Get the offset of the chain link from the start of this entry.
+    fn chain_off(&self) -> OffT {
+        let expiry_size = if self.has_expiry() {
+            ValuesEntry::EXPIRY_SIZE
+        } else {
+            0
+        };
+
+        ValuesEntry::OFF_KEY + self.key_size() as OffT + self.value_size() as OffT + expiry_size
+    }
+
+    /// Get the offset of the version from the start of this entry.
+    fn version_off(&self) -> OffT {
+        let chain_size = if self.has_chain() {
+            ValuesEntry::CHAIN_SIZE
+        } else {
+            0
+        };
+
+        self.chain_off() + chain_size
     }
 
     fn data(&self) -> &ValuesData;
 
-    fn key(&self, file: &MappedFile) -> Vec<u8>;
-    fn keyeq(&self, file: &MappedFile, other: &LevelKeyT) -> bool;
+    /// Verify the checksum of this entry, if the entry has one.
+    fn verify_checksum(&self, file: &SegmentedFile) -> LevelResult<(), LevelReadError>;
+
+    fn key(&self, file: &SegmentedFile) -> Vec<u8>;
+    fn key_starts_with(&self, file: &SegmentedFile, prefix: &[u8]) -> bool;
+    fn keyeq(&self, file: &SegmentedFile, other: &LevelKeyT) -> bool;
+
+    fn val_with_size(&self, file: &SegmentedFile) -> (u32, Vec<u8>);
+    fn value(&self, file: &SegmentedFile) -> Vec<u8>;
+
+    /// Get the expiry timestamp (seconds since the Unix epoch) of this entry, or `None` if the
+    /// entry does not expire.
+    fn expires_at(&self, file: &SegmentedFile) -> Option<u64>;
+
+    /// Get the (1-based) address of the next (older) entry in the chain of this entry, or `None`
+    /// if this is the last entry of its chain.
+    fn chain_next(&self, file: &SegmentedFile) -> Option<OffT>;
 
-    fn val_with_size(&self, file: &MappedFile) -> (u32, Vec<u8>);
-    fn value(&self, file: &MappedFile) -> Vec<u8>;
+    /// Get the version of this entry, or `None` if the entry was written without a version.
+    fn version(&self, file: &SegmentedFile) -> Option<u64>;
 }
 
 pub trait ValEntryWriteExt {
@@ -134,7 +619,35 @@ macro_rules! val_entry_read_impl {
                     &self.data
                 }
 
-                fn key(&self, file: &MappedFile) -> Vec<u8> {
+                fn verify_checksum(&self, file: &SegmentedFile) -> LevelResult<(), LevelReadError> {
+                    if !self.has_checksum() {
+                        return Ok(());
+                    }
+
+                    let len = self.esize() - ValuesEntry::CHECKSUM_SIZE;
+                    if file.check_bounds(self.addr, self.esize()).is_err() {
+                        // the sizes of the entry have been corrupted
+                        return Err(LevelReadError::CorruptEntry {
+                            addr: self.addr,
+                            expected: 0,
+                            actual: 0,
+                        });
+                    }
+
+                    let expected = file.r_u32(self.addr + len);
+                    let actual = ValuesEntry::checksum(file.slice(self.addr, len));
+                    if expected != actual {
+                        return Err(LevelReadError::CorruptEntry {
+                            addr: self.addr,
+                            expected,
+                            actual,
+                        });
+                    }
+
+                    Ok(())
+                }
+
+                fn key(&self, file: &SegmentedFile) -> Vec<u8> {
                     let size = self.key_size() as usize;
                     if size == 0 {
                         return vec![];
@@ -145,12 +658,20 @@ macro_rules! val_entry_read_impl {
                     key
                 }
 
-                fn keyeq(&self, file: &MappedFile, other: &LevelKeyT) -> bool {
-                    return self.ksizeeq(other.len() as u32)
+                fn key_starts_with(&self, file: &SegmentedFile, prefix: &[u8]) -> bool {
+                    return prefix.is_empty()
+                        || (!self.is_key_spilled()
+                            && self.key_size() as usize >= prefix.len()
+                            && file.memeq(self.addr + ValuesEntry::OFF_KEY, prefix));
+                }
+
+                fn keyeq(&self, file: &SegmentedFile, other: &LevelKeyT) -> bool {
+                    return !self.is_key_spilled()
+                        && self.ksizeeq(other.len() as u32)
                         && file.memeq(self.addr + ValuesEntry::OFF_KEY, other);
                 }
 
-                fn val_with_size(&self, file: &MappedFile) -> (u32, Vec<u8>) {
+                fn val_with_size(&self, file: &SegmentedFile) -> (u32, Vec<u8>) {
                     let size = self.value_size();
                     if size == 0 {
                         return (size, vec![]);
@@ -163,9 +684,38 @@ macro_rules! val_entry_read_impl {
                     (size as u32, value)
                 }
 
-                fn value(&self, file: &MappedFile) -> Vec<u8> {
+                fn value(&self, file: &SegmentedFile) -> Vec<u8> {
                     self.val_with_size(file).1
                 }
+
+                fn expires_at(&self, file: &SegmentedFile) -> Option<u64> {
+                    if !self.has_expiry() {
+                        return None;
+                    }
+
+                    let off = ValuesEntry::OFF_KEY
+                        + self.key_size() as OffT
+                        + self.value_size() as OffT;
+                    Some(file.r_u64(self.addr + off))
+                }
+
+                fn chain_next(&self, file: &SegmentedFile) -> Option<OffT> {
+                    if !self.has_chain() {
+                        return None;
+                    }
+
+                    file.try_r_u64(self.addr + self.chain_off())
+                        .ok()
+                        .filter(|next| *next != LevelHashIO::POS_INVALID)
+                }
+
+                fn version(&self, file: &SegmentedFile) -> Option<u64> {
+                    if !self.has_version() {
+                        return None;
+                    }
+
+                    file.try_r_u64(self.addr + self.version_off()).ok()
+                }
             }
         )+
     };
@@ -178,16 +728,110 @@ impl ValuesEntry<'_> {
 
     pub const ENTRY_SIZE_MIN: OffT = Self::OFF_KEY - Self::OFF_KEY_SIZE;
 
+    /// The bit in `value_size` that is set when the value of the entry is stored in the overflow
+    /// file.
+    pub const VALUE_SPILLED_FLAG: u32 = 1 << 31;
+
+    /// The bit in `value_size` that is set when the value of the entry (inline or spilled) has been
+    /// compressed with LZ4. The compressed value is prefixed with the size of the original value.
+    pub const VALUE_COMPRESSED_FLAG: u32 = 1 << 30;
+
+    /// The maximum size of a value that can be stored inline in the values file.
+    pub const VALUE_INLINE_SIZE_MAX: u64 = (Self::VALUE_COMPRESSED_FLAG - 1) as u64;
+
+    /// The size of the reference to a spilled value, stored inline in place of the value.
+    pub const SPILL_REF_SIZE: u64 = SIZE_U64 * 2;
+
+    /// The bit in `key_size` that is set when the entry is followed by the checksum of its
+    /// contents.
+    pub const KEY_CHECKSUM_FLAG: u32 = 1 << 31;
+
+    /// The size of the checksum stored after the value of an entry.
+    pub const CHECKSUM_SIZE: u64 = SIZE_U32;
+
+    /// The bit in `key_size` that is set when the value of the entry is followed by the time
+    /// at which the entry expires.
+    pub const KEY_EXPIRY_FLAG: u32 = 1 << 30;
+
+    /// The size of the expiry timestamp stored after the value of an entry.
+    pub const EXPIRY_SIZE: u64 = SIZE_U64;
+
+    /// The bit in `key_size` that is set when the entry is followed by the (1-based) address of
+    /// the next entry in its chain. The entries of a key are chained in a level hash which does not
+    /// have unique keys, from the latest entry (linked to the slot) to the oldest one.
+    pub const KEY_CHAIN_FLAG: u32 = 1 << 29;
+
+    /// The size of the chain link stored after the expiry timestamp of an entry (if any).
+    pub const CHAIN_SIZE: u64 = SIZE_U64;
+
+    /// The bit in `key_size` that is set when the key of the entry is stored in the overflow file.
+    /// The key is then replaced with a reference to the spilled key, and `key_size` is the size of
+    /// the reference.
+    pub const KEY_SPILLED_FLAG: u32 = 1 << 28;
+
+    /// The bit in `key_size` that is set when the entry is followed by its version, the value of
+    /// the write counter of the level hash when the entry was written or last mutated in place.
+    pub const KEY_VERSION_FLAG: u32 = 1 << 27;
+
+    /// The size of the version stored after the chain link of an entry (if any).
+    pub const VERSION_SIZE: u64 = SIZE_U64;
+
+    /// The maximum size of a key that can be stored inline in the values file, as the larger sizes
+    /// would overlap the flags in `key_size`. The larger keys are always spilled.
+    pub const KEY_INLINE_SIZE_MAX: u64 = (Self::KEY_VERSION_FLAG - 1) as u64;
+
+    /// The size of the reference to a spilled key, stored inline in place of the key : the offset
+    /// and the length of the key in the overflow file, followed by the hash of the key.
+    pub const KEY_SPILL_REF_SIZE: u64 = SIZE_U64 * 3;
+
+    /// Compute the hash of a spilled key, which is compared before the key is read from the
+    /// overflow file.
+    pub fn key_hash(key: &LevelKeyT) -> u64 {
+        xxh64(0, key)
+    }
+
+    /// Compute the checksum of the given entry contents (sizes, key and value).
+    pub fn checksum(data: &[u8]) -> u32 {
+        xxh64(0, data) as u32
+    }
+
     /// Create [ValuesEntry] representing the entry at `addr` in the values file.
-    pub fn at(addr: OffT, file: &MappedFile) -> Self {
-        let data = unsafe { &*(file.map.as_ptr().add(addr as usize) as *const ValuesData) };
+    pub fn at(addr: OffT, file: &SegmentedFile) -> Self {
+        let data = unsafe { &*(file.ptr(addr) as *const ValuesData) };
         Self { addr, data }
     }
 }
 
+impl<'inst> Iterator for ValuesIter<'inst> {
+    type Item = ValuesEntry<'inst>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.addr + ValuesEntry::ENTRY_SIZE_MIN <= self.end {
+            let entry = ValuesEntry::at(self.addr, self.values);
+            if entry.is_empty() {
+                // entries are 8-byte aligned, so a hole can be skipped one word at a time
+                self.addr += SIZE_U64;
+                continue;
+            }
+
+            let entry_size = entry.esize() as OffT;
+            if entry.addr + entry_size > self.end {
+                // the entry extends beyond the last entry in the values file
+                self.addr = self.end;
+                return None;
+            }
+
+            self.addr += align_8(entry_size);
+            return Some(entry);
+        }
+
+        None
+    }
+}
+
 impl ValuesEntryMut<'_> {
-    fn at(addr: OffT, file: &mut MappedFile) -> Self {
-        let data = unsafe { &mut *(file.map.as_mut_ptr().add(addr as usize) as *mut ValuesData) };
+    fn at(addr: OffT, file: &mut SegmentedFile) -> Self {
+        let data = unsafe { &mut *(file.ptr_mut(addr) as *mut ValuesData) };
         Self { addr, data }
     }
 }
@@ -211,210 +855,1851 @@ impl LevelHashIO {
     ///
     /// * `index_dir`: The directory of the index.
     /// * `index_name`: The name of the index.
+    /// * `files`: The paths of the index files.
     /// * `level_size`: The level size of the level hash. This is used to calculate the final capacity
-    /// of the level hash.
+    ///   of the level hash.
     /// * `bucket_size`: The bucket size of the level hash. This is the number of slots that make up
-    /// a single bucket.
+    ///   a single bucket.
+    /// * `level_count`: The number of levels in the level hash. This is ignored for existing indexes.
+    /// * `spill_threshold`: Values larger than this size (bytes) are stored in the overflow file.
+    /// * `checksums`: Whether the entries in the values file are checksummed. This is ignored for
+    ///   existing indexes.
+    /// * `versions`: Whether the entries in the values file are versioned, which implies
+    ///   `checksums`. This is ignored for existing indexes.
+    /// * `aligned_buckets`: Whether the buckets in the keymap are aligned to
+    ///   [Self::KEYMAP_BUCKET_ALIGN_BYTES]. This is ignored for existing indexes.
+    /// * `stash_slots`: The number of slots in the stash at the start of the keymap. This is
+    ///   ignored for existing indexes.
+    /// * `values_growth`: The size (bytes) by which the values file grows, or `None` to use the
+    ///   size stored in the meta file.
+    /// * `values_segment_size`: The number of bytes stored in each segment file of the values
+    ///   file, or `None` if the values file is not segmented. This is ignored for existing indexes.
+    /// * `lock_mode`: The mode of the lock acquired on the index.
+    /// * `keymap_opts`: The options used to memory map the keymap file.
+    /// * `recovery`: Whether the sizes of the files described by the meta file are checked before
+    ///   the files are mapped, and the slot bitmap is built from the keymap. See
+    ///   [LevelHashOptions::open_with_recovery](crate::LevelHashOptions::open_with_recovery).
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         index_dir: &Path,
         index_name: &str,
+        files: &IndexFiles,
         level_size: LevelSizeT,
         bucket_size: BucketSizeT,
+        level_count: LevelCountT,
+        spill_threshold: u64,
+        checksums: bool,
+        versions: bool,
+        aligned_buckets: bool,
+        stash_slots: u32,
+        values_growth: Option<u64>,
+        values_segment_size: Option<u64>,
+        lock_mode: LockMode,
+        keymap_opts: MapOptions,
+        recovery: bool,
     ) -> LevelResult<LevelHashIO, LevelInitError> {
-        create_dir_all(index_dir)
-            .into_lvl_io_e_msg(format!(
-                "failed to create directory: {}",
-                index_dir.display()
-            ))
-            .into_lvl_init_err()?;
+        // the files may be placed outside of the index directory, see [crate::paths::Paths]
+        let parents = (files.data_files().into_iter())
+            .chain([files.lock.as_path()])
+            .filter_map(Path::parent);
+        for dir in [index_dir].into_iter().chain(parents) {
+            create_dir_all(dir)
+                .into_lvl_io_e_msg(format!("failed to create directory: {}", dir.display()))
+                .into_lvl_init_err()?;
+        }
 
-        let file_name = format!("{}{}", index_name, Self::LEVEL_INDEX_EXT);
-        let index_file = index_dir.join(&file_name);
-        let lock_file = index_dir.join(format!("{}.lock", &file_name));
-        let meta_file = index_dir.join(format!("{}{}", &file_name, Self::LEVEL_META_EXT));
-        let keymap_file = index_dir.join(format!("{}{}", &file_name, Self::LEVEL_KEYMAP_EXT));
+        let index_file = files.values.as_path();
+        let meta_file = files.meta.as_path();
+        let keymap_file = files.keymap.as_path();
+        let overflow_file = files.overflow.as_path();
+
+        let lock_file = LockFile::new(&files.lock, lock_mode)?;
+
+        // the files with a bad magic number are rejected instead of being emptied, they may have
+        // been written by another program or a newer version of level hash
+        let found = Self::check_magic_files(index_file, keymap_file, overflow_file)?;
+        if !found.is_empty() {
+            return Err(LevelInitError::Corrupt(found));
+        }
+
+        init_sparse_file(index_file, Some(Self::VALUES_MAGIC_NUMBER))?;
+        init_sparse_file(keymap_file, Some(Self::KEYMAP_MAGIC_NUMBER))?;
+
+        let overflow = OverflowFile::new(overflow_file)?;
+        let mut meta = MetaIO::new(
+            meta_file,
+            level_size,
+            bucket_size,
+            level_count,
+            Self::bucket_align(aligned_buckets),
+            Self::val_version(checksums, versions),
+        )?;
+
+        // the metadata of an operation which was interrupted is rolled back to the last
+        // checkpoint, the read-only level hashes may observe an operation of the writer instead
+        if lock_mode == LockMode::Exclusive {
+            meta.restore_checkpoint();
+        }
 
-        let lock_file = LockFile::new(&lock_file)?;
+        Self::check_versions(&meta)?;
+        Self::init_values_segments(&mut meta, values_segment_size);
+        Self::init_stash(&mut meta, stash_slots);
+        Self::init_values_growth(&mut meta, values_growth);
 
-        init_sparse_file(&index_file, Some(Self::VALUES_MAGIC_NUMBER))?;
-        init_sparse_file(&keymap_file, Some(Self::KEYMAP_MAGIC_NUMBER))?;
+        let mut violations = Self::check_meta(&meta);
+        if recovery && violations.is_empty() {
+            violations = Self::check_file_sizes(&meta, index_file, keymap_file, overflow_file)?;
+        }
+        if !violations.is_empty() {
+            return Err(LevelInitError::Corrupt(
+                violations
+                    .into_iter()
+                    .map(Inconsistency::InvalidLayout)
+                    .collect(),
+            ));
+        }
 
-        let mut meta = MetaIO::new(&meta_file, level_size, bucket_size)?;
+        if meta.read().km_interim_addr != 0 && lock_mode == LockMode::Exclusive {
+            // the levels are not modified by an incremental expansion until it is committed, so
+            // the interim level of an interrupted expansion is dropped with the keymap truncation
+            log_warn!("discarding the interim level of an interrupted expansion");
+            let meta = meta.write();
+            meta.km_interim_addr = 0;
+            meta.km_rehash_next = 0;
+        }
 
         let val_size = meta.read().val_file_size;
+        let checksums = meta.read().val_version >= LEVEL_VALUES_VERSION_CHECKSUMS;
+        let versions = meta.read().val_version >= LEVEL_VALUES_VERSION_ENTRY_VERSIONS;
         let km_size = meta.km_size();
         let val_file_size = Self::val_real_offset(val_size);
         let km_file_size = Self::km_real_offset(km_size);
 
-        ftruncate_safe_path(&index_file, val_file_size);
-        ftruncate_safe_path(&keymap_file, km_file_size);
-
-        let values = MappedFile::from_path(&index_file, Self::VALUES_HEADER_SIZE_BYTES, val_size)
+        ftruncate_safe_path(index_file, val_file_size)
+            .map_err(LevelMapError::TruncateFailed)
             .into_lvl_init_err()?;
-        let keymap = MappedFile::from_path(&keymap_file, Self::KEYMAP_HEADER_SIZE_BYTES, km_size)
+        ftruncate_safe_path(keymap_file, km_file_size)
+            .map_err(LevelMapError::TruncateFailed)
             .into_lvl_init_err()?;
 
-        Ok(LevelHashIO {
+        let values = SegmentedFile::open(
+            index_file,
+            Self::VALUES_HEADER_SIZE_BYTES,
+            val_size,
+            meta.val_segment_size(),
+            Self::VALUES_MAGIC_NUMBER,
+            MapOptions::default(),
+        )
+        .into_lvl_init_err()?;
+        let keymap = MappedFile::from_path(
+            keymap_file,
+            Self::KEYMAP_HEADER_SIZE_BYTES,
+            km_size,
+            keymap_opts,
+        )
+        .into_lvl_init_err()?;
+
+        let mut io = LevelHashIO {
             values,
             keymap,
             meta,
+            overflow,
+            bloom: None,
+            free_list: ValuesFreeList::default(),
             interim_lvl_addr: None,
-            _lock_file: lock_file,
-        })
-    }
-}
+            rehash: None,
+            spill_threshold,
+            compress_threshold: None,
+            key_spill_threshold: None,
+            streamed_value: None,
+            deferred_deallocs: None,
+            slot_bitmap: SlotBitmap::default(),
+            slots_file: None,
+            pins: EntryPins::default(),
+            #[cfg(feature = "secondary-index")]
+            secondary: None,
+            checksums,
+            versions,
+            index_name: index_name.to_string(),
+            _lock_file: Some(lock_file),
+        };
 
-impl LevelHashIO {
-    const POS_INVALID: OffT = 0;
+        run_migrations(
+            &mut io,
+            &MigrationContext {
+                index_dir,
+                bloom_file: &files.bloom,
+            },
+        )?;
+        io.init_fingerprint();
+        io.init_free_list();
+        // the saved slot bitmap is not trusted if the index files may be damaged
+        io.init_slot_bitmap(&files.slots, !recovery);
+        Ok(io)
+    }
 
-    pub const LEVEL_META_EXT: &'static str = "._meta";
-    pub const LEVEL_KEYMAP_EXT: &'static str = "._keymap";
-    pub const LEVEL_INDEX_EXT: &'static str = ".index";
+    /// Create a new [LevelHashIO] instance which is backed by anonymous memory instead of the
+    /// files in the index directory. The contents of such level hash are lost when it is
+    /// dropped.
+    ///
+    /// ## Parameters
+    ///
+    /// * `index_name`: The name of the index. This is only used to name the memory files.
+    /// * `level_size`: The level size of the level hash.
+    /// * `bucket_size`: The bucket size of the level hash.
+    /// * `level_count`: The number of levels in the level hash.
+    /// * `spill_threshold`: Values larger than this size (bytes) are stored in the overflow file.
+    /// * `checksums`: Whether the entries in the values file are checksummed.
+    /// * `versions`: Whether the entries in the values file are versioned, which implies
+    ///   `checksums`.
+    /// * `aligned_buckets`: Whether the buckets in the keymap are aligned to
+    ///   [Self::KEYMAP_BUCKET_ALIGN_BYTES].
+    /// * `stash_slots`: The number of slots in the stash at the start of the keymap.
+    /// * `values_growth`: The size (bytes) by which the values file grows.
+    /// * `keymap_opts`: The options used to memory map the keymap file.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_in_memory(
+        index_name: &str,
+        level_size: LevelSizeT,
+        bucket_size: BucketSizeT,
+        level_count: LevelCountT,
+        spill_threshold: u64,
+        checksums: bool,
+        versions: bool,
+        aligned_buckets: bool,
+        stash_slots: u32,
+        values_growth: Option<u64>,
+        keymap_opts: MapOptions,
+    ) -> LevelResult<LevelHashIO, LevelInitError> {
+        let file_name = format!("{}{}", index_name, Self::LEVEL_INDEX_EXT);
+        let index_file = memfd_file(&file_name)?;
+        let meta_file = memfd_file(&format!("{}{}", file_name, Self::LEVEL_META_EXT))?;
+        let keymap_file = memfd_file(&format!("{}{}", file_name, Self::LEVEL_KEYMAP_EXT))?;
+        let overflow_file = memfd_file(&format!("{}{}", file_name, Self::LEVEL_OVERFLOW_EXT))?;
+
+        let overflow = OverflowFile::from_file(overflow_file);
+        let mut meta = MetaIO::from_file(
+            meta_file,
+            level_size,
+            bucket_size,
+            level_count,
+            Self::bucket_align(aligned_buckets),
+            Self::val_version(checksums, versions),
+        )?;
+        Self::init_stash(&mut meta, stash_slots);
+        Self::init_values_growth(&mut meta, values_growth);
 
-    /// The number of bytes it takes to store the magic number of the keymap/values file.
-    pub const MAGIC_NUMBER_SIZE_BYTES: u64 = SIZE_U64;
+        let val_size = meta.read().val_file_size;
+        let checksums = meta.read().val_version >= LEVEL_VALUES_VERSION_CHECKSUMS;
+        let versions = meta.read().val_version >= LEVEL_VALUES_VERSION_ENTRY_VERSIONS;
+        let km_size = meta.km_size();
 
-    /// Magic number that is used as the file signature to identify the values file.
-    pub const VALUES_MAGIC_NUMBER: u64 = 0x4149445856;
+        ftruncate_safe_file(&index_file, Self::val_real_offset(val_size))
+            .map_err(LevelMapError::TruncateFailed)
+            .into_lvl_init_err()?;
+        ftruncate_safe_file(&keymap_file, Self::km_real_offset(km_size))
+            .map_err(LevelMapError::TruncateFailed)
+            .into_lvl_init_err()?;
 
-    /// The size of the header (bytes) in the values file.
-    pub const VALUES_HEADER_SIZE_BYTES: u64 = Self::MAGIC_NUMBER_SIZE_BYTES;
+        let values = MappedFile::new(index_file.into(), Self::VALUES_HEADER_SIZE_BYTES, val_size)
+            .map(SegmentedFile::single)
+            .into_lvl_init_err()?;
+        let keymap = MappedFile::with_options(
+            keymap_file.into(),
+            Self::KEYMAP_HEADER_SIZE_BYTES,
+            km_size,
+            keymap_opts,
+        )
+        .into_lvl_init_err()?;
+
+        let mut io = LevelHashIO {
+            values,
+            keymap,
+            meta,
+            overflow,
+            bloom: None,
+            free_list: ValuesFreeList::default(),
+            interim_lvl_addr: None,
+            rehash: None,
+            spill_threshold,
+            compress_threshold: None,
+            key_spill_threshold: None,
+            streamed_value: None,
+            deferred_deallocs: None,
+            slot_bitmap: SlotBitmap::default(),
+            slots_file: None,
+            pins: EntryPins::default(),
+            #[cfg(feature = "secondary-index")]
+            secondary: None,
+            checksums,
+            versions,
+            index_name: index_name.to_string(),
+            _lock_file: None,
+        };
 
-    /// The size of one segment region in the values file.
-    pub const VALUES_BLOCK_SIZE_BYTES: u64 = 512 * 1024;
+        // the keymap of an in-memory index is empty, so is its slot bitmap
+        io.init_fingerprint();
+        io.init_free_list();
+        Ok(io)
+    }
 
-    /// The number of bytes used to store the header of the keymap file.
-    pub const KEYMAP_HEADER_SIZE_BYTES: u64 = Self::MAGIC_NUMBER_SIZE_BYTES;
+    /// Check whether the index files have versions which are supported by this version of level hash.
+    fn check_versions(meta: &MetaIO) -> LevelResult<(), LevelInitError> {
+        let meta = meta.read();
+        if meta.val_version > LEVEL_VALUES_VERSION_ENTRY_VERSIONS
+            || meta.km_version > LEVEL_KEYMAP_VERSION_STASH
+        {
+            return Err(LevelInitError::UnsupportedVersion {
+                values: meta.val_version,
+                keymap: meta.km_version,
+            });
+        }
 
-    /// The number of bytes that are used to store an entry in a key map file.
-    pub const KEYMAP_ENTRY_SIZE_BYTES: u64 = SIZE_U64;
+        if meta.val_expiry != 0 && !cfg!(feature = "ttl") {
+            return Err(LevelInitError::FeatureRequired("ttl"));
+        }
 
-    /// Magic number that is used as the file signature to identify the keymap file.
-    pub const KEYMAP_MAGIC_NUMBER: u64 = 0;
-}
+        if meta.val_compressed != 0 && !cfg!(feature = "compression") {
+            return Err(LevelInitError::FeatureRequired("compression"));
+        }
 
-impl LevelHashIO {
-    #[inline]
-    pub fn val_real_offset(off: OffT) -> OffT {
-        Self::VALUES_HEADER_SIZE_BYTES + off
+        Ok(())
     }
 
-    #[inline]
-    pub fn km_real_offset(off: OffT) -> OffT {
-        Self::KEYMAP_HEADER_SIZE_BYTES + off
-    }
+    /// Check that the metadata of an index which is being opened describes files which can be
+    /// mapped, returning a description of each problem. The files must not be mapped if this is
+    /// not empty.
+    fn check_meta(meta: &MetaIO) -> Vec<String> {
+        let meta = meta.read();
+        if let Some(violation) = Self::check_meta_params(meta) {
+            return vec![violation];
+        }
 
-    #[inline]
-    pub fn val_deallocate(&mut self, off: OffT, len: OffT) {
-        self.values.deallocate(Self::val_real_offset(off), len)
-    }
+        let mut violations = Vec::new();
+        for level in 0..meta.km_level_count as _LevelIdxT {
+            let addr = meta.km_level_addr(level);
+            let end = addr
+                .checked_add(meta.km_level_bytes(level))
+                .and_then(|end| end.checked_add(Self::KEYMAP_HEADER_SIZE_BYTES));
+            if end.is_none() {
+                violations.push(format!("L{} at {} is out of bounds", level, addr));
+            }
+        }
 
-    #[inline]
-    pub fn km_deallocate(&mut self, off: OffT, len: OffT) {
-        self.keymap.deallocate(Self::km_real_offset(off), len)
-    }
+        if meta.km_interim_addr != 0
+            && meta
+                .km_interim_addr
+                .checked_add(meta.km_level_bytes(0) * 2)
+                .and_then(|end| end.checked_add(Self::KEYMAP_HEADER_SIZE_BYTES))
+                .is_none()
+        {
+            violations.push(format!(
+                "interim level at {} is out of bounds",
+                meta.km_interim_addr
+            ));
+        }
 
-    #[inline]
-    pub fn km_read_addr(&self, slot_addr: OffT) -> OffT {
-        self.keymap.r_u64(slot_addr)
-    }
+        if meta.val_file_size > OffT::MAX - Self::VALUES_HEADER_SIZE_BYTES {
+            violations.push(format!(
+                "values file size {} is out of bounds",
+                meta.val_file_size
+            ));
+        } else if meta.val_next_addr - 1 > meta.val_file_size {
+            violations.push(format!(
+                "next value address {} is beyond the values file size {}",
+                meta.val_next_addr, meta.val_file_size
+            ));
+        }
 
-    #[inline]
-    pub fn km_write_addr(&mut self, slot_addr: OffT, addr: OffT) {
-        self.keymap.w_u64(slot_addr, addr)
+        violations
     }
 
-    fn val_resize(&mut self, new_size: OffT) -> LevelRemapResult {
-        let meta = self.meta.write();
-        if meta.val_file_size == new_size {
+    /// Check that the values, keymap and overflow files are at least as large as described by the
+    /// meta file, returning a description of each problem. The files are always grown before the
+    /// meta file is updated, so smaller files mean that the meta file is corrupt.
+    fn check_file_sizes(
+        meta: &MetaIO,
+        values_file: &Path,
+        keymap_file: &Path,
+        overflow_file: &Path,
+    ) -> LevelResult<Vec<String>, LevelInitError> {
+        let file_size = |path: &Path| {
+            path.metadata()
+                .map(|metadata| metadata.len())
+                .into_lvl_io_e_msg(format!("failed to read metadata of {}", path.display()))
+        };
+
+        let mut violations = Vec::new();
+        // the values stored in the other segment files are checked when they are mapped
+        let val_size = meta.read().val_file_size.min(meta.val_segment_size());
+        let val_file_size = file_size(values_file)?;
+        if val_file_size < val_size {
+            violations.push(format!(
+                "values file size in meta is {} but the file has {} bytes",
+                val_size, val_file_size
+            ));
+        }
+
+        let km_size = Self::km_real_offset(meta.km_size());
+        let km_file_size = file_size(keymap_file)?;
+        if km_file_size < km_size {
+            violations.push(format!(
+                "keymap file must have {} bytes but has {} bytes",
+                km_size, km_file_size
+            ));
+        }
+
+        // the next overflow address is aligned, but the overflow file is not padded after the
+        // last spilled value
+        let ovf_next_addr = meta.read().ovf_next_addr;
+        let ovf_size = file_size(overflow_file)?.saturating_sub(OverflowFile::HEADER_SIZE_BYTES);
+        if ovf_next_addr > align_8(ovf_size) {
+            violations.push(format!(
+                "next overflow address {} is beyond the overflow file size {}",
+                ovf_next_addr, ovf_size
+            ));
+        }
+
+        Ok(violations)
+    }
+
+    /// Check the magic numbers of the existing values, keymap and overflow files. The magic
+    /// numbers in the byte order of a big-endian host are accepted, as they are converted when the
+    /// index is opened.
+    fn check_magic_files(
+        values_file: &Path,
+        keymap_file: &Path,
+        overflow_file: &Path,
+    ) -> LevelResult<Vec<Inconsistency>, LevelInitError> {
+        let mut found = Vec::new();
+        for (file, path, expected) in [
+            (IndexFile::Values, values_file, Self::VALUES_MAGIC_NUMBER),
+            (IndexFile::Keymap, keymap_file, Self::KEYMAP_MAGIC_NUMBER),
+            (
+                IndexFile::Overflow,
+                overflow_file,
+                OverflowFile::MAGIC_NUMBER,
+            ),
+        ] {
+            if !path.exists() {
+                continue;
+            }
+
+            let f = File::open(path)
+                .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))?;
+
+            // a file which is too short to have a magic number has magic number 0
+            let magic = match read_magic_fd(f.as_fd()) {
+                Ok(magic) => magic,
+                Err(why) if why.error.kind() == ErrorKind::UnexpectedEof => 0,
+                Err(why) => return Err(why.into()),
+            };
+
+            if magic != expected && magic != expected.swap_bytes() {
+                found.push(Inconsistency::BadMagicNumber {
+                    file,
+                    expected,
+                    found: magic,
+                });
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Convert the index files written before version 2 of the keymap to the little-endian byte
+    /// order. The files written on little-endian hosts only need their version to be updated,
+    /// otherwise the keymap and the integers in the value entries are byte-swapped. The conversion
+    /// is done in place and is not crash-safe, so the files are backed up before it is run. See
+    /// [crate::migrations].
+    pub(crate) fn migrate_v1(&mut self, ctx: &MigrationContext) -> LevelResult<(), LevelInitError> {
+        // the files of a v1 index are in the same byte order as its meta file
+        let big_endian = cfg!(target_endian = "big") != self.meta.was_swapped();
+        if big_endian {
+            self.swap_keymap();
+            self.swap_values();
+
+            // the bits of the bloom filter are byte-swapped as well, it is rebuilt from the
+            // converted values file
+            if ctx.bloom_file.exists() {
+                remove_file(ctx.bloom_file).into_lvl_io_e_msg(format!(
+                    "failed to delete file: {}",
+                    ctx.bloom_file.display()
+                ))?;
+            }
+
+            self.values.flush()?;
+            self.keymap.flush()?;
+        }
+
+        self.meta.write().km_version = LEVEL_KEYMAP_VERSION_UNTAGGED;
+        Ok(())
+    }
+
+    /// Reverse the byte order of the slots in the keymap.
+    pub(crate) fn swap_keymap(&mut self) {
+        for off in (0..self.keymap.size).step_by(Self::KEYMAP_ENTRY_SIZE_BYTES as usize) {
+            let addr = self.keymap.r_u64(off);
+            self.keymap.w_u64(off, addr.swap_bytes());
+        }
+    }
+
+    /// Reverse the byte order of the integers in the value entries : the sizes, the references to
+    /// the spilled values, the expiry timestamps and the checksums. The checksums are computed again
+    /// for the converted entries, unless the entry was already corrupt.
+    fn swap_values(&mut self) {
+        let end = (self.meta.read().val_next_addr - 1).min(self.values.size);
+        let mut addr = 0;
+        while addr + ValuesEntry::ENTRY_SIZE_MIN <= end {
+            let header = self
+                .values
+                .slice(addr, ValuesEntry::ENTRY_SIZE_MIN)
+                .to_vec();
+            let key_size = self.values.r_u32(addr + ValuesEntry::OFF_KEY_SIZE);
+            let value_size = self.values.r_u32(addr + ValuesEntry::OFF_VAL_SIZE);
+            if key_size == 0 && value_size == 0 {
+                addr += SIZE_U64;
+                continue;
+            }
+
+            self.values
+                .w_u32(addr + ValuesEntry::OFF_KEY_SIZE, key_size.swap_bytes());
+            self.values
+                .w_u32(addr + ValuesEntry::OFF_VAL_SIZE, value_size.swap_bytes());
+
+            let entry = ValuesEntry::at(addr, &self.values);
+            let esize = entry.esize();
+            if addr + esize > end {
+                // the entry extends beyond the last entry in the values file
+                break;
+            }
+
+            let spilled = entry.is_spilled();
+            let key_spilled = entry.is_key_spilled();
+            let has_expiry = entry.has_expiry();
+            let has_checksum = entry.has_checksum();
+            let value_off = addr + ValuesEntry::OFF_KEY + entry.key_size() as OffT;
+            let expiry_off = value_off + entry.value_size() as OffT;
+            let checksum_off = addr + esize - ValuesEntry::CHECKSUM_SIZE;
+
+            let checksum_valid = has_checksum && {
+                let mut data = self.values.slice(addr, checksum_off - addr).to_vec();
+                data[..header.len()].copy_from_slice(&header);
+                ValuesEntry::checksum(&data) == self.values.r_u32(checksum_off).swap_bytes()
+            };
+
+            if spilled {
+                for off in [value_off, value_off + SIZE_U64] {
+                    let value = self.values.r_u64(off);
+                    self.values.w_u64(off, value.swap_bytes());
+                }
+            }
+
+            if key_spilled {
+                let key_off = addr + ValuesEntry::OFF_KEY;
+                for off in (key_off..value_off).step_by(SIZE_U64 as usize) {
+                    let value = self.values.r_u64(off);
+                    self.values.w_u64(off, value.swap_bytes());
+                }
+            }
+
+            if has_expiry {
+                let expires_at = self.values.r_u64(expiry_off);
+                self.values.w_u64(expiry_off, expires_at.swap_bytes());
+            }
+
+            if checksum_valid {
+                let checksum = ValuesEntry::checksum(self.values.slice(addr, checksum_off - addr));
+                self.values.w_u32(checksum_off, checksum);
+            }
+
+            addr += align_8(esize);
+        }
+    }
+
+    /// Copy the index files to the given directory, with the same index name. The meta file is
+    /// written last and renamed into place, so that an incomplete copy is never mistaken for an
+    /// index. The changes must have been written to the files before the copy is made.
+    pub fn snapshot_to(&self, dir: &Path) -> LevelResult<(), StdIOError> {
+        create_dir_all(dir)
+            .into_lvl_io_e_msg(format!("failed to create directory: {}", dir.display()))?;
+
+        let file_name = format!("{}{}", self.index_name, Self::LEVEL_INDEX_EXT);
+        let meta_file = dir.join(format!("{}{}", file_name, Self::LEVEL_META_EXT));
+        let meta_tmp_file = dir.join(format!("{}{}.tmp", file_name, Self::LEVEL_META_EXT));
+
+        // an existing snapshot is not a valid index while it is being overwritten
+        if meta_file.exists() {
+            remove_file(&meta_file)
+                .into_lvl_io_e_msg(format!("failed to delete file: {}", meta_file.display()))?;
+        }
+
+        // a bloom filter or a slot bitmap left by an earlier snapshot may not match this one
+        let bloom_file = dir.join(format!("{}{}", file_name, Self::LEVEL_BLOOM_EXT));
+        let slots_file = dir.join(format!("{}{}", file_name, Self::LEVEL_SLOTS_EXT));
+        for path in [&bloom_file, &slots_file] {
+            if path.exists() {
+                remove_file(path)
+                    .into_lvl_io_e_msg(format!("failed to delete file: {}", path.display()))?;
+            }
+        }
+
+        let values_file = dir.join(&file_name);
+        let mut files = self
+            .values
+            .segment_fds()
+            .enumerate()
+            .map(|(idx, fd)| (SegmentedFile::segment_path(&values_file, idx), fd))
+            .collect::<Vec<_>>();
+        files.extend([
+            (
+                dir.join(format!("{}{}", file_name, Self::LEVEL_KEYMAP_EXT)),
+                self.keymap.fd.as_fd(),
+            ),
+            (
+                dir.join(format!("{}{}", file_name, Self::LEVEL_OVERFLOW_EXT)),
+                self.overflow.fd(),
+            ),
+        ]);
+        if let Some(bloom) = self.bloom.as_ref() {
+            files.push((bloom_file, bloom.fd()));
+        }
+        files.push((meta_tmp_file.clone(), self.meta.fd()));
+
+        for (path, src) in files {
+            let dst = File::create(&path)
+                .into_lvl_io_e_msg(format!("failed to create file: {}", path.display()))?;
+            clone_or_copy_file(src, &dst)?;
+            dst.sync_all()
+                .into_lvl_io_e_msg(format!("failed to sync file: {}", path.display()))?;
+        }
+
+        rename(&meta_tmp_file, &meta_file).into_lvl_io_e_msg(format!(
+            "failed to rename {} to {}",
+            meta_tmp_file.display(),
+            meta_file.display()
+        ))
+    }
+
+    /// Check whether the key with the given hashes may be present in the level hash. This is always
+    /// `true` if the level hash does not have a bloom filter.
+    #[inline]
+    pub fn bloom_may_contain(&self, fhash: u64, shash: u64) -> bool {
+        self.bloom
+            .as_ref()
+            .is_none_or(|bloom| bloom.may_contain(fhash, shash))
+    }
+
+    /// Add the key with the given hashes to the bloom filter, if the level hash has one.
+    #[inline]
+    pub fn bloom_insert(&mut self, fhash: u64, shash: u64) {
+        if let Some(bloom) = self.bloom.as_mut() {
+            bloom.insert(fhash, shash);
+        }
+    }
+
+    /// Check whether the index with the given files exists.
+    pub fn index_exists(files: &IndexFiles) -> bool {
+        // the meta file is the last index file to be created
+        files
+            .meta
+            .metadata()
+            .map(|metadata| metadata.len() > 0)
+            .unwrap_or(false)
+    }
+
+    /// Update the growth size of the values file in the meta file, falling back to
+    /// [LevelHashIO::VALUES_BLOCK_SIZE_BYTES] for the indexes which do not have one. The values
+    /// file of a new index is sized to one block.
+    fn init_values_growth(meta: &mut MetaIO, values_growth: Option<u64>) {
+        let meta = meta.write();
+        if let Some(block_size) = values_growth {
+            meta.val_block_size = block_size;
+        } else if meta.val_block_size == 0 {
+            meta.val_block_size = Self::VALUES_BLOCK_SIZE_BYTES;
+        }
+
+        if meta.val_file_size == 0 {
+            meta.val_file_size = meta.val_block_size;
+        }
+    }
+
+    /// Store the given limits of the sizes (bytes) of the keys and the values in the meta file. The
+    /// limits which are not given are read from the meta file, and default to
+    /// [KEY_SIZE_MAX_DEFAULT] and [VALUE_SIZE_MAX_DEFAULT] for the indexes created before the limits
+    /// were stored. The limits are not stored if the index is read-only.
+    ///
+    /// ## Returns
+    ///
+    /// The limits of the sizes of the keys and the values.
+    pub fn init_size_limits(
+        &mut self,
+        max_key_size: Option<u64>,
+        max_value_size: Option<u64>,
+    ) -> (u64, u64) {
+        let read_only = self.is_read_only();
+        let meta = self.meta.write();
+        let max_key_size = max_key_size.unwrap_or(match meta.val_max_key_size {
+            0 => KEY_SIZE_MAX_DEFAULT,
+            size => size,
+        });
+        let max_value_size = max_value_size.unwrap_or(match meta.val_max_value_size {
+            0 => VALUE_SIZE_MAX_DEFAULT,
+            size => size,
+        });
+
+        if !read_only {
+            meta.val_max_key_size = max_key_size;
+            meta.val_max_value_size = max_value_size;
+        }
+
+        (max_key_size, max_value_size)
+    }
+
+    /// Set the size of the segments of the values file of a new index. The values file of an
+    /// existing index keeps its segment size.
+    fn init_values_segments(meta: &mut MetaIO, segment_size: Option<u64>) {
+        let meta = meta.write();
+        if meta.val_file_size == 0 {
+            meta.val_segment_size = segment_size.unwrap_or(0);
+        }
+    }
+
+    /// Reserve the given number of slots for the stash at the start of the keymap of a new index,
+    /// moving the levels after it. The keymap of an existing index keeps its stash (if any).
+    fn init_stash(meta: &mut MetaIO, stash_slots: u32) {
+        let meta = meta.write();
+        if meta.val_file_size == 0 && stash_slots > 0 {
+            meta.km_stash_slots = stash_slots;
+            meta.km_version = LEVEL_KEYMAP_VERSION_STASH;
+            meta.km_layout_levels();
+        }
+    }
+
+    /// Get the size (bytes) by which the values file grows.
+    pub fn val_block_size(&self) -> u64 {
+        self.meta.read().val_block_size
+    }
+
+    /// Get the mode of the lock held on the index, or `None` for the in-memory indexes.
+    pub fn lock_mode(&self) -> Option<LockMode> {
+        self._lock_file.as_ref().map(LockFile::mode)
+    }
+
+    /// Whether the index is read-only, i.e. only a shared lock is held on the index.
+    pub fn is_read_only(&self) -> bool {
+        self.lock_mode() == Some(LockMode::Shared)
+    }
+
+    /// Copy the metadata to a checkpoint page of the meta file (see [MetaIO::commit]). This does
+    /// nothing for the read-only level hashes.
+    pub fn commit_meta(&mut self) {
+        if !self.is_read_only() {
+            self.meta.commit();
+        }
+    }
+
+    /// Upgrade the shared lock held on the index to an exclusive lock. This does nothing if the
+    /// exclusive lock is already held, or for the in-memory indexes.
+    pub fn upgrade_lock(&mut self) -> LevelRemapResult {
+        if self.lock_mode() != Some(LockMode::Shared) {
+            return Ok(());
+        }
+
+        let stamp = self.slot_bitmap_stamp();
+        if let Some(lock_file) = self._lock_file.as_mut() {
+            lock_file.upgrade()?;
+        }
+
+        // the lock is released while it is converted, so another process
+        // may have modified the index before the exclusive lock was acquired
+        let val_size = self.meta.read().val_file_size;
+        if self.values.size != val_size {
+            self.values.remap(val_size)?;
+        }
+
+        let km_size = self.meta.km_size();
+        if self.keymap.size != km_size {
+            self.keymap.remap(km_size)?;
+        }
+
+        self.init_free_list();
+        // the slot bitmap is stale if another process has modified the keymap
+        match self.slots_file.clone() {
+            Some(slots_file) if self.slot_bitmap_stamp() != stamp => {
+                self.init_slot_bitmap(&slots_file, true)
+            }
+            _ => self.remove_saved_slot_bitmap(),
+        }
+        Ok(())
+    }
+
+    /// Downgrade the exclusive lock held on the index to a shared lock, after flushing the
+    /// changes. This does nothing if the shared lock is already held, or for the in-memory indexes.
+    pub fn downgrade_lock(&mut self) -> LevelResult<(), StdIOError> {
+        if self.lock_mode() != Some(LockMode::Exclusive) {
             return Ok(());
         }
 
-        ftruncate_safe(self.values.fd.as_raw_fd(), new_size);
-        self.values.remap(new_size)?;
-        meta.val_file_size = new_size;
+        self.flush()?;
+        match self._lock_file.as_mut() {
+            Some(lock_file) => lock_file.downgrade(),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the version of the values file for a new index.
+    fn val_version(checksums: bool, versions: bool) -> u32 {
+        if versions {
+            LEVEL_VALUES_VERSION_ENTRY_VERSIONS
+        } else if checksums {
+            LEVEL_VALUES_VERSION_CHECKSUMS
+        } else {
+            LEVEL_VALUES_VERSION
+        }
+    }
+
+    /// Get the alignment of the buckets in a new keymap.
+    fn bucket_align(aligned_buckets: bool) -> u32 {
+        if aligned_buckets {
+            Self::KEYMAP_BUCKET_ALIGN_BYTES
+        } else {
+            0
+        }
+    }
+}
+
+impl LevelHashIO {
+    pub(crate) const POS_INVALID: OffT = 0;
+
+    /// The level index used to address the slots of the stash.
+    pub const STASH_LEVEL: _LevelIdxT = crate::LEVEL_COUNT_MAX as _LevelIdxT;
+
+    pub const LEVEL_META_EXT: &'static str = "._meta";
+    pub const LEVEL_KEYMAP_EXT: &'static str = "._keymap";
+    pub const LEVEL_INDEX_EXT: &'static str = ".index";
+    pub const LEVEL_OVERFLOW_EXT: &'static str = "._overflow";
+    pub const LEVEL_BLOOM_EXT: &'static str = "._bloom";
+    pub const LEVEL_SLOTS_EXT: &'static str = "._slots";
+    pub const LEVEL_LOCK_EXT: &'static str = ".lock";
+
+    /// The number of bytes it takes to store the magic number of the keymap/values file.
+    pub const MAGIC_NUMBER_SIZE_BYTES: u64 = SIZE_U64;
+
+    /// Magic number that is used as the file signature to identify the values file.
+    pub const VALUES_MAGIC_NUMBER: u64 = 0x4149445856;
+
+    /// The size of the header (bytes) in the values file.
+    pub const VALUES_HEADER_SIZE_BYTES: u64 = Self::MAGIC_NUMBER_SIZE_BYTES;
+
+    /// The default size of one segment region in the values file. The values file grows by this
+    /// many bytes at a time unless a different size is set with
+    /// [LevelHashOptions::values_growth](crate::LevelHashOptions::values_growth).
+    pub const VALUES_BLOCK_SIZE_BYTES: u64 = 512 * 1024;
+
+    /// The minimum number of bytes stored in each segment of a segmented values file. See
+    /// [LevelHashOptions::values_segment_size](crate::LevelHashOptions::values_segment_size).
+    pub const VALUES_SEGMENT_SIZE_MIN: u64 = 64 * 1024;
+
+    /// The size (bytes) of the chunks in which the streamed values are copied between the caller
+    /// and the overflow file.
+    pub const STREAM_CHUNK_SIZE: u64 = 64 * 1024;
+
+    /// The number of bytes used to store the header of the keymap file.
+    pub const KEYMAP_HEADER_SIZE_BYTES: u64 = Self::MAGIC_NUMBER_SIZE_BYTES;
+
+    /// The number of bytes that are used to store an entry in a key map file.
+    pub const KEYMAP_ENTRY_SIZE_BYTES: u64 = SIZE_U64;
+
+    /// The alignment (bytes) of the buckets in the keymap file of the level hashes built with
+    /// [LevelHashOptions::aligned_buckets](crate::LevelHashOptions::aligned_buckets), which is
+    /// the size of a cache line.
+    pub const KEYMAP_BUCKET_ALIGN_BYTES: u32 = 64;
+
+    /// The maximum alignment (bytes) of the buckets in the keymap file.
+    pub const KEYMAP_BUCKET_ALIGN_MAX: u32 = 4096;
+
+    /// The number of low bits of a slot entry which store the (1-based) address of the value entry.
+    /// The high bits store the tag of the key of the entry.
+    pub const SLOT_ADDR_BITS: u32 = 48;
+
+    /// The tag of the slots which were written before [LEVEL_KEYMAP_VERSION]. The key of the entry
+    /// in these slots is always compared.
+    pub const SLOT_TAG_NONE: u16 = 0;
+
+    /// Magic number that is used as the file signature to identify the keymap file.
+    pub const KEYMAP_MAGIC_NUMBER: u64 = 0;
+}
+
+impl LevelHashIO {
+    #[inline]
+    pub fn val_real_offset(off: OffT) -> OffT {
+        Self::VALUES_HEADER_SIZE_BYTES + off
+    }
+
+    #[inline]
+    pub fn km_real_offset(off: OffT) -> OffT {
+        Self::KEYMAP_HEADER_SIZE_BYTES + off
+    }
+
+    #[inline]
+    pub fn val_deallocate(&mut self, off: OffT, len: OffT) -> LevelResult<(), StdIOError> {
+        self.values.deallocate(off, len)
+    }
+
+    /// Deallocate the region of the values file, logging the error (if any) instead of returning
+    /// it. This is used when the region is no longer referenced, so a failure only leaks the space.
+    fn val_deallocate_or_log(&mut self, off: OffT, len: OffT) {
+        if let Some(deferred) = self.deferred_deallocs.as_mut() {
+            deferred.push((off, len));
+            return;
+        }
+
+        if let Err(why) = self.val_deallocate(off, len) {
+            log_error!("{}", why);
+        }
+    }
+
+    /// Defer the deallocation of the regions of the deleted entries until
+    /// [Self::flush_deallocations] is called, so that the adjacent regions are deallocated
+    /// together. The regions are still added to the free list, so no entry must be written until
+    /// the deallocations are flushed.
+    pub fn defer_deallocations(&mut self) {
+        self.deferred_deallocs.get_or_insert_with(Vec::new);
+    }
+
+    /// Deallocate the regions whose deallocation was deferred by [Self::defer_deallocations],
+    /// merging the adjacent regions.
+    pub fn flush_deallocations(&mut self) {
+        let Some(mut deferred) = self.deferred_deallocs.take() else {
+            return;
+        };
+
+        deferred.sort_unstable();
+        let mut merged: Vec<(OffT, OffT)> = Vec::with_capacity(deferred.len());
+        for (off, len) in deferred {
+            match merged.last_mut() {
+                Some((last_off, last_len)) if *last_off + *last_len == off => *last_len += len,
+                _ => merged.push((off, len)),
+            }
+        }
+
+        for (off, len) in merged {
+            self.val_deallocate_or_log(off, len);
+        }
+    }
+
+    /// Release the region of the deleted entry at the given (0-based) address so that it can be
+    /// reused. The region is removed from the end of the used region of the values file if it is
+    /// the last entry, along with the free regions before it. Otherwise, it is added to the free
+    /// list.
+    fn val_release(&mut self, addr: OffT, entry_size: OffT) {
+        let size = align_8(entry_size);
+        self.val_deallocate_or_log(addr, size);
+
+        let meta = self.meta.write();
+        meta.val_live_bytes = meta.val_live_bytes.saturating_sub(size);
+        if addr + size != meta.val_next_addr - 1 {
+            self.free_list.insert(addr, size);
+            return;
+        }
+
+        // let the next entry be written at this address
+        let mut next = addr;
+        while let Some(prev) = self.free_list.take_ending_at(next) {
+            next = prev;
+        }
+
+        meta.val_next_addr = next + 1;
+        if meta.val_tail_addr >= meta.val_next_addr {
+            // the entries do not link to the previous entry, so the new tail is unknown
+            meta.val_tail_addr = Self::POS_INVALID;
+        }
+    }
+
+    /// Release the region of the deleted entry at the given (0-based) address, along with its
+    /// spilled key and value (if any). The release is deferred until the entry is unpinned if the
+    /// entry is pinned.
+    fn release_entry(&mut self, addr: OffT) {
+        if self.pins.is_pinned(addr) {
+            self.pins.released.insert(addr);
+            return;
+        }
+
+        let entry = ValuesEntry::at(addr, &self.values);
+        let entry_size = entry.esize();
+        self.release_spilled(&entry);
+        self.val_release(addr, entry_size);
+    }
+
+    /// Pin the entry at the given (0-based) address, returning the id of the pin.
+    pub fn pin_entry(&mut self, addr: OffT) -> u64 {
+        let id = self.pins.next_id;
+        self.pins.next_id += 1;
+        self.pins.pins.insert(id, addr);
+        *self.pins.counts.entry(addr).or_default() += 1;
+        id
+    }
+
+    /// Get the entry pinned with the given pin, or `None` if the pin has been removed.
+    pub fn pinned_entry(&self, id: u64) -> Option<ValuesEntry<'_>> {
+        let addr = *self.pins.pins.get(&id)?;
+        Some(ValuesEntry::at(addr, &self.values))
+    }
+
+    /// Remove the given pin. The entry is released if it was deleted while it was pinned and this
+    /// was its last pin.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the pin existed.
+    pub fn unpin_entry(&mut self, id: u64) -> bool {
+        let Some(addr) = self.pins.pins.remove(&id) else {
+            return false;
+        };
+
+        let count = self.pins.counts.get_mut(&addr).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.pins.counts.remove(&addr);
+            if self.pins.released.remove(&addr) {
+                self.release_entry(addr);
+            }
+        }
+
+        true
+    }
+
+    /// Remove all the pins, releasing the entries which were deleted while they were pinned.
+    pub fn unpin_all(&mut self) {
+        let ids = self.pins.pins.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            self.unpin_entry(id);
+        }
+    }
+
+    #[inline]
+    pub fn km_deallocate(&mut self, off: OffT, len: OffT) -> LevelResult<(), StdIOError> {
+        self.slot_bitmap.clear_range(off, len);
+        self.keymap.deallocate(Self::km_real_offset(off), len)
+    }
+
+    /// Get the tag of the given key which is stored in the slots of its entries, so that the
+    /// lookups can skip the slots of the other keys without reading the values file. The tag is
+    /// never [Self::SLOT_TAG_NONE].
+    #[inline]
+    pub fn key_tag(key: &LevelKeyT) -> u16 {
+        ((xxh64(0, key) >> Self::SLOT_ADDR_BITS) as u16).max(1)
+    }
+
+    /// Get the (1-based) address of the value entry stored in the given slot entry.
+    #[inline]
+    pub fn slot_val_addr(slot_entry: u64) -> OffT {
+        slot_entry & ((1 << Self::SLOT_ADDR_BITS) - 1)
+    }
+
+    /// Get the tag of the key stored in the given slot entry.
+    #[inline]
+    pub fn slot_tag(slot_entry: u64) -> u16 {
+        (slot_entry >> Self::SLOT_ADDR_BITS) as u16
+    }
+
+    /// Get the largest (1-based) address of an entry in the values file which can be stored in
+    /// the slots of the keymap.
+    pub fn val_addr_max(&self) -> OffT {
+        if self.km_tagged() {
+            (1 << Self::SLOT_ADDR_BITS) - 1
+        } else {
+            OffT::MAX
+        }
+    }
+
+    /// Check whether a new entry can be appended to the values file, or stored in one of its free
+    /// regions. The slots are not searched for an entry which cannot be stored.
+    pub fn check_val_capacity(&self) -> LevelResult<(), LevelInsertionError> {
+        if self.meta.read().val_next_addr > self.val_addr_max() && self.free_list.is_empty() {
+            return Err(self.capacity_exhausted());
+        }
+
+        Ok(())
+    }
+
+    fn capacity_exhausted(&self) -> LevelInsertionError {
+        LevelInsertionError::CapacityExhausted(format!(
+            "the values file cannot grow beyond {} bytes",
+            self.val_addr_max()
+        ))
+    }
+
+    /// Whether the slots of the keymap store the tags of the keys.
+    #[inline]
+    pub fn km_tagged(&self) -> bool {
+        self.meta.read().km_version >= LEVEL_KEYMAP_VERSION
+    }
+
+    /// Read the value address and the key tag stored in the slot at the given slot address,
+    /// returning [LevelReadError::CorruptIndex] if the slot or the entry it points to is outside of
+    /// the mapped files.
+    #[inline]
+    pub fn km_read_slot(&self, slot_addr: OffT) -> LevelResult<(OffT, u16), LevelReadError> {
+        let slot_entry = self.keymap.try_r_u64(slot_addr)?;
+        let addr = Self::slot_val_addr(slot_entry);
+        if addr > Self::POS_INVALID {
+            // the address must point to at least the header of an entry
+            self.values
+                .check_bounds(addr - 1, ValuesEntry::ENTRY_SIZE_MIN)?;
+
+            // the entries are 8-byte aligned
+            if !(addr - 1).is_multiple_of(SIZE_U64) {
+                return Err(LevelReadError::CorruptIndex {
+                    addr: addr - 1,
+                    len: ValuesEntry::ENTRY_SIZE_MIN,
+                    size: self.values.size,
+                });
+            }
+        }
+
+        Ok((addr, Self::slot_tag(slot_entry)))
+    }
+
+    /// Read the value address stored in the slot at the given slot address, returning
+    /// [LevelReadError::CorruptIndex] if the slot or the entry it points to is outside of the
+    /// mapped files.
+    #[inline]
+    pub fn km_read_addr(&self, slot_addr: OffT) -> LevelResult<OffT, LevelReadError> {
+        self.km_read_slot(slot_addr).map(|(addr, _)| addr)
+    }
+
+    /// Read the value address stored in the slot at the given slot address, without checking
+    /// whether it points inside the values file.
+    #[inline]
+    pub fn km_val_addr(&self, slot_addr: OffT) -> OffT {
+        Self::slot_val_addr(self.keymap.r_u64(slot_addr))
+    }
+
+    /// Write the given slot entry (the value address along with the tag, if any) to the slot. The
+    /// write is mirrored to the interim level if the slot has been rehashed by the incremental
+    /// expansion in progress.
+    #[inline]
+    pub fn km_write_addr(&mut self, slot_addr: OffT, addr: OffT) {
+        self.keymap.w_u64(slot_addr, addr);
+        let occupied = Self::slot_val_addr(addr) > Self::POS_INVALID;
+        self.slot_bitmap.set(slot_addr, occupied);
+        if let Some(rehash) = self.rehash.as_mut() {
+            if let Some(interim_addr) = rehash.track_write(slot_addr, addr) {
+                self.keymap.w_u64(interim_addr, addr);
+                self.slot_bitmap.set(interim_addr, occupied);
+            }
+        }
+    }
+
+    /// Load the [SlotBitmap] saved when the index was last closed, or build it from the slots of
+    /// the keymap if it was not saved or the keymap has been modified since. The saved bitmap is
+    /// removed by the writable indexes, it goes stale with their first write. The bitmap is saved
+    /// to the given file when the index is closed, it is only loaded from it if `load` is `true`.
+    fn init_slot_bitmap(&mut self, slots_file: &Path, load: bool) {
+        let bitmap = match load && !self.meta.restored {
+            true => SlotBitmap::load(slots_file, &self.slot_bitmap_stamp()),
+            false => None,
+        };
+        match bitmap {
+            Some(bitmap) => self.slot_bitmap = bitmap,
+            None => self.build_slot_bitmap(),
+        }
+        self.slots_file = Some(slots_file.to_path_buf());
+        self.remove_saved_slot_bitmap();
+    }
+
+    /// Save the [SlotBitmap] to the slots file, so that it is not built again when the index is
+    /// opened. This does nothing for the read-only and the in-memory indexes.
+    fn save_slot_bitmap(&self) {
+        if let Some(path) = self.slots_file.as_ref().filter(|_| !self.is_read_only()) {
+            if let Err(why) = self.slot_bitmap.save(path, &self.slot_bitmap_stamp()) {
+                log_warn!("failed to save the slot bitmap: {}", why);
+            }
+        }
+    }
+
+    /// Remove the saved [SlotBitmap] of a writable index.
+    fn remove_saved_slot_bitmap(&self) {
+        if let Some(path) = self.slots_file.as_ref().filter(|_| !self.is_read_only()) {
+            if path.exists() {
+                if let Err(why) = remove_file(path) {
+                    log_warn!("failed to delete file {}: {}", path.display(), why);
+                }
+            }
+        }
+    }
+
+    /// Get the stamp of the keymap which is saved along with the [SlotBitmap]. Each write to the
+    /// keymap changes at least one of these fields of the metadata.
+    pub(crate) fn slot_bitmap_stamp(&self) -> [u64; 8] {
+        let meta = self.meta.read();
+        [
+            self.meta.km_size(),
+            meta.km_generation,
+            meta.km_interim_addr,
+            meta.val_next_addr,
+            meta.val_tail_addr,
+            meta.val_write_counter,
+            meta.val_fingerprint,
+            (meta.km_occupancy.counts.iter()).fold(0, |sum, count| sum + *count as u64),
+        ]
+    }
+
+    /// Build the [SlotBitmap] of the keymap from its slots, when no saved bitmap can be loaded and
+    /// after the slots are rewritten in bulk. The bitmap is maintained by the writes to the slots
+    /// otherwise.
+    fn build_slot_bitmap(&mut self) {
+        let mut bitmap = SlotBitmap::default();
+        for slot_addr in (0..self.keymap.size).step_by(Self::KEYMAP_ENTRY_SIZE_BYTES as usize) {
+            if self.km_val_addr(slot_addr) > Self::POS_INVALID {
+                bitmap.set(slot_addr, true);
+            }
+        }
+        self.slot_bitmap = bitmap;
+    }
+
+    /// Point the slot to the entry at the given (1-based) address for the given key, tagging the
+    /// slot with the tag of the key if the keymap stores the tags.
+    pub fn km_write_entry(&mut self, slot_addr: OffT, addr: OffT, key: &LevelKeyT) {
+        debug_assert!(addr == Self::slot_val_addr(addr));
+        let tag = if self.km_tagged() {
+            Self::key_tag(key)
+        } else {
+            Self::SLOT_TAG_NONE
+        };
+        self.km_write_addr(slot_addr, addr | (tag as u64) << Self::SLOT_ADDR_BITS);
+    }
+
+    /// Point the slot to another entry for the same key, e.g. the next entry in the chain of the
+    /// current entry, keeping the tag of the slot. The slot is cleared if the address is
+    /// [Self::POS_INVALID].
+    pub fn km_relink(&mut self, slot_addr: OffT, addr: OffT) {
+        let slot_entry = if addr == Self::POS_INVALID {
+            addr
+        } else {
+            let tag = Self::slot_tag(self.keymap.r_u64(slot_addr));
+            addr | (tag as u64) << Self::SLOT_ADDR_BITS
+        };
+        self.km_write_addr(slot_addr, slot_entry);
+    }
+
+    fn val_resize(&mut self, new_size: OffT) -> LevelRemapResult {
+        let meta = self.meta.write();
+        if meta.val_file_size == new_size {
+            return Ok(());
+        }
+
+        self.values.resize(new_size)?;
+        meta.val_file_size = new_size;
+
+        Ok(())
+    }
+
+    fn km_resize(&mut self, new_size: OffT) -> LevelRemapResult {
+        if self.meta.km_size() == new_size {
+            return Ok(());
+        }
+
+        ftruncate_reserve(
+            self.keymap.fd.as_raw_fd(),
+            new_size,
+            &mut self.keymap.reserved,
+        )
+        .map_err(LevelMapError::TruncateFailed)?;
+        self.keymap.remap(new_size)?;
+        self.slot_bitmap
+            .truncate(new_size - Self::KEYMAP_HEADER_SIZE_BYTES);
+
+        Ok(())
+    }
+
+    /// Schedule the changes made to the values, keymap and meta files to be written to the disk.
+    pub fn flush(&self) -> LevelSyncResult {
+        self.values.flush_async()?;
+        self.keymap.flush_async()?;
+        if let Some(bloom) = self.bloom.as_ref() {
+            bloom.flush_async()?;
+        }
+        self.meta.flush_async()
+    }
+
+    /// Write the changes made to the values, keymap, overflow and meta files to the disk, waiting
+    /// for the writes to complete. The meta file is synced last so that it never refers to data
+    /// which has not been written yet.
+    pub fn sync_all(&self) -> LevelSyncResult {
+        self.values.flush()?;
+        self.overflow.sync()?;
+        self.keymap.flush()?;
+        if let Some(bloom) = self.bloom.as_ref() {
+            bloom.flush()?;
+        }
+        self.meta.flush()
+    }
+
+    /// Get the address of the slot entry in the keymap file for the given level, bucket and slot.
+    /// The slots of the stash are addressed with [Self::STASH_LEVEL] and bucket `0`.
+    pub fn slot_addr(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> OffT {
+        if level == Self::STASH_LEVEL {
+            return Self::KEYMAP_ENTRY_SIZE_BYTES * slot as OffT;
+        }
+
+        let lvl_addr = self.meta.read().km_level_addr(level);
+        return self.slot_addr_for_lvl_addr(lvl_addr, bucket, slot);
+    }
+
+    /// Get the address of the slot entry in the keymap file for the given level offset, bucket and slot.
+    fn slot_addr_for_lvl_addr(&self, lvl_addr: OffT, bucket: _BucketIdxT, slot: _SlotIdxT) -> OffT {
+        lvl_addr + // start position of level
+            (self.meta.read().km_bucket_bytes() * bucket as OffT) + // bucket position
+            (Self::KEYMAP_ENTRY_SIZE_BYTES * slot as OffT)
+    }
+
+    pub fn slot_and_val_addr_at(
+        &self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+    ) -> LevelResult<(OffT, Option<OffT>), LevelReadError> {
+        let slot_addr = self.slot_addr(level, bucket, slot);
+        let addr = self.km_read_addr(slot_addr)?;
+
+        if addr <= Self::POS_INVALID {
+            return Ok((slot_addr, None));
+        }
+
+        Ok((slot_addr, Some(addr)))
+    }
+
+    /// Get the [ValuesEntry] for the given level, bucket and slot, or
+    /// [LevelReadError::CorruptIndex] if the slot points outside of the values file.
+    pub fn val_entry_for_slot(
+        &self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+    ) -> LevelResult<Option<ValuesEntry<'_>>, LevelReadError> {
+        let Some(addr) = self.slot_and_val_addr_at(level, bucket, slot)?.1 else {
+            return Ok(None);
+        };
+
+        let entry = ValuesEntry::at(addr - 1, &self.values);
+        self.values.check_bounds(entry.addr, entry.esize())?;
+        Ok(Some(entry))
+    }
+
+    /// Get the [ValuesEntry] for the given level, bucket and slot like [Self::val_entry_for_slot],
+    /// or `None` if the slot is tagged with the tag of a key other than the key with the given tag.
+    /// The values file is not read for the slots of the other keys.
+    pub fn val_entry_for_key_tag(
+        &self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        key_tag: u16,
+    ) -> LevelResult<Option<ValuesEntry<'_>>, LevelReadError> {
+        let slot_addr = self.slot_addr(level, bucket, slot);
+        let (addr, tag) = self.km_read_slot(slot_addr)?;
+        if addr == Self::POS_INVALID || (tag != Self::SLOT_TAG_NONE && tag != key_tag) {
+            return Ok(None);
+        }
+
+        let entry = ValuesEntry::at(addr - 1, &self.values);
+        self.values.check_bounds(entry.addr, entry.esize())?;
+        Ok(Some(entry))
+    }
+
+    /// Get the occupied [ValuesEntry] for the given level, bucket and slot. The slots which point
+    /// outside of the values file are logged and treated as empty.
+    pub fn occupied_entry(
+        &self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+    ) -> Option<ValuesEntry<'_>> {
+        match self.val_entry_for_slot(level, bucket, slot) {
+            Ok(entry) => entry.filter(|entry| !entry.is_empty()),
+            Err(why) => {
+                log_error!("L{}/B{}/S{}: {:?}", level, bucket, slot, why);
+                None
+            }
+        }
+    }
+}
+
+impl LevelHashIO {
+    /// Check if the slot is occupied. The occupancy is read from the [SlotBitmap], so the entry
+    /// of the slot is not read from the values file.
+    //noinspection RsSelfConvention
+    pub fn is_occupied(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> bool {
+        self.slot_bitmap.is_set(self.slot_addr(level, bucket, slot))
+    }
+
+    /// Get the number of occupied slots in the given bucket, from the [SlotBitmap].
+    pub fn bucket_occupancy(&self, level: _LevelIdxT, bucket: _BucketIdxT) -> BucketSizeT {
+        let bucket_size = self.meta.read().km_bucket_size;
+        self.slot_bitmap
+            .count(self.slot_addr(level, bucket, 0), bucket_size as u32) as BucketSizeT
+    }
+
+    /// Get the occupancy of the slots of the given bucket (or of the stash) from the [SlotBitmap],
+    /// with one bit for each slot (see [SlotBitmap::bytes]).
+    pub fn bucket_bitmap(&self, level: _LevelIdxT, bucket: _BucketIdxT) -> Vec<u8> {
+        let meta = self.meta.read();
+        let slots = match level {
+            Self::STASH_LEVEL => meta.km_stash_slots,
+            _ => meta.km_bucket_size as u32,
+        };
+        self.slot_bitmap
+            .bytes(self.slot_addr(level, bucket, 0), slots)
+    }
+
+    /// Get the index of the first free slot in the given bucket, from the [SlotBitmap].
+    pub fn first_free_slot(&self, level: _LevelIdxT, bucket: _BucketIdxT) -> Option<_SlotIdxT> {
+        let bucket_size = self.meta.read().km_bucket_size;
+        self.slot_bitmap
+            .first_free(self.slot_addr(level, bucket, 0), bucket_size as u32)
+            .map(|slot| slot as _SlotIdxT)
+    }
+
+    /// Get the value for the given level, bucket and slot.
+    pub fn value(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> Vec<u8> {
+        self.occupied_entry(level, bucket, slot)
+            .map(|entry| self.entry_value(&entry))
+            .unwrap_or(vec![])
+    }
+
+    /// Get the key of the given entry. If the key of the entry has been spilled to the overflow
+    /// file, the key is read from the overflow file.
+    pub fn entry_key<E: ValEntryReadExt>(&self, entry: &E) -> Vec<u8> {
+        let key = entry.key(&self.values);
+        if !entry.is_key_spilled() {
+            return key;
+        }
+
+        if key.len() != ValuesEntry::KEY_SPILL_REF_SIZE as usize {
+            log_error!("invalid key spill reference of size {}", key.len());
+            return vec![];
+        }
+
+        let (off, len) = Self::read_spill_ref(&key);
+        self.read_spilled(off, len).unwrap_or_default()
+    }
 
-        Ok(())
+    /// Whether the key of the given entry is equal to the given key. The hash of a spilled key is
+    /// compared before the key is read from the overflow file.
+    pub fn entry_keyeq<E: ValEntryReadExt>(&self, entry: &E, key: &LevelKeyT) -> bool {
+        if !entry.is_key_spilled() {
+            return entry.keyeq(&self.values, key);
+        }
+
+        let spill_ref = entry.key(&self.values);
+        if spill_ref.len() != ValuesEntry::KEY_SPILL_REF_SIZE as usize {
+            return false;
+        }
+
+        let (off, len) = Self::read_spill_ref(&spill_ref);
+        let hash = IOEndianness::read_u64(&spill_ref[(SIZE_U64 * 2) as usize..]);
+        len == key.len() as u64
+            && hash == ValuesEntry::key_hash(key)
+            && self
+                .read_spilled(off, len)
+                .is_some_and(|spilled| spilled == key)
     }
 
-    fn km_resize(&mut self, new_size: OffT) -> LevelRemapResult {
-        if self.meta.km_size() == new_size {
-            return Ok(());
+    /// Whether the key of the given entry starts with the given prefix.
+    pub fn entry_key_starts_with<E: ValEntryReadExt>(&self, entry: &E, prefix: &[u8]) -> bool {
+        if !entry.is_key_spilled() {
+            return entry.key_starts_with(&self.values, prefix);
         }
 
-        ftruncate_safe(self.keymap.fd.as_raw_fd(), new_size);
-        self.keymap.remap(new_size)?;
+        self.entry_key(entry).starts_with(prefix)
+    }
 
-        Ok(())
+    /// Get the value of the given entry. If the value of the entry has been spilled to the
+    /// overflow file, the value is read from the overflow file. Compressed values are
+    /// decompressed.
+    pub fn entry_value<E: ValEntryReadExt>(&self, entry: &E) -> Vec<u8> {
+        let mut value = entry.value(&self.values);
+        if entry.is_spilled() {
+            if value.len() != ValuesEntry::SPILL_REF_SIZE as usize {
+                log_error!("invalid spill reference of size {}", value.len());
+                return vec![];
+            }
+
+            let (off, len) = Self::read_spill_ref(&value);
+            value = match self.read_spilled(off, len) {
+                Some(value) => value,
+                None => return vec![],
+            };
+        }
+
+        if entry.is_compressed() {
+            return Self::decompress_value(&value);
+        }
+
+        value
     }
 
-    /// Get the address of the slot entry in the keymap file for the given level, bucket and slot.
-    pub fn slot_addr(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> OffT {
-        let meta = self.meta.read();
-        let lvl_addr = match level {
-            0 => meta.km_l0_addr,
-            1 => meta.km_l1_addr,
-            _ => panic!("invalid level index"),
-        };
+    /// Get the value of the given entry as a slice of the values file, without copying it, or
+    /// `None` if the value has been spilled to the overflow file or compressed.
+    pub fn entry_value_slice(&self, entry: &ValuesEntry) -> Option<&[u8]> {
+        if entry.is_spilled() || entry.is_compressed() {
+            return None;
+        }
 
-        return self.slot_addr_for_lvl_addr(lvl_addr, bucket, slot);
+        let off = entry.addr + ValuesEntry::OFF_KEY + entry.key_size() as OffT;
+        Some(self.values.slice(off, entry.value_size() as OffT))
     }
 
-    /// Get the address of the slot entry in the keymap file for the given level offset, bucket and slot.
-    fn slot_addr_for_lvl_addr(&self, lvl_addr: OffT, bucket: _BucketIdxT, slot: _SlotIdxT) -> OffT {
-        lvl_addr + // start position of level
-            (Self::KEYMAP_ENTRY_SIZE_BYTES * self.meta.read().km_bucket_size as OffT * bucket as OffT) + // bucket position
-            (Self::KEYMAP_ENTRY_SIZE_BYTES * slot as OffT)
+    /// Compress the given value if it is larger than the compression threshold, returning `None`
+    /// if the value is not compressed or if the compressed value is not smaller than the value.
+    #[cfg(feature = "compression")]
+    fn compress_value(&self, value: &LevelValueT) -> Option<Vec<u8>> {
+        let threshold = self.compress_threshold?;
+        if value.len() as u64 <= threshold {
+            return None;
+        }
+
+        let compressed = lz4_flex::compress_prepend_size(value);
+        (compressed.len() < value.len()).then_some(compressed)
     }
 
-    pub fn slot_and_val_addr_at(
+    #[cfg(not(feature = "compression"))]
+    fn compress_value(&self, _value: &LevelValueT) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Decompress the given compressed value. The values which cannot be decompressed are logged
+    /// and read as empty values.
+    #[cfg(feature = "compression")]
+    fn decompress_value(value: &[u8]) -> Vec<u8> {
+        match lz4_flex::decompress_size_prepended(value) {
+            Ok(value) => value,
+            Err(why) => {
+                log_error!("failed to decompress value: {}", why);
+                vec![]
+            }
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn decompress_value(_value: &[u8]) -> Vec<u8> {
+        // the indexes with compressed values are not opened without the feature
+        log_error!("cannot decompress value without the `compression` feature");
+        vec![]
+    }
+
+    /// Get the value of the given entry, after verifying the checksum of the entry.
+    pub fn verified_entry_value<E: ValEntryReadExt>(
         &self,
-        level: _LevelIdxT,
-        bucket: _BucketIdxT,
-        slot: _SlotIdxT,
-    ) -> (OffT, Option<OffT>) {
-        let slot_addr = self.slot_addr(level, bucket, slot);
-        let addr = self.km_read_addr(slot_addr);
+        entry: &E,
+    ) -> LevelResult<Vec<u8>, LevelReadError> {
+        entry.verify_checksum(&self.values)?;
+        Ok(self.entry_value(entry))
+    }
 
-        if addr <= Self::POS_INVALID {
-            return (slot_addr, None);
+    /// Whether the given entry has expired. The entries without an expiry timestamp never expire.
+    pub fn is_expired<E: ValEntryReadExt>(&self, entry: &E) -> bool {
+        entry
+            .expires_at(&self.values)
+            .is_some_and(|expires_at| expires_at <= unix_time_secs())
+    }
+
+    /// Compute the fingerprint of an entry with the given key and value. The fingerprint does not
+    /// depend on the seeds or the hash functions of the level hash, nor on how the entry is stored.
+    pub fn entry_fingerprint(key: &LevelKeyT, value: &LevelValueT) -> u64 {
+        xxh64(xxh64(0, key), value)
+    }
+
+    /// Iterate over the entries in the values file, skipping the entries which were deleted while
+    /// they are pinned (see [EntryPins]).
+    pub fn live_values_iter(&self) -> impl Iterator<Item = ValuesEntry<'_>> {
+        self.values_iter()
+            .filter(|entry| !self.pins.is_released(entry.addr))
+    }
+
+    /// Compute the content fingerprint from the entries in the values file.
+    pub fn compute_fingerprint(&self) -> u64 {
+        self.live_values_iter().fold(0u64, |fingerprint, entry| {
+            let key = self.entry_key(&entry);
+            let value = self.entry_value(&entry);
+            fingerprint.wrapping_add(Self::entry_fingerprint(&key, &value))
+        })
+    }
+
+    /// Rebuild the free list from the holes between the entries in the values file.
+    fn init_free_list(&mut self) {
+        let mut free_list = ValuesFreeList::new(self.meta.read().val_segment_size);
+        let mut end = 0;
+        let mut live_bytes = 0;
+        for entry in self.values_iter() {
+            if entry.addr > end {
+                free_list.insert(end, entry.addr - end);
+            }
+            end = entry.addr + align_8(entry.esize());
+            live_bytes += align_8(entry.esize());
+        }
+
+        let used = (self.meta.read().val_next_addr - 1).min(self.values.size);
+        if used > end {
+            free_list.insert(end, used - end);
+        }
+
+        self.free_list = free_list;
+        if !self.is_read_only() {
+            self.meta.write().val_live_bytes = live_bytes;
+        }
+    }
+
+    /// Compute the content fingerprint of an existing index which was created before the
+    /// fingerprint was maintained in the meta file.
+    fn init_fingerprint(&mut self) {
+        if self.meta.read().val_fingerprint_valid != 0 {
+            return;
         }
 
-        (slot_addr, Some(addr))
+        let fingerprint = self.compute_fingerprint();
+        let meta = self.meta.write();
+        meta.val_fingerprint = fingerprint;
+        meta.val_fingerprint_valid = 1;
     }
 
-    /// Get the address of the value entry in the values file for the given level, bucket and slot.
-    fn val_addr_at(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> Option<OffT> {
-        self.slot_and_val_addr_at(level, bucket, slot).1
+    /// Remove the fingerprint of the given (deleted) entry from the content fingerprint.
+    fn remove_fingerprint(&mut self, key: &LevelKeyT, value: &LevelValueT) {
+        let meta = self.meta.write();
+        meta.val_fingerprint = meta
+            .val_fingerprint
+            .wrapping_sub(Self::entry_fingerprint(key, value));
     }
 
-    /// Get the [ValuesEntry] for the given level, bucket and slot.
-    pub fn val_entry_for_slot(
-        &self,
-        level: _LevelIdxT,
-        bucket: _BucketIdxT,
-        slot: _SlotIdxT,
-    ) -> Option<ValuesEntry> {
-        self.val_addr_at(level, bucket, slot)
-            .map(|addr| ValuesEntry::at(addr - 1, &self.values))
+    /// Get an iterator over the entries in the values file. The entries are visited sequentially in
+    /// the order they are stored in the values file, instead of probing the slots in the keymap.
+    pub fn values_iter(&self) -> ValuesIter<'_> {
+        let next_addr = self.meta.read().val_next_addr;
+        ValuesIter {
+            values: &self.values,
+            addr: 0,
+            end: next_addr.saturating_sub(1).min(self.values.size),
+        }
     }
-}
 
-impl LevelHashIO {
-    /// Check if the slot is occupied.
-    //noinspection RsSelfConvention
-    pub fn is_occupied(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> bool {
-        self.val_entry_for_slot(level, bucket, slot)
-            .take_if(|entry| !entry.is_empty())
-            .is_some()
+    /// Read the offset and the length of a spilled key or value from its inline reference.
+    fn read_spill_ref(spill_ref: &[u8]) -> (OffT, u64) {
+        let off = IOEndianness::read_u64(&spill_ref[..SIZE_U64 as usize]);
+        let len = IOEndianness::read_u64(&spill_ref[SIZE_U64 as usize..(SIZE_U64 * 2) as usize]);
+        (off, len)
     }
 
-    /// Get the value for the given level, bucket and slot.
-    pub fn value(&self, level: _LevelIdxT, bucket: _BucketIdxT, slot: _SlotIdxT) -> Vec<u8> {
-        self.val_entry_for_slot(level, bucket, slot)
-            .take_if(|entry| !entry.is_empty())
-            .map(|entry| entry.value(&self.values))
-            .unwrap_or(vec![])
+    /// Read the spilled key or value at the given offset in the overflow file, or `None` if it
+    /// cannot be read.
+    fn read_spilled(&self, off: OffT, len: u64) -> Option<Vec<u8>> {
+        // the regions after the next overflow address have not been written
+        let ovf_next_addr = self.meta.read().ovf_next_addr;
+        if !matches!(off.checked_add(len), Some(end) if end <= ovf_next_addr) {
+            log_error!(
+                "spilled region [{}, +{}) is beyond the next overflow address {}",
+                off,
+                len,
+                ovf_next_addr
+            );
+            return None;
+        }
+
+        match self.overflow.read_at(off, len) {
+            Ok(data) => Some(data),
+            Err(why) => {
+                log_error!("{}", why);
+                None
+            }
+        }
+    }
+
+    /// Write the given value to the overflow file, returning the inline reference to the value.
+    fn spill_value(&mut self, value: &LevelValueT) -> LevelResult<Vec<u8>, LevelInsertionError> {
+        let mut spill_ref = vec![0u8; ValuesEntry::SPILL_REF_SIZE as usize];
+        self.spill(value, &mut spill_ref)?;
+        Ok(spill_ref)
+    }
+
+    /// Write the given key to the overflow file, returning the inline reference to the key.
+    fn spill_key(&mut self, key: &LevelKeyT) -> LevelResult<Vec<u8>, LevelInsertionError> {
+        let mut spill_ref = vec![0u8; ValuesEntry::KEY_SPILL_REF_SIZE as usize];
+        self.spill(key, &mut spill_ref)?;
+        IOEndianness::write_u64(
+            &mut spill_ref[(SIZE_U64 * 2) as usize..],
+            ValuesEntry::key_hash(key),
+        );
+        Ok(spill_ref)
+    }
+
+    /// Write the given data at the next overflow address, and write its offset and length to the
+    /// start of the given reference.
+    fn spill(&mut self, data: &[u8], spill_ref: &mut [u8]) -> LevelResult<(), LevelInsertionError> {
+        let off = self.meta.read().ovf_next_addr;
+        let len = data.len() as u64;
+
+        self.overflow
+            .write_at(off, data)
+            .map_err(LevelInsertionError::from)?;
+
+        self.meta.write().ovf_next_addr = align_8(off + len);
+
+        Self::write_spill_ref(spill_ref, off, len);
+        Ok(())
+    }
+
+    /// Write the offset and the length of a spilled key or value to the start of its inline
+    /// reference.
+    fn write_spill_ref(spill_ref: &mut [u8], off: OffT, len: u64) {
+        IOEndianness::write_u64(&mut spill_ref[..SIZE_U64 as usize], off);
+        IOEndianness::write_u64(
+            &mut spill_ref[SIZE_U64 as usize..(SIZE_U64 * 2) as usize],
+            len,
+        );
+    }
+
+    /// Release the regions occupied by the spilled key and value of the given entry in the overflow
+    /// file.
+    fn release_spilled<E: ValEntryReadExt>(&mut self, entry: &E) {
+        if entry.is_key_spilled() {
+            let (off, len) = Self::read_spill_ref(&entry.key(&self.values));
+            self.release_overflow(off, len);
+        }
+
+        if entry.is_spilled() {
+            let (off, len) = Self::read_spill_ref(&entry.value(&self.values));
+            self.release_overflow(off, len);
+        }
+    }
+
+    /// Release the given region in the overflow file.
+    fn release_overflow(&mut self, off: OffT, len: u64) {
+        let meta = self.meta.write();
+        if meta.ovf_next_addr == align_8(off + len) {
+            // let the next value be written at this offset
+            meta.ovf_next_addr = off;
+        }
+
+        if let Err(why) = self.overflow.deallocate(off, len) {
+            // the region is only leaked, the value is no longer referenced
+            log_error!("{}", why);
+        }
+    }
+
+    /// Write a value of `len` bytes read from the given reader to the next overflow address, in
+    /// chunks of [Self::STREAM_CHUNK_SIZE] bytes. The value is not referenced by any entry until
+    /// it is set as the [Self::streamed_value] of the entry which is appended next, and must be
+    /// released with [Self::release_streamed] if the entry is not appended.
+    pub fn stream_value(
+        &mut self,
+        key: &LevelKeyT,
+        len: u64,
+        mut reader: impl Read,
+    ) -> LevelResult<StreamedValue, StdIOError> {
+        let off = self.meta.read().ovf_next_addr;
+        let mut hasher = Xxh64::new(xxh64(0, key));
+        let mut buf = vec![0u8; len.min(Self::STREAM_CHUNK_SIZE) as usize];
+        let mut written = 0u64;
+
+        while written < len {
+            let chunk_len = (len - written).min(buf.len() as u64) as usize;
+            let chunk = &mut buf[..chunk_len];
+            let result = reader
+                .read_exact(chunk)
+                .into_lvl_io_e_msg(format!(
+                    "failed to read {} bytes of the value after {} bytes",
+                    chunk_len, written
+                ))
+                .and_then(|_| self.overflow.write_at(off + written, chunk));
+            if let Err(why) = result {
+                if written > 0 {
+                    self.release_overflow(off, written);
+                }
+                return Err(why);
+            }
+
+            hasher.update(chunk);
+            written += chunk_len as u64;
+        }
+
+        self.meta.write().ovf_next_addr = align_8(off + len);
+        Ok(StreamedValue {
+            off,
+            len,
+            fingerprint: hasher.digest(),
+        })
+    }
+
+    /// Release the region of the given streamed value, which is not referenced by any entry.
+    pub fn release_streamed(&mut self, value: StreamedValue) {
+        if value.len > 0 {
+            self.release_overflow(value.off, value.len);
+        }
+    }
+
+    /// Write the value of the given entry to the given writer. The inline values are written
+    /// directly from the values file and the spilled values are copied from the overflow file in
+    /// chunks of [Self::STREAM_CHUNK_SIZE] bytes. The compressed values are decompressed in memory
+    /// before they are written.
+    ///
+    /// ## Returns
+    ///
+    /// The number of bytes written.
+    pub fn write_entry_value(
+        &self,
+        entry: &ValuesEntry,
+        mut writer: impl Write,
+    ) -> LevelResult<u64, StdIOError> {
+        if entry.is_compressed() {
+            let value = self.entry_value(entry);
+            writer
+                .write_all(&value)
+                .into_lvl_io_e_msg("failed to write value".to_string())?;
+            return Ok(value.len() as u64);
+        }
+
+        if !entry.is_spilled() {
+            let value_off = entry.addr + ValuesEntry::OFF_KEY + entry.key_size() as OffT;
+            let len = entry.value_size() as OffT;
+            writer
+                .write_all(self.values.slice(value_off, len))
+                .into_lvl_io_e_msg("failed to write value".to_string())?;
+            return Ok(len);
+        }
+
+        let spill_ref = entry.value(&self.values);
+        if spill_ref.len() != ValuesEntry::SPILL_REF_SIZE as usize {
+            log_error!("invalid spill reference of size {}", spill_ref.len());
+            return Ok(0);
+        }
+
+        // the invalid references are read as empty values, like with Self::entry_value
+        let (off, len) = Self::read_spill_ref(&spill_ref);
+        let ovf_next_addr = self.meta.read().ovf_next_addr;
+        if !matches!(off.checked_add(len), Some(end) if end <= ovf_next_addr) {
+            log_error!(
+                "spilled region [{}, +{}) is beyond the next overflow address {}",
+                off,
+                len,
+                ovf_next_addr
+            );
+            return Ok(0);
+        }
+
+        let mut buf = vec![0u8; len.min(Self::STREAM_CHUNK_SIZE) as usize];
+        let mut copied = 0u64;
+        while copied < len {
+            let chunk = &mut buf[..(len - copied).min(Self::STREAM_CHUNK_SIZE) as usize];
+            self.overflow.read_into(off + copied, chunk)?;
+            writer
+                .write_all(chunk)
+                .into_lvl_io_e_msg("failed to write value".to_string())?;
+            copied += chunk.len() as u64;
+        }
+
+        Ok(len)
     }
 }
 
@@ -432,134 +2717,525 @@ impl LevelHashIO {
     ) -> LevelUpdateResult {
         // IMP: Update slot_addr only after writing the new value entry
 
-        let (slot_addr, val_addr) = self.slot_and_val_addr_at(level, bucket, slot);
+        let (slot_addr, val_addr) = self.slot_and_val_addr_at(level, bucket, slot)?;
         if val_addr.is_none() {
             return Err(LevelUpdateError::SlotEmpty);
         }
 
         let val_addr = val_addr.unwrap();
         let this_entry = ValuesEntry::at(val_addr - 1, &mut self.values);
+        self.values
+            .check_bounds(this_entry.addr, this_entry.esize())?;
         if this_entry.is_empty() {
             return Err(LevelUpdateError::EntryNotOccupied);
         }
 
-        let key = this_entry.key(&self.values);
-        let value = this_entry.value(&self.values);
-        let esize = this_entry.esize();
+        let key = self.entry_key(&this_entry);
+        let value = self.entry_value(&this_entry);
+        let expires_at = this_entry.expires_at(&self.values);
+        let chain_next = this_entry.chain_next(&self.values);
 
-        self.append_entry_at_slot(slot_addr, &key, new_value)
+        // the updated entry expires at the same time as the current entry
+        // and takes its place in the chain of the key
+        self.append_entry_at_slot(slot_addr, &key, new_value, expires_at, chain_next)
             .into_lvl_upd_err()?;
 
-        self.val_deallocate(this_entry.addr, esize);
+        self.release_entry(this_entry.addr);
+        self.remove_fingerprint(&key, &value);
+        #[cfg(feature = "secondary-index")]
+        self.unindex_entry(&key, &value);
 
         Ok(value)
     }
 
-    /// Create a new entry or update the existing entry at the given slot position. If the given
-    /// `key` is empty and the there exists an existing entry, then the entry will be removed and
-    /// the keymap will be updated with a null pointer (0). Otherwise, a new entry will be appended
-    /// to the values file, the existing entry will be removed and the keymap will be updated to
-    /// point to the new entry.
-    pub fn create_or_update_entry(
+    /// Mutate the value of the entry at the given level, bucket and slot with the given function,
+    /// which cannot change the size of the value. The value is mutated in-place in the values file
+    /// if it is stored in the entry (i.e. it has neither been spilled nor compressed) and the entry
+    /// is not pinned, and the checksum of the entry (if any) is rewritten. Otherwise, the mutated
+    /// value is written to a new entry with [Self::update_entry_value].
+    ///
+    /// ## Returns
+    ///
+    /// The size (bytes) of the value.
+    pub fn mutate_entry_value(
         &mut self,
         level: _LevelIdxT,
         bucket: _BucketIdxT,
         slot: _SlotIdxT,
-        key: &LevelKeyT,
-        value: &LevelValueT,
-    ) -> LevelResult<(), LevelInsertionError> {
-        let slot_addr = self.slot_addr(level, bucket, slot);
+        f: impl FnOnce(&mut [u8]),
+    ) -> LevelResult<u64, LevelUpdateError> {
+        let Some(val_addr) = self.slot_and_val_addr_at(level, bucket, slot)?.1 else {
+            return Err(LevelUpdateError::SlotEmpty);
+        };
 
-        if key.len() == 0 {
-            self.delete_at_slot(slot_addr, key, false);
-            return Ok(());
+        let entry = ValuesEntry::at(val_addr - 1, &self.values);
+        self.values.check_bounds(entry.addr, entry.esize())?;
+        if entry.is_empty() {
+            return Err(LevelUpdateError::EntryNotOccupied);
         }
 
-        let existing_val_addr = self.km_read_addr(slot_addr);
-        let is_update = existing_val_addr > Self::POS_INVALID;
+        let key = self.entry_key(&entry);
+        let value = self.entry_value(&entry);
+        if entry.is_spilled() || entry.is_compressed() || self.pins.is_pinned(entry.addr) {
+            let mut new_value = value;
+            f(&mut new_value);
+            self.update_entry_value(level, bucket, slot, &new_value)?;
+            return Ok(new_value.len() as u64);
+        }
 
-        self.append_entry_at_slot(slot_addr, key, value)?;
+        let addr = entry.addr;
+        let entry_size = entry.esize();
+        let checksum = entry.has_checksum();
+        let version_off = entry.has_version().then(|| addr + entry.version_off());
+        let value_off = addr + ValuesEntry::OFF_KEY + entry.key_size() as OffT;
+
+        #[cfg(feature = "secondary-index")]
+        self.unindex_entry(&key, &value);
+        let new_value = self.values.slice_mut(value_off, value.len() as OffT);
+        f(new_value);
+        let fingerprint = Self::entry_fingerprint(&key, new_value);
+
+        let version = self.next_write_version();
+        if let Some(version_off) = version_off {
+            self.values.w_u64(version_off, version);
+        }
 
-        if is_update {
-            self.delete_at(existing_val_addr, None, false);
+        if checksum {
+            let checksum_off = addr + entry_size - ValuesEntry::CHECKSUM_SIZE;
+            let checksum = ValuesEntry::checksum(self.values.slice(addr, checksum_off - addr));
+            self.values.w_u32(checksum_off, checksum);
         }
 
-        Ok(())
+        self.remove_fingerprint(&key, &value);
+        let meta = self.meta.write();
+        meta.val_fingerprint = meta.val_fingerprint.wrapping_add(fingerprint);
+        #[cfg(feature = "secondary-index")]
+        self.index_entry(addr, &key);
+
+        Ok(value.len() as u64)
+    }
+
+    /// Increment the write counter in the meta file.
+    ///
+    /// ## Returns
+    ///
+    /// The incremented counter, which is the version of the entry being written.
+    fn next_write_version(&mut self) -> u64 {
+        let meta = self.meta.write();
+        meta.val_write_counter += 1;
+        meta.val_write_counter
     }
 
     /// Append a new entry to the values file at the given slot position. The slot entry at the given
-    /// slot address in the keymap file will be updated to point to the new entry.
+    /// slot address in the keymap file will be updated to point to the new entry. The entry expires
+    /// at `expires_at` (seconds since the Unix epoch), if specified, and is chained to the entry at
+    /// the (1-based) address `chain_next`, if specified.
     pub fn append_entry_at_slot(
         &mut self,
         slot_addr: OffT,
         key: &LevelKeyT,
         value: &LevelValueT,
+        expires_at: Option<u64>,
+        chain_next: Option<OffT>,
     ) -> LevelResult<(), LevelInsertionError> {
-        let this_val_addr: OffT;
+        let next_val_addr: OffT;
         let val_file_size: OffT;
+        let block_size: OffT;
         {
             let meta = self.meta.read();
-            this_val_addr = meta.val_next_addr;
+            next_val_addr = meta.val_next_addr;
             val_file_size = meta.val_file_size;
+            block_size = meta.val_block_size;
         }
 
-        let key_len = key.len() as u32;
-        let val_len = value.len() as u32;
+        // a streamed value is already in the overflow file and is never compressed
+        let streamed = self.streamed_value;
+        let fingerprint = match streamed {
+            Some(streamed) => streamed.fingerprint,
+            None => Self::entry_fingerprint(key, value),
+        };
+        let compressed = match streamed {
+            Some(_) => None,
+            None => self.compress_value(value),
+        };
+        let value = compressed.as_deref().unwrap_or(value);
+        // the entries which could not fit in a segment of the values file are stored in the
+        // overflow file
+        let too_large = ValuesEntry::ENTRY_SIZE_MIN
+            + key.len() as OffT
+            + value.len() as OffT
+            + ValuesEntry::EXPIRY_SIZE
+            + ValuesEntry::CHAIN_SIZE
+            + ValuesEntry::VERSION_SIZE
+            + ValuesEntry::CHECKSUM_SIZE
+            > self.values.segment_size();
+        let spill = streamed.is_some() || too_large || value.len() as u64 > self.spill_threshold;
+        let spill_key = too_large
+            || key.len() as u64 > ValuesEntry::KEY_INLINE_SIZE_MAX
+            || self
+                .key_spill_threshold
+                .is_some_and(|threshold| key.len() as u64 > threshold);
+
+        let key_len = if spill_key {
+            ValuesEntry::KEY_SPILL_REF_SIZE as u32
+        } else {
+            key.len() as u32
+        };
+        let val_len = if spill {
+            ValuesEntry::SPILL_REF_SIZE as u32
+        } else {
+            value.len() as u32
+        };
+
+        let expiry_size = if expires_at.is_some() {
+            ValuesEntry::EXPIRY_SIZE
+        } else {
+            0
+        };
+
+        let chain_size = if chain_next.is_some() {
+            ValuesEntry::CHAIN_SIZE
+        } else {
+            0
+        };
+
+        let version_size = if self.versions {
+            ValuesEntry::VERSION_SIZE
+        } else {
+            0
+        };
+
+        let checksum_size = if self.checksums {
+            ValuesEntry::CHECKSUM_SIZE
+        } else {
+            0
+        };
 
-        let entry_size = ValuesEntry::ENTRY_SIZE_MIN + key_len as OffT + val_len as OffT;
+        let entry_size = ValuesEntry::ENTRY_SIZE_MIN
+            + key_len as OffT
+            + val_len as OffT
+            + expiry_size
+            + chain_size
+            + version_size
+            + checksum_size;
         assert!(entry_size <= u64::MAX as OffT);
 
-        {
+        // reuse the region of a deleted entry if there is one which fits this entry
+        let reused_addr = self.free_list.allocate(align_8(entry_size));
+        let mut this_val_addr = reused_addr.map_or(next_val_addr, |addr| addr + 1);
+
+        // an entry is not appended across the end of a segment, the rest of the segment is
+        // left free instead
+        let mut skipped = None;
+        if reused_addr.is_none() {
+            let segment_end = self.values.segment_end(this_val_addr - 1);
+            if this_val_addr - 1 + entry_size > segment_end {
+                skipped = Some((this_val_addr - 1, segment_end - (this_val_addr - 1)));
+                this_val_addr = segment_end + 1;
+            }
+
             let min_file_size = this_val_addr - 1 + entry_size;
+            if this_val_addr > self.val_addr_max() {
+                return Err(self.capacity_exhausted());
+            }
+
             let mut new_val_file_size = val_file_size;
 
             while new_val_file_size <= min_file_size {
-                new_val_file_size += Self::VALUES_BLOCK_SIZE_BYTES;
+                new_val_file_size += block_size;
             }
 
             self.val_resize(Self::val_real_offset(new_val_file_size))
                 .into_lvl_ins_err()?;
+
+            // the meta file is out of sync with the values file if the address of the next entry
+            // is already occupied, in which case the entry would overwrite another entry
+            if !ValuesEntry::at(this_val_addr - 1, &self.values).is_empty() {
+                return Err(LevelInsertionError::ReadError(
+                    LevelReadError::CorruptIndex {
+                        addr: this_val_addr - 1,
+                        len: entry_size,
+                        size: self.values.size,
+                    },
+                ));
+            }
+        }
+
+        // the key and the value are spilled only after the values file has been resized
+        // so that we don't leave unreferenced keys or values in the overflow file
+        let key_spill_ref: Vec<u8>;
+        let inline_key = if spill_key {
+            key_spill_ref = match self.spill_key(key) {
+                Ok(spill_ref) => spill_ref,
+                Err(why) => {
+                    if let Some(addr) = reused_addr {
+                        self.free_list.insert(addr, align_8(entry_size));
+                    }
+                    return Err(why);
+                }
+            };
+            &key_spill_ref
+        } else {
+            key
+        };
+
+        let mut spill_ref: Vec<u8>;
+        let value = if let Some(streamed) = streamed {
+            spill_ref = vec![0u8; ValuesEntry::SPILL_REF_SIZE as usize];
+            Self::write_spill_ref(&mut spill_ref, streamed.off, streamed.len);
+            &spill_ref
+        } else if spill {
+            spill_ref = match self.spill_value(value) {
+                Ok(spill_ref) => spill_ref,
+                Err(why) => {
+                    if spill_key {
+                        let (off, len) = Self::read_spill_ref(inline_key);
+                        self.release_overflow(off, len);
+                    }
+                    if let Some(addr) = reused_addr {
+                        self.free_list.insert(addr, align_8(entry_size));
+                    }
+                    return Err(why);
+                }
+            };
+            &spill_ref
+        } else {
+            value
+        };
+
+        if let Some(addr) = reused_addr {
+            // the region is not zeroed if it could not be deallocated
+            self.values.slice_mut(addr, align_8(entry_size)).fill(0);
+        }
+
+        let mut this_entry = ValuesEntryMut::at(this_val_addr - 1, &mut self.values);
+        let this_entry_addr = this_entry.addr;
+
+        debug_assert!(
+            this_entry.is_empty(),
+            "addr pointed by meta.next_entry is already occupied"
+        );
+
+        let this_data = this_entry.data_mut();
+
+        let key_off = this_entry_addr + ValuesEntry::OFF_KEY;
+        self.values.write_at(key_off, inline_key);
+        let mut key_flags = 0;
+        if spill_key {
+            key_flags |= ValuesEntry::KEY_SPILLED_FLAG;
+        }
+        if self.checksums {
+            key_flags |= ValuesEntry::KEY_CHECKSUM_FLAG;
+        }
+        if expires_at.is_some() {
+            key_flags |= ValuesEntry::KEY_EXPIRY_FLAG;
+        }
+        if chain_next.is_some() {
+            key_flags |= ValuesEntry::KEY_CHAIN_FLAG;
+        }
+        if self.versions {
+            key_flags |= ValuesEntry::KEY_VERSION_FLAG;
+        }
+        this_data.key_size = (key_len | key_flags).to_le();
+
+        self.values.write_at(key_off + key_len as OffT, value);
+        let mut value_flags = 0;
+        if spill {
+            value_flags |= ValuesEntry::VALUE_SPILLED_FLAG;
+        }
+        if compressed.is_some() {
+            value_flags |= ValuesEntry::VALUE_COMPRESSED_FLAG;
+        }
+        this_data.value_size = (val_len | value_flags).to_le();
+
+        if let Some(expires_at) = expires_at {
+            let expiry_off = key_off + key_len as OffT + val_len as OffT;
+            self.values.w_u64(expiry_off, expires_at);
+        }
+
+        if let Some(chain_next) = chain_next {
+            let chain_off = key_off + key_len as OffT + val_len as OffT + expiry_size;
+            self.values.w_u64(chain_off, chain_next);
+        }
+
+        let version = self.next_write_version();
+        if self.versions {
+            let version_off =
+                key_off + key_len as OffT + val_len as OffT + expiry_size + chain_size;
+            self.values.w_u64(version_off, version);
+        }
+
+        if self.checksums {
+            let checksum_off = this_entry_addr + entry_size - ValuesEntry::CHECKSUM_SIZE;
+            let checksum = ValuesEntry::checksum(
+                self.values
+                    .slice(this_entry_addr, checksum_off - this_entry_addr),
+            );
+            self.values.w_u32(checksum_off, checksum);
+        }
+
+        // finally, current_tail = this_entry
+        if let Some((addr, size)) = skipped {
+            self.free_list.insert(addr, size);
+        }
+
+        let meta = self.meta.write();
+        if reused_addr.is_none() {
+            meta.val_tail_addr = this_entry.addr + 1;
+            meta.val_next_addr = meta.val_tail_addr + align_8(entry_size);
+        }
+        meta.val_fingerprint = meta.val_fingerprint.wrapping_add(fingerprint);
+        meta.val_live_bytes += align_8(entry_size);
+        if expires_at.is_some() {
+            meta.val_expiry = 1;
+        }
+        if compressed.is_some() {
+            meta.val_compressed = 1;
+        }
+
+        self.km_write_entry(slot_addr, this_val_addr, key);
+        #[cfg(feature = "secondary-index")]
+        self.index_entry(this_entry_addr, key);
+
+        // the streamed value is referenced by this entry from now on
+        self.streamed_value = None;
+        Ok(())
+    }
+
+    /// Delete the entry at the given slot position, optionally reading the existing value if `read_value`
+    /// is true. The slot entry at the given slot address in the keymap file will be updated to point
+    /// to the next entry in the chain of the deleted entry, or to a null pointer (0) if there is none.
+    /// The entry will be deleted only if the keys match.
+    pub fn delete_at_slot(
+        &mut self,
+        slot_addr: OffT,
+        key: &LevelKeyT,
+        read_value: bool,
+    ) -> Option<Vec<u8>> {
+        let val_addr = self.km_val_addr(slot_addr);
+        self.km_relink(slot_addr, self.chain_next_at(val_addr));
+        return self.delete_at(val_addr, Some(key), read_value);
+    }
+
+    /// Delete the entry at the given slot address and all the entries in its chain. The slot entry
+    /// in the keymap file will be updated to a null pointer (0).
+    ///
+    /// ## Returns
+    ///
+    /// The values of the deleted entries which have not expired, from the latest to the oldest.
+    pub fn delete_chain_at_slot(&mut self, slot_addr: OffT) -> Vec<Vec<u8>> {
+        let val_addr = self.km_val_addr(slot_addr);
+        let chain = self
+            .chain_entries(val_addr)
+            .into_iter()
+            .map(|entry| (entry.addr + 1, self.is_expired(&entry)))
+            .collect::<Vec<_>>();
+
+        self.km_write_addr(slot_addr, Self::POS_INVALID);
+
+        let mut values = Vec::new();
+        for (addr, expired) in chain {
+            let value = self.delete_at(addr, None, true);
+            if !expired {
+                values.extend(value);
+            }
+        }
+
+        values
+    }
+
+    /// Delete the entries in the chain of the entry at the given (0-based) address, so that the
+    /// entry is the only entry of its chain.
+    ///
+    /// ## Returns
+    ///
+    /// The number of entries that were deleted.
+    pub fn truncate_chain(&mut self, addr: OffT) -> u64 {
+        let chain = self
+            .chain_entries(addr + 1)
+            .into_iter()
+            .skip(1)
+            .map(|entry| entry.addr + 1)
+            .collect::<Vec<_>>();
+
+        if !chain.is_empty() {
+            self.set_chain_next(addr, Self::POS_INVALID);
+        }
+
+        for &val_addr in &chain {
+            self.delete_at(val_addr, None, false);
+        }
+
+        chain.len() as u64
+    }
+
+    /// Get the (1-based) address of the next entry in the chain of the entry at the given (1-based)
+    /// address, or a null pointer (0) if there is none.
+    fn chain_next_at(&self, val_addr: OffT) -> OffT {
+        if val_addr <= Self::POS_INVALID {
+            return Self::POS_INVALID;
         }
 
-        let mut this_entry = ValuesEntryMut::at(this_val_addr - 1, &mut self.values);
-        let this_entry_addr = this_entry.addr;
+        ValuesEntry::at(val_addr - 1, &self.values)
+            .chain_next(&self.values)
+            .unwrap_or(Self::POS_INVALID)
+    }
 
+    /// Update the chain link of the entry at the given (0-based) address to the given (1-based)
+    /// address, and recompute the checksum of the entry. The entry must have a chain link.
+    pub fn set_chain_next(&mut self, addr: OffT, chain_next: OffT) {
+        let entry = ValuesEntry::at(addr, &self.values);
         assert!(
-            this_entry.is_empty(),
-            "addr pointed by meta.next_entry is already occupied"
+            entry.has_chain(),
+            "entry at {} does not have a chain link",
+            addr
         );
 
-        let this_data = this_entry.data_mut();
+        let chain_off = addr + entry.chain_off();
+        let checksum_off = entry
+            .has_checksum()
+            .then(|| addr + entry.esize() - ValuesEntry::CHECKSUM_SIZE);
 
-        let key_off = this_entry_addr + ValuesEntry::OFF_KEY;
-        self.values.write_at(key_off, key);
-        this_data.key_size = key_len;
+        self.values.w_u64(chain_off, chain_next);
+        if let Some(checksum_off) = checksum_off {
+            let checksum = ValuesEntry::checksum(self.values.slice(addr, checksum_off - addr));
+            self.values.w_u32(checksum_off, checksum);
+        }
+    }
 
-        self.values.write_at(key_off + key_len as OffT, value);
-        this_data.value_size = val_len;
+    /// Get the entry at the given (1-based) address, followed by the entries in its chain. The
+    /// chain is cut at the first link which points outside of the values file, to an empty entry
+    /// or back into the chain.
+    pub fn chain_entries(&self, val_addr: OffT) -> Vec<ValuesEntry<'_>> {
+        let mut entries = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = Some(val_addr).filter(|addr| *addr > Self::POS_INVALID);
+
+        while let Some(val_addr) = next.filter(|addr| visited.insert(*addr)) {
+            if let Err(why) = self
+                .values
+                .check_bounds(val_addr - 1, ValuesEntry::ENTRY_SIZE_MIN)
+            {
+                log_error!("{:?}", why);
+                break;
+            }
 
-        // finally, current_tail = this_entry
-        let meta = self.meta.write();
-        meta.val_tail_addr = this_entry.addr + 1;
-        meta.val_next_addr = meta.val_tail_addr + align_8(entry_size);
+            let entry = ValuesEntry::at(val_addr - 1, &self.values);
+            if entry.is_empty() {
+                log_error!("chain links to an empty entry at {}", val_addr);
+                break;
+            }
 
-        self.km_write_addr(slot_addr, this_val_addr);
+            if let Err(why) = self.values.check_bounds(entry.addr, entry.esize()) {
+                log_error!("{:?}", why);
+                break;
+            }
 
-        Ok(())
-    }
+            next = entry.chain_next(&self.values);
+            entries.push(entry);
+        }
 
-    /// Delete the entry at the given slot position, optionally reading the existing value if `read_value`
-    /// is true. The slot entry at the given slot address in the keymap file will be updated to a
-    /// null pointer (0). The entry will be deleted only if the keys match.
-    fn delete_at_slot(
-        &mut self,
-        slot_addr: OffT,
-        key: &LevelKeyT,
-        read_value: bool,
-    ) -> Option<Vec<u8>> {
-        let val_addr = self.keymap.r_u64(slot_addr);
-        self.km_deallocate(slot_addr, Self::KEYMAP_ENTRY_SIZE_BYTES);
-        return self.delete_at(val_addr, Some(key), read_value);
+        entries
     }
 
     /// Delete the entry at the given value address, optionally returning the existing value if `read_value`
@@ -584,28 +3260,20 @@ impl LevelHashIO {
         if let Some(k) = key {
             // if we have been provided with a key, then check if the key matches
             // if not, then do not delete
-            if !entry.keyeq(&mut self.values, k) {
-                return read_value.then(|| entry.value(&mut self.values));
+            if !self.entry_keyeq(&entry, k) {
+                return read_value.then(|| self.entry_value(&entry));
             }
         }
 
-        let meta = self.meta.write();
-
-        if meta.val_tail_addr == val_addr {
-            // let the next entry be written at this tail address
-            meta.val_next_addr = val_addr;
-        }
-
-        let entry_size = entry.esize() as OffT;
-        let mut result: Option<Vec<u8>> = None;
-
-        if read_value {
-            result = Some(entry.value(&mut self.values));
-        }
+        let entry_key = self.entry_key(&entry);
+        let value = self.entry_value(&entry);
 
-        self.val_deallocate(entry.addr, entry_size);
+        self.release_entry(entry.addr);
+        self.remove_fingerprint(&entry_key, &value);
+        #[cfg(feature = "secondary-index")]
+        self.unindex_entry(&entry_key, &value);
 
-        return result;
+        return read_value.then_some(value);
     }
 
     /// Clear all entries in the keymap and values files.
@@ -613,41 +3281,143 @@ impl LevelHashIO {
         let meta = self.meta.write();
         meta.val_tail_addr = Self::POS_INVALID;
         meta.val_next_addr = 1;
-        meta.km_l0_addr = 0;
+        meta.val_live_bytes = 0;
+        meta.ovf_next_addr = 0;
+        meta.val_fingerprint = 0;
+        meta.val_fingerprint_valid = 1;
+        meta.val_expiry = 0;
+        meta.val_compressed = 0;
+
+        let km_size = meta.km_layout_levels();
+        #[cfg(feature = "secondary-index")]
+        if let Some(secondary) = self.secondary.as_mut() {
+            secondary.clear();
+        }
 
-        let level_size = meta.km_level_size;
-        let bucket_size = meta.km_bucket_size;
-        let l1_addr = (1 << level_size) * bucket_size as OffT * Self::KEYMAP_ENTRY_SIZE_BYTES;
-        meta.km_l1_addr = l1_addr;
+        self.km_resize(Self::km_real_offset(km_size))?;
+        self.km_deallocate(0, km_size)?;
 
-        let km_size = l1_addr + (l1_addr >> 1);
+        let block_size = self.val_block_size();
+        self.val_resize(Self::val_real_offset(block_size))?;
+        self.val_deallocate(0, block_size)?;
 
-        self.km_resize(Self::km_real_offset(km_size))?;
-        self.km_deallocate(0, km_size);
+        self.overflow.clear()?;
+        self.free_list.clear();
 
-        self.val_resize(Self::val_real_offset(Self::VALUES_BLOCK_SIZE_BYTES))?;
-        self.val_deallocate(0, Self::VALUES_BLOCK_SIZE_BYTES);
+        // the pinned entries have been deallocated with the other entries
+        self.pins.pins.clear();
+        self.pins.counts.clear();
+        self.pins.released.clear();
+
+        if let Some(bloom) = self.bloom.as_mut() {
+            let size = bloom.size();
+            bloom.reset(size)?;
+        }
 
         Ok(())
     }
 
-    /// Prepare the interim level for the given number of buckets.
-    pub fn prepare_interim(&mut self, bucket_count: u32) -> LevelResult<(), LevelMapError> {
-        assert!(self.interim_lvl_addr.is_none());
+    /// Prepare the interim level for the given number of buckets. Fails with
+    /// [LevelExpansionError::ConcurrentModificationError] if an interim level has already been
+    /// prepared.
+    pub fn prepare_interim(&mut self, bucket_count: u32) -> LevelResult<(), LevelExpansionError> {
+        if self.interim_lvl_addr.is_some() {
+            return Err(LevelExpansionError::ConcurrentModificationError);
+        }
 
-        let interim_size: OffT = bucket_count as OffT
-            * self.meta.read().km_bucket_size as OffT
-            * Self::KEYMAP_ENTRY_SIZE_BYTES;
+        let interim_size: OffT = bucket_count as OffT * self.meta.read().km_bucket_bytes();
 
         // ensure the keymap can accomodate the interim level
-        let len = self.keymap.size;
-        self.km_resize(Self::km_real_offset(len) + interim_size)?;
-        self.interim_lvl_addr = Some(len);
+        let addr = self.meta.read().km_level_start(self.keymap.size);
+        self.km_resize(Self::km_real_offset(addr) + interim_size)
+            .map_err(LevelExpansionError::MmapError)?;
+        self.interim_lvl_addr = Some(addr);
+
+        Ok(())
+    }
+
+    /// Prepare the interim level for an incremental expansion with the given number of buckets.
+    /// The address of the interim level and the progress of the expansion are recorded in the meta
+    /// file until [Self::end_rehash] is called.
+    pub fn begin_rehash(&mut self, bucket_count: u32) -> LevelResult<(), LevelExpansionError> {
+        self.prepare_interim(bucket_count)?;
+
+        let meta = self.meta.write();
+        let bottom_level = meta.km_level_count as _LevelIdxT - 1;
+        meta.km_interim_addr = self.interim_lvl_addr.unwrap();
+        meta.km_rehash_next = 0;
+        self.rehash = Some(Rehash {
+            bottom_addr: meta.km_level_addr(bottom_level),
+            bucket_bytes: meta.km_bucket_bytes(),
+            next_bucket: 0,
+            mirror: HashMap::new(),
+            pending: BTreeSet::new(),
+        });
 
         Ok(())
     }
 
-    /// Move the given slot to the interim level, returning `true` if the move was successful.
+    /// Record that the given number of buckets of the bottom level have been rehashed by the
+    /// incremental expansion.
+    pub fn set_rehash_next(&mut self, next_bucket: _BucketIdxT) {
+        if let Some(rehash) = self.rehash.as_mut() {
+            rehash.next_bucket = next_bucket;
+            self.meta.write().km_rehash_next = next_bucket;
+        }
+    }
+
+    /// Stop tracking the incremental expansion, before its interim level is committed with
+    /// [Self::commit_interim] or dropped with [Self::rollback_interim].
+    pub fn end_rehash(&mut self) {
+        self.rehash = None;
+        let meta = self.meta.write();
+        meta.km_interim_addr = 0;
+        meta.km_rehash_next = 0;
+    }
+
+    /// Move the entry at the given slot to another slot in the same level. The destination slot
+    /// must be free, it is overwritten otherwise. Only the slot entries in the keymap are updated,
+    /// the value entry is not copied.
+    pub fn move_slot(
+        &mut self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        dest_bucket: _BucketIdxT,
+        dest_slot: _SlotIdxT,
+    ) {
+        let s_slot_addr = self.slot_addr(level, bucket, slot);
+        let d_slot_addr = self.slot_addr(level, dest_bucket, dest_slot);
+
+        // write the destination first so that the entry is never unreachable
+        let e_val_addr = self.keymap.r_u64(s_slot_addr);
+        self.km_write_addr(d_slot_addr, e_val_addr);
+        self.km_write_addr(s_slot_addr, Self::POS_INVALID);
+    }
+
+    /// Move the entry at the given slot to a slot in another level. The destination slot must be
+    /// free, it is overwritten otherwise. Like [Self::move_slot], only the slot entries in the
+    /// keymap are updated.
+    pub fn move_slot_to_level(
+        &mut self,
+        level: _LevelIdxT,
+        bucket: _BucketIdxT,
+        slot: _SlotIdxT,
+        dest_level: _LevelIdxT,
+        dest_bucket: _BucketIdxT,
+        dest_slot: _SlotIdxT,
+    ) {
+        let s_slot_addr = self.slot_addr(level, bucket, slot);
+        let d_slot_addr = self.slot_addr(dest_level, dest_bucket, dest_slot);
+
+        // write the destination first so that the entry is never unreachable
+        let e_val_addr = self.keymap.r_u64(s_slot_addr);
+        self.km_write_addr(d_slot_addr, e_val_addr);
+        self.km_write_addr(s_slot_addr, Self::POS_INVALID);
+    }
+
+    /// Move the given slot to the interim level, returning `true` if the move was successful. The
+    /// move fails if the destination slot is occupied, or if no interim level has been prepared.
     pub fn move_to_interim(
         &mut self,
         level: _LevelIdxT,
@@ -656,9 +3426,9 @@ impl LevelHashIO {
         interim_bucket: _BucketIdxT,
         interim_slot: _SlotIdxT,
     ) -> bool {
-        assert!(self.interim_lvl_addr.is_some());
-
-        let interim_lvl = self.interim_lvl_addr.unwrap();
+        let Some(interim_lvl) = self.interim_lvl_addr else {
+            return false;
+        };
 
         // current (source) slot
         let s_slot_addr = self.slot_addr(level, bucket, slot);
@@ -678,10 +3448,14 @@ impl LevelHashIO {
         // where the source slot points to
 
         // 1. read the address where the source slot points
-        let e_val_addr = self.km_read_addr(s_slot_addr);
+        let e_val_addr = self.keymap.r_u64(s_slot_addr);
 
         // 2. move the destination slot and write the address of the source slot's value
         self.km_write_addr(d_slot_addr, e_val_addr);
+        if let Some(rehash) = self.rehash.as_mut() {
+            // the later writes to the source slot are mirrored to the destination slot
+            rehash.mirror.insert(s_slot_addr, d_slot_addr);
+        }
 
         // 3. deallocate the space occupied by the source slot
         // self.km_deallocate(s_slot_addr, Self::KEYMAP_ENTRY_SIZE_BYTES);
@@ -689,25 +3463,562 @@ impl LevelHashIO {
         return true;
     }
 
+    /// Drop the interim level prepared with [Self::prepare_interim], truncating the keymap to its
+    /// size before the interim level was added. The slots of the other levels are not modified by
+    /// [Self::move_to_interim], so this restores the keymap to its state before the expansion.
+    pub fn rollback_interim(&mut self) -> LevelRemapResult {
+        let Some(interim_lvl_addr) = self.interim_lvl_addr.take() else {
+            return Ok(());
+        };
+
+        // the interim level starts at the end of the mapped region of the keymap
+        ftruncate_safe(
+            self.keymap.fd.as_raw_fd(),
+            Self::km_real_offset(interim_lvl_addr),
+        )
+        .map_err(LevelMapError::TruncateFailed)?;
+        self.slot_bitmap.truncate(interim_lvl_addr);
+        self.keymap.remap(interim_lvl_addr)
+    }
+
     /// Finalize the expansion of the level hash. This updates the level metadata with the updated
     /// values of the level addresses in the keymap file.
     pub fn commit_interim(&mut self, new_level_size: u8) {
         assert!(self.interim_lvl_addr.is_some());
 
         let meta = self.meta.write();
-        let level_size = meta.km_level_size;
-        let l0_addr = meta.km_l0_addr;
-        let l1_addr = meta.km_l1_addr;
+        let bottom_level = meta.km_level_count as _LevelIdxT - 1;
+        let bottom_addr = meta.km_level_addr(bottom_level);
+        let bottom_bytes = meta.km_level_bytes(bottom_level);
 
         // update the level size
         meta.km_level_size = new_level_size;
 
-        // current top level becomes the new bottom level
+        // every level moves one level down, the current bottom level is dropped
         // and interim level becomes the new top level
-        meta.km_l1_addr = l0_addr;
-        meta.km_l0_addr = self.interim_lvl_addr.unwrap();
+        for level in (1..=bottom_level).rev() {
+            let addr = meta.km_level_addr(level - 1);
+            meta.set_km_level_addr(level, addr);
+        }
+        meta.set_km_level_addr(0, self.interim_lvl_addr.unwrap());
         self.interim_lvl_addr = None;
 
-        self.km_deallocate(l1_addr, 1 << (level_size - 1))
+        if let Err(why) = self.km_deallocate(bottom_addr, bottom_bytes) {
+            // the dropped level is no longer referenced by the metadata
+            log_error!("{}", why);
+        }
+    }
+}
+
+impl LevelHashIO {
+    /// Shrink the keymap by one level size. The top level is dropped, every other level moves one
+    /// level up and a new bottom level is added, after which the entries of the dropped top level
+    /// are written to their new slots. The levels are then laid out contiguously from the start of
+    /// the keymap file and the keymap file is truncated.
+    ///
+    /// ## Parameters
+    ///
+    /// * `new_level_size`: The level size after shrinking.
+    /// * `moves`: The (bucket, slot) of each of the entries in the top level, and the (level,
+    ///   bucket, slot) to which the entry is moved in the shrunk keymap. The destination slots
+    ///   must be free.
+    pub fn shrink_keymap(
+        &mut self,
+        new_level_size: LevelSizeT,
+        moves: &[SlotMove],
+    ) -> LevelRemapResult {
+        let meta = self.meta.read();
+        let level_count = meta.km_level_count as _LevelIdxT;
+
+        // the levels below the top level move one level up, and the new bottom level has half the
+        // buckets of the current bottom level
+        let mut levels: Vec<Vec<u8>> = (1..level_count)
+            .map(|level| {
+                let addr = meta.km_level_addr(level) as usize;
+                let len = meta.km_level_bytes(level) as usize;
+                self.keymap.map[addr..addr + len].to_vec()
+            })
+            .collect();
+        levels.push(vec![0u8; meta.km_level_bytes(level_count - 1) as usize / 2]);
+
+        for &((bucket, slot), (dest_level, dest_bucket, dest_slot)) in moves {
+            let val_addr = self.keymap.r_u64(self.slot_addr(0, bucket, slot));
+            let off = self.slot_addr_for_lvl_addr(0, dest_bucket, dest_slot) as usize;
+            IOEndianness::write_u64(
+                &mut levels[dest_level as usize][off..off + Self::KEYMAP_ENTRY_SIZE_BYTES as usize],
+                val_addr,
+            );
+        }
+
+        let meta = self.meta.write();
+        meta.km_level_size = new_level_size;
+        let km_size = meta.km_layout_levels();
+
+        for (level, data) in levels.iter().enumerate() {
+            let addr = meta.km_level_addr(level as _LevelIdxT);
+            self.keymap.write_at(addr, data);
+        }
+
+        self.km_resize(Self::km_real_offset(km_size))?;
+        self.build_slot_bitmap();
+        Ok(())
+    }
+}
+
+impl LevelHashIO {
+    /// Check that the levels described by the meta file are valid and fit in the keymap file,
+    /// returning a description of each problem. The slots must not be read if this is not empty.
+    pub fn check_layout(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Some(violation) = self.check_level_params() {
+            violations.push(violation);
+            return violations;
+        }
+
+        let levels = self.level_extents();
+
+        for (i, (i_start, i_end, _)) in levels.iter().enumerate() {
+            for (j, (j_start, j_end, _)) in levels.iter().enumerate().skip(i + 1) {
+                if i_start < j_end && j_start < i_end {
+                    violations.push(format!(
+                        "levels overlap: L{}=[{}, {}) L{}=[{}, {})",
+                        i, i_start, i_end, j, j_start, j_end
+                    ));
+                }
+            }
+        }
+
+        let stash_end = self.meta.read().km_stash_bytes();
+        for (level, (start, _, _)) in levels.iter().enumerate() {
+            if *start < stash_end {
+                violations.push(format!(
+                    "L{} starts at {} but the stash ends at {}",
+                    level, start, stash_end
+                ));
+            }
+        }
+
+        let km_size = self.meta.km_size();
+        for (level, (_, end, _)) in levels.iter().enumerate() {
+            if *end > km_size {
+                violations.push(format!(
+                    "L{} ends at {} but the keymap size in meta is {}",
+                    level, end, km_size
+                ));
+            }
+            if *end > self.keymap.size {
+                violations.push(format!(
+                    "L{} ends at {} but only {} bytes of the keymap are mapped",
+                    level, end, self.keymap.size
+                ));
+            }
+        }
+
+        violations
+    }
+
+    /// Check that the level size, bucket size and level count in the meta file are valid,
+    /// returning a description of the first invalid parameter.
+    pub fn check_level_params(&self) -> Option<String> {
+        Self::check_meta_params(self.meta.read())
+    }
+
+    fn check_meta_params(meta: &LevelMeta) -> Option<String> {
+        if meta.km_level_size == 0 || meta.km_level_size > crate::LEVEL_SIZE_MAX {
+            return Some(format!("invalid level size: {}", meta.km_level_size));
+        }
+
+        if meta.km_bucket_size == 0 {
+            return Some("invalid bucket size: 0".to_string());
+        }
+
+        let align = meta.km_bucket_align;
+        if align != 0 && (!align.is_power_of_two() || align > Self::KEYMAP_BUCKET_ALIGN_MAX) {
+            return Some(format!("invalid bucket alignment: {}", align));
+        }
+
+        let segment_size = meta.val_segment_size;
+        if segment_size != 0
+            && (segment_size < Self::VALUES_SEGMENT_SIZE_MIN
+                || !segment_size.is_multiple_of(SIZE_U64))
+        {
+            return Some(format!("invalid values segment size: {}", segment_size));
+        }
+
+        let level_count = meta.km_level_count as _LevelIdxT;
+        if level_count < 2 || level_count > crate::LEVEL_COUNT_MAX as _LevelIdxT {
+            return Some(format!("invalid level count: {}", level_count));
+        }
+
+        if (meta.km_level_size as _LevelIdxT) < level_count - 1 {
+            return Some(format!(
+                "level size {} is too small for {} levels",
+                meta.km_level_size, level_count
+            ));
+        }
+
+        None
+    }
+
+    /// Get the (start address, end address, bucket count) of each level in the keymap file.
+    pub fn level_extents(&self) -> Vec<(OffT, OffT, u64)> {
+        let meta = self.meta.read();
+        let l0_buckets = 1u64 << meta.km_level_size;
+        (0..meta.km_level_count as _LevelIdxT)
+            .map(|level| {
+                let start = meta.km_level_addr(level);
+                (
+                    start,
+                    start + meta.km_level_bytes(level),
+                    l0_buckets >> level,
+                )
+            })
+            .collect()
+    }
+
+    /// Read the magic numbers of the values and keymap files, or `None` for the in-memory level
+    /// hashes whose files do not have a magic number.
+    pub fn read_magic_numbers(&self) -> LevelResult<Option<(u64, u64)>, StdIOError> {
+        if self.lock_mode().is_none() {
+            return Ok(None);
+        }
+
+        let values = read_magic_fd(self.values.fd())?;
+        let keymap = read_magic_fd(self.keymap.fd.as_fd())?;
+        Ok(Some((values, keymap)))
+    }
+
+    /// Write the expected magic numbers to the values and keymap files. This does nothing for the
+    /// in-memory level hashes.
+    pub fn write_magic_numbers(&self) -> LevelResult<(), StdIOError> {
+        if self.lock_mode().is_none() {
+            return Ok(());
+        }
+
+        write_magic_fd(self.values.fd(), Self::VALUES_MAGIC_NUMBER)?;
+        write_magic_fd(self.keymap.fd.as_fd(), Self::KEYMAP_MAGIC_NUMBER)
+    }
+
+    /// Lay out the levels contiguously from the start of the keymap file and empty all the slots.
+    /// The value entries are not modified.
+    pub fn reset_keymap(&mut self) -> LevelRemapResult {
+        let km_size = self.meta.write().km_layout_levels();
+        self.km_resize(Self::km_real_offset(km_size))?;
+        self.km_deallocate(0, km_size)?;
+        Ok(())
+    }
+
+    /// Get the size (bytes) of the garbage in the used region of the values file, i.e. the regions
+    /// which are not used by an entry.
+    pub fn val_garbage_bytes(&self) -> OffT {
+        let meta = self.meta.read();
+        (meta.val_next_addr - 1).saturating_sub(meta.val_live_bytes)
+    }
+
+    /// Move the entries in the values file towards the start of the file, in the order they are
+    /// stored, so that the used region of the file has no garbage. An entry is not moved across
+    /// the end of a segment of the values file. The slots at the given addresses in the keymap
+    /// and the chain links of the entries are updated to the new addresses of the entries. The
+    /// values file keeps its size, the region after the last entry is deallocated.
+    ///
+    /// ## Returns
+    ///
+    /// The number of bytes by which the used region of the values file shrunk.
+    pub fn compact_values(&mut self, slot_addrs: &[OffT]) -> LevelResult<OffT, StdIOError> {
+        let used = (self.meta.read().val_next_addr - 1).min(self.values.size);
+
+        // the (0-based) address, new address and size of each entry
+        let mut moves = Vec::new();
+        let mut next = 0;
+        for entry in self.values_iter() {
+            let size = align_8(entry.esize());
+            let segment_end = self.values.segment_end(next);
+            if self.pins.is_pinned(entry.addr) {
+                // the pinned entries stay where they are, the entries before them only move
+                // towards the start of the file so they never overlap a pinned entry
+                next = entry.addr;
+            } else if next + size > segment_end {
+                next = segment_end;
+            }
+
+            moves.push((entry.addr, next, size));
+            next += size;
+        }
+
+        // the entries only move towards the start of the file, so an entry is never overwritten
+        // before it has been moved
+        let mut moved = HashMap::new();
+        let mut end = 0;
+        for &(addr, new_addr, size) in &moves {
+            if new_addr > end {
+                // the end of a segment which is skipped
+                self.values.slice_mut(end, new_addr - end).fill(0);
+            }
+            if addr != new_addr {
+                let data = self.values.slice(addr, size).to_vec();
+                self.values.write_at(new_addr, &data);
+                moved.insert(addr + 1, new_addr + 1);
+            }
+            end = new_addr + size;
+        }
+
+        for &(_, new_addr, _) in &moves {
+            let entry = ValuesEntry::at(new_addr, &self.values);
+            let chain_next = entry.chain_next(&self.values);
+            if let Some(&new_next) = chain_next.and_then(|next| moved.get(&next)) {
+                self.set_chain_next(new_addr, new_next);
+            }
+        }
+
+        for &slot_addr in slot_addrs {
+            if let Some(&new_addr) = moved.get(&self.km_val_addr(slot_addr)) {
+                self.km_relink(slot_addr, new_addr);
+            }
+        }
+
+        if used > next {
+            self.val_deallocate(next, used - next)?;
+        }
+
+        let meta = self.meta.write();
+        meta.val_next_addr = next + 1;
+        if let Some(&tail) = moved.get(&meta.val_tail_addr) {
+            meta.val_tail_addr = tail;
+        }
+        self.init_free_list();
+
+        Ok(used.saturating_sub(next))
+    }
+
+    /// Recompute the content fingerprint and the free list from the entries in the values file.
+    /// The tail address is reset since the entries do not link to the previous entry.
+    pub fn rebuild_values_state(&mut self) {
+        let fingerprint = self.compute_fingerprint();
+        let meta = self.meta.write();
+        meta.val_fingerprint = fingerprint;
+        meta.val_fingerprint_valid = 1;
+        meta.val_tail_addr = Self::POS_INVALID;
+
+        self.init_free_list();
+        #[cfg(feature = "secondary-index")]
+        self.rebuild_secondary();
+    }
+}
+
+#[cfg(feature = "secondary-index")]
+impl LevelHashIO {
+    /// Set the extractor of the secondary index, and build the index from the entries in the
+    /// values file.
+    pub fn set_secondary_index(&mut self, extractor: Box<dyn SecondaryKeys>) {
+        self.secondary = Some(SecondaryIndex::new(extractor));
+        self.rebuild_secondary();
+    }
+
+    /// Rebuild the secondary index (if any) from the entries in the values file.
+    fn rebuild_secondary(&mut self) {
+        let Some(mut secondary) = self.secondary.take() else {
+            return;
+        };
+
+        secondary.clear();
+        for entry in self.live_values_iter() {
+            secondary.add(&self.entry_key(&entry), &self.entry_value(&entry));
+        }
+        self.secondary = Some(secondary);
+    }
+
+    /// Add the entry at the given (0-based) address to the secondary index (if any). The value is
+    /// read back from the entry, so that the streamed values are indexed by their contents.
+    fn index_entry(&mut self, addr: OffT, key: &LevelKeyT) {
+        if self.secondary.is_none() {
+            return;
+        }
+
+        let value = self.entry_value(&ValuesEntry::at(addr, &self.values));
+        if let Some(secondary) = self.secondary.as_mut() {
+            secondary.add(key, &value);
+        }
+    }
+
+    /// Remove the given (deleted) entry from the secondary index (if any).
+    fn unindex_entry(&mut self, key: &LevelKeyT, value: &LevelValueT) {
+        if let Some(secondary) = self.secondary.as_mut() {
+            secondary.remove(key, value);
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+impl LevelHashIO {
+    /// Set the shim which is consulted before the operations on the keymap and the values file.
+    pub fn set_io_shim(&mut self, shim: std::sync::Arc<dyn crate::testing::IOShim>) {
+        self.keymap.shim = Some((shim.clone(), IndexFile::Keymap));
+        self.values.shim = Some((shim, IndexFile::Values));
+    }
+}
+
+impl Drop for LevelHashIO {
+    fn drop(&mut self) {
+        // release the entries which were deleted while they were pinned
+        self.unpin_all();
+
+        // a panic may have interrupted an operation, whose metadata must not be checkpointed
+        if !std::thread::panicking() {
+            self.commit_meta();
+            self.save_slot_bitmap();
+        }
+    }
+}
+
+#[cfg(feature = "debug-invariants")]
+impl LevelHashIO {
+    /// Check the invariants of the level hash files, returning a description of each violated
+    /// invariant.
+    ///
+    /// ## Parameters
+    ///
+    /// * `item_counts`: The number of occupied slots that are expected in each level.
+    pub fn check_invariants(&self, item_counts: &[u32]) -> Vec<String> {
+        let mut violations = Vec::new();
+        let meta = self.meta.read();
+
+        // the interim level of an incremental expansion is kept between the write operations
+        if self.interim_lvl_addr.is_some() && self.rehash.is_none() {
+            violations.push("interim level was not committed".to_string());
+        }
+
+        violations.extend(self.check_layout());
+        if !violations.is_empty() {
+            return violations;
+        }
+
+        if meta.val_next_addr == Self::POS_INVALID {
+            violations.push("next value address is 0".to_string());
+        } else if meta.val_next_addr - 1 > self.values.size {
+            violations.push(format!(
+                "next value address {} is beyond the values file size {}",
+                meta.val_next_addr, self.values.size
+            ));
+        }
+
+        if meta.val_tail_addr != Self::POS_INVALID && meta.val_tail_addr >= meta.val_next_addr {
+            violations.push(format!(
+                "tail value address {} is not before the next value address {}",
+                meta.val_tail_addr, meta.val_next_addr
+            ));
+        }
+
+        let used = meta.val_next_addr.saturating_sub(1);
+        if self.free_list.overlaps(used, OffT::MAX - used) {
+            violations.push(format!(
+                "the free list has a region after the next value address {}",
+                meta.val_next_addr
+            ));
+        }
+
+        if meta.val_file_size != self.values.size {
+            violations.push(format!(
+                "values file size in meta ({}) does not match the mapped size ({})",
+                meta.val_file_size, self.values.size
+            ));
+        }
+
+        if !violations.is_empty() {
+            // the slot checks below rely on the layout being valid
+            return violations;
+        }
+
+        let mut seen = std::collections::HashMap::new();
+        for (level, (lvl_addr, _, buckets)) in self.level_extents().into_iter().enumerate() {
+            let mut occupied = 0u32;
+            for bucket in 0..buckets as _BucketIdxT {
+                for slot in 0..meta.km_bucket_size as _SlotIdxT {
+                    let slot_addr = self.slot_addr_for_lvl_addr(lvl_addr, bucket, slot);
+                    let slot_entry = self.keymap.r_u64(slot_addr);
+                    let val_addr = Self::slot_val_addr(slot_entry);
+                    let pos = format!("L{}/B{}/S{}", level, bucket, slot);
+                    if self.slot_bitmap.is_set(slot_addr) != (val_addr != Self::POS_INVALID) {
+                        violations.push(format!("{} is out of sync with the slot bitmap", pos));
+                    }
+
+                    if val_addr == Self::POS_INVALID {
+                        continue;
+                    }
+
+                    if let Some(other) = seen.insert(val_addr, pos.clone()) {
+                        violations.push(format!(
+                            "{} and {} point to the same value address {}",
+                            other, pos, val_addr
+                        ));
+                    }
+
+                    if val_addr >= meta.val_next_addr
+                        || val_addr - 1 + ValuesEntry::ENTRY_SIZE_MIN > self.values.size
+                    {
+                        violations.push(format!(
+                            "{} points to invalid value address {}",
+                            pos, val_addr
+                        ));
+                        continue;
+                    }
+
+                    let entry = ValuesEntry::at(val_addr - 1, &self.values);
+                    if entry.is_empty() {
+                        violations
+                            .push(format!("{} points to an empty entry at {}", pos, val_addr));
+                        continue;
+                    }
+
+                    if entry.addr + entry.esize() > self.values.size {
+                        violations.push(format!(
+                            "{} points to an entry at {} of size {} which exceeds the values file",
+                            pos,
+                            val_addr,
+                            entry.esize()
+                        ));
+                        continue;
+                    }
+
+                    if self.free_list.overlaps(entry.addr, entry.esize()) {
+                        violations.push(format!(
+                            "{} points to an entry at {} which overlaps a free region",
+                            pos, val_addr
+                        ));
+                    }
+
+                    occupied += 1;
+
+                    if let Err(why) = entry.verify_checksum(&self.values) {
+                        violations.push(format!("{} points to a corrupt entry: {:?}", pos, why));
+                    }
+
+                    let tag = Self::slot_tag(slot_entry);
+                    let key_tag = Self::key_tag(&self.entry_key(&entry));
+                    if tag != Self::SLOT_TAG_NONE && tag != key_tag {
+                        violations.push(format!(
+                            "{} has the tag {:#x} which is not the tag of the key of its entry",
+                            pos, tag
+                        ));
+                    }
+                }
+            }
+
+            if occupied != item_counts[level] {
+                violations.push(format!(
+                    "L{} has {} occupied slots but the item count is {}",
+                    level, occupied, item_counts[level]
+                ));
+            }
+        }
+
+        let fingerprint = self.compute_fingerprint();
+        if meta.val_fingerprint_valid != 0 && meta.val_fingerprint != fingerprint {
+            violations.push(format!(
+                "content fingerprint in meta ({:#x}) does not match the entries ({:#x})",
+                meta.val_fingerprint, fingerprint
+            ));
+        }
+
+        violations
     }
 }
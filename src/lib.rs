@@ -15,8 +15,6 @@
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-#![feature(assert_matches)]
-
 #[cfg(not(all(
     any(target_arch = "aarch64", target_arch = "x86_64"),
     any(target_os = "linux", target_os = "android")
@@ -24,19 +22,64 @@
 compile_err!("This library only works on aarch64/x86_64 Linux/Android!");
 
 pub use level_hash::*;
+#[cfg(feature = "tokio")]
+pub use async_hash::AsyncLevelHash;
+pub use batch::WriteBatch;
+pub use bitvec::BitVec;
+pub use group::LevelHashGroup;
+pub use partition::PartitionCursor;
+pub use sharded::ShardedLevelHash;
+#[cfg(feature = "serde")]
+pub use typed::TypedLevelHash;
 
+#[cfg(feature = "tokio")]
+pub mod async_hash;
+pub mod archive;
+pub mod batch;
+pub mod bitvec;
+pub(crate) mod bloom;
+pub mod cancel;
+pub mod expand;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub(crate) mod fs;
+pub mod fsck;
+pub mod group;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 pub(crate) mod io;
+#[cfg(feature = "jni")]
+pub mod jni;
+pub mod key_transform;
 pub(crate) mod level_io;
+pub mod observer;
+pub mod partition;
+pub mod paths;
 
 #[allow(unused_macros, unused_imports)]
 pub(crate) mod log_macros;
 pub(crate) mod meta;
+pub(crate) mod migrations;
+pub(crate) mod occupancy;
+pub(crate) mod overflow;
 pub(crate) mod reprs;
+#[cfg(feature = "secondary-index")]
+pub mod secondary;
+pub mod sharded;
+pub mod siphash;
 pub(crate) mod size;
+pub(crate) mod slot_bitmap;
+#[cfg(test)]
+pub(crate) mod test_macros;
 pub(crate) mod types;
 
 pub mod result;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+#[cfg(feature = "serde")]
+pub mod typed;
 pub mod util;
+pub mod xxhash;
 
 mod level_hash;
@@ -15,39 +15,71 @@
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+//! The logging macros of level hash. The messages are emitted as [tracing] events when the
+//! `logging` feature is enabled, so that the embedding application can capture (or silence) them
+//! by installing a subscriber. Without a subscriber, nothing is logged.
+
 macro_rules! log_trace {
     ($($arg:tt)*) => {
-        crate::log_macros::do_log!("TRACE", $($arg)*)
+        crate::log_macros::do_log!(trace, $($arg)*)
     };
 }
 
 macro_rules! log_debug {
     ($($arg:tt)*) => {
-        crate::log_macros::do_log!("DEBUG", $($arg)*)
+        crate::log_macros::do_log!(debug, $($arg)*)
     };
 }
 
 macro_rules! log_info {
     ($($arg:tt)*) => {
-        crate::log_macros::do_log!("INFO", $($arg)*)
+        crate::log_macros::do_log!(info, $($arg)*)
     };
 }
 
 macro_rules! log_warn {
     ($($arg:tt)*) => {
-        crate::log_macros::do_log!("WARN", $($arg)*)
+        crate::log_macros::do_log!(warn, $($arg)*)
     };
 }
 
 macro_rules! log_error {
     ($($arg:tt)*) => {
-        crate::log_macros::do_log!("ERROR", $($arg)*)
+        crate::log_macros::do_log!(error, $($arg)*)
+    };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! do_log {
+    ($level:ident, $($arg:tt)*) => {
+        ::tracing::$level!($($arg)*)
     };
 }
 
+// the arguments are still type-checked (but never formatted) when logging is disabled
+#[cfg(not(feature = "logging"))]
 macro_rules! do_log {
-    ($level:literal, $($arg:tt)*) => {
-        println!("[{}] [{}:{}:{}] {}", $level, module_path!(), file!(), line!(), format_args!($($arg)*))
+    ($level:ident, $($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Enter a span at the debug level with the given fields, which is exited at the end of the
+/// enclosing block.
+#[cfg(feature = "logging")]
+macro_rules! log_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        let _span = ::tracing::debug_span!($name $(, $field = $value)*).entered();
+    };
+}
+
+// the fields are type-checked, but never evaluated when logging is disabled
+#[cfg(not(feature = "logging"))]
+macro_rules! log_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        if false {
+            $(let _ = $value;)*
+        }
     };
 }
 
@@ -55,5 +87,6 @@ pub(crate) use do_log;
 pub(crate) use log_debug;
 pub(crate) use log_error;
 pub(crate) use log_info;
+pub(crate) use log_span;
 pub(crate) use log_trace;
 pub(crate) use log_warn;
@@ -14,26 +14,43 @@
  *  You should have received a copy of the GNU General Public License
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
-use std::cmp::min;
+use std::cmp::max;
 use std::fs::File;
+use std::os::fd::AsFd;
+use std::os::fd::BorrowedFd;
 use std::path::Path;
+use std::sync::atomic::fence;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 
 use crate::fs::init_sparse_file;
 use crate::io::MappedFile;
 use crate::level_io::LevelHashIO;
 use crate::level_io::LEVEL_KEYMAP_VERSION;
-use crate::level_io::LEVEL_VALUES_VERSION;
+use crate::level_io::LEVEL_KEYMAP_VERSION_ALIGNED;
+use crate::log_macros::log_warn;
 use crate::reprs::LevelMeta;
 use crate::result::IntoLevelIOErr;
 use crate::result::IntoLevelInitErr;
 use crate::result::LevelInitError;
 use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::size::SIZE_U64;
 use crate::types::BucketSizeT;
+use crate::types::LevelCountT;
 use crate::types::LevelSizeT;
 use crate::types::OffT;
+use crate::types::_LevelIdxT;
+use crate::xxhash::xxh64;
+use crate::LEVEL_COUNT_DEFAULT;
 
 #[derive(Debug)]
 struct LevelMetaPtr(*mut LevelMeta);
+
+// the pointer points into the memory map owned by the same MetaIO, which
+// moves along with the pointer
+unsafe impl Send for LevelMetaPtr {}
+
 impl LevelMetaPtr {
     fn new(ptr: *mut LevelMeta) -> Self {
         LevelMetaPtr(ptr)
@@ -56,20 +73,167 @@ impl LevelMetaPtr {
     }
 }
 
+impl LevelMeta {
+    /// Get the address of the given level in the keymap file.
+    pub fn km_level_addr(&self, level: _LevelIdxT) -> OffT {
+        match level {
+            0 => self.km_l0_addr,
+            1 => self.km_l1_addr,
+            2 => self.km_l2_addr,
+            3 => self.km_l3_addr,
+            _ => panic!("invalid level index"),
+        }
+    }
+
+    /// Set the address of the given level in the keymap file.
+    pub fn set_km_level_addr(&mut self, level: _LevelIdxT, addr: OffT) {
+        match level {
+            0 => self.km_l0_addr = addr,
+            1 => self.km_l1_addr = addr,
+            2 => self.km_l2_addr = addr,
+            3 => self.km_l3_addr = addr,
+            _ => panic!("invalid level index"),
+        }
+    }
+
+    /// Get the size (bytes) of the given level in the keymap file. Each level has half the buckets
+    /// of the level above it.
+    pub fn km_level_bytes(&self, level: _LevelIdxT) -> OffT {
+        ((1u64 << self.km_level_size) >> level) * self.km_bucket_bytes()
+    }
+
+    /// Get the size (bytes) of a bucket in the keymap file, including the padding after its slots
+    /// if the buckets are aligned.
+    pub fn km_bucket_bytes(&self) -> OffT {
+        let bytes = self.km_bucket_size as OffT * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES;
+        match self.km_bucket_align {
+            0 => bytes,
+            align => bytes.next_multiple_of(align as OffT),
+        }
+    }
+
+    /// Get the first address at or after the given address in the keymap file at which a level
+    /// can start, so that its buckets are aligned.
+    pub fn km_level_start(&self, addr: OffT) -> OffT {
+        match self.km_bucket_align {
+            0 => addr,
+            align => {
+                // the alignment is relative to the start of the file, before the header
+                let header = LevelHashIO::KEYMAP_HEADER_SIZE_BYTES;
+                (addr + header).next_multiple_of(align as OffT) - header
+            }
+        }
+    }
+
+    /// Reverse the byte order of all the fields, converting the metadata between the little-endian
+    /// and the big-endian representations.
+    pub fn swap_bytes(&mut self) {
+        self.val_version = self.val_version.swap_bytes();
+        self.km_version = self.km_version.swap_bytes();
+        self.val_tail_addr = self.val_tail_addr.swap_bytes();
+        self.val_next_addr = self.val_next_addr.swap_bytes();
+        self.val_file_size = self.val_file_size.swap_bytes();
+        self.km_l0_addr = self.km_l0_addr.swap_bytes();
+        self.km_l1_addr = self.km_l1_addr.swap_bytes();
+        self.ovf_next_addr = self.ovf_next_addr.swap_bytes();
+        self.km_l2_addr = self.km_l2_addr.swap_bytes();
+        self.km_l3_addr = self.km_l3_addr.swap_bytes();
+        self.val_fingerprint = self.val_fingerprint.swap_bytes();
+        self.val_block_size = self.val_block_size.swap_bytes();
+        self.km_occupancy.valid = self.km_occupancy.valid.swap_bytes();
+        for count in self.km_occupancy.counts.iter_mut() {
+            *count = count.swap_bytes();
+        }
+        self.km_generation = self.km_generation.swap_bytes();
+        self.km_bucket_align = self.km_bucket_align.swap_bytes();
+        self.km_interim_addr = self.km_interim_addr.swap_bytes();
+        self.km_rehash_next = self.km_rehash_next.swap_bytes();
+        self.val_segment_size = self.val_segment_size.swap_bytes();
+        self.km_seed_1 = self.km_seed_1.swap_bytes();
+        self.km_seed_2 = self.km_seed_2.swap_bytes();
+        self.km_hash_check = self.km_hash_check.swap_bytes();
+        self.km_default_hash = self.km_default_hash.swap_bytes();
+        self.km_stash_slots = self.km_stash_slots.swap_bytes();
+        self.val_live_bytes = self.val_live_bytes.swap_bytes();
+        self.val_max_key_size = self.val_max_key_size.swap_bytes();
+        self.val_max_value_size = self.val_max_value_size.swap_bytes();
+        self.val_write_counter = self.val_write_counter.swap_bytes();
+    }
+
+    /// Get the size (bytes) of the stash at the start of the keymap file.
+    pub fn km_stash_bytes(&self) -> OffT {
+        self.km_stash_slots as OffT * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES
+    }
+
+    /// Place the levels next to each other (top level first) at the start of the keymap, after the
+    /// stash.
+    ///
+    /// ## Returns
+    ///
+    /// The size of the keymap required to contain the levels.
+    pub fn km_layout_levels(&mut self) -> OffT {
+        let mut addr = self.km_stash_bytes();
+        for level in 0..self.km_level_count as _LevelIdxT {
+            addr = self.km_level_start(addr);
+            self.set_km_level_addr(level, addr);
+            addr += self.km_level_bytes(level);
+        }
+        addr
+    }
+}
+
+/// The memory-mapped meta file.
+///
+/// The meta file is stored in little-endian byte order. On big-endian hosts, the metadata is
+/// converted to the native byte order when the file is opened and back when it is closed. The byte
+/// order of the file is detected from the keymap version, so a meta file which was not converted
+/// back (e.g. the process was killed) or was written in the native byte order of a big-endian host
+/// before version 2 of the keymap is read correctly as well.
+///
+/// The metadata at the start of the file is updated field by field while the level hash is
+/// written, so a crash in the middle of an operation may leave the fields mutually inconsistent.
+/// The metadata is double-buffered to recover from this : after each write operation, it is copied
+/// to the older of the two checkpoint pages after it, along with a sequence number and a checksum.
+/// The metadata which differs from the newest valid checkpoint page when the index is opened for
+/// writing was left by an interrupted operation, and is restored from the page (see
+/// [Self::restore_checkpoint]). Only the metadata is rolled back : the slots and the entries
+/// written by the interrupted operation are left in the keymap and the values file, and the level
+/// hash repairs them when it is opened, dropping the entries written after the checkpoint. The
+/// versions of level hash which do not know about the checkpoint pages truncate them, and the
+/// metadata is then used as it is.
 #[derive(Debug)]
 pub(crate) struct MetaIO {
     _file: MappedFile,
     meta: LevelMetaPtr,
+    swapped: bool,
+    /// Whether the metadata was restored from a checkpoint page when the index was opened.
+    pub restored: bool,
+    // the index and the sequence number of the newest valid checkpoint page, which is found when
+    // the file is opened and then only changes with the commits
+    checkpoint: Option<(usize, u64)>,
 }
 
 impl MetaIO {
     #[allow(non_upper_case_globals)]
     pub const META__SIZE_BYTES: OffT = size_of::<LevelMeta>() as OffT;
 
+    /// The size (bytes) of a checkpoint page : a copy of the metadata, followed by the sequence
+    /// number of the page and the checksum of the copy.
+    pub const META_PAGE_SIZE_BYTES: OffT = Self::META__SIZE_BYTES + 2 * SIZE_U64;
+
+    /// The size (bytes) of the meta file, with the metadata followed by two checkpoint pages.
+    pub const META_FILE_SIZE_BYTES: OffT = Self::META__SIZE_BYTES + 2 * Self::META_PAGE_SIZE_BYTES;
+
+    /// Create a new [MetaIO] from the meta file at the given path. The file is created if it does
+    /// not exist. The level size, bucket size, level count, bucket alignment and values version
+    /// are used only if the meta file is new.
     pub fn new(
         path: &Path,
         level_size: LevelSizeT,
         bucket_size: BucketSizeT,
+        level_count: LevelCountT,
+        bucket_align: u32,
+        val_version: u32,
     ) -> LevelResult<MetaIO, LevelInitError> {
         init_sparse_file(path, None)?;
 
@@ -81,21 +245,56 @@ impl MetaIO {
             .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))
             .into_lvl_init_err()?;
 
-        file.set_len(Self::META__SIZE_BYTES)
+        Self::from_file(
+            file,
+            level_size,
+            bucket_size,
+            level_count,
+            bucket_align,
+            val_version,
+        )
+    }
+
+    /// Create a new [MetaIO] from the given (already opened) meta file.
+    pub fn from_file(
+        file: File,
+        level_size: LevelSizeT,
+        bucket_size: BucketSizeT,
+        level_count: LevelCountT,
+        bucket_align: u32,
+        val_version: u32,
+    ) -> LevelResult<MetaIO, LevelInitError> {
+        file.set_len(Self::META_FILE_SIZE_BYTES)
             .into_lvl_io_e_msg(format!(
-                "failed to set length [{}] of file: {}",
-                Self::META__SIZE_BYTES,
-                path.display()
+                "failed to set length [{}] of meta file",
+                Self::META_FILE_SIZE_BYTES
             ))
             .into_lvl_init_err()?;
 
         let mut mmap =
-            MappedFile::new(file.into(), 0, Self::META__SIZE_BYTES).into_lvl_init_err()?;
+            MappedFile::new(file.into(), 0, Self::META_FILE_SIZE_BYTES).into_lvl_init_err()?;
         let meta = LevelMetaPtr::new(mmap.map.as_mut_ptr() as *mut LevelMeta);
-        let mut meta_io = MetaIO { _file: mmap, meta };
+        let mut meta_io = MetaIO {
+            _file: mmap,
+            meta,
+            swapped: false,
+            restored: false,
+            checkpoint: None,
+        };
+        meta_io.checkpoint = meta_io.newest_page();
+
         let meta = meta_io.write();
+
+        // the versions are small numbers, so the version is larger than its byte-swapped
+        // counterpart only if the file is in the other byte order
+        let swapped = meta.km_version.swap_bytes() < meta.km_version;
+        if swapped {
+            meta.swap_bytes();
+        }
+
+        let is_new = meta.km_level_size == 0;
         if meta.val_version == 0 {
-            meta.val_version = LEVEL_VALUES_VERSION;
+            meta.val_version = val_version;
         }
 
         if meta.km_version == 0 {
@@ -114,31 +313,244 @@ impl MetaIO {
             meta.km_bucket_size = bucket_size;
         }
 
-        // default value of l0Addr is 0
-        // only the value of l1Addr should be updated
-        if meta.km_l1_addr == 0 {
-            let bsize = meta.km_bucket_size as u64;
-            let addr = (1u64 << level_size) * bsize * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES;
-            meta.km_l1_addr = addr;
+        if is_new || meta.km_level_count == 0 {
+            // a new index has the given level count (even if the file had another one), the
+            // indexes created before the level count was stored always have two levels
+            meta.km_level_count = if is_new {
+                level_count
+            } else {
+                LEVEL_COUNT_DEFAULT
+            };
         }
 
+        // the levels of existing indexes must not be moved, the
+        // top level can be at any address (including 0) after expansion
+        if is_new {
+            // the keymaps with aligned buckets cannot be read by the versions of level hash which
+            // do not know about the alignment
+            meta.km_bucket_align = bucket_align;
+            if bucket_align != 0 {
+                meta.km_version = LEVEL_KEYMAP_VERSION_ALIGNED;
+            }
+            meta.km_layout_levels();
+            meta.val_fingerprint = 0;
+            meta.val_fingerprint_valid = 1;
+            meta.km_occupancy.valid = 1;
+        }
+
+        // a structural change which was interrupted (e.g. the process was killed) cannot be in
+        // progress anymore, as the index is locked when it is opened
+        if meta.km_generation & 1 == 1 {
+            meta.km_generation += 1;
+        }
+
+        meta_io.swapped = swapped;
         Ok(meta_io)
     }
 
-    pub fn km_start_addr(&mut self) -> OffT {
-        let meta = self.read();
-        min(meta.km_l0_addr, meta.km_l1_addr)
+    /// Whether the meta file was stored in the byte order other than the native byte order of the
+    /// host when it was opened.
+    pub fn was_swapped(&self) -> bool {
+        self.swapped
     }
 
-    pub fn km_size(&mut self) -> OffT {
+    pub fn km_size(&self) -> OffT {
         let meta = self.read();
-        let l0_bytes = (1u64 << meta.km_level_size)
-            * meta.km_bucket_size as u64
-            * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES;
-        let mut size = self.km_start_addr();
-        size += l0_bytes;
-        size += l0_bytes >> 1;
-        return size;
+
+        // the levels are not necessarily placed next to each other
+        // (e.g. after expansion), so the keymap must be large enough
+        // to contain the level which ends last
+        let size = (0..meta.km_level_count as _LevelIdxT)
+            .map(|level| meta.km_level_addr(level) + meta.km_level_bytes(level))
+            .fold(0, max);
+
+        // the interim level of an incremental expansion has twice the buckets of the top level
+        match meta.km_interim_addr {
+            0 => size,
+            addr => max(size, addr + meta.km_level_bytes(0) * 2),
+        }
+    }
+
+    /// Get the number of bytes stored in each segment of the values file, which is [OffT::MAX] if
+    /// the values file is not segmented.
+    pub fn val_segment_size(&self) -> OffT {
+        match self.read().val_segment_size {
+            0 => OffT::MAX,
+            size => size,
+        }
+    }
+
+    /// Schedule the changes made to the metadata to be written to the file.
+    pub fn flush_async(&self) -> LevelResult<(), StdIOError> {
+        self._file.flush_async()
+    }
+
+    /// Write the changes made to the metadata to the file, waiting for the writes to complete.
+    pub fn flush(&self) -> LevelResult<(), StdIOError> {
+        self._file.flush()
+    }
+
+    /// Get the descriptor of the meta file.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self._file.fd.as_fd()
+    }
+
+    /// Get the generation of the keymap, which is odd while a structural change is in progress.
+    /// The reads made after this call happen after the generation is read.
+    pub fn generation(&self) -> u64 {
+        self.km_generation().load(Ordering::Acquire)
+    }
+
+    /// Get the generation of the keymap again after a read. The reads made before this call happen
+    /// before the generation is read.
+    pub fn generation_after_read(&self) -> u64 {
+        fence(Ordering::Acquire);
+        self.km_generation().load(Ordering::Relaxed)
+    }
+
+    /// Mark the start of a structural change of the keymap, making the generation odd.
+    pub fn begin_structural_change(&mut self) {
+        let generation = self.km_generation();
+        debug_assert!(generation.load(Ordering::Relaxed) & 1 == 0);
+        generation.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+    }
+
+    /// Mark the end of a structural change of the keymap, making the generation even.
+    pub fn end_structural_change(&mut self) {
+        let generation = self.km_generation();
+        debug_assert!(generation.load(Ordering::Relaxed) & 1 == 1);
+        generation.fetch_add(1, Ordering::Release);
+    }
+
+    fn km_generation(&self) -> &AtomicU64 {
+        // the generation is aligned to 8 bytes in the page-aligned memory map
+        unsafe {
+            let ptr = std::ptr::addr_of!((*self.meta.ptr()).km_generation);
+            AtomicU64::from_ptr(ptr as *mut u64)
+        }
+    }
+
+    /// Get the address of the checkpoint page at the given index (`0` or `1`).
+    fn page_addr(idx: usize) -> OffT {
+        Self::META__SIZE_BYTES + idx as OffT * Self::META_PAGE_SIZE_BYTES
+    }
+
+    /// Get the sequence number of the checkpoint page at the given index, or `None` if the page
+    /// has never been written or its checksum does not match its contents.
+    fn page_seq(&self, idx: usize) -> Option<u64> {
+        let addr = Self::page_addr(idx);
+        let seq = self._file.r_u64(addr + Self::META__SIZE_BYTES);
+        let checksum = self._file.r_u64(addr + Self::META__SIZE_BYTES + SIZE_U64);
+        let bytes = &self._file.map[addr as usize..(addr + Self::META__SIZE_BYTES) as usize];
+        (seq != 0 && checksum == xxh64(seq, bytes)).then_some(seq)
+    }
+
+    /// Get the index and the sequence number of the newest valid checkpoint page, if any.
+    fn newest_page(&self) -> Option<(usize, u64)> {
+        (0..2)
+            .filter_map(|idx| self.page_seq(idx).map(|seq| (idx, seq)))
+            .max_by_key(|(_, seq)| *seq)
+    }
+
+    /// Get the bytes of the metadata in the byte order of the file (little-endian).
+    fn live_bytes(&self) -> [u8; Self::META__SIZE_BYTES as usize] {
+        #[allow(unused_mut)]
+        let mut bytes = self.file_bytes(0);
+        #[cfg(target_endian = "big")]
+        Self::swap_meta_bytes(&mut bytes);
+        bytes
+    }
+
+    /// Copy the bytes of the metadata (or of the copy in a checkpoint page) at the given address
+    /// of the file.
+    fn file_bytes(&self, addr: usize) -> [u8; Self::META__SIZE_BYTES as usize] {
+        let mut bytes = [0u8; Self::META__SIZE_BYTES as usize];
+        bytes.copy_from_slice(&self._file.map[addr..addr + Self::META__SIZE_BYTES as usize]);
+        bytes
+    }
+
+    /// Reverse the byte order of the fields of the metadata stored in the given bytes.
+    #[cfg(target_endian = "big")]
+    fn swap_meta_bytes(bytes: &mut [u8]) {
+        // the fields are swapped in a copy which is aligned like the metadata
+        let mut words = vec![0u64; bytes.len().div_ceil(SIZE_U64 as usize)];
+        let aligned =
+            unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, bytes.len()) };
+        aligned.copy_from_slice(bytes);
+        let meta: &mut LevelMeta = aligned.into();
+        meta.swap_bytes();
+        bytes.copy_from_slice(aligned);
+    }
+
+    /// Copy the metadata to the older checkpoint page, with the next sequence number. This must
+    /// only be called between the write operations, and never by the read-only level hashes which
+    /// may observe an operation of the writer. Nothing is copied while a structural change is in
+    /// progress, as the metadata may not be consistent until it is complete.
+    pub fn commit(&mut self) {
+        if self.generation() & 1 == 1 {
+            return;
+        }
+
+        let bytes = self.live_bytes();
+        if let Some((idx, _)) = self.checkpoint {
+            let addr = Self::page_addr(idx) as usize;
+            if self._file.map[addr..addr + bytes.len()] == bytes {
+                // nothing has changed since the last checkpoint
+                return;
+            }
+        }
+
+        let (idx, seq) = match self.checkpoint {
+            Some((idx, seq)) => (1 - idx, seq + 1),
+            None => (0, 1),
+        };
+
+        // the checksum is written last, so a page which is torn by a crash is invalid
+        let addr = Self::page_addr(idx);
+        let seq_addr = addr + Self::META__SIZE_BYTES;
+        self._file.w_u64(seq_addr + SIZE_U64, 0);
+        self._file.write_at(addr, &bytes);
+        self._file.w_u64(seq_addr, seq);
+        self._file.w_u64(seq_addr + SIZE_U64, xxh64(seq, &bytes));
+        self.checkpoint = Some((idx, seq));
+    }
+
+    /// Restore the metadata from the newest valid checkpoint page if it differs from the page,
+    /// which means that an operation was interrupted after the page was written. The metadata is
+    /// kept as it is if there is no valid page, e.g. in a new meta file or in a meta file written
+    /// by a version of level hash which does not know about the pages.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the metadata was restored.
+    pub fn restore_checkpoint(&mut self) -> bool {
+        let Some((idx, seq)) = self.checkpoint else {
+            return false;
+        };
+
+        #[allow(unused_mut)]
+        let mut bytes = self.file_bytes(Self::page_addr(idx) as usize);
+        if bytes == self.live_bytes() {
+            return false;
+        }
+
+        log_warn!(
+            "restoring the metadata from checkpoint {} after an interrupted operation",
+            seq
+        );
+        #[cfg(target_endian = "big")]
+        Self::swap_meta_bytes(&mut bytes);
+        let generation = self.generation();
+        self._file.write_at(0, &bytes);
+
+        // the generation never goes back, the readers may have observed the interrupted operation
+        let meta = self.write();
+        meta.km_generation = meta.km_generation.max(generation);
+        self.commit();
+
+        self.restored = true;
+        true
     }
 
     #[inline]
@@ -152,11 +564,20 @@ impl MetaIO {
     }
 }
 
+#[cfg(target_endian = "big")]
+impl Drop for MetaIO {
+    fn drop(&mut self) {
+        // the meta file is always stored in little-endian byte order
+        self.write().swap_bytes();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
 
     use super::*;
+    use crate::level_io::LEVEL_VALUES_VERSION;
     use crate::{BUCKET_SIZE_DEFAULT, LEVEL_SIZE_DEFAULT};
 
     fn create_meta_io(name: &str, create_new: bool) -> MetaIO {
@@ -167,8 +588,15 @@ mod tests {
         fs::create_dir_all(&meta_dir).expect("Failed to create directories");
 
         let meta_file = meta_dir.join(format!("{}.storage._meta", name));
-        MetaIO::new(meta_file.as_path(), LEVEL_SIZE_DEFAULT, BUCKET_SIZE_DEFAULT)
-            .expect("failed to create meta file")
+        MetaIO::new(
+            meta_file.as_path(),
+            LEVEL_SIZE_DEFAULT,
+            BUCKET_SIZE_DEFAULT,
+            LEVEL_COUNT_DEFAULT,
+            0,
+            LEVEL_VALUES_VERSION,
+        )
+        .expect("failed to create meta file")
     }
 
     #[test]
@@ -191,6 +619,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_meta_checkpoint_pages() {
+        {
+            let mut io = create_meta_io("checkpoint", true);
+            assert!(!io.restore_checkpoint());
+            io.write().val_next_addr = 200;
+            io.commit();
+            io.write().val_next_addr = 300;
+            io.commit();
+            assert_eq!(io.newest_page().map(|(_, seq)| seq), Some(2));
+
+            // an interrupted operation
+            io.write().val_next_addr = 400;
+            io.write().km_generation = 4;
+        }
+
+        {
+            let mut io = create_meta_io("checkpoint", false);
+            assert!(io.restore_checkpoint());
+            assert!(io.restored);
+            assert_eq!(io.read().val_next_addr, 300);
+            assert_eq!(io.generation(), 4);
+            assert_eq!(io.newest_page(), Some((0, 3)));
+            assert!(!io.restore_checkpoint());
+
+            // a page torn by a crash
+            let addr = MetaIO::page_addr(0) + MetaIO::META__SIZE_BYTES + SIZE_U64;
+            io._file.w_u64(addr, 0);
+        }
+
+        {
+            // the torn page is ignored, and the metadata is restored from the older page
+            let mut io = create_meta_io("checkpoint", false);
+            assert_eq!(io.newest_page(), Some((1, 2)));
+            assert!(io.restore_checkpoint());
+            assert_eq!(io.read().val_next_addr, 300);
+            assert_eq!(io.generation(), 4);
+            assert_eq!(io.newest_page(), Some((0, 3)));
+            io.write().val_next_addr = 500;
+            io.commit();
+            assert_eq!(io.newest_page(), Some((1, 4)));
+        }
+    }
+
     #[test]
     fn test_meta_init_with_existing_file() {
         {
@@ -227,4 +699,80 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn test_meta_in_other_byte_order() {
+        {
+            let mut io = create_meta_io("other-byte-order", true);
+            assert!(!io.was_swapped());
+            io.write().val_next_addr = 200;
+            io.write().km_occupancy.counts[1] = 3;
+            io.write().swap_bytes();
+        }
+
+        let io = create_meta_io("other-byte-order", false);
+        let meta = io.read();
+        assert!(io.was_swapped());
+        assert_eq!(meta.km_version, LEVEL_KEYMAP_VERSION);
+        assert_eq!(meta.val_next_addr, 200);
+        assert_eq!(meta.km_level_size, LEVEL_SIZE_DEFAULT);
+        assert_eq!(meta.km_occupancy.counts[1], 3);
+    }
+
+    #[test]
+    fn test_meta_init_with_level_count() {
+        let meta_dir = Path::new("target/tests/level-hash/meta-level-count");
+        if meta_dir.exists() {
+            fs::remove_dir_all(meta_dir).expect("Failed to delete existing directory");
+        }
+        fs::create_dir_all(meta_dir).expect("Failed to create directories");
+        let meta_file = meta_dir.join("level-count.storage._meta");
+
+        {
+            let mut io = MetaIO::new(&meta_file, 4, 2, 3, 0, LEVEL_VALUES_VERSION)
+                .expect("failed to create meta file");
+            let l0_bytes = 16 * 2 * LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES;
+            let meta = io.read();
+            assert_eq!(meta.km_level_count, 3);
+            assert_eq!(meta.km_l0_addr, 0);
+            assert_eq!(meta.km_l1_addr, l0_bytes);
+            assert_eq!(meta.km_l2_addr, l0_bytes + l0_bytes / 2);
+            assert_eq!(meta.km_l3_addr, 0);
+            assert_eq!(io.km_size(), l0_bytes + l0_bytes / 2 + l0_bytes / 4);
+
+            // simulate an index created before the level count was stored
+            io.write().km_level_count = 0;
+        }
+
+        let io = MetaIO::new(&meta_file, 4, 2, 3, 0, LEVEL_VALUES_VERSION)
+            .expect("failed to open meta file");
+        assert_eq!(io.read().km_level_count, LEVEL_COUNT_DEFAULT);
+    }
+
+    #[test]
+    fn test_meta_init_with_aligned_buckets() {
+        let meta_dir = Path::new("target/tests/level-hash/meta-aligned-buckets");
+        if meta_dir.exists() {
+            fs::remove_dir_all(meta_dir).expect("Failed to delete existing directory");
+        }
+        fs::create_dir_all(meta_dir).expect("Failed to create directories");
+        let meta_file = meta_dir.join("aligned-buckets.storage._meta");
+
+        // 3 slots (24 bytes) per bucket are padded to 64 bytes, and the levels start 8 bytes
+        // before a multiple of 64, after the 8-byte header of the keymap file
+        let io = MetaIO::new(&meta_file, 4, 3, 3, 64, LEVEL_VALUES_VERSION)
+            .expect("failed to create meta file");
+        let meta = io.read();
+        assert_eq!(meta.km_version, LEVEL_KEYMAP_VERSION_ALIGNED);
+        assert_eq!(meta.km_bucket_bytes(), 64);
+        assert_eq!(meta.km_l0_addr, 56);
+        assert_eq!(meta.km_l1_addr, 56 + 16 * 64);
+        assert_eq!(meta.km_l2_addr, 56 + 16 * 64 + 8 * 64);
+        assert_eq!(io.km_size(), 56 + (16 + 8 + 4) * 64);
+        for level in 0..3 {
+            let addr = meta.km_level_addr(level) + LevelHashIO::KEYMAP_HEADER_SIZE_BYTES;
+            assert_eq!(addr % 64, 0);
+        }
+    }
 }
@@ -0,0 +1,142 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The migrations which upgrade the index files written by older versions of level hash. The
+//! versions of the values file and the keymap are read from the meta file when the index is
+//! opened, and the pending migrations are run in the order they are registered in [MIGRATIONS].
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::level_io::LevelHashIO;
+use crate::level_io::LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN;
+use crate::log_macros::log_info;
+use crate::log_macros::log_warn;
+use crate::result::IntoLevelInitErr;
+use crate::result::LevelInitError;
+use crate::result::LevelResult;
+
+/// The paths used by the migrations of an index.
+///
+/// # Properties
+///
+/// * `index_dir`: The directory of the index. The backups are created in this directory.
+/// * `bloom_file`: The path of the bloom filter file, which is derived from the values file.
+pub(crate) struct MigrationContext<'a> {
+    pub index_dir: &'a Path,
+    pub bloom_file: &'a Path,
+}
+
+/// A migration of the index files from an older format.
+///
+/// # Properties
+///
+/// * `name`: The name of the migration, used in the logs and in the name of the backup directory.
+/// * `pending`: Whether the index needs the migration.
+/// * `rewrites`: Whether the migration rewrites the contents of the index files, instead of only
+///   updating the versions in the meta file. The index files are backed up before such a migration
+///   is run, and a read-only index cannot be opened until it has been migrated.
+/// * `run`: Run the migration. The index is locked exclusively.
+pub(crate) struct Migration {
+    pub name: &'static str,
+    pub pending: fn(&LevelHashIO) -> bool,
+    pub rewrites: fn(&LevelHashIO) -> bool,
+    pub run: fn(&mut LevelHashIO, &MigrationContext) -> LevelResult<(), LevelInitError>,
+}
+
+/// The registered migrations, in the order they are run.
+pub(crate) const MIGRATIONS: &[Migration] = &[Migration {
+    name: "little-endian",
+    pending: |io| io.meta.read().km_version <= LEVEL_KEYMAP_VERSION_NATIVE_ENDIAN,
+    // the files of a v1 index are in the same byte order as its meta file
+    rewrites: |io| cfg!(target_endian = "big") != io.meta.was_swapped(),
+    run: LevelHashIO::migrate_v1,
+}];
+
+/// Get the directory in which the index files are backed up before the given migration is run.
+pub(crate) fn backup_dir(index_dir: &Path, index_name: &str, migration: &Migration) -> PathBuf {
+    index_dir.join(format!(
+        "{}{}.backup-{}",
+        index_name,
+        LevelHashIO::LEVEL_INDEX_EXT,
+        migration.name
+    ))
+}
+
+/// Run the pending migrations of the given index.
+///
+/// A read-only index is only checked : the migrations which only update the versions are skipped,
+/// and [LevelInitError::MigrationRequired] is returned if a migration would rewrite the files.
+pub(crate) fn run_migrations(
+    io: &mut LevelHashIO,
+    ctx: &MigrationContext,
+) -> LevelResult<(), LevelInitError> {
+    for migration in MIGRATIONS {
+        if !(migration.pending)(io) {
+            continue;
+        }
+
+        let rewrites = (migration.rewrites)(io);
+        if io.is_read_only() {
+            if !rewrites {
+                continue;
+            }
+
+            if io.meta.was_swapped() {
+                // leave the meta file as it was, it is converted along with the other files
+                io.meta.write().swap_bytes();
+            }
+            return Err(LevelInitError::MigrationRequired);
+        }
+
+        if rewrites {
+            backup(io, &backup_dir(ctx.index_dir, &io.index_name, migration))?;
+        }
+
+        log_info!(
+            "running migration '{}' of {}",
+            migration.name,
+            io.index_name
+        );
+        (migration.run)(io, ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Copy the index files to the given backup directory, unless a backup already exists there. An
+/// existing backup was made before an earlier attempt of the same migration, which may have left
+/// the index files partially converted, so it is never overwritten.
+fn backup(io: &mut LevelHashIO, dir: &Path) -> LevelResult<(), LevelInitError> {
+    if dir.exists() {
+        log_warn!("keeping the existing backup in {}", dir.display());
+        return Ok(());
+    }
+
+    // the meta file is backed up in the byte order it was stored in
+    let swapped = io.meta.was_swapped();
+    if swapped {
+        io.meta.write().swap_bytes();
+    }
+
+    let result = io.snapshot_to(dir);
+    if swapped {
+        io.meta.write().swap_bytes();
+    }
+
+    result.into_lvl_init_err()
+}
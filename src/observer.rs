@@ -0,0 +1,249 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Observers which are notified of the internal events of a [LevelHash](crate::LevelHash), e.g.
+//! to collect metrics about the insertion failures. The observer is set with
+//! [LevelHashOptions::observer](crate::LevelHashOptions::observer).
+
+use crate::fsck::IndexFile;
+use crate::result::LevelInsertionError;
+use crate::types::LevelSizeT;
+use crate::types::OffT;
+use crate::Level;
+
+/// The kind of movement which is attempted to make room for a new entry whose buckets are full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementKind {
+    /// The entries are moved to their alternative buckets in the same level.
+    SameLevel,
+
+    /// An entry of a lower level is moved to one of its buckets in the level above.
+    BottomToTop,
+}
+
+/// An event in a [LevelHash](crate::LevelHash) which is passed to its [LevelObserver].
+#[derive(Debug)]
+pub enum LevelEvent<'a> {
+    /// The entries in the given level were (or could not be) moved to make room for a new entry,
+    /// after all the slots in the buckets of the entry were found occupied.
+    Movement {
+        level: Level,
+        kind: MovementKind,
+        moved: bool,
+    },
+
+    /// The level hash is being expanded, automatically or with
+    /// [LevelHash::expand](crate::LevelHash::expand). `level_size` is the level size before the
+    /// expansion.
+    ExpansionStarted { level_size: LevelSizeT },
+
+    /// The expansion has finished. `level_size` is the level size after the expansion, which is
+    /// unchanged if the expansion failed.
+    ExpansionFinished { level_size: LevelSizeT, ok: bool },
+
+    /// An entry could not be inserted because no slot could be found for it. The error carries
+    /// the diagnostics of the buckets which were probed, if any.
+    InsertionFailed { error: &'a LevelInsertionError },
+
+    /// An index file was resized by a write operation, e.g. when the values file grows or the
+    /// keymap is expanded, shrunk or cleared.
+    Resized {
+        file: IndexFile,
+        old_size: OffT,
+        new_size: OffT,
+    },
+
+    /// The bytes written to the index files since the level hash was opened (see
+    /// [WriteStats::total_bytes](crate::WriteStats::total_bytes)) have exceeded another multiple
+    /// of the [write budget](crate::LevelHashOptions::write_budget). This suggests flushing or
+    /// compacting the level hash in the background.
+    WriteBudgetExceeded { written: u64, budget: u64 },
+}
+
+/// An observer which is notified of the events in a [LevelHash](crate::LevelHash). The observer
+/// is called synchronously by the operation which caused the event, so it must not block.
+///
+/// Any `FnMut(&LevelEvent)` can be used as an observer.
+pub trait LevelObserver: Send {
+    /// Handle the given event.
+    fn on_event(&mut self, event: &LevelEvent<'_>);
+}
+
+impl<F> LevelObserver for F
+where
+    F: FnMut(&LevelEvent<'_>) + Send,
+{
+    fn on_event(&mut self, event: &LevelEvent<'_>) {
+        self(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use crate::expand::NeverExpand;
+    use crate::fsck::IndexFile;
+    use crate::observer::LevelEvent;
+    use crate::observer::MovementKind;
+    use crate::result::LevelInsertionError;
+    use crate::test_macros::assert_matches;
+    use crate::LevelHash;
+
+    /// The events of [LevelEvent] without the borrowed data.
+    #[derive(Debug, PartialEq)]
+    enum Recorded {
+        Movement(MovementKind, bool),
+        ExpansionStarted(u8),
+        ExpansionFinished(u8, bool),
+        InsertionFailed,
+        Resized(IndexFile),
+        WriteBudgetExceeded(u64),
+    }
+
+    // the first and second bytes of the keys are their bucket indexes
+    fn first_byte(_: u64, data: &[u8]) -> u64 {
+        data[0] as u64
+    }
+
+    fn second_byte(_: u64, data: &[u8]) -> u64 {
+        data[1] as u64
+    }
+
+    fn observed_level_hash(name: &str) -> (LevelHash, Arc<Mutex<Vec<Recorded>>>) {
+        let dir_path = format!("target/tests/level-hash/observer-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let hash = LevelHash::options()
+            .index_dir(index_dir)
+            .index_name(name)
+            .level_size(2)
+            .bucket_size(1)
+            .movement_depth(1)
+            .hash_fns(first_byte, second_byte)
+            .expand_policy(NeverExpand)
+            .observer(move |event: &LevelEvent<'_>| {
+                let event = match event {
+                    LevelEvent::Movement { kind, moved, .. } => Recorded::Movement(*kind, *moved),
+                    LevelEvent::ExpansionStarted { level_size } => {
+                        Recorded::ExpansionStarted(*level_size)
+                    }
+                    LevelEvent::ExpansionFinished { level_size, ok } => {
+                        Recorded::ExpansionFinished(*level_size, *ok)
+                    }
+                    LevelEvent::InsertionFailed { .. } => Recorded::InsertionFailed,
+                    LevelEvent::Resized { file, .. } => Recorded::Resized(*file),
+                    LevelEvent::WriteBudgetExceeded { written, .. } => {
+                        Recorded::WriteBudgetExceeded(*written)
+                    }
+                };
+                recorded.lock().unwrap().push(event);
+            })
+            .build()
+            .expect("Failed to create level hash");
+
+        (hash, events)
+    }
+
+    #[test]
+    fn observer_events() {
+        let (mut hash, events) = observed_level_hash("events");
+
+        // A and B fill the buckets 0 and 1 in the top level, C fills bucket 0 in the bottom level
+        hash.insert(&[0, 1, b'A'], b"a").unwrap();
+        hash.insert(&[1, 2, b'B'], b"b").unwrap();
+        hash.insert(&[0, 0, b'C'], b"c").unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Recorded::Resized(IndexFile::Values),
+                Recorded::Resized(IndexFile::Values),
+                Recorded::Resized(IndexFile::Values),
+            ]
+        );
+        events.lock().unwrap().clear();
+
+        // D can only be inserted by moving two entries
+        assert_matches!(
+            hash.insert(&[0, 0, b'D'], b"d"),
+            Err(LevelInsertionError::InsertionFailure(_))
+        );
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Recorded::Movement(MovementKind::SameLevel, false),
+                Recorded::Movement(MovementKind::SameLevel, false),
+                Recorded::InsertionFailed,
+            ]
+        );
+
+        events.lock().unwrap().clear();
+        hash.expand().unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], Recorded::ExpansionStarted(2));
+        assert!(events.contains(&Recorded::Resized(IndexFile::Keymap)));
+        assert_eq!(events.last(), Some(&Recorded::ExpansionFinished(3, true)));
+    }
+
+    #[test]
+    fn write_budget_exceeded() {
+        let dir_path = "target/tests/level-hash/observer-write-budget";
+        let index_dir = Path::new(dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut hash = LevelHash::options()
+            .index_dir(index_dir)
+            .index_name("write-budget")
+            .write_budget(1024)
+            .observer(move |event: &LevelEvent<'_>| {
+                if let LevelEvent::WriteBudgetExceeded { written, budget } = event {
+                    assert_eq!(*budget, 1024);
+                    recorded.lock().unwrap().push(*written);
+                }
+            })
+            .build()
+            .expect("Failed to create level hash");
+
+        hash.insert(b"key", b"value").unwrap();
+        assert!(events.lock().unwrap().is_empty());
+
+        let value = [7u8; 1500];
+        hash.insert(b"large", &value).unwrap();
+        let written = hash.write_stats().total_bytes();
+        assert!(written >= 1500);
+        assert_eq!(*events.lock().unwrap(), vec![written]);
+
+        // the next notification is after the next multiple of the budget
+        hash.insert(b"other", b"value").unwrap();
+        assert_eq!(events.lock().unwrap().len(), 1);
+        hash.insert(b"another", &value).unwrap();
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,83 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::reprs::Occupancy;
+use crate::types::LevelCountT;
+use crate::types::_LevelIdxT;
+use crate::LEVEL_COUNT_MAX;
+
+/// The number of occupied slots in each level of a level hash. The occupancy is stored in the meta
+/// file, so that the load factor of an existing index is known without counting its slots. The
+/// entries in the stash are not counted, they are passed with a level index past the last level
+/// and ignored.
+impl Occupancy {
+    /// Whether the counts reflect the keymap. The occupancy of the indexes created before it was
+    /// stored must be counted when the index is opened.
+    pub fn is_valid(&self) -> bool {
+        self.valid != 0
+    }
+
+    /// Get the number of occupied slots in the given level.
+    #[inline]
+    pub fn level(&self, level: _LevelIdxT) -> u32 {
+        self.counts.get(level as usize).copied().unwrap_or(0)
+    }
+
+    /// Get the number of occupied slots in all the levels.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().map(|count| *count as u64).sum()
+    }
+
+    /// Get the load factor for the given number of slots.
+    pub fn load_factor(&self, slots: u64) -> f32 {
+        (self.total() as f64 / slots as f64) as f32
+    }
+
+    /// Record an entry inserted in the given level.
+    #[inline]
+    pub fn increment(&mut self, level: _LevelIdxT) {
+        if let Some(count) = self.counts.get_mut(level as usize) {
+            *count += 1;
+        }
+    }
+
+    /// Record an entry removed from the given level.
+    #[inline]
+    pub fn decrement(&mut self, level: _LevelIdxT) {
+        if let Some(count) = self.counts.get_mut(level as usize) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Move the counts of the levels one level down after the level hash is expanded. The count of
+    /// the bottom level is discarded, since its entries are moved to the new top level.
+    ///
+    /// ## Parameters
+    ///
+    /// * `level_count` - The number of levels in the level hash.
+    /// * `top_count` - The number of occupied slots in the new top level.
+    pub fn shift_down(&mut self, level_count: LevelCountT, top_count: u32) {
+        self.counts.copy_within(0..level_count as usize - 1, 1);
+        self.counts[0] = top_count;
+    }
+
+    /// Replace the counts of all the levels, marking the occupancy as valid.
+    pub fn set(&mut self, counts: [u32; LEVEL_COUNT_MAX as usize]) {
+        self.counts = counts;
+        self.valid = 1;
+    }
+}
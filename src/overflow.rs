@@ -0,0 +1,125 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fs::File;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::fs::fallocate_safe_punch;
+use crate::fs::ftruncate_safe;
+use crate::fs::init_sparse_file;
+use crate::result::IntoLevelIOErr;
+use crate::result::IntoLevelInitErr;
+use crate::result::LevelInitError;
+use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::size::SIZE_U64;
+use crate::types::OffT;
+
+/// The overflow file stores the values which are too large to be stored inline in the values
+/// file. Unlike the values file, the overflow file is not memory-mapped. The values are read and
+/// written with positional I/O so that huge values do not need to be mapped in memory.
+#[derive(Debug)]
+pub(crate) struct OverflowFile {
+    file: File,
+}
+
+impl OverflowFile {
+    /// Magic number that is used as the file signature to identify the overflow file.
+    pub const MAGIC_NUMBER: u64 = 0x414944584F;
+
+    /// The size of the header (bytes) in the overflow file.
+    pub const HEADER_SIZE_BYTES: u64 = SIZE_U64;
+
+    /// Open (or create) the overflow file at the given path.
+    pub fn new(path: &Path) -> LevelResult<Self, LevelInitError> {
+        init_sparse_file(path, Some(Self::MAGIC_NUMBER))?;
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(path)
+            .into_lvl_io_e_msg(format!("failed to open file: {}", path.display()))
+            .into_lvl_init_err()?;
+
+        Ok(Self::from_file(file))
+    }
+
+    /// Create a new [OverflowFile] from the given (already opened) file.
+    pub fn from_file(file: File) -> Self {
+        Self { file }
+    }
+
+    #[inline]
+    fn real_offset(off: OffT) -> OffT {
+        Self::HEADER_SIZE_BYTES + off
+    }
+
+    /// Write the given data at the given (0-based) offset in the overflow file.
+    pub fn write_at(&self, off: OffT, data: &[u8]) -> LevelResult<(), StdIOError> {
+        self.file
+            .write_all_at(data, Self::real_offset(off))
+            .into_lvl_io_e_msg(format!(
+                "failed to write {} bytes to overflow file",
+                data.len()
+            ))
+    }
+
+    /// Read `len` bytes at the given (0-based) offset in the overflow file.
+    pub fn read_at(&self, off: OffT, len: OffT) -> LevelResult<Vec<u8>, StdIOError> {
+        let mut data = vec![0u8; len as usize];
+        self.read_into(off, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read the bytes at the given (0-based) offset in the overflow file into the given buffer,
+    /// filling it.
+    pub fn read_into(&self, off: OffT, buf: &mut [u8]) -> LevelResult<(), StdIOError> {
+        self.file
+            .read_exact_at(buf, Self::real_offset(off))
+            .into_lvl_io_e_msg(format!(
+                "failed to read {} bytes from overflow file",
+                buf.len()
+            ))
+    }
+
+    /// Punch a hole in the region of the overflow file at the given (0-based) offset.
+    #[inline]
+    pub fn deallocate(&self, off: OffT, len: OffT) -> LevelResult<(), StdIOError> {
+        fallocate_safe_punch(self.file.as_raw_fd(), Self::real_offset(off), len)
+    }
+
+    /// Get the descriptor of the overflow file.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+
+    /// Write the changes made to the overflow file to the disk.
+    pub fn sync(&self) -> LevelResult<(), StdIOError> {
+        self.file
+            .sync_data()
+            .into_lvl_io_e_msg("failed to sync overflow file".to_string())
+    }
+
+    /// Remove all the values from the overflow file.
+    pub fn clear(&self) -> LevelResult<(), StdIOError> {
+        ftruncate_safe(self.file.as_raw_fd(), Self::HEADER_SIZE_BYTES)
+    }
+}
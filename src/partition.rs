@@ -0,0 +1,150 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The cursors which scan disjoint ranges of the buckets of a level hash, as returned by
+//! [LevelHash::partitions](crate::LevelHash::partitions).
+
+use std::ops::Range;
+
+use crate::level_io::LevelHashIO;
+use crate::level_io::ValEntryReadExt;
+use crate::level_io::ValuesEntry;
+use crate::log_macros::log_error;
+use crate::types::OffT;
+
+/// The layout of the slots of the keymap, read from the meta file when the partitions are created
+/// so that the cursors do not read the meta file while they scan the slots.
+///
+/// # Properties
+///
+/// * `levels`: The address and the number of buckets of each level, from the top level.
+/// * `bucket_bytes`: The size (bytes) of a bucket in the keymap, including its padding.
+/// * `bucket_size`: The number of slots in each bucket.
+/// * `stash_slots`: The number of slots of the stash, which is scanned as the last bucket.
+#[derive(Debug, Clone)]
+pub(crate) struct KeymapLayout {
+    pub levels: Vec<(OffT, u32)>,
+    pub bucket_bytes: OffT,
+    pub bucket_size: u32,
+    pub stash_slots: u32,
+}
+
+impl KeymapLayout {
+    /// Get the number of buckets scanned by the cursors, including the stash (if any).
+    pub fn bucket_count(&self) -> u32 {
+        let buckets = self.levels.iter().map(|(_, buckets)| buckets).sum::<u32>();
+        buckets + u32::from(self.stash_slots > 0)
+    }
+
+    /// Get the address of the first slot and the number of slots of the bucket with the given
+    /// index, the buckets being numbered from the first bucket of the top level.
+    fn bucket(&self, index: u32) -> (OffT, u32) {
+        let mut index = index;
+        for &(addr, buckets) in &self.levels {
+            if index < buckets {
+                return (addr + self.bucket_bytes * index as OffT, self.bucket_size);
+            }
+            index -= buckets;
+        }
+
+        (0, self.stash_slots)
+    }
+}
+
+/// A cursor which iterates over the entries of a disjoint range of the buckets of a level hash.
+/// The cursors returned by [LevelHash::partitions](crate::LevelHash::partitions) can be moved to
+/// other threads to scan the level hash in parallel. The expired entries are skipped, and only
+/// the latest entry of the keys which are not unique is visited.
+pub struct PartitionCursor<'a> {
+    io: &'a LevelHashIO,
+    layout: KeymapLayout,
+    buckets: Range<u32>,
+    slot: u32,
+}
+
+// the cursors only read the keymap, the values and the overflow files, which are not modified
+// while the level hash is borrowed by the cursors
+unsafe impl Send for PartitionCursor<'_> {}
+
+impl<'a> PartitionCursor<'a> {
+    pub(crate) fn new(io: &'a LevelHashIO, layout: KeymapLayout, buckets: Range<u32>) -> Self {
+        Self {
+            io,
+            layout,
+            buckets,
+            slot: 0,
+        }
+    }
+
+    /// Get the range of the buckets which are left to be scanned by this cursor. The buckets are
+    /// numbered level by level from the first bucket of the top level, and the stash (if any) is
+    /// the last bucket.
+    pub fn buckets(&self) -> Range<u32> {
+        self.buckets.clone()
+    }
+
+    /// Restrict the cursor to the given range of its buckets, e.g. to scan the buckets in batches.
+    #[cfg(feature = "jni")]
+    pub(crate) fn take_buckets(mut self, buckets: Range<u32>) -> Self {
+        self.buckets = buckets.start.max(self.buckets.start)..buckets.end.min(self.buckets.end);
+        self.slot = 0;
+        self
+    }
+
+    /// Read the occupied entry of the slot at the given address, if it has not expired.
+    fn read_slot(&self, slot_addr: OffT) -> Option<ValuesEntry<'a>> {
+        let addr = match self.io.km_read_addr(slot_addr) {
+            Ok(addr) if addr > LevelHashIO::POS_INVALID => addr,
+            Ok(_) => return None,
+            Err(why) => {
+                log_error!("slot {}: {:?}", slot_addr, why);
+                return None;
+            }
+        };
+
+        let entry = ValuesEntry::at(addr - 1, &self.io.values);
+        if let Err(why) = self.io.values.check_bounds(entry.addr, entry.esize()) {
+            log_error!("slot {}: {:?}", slot_addr, why);
+            return None;
+        }
+
+        (!entry.is_empty() && !self.io.is_expired(&entry)).then_some(entry)
+    }
+}
+
+impl Iterator for PartitionCursor<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.buckets.is_empty() {
+            let (addr, slots) = self.layout.bucket(self.buckets.start);
+            if self.slot >= slots {
+                self.buckets.start += 1;
+                self.slot = 0;
+                continue;
+            }
+
+            let slot_addr = addr + LevelHashIO::KEYMAP_ENTRY_SIZE_BYTES * self.slot as OffT;
+            self.slot += 1;
+            if let Some(entry) = self.read_slot(slot_addr) {
+                return Some((self.io.entry_key(&entry), self.io.entry_value(&entry)));
+            }
+        }
+
+        None
+    }
+}
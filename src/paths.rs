@@ -0,0 +1,136 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The placement and the names of the files of an index. By default, the files of an index named
+//! `{name}` are stored in the index directory as `{name}.index` (the values file),
+//! `{name}.index._meta`, `{name}.index._keymap`, `{name}.index._overflow`, `{name}.index._bloom`,
+//! `{name}.index._slots` and `{name}.index.lock`. A custom [Paths] set with
+//! [LevelHashOptions::paths](crate::LevelHashOptions::paths) can place the files elsewhere, e.g.
+//! with other extensions, in subdirectories, or on separate devices.
+
+use std::fmt::Debug;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::level_io::LevelHashIO;
+
+/// The strategy which names the files of an index. Each method gets the path of a file from the
+/// index directory and the name of the index, and defaults to the path in the default layout, so
+/// that only the files to be placed elsewhere have to be overridden. The parent directories of the
+/// files are created when the index is created.
+///
+/// The paths must be the same every time the index is opened, and must be distinct for the
+/// indexes which are open at the same time. The segments of a segmented values file are named
+/// after the values file. The snapshots, the archives and the backups made by the migrations are
+/// always written in the default layout.
+pub trait Paths: Debug + Send + Sync {
+    /// Get the path of the values file.
+    fn values_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, "")
+    }
+
+    /// Get the path of the meta file.
+    fn meta_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, LevelHashIO::LEVEL_META_EXT)
+    }
+
+    /// Get the path of the keymap file.
+    fn keymap_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, LevelHashIO::LEVEL_KEYMAP_EXT)
+    }
+
+    /// Get the path of the overflow file, which stores the large values.
+    fn overflow_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, LevelHashIO::LEVEL_OVERFLOW_EXT)
+    }
+
+    /// Get the path of the bloom filter file.
+    fn bloom_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, LevelHashIO::LEVEL_BLOOM_EXT)
+    }
+
+    /// Get the path of the file to which the bitmap of the occupied slots is saved when the index
+    /// is closed.
+    fn slots_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, LevelHashIO::LEVEL_SLOTS_EXT)
+    }
+
+    /// Get the path of the lock file.
+    fn lock_file(&self, index_dir: &Path, index_name: &str) -> PathBuf {
+        default_path(index_dir, index_name, LevelHashIO::LEVEL_LOCK_EXT)
+    }
+}
+
+/// The default layout of the index files, where all the files are stored in the index directory.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultPaths;
+
+impl Paths for DefaultPaths {}
+
+/// Get the path of the index file with the given extension in the default layout.
+fn default_path(index_dir: &Path, index_name: &str, ext: &str) -> PathBuf {
+    index_dir.join(format!(
+        "{}{}{}",
+        index_name,
+        LevelHashIO::LEVEL_INDEX_EXT,
+        ext
+    ))
+}
+
+/// The paths of the files of an index, resolved with a [Paths].
+#[derive(Debug, Clone)]
+pub(crate) struct IndexFiles {
+    pub values: PathBuf,
+    pub meta: PathBuf,
+    pub keymap: PathBuf,
+    pub overflow: PathBuf,
+    pub bloom: PathBuf,
+    pub slots: PathBuf,
+    pub lock: PathBuf,
+}
+
+impl IndexFiles {
+    /// Resolve the paths of the files of the given index.
+    pub fn new(paths: &dyn Paths, index_dir: &Path, index_name: &str) -> Self {
+        Self {
+            values: paths.values_file(index_dir, index_name),
+            meta: paths.meta_file(index_dir, index_name),
+            keymap: paths.keymap_file(index_dir, index_name),
+            overflow: paths.overflow_file(index_dir, index_name),
+            bloom: paths.bloom_file(index_dir, index_name),
+            slots: paths.slots_file(index_dir, index_name),
+            lock: paths.lock_file(index_dir, index_name),
+        }
+    }
+
+    /// Resolve the paths of the files of the given index in the default layout.
+    pub fn default_layout(index_dir: &Path, index_name: &str) -> Self {
+        Self::new(&DefaultPaths, index_dir, index_name)
+    }
+
+    /// Get the paths of the files, except the lock file and the segments of the values file.
+    pub fn data_files(&self) -> [&Path; 6] {
+        [
+            &self.values,
+            &self.meta,
+            &self.keymap,
+            &self.overflow,
+            &self.bloom,
+            &self.slots,
+        ]
+    }
+}
@@ -22,8 +22,10 @@
 //! - All `struct`s must have `#[repr(C)]`
 
 use crate::types::BucketSizeT;
+use crate::types::LevelCountT;
 use crate::types::LevelSizeT;
 use crate::types::OffT;
+use crate::LEVEL_COUNT_MAX;
 
 macro_rules! def_layout {
     (struct $name:ident {
@@ -77,6 +79,84 @@ def_layout!(
         km_bucket_size: BucketSizeT,
         km_l0_addr: OffT,
         km_l1_addr: OffT,
+        ovf_next_addr: OffT,
+        // addresses of the levels below L1, only used when the level hash
+        // has more than two levels
+        km_l2_addr: OffT,
+        km_l3_addr: OffT,
+        // 0 for the indexes created before the level count was configurable,
+        // which always have two levels
+        km_level_count: LevelCountT,
+        // 1 if val_fingerprint reflects the entries in the values file, 0 for
+        // the indexes created before the fingerprint was maintained
+        val_fingerprint_valid: u8,
+        // wrapping sum of the fingerprints of the live entries
+        val_fingerprint: u64,
+        // the size by which the values file grows, 0 for the indexes created
+        // before the size was configurable
+        val_block_size: OffT,
+        // 1 if entries with an expiry timestamp have been written to the
+        // values file, such indexes can only be opened with the `ttl` feature
+        val_expiry: u8,
+        // the number of occupied slots in each level
+        km_occupancy: Occupancy,
+        // incremented before and after every structural change of the keymap
+        // (expansion, shrinking, clearing, repair), so it is odd while such a
+        // change is in progress
+        km_generation: u64,
+        // 1 if entries with a compressed value have been written to the
+        // values file, such indexes can only be opened with the `compression`
+        // feature
+        val_compressed: u8,
+        // the alignment (bytes) of the buckets in the keymap, relative to the
+        // start of the keymap file, 0 if the buckets are not aligned
+        km_bucket_align: u32,
+        // the address of the interim level of an incremental expansion in
+        // the keymap, 0 if no incremental expansion is in progress
+        km_interim_addr: OffT,
+        // the number of buckets of the bottom level which have been rehashed
+        // to the interim level by the incremental expansion
+        km_rehash_next: u32,
+        // the number of bytes stored in each segment file of the values
+        // file, 0 if the values file is not segmented
+        val_segment_size: u64,
+        // the seeds of the hash functions, 0 for the indexes created before
+        // the seeds were stored in the meta file
+        km_seed_1: u64,
+        km_seed_2: u64,
+        // the hashes of a fixed key computed with the hash functions and the
+        // seeds of the index, to detect other hash functions on open
+        km_hash_check: u64,
+        // the hash function which is used if no hash functions are set, 0
+        // for xxh64 (the indexes created before SipHash was the default)
+        // and 1 for SipHash-1-3
+        km_default_hash: u8,
+        // the number of slots in the stash at the start of the keymap, 0 if
+        // the index has no stash
+        km_stash_slots: u32,
+        // the total size (bytes) of the entries in the used region of the
+        // values file, the rest of the used region is garbage. This is
+        // recomputed when the index is opened
+        val_live_bytes: OffT,
+        // the maximum sizes (bytes) of the keys and the values which can be
+        // inserted, 0 for the indexes created before the limits were stored
+        // in the meta file
+        val_max_key_size: u64,
+        val_max_value_size: u64,
+        // incremented for every entry written to the values file and for
+        // every value mutated in place, the versions of the entries are
+        // the values of this counter
+        val_write_counter: u64,
+    }
+);
+
+def_layout!(
+    struct Occupancy {
+        // 1 if the counts reflect the keymap, 0 for the indexes created
+        // before the occupancy was stored in the meta file
+        valid: u32,
+        // the number of occupied slots in each level, top level first
+        counts: [u32; LEVEL_COUNT_MAX as usize],
     }
 );
 
@@ -93,9 +173,21 @@ def_layout!(
         // sizeof(key_size) = 4
         // sizeof(value_size) = 4
         // entry_size = 4 + 6 + 4 + 4 = 18 bytes
+        // the fourth most significant bit of key_size is set if the key has been
+        // spilled to the overflow file, in which case the key bytes of the entry
+        // contain the offset (u64), the length (u64) and the hash (u64) of the
+        // key in the overflow file instead of the key itself
         key_size: u32,
+        // the most significant bit of value_size is set if the value has been
+        // spilled to the overflow file, in which case the value bytes of the
+        // entry contain the offset (u64) and the length (u64) of the value in
+        // the overflow file instead of the value itself
+        // the second most significant bit of value_size is set if the value
+        // (inline or spilled) has been compressed with LZ4
         value_size: u32,
         // key_size bytes of key
         // value_size bytes of value
+        // u64 expiry timestamp, if the second most significant bit of key_size is set
+        // u32 checksum, if the most significant bit of key_size is set
     }
 );
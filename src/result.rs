@@ -15,9 +15,17 @@
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::error::Error;
 use std::fmt::Display;
+use std::fmt::Formatter;
+use std::path::PathBuf;
 
+use crate::fsck::Inconsistency;
+use crate::trace::ReplayReport;
+use crate::types::OffT;
+use crate::Level;
 use crate::LevelHash;
+use crate::LockOwner;
 
 pub type LevelResult<T, E> = Result<T, E>;
 
@@ -27,12 +35,32 @@ pub type LevelInsertionResult = LevelResult<(), LevelInsertionError>;
 
 pub type LevelExpansionResult = LevelResult<(), LevelExpansionError>;
 
+pub type LevelShrinkResult = LevelResult<(), LevelShrinkError>;
+
+pub type LevelCompactResult = LevelResult<u64, LevelCompactError>;
+
 pub type LevelUpdateResult = LevelResult<Vec<u8>, LevelUpdateError>;
 
+pub type LevelReadResult = LevelResult<Option<Vec<u8>>, LevelReadError>;
+
 pub type LevelRemapResult = Result<(), LevelMapError>;
 
 pub type LevelClearResult = LevelRemapResult;
 
+pub type LevelSyncResult = LevelResult<(), StdIOError>;
+
+pub type LevelReplayResult = LevelResult<(LevelHash, ReplayReport), LevelReplayError>;
+
+pub type LevelImportResult = LevelResult<u64, LevelArchiveError>;
+
+pub type LevelBatchResult = LevelResult<(), LevelBatchError>;
+
+#[cfg(feature = "serde")]
+pub type LevelTypedResult<T> = LevelResult<T, LevelTypedError>;
+
+#[cfg(feature = "tokio")]
+pub type LevelAsyncResult<T> = LevelResult<T, LevelAsyncError>;
+
 /// An I/O error in level hash.
 #[derive(Debug)]
 pub struct StdIOError {
@@ -50,6 +78,11 @@ impl StdIOError {
     pub fn new(message: Option<String>, error: std::io::Error) -> Self {
         StdIOError { message, error }
     }
+
+    /// Whether the error occurred because there is no space left on the device (`ENOSPC`).
+    pub fn is_no_space(&self) -> bool {
+        self.error.raw_os_error() == Some(libc::ENOSPC)
+    }
 }
 
 impl Display for StdIOError {
@@ -65,8 +98,15 @@ impl Display for StdIOError {
     }
 }
 
+impl Error for StdIOError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 /// Level hash error enumeration.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LevelInitError {
     /// An error caused due to IO operations.
     IOError(StdIOError),
@@ -76,10 +116,67 @@ pub enum LevelInitError {
 
     /// An error caused due to invalid arguments.
     InvalidArg(String),
+
+    /// Occurs when opening an index with [LevelHash::open] which does not exist.
+    IndexNotFound {
+        /// The directory of the index.
+        index_dir: PathBuf,
+        /// The name of the index.
+        index_name: String,
+    },
+
+    /// Occurs when the index files have been written by a newer version of level hash.
+    UnsupportedVersion {
+        /// The version of the values file.
+        values: u32,
+        /// The version of the keymap file.
+        keymap: u32,
+    },
+
+    /// Occurs when a read-only level hash is opened for an index whose files must be rewritten by
+    /// a migration, e.g. an index which was written in the big-endian byte order by an older
+    /// version of level hash. The index must be opened for writing once, so that it can be
+    /// migrated.
+    MigrationRequired,
+
+    /// Occurs when the index uses a feature of level hash which is not enabled in this build,
+    /// e.g. the expiring entries (`ttl`).
+    FeatureRequired(&'static str),
+
+    /// Occurs when an entry cannot be inserted into a level hash built with
+    /// [LevelHash::bulk_load](crate::LevelHash::bulk_load).
+    InsertionError(LevelInsertionError),
+
+    /// Occurs when an index file has a bad magic number, or when an index opened with
+    /// [LevelHashOptions::open_with_recovery](crate::LevelHashOptions::open_with_recovery) is
+    /// corrupt and cannot be opened safely.
+    Corrupt(Vec<Inconsistency>),
+
+    /// Occurs when the index is opened with other seeds than the seeds it was created with. See
+    /// [LevelHashOptions::seeds](crate::LevelHashOptions::seeds).
+    SeedMismatch {
+        /// The seeds stored in the index.
+        expected: (u64, u64),
+        /// The seeds the index was opened with.
+        found: (u64, u64),
+    },
+
+    /// Occurs when the index is opened with other hash functions than the hash functions it was
+    /// created with. See [LevelHashOptions::hash_fns](crate::LevelHashOptions::hash_fns).
+    HashFnMismatch,
+
+    /// Occurs when the lock file of an index cannot be deleted with
+    /// [LevelHashOptions::force_unlock](crate::LevelHashOptions::force_unlock), because the index
+    /// is locked or the owner of the lock is still running.
+    Locked {
+        /// The owner of the lock, if it is known.
+        owner: Option<LockOwner>,
+    },
 }
 
 /// Error occured during an insertion operation in level hash.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LevelInsertionError {
     /// Occurs when an entry with a given key already exists.
     DuplicateKey,
@@ -95,16 +192,145 @@ pub enum LevelInsertionError {
 
     /// Occurs when the level hash fails to move an entry between buckets to
     /// make space for a new entry.
-    MovementFailure,
+    MovementFailure(InsertionDiagnostics),
 
     /// Occurs when an entry cannot be inserted into the level hash. There might be
     /// multiple reasons for this error to occur, but the most common one is when the
     /// level hash reaches a certain load factor (usually >0.9) and the level hash
     /// cannot be expanded further resulting in hash collisions for the given key.
-    InsertionFailure,
+    InsertionFailure(InsertionDiagnostics),
+
+    /// Occurs when the key is empty. Entries with empty keys are not supported, as an entry with an
+    /// empty key and an empty value cannot be distinguished from a deleted entry.
+    EmptyKey,
+
+    /// Occurs when the size of the key is larger than the maximum key size configured
+    /// with [LevelHashOptions::max_key_size][crate::LevelHashOptions::max_key_size].
+    KeyTooLarge {
+        /// The size of the key, in bytes.
+        size: u64,
+        /// The maximum allowed size of a key, in bytes.
+        max: u64,
+    },
+
+    /// Occurs when the size of the value is larger than the maximum value size configured
+    /// with [LevelHashOptions::max_value_size][crate::LevelHashOptions::max_value_size].
+    ValueTooLarge {
+        /// The size of the value, in bytes.
+        size: u64,
+        /// The maximum allowed size of a value, in bytes.
+        max: u64,
+    },
+
+    /// Occurs when there is an I/O error writing the value to the overflow file.
+    IOError(StdIOError),
+
+    /// Occurs when the slots probed for the entry cannot be read because the index files are
+    /// corrupt.
+    ReadError(LevelReadError),
+
+    /// Occurs when the level hash holds a shared lock on the index. See
+    /// [LevelHashOptions::read_only][crate::LevelHashOptions::read_only].
+    ReadOnly,
+
+    /// Occurs when the entry cannot be stored because a limit of the format of the index files
+    /// would be exceeded, e.g. the values file would grow beyond the addresses which can be stored
+    /// in the keymap. See [LevelHash::capacity_info](crate::LevelHash::capacity_info).
+    CapacityExhausted(String),
+
+    /// Occurs when the index files cannot grow because there is no space left on the device. The
+    /// index is left as it was before the insertion, which can be retried once some space has been
+    /// freed.
+    NoSpace(StdIOError),
+}
+
+/// The state of the level hash when an entry could not be inserted, attached to
+/// [LevelInsertionError::InsertionFailure] and [LevelInsertionError::MovementFailure].
+///
+/// The diagnostics are collected only for the errors returned from the public API of the level
+/// hash, the diagnostics are empty (default) otherwise.
+///
+/// # Properties
+///
+/// * `candidate_buckets`: The buckets in which the entry could have been inserted, in the order
+///   they were probed.
+/// * `load_factor`: The load factor of the level hash when the insertion failed.
+/// * `expand_count`: The number of times the level hash was expanded since it was opened.
+/// * `expansion_attempted`: Whether the level hash was expanded automatically before the entry
+///   was inserted.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InsertionDiagnostics {
+    pub candidate_buckets: Vec<CandidateBucket>,
+    pub load_factor: f32,
+    pub expand_count: u32,
+    pub expansion_attempted: bool,
+}
+
+/// A bucket in which an entry could have been inserted.
+///
+/// # Properties
+///
+/// * `level`: The level of the bucket.
+/// * `bucket`: The index of the bucket in the level.
+/// * `occupied`: The number of occupied slots in the bucket.
+/// * `slots`: The total number of slots in the bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CandidateBucket {
+    pub level: Level,
+    pub bucket: u32,
+    pub occupied: u32,
+    pub slots: u32,
+}
+
+/// Error occured while reading an entry from the level hash.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelReadError {
+    /// Occurs when the checksum stored in an entry does not match the contents of the entry. The
+    /// entry has been corrupted on the storage and its key and value cannot be trusted.
+    CorruptEntry {
+        /// The address of the entry in the values file.
+        addr: OffT,
+        /// The checksum stored in the entry.
+        expected: u32,
+        /// The checksum computed from the contents of the entry.
+        actual: u32,
+    },
+
+    /// Occurs when an address read from the index files points outside of the file it refers to,
+    /// or is not aligned like the entries in the values file. The index files have been corrupted
+    /// and the index must be rebuilt.
+    CorruptIndex {
+        /// The address of the region which was accessed.
+        addr: OffT,
+        /// The size of the region which was accessed.
+        len: OffT,
+        /// The size of the mapped region of the file.
+        size: OffT,
+    },
+
+    /// Occurs when the keymap was changed structurally (e.g. expanded or cleared) while the entry
+    /// was being read. The read may have observed the interim state of the change, and must be
+    /// retried.
+    ConcurrentModification {
+        /// The generation of the keymap when the read started.
+        generation: u64,
+    },
+
+    /// Occurs when a value streamed with [LevelHash::read_value_into] cannot be read from the
+    /// overflow file or written to the writer.
+    IOError(StdIOError),
+}
+
+impl LevelReadError {
+    /// Whether the read which failed with this error can be retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, LevelReadError::ConcurrentModification { .. })
+    }
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LevelUpdateError {
     /// Error indicating that the slot for the given key was not found.
     SlotNotFound,
@@ -117,9 +343,26 @@ pub enum LevelUpdateError {
 
     /// Error indicating there was an error inserting the new value to the entry.
     InsertionErr(LevelInsertionError),
+
+    /// Error indicating the entry being updated cannot be read because the index files are
+    /// corrupt.
+    ReadError(LevelReadError),
+
+    /// Error indicating the level hash holds a shared lock on the index.
+    ReadOnly,
+
+    /// Error indicating the value of the entry is not the expected value. The error carries the
+    /// current value of the entry.
+    ValueMismatch(Vec<u8>),
+
+    /// Error indicating the [SlotHandle](crate::SlotHandle) no longer refers to the entry it was
+    /// returned for, e.g. because the entry was moved, replaced or removed. The entry must be
+    /// looked up again with its key.
+    StaleHandle,
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LevelExpansionError {
     /// Occurs when the level hash already has the maximum level size and cannot be expanded further
     MaxLevelSizeReached,
@@ -133,12 +376,553 @@ pub enum LevelExpansionError {
     /// Occurs when trying to expand the level hash while another hash-level operation is in progress.
     /// This hash-level operation can be another expand operation or the clear operation.
     ConcurrentModificationError,
+
+    /// Occurs when the level hash holds a shared lock on the index.
+    ReadOnly,
+
+    /// Occurs when the [CancellationToken](crate::cancel::CancellationToken) of
+    /// [LevelHash::expand_cancellable] is cancelled. The rehashed buckets are kept and the
+    /// expansion stays in progress until the level hash is closed, so it can be resumed.
+    Cancelled,
+}
+
+/// Error occured while shrinking the level hash with [LevelHash::shrink].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelShrinkError {
+    /// Occurs when the level hash already has the minimum level size for its level count and
+    /// cannot be shrunk further.
+    MinLevelSizeReached,
+
+    /// Occurs when the level hash has too many entries to be shrunk. The load factor after
+    /// shrinking would exceed the load factor threshold of the expand policy (or `1.0` if the
+    /// policy does not expand based on the load factor).
+    LoadFactorTooHigh {
+        /// The load factor the level hash would have after shrinking.
+        load_factor: f32,
+    },
+
+    /// Occurs when an entry in the top level cannot be moved to any of its buckets in the
+    /// remaining levels. The level hash is not modified in this case.
+    InsertionFailure,
+
+    /// Occurs when the keymap file cannot be resized.
+    MmapError(LevelMapError),
+
+    /// Occurs when the level hash holds a shared lock on the index.
+    ReadOnly,
+}
+
+/// Error occured while compacting the values file with [LevelHash::compact].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelCompactError {
+    /// Occurs when the region after the compacted entries cannot be deallocated, or the changes
+    /// cannot be synced.
+    IOError(StdIOError),
+
+    /// Occurs when the level hash holds a shared lock on the index.
+    ReadOnly,
+}
+
+/// Error occured during an operation on a [TypedLevelHash][crate::typed::TypedLevelHash].
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelTypedError {
+    /// Occurs when a key or a value cannot be encoded or decoded.
+    CodecError(bincode::Error),
+
+    /// Occurs when the encoded entry cannot be inserted into the level hash.
+    InsertionError(LevelInsertionError),
+
+    /// Occurs when the encoded entry cannot be updated in the level hash.
+    UpdateError(LevelUpdateError),
+
+    /// Occurs when the encoded entry cannot be read from the level hash.
+    ReadError(LevelReadError),
+}
+
+/// Error occured during an operation on an [AsyncLevelHash][crate::async_hash::AsyncLevelHash].
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelAsyncError {
+    /// Occurs when the blocking task which performs the operation panics or is cancelled.
+    TaskError(tokio::task::JoinError),
+
+    /// Occurs when the entry cannot be inserted into the level hash.
+    InsertionError(LevelInsertionError),
+
+    /// Occurs when the entry cannot be updated in the level hash.
+    UpdateError(LevelUpdateError),
+}
+
+/// Error occured while replaying a trace with [replay][crate::trace::replay].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelReplayError {
+    /// Occurs when the trace file cannot be read.
+    IOError(StdIOError),
+
+    /// Occurs when the trace file is not a valid trace.
+    InvalidTrace(String),
+
+    /// Occurs when the level hash to replay the trace cannot be created.
+    InitError(LevelInitError),
+}
+
+/// Error occured while importing an archive with [LevelHash::import].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelArchiveError {
+    /// Occurs when the archive file cannot be read.
+    IOError(StdIOError),
+
+    /// Occurs when the archive file is not a valid archive.
+    InvalidArchive(String),
+
+    /// Occurs when an entry of the archive cannot be written to the level hash.
+    UpdateError(LevelUpdateError),
+}
+
+/// Error occured while committing a [WriteBatch][crate::batch::WriteBatch]. The operations which
+/// were applied before the failed operation have been rolled back.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LevelBatchError {
+    /// Occurs when an insertion in the batch fails.
+    InsertionError {
+        /// The index of the insertion in the batch.
+        index: usize,
+        /// The error of the insertion.
+        error: LevelInsertionError,
+    },
+
+    /// Occurs when an update in the batch fails.
+    UpdateError {
+        /// The index of the update in the batch.
+        index: usize,
+        /// The error of the update.
+        error: LevelUpdateError,
+    },
+
+    /// Occurs when the changes cannot be synced after all the operations have been applied. The
+    /// changes are not rolled back in this case.
+    IOError(StdIOError),
+
+    /// Occurs when the level hash holds a shared lock on the index.
+    ReadOnly,
 }
 
 /// Error occured during memory-mapping a file.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LevelMapError {
     IOError(StdIOError),
+
+    /// Occurs when the file cannot be truncated (or extended) to the size which is about to be
+    /// mapped. Accessing the mapped region beyond the end of the file would raise `SIGBUS`.
+    TruncateFailed(StdIOError),
+}
+
+impl Display for LevelInitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelInitError::IOError(_) => write!(f, "failed to open the index files"),
+            LevelInitError::MmapError(_) => write!(f, "failed to map the index files"),
+            LevelInitError::InvalidArg(message) => write!(f, "invalid argument: {}", message),
+            LevelInitError::IndexNotFound {
+                index_dir,
+                index_name,
+            } => write!(
+                f,
+                "index '{}' not found in {}",
+                index_name,
+                index_dir.display()
+            ),
+            LevelInitError::UnsupportedVersion { values, keymap } => write!(
+                f,
+                "unsupported index version (values: {}, keymap: {})",
+                values, keymap
+            ),
+            LevelInitError::MigrationRequired => write!(
+                f,
+                "the index must be opened for writing once to migrate it to the current format"
+            ),
+            LevelInitError::FeatureRequired(feature) => {
+                write!(f, "the index requires the '{}' feature", feature)
+            }
+            LevelInitError::InsertionError(_) => write!(f, "failed to insert an entry"),
+            LevelInitError::Corrupt(inconsistencies) => write!(
+                f,
+                "the index is corrupt ({} inconsistencies)",
+                inconsistencies.len()
+            ),
+            LevelInitError::SeedMismatch { expected, found } => write!(
+                f,
+                "the index was created with the seeds {:?}, but opened with {:?}",
+                expected, found
+            ),
+            LevelInitError::HashFnMismatch => write!(
+                f,
+                "the index was created with other hash functions than the given hash functions"
+            ),
+            LevelInitError::Locked { owner: Some(owner) } => {
+                write!(f, "the index is locked by the process {}", owner.pid)
+            }
+            LevelInitError::Locked { owner: None } => write!(f, "the index is locked"),
+        }
+    }
+}
+
+impl Error for LevelInitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelInitError::IOError(e) => Some(e),
+            LevelInitError::MmapError(e) => Some(e),
+            LevelInitError::InsertionError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LevelInsertionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelInsertionError::DuplicateKey => write!(f, "an entry with the key already exists"),
+            LevelInsertionError::ExpansionFailure(_) => {
+                write!(f, "failed to expand the level hash")
+            }
+            LevelInsertionError::LevelOverflow => write!(f, "the level hash is full"),
+            LevelInsertionError::MmapError(_) => write!(f, "failed to resize the index files"),
+            LevelInsertionError::MovementFailure(diagnostics) => write!(
+                f,
+                "failed to move the entries to make room for the entry (load factor: {:.2})",
+                diagnostics.load_factor
+            ),
+            LevelInsertionError::InsertionFailure(diagnostics) => write!(
+                f,
+                "no free slot found for the entry (load factor: {:.2})",
+                diagnostics.load_factor
+            ),
+            LevelInsertionError::EmptyKey => write!(f, "the key is empty"),
+            LevelInsertionError::KeyTooLarge { size, max } => write!(
+                f,
+                "the key is too large ({} bytes, the maximum is {} bytes)",
+                size, max
+            ),
+            LevelInsertionError::ValueTooLarge { size, max } => write!(
+                f,
+                "the value is too large ({} bytes, the maximum is {} bytes)",
+                size, max
+            ),
+            LevelInsertionError::IOError(_) => write!(f, "failed to write the overflow file"),
+            LevelInsertionError::ReadError(_) => write!(f, "failed to read the slots of the key"),
+            LevelInsertionError::ReadOnly => write!(f, "the level hash is read-only"),
+            LevelInsertionError::CapacityExhausted(message) => {
+                write!(f, "the capacity of the index is exhausted: {}", message)
+            }
+            LevelInsertionError::NoSpace(_) => write!(f, "no space left on the device"),
+        }
+    }
+}
+
+impl Error for LevelInsertionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelInsertionError::ExpansionFailure(e) => Some(e.as_ref()),
+            LevelInsertionError::MmapError(e) => Some(e),
+            LevelInsertionError::IOError(e) => Some(e),
+            LevelInsertionError::ReadError(e) => Some(e),
+            LevelInsertionError::NoSpace(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LevelReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelReadError::CorruptEntry {
+                addr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "corrupt entry at {}: checksum {:#x}, expected {:#x}",
+                addr, actual, expected
+            ),
+            LevelReadError::CorruptIndex { addr, len, size } => write!(
+                f,
+                "corrupt index: {} bytes at {} are outside of the mapped {} bytes",
+                len, addr, size
+            ),
+            LevelReadError::ConcurrentModification { generation } => write!(
+                f,
+                "the keymap was modified while reading (generation {})",
+                generation
+            ),
+            LevelReadError::IOError(_) => write!(f, "failed to stream the value"),
+        }
+    }
+}
+
+impl Error for LevelReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelReadError::IOError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LevelUpdateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelUpdateError::SlotNotFound => write!(f, "no entry found for the key"),
+            LevelUpdateError::SlotEmpty => write!(f, "the slot of the entry is empty"),
+            LevelUpdateError::EntryNotOccupied => write!(f, "the entry is not occupied"),
+            LevelUpdateError::InsertionErr(_) => write!(f, "failed to write the new value"),
+            LevelUpdateError::ReadError(_) => write!(f, "failed to read the entry"),
+            LevelUpdateError::ReadOnly => write!(f, "the level hash is read-only"),
+            LevelUpdateError::ValueMismatch(_) => {
+                write!(f, "the value of the entry is not the expected value")
+            }
+            LevelUpdateError::StaleHandle => write!(f, "the slot handle is stale"),
+        }
+    }
+}
+
+impl Error for LevelUpdateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelUpdateError::InsertionErr(e) => Some(e),
+            LevelUpdateError::ReadError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LevelExpansionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelExpansionError::MaxLevelSizeReached => {
+                write!(f, "the level hash already has the maximum level size")
+            }
+            LevelExpansionError::MmapError(_) => write!(f, "failed to resize the keymap"),
+            LevelExpansionError::UpdateError(_) => write!(f, "failed to move an entry"),
+            LevelExpansionError::ConcurrentModificationError => {
+                write!(
+                    f,
+                    "another structural change of the level hash is in progress"
+                )
+            }
+            LevelExpansionError::ReadOnly => write!(f, "the level hash is read-only"),
+            LevelExpansionError::Cancelled => write!(f, "the expansion was cancelled"),
+        }
+    }
+}
+
+impl Error for LevelExpansionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelExpansionError::MmapError(e) => Some(e),
+            LevelExpansionError::UpdateError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LevelShrinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelShrinkError::MinLevelSizeReached => {
+                write!(f, "the level hash already has the minimum level size")
+            }
+            LevelShrinkError::LoadFactorTooHigh { load_factor } => write!(
+                f,
+                "the load factor after shrinking would be too high ({:.2})",
+                load_factor
+            ),
+            LevelShrinkError::InsertionFailure => {
+                write!(
+                    f,
+                    "an entry of the top level cannot be moved to the other levels"
+                )
+            }
+            LevelShrinkError::MmapError(_) => write!(f, "failed to resize the keymap"),
+            LevelShrinkError::ReadOnly => write!(f, "the level hash is read-only"),
+        }
+    }
+}
+
+impl Error for LevelShrinkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelShrinkError::MmapError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LevelCompactError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelCompactError::IOError(_) => write!(f, "failed to compact the values file"),
+            LevelCompactError::ReadOnly => write!(f, "the level hash is read-only"),
+        }
+    }
+}
+
+impl Error for LevelCompactError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelCompactError::IOError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Display for LevelTypedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelTypedError::CodecError(_) => write!(f, "failed to encode or decode the entry"),
+            LevelTypedError::InsertionError(_) => write!(f, "failed to insert the entry"),
+            LevelTypedError::UpdateError(_) => write!(f, "failed to update the entry"),
+            LevelTypedError::ReadError(_) => write!(f, "failed to read the entry"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Error for LevelTypedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelTypedError::CodecError(e) => Some(e.as_ref()),
+            LevelTypedError::InsertionError(e) => Some(e),
+            LevelTypedError::UpdateError(e) => Some(e),
+            LevelTypedError::ReadError(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Display for LevelAsyncError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelAsyncError::TaskError(_) => write!(f, "the blocking task failed"),
+            LevelAsyncError::InsertionError(_) => write!(f, "failed to insert the entry"),
+            LevelAsyncError::UpdateError(_) => write!(f, "failed to update the entry"),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Error for LevelAsyncError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelAsyncError::TaskError(e) => Some(e),
+            LevelAsyncError::InsertionError(e) => Some(e),
+            LevelAsyncError::UpdateError(e) => Some(e),
+        }
+    }
+}
+
+impl Display for LevelReplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelReplayError::IOError(_) => write!(f, "failed to read the trace"),
+            LevelReplayError::InvalidTrace(message) => write!(f, "invalid trace: {}", message),
+            LevelReplayError::InitError(_) => write!(f, "failed to create the level hash"),
+        }
+    }
+}
+
+impl Error for LevelReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelReplayError::IOError(e) => Some(e),
+            LevelReplayError::InitError(e) => Some(e),
+            LevelReplayError::InvalidTrace(_) => None,
+        }
+    }
+}
+
+impl Display for LevelArchiveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelArchiveError::IOError(_) => write!(f, "failed to read the archive"),
+            LevelArchiveError::InvalidArchive(message) => {
+                write!(f, "invalid archive: {}", message)
+            }
+            LevelArchiveError::UpdateError(_) => write!(f, "failed to write an entry"),
+        }
+    }
+}
+
+impl Error for LevelArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelArchiveError::IOError(e) => Some(e),
+            LevelArchiveError::UpdateError(e) => Some(e),
+            LevelArchiveError::InvalidArchive(_) => None,
+        }
+    }
+}
+
+impl Display for LevelBatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelBatchError::InsertionError { index, .. } => {
+                write!(f, "the insertion at index {} of the batch failed", index)
+            }
+            LevelBatchError::UpdateError { index, .. } => {
+                write!(f, "the update at index {} of the batch failed", index)
+            }
+            LevelBatchError::IOError(_) => write!(f, "failed to sync the batch"),
+            LevelBatchError::ReadOnly => write!(f, "the level hash is read-only"),
+        }
+    }
+}
+
+impl Error for LevelBatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelBatchError::InsertionError { error, .. } => Some(error),
+            LevelBatchError::UpdateError { error, .. } => Some(error),
+            LevelBatchError::IOError(e) => Some(e),
+            LevelBatchError::ReadOnly => None,
+        }
+    }
+}
+
+impl LevelMapError {
+    /// Whether the file could not be resized because there is no space left on the device.
+    pub fn is_no_space(&self) -> bool {
+        match self {
+            LevelMapError::IOError(e) | LevelMapError::TruncateFailed(e) => e.is_no_space(),
+        }
+    }
+}
+
+impl Display for LevelMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelMapError::IOError(_) => write!(f, "failed to map the file"),
+            LevelMapError::TruncateFailed(_) => write!(f, "failed to resize the file"),
+        }
+    }
+}
+
+impl Error for LevelMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LevelMapError::IOError(e) => Some(e),
+            LevelMapError::TruncateFailed(e) => Some(e),
+        }
+    }
 }
 
 pub trait IntoLevelIOErr<T> {
@@ -214,7 +998,11 @@ impl<T> IntoLevelExpErr<T> for LevelResult<T, LevelMapError> {
 
 impl<T> IntoLevelInsertionErr<T> for LevelResult<T, LevelExpansionError> {
     fn into_lvl_ins_err(self) -> LevelResult<T, LevelInsertionError> {
-        self.map_err(|e| LevelInsertionError::ExpansionFailure(Box::new(e)))
+        self.map_err(|e| match e {
+            // the expansion failed because the disk is full, which is reported as such
+            LevelExpansionError::MmapError(e) if e.is_no_space() => LevelInsertionError::from(e),
+            e => LevelInsertionError::ExpansionFailure(Box::new(e)),
+        })
     }
 }
 
@@ -232,21 +1020,106 @@ map_err!(StdIOError, LevelInitError::IOError);
 map_err!(StdIOError, LevelMapError::IOError);
 
 map_err!(LevelMapError, LevelInitError::MmapError);
-map_err!(LevelMapError, LevelInsertionError::MmapError);
+map_err!(StdIOError, LevelReadError::IOError);
 map_err!(LevelMapError, LevelExpansionError::MmapError);
+map_err!(LevelMapError, LevelShrinkError::MmapError);
+map_err!(StdIOError, LevelCompactError::IOError);
 
 map_err!(LevelInsertionError, LevelUpdateError::InsertionErr);
+map_err!(LevelReadError, LevelInsertionError::ReadError);
+map_err!(LevelReadError, LevelUpdateError::ReadError);
 
 map_err!(LevelUpdateError, LevelExpansionError::UpdateError);
 
+map_err!(StdIOError, LevelReplayError::IOError);
+map_err!(LevelInitError, LevelReplayError::InitError);
+
+map_err!(StdIOError, LevelArchiveError::IOError);
+map_err!(LevelUpdateError, LevelArchiveError::UpdateError);
+
+map_err!(StdIOError, LevelBatchError::IOError);
+
+#[cfg(feature = "serde")]
+map_err!(LevelInsertionError, LevelTypedError::InsertionError);
+#[cfg(feature = "serde")]
+map_err!(LevelUpdateError, LevelTypedError::UpdateError);
+
+#[cfg(feature = "serde")]
+map_err!(LevelReadError, LevelTypedError::ReadError);
+
+#[cfg(feature = "tokio")]
+map_err!(LevelInsertionError, LevelAsyncError::InsertionError);
+#[cfg(feature = "tokio")]
+map_err!(LevelUpdateError, LevelAsyncError::UpdateError);
+
 impl From<std::io::Error> for StdIOError {
     fn from(value: std::io::Error) -> Self {
         return Self::new(None, value);
     }
 }
 
+impl From<LevelMapError> for LevelInsertionError {
+    fn from(value: LevelMapError) -> Self {
+        match value {
+            LevelMapError::IOError(e) | LevelMapError::TruncateFailed(e) if e.is_no_space() => {
+                LevelInsertionError::NoSpace(e)
+            }
+            value => LevelInsertionError::MmapError(value),
+        }
+    }
+}
+
+impl From<StdIOError> for LevelInsertionError {
+    fn from(value: StdIOError) -> Self {
+        if value.is_no_space() {
+            return LevelInsertionError::NoSpace(value);
+        }
+
+        LevelInsertionError::IOError(value)
+    }
+}
+
 impl From<LevelInsertionError> for LevelExpansionError {
     fn from(value: LevelInsertionError) -> Self {
         LevelExpansionError::UpdateError(LevelUpdateError::from(value))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+
+    use crate::result::LevelInsertionError;
+    use crate::result::LevelMapError;
+    use crate::result::LevelUpdateError;
+    use crate::result::StdIOError;
+
+    #[test]
+    fn error_sources() {
+        let io_error = std::io::Error::other("disk full");
+        let error = LevelUpdateError::from(LevelInsertionError::MmapError(
+            LevelMapError::TruncateFailed(StdIOError::with_message(
+                "failed to resize values file".to_string(),
+                io_error,
+            )),
+        ));
+
+        let mut chain = vec![error.to_string()];
+        let mut source = error.source();
+        while let Some(error) = source {
+            chain.push(error.to_string());
+            source = error.source();
+        }
+
+        assert_eq!(
+            chain,
+            [
+                "failed to write the new value",
+                "failed to resize the index files",
+                "failed to resize the file",
+                "I/O error: failed to resize values file reason: disk full",
+                "disk full",
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The secondary index of a level hash, which maps the secondary keys extracted from the entries
+//! to the keys of the entries, e.g. to find the keys whose value is a given file ID without
+//! scanning the whole index. See
+//! [LevelHashOptions::secondary_index](crate::LevelHashOptions::secondary_index).
+//!
+//! The secondary index is kept in memory. It is built from the values file when the level hash is
+//! opened, and is updated as the entries are written and deleted.
+//!
+//! This module is only available with the `secondary-index` feature.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+
+use crate::types::LevelKeyT;
+use crate::types::LevelValueT;
+use crate::xxhash::xxh64;
+
+/// Extracts the secondary keys of the entries of a level hash. An entry can have any number of
+/// secondary keys, including none. The secondary keys of an entry must only depend on its key and
+/// its value.
+pub trait SecondaryKeys: Send {
+    /// Get the secondary keys of the entry with the given key and value.
+    fn secondary_keys(&self, key: &LevelKeyT, value: &LevelValueT) -> Vec<Vec<u8>>;
+}
+
+impl<F> SecondaryKeys for F
+where
+    F: Fn(&LevelKeyT, &LevelValueT) -> Vec<Vec<u8>> + Send,
+{
+    fn secondary_keys(&self, key: &LevelKeyT, value: &LevelValueT) -> Vec<Vec<u8>> {
+        self(key, value)
+    }
+}
+
+/// A [SecondaryKeys] whose only secondary key is the hash of the whole value, which is used to
+/// find the keys of a value with [LevelHash::find_keys_by_value_hash](crate::LevelHash::find_keys_by_value_hash).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValueHash;
+
+impl ValueHash {
+    /// Get the secondary key of the entries with the given value.
+    pub fn secondary_key(value: &LevelValueT) -> Vec<u8> {
+        xxh64(0, value).to_le_bytes().to_vec()
+    }
+}
+
+impl SecondaryKeys for ValueHash {
+    fn secondary_keys(&self, _key: &LevelKeyT, value: &LevelValueT) -> Vec<Vec<u8>> {
+        vec![Self::secondary_key(value)]
+    }
+}
+
+/// The in-memory secondary index of a level hash.
+///
+/// # Properties
+///
+/// * `extractor`: Extracts the secondary keys of the entries.
+/// * `keys`: The keys of the entries of each secondary key, with the number of entries of each key
+///   (the keys which are not unique may have several entries with the same secondary key).
+pub(crate) struct SecondaryIndex {
+    extractor: Box<dyn SecondaryKeys>,
+    keys: HashMap<Vec<u8>, HashMap<Vec<u8>, u32>>,
+}
+
+impl SecondaryIndex {
+    pub fn new(extractor: Box<dyn SecondaryKeys>) -> Self {
+        Self {
+            extractor,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Add the entry with the given key and value to the index.
+    pub fn add(&mut self, key: &LevelKeyT, value: &LevelValueT) {
+        for secondary_key in self.extractor.secondary_keys(key, value) {
+            let keys = self.keys.entry(secondary_key).or_default();
+            *keys.entry(key.to_vec()).or_default() += 1;
+        }
+    }
+
+    /// Remove the (deleted) entry with the given key and value from the index.
+    pub fn remove(&mut self, key: &LevelKeyT, value: &LevelValueT) {
+        for secondary_key in self.extractor.secondary_keys(key, value) {
+            let Some(keys) = self.keys.get_mut(&secondary_key) else {
+                continue;
+            };
+
+            if let Some(count) = keys.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    keys.remove(key);
+                }
+            }
+            if keys.is_empty() {
+                self.keys.remove(&secondary_key);
+            }
+        }
+    }
+
+    /// Remove all the entries from the index.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Get the keys of the entries with the given secondary key, in sorted order.
+    pub fn get(&self, secondary_key: &[u8]) -> Vec<Vec<u8>> {
+        let mut keys = self
+            .keys
+            .get(secondary_key)
+            .map(|keys| keys.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        keys.sort();
+        keys
+    }
+}
+
+impl Debug for SecondaryIndex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecondaryIndex")
+            .field("secondary_keys", &self.keys.len())
+            .finish()
+    }
+}
@@ -0,0 +1,384 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A level hash which splits the keys across multiple independent [LevelHash] instances.
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::key_transform::KeyTransform;
+use crate::result::LevelInsertionError;
+use crate::result::LevelInsertionResult;
+use crate::result::LevelReadResult;
+use crate::result::LevelResult;
+use crate::result::LevelSyncResult;
+use crate::result::LevelUpdateError;
+use crate::result::LevelUpdateResult;
+use crate::types::LevelKeyT;
+use crate::types::LevelValueT;
+use crate::xxhash::xxh64;
+use crate::LevelHash;
+
+/// The maximum number of shards of a [ShardedLevelHash].
+pub const SHARD_COUNT_MAX: usize = 256;
+
+/// The seed of the hash which selects the shard of a key. The shard of a key is not stored in the
+/// index, so this must never change.
+const SHARD_HASH_SEED: u64 = 0x5348415244;
+
+/// A level hash which splits the keys across multiple [LevelHash] instances (shards), each with its
+/// own index files. The shard of a key is selected by the hash of the key, which is independent of
+/// the seeds and the hash functions of the shards. Each shard is smaller than a single level hash
+/// with the same entries, so the shards can grow beyond [LEVEL_SIZE_MAX](crate::LEVEL_SIZE_MAX)
+/// and their files can be mapped separately.
+///
+/// The shards are locked independently, so the operations on the keys in different shards can be
+/// performed in parallel from multiple threads. A [ShardedLevelHash] is built with
+/// [LevelHashOptions::build_sharded](crate::LevelHashOptions::build_sharded), and must always be
+/// opened with the same number of shards.
+pub struct ShardedLevelHash {
+    shards: Vec<Mutex<LevelHash>>,
+    key_transform: Option<Box<dyn KeyTransform>>,
+}
+
+impl ShardedLevelHash {
+    pub(crate) fn new(
+        shards: Vec<LevelHash>,
+        key_transform: Option<Box<dyn KeyTransform>>,
+    ) -> Self {
+        Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            key_transform,
+        }
+    }
+
+    /// Get the name of the index of the given shard of a sharded level hash with the given index
+    /// name.
+    pub fn shard_index_name(index_name: &str, shard: usize) -> String {
+        format!("{}-shard{}", index_name, shard)
+    }
+
+    /// Get the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get the index of the shard which stores the entries for the given key.
+    pub fn shard_of(&self, key: &LevelKeyT) -> usize {
+        self.shard_idx(&self.transform_key(key))
+    }
+
+    fn shard_idx(&self, key: &LevelKeyT) -> usize {
+        (xxh64(SHARD_HASH_SEED, key) % self.shards.len() as u64) as usize
+    }
+
+    fn transform_key<'a>(&self, key: &'a LevelKeyT) -> Cow<'a, LevelKeyT> {
+        match &self.key_transform {
+            Some(transform) => transform.transform(key),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Lock the shard which stores the entries for the given (transformed) key.
+    fn shard(&self, key: &LevelKeyT) -> MutexGuard<'_, LevelHash> {
+        self.lock(self.shard_idx(key))
+    }
+
+    fn lock(&self, shard: usize) -> MutexGuard<'_, LevelHash> {
+        self.shards[shard]
+            .lock()
+            .expect("level hash mutex is poisoned")
+    }
+
+    /// Get the value associated with the given key. See [LevelHash::get_value].
+    pub fn get_value(&self, key: &LevelKeyT) -> Vec<u8> {
+        let key = self.transform_key(key);
+        self.shard(&key).get_value(&key)
+    }
+
+    /// Get the value associated with the given key, verifying the checksum of the entry. See
+    /// [LevelHash::try_get_value].
+    pub fn try_get_value(&self, key: &LevelKeyT) -> LevelReadResult {
+        let key = self.transform_key(key);
+        self.shard(&key).try_get_value(&key)
+    }
+
+    /// Check whether there is an entry for the given key. See [LevelHash::contains_key].
+    pub fn contains_key(&self, key: &LevelKeyT) -> bool {
+        let key = self.transform_key(key);
+        self.shard(&key).contains_key(&key)
+    }
+
+    /// Get the values of all the entries associated with the given key. See [LevelHash::get_all].
+    pub fn get_all(&self, key: &LevelKeyT) -> Vec<Vec<u8>> {
+        let key = self.transform_key(key);
+        self.shard(&key).get_all(&key)
+    }
+
+    /// Insert the given key-value pair. See [LevelHash::insert].
+    pub fn insert(&self, key: &LevelKeyT, value: &LevelValueT) -> LevelInsertionResult {
+        let key = self.transform_key(key);
+        self.shard(&key).insert(&key, value)
+    }
+
+    /// Update the entry associated with the given key with the new value. See [LevelHash::update].
+    pub fn update(&self, key: &LevelKeyT, new_value: &LevelValueT) -> LevelUpdateResult {
+        let key = self.transform_key(key);
+        self.shard(&key).update(&key, new_value)
+    }
+
+    /// Merge the given operand into the value of the entry for the given key. See
+    /// [LevelHash::insert_or_merge].
+    pub fn insert_or_merge<F>(
+        &self,
+        key: &LevelKeyT,
+        operand: &[u8],
+        merge_fn: F,
+    ) -> LevelResult<Vec<u8>, LevelUpdateError>
+    where
+        F: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8>,
+    {
+        let key = self.transform_key(key);
+        self.shard(&key).insert_or_merge(&key, operand, merge_fn)
+    }
+
+    /// Get the value of the entry for the given key, or insert the value computed by `value_fn` if
+    /// there is no entry for the key. See [LevelHash::get_or_insert_with].
+    pub fn get_or_insert_with<F>(
+        &self,
+        key: &LevelKeyT,
+        value_fn: F,
+    ) -> LevelResult<Vec<u8>, LevelInsertionError>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        let key = self.transform_key(key);
+        self.shard(&key).get_or_insert_with(&key, value_fn)
+    }
+
+    /// Remove the entry associated with the given key. See [LevelHash::remove].
+    pub fn remove(&self, key: &LevelKeyT) -> Option<Vec<u8>> {
+        let key = self.transform_key(key);
+        self.shard(&key).remove(&key)
+    }
+
+    /// Remove all the entries associated with the given key. See [LevelHash::remove_all].
+    pub fn remove_all(&self, key: &LevelKeyT) -> Vec<Vec<u8>> {
+        let key = self.transform_key(key);
+        self.shard(&key).remove_all(&key)
+    }
+
+    /// Get the sum of [LevelHash::total_slots] of all the shards.
+    pub fn total_slots(&self) -> u64 {
+        (0..self.shards.len())
+            .map(|shard| self.lock(shard).total_slots())
+            .sum()
+    }
+
+    /// Get the load factor of all the shards together, i.e. the number of entries in all the shards
+    /// divided by [Self::total_slots].
+    pub fn load_factor(&self) -> f32 {
+        let (entries, slots) = (0..self.shards.len())
+            .map(|shard| {
+                let hash = self.lock(shard);
+                let slots = hash.total_slots() as f64;
+                (hash.load_factor() as f64 * slots, slots)
+            })
+            .fold((0f64, 0f64), |(e, s), (entries, slots)| {
+                (e + entries, s + slots)
+            });
+
+        (entries / slots) as f32
+    }
+
+    /// Schedule the changes made to all the shards to be written to the disk. See
+    /// [LevelHash::flush].
+    pub fn flush(&self) -> LevelSyncResult {
+        for shard in 0..self.shards.len() {
+            self.lock(shard).flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the changes made to all the shards to the disk. See [LevelHash::sync_all].
+    pub fn sync_all(&self) -> LevelSyncResult {
+        for shard in 0..self.shards.len() {
+            self.lock(shard).sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// Perform the given operation on the given shard, while the shard is locked. This can be used
+    /// for the operations which are not exposed by [ShardedLevelHash], like [LevelHash::expand].
+    /// The keys passed to the shard are not transformed.
+    ///
+    /// ## Parameters
+    ///
+    /// * `shard` - The index of the shard, less than [Self::shard_count].
+    /// * `op` - The operation to perform.
+    pub fn with_shard<F, R>(&self, shard: usize, op: F) -> R
+    where
+        F: FnOnce(&mut LevelHash) -> R,
+    {
+        op(&mut self.lock(shard))
+    }
+
+    /// Get the shards of this level hash, in the order of their indexes.
+    pub fn into_shards(self) -> Vec<LevelHash> {
+        self.shards
+            .into_iter()
+            .map(|shard| shard.into_inner().expect("level hash mutex is poisoned"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+
+    use crate::key_transform::AsciiCaseInsensitive;
+    use crate::result::LevelInitError;
+    use crate::sharded::ShardedLevelHash;
+    use crate::test_macros::assert_matches;
+    use crate::LevelHash;
+    use crate::LevelHashOptions;
+
+    fn sharded_options(name: &str) -> LevelHashOptions {
+        let dir_path = format!("target/tests/level-hash/sharded-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir).expect("Failed to delete existing directory");
+        }
+
+        let mut options = LevelHash::options();
+        options
+            .index_dir(index_dir)
+            .index_name(name)
+            .level_size(2)
+            .bucket_size(4);
+        options
+    }
+
+    #[test]
+    fn sharded_insert_get_remove() {
+        let mut options = sharded_options("insert-get-remove");
+        let hash = options.build_sharded(4).unwrap();
+        assert_eq!(hash.shard_count(), 4);
+
+        for i in 0..200 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            hash.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        for shard in 0..hash.shard_count() {
+            assert!(hash.with_shard(shard, |shard| shard.load_factor()) > 0f32);
+        }
+
+        for i in 0..200 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            assert_eq!(hash.get_value(key.as_bytes()), value.as_bytes());
+            assert!(hash.with_shard(hash.shard_of(key.as_bytes()), |shard| {
+                shard.contains_key(key.as_bytes())
+            }));
+        }
+
+        assert_eq!(hash.update(b"key0", b"value").unwrap(), b"value0".to_vec());
+        assert_eq!(hash.remove(b"key0"), Some(b"value".to_vec()));
+        assert!(!hash.contains_key(b"key0"));
+    }
+
+    #[test]
+    fn sharded_reopen() {
+        let mut options = sharded_options("reopen");
+        let hash = options.build_sharded(4).unwrap();
+        hash.insert(b"key1", b"value1").unwrap();
+        drop(hash);
+
+        let mut options = LevelHash::options();
+        options
+            .index_dir(Path::new("target/tests/level-hash/sharded-reopen"))
+            .index_name("reopen");
+
+        for shard_count in [2, 8] {
+            assert_matches!(
+                options.build_sharded(shard_count).err(),
+                Some(LevelInitError::InvalidArg(_))
+            );
+        }
+
+        let hash = options.build_sharded(4).unwrap();
+        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+    }
+
+    #[test]
+    fn sharded_parallel_writers() {
+        let mut options = sharded_options("parallel-writers");
+        options.level_size(4);
+        let hash = options.build_sharded(4).unwrap();
+
+        thread::scope(|scope| {
+            for t in 0..4 {
+                let hash = &hash;
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        let key = format!("key{}-{}", t, i);
+                        hash.insert(key.as_bytes(), key.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        for t in 0..4 {
+            for i in 0..100 {
+                let key = format!("key{}-{}", t, i);
+                assert_eq!(hash.get_value(key.as_bytes()), key.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn sharded_key_transform() {
+        let mut options = sharded_options("key-transform");
+        options.key_transform(AsciiCaseInsensitive);
+        let hash = options.build_sharded(4).unwrap();
+
+        hash.insert(b"KEY1", b"value1").unwrap();
+        assert_eq!(hash.shard_of(b"KEY1"), hash.shard_of(b"key1"));
+        assert_eq!(hash.get_value(b"key1"), b"value1".to_vec());
+        assert_eq!(hash.remove(b"Key1"), Some(b"value1".to_vec()));
+    }
+
+    #[test]
+    fn sharded_invalid_options() {
+        assert_matches!(
+            sharded_options("invalid-options").build_sharded(0).err(),
+            Some(LevelInitError::InvalidArg(_))
+        );
+
+        assert_eq!(
+            ShardedLevelHash::shard_index_name("index", 3),
+            "index-shard3"
+        );
+    }
+}
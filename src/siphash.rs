@@ -0,0 +1,119 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A portable implementation of the keyed [SipHash](https://en.wikipedia.org/wiki/SipHash) hash
+//! function, which is used as the default hash function of the level hash. Unlike
+//! [xxh64](crate::xxhash::xxh64), the hashes cannot be predicted without the key, so the keys which
+//! collide in the same buckets cannot be chosen by an attacker who does not know the seeds of the
+//! index.
+
+/// Compute the SipHash-1-3 hash of the given data with the given seed. The 128-bit key of SipHash
+/// is made of the seed and its bitwise complement. This function can be used as a
+/// [HashFn][crate::HashFn].
+pub fn siphash13(seed: u64, data: &[u8]) -> u64 {
+    siphash13_with_keys(seed, !seed, data)
+}
+
+/// Compute the SipHash-1-3 hash of the given data with the given 128-bit key (`k0`, `k1`).
+pub fn siphash13_with_keys(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    siphash::<1, 3>(k0, k1, data)
+}
+
+fn siphash<const C: usize, const D: usize>(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+
+    let mut words = data.chunks_exact(8);
+    for word in &mut words {
+        compress::<C>(&mut v, u64::from_le_bytes(word.try_into().unwrap()));
+    }
+
+    // the last word holds the remaining bytes and the low byte of the length
+    let mut last = [0u8; 8];
+    let rest = words.remainder();
+    last[..rest.len()].copy_from_slice(rest);
+    last[7] = data.len() as u8;
+    compress::<C>(&mut v, u64::from_le_bytes(last));
+
+    v[2] ^= 0xff;
+    for _ in 0..D {
+        sip_round(&mut v);
+    }
+
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[inline]
+fn compress<const C: usize>(v: &mut [u64; 4], word: u64) {
+    v[3] ^= word;
+    for _ in 0..C {
+        sip_round(v);
+    }
+    v[0] ^= word;
+}
+
+#[inline]
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    use crate::siphash::siphash;
+    use crate::siphash::siphash13;
+    use crate::siphash::siphash13_with_keys;
+
+    #[test]
+    fn reference_values() {
+        // the test vector of the SipHash paper, which uses SipHash-2-4
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(
+            siphash::<2, 4>(0x0706050403020100, 0x0f0e0d0c0b0a0908, &data),
+            0xa129ca6149be45e5
+        );
+
+        // the default hasher of the standard library is SipHash-1-3 with a zero key
+        for data in [
+            &b""[..],
+            b"a",
+            b"abcdefgh",
+            b"The quick brown fox jumps over the lazy dog",
+        ] {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(data);
+            assert_eq!(siphash13_with_keys(0, 0, data), hasher.finish());
+        }
+
+        assert_ne!(siphash13(1, b"key"), siphash13(2, b"key"));
+    }
+}
@@ -0,0 +1,209 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs::read;
+use std::fs::rename;
+use std::fs::write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use byteorder::ByteOrder;
+
+use crate::io::IOEndianness;
+use crate::log_macros::log_warn;
+use crate::result::IntoLevelIOErr;
+use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::size::SIZE_U64;
+use crate::types::OffT;
+use crate::xxhash::xxh64;
+
+/// The occupancy of the slots in the keymap, with one bit for each slot entry (including the
+/// stash and the interim level of an expansion), addressed by the slot address. The bitmap is used
+/// to check whether a slot is occupied and to count the occupied slots of a bucket, without reading
+/// the slots from the keymap or their entries from the values file.
+///
+/// The bitmap is kept in memory, the writes to the individual slots are recorded as they are made.
+/// It is saved to a sidecar file when the level hash is closed and loaded from it when the level
+/// hash is opened again, along with a stamp of the metadata of the keymap which tells whether the
+/// keymap has been modified since. It is built from the keymap when there is no such file (e.g.
+/// the level hash was not closed cleanly), and rebuilt after the keymap is shrunk.
+#[derive(Debug, Default)]
+pub(crate) struct SlotBitmap {
+    words: Vec<u64>,
+}
+
+impl SlotBitmap {
+    /// The size (bytes) of a slot entry in the keymap.
+    const SLOT_BYTES: OffT = 8;
+
+    /// Magic number that is used as the file signature to identify the slot bitmap file.
+    pub const MAGIC_NUMBER: u64 = 0x4149445853;
+
+    /// Load the bitmap saved to the given file by [Self::save]. `None` is returned if the file
+    /// does not exist, is damaged, or was saved with another stamp.
+    pub fn load(path: &Path, stamp: &[u64]) -> Option<Self> {
+        let bytes = read(path).ok()?;
+        let header = (stamp.len() + 3) * SIZE_U64 as usize;
+        if bytes.len() < header {
+            log_warn!("ignoring the damaged slot bitmap: {}", path.display());
+            return None;
+        }
+
+        let mut fields = bytes[..header]
+            .chunks_exact(SIZE_U64 as usize)
+            .map(IOEndianness::read_u64);
+        if fields.next() != Some(Self::MAGIC_NUMBER) {
+            log_warn!("ignoring the damaged slot bitmap: {}", path.display());
+            return None;
+        }
+        if !stamp.iter().all(|field| fields.next() == Some(*field)) {
+            return None;
+        }
+
+        let checksum = fields.next()?;
+        let len = fields.next()?;
+        let data = &bytes[header..];
+        if xxh64(0, data) != checksum || data.len() as u64 != len * SIZE_U64 {
+            log_warn!("ignoring the damaged slot bitmap: {}", path.display());
+            return None;
+        }
+
+        let words = data
+            .chunks_exact(SIZE_U64 as usize)
+            .map(IOEndianness::read_u64)
+            .collect();
+        Some(Self { words })
+    }
+
+    /// Save the bitmap to the given file, along with the given stamp. The file is replaced
+    /// atomically, so that a damaged file is never read by [Self::load].
+    pub fn save(&self, path: &Path, stamp: &[u64]) -> LevelResult<(), StdIOError> {
+        let header = (stamp.len() + 3) * SIZE_U64 as usize;
+        let mut bytes = vec![0u8; header + self.words.len() * SIZE_U64 as usize];
+        IOEndianness::write_u64_into(&self.words, &mut bytes[header..]);
+
+        let fields = [Self::MAGIC_NUMBER]
+            .into_iter()
+            .chain(stamp.iter().copied())
+            .chain([xxh64(0, &bytes[header..]), self.words.len() as u64])
+            .collect::<Vec<_>>();
+        IOEndianness::write_u64_into(&fields, &mut bytes[..header]);
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        write(&tmp_path, bytes)
+            .into_lvl_io_e_msg(format!("failed to write file: {}", tmp_path.display()))?;
+        rename(&tmp_path, path).into_lvl_io_e_msg(format!(
+            "failed to rename {} to {}",
+            tmp_path.display(),
+            path.display()
+        ))
+    }
+
+    #[inline]
+    fn index(slot_addr: OffT) -> usize {
+        (slot_addr / Self::SLOT_BYTES) as usize
+    }
+
+    /// Record whether the slot at the given slot address is occupied.
+    #[inline]
+    pub fn set(&mut self, slot_addr: OffT, occupied: bool) {
+        let index = Self::index(slot_addr);
+        let word = index / 64;
+        if word >= self.words.len() {
+            if !occupied {
+                return;
+            }
+            self.words.resize(word + 1, 0);
+        }
+
+        let bit = 1u64 << (index % 64);
+        if occupied {
+            self.words[word] |= bit;
+        } else {
+            self.words[word] &= !bit;
+        }
+    }
+
+    /// Whether the slot at the given slot address is occupied.
+    #[inline]
+    pub fn is_set(&self, slot_addr: OffT) -> bool {
+        let index = Self::index(slot_addr);
+        self.words
+            .get(index / 64)
+            .is_some_and(|word| word & (1u64 << (index % 64)) != 0)
+    }
+
+    /// Mark the slots in the given region (bytes) of the keymap as free.
+    pub fn clear_range(&mut self, off: OffT, len: OffT) {
+        for slot_addr in (off..off + len).step_by(Self::SLOT_BYTES as usize) {
+            self.set(slot_addr, false);
+        }
+    }
+
+    /// Forget the slots at or after the given size (bytes) of the keymap, after it is truncated.
+    pub fn truncate(&mut self, size: OffT) {
+        let len = Self::index(size);
+        self.words.truncate(len.div_ceil(64));
+        if let Some(word) = self.words.get_mut(len / 64) {
+            *word &= (1u64 << (len % 64)) - 1;
+        }
+    }
+
+    /// Get the number of occupied slots among the given number of slots starting at the given slot
+    /// address, i.e. the occupied slots of a bucket.
+    pub fn count(&self, slot_addr: OffT, slots: u32) -> u32 {
+        self.occupied(slot_addr, slots).count() as u32
+    }
+
+    /// Get the index of the first free slot among the given number of slots starting at the given
+    /// slot address, i.e. the first free slot of a bucket. The bits are tested a word at a time.
+    pub fn first_free(&self, slot_addr: OffT, slots: u32) -> Option<u32> {
+        let start = Self::index(slot_addr);
+        let end = start + slots as usize;
+        let mut index = start;
+        while index < end {
+            let shift = index % 64;
+            let word = self.words.get(index / 64).copied().unwrap_or(0);
+            let free = (!word >> shift).trailing_zeros() as usize;
+            if free < 64 - shift {
+                return (index + free < end).then(|| (index + free - start) as u32);
+            }
+            index += 64 - shift;
+        }
+
+        None
+    }
+
+    /// Get the occupancy of the given number of slots starting at the given slot address as a
+    /// bitmap, where bit `i % 8` of byte `i / 8` is set if the `i`-th slot is occupied.
+    pub fn bytes(&self, slot_addr: OffT, slots: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; slots.div_ceil(8) as usize];
+        for slot in self.occupied(slot_addr, slots) {
+            bytes[slot as usize / 8] |= 1 << (slot % 8);
+        }
+        bytes
+    }
+
+    /// Get the indexes of the occupied slots among the given number of slots starting at the given
+    /// slot address, in ascending order.
+    pub fn occupied(&self, slot_addr: OffT, slots: u32) -> impl Iterator<Item = u32> + '_ {
+        (0..slots).filter(move |slot| self.is_set(slot_addr + *slot as OffT * Self::SLOT_BYTES))
+    }
+}
@@ -0,0 +1,43 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+// same as the unstable `std::assert_matches::assert_matches`, so that the tests build on stable
+macro_rules! assert_matches {
+    ($left:expr, $(|)? $($pattern:pat_param)|+ $(if $guard:expr)? $(,)?) => {
+        match $left {
+            $($pattern)|+ $(if $guard)? => {}
+            ref left_val => panic!(
+                "assertion `left matches right` failed\n  left: {:?}\n right: {}",
+                left_val,
+                stringify!($($pattern)|+ $(if $guard)?)
+            ),
+        }
+    };
+    ($left:expr, $(|)? $($pattern:pat_param)|+ $(if $guard:expr)?, $($arg:tt)+) => {
+        match $left {
+            $($pattern)|+ $(if $guard)? => {}
+            ref left_val => panic!(
+                "assertion `left matches right` failed: {}\n  left: {:?}\n right: {}",
+                format_args!($($arg)+),
+                left_val,
+                stringify!($($pattern)|+ $(if $guard)?)
+            ),
+        }
+    };
+}
+
+pub(crate) use assert_matches;
@@ -0,0 +1,186 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Deterministic failure injection for the tests of level hash and of its users. An [IOShim] set
+//! with [LevelHashOptions::io_shim](crate::LevelHashOptions::io_shim) is consulted before the
+//! operations on the memory-mapped index files, and can fail any of them. [FaultPlan] fails the
+//! operations at the chosen points, or simulates a crash by dropping all the writes after a given
+//! number of writes.
+//!
+//! This module is only available with the `testing` feature, which must not be enabled in
+//! production builds.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::fsck::IndexFile;
+use crate::types::OffT;
+
+/// An operation on a memory-mapped index file, passed to [IOShim::before]. The offsets are the
+/// (0-based) addresses in the file, i.e. they do not include the header of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOp {
+    /// The file is resized to store `size` bytes, or mapped again after it was resized.
+    Resize { size: OffT },
+
+    /// `len` bytes are written at `off`. The writes made through the slices of the values file
+    /// (e.g. the entries moved by the compaction) are not reported.
+    Write { off: OffT, len: OffT },
+
+    /// The changes made to the file are flushed.
+    Flush,
+
+    /// The region of `len` bytes at `off` is deallocated.
+    Deallocate { off: OffT, len: OffT },
+}
+
+/// The kind of a [FileOp], without its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileOpKind {
+    Resize,
+    Write,
+    Flush,
+    Deallocate,
+}
+
+impl FileOp {
+    /// Get the kind of this operation.
+    pub fn kind(&self) -> FileOpKind {
+        match self {
+            FileOp::Resize { .. } => FileOpKind::Resize,
+            FileOp::Write { .. } => FileOpKind::Write,
+            FileOp::Flush => FileOpKind::Flush,
+            FileOp::Deallocate { .. } => FileOpKind::Deallocate,
+        }
+    }
+}
+
+/// A shim which is consulted before the operations on the keymap and the values file of a level
+/// hash, once the index has been opened. The shim is shared by the files of the level hash, so
+/// the operations are reported in the order they are made.
+pub trait IOShim: Debug + Send + Sync {
+    /// Called before the given operation is made on the given file. If an error is returned, the
+    /// operation fails with the error. The writes cannot fail, so a failed write is dropped
+    /// instead : the bytes are not written to the file.
+    fn before(&self, file: IndexFile, op: FileOp) -> std::io::Result<()>;
+}
+
+/// The shim of a memory-mapped file, along with the index file it is consulted for.
+pub(crate) type FileShim = Option<(Arc<dyn IOShim>, IndexFile)>;
+
+/// Consult the given shim (if any) before the given operation.
+#[inline]
+pub(crate) fn before(shim: &FileShim, op: FileOp) -> std::io::Result<()> {
+    match shim {
+        Some((shim, file)) => shim.before(*file, op),
+        None => Ok(()),
+    }
+}
+
+/// An [IOShim] which fails the operations at the chosen points. The operations are counted per
+/// file and per [FileOpKind], starting from 1.
+///
+/// # Properties
+///
+/// * `failures`: The operations to fail, as the file, the kind and the number of the operation.
+///   A file of `None` matches the operations on any file, which are then counted across the files.
+/// * `crash_after`: The number of writes after which all the operations fail, simulating a
+///   process which was killed.
+/// * `counts`: The number of operations made so far, per file and per kind.
+#[derive(Debug, Default)]
+pub struct FaultPlan {
+    failures: Vec<(Option<IndexFile>, FileOpKind, u64)>,
+    crash_after: Option<u64>,
+    counts: Mutex<HashMap<(Option<IndexFile>, FileOpKind), u64>>,
+}
+
+impl FaultPlan {
+    /// Create a plan which does not fail any operation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the `nth` (1-based) operation of the given kind on the given file, or on any file if
+    /// `file` is `None`.
+    pub fn fail_nth(mut self, file: Option<IndexFile>, kind: FileOpKind, nth: u64) -> Self {
+        self.failures.push((file, kind, nth));
+        self
+    }
+
+    /// Simulate a crash after the given number of writes (on any file) : the later writes are
+    /// dropped and the other operations fail.
+    pub fn crash_after_writes(mut self, writes: u64) -> Self {
+        self.crash_after = Some(writes);
+        self
+    }
+
+    /// Get the number of operations of the given kind made on the given file, or on any file if
+    /// `file` is `None`. The failed operations are counted.
+    pub fn count(&self, file: Option<IndexFile>, kind: FileOpKind) -> u64 {
+        let counts = self.counts.lock().unwrap();
+        counts.get(&(file, kind)).copied().unwrap_or(0)
+    }
+
+    /// Whether the simulated crash has happened.
+    pub fn crashed(&self) -> bool {
+        self.crash_after
+            .is_some_and(|writes| self.count(None, FileOpKind::Write) > writes)
+    }
+
+    /// Wrap this plan in an [Arc], as expected by
+    /// [LevelHashOptions::io_shim](crate::LevelHashOptions::io_shim).
+    pub fn shared(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+}
+
+impl IOShim for FaultPlan {
+    fn before(&self, file: IndexFile, op: FileOp) -> std::io::Result<()> {
+        let kind = op.kind();
+        let (nth, nth_any) = {
+            let mut counts = self.counts.lock().unwrap();
+            let mut increment = |file| {
+                let count = counts.entry((file, kind)).or_default();
+                *count += 1;
+                *count
+            };
+            (increment(Some(file)), increment(None))
+        };
+
+        let fails = self
+            .failures
+            .iter()
+            .any(|&(fail_file, fail_kind, fail_nth)| {
+                fail_kind == kind
+                    && match fail_file {
+                        Some(fail_file) => fail_file == file && fail_nth == nth,
+                        None => fail_nth == nth_any,
+                    }
+            });
+
+        if fails || self.crashed() {
+            return Err(std::io::Error::other(format!(
+                "injected failure of {:?} on the {:?} file",
+                op, file
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,541 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Recording and replaying the operations performed on a [LevelHash][crate::LevelHash].
+//!
+//! A [LevelHash][crate::LevelHash] built with [LevelHashOptions::trace_file] records every mutating call to its
+//! public API in a compact binary trace. The keys are never written to the trace; only their
+//! length and the two hashes computed by the level hash are recorded. Similarly, only the length
+//! of the values is recorded. [replay] reproduces the exact operation sequence against a fresh
+//! index, which places the keys in the same buckets as the original index did.
+//!
+//! Structure of the trace file :
+//!
+//! ```text
+//! trace {
+//!   u64 magic_number;
+//!   u8 version;
+//!   u8 level_size;
+//!   u8 bucket_size;
+//!   u8 level_count;
+//!   u8 flags;
+//!   f32 load_factor_threshold;
+//!   u8 movement_depth;
+//!   record records[];
+//! }
+//! record {
+//!   u8 op;
+//!   u8 ok;
+//!   key key?;          // insert, update and remove only
+//!   u64 value_size;    // insert and update only
+//! }
+//! key {
+//!   u64 fhash;
+//!   u64 shash;
+//!   u32 key_size;
+//! }
+//! ```
+//!
+//! All the fields are little-endian.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use crate::result::IntoLevelIOErr;
+use crate::result::IntoLevelInitErr;
+use crate::result::LevelInitError;
+use crate::result::LevelReplayError;
+use crate::result::LevelReplayResult;
+use crate::result::LevelResult;
+use crate::result::StdIOError;
+use crate::types::BucketSizeT;
+use crate::types::LevelCountT;
+use crate::types::LevelSizeT;
+use crate::LevelHashOptions;
+
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+
+/// Magic number that is used as the file signature to identify the trace files.
+pub const TRACE_MAGIC_NUMBER: u64 = 0x4149445854;
+
+/// The version of the trace file format.
+pub const TRACE_VERSION: u8 = 3;
+
+const FLAG_UNIQUE_KEYS: u8 = 1;
+const FLAG_AUTO_EXPAND: u8 = 1 << 1;
+
+/// The size of the keys generated by [replay] which contain the recorded key hashes.
+const REPLAY_KEY_SIZE_MIN: usize = 16;
+
+/// A mutating operation on the level hash which is recorded in the trace.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceOp {
+    Insert = 1,
+    Update = 2,
+    Remove = 3,
+    Expand = 4,
+    Clear = 5,
+    DedupeKeepLatest = 6,
+    Shrink = 7,
+    RemoveAll = 8,
+    // the level size is recorded in place of the value size
+    ClearAndReset = 9,
+}
+
+impl TraceOp {
+    fn from_u8(op: u8) -> Option<Self> {
+        match op {
+            1 => Some(TraceOp::Insert),
+            2 => Some(TraceOp::Update),
+            3 => Some(TraceOp::Remove),
+            4 => Some(TraceOp::Expand),
+            5 => Some(TraceOp::Clear),
+            6 => Some(TraceOp::DedupeKeepLatest),
+            7 => Some(TraceOp::Shrink),
+            8 => Some(TraceOp::RemoveAll),
+            9 => Some(TraceOp::ClearAndReset),
+            _ => None,
+        }
+    }
+
+    fn has_key(&self) -> bool {
+        matches!(
+            self,
+            TraceOp::Insert | TraceOp::Update | TraceOp::Remove | TraceOp::RemoveAll
+        )
+    }
+
+    fn has_value(&self) -> bool {
+        matches!(
+            self,
+            TraceOp::Insert | TraceOp::Update | TraceOp::ClearAndReset
+        )
+    }
+}
+
+/// A redacted key in the trace.
+///
+/// # Properties
+///
+/// * `fhash`: The hash of the key computed with the first hash function.
+/// * `shash`: The hash of the key computed with the second hash function.
+/// * `size`: The size of the key, in bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct TraceKey {
+    pub fhash: u64,
+    pub shash: u64,
+    pub size: u32,
+}
+
+/// The configuration of the level hash which is stored in the header of the trace.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct TraceHeader {
+    pub level_size: LevelSizeT,
+    pub bucket_size: BucketSizeT,
+    pub level_count: LevelCountT,
+    pub unique_keys: bool,
+    pub auto_expand: bool,
+    pub load_factor_threshold: f32,
+    pub movement_depth: u8,
+}
+
+/// Writes the operations performed on a level hash to a trace file.
+#[derive(Debug)]
+pub(crate) struct TraceWriter {
+    out: BufWriter<File>,
+}
+
+impl TraceWriter {
+    /// Create (or truncate) the trace file at the given path and write the header to it.
+    pub fn create(path: &Path, header: &TraceHeader) -> LevelResult<Self, LevelInitError> {
+        let file = File::create(path)
+            .into_lvl_io_e_msg(format!("failed to create trace file: {}", path.display()))
+            .into_lvl_init_err()?;
+
+        let mut writer = Self {
+            out: BufWriter::new(file),
+        };
+
+        writer
+            .write_header(header)
+            .into_lvl_io_e_msg(format!("failed to write trace header: {}", path.display()))
+            .into_lvl_init_err()?;
+
+        Ok(writer)
+    }
+
+    fn write_header(&mut self, header: &TraceHeader) -> std::io::Result<()> {
+        let mut flags = 0u8;
+        if header.unique_keys {
+            flags |= FLAG_UNIQUE_KEYS;
+        }
+        if header.auto_expand {
+            flags |= FLAG_AUTO_EXPAND;
+        }
+
+        self.out.write_u64::<LittleEndian>(TRACE_MAGIC_NUMBER)?;
+        self.out.write_u8(TRACE_VERSION)?;
+        self.out.write_u8(header.level_size)?;
+        self.out.write_u8(header.bucket_size)?;
+        self.out.write_u8(header.level_count)?;
+        self.out.write_u8(flags)?;
+        self.out
+            .write_f32::<LittleEndian>(header.load_factor_threshold)?;
+        self.out.write_u8(header.movement_depth)
+    }
+
+    /// Record an operation in the trace.
+    ///
+    /// ## Parameters
+    ///
+    /// * `op`: The operation that was performed.
+    /// * `key`: The key of the entry, for operations on a single entry.
+    /// * `value_size`: The size of the value, for insertions and updates.
+    /// * `ok`: Whether the operation was successful.
+    pub fn record(
+        &mut self,
+        op: TraceOp,
+        key: Option<TraceKey>,
+        value_size: u64,
+        ok: bool,
+    ) -> LevelResult<(), StdIOError> {
+        self.write_record(op, key, value_size, ok)
+            .into_lvl_io_e_msg(format!("failed to record {:?} operation", op))
+    }
+
+    fn write_record(
+        &mut self,
+        op: TraceOp,
+        key: Option<TraceKey>,
+        value_size: u64,
+        ok: bool,
+    ) -> std::io::Result<()> {
+        self.out.write_u8(op as u8)?;
+        self.out.write_u8(ok as u8)?;
+
+        if op.has_key() {
+            let key = key.expect("key is required for operation");
+            self.out.write_u64::<LittleEndian>(key.fhash)?;
+            self.out.write_u64::<LittleEndian>(key.shash)?;
+            self.out.write_u32::<LittleEndian>(key.size)?;
+        }
+
+        if op.has_value() {
+            self.out.write_u64::<LittleEndian>(value_size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the buffered records to the trace file.
+    pub fn flush(&mut self) -> LevelResult<(), StdIOError> {
+        self.out
+            .flush()
+            .into_lvl_io_e_msg("failed to flush trace file".to_string())
+    }
+}
+
+/// The result of replaying a trace.
+///
+/// # Properties
+///
+/// * `operations`: The number of operations that were replayed.
+/// * `mismatches`: The number of operations whose outcome (success or failure) differed from the
+///   recorded outcome.
+/// * `first_mismatch`: The (0-based) index of the first operation whose outcome differed from the
+///   recorded outcome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub operations: u64,
+    pub mismatches: u64,
+    pub first_mismatch: Option<u64>,
+}
+
+/// Replay the operations recorded in the given trace file against a fresh index.
+///
+/// The level size, bucket size, level count, unique keys, auto expand, load factor threshold and
+/// movement depth of the level hash are read from the trace and override the given options. The
+/// seeds and the hash functions are replaced so that the replayed keys are placed in the same
+/// buckets as the original keys.
+/// The index directory and name (or [LevelHashOptions::in_memory]) must be set in the options.
+/// Any existing index with the same name is replaced.
+///
+/// ## Returns
+///
+/// The level hash after all the operations have been replayed, and the [ReplayReport].
+pub fn replay(trace: &Path, options: &mut LevelHashOptions) -> LevelReplayResult {
+    let file = File::open(trace)
+        .into_lvl_io_e_msg(format!("failed to open trace file: {}", trace.display()))?;
+    let mut input = BufReader::new(file);
+
+    let header = read_header(&mut input)?;
+    options
+        .level_size(header.level_size)
+        .bucket_size(header.bucket_size)
+        .level_count(header.level_count)
+        .unique_keys(header.unique_keys)
+        .auto_expand(header.auto_expand)
+        .load_factor_threshold(header.load_factor_threshold)
+        .movement_depth(header.movement_depth)
+        .seeds(1, 1)
+        .hash_fns(replay_fhash, replay_shash);
+
+    options.remove_existing_index()?;
+    let mut hash = options.build()?;
+
+    let mut report = ReplayReport::default();
+    while let Some((op, key, value_size, ok)) = read_record(&mut input)? {
+        let key = key.map(replay_key).unwrap_or_default();
+        let value = vec![0u8; value_size as usize];
+
+        let replayed_ok = match op {
+            TraceOp::Insert => hash.insert(&key, &value).is_ok(),
+            TraceOp::Update => hash.update(&key, &value).is_ok(),
+            TraceOp::Remove => hash.remove(&key).is_some(),
+            TraceOp::RemoveAll => !hash.remove_all(&key).is_empty(),
+            TraceOp::Expand => hash.expand().is_ok(),
+            TraceOp::Shrink => hash.shrink().is_ok(),
+            TraceOp::Clear => hash.clear().is_ok(),
+            TraceOp::ClearAndReset => hash.clear_and_reset(value_size as LevelSizeT).is_ok(),
+            TraceOp::DedupeKeepLatest => {
+                hash.dedupe_keep_latest();
+                true
+            }
+        };
+
+        if replayed_ok != ok {
+            report.mismatches += 1;
+            report.first_mismatch.get_or_insert(report.operations);
+        }
+
+        report.operations += 1;
+    }
+
+    Ok((hash, report))
+}
+
+fn read_header(input: &mut impl Read) -> LevelResult<TraceHeader, LevelReplayError> {
+    let mut header = || -> std::io::Result<(u64, u8, TraceHeader)> {
+        let magic = input.read_u64::<LittleEndian>()?;
+        let version = input.read_u8()?;
+        let level_size = input.read_u8()?;
+        let bucket_size = input.read_u8()?;
+        let level_count = input.read_u8()?;
+        let flags = input.read_u8()?;
+        let load_factor_threshold = input.read_f32::<LittleEndian>()?;
+        let movement_depth = input.read_u8()?;
+        Ok((
+            magic,
+            version,
+            TraceHeader {
+                level_size,
+                bucket_size,
+                level_count,
+                unique_keys: flags & FLAG_UNIQUE_KEYS != 0,
+                auto_expand: flags & FLAG_AUTO_EXPAND != 0,
+                load_factor_threshold,
+                movement_depth,
+            },
+        ))
+    };
+
+    let (magic, version, header) =
+        header().into_lvl_io_e_msg("failed to read trace header".to_string())?;
+
+    if magic != TRACE_MAGIC_NUMBER {
+        return Err(LevelReplayError::InvalidTrace(format!(
+            "magic number mismatch: {} != {}",
+            magic, TRACE_MAGIC_NUMBER
+        )));
+    }
+
+    if version != TRACE_VERSION {
+        return Err(LevelReplayError::InvalidTrace(format!(
+            "unsupported trace version: {}",
+            version
+        )));
+    }
+
+    Ok(header)
+}
+
+/// A recorded operation : the operation, its key (if any), the size of its value and whether it
+/// succeeded.
+type TraceRecord = (TraceOp, Option<TraceKey>, u64, bool);
+
+fn read_record(input: &mut impl Read) -> LevelResult<Option<TraceRecord>, LevelReplayError> {
+    let op = match input.read_u8() {
+        Ok(op) => op,
+        Err(why) if why.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(why) => {
+            return Err(
+                StdIOError::with_message("failed to read trace record".to_string(), why).into(),
+            )
+        }
+    };
+
+    let op = TraceOp::from_u8(op)
+        .ok_or_else(|| LevelReplayError::InvalidTrace(format!("invalid operation: {}", op)))?;
+
+    let mut record = || -> std::io::Result<(Option<TraceKey>, u64, bool)> {
+        let ok = input.read_u8()? != 0;
+        let key = if op.has_key() {
+            Some(TraceKey {
+                fhash: input.read_u64::<LittleEndian>()?,
+                shash: input.read_u64::<LittleEndian>()?,
+                size: input.read_u32::<LittleEndian>()?,
+            })
+        } else {
+            None
+        };
+        let value_size = if op.has_value() {
+            input.read_u64::<LittleEndian>()?
+        } else {
+            0
+        };
+        Ok((key, value_size, ok))
+    };
+
+    let (key, value_size, ok) = record().into_lvl_io_e_msg(format!("truncated {:?} record", op))?;
+
+    Ok(Some((op, key, value_size, ok)))
+}
+
+/// Create the key that is used to replay the operations on the recorded key. The key contains the
+/// recorded hashes, and is padded to the size of the original key.
+fn replay_key(key: TraceKey) -> Vec<u8> {
+    let size = REPLAY_KEY_SIZE_MIN.max(key.size as usize);
+    let mut replay_key = Vec::with_capacity(size);
+    replay_key.extend_from_slice(&key.fhash.to_le_bytes());
+    replay_key.extend_from_slice(&key.shash.to_le_bytes());
+    replay_key.resize(size, 0);
+    replay_key
+}
+
+fn replay_fhash(_seed: u64, key: &[u8]) -> u64 {
+    u64::from_le_bytes(key[..8].try_into().unwrap())
+}
+
+fn replay_shash(_seed: u64, key: &[u8]) -> u64 {
+    u64::from_le_bytes(key[8..16].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use crate::result::LevelReplayError;
+    use crate::trace::replay;
+    use crate::trace::ReplayReport;
+    use crate::Level;
+    use crate::LevelHash;
+
+    fn trace_dir(name: &str) -> PathBuf {
+        let dir_path = format!("target/tests/level-hash/trace-{}", name);
+        let dir = Path::new(&dir_path);
+        if dir.exists() {
+            fs::remove_dir_all(dir).expect("Failed to delete existing directory");
+        }
+        fs::create_dir_all(dir).expect("Failed to create directories");
+        dir.to_path_buf()
+    }
+
+    /// Get the size of the value in each slot of the level hash.
+    fn slot_value_sizes(hash: &mut LevelHash, level_size: u8, bucket_size: u8) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        for (level, buckets) in [
+            (Level::L0, 1u32 << level_size),
+            (Level::L1, 1u32 << (level_size - 1)),
+        ] {
+            for bucket in 0..buckets {
+                for slot in 0..bucket_size {
+                    sizes.push(hash.get_value_at(level, bucket, slot as _).len());
+                }
+            }
+        }
+        sizes
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let dir = trace_dir("record-and-replay");
+        let trace_file = dir.join("ops.trace");
+
+        let mut hash = LevelHash::options()
+            .index_dir(&dir)
+            .index_name("record-and-replay")
+            .level_size(2)
+            .bucket_size(2)
+            .auto_expand(false)
+            .trace_file(&trace_file)
+            .build()
+            .expect("failed to create level hash");
+
+        let mut operations = 0;
+        for i in 0..16 {
+            let key = format!("a-rather-long-key-{}", i).into_bytes();
+            let value = format!("value{}", i).into_bytes();
+            let _ = hash.insert(&key, &value);
+            operations += 1;
+        }
+
+        assert!(hash.insert(b"a-rather-long-key-0", b"value").is_err());
+        assert!(hash.update(b"a-rather-long-key-1", b"updated").is_ok());
+        assert!(hash.remove(b"a-rather-long-key-2").is_some());
+        assert!(hash.remove(b"missing").is_none());
+        assert!(hash.expand().is_ok());
+        assert!(hash.insert(b"a-rather-long-key-2", b"value").is_ok());
+        operations += 6;
+
+        hash.flush().expect("failed to flush level hash");
+        let expected = slot_value_sizes(&mut hash, 3, 2);
+        drop(hash);
+
+        let (mut replayed, report) = replay(&trace_file, LevelHash::options().in_memory(true))
+            .expect("failed to replay trace");
+
+        assert_eq!(
+            report,
+            ReplayReport {
+                operations,
+                mismatches: 0,
+                first_mismatch: None,
+            }
+        );
+        assert_eq!(slot_value_sizes(&mut replayed, 3, 2), expected);
+    }
+
+    #[test]
+    fn replay_invalid_trace() {
+        let dir = trace_dir("invalid");
+        let trace_file = dir.join("ops.trace");
+        fs::write(&trace_file, [0u8; 32]).expect("failed to write trace file");
+
+        let result = replay(&trace_file, LevelHash::options().in_memory(true));
+        assert!(matches!(result, Err(LevelReplayError::InvalidTrace(_))));
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Typed keys and values on top of the raw byte API of [LevelHash].
+
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::result::LevelTypedError;
+use crate::result::LevelTypedResult;
+use crate::LevelHash;
+
+/// A [LevelHash] which stores keys of type `K` and values of type `V`. The keys and the values
+/// are encoded with [bincode] before they are written to the level hash and decoded when they
+/// are read.
+///
+/// As the keys are compared in their encoded form, the encoding of equal keys must be equal.
+pub struct TypedLevelHash<K, V> {
+    hash: LevelHash,
+    _marker: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> TypedLevelHash<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Create a new [TypedLevelHash] which stores its entries in the given level hash.
+    pub fn new(hash: LevelHash) -> Self {
+        Self {
+            hash,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get the underlying level hash.
+    pub fn inner(&self) -> &LevelHash {
+        &self.hash
+    }
+
+    /// Get the underlying level hash, mutably.
+    pub fn inner_mut(&mut self) -> &mut LevelHash {
+        &mut self.hash
+    }
+
+    /// Consume this [TypedLevelHash], returning the underlying level hash.
+    pub fn into_inner(self) -> LevelHash {
+        self.hash
+    }
+
+    /// Get the value associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the decoded value if an entry is found, `None` otherwise.
+    pub fn get(&self, key: &K) -> LevelTypedResult<Option<V>> {
+        let key = Self::encode(key)?;
+        self.hash
+            .try_get_value(&key)?
+            .map(|value| Self::decode(&value))
+            .transpose()
+    }
+
+    /// Insert the given key-value pair in the level hash.
+    pub fn insert(&mut self, key: &K, value: &V) -> LevelTypedResult<()> {
+        let key = Self::encode(key)?;
+        let value = Self::encode(value)?;
+        Ok(self.hash.insert(&key, &value)?)
+    }
+
+    /// Update the entry associated with the given key with the new value.
+    ///
+    /// ## Returns
+    ///
+    /// The decoded previous value of the entry.
+    pub fn update(&mut self, key: &K, new_value: &V) -> LevelTypedResult<V> {
+        let key = Self::encode(key)?;
+        let new_value = Self::encode(new_value)?;
+        let value = self.hash.update(&key, &new_value)?;
+        Self::decode(&value)
+    }
+
+    /// Remove the entry associated with the given key.
+    ///
+    /// ## Returns
+    ///
+    /// `Some` containing the decoded value of the removed entry if found, `None` otherwise.
+    pub fn remove(&mut self, key: &K) -> LevelTypedResult<Option<V>> {
+        let key = Self::encode(key)?;
+        self.hash
+            .remove(&key)
+            .map(|value| Self::decode(&value))
+            .transpose()
+    }
+
+    fn encode<T: Serialize>(value: &T) -> LevelTypedResult<Vec<u8>> {
+        bincode::serialize(value).map_err(LevelTypedError::CodecError)
+    }
+
+    fn decode<T: DeserializeOwned>(value: &[u8]) -> LevelTypedResult<T> {
+        bincode::deserialize(value).map_err(LevelTypedError::CodecError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::hash::Hasher;
+    use std::path::Path;
+
+    use gxhash::GxHasher;
+
+    use crate::typed::TypedLevelHash;
+    use crate::LevelHash;
+
+    fn gxhash(seed: u64, data: &[u8]) -> u64 {
+        let mut hasher = GxHasher::with_seed(seed as i64);
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    fn create_typed_level_hash(name: &str) -> TypedLevelHash<String, u64> {
+        let dir_path = format!("target/tests/level-hash/typed-{}", name);
+        let index_dir = Path::new(&dir_path);
+        if index_dir.exists() {
+            fs::remove_dir_all(&index_dir).expect("Failed to delete existing directory");
+        }
+
+        LevelHash::options()
+            .index_dir(index_dir)
+            .index_name(name)
+            .level_size(2)
+            .bucket_size(4)
+            .hash_fns(self::gxhash, self::gxhash)
+            .build_typed()
+            .expect("failed to create level hash")
+    }
+
+    #[test]
+    fn typed_insert_get_update_remove() {
+        let mut hash = create_typed_level_hash("insert-get-update-remove");
+        let key = "key1".to_string();
+
+        assert_eq!(hash.get(&key).unwrap(), None);
+
+        hash.insert(&key, &42).unwrap();
+        assert_eq!(hash.get(&key).unwrap(), Some(42));
+
+        assert_eq!(hash.update(&key, &84).unwrap(), 42);
+        assert_eq!(hash.get(&key).unwrap(), Some(84));
+
+        assert_eq!(hash.remove(&key).unwrap(), Some(84));
+        assert_eq!(hash.get(&key).unwrap(), None);
+    }
+}
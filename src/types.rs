@@ -23,6 +23,7 @@ pub type LevelValueT = [u8];
 
 pub type LevelSizeT = u8;
 pub type BucketSizeT = u8;
+pub type LevelCountT = u8;
 
 pub(crate) type _LevelIdxT = u32;
 pub(crate) type _BucketIdxT = u32;
@@ -14,8 +14,9 @@
  *  You should have received a copy of the GNU General Public License
  *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
  */
-use std::fs::File;
-use std::path::Path;
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use rand::rngs::StdRng;
 use rand::RngCore;
@@ -23,39 +24,30 @@ use rand::SeedableRng;
 
 use crate::types::OffT;
 
-/// Open the file in read-write mode, or panic.
-pub(crate) fn file_open_or_panic(path: &Path, read: bool, write: bool, create: bool) -> File {
-    let file = File::options()
-        .read(read)
-        .write(write)
-        .create(create)
-        .open(path);
-
-    match file {
-        Ok(file) => file,
-        Err(why) => panic!("couldn't open {}: {}", path.display(), why),
-    }
-}
+/// The seeds of [generate_seeds_deterministic]. These are the seeds that the earlier versions of
+/// level hash generated from a fixed random number generator seed.
+const DETERMINISTIC_SEEDS: (u64, u64) = (0x30ac64b668000000, 0x5f75100000000000);
 
-/// Generate a random seed pair.
+/// Generate a random seed pair from the entropy of the operating system.
 pub fn generate_seeds() -> (u64, u64) {
-    let mut rand = StdRng::seed_from_u64(6248403840530382848);
+    seeds_from_rng(&mut StdRng::from_entropy())
+}
 
-    let mut fseed: u64;
-    let mut sseed: u64;
+/// Generate the same seed pair on every call. These were the default seeds of the indexes created
+/// before the seeds were stored in the index, and are only meant for the tests which need
+/// reproducible slot positions, as the slot positions can be predicted from these seeds.
+pub fn generate_seeds_deterministic() -> (u64, u64) {
+    DETERMINISTIC_SEEDS
+}
 
+fn seeds_from_rng(rand: &mut StdRng) -> (u64, u64) {
     loop {
-        fseed = rand.next_u64();
-        sseed = rand.next_u64();
-        fseed = fseed << (rand.next_u64() % 63);
-        sseed = sseed << (rand.next_u64() % 63);
-
-        if fseed != sseed {
-            break;
+        let fseed = rand.next_u64();
+        let sseed = rand.next_u64();
+        if fseed != sseed && fseed != 0 && sseed != 0 {
+            return (fseed, sseed);
         }
     }
-
-    (fseed, sseed)
 }
 
 #[inline]
@@ -63,6 +55,18 @@ pub(crate) fn align_8(addr: OffT) -> OffT {
     (addr + 7) & !7
 }
 
+/// Get the size of a memory page.
+pub(crate) fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+/// Get the current time in seconds since the Unix epoch.
+pub(crate) fn unix_time_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
 pub(crate) trait IsTrue {
     fn is_true(self) -> bool;
 }
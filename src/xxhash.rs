@@ -0,0 +1,235 @@
+/*
+ *  This file is part of AndroidIDE.
+ *
+ *  AndroidIDE is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  AndroidIDE is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *   along with AndroidIDE.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A portable implementation of the 64-bit [xxHash](https://xxhash.com) algorithm (XXH64), which is
+//! the default hash function of the indexes created before [SipHash](crate::siphash) was the
+//! default.
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+/// Compute the XXH64 hash of the given data with the given seed. This function can be used as a
+/// [HashFn][crate::HashFn].
+pub fn xxh64(seed: u64, data: &[u8]) -> u64 {
+    let len = data.len() as u64;
+    let mut rest = data;
+
+    let hash = if rest.len() >= 32 {
+        let mut acc = init_acc(seed);
+        while rest.len() >= 32 {
+            stripe(&mut acc, rest);
+            rest = &rest[32..];
+        }
+
+        merge_acc(acc)
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+
+    finalize(hash.wrapping_add(len), rest)
+}
+
+/// A streaming XXH64 hasher, which computes the same hash as [xxh64] for data which is not
+/// available as a single slice.
+#[derive(Debug, Clone)]
+pub struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    len: u64,
+}
+
+impl Xxh64 {
+    /// Create a new hasher with the given seed.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            acc: init_acc(seed),
+            buf: [0; 32],
+            buf_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Hash the given data, after the data hashed before.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        if self.buf_len > 0 {
+            let fill = (32 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + fill].copy_from_slice(&data[..fill]);
+            self.buf_len += fill;
+            data = &data[fill..];
+            if self.buf_len < 32 {
+                return;
+            }
+
+            stripe(&mut self.acc, &self.buf);
+            self.buf_len = 0;
+        }
+
+        while data.len() >= 32 {
+            stripe(&mut self.acc, data);
+            data = &data[32..];
+        }
+
+        self.buf[..data.len()].copy_from_slice(data);
+        self.buf_len = data.len();
+    }
+
+    /// Get the hash of all the data hashed so far.
+    pub fn digest(&self) -> u64 {
+        let hash = if self.len >= 32 {
+            merge_acc(self.acc)
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+
+        finalize(hash.wrapping_add(self.len), &self.buf[..self.buf_len])
+    }
+}
+
+#[inline]
+fn init_acc(seed: u64) -> [u64; 4] {
+    [
+        seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+        seed.wrapping_add(PRIME64_2),
+        seed,
+        seed.wrapping_sub(PRIME64_1),
+    ]
+}
+
+#[inline]
+fn stripe(acc: &mut [u64; 4], data: &[u8]) {
+    for (i, acc) in acc.iter_mut().enumerate() {
+        *acc = round(*acc, read_u64(&data[i * 8..]));
+    }
+}
+
+fn merge_acc(acc: [u64; 4]) -> u64 {
+    let mut hash = acc[0]
+        .rotate_left(1)
+        .wrapping_add(acc[1].rotate_left(7))
+        .wrapping_add(acc[2].rotate_left(12))
+        .wrapping_add(acc[3].rotate_left(18));
+
+    for acc in acc {
+        hash = merge_round(hash, acc);
+    }
+
+    hash
+}
+
+/// Mix the remaining (less than 32) bytes of the data into the hash.
+fn finalize(mut hash: u64, mut rest: &[u8]) -> u64 {
+    while rest.len() >= 8 {
+        hash ^= round(0, read_u64(rest));
+        hash = hash
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+
+    if rest.len() >= 4 {
+        hash ^= (read_u32(rest) as u64).wrapping_mul(PRIME64_1);
+        hash = hash
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+
+    for &byte in rest {
+        hash ^= (byte as u64).wrapping_mul(PRIME64_5);
+        hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+
+    avalanche(hash)
+}
+
+#[inline]
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+#[inline]
+fn merge_round(hash: u64, acc: u64) -> u64 {
+    (hash ^ round(0, acc))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+#[inline]
+fn avalanche(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+#[inline]
+fn read_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data[..8].try_into().unwrap())
+}
+
+#[inline]
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::xxhash::xxh64;
+    use crate::xxhash::Xxh64;
+
+    #[test]
+    fn reference_values() {
+        assert_eq!(xxh64(0, b""), 0xEF46DB3751D8E999);
+        assert_eq!(xxh64(0, b"a"), 0xD24EC4F1A98C6E5B);
+        assert_eq!(xxh64(0, b"abc"), 0x44BC2CF5AD770999);
+        assert_eq!(
+            xxh64(0, b"Nobody inspects the spammish repetition"),
+            0xFBCEA83C8A378BF1
+        );
+        assert_eq!(
+            xxh64(0, b"The quick brown fox jumps over the lazy dog"),
+            0x0B242D361FDA71BC
+        );
+    }
+
+    #[test]
+    fn streaming() {
+        let data = (0..200u8).collect::<Vec<_>>();
+        for len in [0, 5, 31, 32, 33, 64, 100, 200] {
+            for chunk_size in [1, 7, 32, 50] {
+                let mut hasher = Xxh64::new(42);
+                data[..len]
+                    .chunks(chunk_size)
+                    .for_each(|chunk| hasher.update(chunk));
+                assert_eq!(hasher.digest(), xxh64(42, &data[..len]));
+            }
+        }
+    }
+}